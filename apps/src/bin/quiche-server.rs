@@ -422,6 +422,7 @@ fn main() {
             let recv_info = quiche::RecvInfo {
                 to: local_addr,
                 from,
+                ecn: quiche::Ecn::NotEct,
             };
 
             // Process potentially coalesced packets.
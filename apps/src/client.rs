@@ -311,6 +311,7 @@ pub fn connect(
                 let recv_info = quiche::RecvInfo {
                     to: local_addr,
                     from,
+                    ecn: quiche::Ecn::NotEct,
                 };
 
                 // Process potentially coalesced packets.
@@ -743,13 +743,12 @@ fn make_h3_config(
     }
 
     if let Some(v) = qpack_max_table_capacity {
-        // quiche doesn't support dynamic QPACK, so clamp to 0 for now.
-        config.set_qpack_max_table_capacity(v.clamp(0, 0));
+        config.set_qpack_max_table_capacity(v);
+        config.set_qpack_encoder_max_table_capacity(v);
     }
 
     if let Some(v) = qpack_blocked_streams {
-        // quiche doesn't support dynamic QPACK, so clamp to 0 for now.
-        config.set_qpack_blocked_streams(v.clamp(0, 0));
+        config.set_qpack_blocked_streams(v);
     }
 
     config
@@ -1349,6 +1348,15 @@ impl HttpConn for Http3Conn {
                     );
                 },
 
+                // Server push is never enabled using MAX_PUSH_ID.
+                Ok((_, quiche::h3::Event::PushPromise { .. })) => unreachable!(),
+
+                Ok((_, quiche::h3::Event::CancelPush)) => unreachable!(),
+
+                // WebTransport is never enabled.
+                Ok((_, quiche::h3::Event::WebTransportStream { .. })) =>
+                    unreachable!(),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -1546,6 +1554,15 @@ impl HttpConn for Http3Conn {
                         .send_goaway(conn, self.largest_processed_request)?;
                 },
 
+                Ok((_, quiche::h3::Event::PushPromise { .. })) => unreachable!(),
+
+                // No push is ever promised.
+                Ok((_, quiche::h3::Event::CancelPush)) => unreachable!(),
+
+                // WebTransport is never enabled.
+                Ok((_, quiche::h3::Event::WebTransportStream { .. })) =>
+                    unreachable!(),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -154,6 +154,12 @@ pub fn server_process(
                     // Peer signalled it is going away, handle it.
                 },
 
+                Ok((_stream_id, quiche::h3::Event::PushPromise { .. })) => {},
+
+                Ok((_push_id, quiche::h3::Event::CancelPush)) => {},
+
+                Ok((_, quiche::h3::Event::WebTransportStream { .. })) => {},
+
                 Err(quiche::h3::Error::Done) => {
                     // Done reading.
                     break;
@@ -55,7 +55,7 @@ fuzz_target!(|data: &[u8]| {
     )
     .unwrap();
 
-    let info = quiche::RecvInfo { from, to };
+    let info = quiche::RecvInfo::new(from, to);
 
     conn.recv(&mut buf, info).ok();
 
@@ -53,7 +53,7 @@ fuzz_target!(|data: &[u8]| {
         quiche::accept(&SCID, None, to, from, &mut config.lock().unwrap())
             .unwrap();
 
-    let info = quiche::RecvInfo { from, to };
+    let info = quiche::RecvInfo::new(from, to);
 
     conn.recv(&mut buf, info).ok();
 
@@ -62,7 +62,7 @@ fuzz_target!(|data: &[u8]| {
     )
     .unwrap();
 
-    let info = quiche::RecvInfo { from, to };
+    let info = quiche::RecvInfo::new(from, to);
 
     while !conn.is_established() || !connc.is_established() {
         let flight = quiche::test_utils::emit_flight(&mut connc).unwrap();
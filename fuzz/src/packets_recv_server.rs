@@ -53,7 +53,7 @@ fuzz_target!(|data: &[u8]| {
         quiche::accept(&SCID, None, to, from, &mut config.lock().unwrap())
             .unwrap();
 
-    let info = quiche::RecvInfo { from, to };
+    let info = quiche::RecvInfo::new(from, to);
 
     let mut h3_conn = None;
     for pkt in packets.iter() {
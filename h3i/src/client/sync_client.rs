@@ -322,6 +322,7 @@ pub fn connect_with_early_data(
                 let recv_info = quiche::RecvInfo {
                     to: local_addr,
                     from,
+                    ecn: quiche::Ecn::NotEct,
                 };
 
                 // Process potentially coalesced packets.
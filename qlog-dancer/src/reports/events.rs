@@ -112,6 +112,9 @@ pub fn frames_to_string(frames: &[QuicFrame]) -> String {
             qlog::events::quic::QuicFrame::ResetStream { stream_id, error_code, final_size, .. } => {
                 s += &format!(" RESET_STREAM {{id={stream_id}, error_code={error_code}, final_size={final_size}}}");
             },
+            qlog::events::quic::QuicFrame::ResetStreamAt { stream_id, error_code, final_size, reliable_size, .. } => {
+                s += &format!(" RESET_STREAM_AT {{id={stream_id}, error_code={error_code}, final_size={final_size}, reliable_size={reliable_size}}}");
+            },
             qlog::events::quic::QuicFrame::StopSending { stream_id, error_code, ..} => {
                 s += &format!(" STOP_SENDING {{id={stream_id}, error_code={error_code}}}");
             },
@@ -176,6 +179,39 @@ pub fn frames_to_string(frames: &[QuicFrame]) -> String {
             qlog::events::quic::QuicFrame::Datagram { length, .. } => {
                s += &format!(" DATAGRAM {{len={length}}}");
             },
+            qlog::events::quic::QuicFrame::AckFrequency { sequence_number, ack_eliciting_threshold, request_max_ack_delay, reordering_threshold } => {
+               s += &format!(" ACK_FREQUENCY {{sn={sequence_number}, threshold={ack_eliciting_threshold}, max_ack_delay={request_max_ack_delay}, reordering={reordering_threshold}}}");
+            },
+            qlog::events::quic::QuicFrame::ImmediateAck => {
+                s += " IMMEDIATE_ACK";
+            },
+            qlog::events::quic::QuicFrame::PathAck { path_id, .. } => {
+                s += &format!(" PATH_ACK {{path_id={path_id}}}");
+            },
+            qlog::events::quic::QuicFrame::PathAbandon { path_id, error_code } => {
+                s += &format!(" PATH_ABANDON {{path_id={path_id}, error_code={error_code}}}");
+            },
+            qlog::events::quic::QuicFrame::PathStatusBackup { path_id, path_status_sequence_number } => {
+                s += &format!(" PATH_STATUS_BACKUP {{path_id={path_id}, sn={path_status_sequence_number}}}");
+            },
+            qlog::events::quic::QuicFrame::PathStatusAvailable { path_id, path_status_sequence_number } => {
+                s += &format!(" PATH_STATUS_AVAILABLE {{path_id={path_id}, sn={path_status_sequence_number}}}");
+            },
+            qlog::events::quic::QuicFrame::PathNewConnectionId { path_id, sequence_number, .. } => {
+                s += &format!(" PATH_NEW_CONNECTION_ID {{path_id={path_id}, sn={sequence_number}}}");
+            },
+            qlog::events::quic::QuicFrame::PathRetireConnectionId { path_id, sequence_number } => {
+                s += &format!(" PATH_RETIRE_CONNECTION_ID {{path_id={path_id}, sn={sequence_number}}}");
+            },
+            qlog::events::quic::QuicFrame::MaxPathId { maximum_path_id } => {
+                s += &format!(" MAX_PATH_ID {{max={maximum_path_id}}}");
+            },
+            qlog::events::quic::QuicFrame::PathsBlocked { maximum_path_id } => {
+                s += &format!(" PATHS_BLOCKED {{max={maximum_path_id}}}");
+            },
+            qlog::events::quic::QuicFrame::PathCidsBlocked { path_id, next_sequence_number } => {
+                s += &format!(" PATH_CIDS_BLOCKED {{path_id={path_id}, next_sn={next_sequence_number}}}");
+            },
             qlog::events::quic::QuicFrame::Unknown { raw_frame_type, .. } => {
                s += &format!(" UNKNOWN {{raw_type={raw_frame_type}}}");
             },
@@ -356,6 +356,7 @@ pub enum QuicFrameTypeName {
     Ping,
     Ack,
     ResetStream,
+    ResetStreamAt,
     StopSending,
     Crypto,
     NewToken,
@@ -374,6 +375,17 @@ pub enum QuicFrameTypeName {
     ApplicationClose,
     HandshakeDone,
     Datagram,
+    AckFrequency,
+    ImmediateAck,
+    PathAck,
+    PathAbandon,
+    PathStatusBackup,
+    PathStatusAvailable,
+    PathNewConnectionId,
+    PathRetireConnectionId,
+    MaxPathId,
+    PathsBlocked,
+    PathCidsBlocked,
     #[default]
     Unknown,
 }
@@ -418,6 +430,16 @@ pub enum QuicFrame {
         payload_length: Option<u32>,
     },
 
+    ResetStreamAt {
+        stream_id: u64,
+        error_code: u64,
+        final_size: u64,
+        reliable_size: u64,
+
+        length: Option<u32>,
+        payload_length: Option<u32>,
+    },
+
     StopSending {
         stream_id: u64,
         error_code: u64,
@@ -509,6 +531,67 @@ pub enum QuicFrame {
         raw: Option<Bytes>,
     },
 
+    AckFrequency {
+        sequence_number: u64,
+        ack_eliciting_threshold: u64,
+        request_max_ack_delay: f32,
+        reordering_threshold: u64,
+    },
+
+    ImmediateAck,
+
+    PathAck {
+        path_id: u64,
+        ack_delay: Option<f32>,
+        acked_ranges: Option<AckedRanges>,
+
+        ect1: Option<u64>,
+        ect0: Option<u64>,
+        ce: Option<u64>,
+    },
+
+    PathAbandon {
+        path_id: u64,
+        error_code: u64,
+    },
+
+    PathStatusBackup {
+        path_id: u64,
+        path_status_sequence_number: u64,
+    },
+
+    PathStatusAvailable {
+        path_id: u64,
+        path_status_sequence_number: u64,
+    },
+
+    PathNewConnectionId {
+        path_id: u64,
+        sequence_number: u32,
+        retire_prior_to: u32,
+        connection_id_length: Option<u8>,
+        connection_id: Bytes,
+        stateless_reset_token: Option<StatelessResetToken>,
+    },
+
+    PathRetireConnectionId {
+        path_id: u64,
+        sequence_number: u32,
+    },
+
+    MaxPathId {
+        maximum_path_id: u64,
+    },
+
+    PathsBlocked {
+        maximum_path_id: u64,
+    },
+
+    PathCidsBlocked {
+        path_id: u64,
+        next_sequence_number: u64,
+    },
+
     Unknown {
         raw_frame_type: u64,
         frame_type_value: Option<u64>,
@@ -169,10 +169,8 @@ fn main() {
 
             debug!("got {len} bytes");
 
-            let recv_info = quiche::RecvInfo {
-                to: socket.local_addr().unwrap(),
-                from,
-            };
+            let recv_info =
+                quiche::RecvInfo::new(from, socket.local_addr().unwrap());
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
@@ -188,10 +188,7 @@ fn main() {
 
             debug!("got {len} bytes");
 
-            let recv_info = quiche::RecvInfo {
-                to: local_addr,
-                from,
-            };
+            let recv_info = quiche::RecvInfo::new(from, local_addr);
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
@@ -280,6 +277,14 @@ fn main() {
                         info!("GOAWAY id={goaway_id}");
                     },
 
+                    Ok((_, quiche::h3::Event::PushPromise { .. })) =>
+                        unreachable!(),
+
+                    Ok((_, quiche::h3::Event::CancelPush)) => unreachable!(),
+
+                    Ok((_, quiche::h3::Event::WebTransportStream { .. })) =>
+                        unreachable!(),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },
@@ -289,10 +289,8 @@ fn main() {
                 }
             };
 
-            let recv_info = quiche::RecvInfo {
-                to: socket.local_addr().unwrap(),
-                from,
-            };
+            let recv_info =
+                quiche::RecvInfo::new(from, socket.local_addr().unwrap());
 
             // Process potentially coalesced packets.
             let read = match client.conn.recv(pkt_buf, recv_info) {
@@ -376,6 +374,14 @@ fn main() {
 
                         Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
 
+                        Ok((_, quiche::h3::Event::PushPromise { .. })) =>
+                            unreachable!(),
+
+                        Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                        Ok((_, quiche::h3::Event::WebTransportStream { .. })) =>
+                            unreachable!(),
+
                         Err(quiche::h3::Error::Done) => {
                             break;
                         },
@@ -72,7 +72,7 @@ fn main() {
         debug!("Got stream={stream_id} len={len}");
 
         if stream_id == 0 {
-            dec.control(&mut data[..len]).unwrap();
+            dec.control(&data[..len]).unwrap();
             continue;
         }
 
@@ -286,10 +286,8 @@ fn main() {
                 }
             };
 
-            let recv_info = quiche::RecvInfo {
-                to: socket.local_addr().unwrap(),
-                from,
-            };
+            let recv_info =
+                quiche::RecvInfo::new(from, socket.local_addr().unwrap());
 
             // Process potentially coalesced packets.
             let read = match client.conn.recv(pkt_buf, recv_info) {
@@ -58,6 +58,12 @@ extern "C" {
 // The current QUIC wire version.
 #define QUICHE_PROTOCOL_VERSION 0x00000001
 
+// The QUIC version 1 wire version.
+#define QUICHE_PROTOCOL_VERSION_V1 0x00000001
+
+// The QUIC version 2 wire version.
+#define QUICHE_PROTOCOL_VERSION_V2 0x6b3343cf
+
 // The maximum length of a connection ID.
 #define QUICHE_MAX_CONN_ID_LEN 20
 
@@ -137,6 +143,18 @@ enum quiche_error {
 
     /// An invalid DCID was used when connecting to a remote peer.
     QUICHE_ERR_INVALID_DCID_INITIALIZATION = -23,
+
+    // The version information received from the peer doesn't match the
+    // version negotiation outcome.
+    QUICHE_ERR_VERSION_NEGOTIATION = -24,
+
+    // The confidentiality or integrity limit of the AEAD algorithm used by the
+    // connection was reached.
+    QUICHE_ERR_AEAD_LIMIT_REACHED = -25,
+
+    // The provided connection snapshot cannot be decoded, because it is
+    // malformed or was created by an incompatible version of the library.
+    QUICHE_ERR_INVALID_SNAPSHOT = -26,
 };
 
 // Returns a human readable string with the quiche version number.
@@ -177,20 +195,37 @@ void quiche_config_grease(quiche_config *config, bool v);
 // Configures whether to do path MTU discovery.
 void quiche_config_discover_pmtu(quiche_config *config, bool v);
 
+// Configures whether to mark outgoing packets as ECN-capable.
+void quiche_config_enable_ecn(quiche_config *config, bool v);
+
 // Enables logging of secrets.
 void quiche_config_log_keys(quiche_config *config);
 
 // Enables sending or receiving early data.
 void quiche_config_enable_early_data(quiche_config *config);
 
+// Enables detection of replayed early data on servers, remembering the
+// ClientHellos that offered early data for the given window.
+void quiche_config_enable_anti_replay(quiche_config *config, uint64_t window_ms,
+                                      size_t capacity);
+
 // Configures the list of supported application protocols.
 int quiche_config_set_application_protos(quiche_config *config,
                                          const uint8_t *protos,
                                          size_t protos_len);
 
+// Configures the list of versions supported for compatible version
+// negotiation, in order of preference.
+int quiche_config_set_available_versions(quiche_config *config,
+                                         const uint32_t *versions,
+                                         size_t versions_len);
+
 // Sets the anti-amplification limit factor.
 void quiche_config_set_max_amplification_factor(quiche_config *config, size_t v);
 
+// Sets the maximum number of packets protected with the same 1-RTT keys.
+void quiche_config_set_max_packets_per_key(quiche_config *config, uint64_t v);
+
 // Sets the `max_idle_timeout` transport parameter, in milliseconds, default is
 // no timeout.
 void quiche_config_set_max_idle_timeout(quiche_config *config, uint64_t v);
@@ -225,6 +260,10 @@ void quiche_config_set_ack_delay_exponent(quiche_config *config, uint64_t v);
 // Sets the `max_ack_delay` transport parameter.
 void quiche_config_set_max_ack_delay(quiche_config *config, uint64_t v);
 
+// Sets the `min_ack_delay` transport parameter, enabling the ACK frequency
+// extension.
+void quiche_config_set_min_ack_delay(quiche_config *config, uint64_t v);
+
 // Sets the `disable_active_migration` transport parameter.
 void quiche_config_set_disable_active_migration(quiche_config *config, bool v);
 
@@ -239,6 +278,9 @@ enum quiche_cc_algorithm {
     QUICHE_CC_CUBIC = 1,
     QUICHE_CC_NONE = 2,
     QUICHE_CC_BBR2_GCONGESTION = 4,
+    QUICHE_CC_BBR3_GCONGESTION = 5,
+    QUICHE_CC_PRAGUE = 6,
+    QUICHE_CC_LEDBAT = 7,
 };
 
 // Sets the congestion control algorithm used.
@@ -264,6 +306,12 @@ void quiche_config_set_max_connection_window(quiche_config *config, uint64_t v);
 // Sets the maximum stream window.
 void quiche_config_set_max_stream_window(quiche_config *config, uint64_t v);
 
+// Sets the `initial_max_path_id` transport parameter, enabling multipath.
+void quiche_config_set_initial_max_path_id(quiche_config *config, uint64_t v);
+
+// Configures whether to enable the reliable stream reset extension.
+void quiche_config_enable_reset_stream_at(quiche_config *config, bool v);
+
 // Sets the limit of active connection IDs.
 void quiche_config_set_active_connection_id_limit(quiche_config *config, uint64_t v);
 
@@ -304,6 +352,10 @@ quiche_conn *quiche_connect(const char *server_name,
                             const struct sockaddr *peer, socklen_t peer_len,
                             quiche_config *config);
 
+// Restores a connection from the state exported by quiche_conn_export_state().
+quiche_conn *quiche_restore(const uint8_t *state, size_t state_len,
+                            quiche_config *config);
+
 // Writes a version negotiation packet.
 ssize_t quiche_negotiate_version(const uint8_t *scid, size_t scid_len,
                                  const uint8_t *dcid, size_t dcid_len,
@@ -354,6 +406,15 @@ int quiche_conn_set_session(quiche_conn *conn, const uint8_t *buf, size_t buf_le
 // no timeout.
 int quiche_conn_set_max_idle_timeout(quiche_conn *conn, uint64_t v);
 
+// Advertises a preferred address to the client. Either "addr_v4" or "addr_v6"
+// can be NULL, but not both. Must be called before any packet is sent or
+// received.
+int quiche_conn_set_preferred_address(quiche_conn *conn,
+                                      const struct sockaddr *addr_v4, socklen_t addr_v4_len,
+                                      const struct sockaddr *addr_v6, socklen_t addr_v6_len,
+                                      const uint8_t *scid, size_t scid_len,
+                                      const uint8_t *reset_token);
+
 typedef struct {
     // The remote address the packet was received from.
     struct sockaddr *from;
@@ -362,6 +423,9 @@ typedef struct {
     // The local address the packet was received on.
     struct sockaddr *to;
     socklen_t to_len;
+
+    // The ECN codepoint of the received packet.
+    uint8_t ecn;
 } quiche_recv_info;
 
 // Processes QUIC packets received from the peer.
@@ -379,6 +443,9 @@ typedef struct {
 
     // The time to send the packet out.
     struct timespec at;
+
+    // The ECN codepoint to set on the packet.
+    uint8_t ecn;
 } quiche_send_info;
 
 // Writes a single QUIC packet to be sent to the peer.
@@ -429,6 +496,10 @@ int quiche_conn_stream_priority(quiche_conn *conn, uint64_t stream_id,
 int quiche_conn_stream_shutdown(quiche_conn *conn, uint64_t stream_id,
                                 enum quiche_shutdown direction, uint64_t err);
 
+// Resets the stream, but still delivers data up to `reliable_size` to the peer.
+int quiche_conn_stream_reset_at(quiche_conn *conn, uint64_t stream_id,
+                                uint64_t err, uint64_t reliable_size);
+
 // Returns the stream's send capacity in bytes.
 ssize_t quiche_conn_stream_capacity(quiche_conn *conn, uint64_t stream_id);
 
@@ -451,6 +522,10 @@ int64_t quiche_conn_stream_writable_next(quiche_conn *conn);
 // Returns true if all the data has been read from the specified stream.
 bool quiche_conn_stream_finished(const quiche_conn *conn, uint64_t stream_id);
 
+// Returns true if data was received on the specified stream in 0-RTT packets.
+bool quiche_conn_stream_received_early_data(const quiche_conn *conn,
+                                            uint64_t stream_id);
+
 typedef struct quiche_stream_iter quiche_stream_iter;
 
 // Returns an iterator over streams that have outstanding data to read.
@@ -475,6 +550,9 @@ void quiche_conn_on_timeout(quiche_conn *conn);
 int quiche_conn_close(quiche_conn *conn, bool app, uint64_t err,
                       const uint8_t *reason, size_t reason_len);
 
+// Initiates an update of the 1-RTT packet protection keys.
+int quiche_conn_initiate_key_update(quiche_conn *conn);
+
 // Returns a string uniquely representing the connection.
 void quiche_conn_trace_id(const quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
@@ -506,6 +584,11 @@ void quiche_conn_peer_cert(const quiche_conn *conn, const uint8_t **out, size_t
 // Returns the serialized cryptographic session for the connection.
 void quiche_conn_session(const quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
+// Serializes the connection's state into the given buffer, so that it can be
+// restored later using quiche_restore().
+ssize_t quiche_conn_export_state(const quiche_conn *conn, uint8_t *out,
+                                 size_t out_len);
+
 // Returns the server name requested by the client.
 void quiche_conn_server_name(const quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
@@ -599,6 +682,18 @@ typedef struct {
     // The number of DATAGRAM frames sent.
     size_t dgram_sent;
 
+    // The number of outgoing DATAGRAM frames dropped because they expired
+    // before they could be sent.
+    size_t dgram_expired;
+
+    // The number of outgoing DATAGRAM frames dropped because they could
+    // never fit in a packet.
+    size_t dgram_send_dropped;
+
+    // The number of received DATAGRAM frames dropped because the receive
+    // queue was full.
+    size_t dgram_recv_dropped;
+
     // The number of known paths for the connection.
     size_t paths_count;
 
@@ -638,6 +733,15 @@ typedef struct {
     // True if the send buffer is in an inconsistent state, which could lead to
     // connection stalls  or excess buffering.
     bool tx_buffered_inconsistent;
+
+    // The number of received packets marked with ECT(0).
+    uint64_t ecn_ect0_recv_count;
+
+    // The number of received packets marked with ECT(1).
+    uint64_t ecn_ect1_recv_count;
+
+    // The number of received packets marked with ECN-CE.
+    uint64_t ecn_ce_recv_count;
 } quiche_stats;
 
 // Collects and returns statistics about the connection.
@@ -768,6 +872,18 @@ typedef struct {
     // The congestion window in bytes at the end of the startup or slow start,
     // or 0 if the connection is still in startup.
     uint64_t startup_exit_cwnd;
+
+    // Whether the path was validated as ECN-capable.
+    bool ecn_capable;
+
+    // The number of packets sent marked with ECT(0) on this path.
+    size_t ecn_ect0_sent_count;
+
+    // The number of packets sent marked with ECT(1) on this path.
+    size_t ecn_ect1_sent_count;
+
+    // The number of ECN-CE marks reported by the peer on this path.
+    uint64_t ecn_ce_reported_count;
 } quiche_path_stats;
 
 
@@ -780,6 +896,12 @@ int quiche_conn_path_stats(const quiche_conn *conn, size_t idx, quiche_path_stat
 // Returns whether or not this is a server-side connection.
 bool quiche_conn_is_server(const quiche_conn *conn);
 
+// Returns the QUIC version used by the connection.
+uint32_t quiche_conn_version(const quiche_conn *conn);
+
+// Returns the QUIC version the connection was started with.
+uint32_t quiche_conn_original_version(const quiche_conn *conn);
+
 // Returns the maximum DATAGRAM payload that can be sent.
 ssize_t quiche_conn_dgram_max_writable_len(const quiche_conn *conn);
 
@@ -806,6 +928,23 @@ ssize_t quiche_conn_dgram_recv(quiche_conn *conn, uint8_t *buf,
 ssize_t quiche_conn_dgram_send(quiche_conn *conn, const uint8_t *buf,
                                size_t buf_len);
 
+// Sends data in a DATAGRAM frame with the given urgency, tracking its fate.
+// The ID assigned to the frame is written to `out_id`.
+ssize_t quiche_conn_dgram_send_tracked(quiche_conn *conn, const uint8_t *buf,
+                                       size_t buf_len, uint8_t urgency,
+                                       uint64_t *out_id);
+
+enum quiche_dgram_event_type {
+    QUICHE_DGRAM_EVENT_ACKED,
+    QUICHE_DGRAM_EVENT_LOST,
+    QUICHE_DGRAM_EVENT_DROPPED,
+};
+
+// Retrieves the next event about a tracked DATAGRAM frame, writing the
+// frame's ID to `out_id`. Returns the event type, or QUICHE_ERR_DONE if there
+// is no event to process.
+int quiche_conn_dgram_event_next(quiche_conn *conn, uint64_t *out_id);
+
 // Purges queued outgoing DATAGRAMs matching the predicate.
 void quiche_conn_dgram_purge_outgoing(quiche_conn *conn,
                                       bool (*f)(uint8_t *, size_t));
@@ -1019,6 +1158,12 @@ enum quiche_h3_error {
     // over HTTP/1.1.
     QUICHE_H3_ERR_VERSION_FALLBACK = -20,
 
+    // Error on the QPACK encoder stream.
+    QUICHE_H3_ERR_QPACK_ENCODER_STREAM_ERROR = -21,
+
+    // Error on the QPACK decoder stream.
+    QUICHE_H3_ERR_QPACK_DECODER_STREAM_ERROR = -22,
+
     // The following QUICHE_H3_TRANSPORT_ERR_* errors are propagated
     // from the QUIC transport layer.
 
@@ -1104,9 +1249,15 @@ void quiche_h3_config_set_qpack_max_table_capacity(quiche_h3_config *config, uin
 // Sets the `SETTINGS_QPACK_BLOCKED_STREAMS` setting.
 void quiche_h3_config_set_qpack_blocked_streams(quiche_h3_config *config, uint64_t v);
 
+// Sets the maximum capacity of the dynamic table used by the QPACK encoder.
+void quiche_h3_config_set_qpack_encoder_max_table_capacity(quiche_h3_config *config, uint64_t v);
+
 // Sets the `SETTINGS_ENABLE_CONNECT_PROTOCOL` setting.
 void quiche_h3_config_enable_extended_connect(quiche_h3_config *config, bool enabled);
 
+// Sets the `SETTINGS_WT_MAX_SESSIONS` setting, enabling WebTransport when non-zero.
+void quiche_h3_config_set_webtransport_max_sessions(quiche_h3_config *config, uint64_t v);
+
 // Frees the HTTP/3 config object.
 void quiche_h3_config_free(quiche_h3_config *config);
 
@@ -1117,6 +1268,16 @@ typedef struct quiche_h3_conn quiche_h3_conn;
 quiche_h3_conn *quiche_h3_conn_new_with_transport(quiche_conn *quiche_conn,
                                                   quiche_h3_config *config);
 
+// Restores an HTTP/3 connection from the state exported by
+// quiche_h3_conn_export_state(), on top of the restored QUIC connection.
+quiche_h3_conn *quiche_h3_conn_restore(const quiche_conn *quiche_conn,
+                                       const uint8_t *state, size_t state_len);
+
+// Serializes the HTTP/3 connection's state into the given buffer, so that it
+// can be restored later using quiche_h3_conn_restore().
+ssize_t quiche_h3_conn_export_state(const quiche_h3_conn *conn, uint8_t *out,
+                                    size_t out_len);
+
 enum quiche_h3_event_type {
     QUICHE_H3_EVENT_HEADERS,
     QUICHE_H3_EVENT_DATA,
@@ -1124,6 +1285,9 @@ enum quiche_h3_event_type {
     QUICHE_H3_EVENT_GOAWAY,
     QUICHE_H3_EVENT_RESET,
     QUICHE_H3_EVENT_PRIORITY_UPDATE,
+    QUICHE_H3_EVENT_PUSH_PROMISE,
+    QUICHE_H3_EVENT_CANCEL_PUSH,
+    QUICHE_H3_EVENT_WEBTRANSPORT_STREAM,
 };
 
 typedef struct quiche_h3_event quiche_h3_event;
@@ -1160,9 +1324,18 @@ int quiche_h3_for_each_setting(quiche_h3_conn *conn,
 // Check whether more frames will follow the headers on the stream.
 bool quiche_h3_event_headers_has_more_frames(quiche_h3_event *ev);
 
+// Returns the push ID of a QUICHE_H3_EVENT_PUSH_PROMISE event.
+uint64_t quiche_h3_event_push_promise_id(quiche_h3_event *ev);
+
+// Returns the session ID of a QUICHE_H3_EVENT_WEBTRANSPORT_STREAM event.
+uint64_t quiche_h3_event_webtransport_session_id(quiche_h3_event *ev);
+
 // Check whether or not extended connection is enabled by the peer
 bool quiche_h3_extended_connect_enabled_by_peer(quiche_h3_conn *conn);
 
+// Check whether or not WebTransport is enabled by the peer.
+bool quiche_h3_webtransport_enabled_by_peer(quiche_h3_conn *conn);
+
 // Frees the HTTP/3 event object.
 void quiche_h3_event_free(quiche_h3_event *ev);
 
@@ -1185,6 +1358,12 @@ int64_t quiche_h3_send_request(quiche_h3_conn *conn, quiche_conn *quic_conn,
                                const quiche_h3_header *headers, size_t headers_len,
                                bool fin);
 
+// Opens a WebTransport stream associated with the given session.
+int64_t quiche_h3_open_webtransport_stream(quiche_h3_conn *conn,
+                                           quiche_conn *quic_conn,
+                                           uint64_t session_id,
+                                           bool bidirectional);
+
 // Sends an HTTP/3 response on the specified stream with default priority.
 int quiche_h3_send_response(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, const quiche_h3_header *headers,
@@ -1215,6 +1394,33 @@ ssize_t quiche_h3_recv_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
 int quiche_h3_send_goaway(quiche_h3_conn *conn, quiche_conn *quic_conn,
                           uint64_t id);
 
+// Sends a MAX_PUSH_ID frame to allow the server to push responses.
+int quiche_h3_send_max_push_id(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                               uint64_t push_id);
+
+// Sends a PUSH_PROMISE frame on the specified request stream.
+int64_t quiche_h3_send_push_promise(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                                    uint64_t stream_id,
+                                    const quiche_h3_header *headers,
+                                    size_t headers_len);
+
+// Sends the response of a promised push on a new push stream.
+int64_t quiche_h3_send_push_response(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                                     uint64_t push_id,
+                                     const quiche_h3_header *headers,
+                                     size_t headers_len, bool fin);
+
+// Cancels a server push.
+int quiche_h3_cancel_push(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                          uint64_t push_id);
+
+// Returns the push ID of the specified push stream, if any.
+bool quiche_h3_push_id(quiche_h3_conn *conn, uint64_t stream_id, uint64_t *out);
+
+// Returns true if the request on the specified stream was received in early
+// data.
+bool quiche_h3_is_early_data_request(quiche_h3_conn *conn, uint64_t stream_id);
+
 // Try to parse an Extensible Priority field value.
 int quiche_h3_parse_extensible_priority(uint8_t *priority,
                                         size_t priority_len,
@@ -0,0 +1,537 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! ACK frequency extension ([draft-ietf-quic-ack-frequency]).
+//!
+//! Once the peer sent an ACK_FREQUENCY frame, ACKs in the application packet
+//! number space are delayed until the requested number of ack-eliciting
+//! packets has been received, or until the requested maximum ACK delay
+//! elapsed. Packets carrying an IMMEDIATE_ACK frame, reordered packets and
+//! packets marked with ECN-CE are still acknowledged right away.
+//!
+//! On the sending side, the congestion controller can ask the peer for less
+//! frequent ACKs, which are then requested with ACK_FREQUENCY frames.
+//!
+//! [draft-ietf-quic-ack-frequency]: https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::frame;
+use crate::snapshot;
+use crate::Result;
+
+/// The reordering threshold requested when ACKs are made less frequent, so
+/// that the peer still reports gaps before loss detection kicks in.
+const REORDERING_THRESHOLD: u64 = 2;
+
+/// The ACK frequency parameters carried by an ACK_FREQUENCY frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AckFrequencyParams {
+    /// The number of ack-eliciting packets that can be received without
+    /// sending an ACK.
+    pub ack_eliciting_threshold: u64,
+
+    /// The maximum amount of time an ACK can be delayed.
+    pub max_ack_delay: Duration,
+
+    /// The packet reordering that triggers an immediate ACK, or 0 to never
+    /// send ACKs immediately because of reordering.
+    pub reordering_threshold: u64,
+}
+
+/// Tracks the ACK frequency state of a connection, both as requested by the
+/// peer and as requested from it.
+#[derive(Debug, Default)]
+pub struct AckFrequency {
+    /// The parameters requested by the peer, and the sequence number of the
+    /// ACK_FREQUENCY frame that carried them.
+    recv_params: Option<(u64, AckFrequencyParams)>,
+
+    /// The number of ack-eliciting packets received since the last ACK was
+    /// sent.
+    ack_eliciting_count: u64,
+
+    /// Whether the packet being processed carried an IMMEDIATE_ACK frame.
+    immediate_ack: bool,
+
+    /// The time at which a delayed ACK needs to be sent.
+    ack_timer: Option<Instant>,
+
+    /// The sequence number of the next ACK_FREQUENCY frame to send.
+    next_seq_num: u64,
+
+    /// The parameters requested from the peer, and the sequence number of the
+    /// ACK_FREQUENCY frame that carried them.
+    sent_params: Option<(u64, AckFrequencyParams)>,
+
+    /// Whether the requested parameters still need to be sent.
+    send_pending: bool,
+}
+
+impl AckFrequency {
+    /// Processes an ACK_FREQUENCY frame received from the peer.
+    pub fn on_ack_frequency(&mut self, seq_num: u64, params: AckFrequencyParams) {
+        // Ignore frames that are older than the ones already processed.
+        if self.recv_params.is_some_and(|(s, _)| seq_num <= s) {
+            return;
+        }
+
+        self.recv_params = Some((seq_num, params));
+    }
+
+    /// Processes an IMMEDIATE_ACK frame received from the peer.
+    pub fn on_immediate_ack(&mut self) {
+        self.immediate_ack = true;
+    }
+
+    /// Records that an application packet was received, and returns whether
+    /// it needs to be acknowledged right away.
+    ///
+    /// `largest_rx_pkt_num` is the largest packet number received before this
+    /// one. When the ACK is delayed instead, the ACK timer is armed.
+    pub fn on_packet_received(
+        &mut self, pkt_num: u64, largest_rx_pkt_num: u64, ack_eliciting: bool,
+        ce: bool, now: Instant,
+    ) -> bool {
+        let immediate_ack = std::mem::take(&mut self.immediate_ack);
+
+        if !ack_eliciting {
+            return false;
+        }
+
+        // Acknowledge every packet until the peer asks otherwise.
+        let params = match self.recv_params {
+            Some((_, params)) => params,
+
+            None => return true,
+        };
+
+        self.ack_eliciting_count += 1;
+
+        let reordered = params.reordering_threshold > 0 &&
+            (pkt_num < largest_rx_pkt_num ||
+                pkt_num - largest_rx_pkt_num > params.reordering_threshold);
+
+        if immediate_ack ||
+            ce ||
+            reordered ||
+            self.ack_eliciting_count > params.ack_eliciting_threshold
+        {
+            return true;
+        }
+
+        if self.ack_timer.is_none() {
+            self.ack_timer = Some(now + params.max_ack_delay);
+        }
+
+        false
+    }
+
+    /// Records that an ACK was sent for the application packet number space.
+    pub fn on_ack_sent(&mut self) {
+        self.ack_eliciting_count = 0;
+        self.ack_timer = None;
+    }
+
+    /// Returns the time at which a delayed ACK needs to be sent, if any.
+    pub fn ack_timer(&self) -> Option<Instant> {
+        self.ack_timer
+    }
+
+    /// Returns whether an ACK is being delayed.
+    pub fn ack_pending(&self) -> bool {
+        self.ack_timer.is_some()
+    }
+
+    /// Checks whether the ACK timer expired, in which case the delayed ACK
+    /// needs to be sent.
+    pub fn on_timeout(&mut self, now: Instant) -> bool {
+        if self.ack_timer.is_some_and(|timer| timer <= now) {
+            self.ack_timer = None;
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns the parameters currently requested from the peer.
+    pub fn requested_params(&self) -> Option<AckFrequencyParams> {
+        self.sent_params.map(|(_, params)| params)
+    }
+
+    /// Updates the parameters requested from the peer, given the
+    /// ack-eliciting threshold wanted by the congestion controller and the
+    /// ACK delay bounds advertised by the peer.
+    pub fn update_request(
+        &mut self, threshold: Option<u64>, min_rtt: Option<Duration>,
+        peer_min_ack_delay: Duration, peer_max_ack_delay: Duration,
+    ) {
+        let params = match threshold {
+            Some(ack_eliciting_threshold) => AckFrequencyParams {
+                ack_eliciting_threshold,
+
+                // Still get a few ACKs per round trip when the flight is
+                // smaller than the threshold.
+                max_ack_delay: min_rtt
+                    .map_or(peer_max_ack_delay, |min_rtt| min_rtt / 4)
+                    .clamp(peer_min_ack_delay, peer_max_ack_delay),
+
+                reordering_threshold: REORDERING_THRESHOLD,
+            },
+
+            // Restore the default behavior if anything else was requested.
+            None if self.sent_params.is_some() => AckFrequencyParams {
+                ack_eliciting_threshold: 1,
+                max_ack_delay: peer_max_ack_delay,
+                reordering_threshold: 1,
+            },
+
+            None => return,
+        };
+
+        self.request(params);
+    }
+
+    /// Requests new parameters from the peer, if they differ from the ones
+    /// already requested.
+    fn request(&mut self, params: AckFrequencyParams) {
+        if self.requested_params() == Some(params) {
+            return;
+        }
+
+        self.sent_params = Some((self.next_seq_num, params));
+        self.next_seq_num += 1;
+
+        self.send_pending = true;
+    }
+
+    /// Returns whether an ACK_FREQUENCY frame needs to be sent.
+    pub fn should_send(&self) -> bool {
+        self.send_pending
+    }
+
+    /// Returns the ACK_FREQUENCY frame to send, if any.
+    pub fn frame(&self) -> Option<frame::Frame> {
+        if !self.send_pending {
+            return None;
+        }
+
+        self.sent_params
+            .map(|(seq_num, params)| frame::Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold: params.ack_eliciting_threshold,
+                request_max_ack_delay: params.max_ack_delay.as_micros() as u64,
+                reordering_threshold: params.reordering_threshold,
+            })
+    }
+
+    /// Records that the pending ACK_FREQUENCY frame was sent.
+    pub fn on_frame_sent(&mut self) {
+        self.send_pending = false;
+    }
+
+    /// Records that the ACK_FREQUENCY frame with the given sequence number was
+    /// lost. It is only sent again if no newer frame superseded it.
+    pub fn on_frame_lost(&mut self, seq_num: u64) {
+        if self.sent_params.is_some_and(|(s, _)| s == seq_num) {
+            self.send_pending = true;
+        }
+    }
+
+    /// Writes the ACK frequency state to a connection snapshot.
+    ///
+    /// The ACK timer is recorded relative to `now`.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer, now: Instant) {
+        for params in [&self.recv_params, &self.sent_params] {
+            w.put_bool(params.is_some());
+
+            if let Some((seq_num, params)) = params {
+                w.put_u64(*seq_num);
+                w.put_u64(params.ack_eliciting_threshold);
+                w.put_u64(params.max_ack_delay.as_micros() as u64);
+                w.put_u64(params.reordering_threshold);
+            }
+        }
+
+        w.put_u64(self.ack_eliciting_count);
+        w.put_bool(self.ack_timer.is_some());
+        w.put_instant(self.ack_timer.unwrap_or(now), now);
+        w.put_u64(self.next_seq_num);
+    }
+
+    /// Reads ACK frequency state written by [`to_snapshot()`].
+    ///
+    /// As ACK_FREQUENCY frames in flight are not part of the snapshot, the
+    /// parameters requested from the peer are sent again.
+    ///
+    /// [`to_snapshot()`]: struct.AckFrequency.html#method.to_snapshot
+    pub fn from_snapshot(
+        r: &mut snapshot::Reader, now: Instant,
+    ) -> Result<AckFrequency> {
+        let mut read_params = || -> Result<_> {
+            if !r.get_bool()? {
+                return Ok(None);
+            }
+
+            Ok(Some((r.get_u64()?, AckFrequencyParams {
+                ack_eliciting_threshold: r.get_u64()?,
+                max_ack_delay: Duration::from_micros(r.get_u64()?),
+                reordering_threshold: r.get_u64()?,
+            })))
+        };
+
+        let recv_params = read_params()?;
+        let sent_params = read_params()?;
+
+        let ack_eliciting_count = r.get_u64()?;
+        let has_ack_timer = r.get_bool()?;
+        let ack_timer = r.get_instant(now)?;
+        let send_pending = sent_params.is_some();
+
+        Ok(AckFrequency {
+            recv_params,
+            ack_eliciting_count,
+            immediate_ack: false,
+            ack_timer: has_ack_timer.then_some(ack_timer),
+            next_seq_num: r.get_u64()?,
+            sent_params,
+            send_pending,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(
+        ack_eliciting_threshold: u64, reordering_threshold: u64,
+    ) -> AckFrequencyParams {
+        AckFrequencyParams {
+            ack_eliciting_threshold,
+            max_ack_delay: Duration::from_millis(10),
+            reordering_threshold,
+        }
+    }
+
+    #[test]
+    fn default_acks_immediately() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        assert!(af.on_packet_received(1, 0, true, false, now));
+        assert!(!af.on_packet_received(2, 1, false, false, now));
+        assert!(!af.ack_pending());
+    }
+
+    #[test]
+    fn threshold() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_ack_frequency(0, params(2, 0));
+
+        assert!(!af.on_packet_received(1, 0, true, false, now));
+        assert_eq!(af.ack_timer(), Some(now + Duration::from_millis(10)));
+
+        // The timer isn't re-armed by later packets.
+        let later = now + Duration::from_millis(1);
+        assert!(!af.on_packet_received(2, 1, true, false, later));
+        assert_eq!(af.ack_timer(), Some(now + Duration::from_millis(10)));
+
+        // Non ack-eliciting packets don't count.
+        assert!(!af.on_packet_received(3, 2, false, false, later));
+
+        assert!(af.on_packet_received(4, 3, true, false, later));
+
+        af.on_ack_sent();
+        assert!(!af.ack_pending());
+
+        assert!(!af.on_packet_received(5, 4, true, false, later));
+        assert!(af.ack_pending());
+    }
+
+    #[test]
+    fn timeout() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_ack_frequency(0, params(10, 0));
+
+        assert!(!af.on_packet_received(1, 0, true, false, now));
+
+        assert!(!af.on_timeout(now + Duration::from_millis(5)));
+        assert!(af.on_timeout(now + Duration::from_millis(10)));
+        assert_eq!(af.ack_timer(), None);
+    }
+
+    #[test]
+    fn immediate_ack_and_ce() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_ack_frequency(0, params(10, 0));
+
+        af.on_immediate_ack();
+        assert!(af.on_packet_received(1, 0, true, false, now));
+
+        // The IMMEDIATE_ACK frame only applies to the packet carrying it.
+        assert!(!af.on_packet_received(2, 1, true, false, now));
+
+        assert!(af.on_packet_received(3, 2, true, true, now));
+    }
+
+    #[test]
+    fn reordering() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_ack_frequency(0, params(10, 2));
+
+        assert!(!af.on_packet_received(11, 10, true, false, now));
+        assert!(!af.on_packet_received(13, 11, true, false, now));
+        assert!(af.on_packet_received(16, 13, true, false, now));
+        assert!(af.on_packet_received(12, 16, true, false, now));
+
+        // Reordering is ignored with a threshold of 0.
+        af.on_ack_frequency(1, params(10, 0));
+        assert!(!af.on_packet_received(14, 16, true, false, now));
+        assert!(!af.on_packet_received(30, 16, true, false, now));
+    }
+
+    #[test]
+    fn out_of_order_frames() {
+        let mut af = AckFrequency::default();
+        let now = Instant::now();
+
+        af.on_ack_frequency(5, params(10, 0));
+        af.on_ack_frequency(4, params(0, 0));
+
+        assert!(!af.on_packet_received(1, 0, true, false, now));
+    }
+
+    #[test]
+    fn update_request() {
+        let mut af = AckFrequency::default();
+
+        let min_ack_delay = Duration::from_millis(1);
+        let max_ack_delay = Duration::from_millis(25);
+
+        // Nothing is requested until the congestion controller asks for it.
+        af.update_request(None, None, min_ack_delay, max_ack_delay);
+        assert!(!af.should_send());
+
+        af.update_request(
+            Some(5),
+            Some(Duration::from_millis(40)),
+            min_ack_delay,
+            max_ack_delay,
+        );
+        assert_eq!(
+            af.requested_params(),
+            Some(AckFrequencyParams {
+                ack_eliciting_threshold: 5,
+                max_ack_delay: Duration::from_millis(10),
+                reordering_threshold: REORDERING_THRESHOLD,
+            })
+        );
+
+        // The delay is bounded by the peer's limits.
+        af.update_request(
+            Some(5),
+            Some(Duration::from_micros(100)),
+            min_ack_delay,
+            max_ack_delay,
+        );
+        assert_eq!(af.requested_params().unwrap().max_ack_delay, min_ack_delay);
+
+        af.update_request(
+            Some(5),
+            Some(Duration::from_secs(1)),
+            min_ack_delay,
+            max_ack_delay,
+        );
+        assert_eq!(af.requested_params().unwrap().max_ack_delay, max_ack_delay);
+
+        af.update_request(None, None, min_ack_delay, max_ack_delay);
+        assert_eq!(
+            af.requested_params(),
+            Some(AckFrequencyParams {
+                ack_eliciting_threshold: 1,
+                max_ack_delay,
+                reordering_threshold: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn request() {
+        let mut af = AckFrequency::default();
+
+        assert!(!af.should_send());
+        assert_eq!(af.frame(), None);
+
+        af.request(params(4, 2));
+        assert!(af.should_send());
+        assert_eq!(
+            af.frame(),
+            Some(frame::Frame::AckFrequency {
+                seq_num: 0,
+                ack_eliciting_threshold: 4,
+                request_max_ack_delay: 10_000,
+                reordering_threshold: 2,
+            })
+        );
+
+        af.on_frame_sent();
+        assert!(!af.should_send());
+
+        // Requesting the same parameters doesn't generate a new frame.
+        af.request(params(4, 2));
+        assert!(!af.should_send());
+
+        // Only the latest frame is retransmitted.
+        af.request(params(8, 2));
+        af.on_frame_sent();
+
+        af.on_frame_lost(0);
+        assert!(!af.should_send());
+
+        af.on_frame_lost(1);
+        assert!(af.should_send());
+        assert_eq!(
+            af.frame(),
+            Some(frame::Frame::AckFrequency {
+                seq_num: 1,
+                ack_eliciting_threshold: 8,
+                request_max_ack_delay: 10_000,
+                reordering_threshold: 2,
+            })
+        );
+    }
+}
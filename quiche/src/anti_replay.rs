@@ -0,0 +1,230 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Anti-replay protection for 0-RTT data.
+//!
+//! Early data sent by clients is not protected against replay by TLS itself
+//! ([RFC 8446 Section 8]): an attacker that captured a client's first flight
+//! can send it again, possibly to a different server instance sharing the
+//! same session ticket keys, and have the request executed twice.
+//!
+//! Servers can guard against this by configuring an [`AntiReplay`] store with
+//! [`Config::set_anti_replay()`]. Every ClientHello offering early data is
+//! then checked against the store before being handed to TLS, and early data
+//! is rejected for ClientHellos that were already seen. The client falls back
+//! to sending its data again after the handshake, in 1-RTT packets.
+//!
+//! [RFC 8446 Section 8]: https://www.rfc-editor.org/rfc/rfc8446#section-8
+//! [`Config::set_anti_replay()`]: ../struct.Config.html#method.set_anti_replay
+
+use std::hash::BuildHasher;
+use std::hash::RandomState;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::client_hello;
+
+/// The number of filter bits used per entry.
+const BITS_PER_ENTRY: usize = 10;
+
+/// The number of bits set per entry, optimal for [`BITS_PER_ENTRY`].
+const HASH_COUNT: u64 = 7;
+
+/// A store recording the ClientHellos that offered early data.
+///
+/// Implementations are shared between all the connections created from the
+/// same [`Config`], and can be backed by storage shared between multiple
+/// server instances, so that a ClientHello replayed to a different instance
+/// is also detected.
+///
+/// [`Config`]: ../struct.Config.html
+pub trait AntiReplay: Send + Sync {
+    /// Records the given ClientHello identifier, returning `false` if it was
+    /// already recorded.
+    ///
+    /// Returning `false` makes the server reject early data for the
+    /// connection. False positives are safe, as they only cost the client an
+    /// extra round-trip, but false negatives allow the early data to be
+    /// replayed.
+    fn check(&self, id: &[u8], now: Instant) -> bool;
+}
+
+/// An [`AntiReplay`] store based on a pair of rotating bloom filters.
+///
+/// Identifiers are recorded in the current filter, and checked against both
+/// the current and previous ones. Once the window elapses the current filter
+/// becomes the previous one, so an identifier is remembered for at least the
+/// configured window, and at most twice that.
+///
+/// Replays of ClientHellos older than the window are not detected, so the
+/// window needs to cover the lifetime of the session tickets issued by the
+/// server.
+pub struct BloomAntiReplay {
+    window: Duration,
+
+    hasher: RandomState,
+
+    filters: Mutex<Filters>,
+}
+
+struct Filters {
+    current: Vec<u64>,
+
+    previous: Vec<u64>,
+
+    rotated_at: Option<Instant>,
+}
+
+impl BloomAntiReplay {
+    /// Creates a store remembering identifiers for the given window.
+    ///
+    /// The `capacity` is the expected number of ClientHellos offering early
+    /// data received during a window, and sizes the filters so that the false
+    /// positive rate stays around 1% up to that number.
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        let words = capacity.max(1).saturating_mul(BITS_PER_ENTRY).div_ceil(64);
+
+        BloomAntiReplay {
+            window,
+
+            hasher: RandomState::new(),
+
+            filters: Mutex::new(Filters {
+                current: vec![0; words],
+                previous: vec![0; words],
+                rotated_at: None,
+            }),
+        }
+    }
+
+    fn rotate(&self, filters: &mut Filters, now: Instant) {
+        let rotated_at = *filters.rotated_at.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(rotated_at);
+
+        if elapsed < self.window {
+            return;
+        }
+
+        if elapsed >= self.window * 2 {
+            filters.previous.fill(0);
+        } else {
+            std::mem::swap(&mut filters.current, &mut filters.previous);
+        }
+
+        filters.current.fill(0);
+        filters.rotated_at = Some(now);
+    }
+}
+
+impl AntiReplay for BloomAntiReplay {
+    fn check(&self, id: &[u8], now: Instant) -> bool {
+        let mut filters = self.filters.lock().unwrap();
+
+        self.rotate(&mut filters, now);
+
+        let bits = filters.current.len() as u64 * 64;
+
+        // Derive all the bit positions from two hashes, as per Kirsch and
+        // Mitzenmacher.
+        let h1 = self.hasher.hash_one((0u8, id));
+        let h2 = self.hasher.hash_one((1u8, id)) | 1;
+
+        let mut in_current = true;
+        let mut in_previous = true;
+
+        for i in 0..HASH_COUNT {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % bits;
+
+            let word = (bit / 64) as usize;
+            let mask = 1 << (bit % 64);
+
+            in_current &= filters.current[word] & mask != 0;
+            in_previous &= filters.previous[word] & mask != 0;
+
+            filters.current[word] |= mask;
+        }
+
+        !(in_current || in_previous)
+    }
+}
+
+/// Returns the random of the given ClientHello if it offers early data.
+pub(crate) fn early_data_client_random(buf: &[u8]) -> Option<&[u8]> {
+    client_hello::extension(buf, client_hello::EARLY_DATA)?;
+
+    client_hello::random(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_detects_replay() {
+        let now = Instant::now();
+        let store = BloomAntiReplay::new(Duration::from_secs(10), 100);
+
+        assert!(store.check(b"hello", now));
+        assert!(store.check(b"world", now));
+
+        assert!(!store.check(b"hello", now));
+        assert!(!store.check(b"world", now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn bloom_window() {
+        let now = Instant::now();
+        let window = Duration::from_secs(10);
+        let store = BloomAntiReplay::new(window, 100);
+
+        assert!(store.check(b"hello", now));
+
+        // Still remembered in the previous filter after a rotation.
+        assert!(store.check(b"world", now + window));
+        assert!(!store.check(b"hello", now + window));
+
+        // Forgotten after two windows.
+        assert!(store.check(b"hello", now + window * 3));
+        assert!(!store.check(b"hello", now + window * 3));
+    }
+
+    #[test]
+    fn bloom_false_positive_rate() {
+        let now = Instant::now();
+        let store = BloomAntiReplay::new(Duration::from_secs(10), 1000);
+
+        for i in 0..1000u32 {
+            store.check(&i.to_be_bytes(), now);
+        }
+
+        let false_positives = (1000..1100u32)
+            .filter(|i| !store.check(&i.to_be_bytes(), now))
+            .count();
+
+        assert!(false_positives < 10, "{false_positives}");
+    }
+}
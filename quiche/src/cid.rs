@@ -29,6 +29,7 @@ use crate::Result;
 use std::cmp;
 
 use crate::frame;
+use crate::snapshot;
 
 use crate::packet::ConnectionId;
 
@@ -823,6 +824,110 @@ impl ConnectionIdentifiers {
     pub fn pop_retired_scid(&mut self) -> Option<ConnectionId<'static>> {
         self.retired_scids.pop_front()
     }
+
+    /// Writes the Connection IDs to a connection snapshot.
+    ///
+    /// Only the association of Connection IDs with `active_path_id` is
+    /// recorded, as other paths are not part of the snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer, active_path_id: usize) {
+        for cids in [&self.dcids, &self.scids] {
+            w.put_u64(cids.capacity as u64);
+            w.put_u64(cids.len() as u64);
+
+            for e in cids.iter() {
+                w.put_bytes(&e.cid);
+                w.put_u64(e.seq);
+                w.put_opt_u128(e.reset_token);
+                w.put_bool(e.path_id == Some(active_path_id));
+            }
+        }
+
+        w.put_u64(self.retire_dcid_seqs.capacity as u64);
+        w.put_u64(self.retire_dcid_seqs.inner.len() as u64);
+        for seq in &self.retire_dcid_seqs.inner {
+            w.put_u64(*seq);
+        }
+
+        w.put_u64(self.retired_scids.len() as u64);
+        for cid in &self.retired_scids {
+            w.put_bytes(cid);
+        }
+
+        w.put_u64(self.largest_peer_retire_prior_to);
+        w.put_u64(self.largest_destination_seq);
+        w.put_u64(self.next_scid_seq);
+        w.put_u64(self.retire_prior_to);
+        w.put_u64(self.source_conn_id_limit as u64);
+        w.put_bool(self.zero_length_scid);
+        w.put_bool(self.zero_length_dcid);
+    }
+
+    /// Reads Connection IDs written by [`to_snapshot()`], associating them
+    /// with `active_path_id` where needed.
+    ///
+    /// As NEW_CONNECTION_ID frames in flight are not part of the snapshot, all
+    /// the source Connection IDs but the initial one are advertised again.
+    ///
+    /// [`to_snapshot()`]: struct.ConnectionIdentifiers.html#method.to_snapshot
+    pub fn from_snapshot(
+        r: &mut snapshot::Reader, active_path_id: usize,
+    ) -> Result<ConnectionIdentifiers> {
+        let read_cids = |r: &mut snapshot::Reader| {
+            let capacity = r.get_u64()? as usize;
+
+            let mut inner = VecDeque::new();
+
+            for _ in 0..r.get_count(18)? {
+                inner.push_back(ConnectionIdEntry {
+                    cid: ConnectionId::from_vec(r.get_bytes()?.to_vec()),
+                    seq: r.get_u64()?,
+                    reset_token: r.get_opt_u128()?,
+                    path_id: r.get_bool()?.then_some(active_path_id),
+                });
+            }
+
+            if inner.is_empty() || inner.len() > capacity {
+                return Err(Error::InvalidSnapshot);
+            }
+
+            Ok(BoundedNonEmptyConnectionIdVecDeque { inner, capacity })
+        };
+
+        let dcids = read_cids(r)?;
+        let scids = read_cids(r)?;
+
+        let advertise_new_scid_seqs =
+            scids.iter().map(|e| e.seq).filter(|seq| *seq > 0).collect();
+
+        let mut retire_dcid_seqs =
+            BoundedConnectionIdSeqSet::new(r.get_u64()? as usize);
+        for _ in 0..r.get_count(8)? {
+            retire_dcid_seqs
+                .insert(r.get_u64()?)
+                .map_err(snapshot::decode_error)?;
+        }
+
+        let mut retired_scids = VecDeque::new();
+        for _ in 0..r.get_count(8)? {
+            retired_scids
+                .push_back(ConnectionId::from_vec(r.get_bytes()?.to_vec()));
+        }
+
+        Ok(ConnectionIdentifiers {
+            dcids,
+            scids,
+            advertise_new_scid_seqs,
+            retire_dcid_seqs,
+            retired_scids,
+            largest_peer_retire_prior_to: r.get_u64()?,
+            largest_destination_seq: r.get_u64()?,
+            next_scid_seq: r.get_u64()?,
+            retire_prior_to: r.get_u64()?,
+            source_conn_id_limit: r.get_u64()? as usize,
+            zero_length_scid: r.get_bool()?,
+            zero_length_dcid: r.get_bool()?,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,165 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Minimal parsing of TLS ClientHello messages.
+//!
+//! The server buffers the client's ClientHello before handing it to TLS, so
+//! that it can be checked against the anti-replay store and inspected for
+//! compatible version negotiation first. Only the fields needed for that are
+//! parsed here, anything malformed is left for TLS to fail on.
+
+/// The TLS handshake message type of a ClientHello.
+const CLIENT_HELLO: u8 = 1;
+
+/// The length of the ClientHello random.
+const RANDOM_LEN: usize = 32;
+
+/// The TLS extension type of the early_data extension.
+pub(crate) const EARLY_DATA: u16 = 42;
+
+/// The TLS extension type of the quic_transport_parameters extension.
+pub(crate) const QUIC_TRANSPORT_PARAMETERS: u16 = 57;
+
+/// Returns the length of the ClientHello message at the start of `buf`, if
+/// its header is complete.
+pub(crate) fn len(buf: &[u8]) -> Option<usize> {
+    let mut b = octets::Octets::with_slice(buf);
+
+    if b.get_u8().ok()? != CLIENT_HELLO {
+        return None;
+    }
+
+    let len = b.get_u24().ok()?;
+
+    Some(4 + len as usize)
+}
+
+/// Returns the random of the given ClientHello.
+pub(crate) fn random(client_hello: &[u8]) -> Option<&[u8]> {
+    parse(client_hello).map(|(random, _)| random)
+}
+
+/// Returns the data of the extension of type `ty` in the given ClientHello,
+/// if present.
+pub(crate) fn extension(client_hello: &[u8], ty: u16) -> Option<&[u8]> {
+    let (_, mut exts) = parse(client_hello)?;
+
+    while exts.cap() > 0 {
+        let ext_ty = exts.get_u16().ok()?;
+        let data = exts.get_bytes_with_u16_length().ok()?;
+
+        if ext_ty == ty {
+            return Some(data.buf());
+        }
+    }
+
+    None
+}
+
+/// Splits the given ClientHello into its random and its extensions.
+fn parse(client_hello: &[u8]) -> Option<(&[u8], octets::Octets<'_>)> {
+    let mut b = octets::Octets::with_slice(client_hello);
+
+    if b.get_u8().ok()? != CLIENT_HELLO {
+        return None;
+    }
+
+    let len = b.get_u24().ok()? as usize;
+    let mut b = b.get_bytes(len).ok()?;
+
+    // legacy_version
+    b.skip(2).ok()?;
+
+    let random = b.get_bytes(RANDOM_LEN).ok()?.buf();
+
+    // legacy_session_id, cipher_suites and legacy_compression_methods
+    b.get_bytes_with_u8_length().ok()?;
+    b.get_bytes_with_u16_length().ok()?;
+    b.get_bytes_with_u8_length().ok()?;
+
+    let exts = b.get_bytes_with_u16_length().ok()?;
+
+    Some((random, exts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_hello(
+        client_random: &[u8; RANDOM_LEN], exts: &[(u16, &[u8])],
+    ) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(client_random);
+
+        // Empty session ID, a single cipher suite and null compression.
+        body.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+
+        let mut ext_buf = Vec::new();
+
+        for (ty, data) in exts {
+            ext_buf.extend_from_slice(&ty.to_be_bytes());
+            ext_buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            ext_buf.extend_from_slice(data);
+        }
+
+        body.extend_from_slice(&(ext_buf.len() as u16).to_be_bytes());
+        body.extend_from_slice(&ext_buf);
+
+        let mut msg = vec![CLIENT_HELLO];
+        msg.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        msg.extend_from_slice(&body);
+        msg
+    }
+
+    #[test]
+    fn parse_client_hello() {
+        let client_random = [0xab; RANDOM_LEN];
+
+        let ch =
+            client_hello(&client_random, &[(0, b"\x00\x01a"), (57, b"\x01\x02")]);
+        assert_eq!(len(&ch), Some(ch.len()));
+        assert_eq!(len(&ch[..3]), None);
+        assert_eq!(random(&ch), Some(&client_random[..]));
+        assert_eq!(extension(&ch, 0), Some(&b"\x00\x01a"[..]));
+        assert_eq!(
+            extension(&ch, QUIC_TRANSPORT_PARAMETERS),
+            Some(&b"\x01\x02"[..])
+        );
+        assert_eq!(extension(&ch, EARLY_DATA), None);
+
+        // Truncated message.
+        assert_eq!(random(&ch[..ch.len() - 1]), None);
+        assert_eq!(extension(&ch[..ch.len() - 1], 0), None);
+
+        // Not a ClientHello.
+        let mut ch = client_hello(&client_random, &[(42, b"")]);
+        ch[0] = 2;
+        assert_eq!(len(&ch), None);
+        assert_eq!(random(&ch), None);
+        assert_eq!(extension(&ch, EARLY_DATA), None);
+    }
+}
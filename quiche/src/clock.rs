@@ -0,0 +1,155 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Time sources for connections.
+//!
+//! Connections read the current time from the [`Clock`] configured with
+//! [`Config::set_clock()`] whenever they need it: to arm and check timers,
+//! sample RTTs, pace packets and track idle timeouts. By default this is the
+//! [`SystemClock`], but tests and simulations can use a [`ManualClock`] to
+//! control time explicitly, and run timing-dependent scenarios faster than
+//! real time.
+//!
+//! [`Config::set_clock()`]: ../struct.Config.html#method.set_clock
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// A source of the current time.
+///
+/// Implementations are shared between all the connections created from the
+/// same [`Config`], and must never go backwards.
+///
+/// [`Config`]: ../struct.Config.html
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock, as returned by [`Instant::now()`].
+///
+/// [`Instant::now()`]: https://doc.rust-lang.org/std/time/struct.Instant.html#method.now
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves forward when told to.
+///
+/// ## Examples:
+///
+/// ```
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use std::time::Instant;
+///
+/// let clock = Arc::new(quiche::ManualClock::new(Instant::now()));
+/// config.set_clock(clock.clone());
+///
+/// // Connections created from `config` now observe time passing only when
+/// // the clock is advanced.
+/// clock.advance(Duration::from_millis(10));
+/// # Ok::<(), quiche::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+
+    /// The time elapsed since `start`, in nanoseconds.
+    elapsed: AtomicU64,
+}
+
+impl ManualClock {
+    /// Creates a clock showing the given time.
+    pub fn new(now: Instant) -> ManualClock {
+        ManualClock {
+            start: now,
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the clock forward by the given amount of time.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Moves the clock forward to the given time.
+    ///
+    /// Times earlier than the current time are ignored, as the clock never
+    /// goes backwards.
+    pub fn set(&self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.start).as_nanos();
+
+        self.elapsed.fetch_max(elapsed as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock() {
+        let start = Instant::now();
+        let clock = ManualClock::new(start);
+
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(clock.now(), start + Duration::from_millis(10));
+
+        clock.set(start + Duration::from_secs(1));
+        assert_eq!(clock.now(), start + Duration::from_secs(1));
+
+        // The clock never goes backwards.
+        clock.set(start + Duration::from_millis(500));
+        assert_eq!(clock.now(), start + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn system_clock() {
+        let before = Instant::now();
+        let now = SystemClock.now();
+
+        assert!(now >= before);
+        assert!(now <= Instant::now());
+    }
+}
@@ -36,6 +36,19 @@ impl Algorithm {
             },
         }
     }
+
+    // The TLS 1.3 variants of the AES-GCM AEADs require nonces to be
+    // strictly increasing, which doesn't hold when packets are sealed using
+    // multiple multipath packet number spaces.
+    fn get_evp_aead_unordered(self) -> *const EVP_AEAD {
+        match self {
+            Algorithm::AES128_GCM => unsafe { EVP_aead_aes_128_gcm() },
+            Algorithm::AES256_GCM => unsafe { EVP_aead_aes_256_gcm() },
+            Algorithm::ChaCha20_Poly1305 => unsafe {
+                EVP_aead_chacha20_poly1305()
+            },
+        }
+    }
 }
 
 pub(crate) struct PacketKey {
@@ -43,6 +56,12 @@ pub(crate) struct PacketKey {
 
     ctx: EVP_AEAD_CTX,
 
+    // Context used to seal packets on multipath path IDs other than 0,
+    // created on first use.
+    mp_ctx: std::sync::OnceLock<EVP_AEAD_CTX>,
+
+    key: Vec<u8>,
+
     nonce: Vec<u8>,
 }
 
@@ -52,20 +71,24 @@ impl PacketKey {
     ) -> Result<Self> {
         Ok(Self {
             alg,
-            ctx: make_aead_ctx(alg, &key)?,
+            ctx: make_aead_ctx(alg, alg.get_evp_aead(), &key)?,
+            mp_ctx: std::sync::OnceLock::new(),
+            key,
             nonce: iv,
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8], enc: u32) -> Result<Self> {
+    pub fn from_secret(
+        aead: Algorithm, version: u32, secret: &[u8], enc: u32,
+    ) -> Result<Self> {
         let key_len = aead.key_len();
         let nonce_len = aead.nonce_len();
 
         let mut key = vec![0; key_len];
         let mut iv = vec![0; nonce_len];
 
-        derive_pkt_key(aead, secret, &mut key)?;
-        derive_pkt_iv(aead, secret, &mut iv)?;
+        derive_pkt_key(aead, version, secret, &mut key)?;
+        derive_pkt_iv(aead, version, secret, &mut iv)?;
 
         let pkt_key = Self::new(aead, key, iv, enc)?;
 
@@ -75,13 +98,14 @@ impl PacketKey {
         // packet number) to be zero, which would not be the case for packet
         // number spaces after Initial as the same packet number sequence is
         // shared.
-        let _ = pkt_key.seal_with_u64_counter(0, b"", &mut [0_u8; 16], 0, None);
+        let _ =
+            pkt_key.seal_with_u64_counter(0, 0, b"", &mut [0_u8; 16], 0, None);
 
         Ok(pkt_key)
     }
 
     pub fn open_with_u64_counter(
-        &self, counter: u64, ad: &[u8], buf: &mut [u8],
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
     ) -> Result<usize> {
         let tag_len = self.alg.tag_len();
 
@@ -92,7 +116,7 @@ impl PacketKey {
 
         let max_out_len = out_len;
 
-        let nonce = make_nonce(&self.nonce, counter);
+        let nonce = make_nonce(&self.nonce, path_id, counter);
 
         let rc = unsafe {
             EVP_AEAD_CTX_open(
@@ -117,8 +141,8 @@ impl PacketKey {
     }
 
     pub fn seal_with_u64_counter(
-        &self, counter: u64, ad: &[u8], buf: &mut [u8], in_len: usize,
-        extra_in: Option<&[u8]>,
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
+        in_len: usize, extra_in: Option<&[u8]>,
     ) -> Result<usize> {
         let tag_len = self.alg.tag_len();
 
@@ -135,11 +159,13 @@ impl PacketKey {
             return Err(Error::CryptoFail);
         }
 
-        let nonce = make_nonce(&self.nonce, counter);
+        let nonce = make_nonce(&self.nonce, path_id, counter);
+
+        let ctx = self.seal_ctx(path_id)?;
 
         let rc = unsafe {
             EVP_AEAD_CTX_seal_scatter(
-                &self.ctx,                  // ctx
+                ctx,                        // ctx
                 buf.as_mut_ptr(),           // out
                 buf[in_len..].as_mut_ptr(), // out_tag
                 &mut out_tag_len,           // out_tag_len
@@ -161,6 +187,24 @@ impl PacketKey {
 
         Ok(in_len + out_tag_len)
     }
+
+    fn seal_ctx(&self, path_id: u32) -> Result<&EVP_AEAD_CTX> {
+        if path_id == 0 {
+            return Ok(&self.ctx);
+        }
+
+        if let Some(ctx) = self.mp_ctx.get() {
+            return Ok(ctx);
+        }
+
+        let ctx = make_aead_ctx(
+            self.alg,
+            self.alg.get_evp_aead_unordered(),
+            &self.key,
+        )?;
+
+        Ok(self.mp_ctx.get_or_init(|| ctx))
+    }
 }
 
 #[derive(Clone)]
@@ -248,12 +292,12 @@ impl HeaderProtectionKey {
     }
 }
 
-fn make_aead_ctx(alg: Algorithm, key: &[u8]) -> Result<EVP_AEAD_CTX> {
+fn make_aead_ctx(
+    alg: Algorithm, aead: *const EVP_AEAD, key: &[u8],
+) -> Result<EVP_AEAD_CTX> {
     let mut ctx = MaybeUninit::uninit();
 
     let ctx = unsafe {
-        let aead = alg.get_evp_aead();
-
         let rc = EVP_AEAD_CTX_init(
             ctx.as_mut_ptr(),
             aead,
@@ -326,6 +370,10 @@ extern "C" {
 
     fn EVP_aead_chacha20_poly1305() -> *const EVP_AEAD;
 
+    fn EVP_aead_aes_128_gcm() -> *const EVP_AEAD;
+
+    fn EVP_aead_aes_256_gcm() -> *const EVP_AEAD;
+
     // HKDF
     fn HKDF_extract(
         out_key: *mut u8, out_len: *mut usize, digest: *const EVP_MD,
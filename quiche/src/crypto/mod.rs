@@ -109,6 +109,31 @@ impl Algorithm {
             Algorithm::ChaCha20_Poly1305 => 12,
         }
     }
+
+    /// Returns the maximum number of packets that can be protected with a
+    /// single key.
+    ///
+    /// See [RFC 9001 Section 6.6](https://www.rfc-editor.org/rfc/rfc9001#section-6.6).
+    pub const fn confidentiality_limit(self) -> u64 {
+        match self {
+            Algorithm::AES128_GCM => 1 << 23,
+            Algorithm::AES256_GCM => 1 << 23,
+            // The limit is larger than the number of possible packets.
+            Algorithm::ChaCha20_Poly1305 => u64::MAX,
+        }
+    }
+
+    /// Returns the maximum number of received packets that can fail
+    /// authentication over the lifetime of a connection.
+    ///
+    /// See [RFC 9001 Section 6.6](https://www.rfc-editor.org/rfc/rfc9001#section-6.6).
+    pub const fn integrity_limit(self) -> u64 {
+        match self {
+            Algorithm::AES128_GCM => 1 << 52,
+            Algorithm::AES256_GCM => 1 << 52,
+            Algorithm::ChaCha20_Poly1305 => 1 << 36,
+        }
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -128,8 +153,12 @@ type HeaderProtectionMask = [u8; HP_MASK_LEN];
 pub struct Open {
     alg: Algorithm,
 
+    version: u32,
+
     secret: Vec<u8>,
 
+    hp_key: Vec<u8>,
+
     header: HeaderProtectionKey,
 
     packet: PacketKey,
@@ -142,29 +171,54 @@ impl Open {
     pub const DECRYPT: u32 = 0;
 
     pub fn new(
-        alg: Algorithm, key: Vec<u8>, iv: Vec<u8>, hp_key: Vec<u8>,
+        alg: Algorithm, version: u32, key: Vec<u8>, iv: Vec<u8>, hp_key: Vec<u8>,
         secret: Vec<u8>,
     ) -> Result<Open> {
         Ok(Open {
             alg,
 
+            version,
+
             secret,
 
-            header: HeaderProtectionKey::new(alg, hp_key)?,
+            header: HeaderProtectionKey::new(alg, hp_key.clone())?,
+
+            hp_key,
 
             packet: PacketKey::new(alg, key, iv, Self::DECRYPT)?,
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Open> {
+    pub fn from_secret(
+        aead: Algorithm, version: u32, secret: &[u8],
+    ) -> Result<Open> {
+        let mut hp_key = vec![0; aead.key_len()];
+
+        derive_hdr_key(aead, version, secret, &mut hp_key)?;
+
+        Self::from_secret_and_hp_key(aead, version, secret, hp_key)
+    }
+
+    /// Creates a key from the given traffic secret, but with an explicit
+    /// header protection key.
+    ///
+    /// This is needed to restore keys after a key update, as the header
+    /// protection key is not updated along with the traffic secret.
+    pub fn from_secret_and_hp_key(
+        aead: Algorithm, version: u32, secret: &[u8], hp_key: Vec<u8>,
+    ) -> Result<Open> {
         Ok(Open {
             alg: aead,
 
+            version,
+
             secret: secret.to_vec(),
 
-            header: HeaderProtectionKey::from_secret(aead, secret)?,
+            header: HeaderProtectionKey::new(aead, hp_key.clone())?,
+
+            hp_key,
 
-            packet: PacketKey::from_secret(aead, secret, Self::DECRYPT)?,
+            packet: PacketKey::from_secret(aead, version, secret, Self::DECRYPT)?,
         })
     }
 
@@ -180,25 +234,43 @@ impl Open {
         self.alg
     }
 
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    pub fn hp_key(&self) -> &[u8] {
+        &self.hp_key
+    }
+
     pub fn derive_next_packet_key(&self) -> Result<Open> {
-        let next_secret = derive_next_secret(self.alg, &self.secret)?;
+        let next_secret =
+            derive_next_secret(self.alg, self.version, &self.secret)?;
 
-        let next_packet_key =
-            PacketKey::from_secret(self.alg, &next_secret, Self::DECRYPT)?;
+        let next_packet_key = PacketKey::from_secret(
+            self.alg,
+            self.version,
+            &next_secret,
+            Self::DECRYPT,
+        )?;
 
         Ok(Open {
             alg: self.alg,
 
+            version: self.version,
+
             secret: next_secret,
 
+            hp_key: self.hp_key.clone(),
+
             header: self.header.clone(),
 
             packet: next_packet_key,
         })
     }
 
-    pub fn open_with_u64_counter(
-        &self, counter: u64, ad: &[u8], buf: &mut [u8],
+    /// Decrypts a packet received on the given multipath path identifier.
+    pub fn open_with_path_id(
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
     ) -> Result<usize> {
         if cfg!(feature = "fuzzing") {
             let tag_len = self.alg.tag_len();
@@ -212,15 +284,19 @@ impl Open {
             return Ok(out_len);
         }
 
-        self.packet.open_with_u64_counter(counter, ad, buf)
+        self.packet.open_with_u64_counter(path_id, counter, ad, buf)
     }
 }
 
 pub struct Seal {
     alg: Algorithm,
 
+    version: u32,
+
     secret: Vec<u8>,
 
+    hp_key: Vec<u8>,
+
     header: HeaderProtectionKey,
 
     packet: PacketKey,
@@ -233,29 +309,54 @@ impl Seal {
     pub const ENCRYPT: u32 = 1;
 
     pub fn new(
-        alg: Algorithm, key: Vec<u8>, iv: Vec<u8>, hp_key: Vec<u8>,
+        alg: Algorithm, version: u32, key: Vec<u8>, iv: Vec<u8>, hp_key: Vec<u8>,
         secret: Vec<u8>,
     ) -> Result<Seal> {
         Ok(Seal {
             alg,
 
+            version,
+
             secret,
 
-            header: HeaderProtectionKey::new(alg, hp_key)?,
+            header: HeaderProtectionKey::new(alg, hp_key.clone())?,
+
+            hp_key,
 
             packet: PacketKey::new(alg, key, iv, Self::ENCRYPT)?,
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Seal> {
+    pub fn from_secret(
+        aead: Algorithm, version: u32, secret: &[u8],
+    ) -> Result<Seal> {
+        let mut hp_key = vec![0; aead.key_len()];
+
+        derive_hdr_key(aead, version, secret, &mut hp_key)?;
+
+        Self::from_secret_and_hp_key(aead, version, secret, hp_key)
+    }
+
+    /// Creates a key from the given traffic secret, but with an explicit
+    /// header protection key.
+    ///
+    /// This is needed to restore keys after a key update, as the header
+    /// protection key is not updated along with the traffic secret.
+    pub fn from_secret_and_hp_key(
+        aead: Algorithm, version: u32, secret: &[u8], hp_key: Vec<u8>,
+    ) -> Result<Seal> {
         Ok(Seal {
             alg: aead,
 
+            version,
+
             secret: secret.to_vec(),
 
-            header: HeaderProtectionKey::from_secret(aead, secret)?,
+            header: HeaderProtectionKey::new(aead, hp_key.clone())?,
+
+            hp_key,
 
-            packet: PacketKey::from_secret(aead, secret, Self::ENCRYPT)?,
+            packet: PacketKey::from_secret(aead, version, secret, Self::ENCRYPT)?,
         })
     }
 
@@ -271,26 +372,44 @@ impl Seal {
         self.alg
     }
 
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    pub fn hp_key(&self) -> &[u8] {
+        &self.hp_key
+    }
+
     pub fn derive_next_packet_key(&self) -> Result<Seal> {
-        let next_secret = derive_next_secret(self.alg, &self.secret)?;
+        let next_secret =
+            derive_next_secret(self.alg, self.version, &self.secret)?;
 
-        let next_packet_key =
-            PacketKey::from_secret(self.alg, &next_secret, Self::ENCRYPT)?;
+        let next_packet_key = PacketKey::from_secret(
+            self.alg,
+            self.version,
+            &next_secret,
+            Self::ENCRYPT,
+        )?;
 
         Ok(Seal {
             alg: self.alg,
 
+            version: self.version,
+
             secret: next_secret,
 
+            hp_key: self.hp_key.clone(),
+
             header: self.header.clone(),
 
             packet: next_packet_key,
         })
     }
 
-    pub fn seal_with_u64_counter(
-        &self, counter: u64, ad: &[u8], buf: &mut [u8], in_len: usize,
-        extra_in: Option<&[u8]>,
+    /// Encrypts a packet sent on the given multipath path identifier.
+    pub fn seal_with_path_id(
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
+        in_len: usize, extra_in: Option<&[u8]>,
     ) -> Result<usize> {
         if cfg!(feature = "fuzzing") {
             let tag_len = self.alg.tag_len();
@@ -310,19 +429,7 @@ impl Seal {
         }
 
         self.packet
-            .seal_with_u64_counter(counter, ad, buf, in_len, extra_in)
-    }
-}
-
-impl HeaderProtectionKey {
-    pub fn from_secret(aead: Algorithm, secret: &[u8]) -> Result<Self> {
-        let key_len = aead.key_len();
-
-        let mut hp_key = vec![0; key_len];
-
-        derive_hdr_key(aead, secret, &mut hp_key)?;
-
-        Self::new(aead, hp_key)
+            .seal_with_u64_counter(path_id, counter, ad, buf, in_len, extra_in)
     }
 }
 
@@ -351,13 +458,13 @@ pub fn derive_initial_key_material(
     if did_reset {
         let (open, seal) = if is_server {
             (
-                Open::from_secret(aead, &client_secret)?,
-                Seal::from_secret(aead, &server_secret)?,
+                Open::from_secret(aead, version, &client_secret)?,
+                Seal::from_secret(aead, version, &server_secret)?,
             )
         } else {
             (
-                Open::from_secret(aead, &server_secret)?,
-                Seal::from_secret(aead, &client_secret)?,
+                Open::from_secret(aead, version, &server_secret)?,
+                Seal::from_secret(aead, version, &client_secret)?,
             )
         };
 
@@ -369,28 +476,56 @@ pub fn derive_initial_key_material(
     let mut client_iv = vec![0; nonce_len];
     let mut client_hp_key = vec![0; key_len];
 
-    derive_pkt_key(aead, &client_secret, &mut client_key)?;
-    derive_pkt_iv(aead, &client_secret, &mut client_iv)?;
-    derive_hdr_key(aead, &client_secret, &mut client_hp_key)?;
+    derive_pkt_key(aead, version, &client_secret, &mut client_key)?;
+    derive_pkt_iv(aead, version, &client_secret, &mut client_iv)?;
+    derive_hdr_key(aead, version, &client_secret, &mut client_hp_key)?;
 
     // Server.
     let mut server_key = vec![0; key_len];
     let mut server_iv = vec![0; nonce_len];
     let mut server_hp_key = vec![0; key_len];
 
-    derive_pkt_key(aead, &server_secret, &mut server_key)?;
-    derive_pkt_iv(aead, &server_secret, &mut server_iv)?;
-    derive_hdr_key(aead, &server_secret, &mut server_hp_key)?;
+    derive_pkt_key(aead, version, &server_secret, &mut server_key)?;
+    derive_pkt_iv(aead, version, &server_secret, &mut server_iv)?;
+    derive_hdr_key(aead, version, &server_secret, &mut server_hp_key)?;
 
     let (open, seal) = if is_server {
         (
-            Open::new(aead, client_key, client_iv, client_hp_key, client_secret)?,
-            Seal::new(aead, server_key, server_iv, server_hp_key, server_secret)?,
+            Open::new(
+                aead,
+                version,
+                client_key,
+                client_iv,
+                client_hp_key,
+                client_secret,
+            )?,
+            Seal::new(
+                aead,
+                version,
+                server_key,
+                server_iv,
+                server_hp_key,
+                server_secret,
+            )?,
         )
     } else {
         (
-            Open::new(aead, server_key, server_iv, server_hp_key, server_secret)?,
-            Seal::new(aead, client_key, client_iv, client_hp_key, client_secret)?,
+            Open::new(
+                aead,
+                version,
+                server_key,
+                server_iv,
+                server_hp_key,
+                server_secret,
+            )?,
+            Seal::new(
+                aead,
+                version,
+                client_key,
+                client_iv,
+                client_hp_key,
+                client_secret,
+            )?,
         )
     };
 
@@ -405,9 +540,16 @@ fn derive_initial_secret(
         0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f, 0x0a,
     ];
 
+    const INITIAL_SALT_V2: [u8; 20] = [
+        0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe,
+        0x6e, 0x26, 0x9d, 0xcb, 0xf9, 0xbd, 0x2e, 0xd9,
+    ];
+
     let salt = match version {
         crate::PROTOCOL_VERSION_V1 => &INITIAL_SALT_V1,
 
+        crate::PROTOCOL_VERSION_V2 => &INITIAL_SALT_V2,
+
         _ => &INITIAL_SALT_V1,
     };
 
@@ -428,20 +570,30 @@ fn derive_server_initial_secret(
     hkdf_expand_label(aead, prk, LABEL, out)
 }
 
-fn derive_next_secret(aead: Algorithm, secret: &[u8]) -> Result<Vec<u8>> {
-    const LABEL: &[u8] = b"quic ku";
+fn derive_next_secret(
+    aead: Algorithm, version: u32, secret: &[u8],
+) -> Result<Vec<u8>> {
+    let label: &[u8] = match version {
+        crate::PROTOCOL_VERSION_V2 => b"quicv2 ku",
+
+        _ => b"quic ku",
+    };
 
     let mut next_secret = vec![0u8; secret.len()];
 
-    hkdf_expand_label(aead, secret, LABEL, &mut next_secret)?;
+    hkdf_expand_label(aead, secret, label, &mut next_secret)?;
 
     Ok(next_secret)
 }
 
 pub fn derive_hdr_key(
-    aead: Algorithm, secret: &[u8], out: &mut [u8],
+    aead: Algorithm, version: u32, secret: &[u8], out: &mut [u8],
 ) -> Result<()> {
-    const LABEL: &[u8] = b"quic hp";
+    let label: &[u8] = match version {
+        crate::PROTOCOL_VERSION_V2 => b"quicv2 hp",
+
+        _ => b"quic hp",
+    };
 
     let key_len = aead.key_len();
 
@@ -449,11 +601,17 @@ pub fn derive_hdr_key(
         return Err(Error::CryptoFail);
     }
 
-    hkdf_expand_label(aead, secret, LABEL, &mut out[..key_len])
+    hkdf_expand_label(aead, secret, label, &mut out[..key_len])
 }
 
-pub fn derive_pkt_key(aead: Algorithm, prk: &[u8], out: &mut [u8]) -> Result<()> {
-    const LABEL: &[u8] = b"quic key";
+pub fn derive_pkt_key(
+    aead: Algorithm, version: u32, prk: &[u8], out: &mut [u8],
+) -> Result<()> {
+    let label: &[u8] = match version {
+        crate::PROTOCOL_VERSION_V2 => b"quicv2 key",
+
+        _ => b"quic key",
+    };
 
     let key_len: usize = aead.key_len();
 
@@ -461,11 +619,17 @@ pub fn derive_pkt_key(aead: Algorithm, prk: &[u8], out: &mut [u8]) -> Result<()>
         return Err(Error::CryptoFail);
     }
 
-    hkdf_expand_label(aead, prk, LABEL, &mut out[..key_len])
+    hkdf_expand_label(aead, prk, label, &mut out[..key_len])
 }
 
-pub fn derive_pkt_iv(aead: Algorithm, prk: &[u8], out: &mut [u8]) -> Result<()> {
-    const LABEL: &[u8] = b"quic iv";
+pub fn derive_pkt_iv(
+    aead: Algorithm, version: u32, prk: &[u8], out: &mut [u8],
+) -> Result<()> {
+    let label: &[u8] = match version {
+        crate::PROTOCOL_VERSION_V2 => b"quicv2 iv",
+
+        _ => b"quic iv",
+    };
 
     let nonce_len = aead.nonce_len();
 
@@ -473,7 +637,7 @@ pub fn derive_pkt_iv(aead: Algorithm, prk: &[u8], out: &mut [u8]) -> Result<()>
         return Err(Error::CryptoFail);
     }
 
-    hkdf_expand_label(aead, prk, LABEL, &mut out[..nonce_len])
+    hkdf_expand_label(aead, prk, label, &mut out[..nonce_len])
 }
 
 fn hkdf_expand_label(
@@ -492,7 +656,7 @@ fn hkdf_expand_label(
     Ok(())
 }
 
-fn make_nonce(iv: &[u8], counter: u64) -> [u8; MAX_NONCE_LEN] {
+fn make_nonce(iv: &[u8], path_id: u32, counter: u64) -> [u8; MAX_NONCE_LEN] {
     let mut nonce = [0; MAX_NONCE_LEN];
     nonce.copy_from_slice(iv);
 
@@ -502,6 +666,13 @@ fn make_nonce(iv: &[u8], counter: u64) -> [u8; MAX_NONCE_LEN] {
         *a ^= b;
     }
 
+    // With multipath, the path identifier is XORed into the first bytes of
+    // the IV, so that packets with the same number on different paths use
+    // different nonces. Path 0 leaves the IV unchanged.
+    for (a, b) in nonce[..4].iter_mut().zip(path_id.to_be_bytes().iter()) {
+        *a ^= b;
+    }
+
     nonce
 }
 
@@ -544,13 +715,11 @@ mod tests {
         let mut hdr_key = [0; 16];
 
         let aead = Algorithm::AES128_GCM;
+        let version = crate::PROTOCOL_VERSION_V1;
 
-        assert!(derive_initial_secret(
-            &dcid,
-            crate::PROTOCOL_VERSION_V1,
-            &mut initial_secret,
-        )
-        .is_ok());
+        assert!(
+            derive_initial_secret(&dcid, version, &mut initial_secret).is_ok()
+        );
 
         // Client.
         assert!(
@@ -564,21 +733,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key = [
             0x1f, 0x36, 0x96, 0x13, 0xdd, 0x76, 0xd5, 0x46, 0x77, 0x30, 0xef,
             0xcb, 0xe3, 0xb1, 0xa2, 0x2d,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv = [
             0xfa, 0x04, 0x4b, 0x2f, 0x42, 0xa3, 0xfd, 0x3b, 0x46, 0xfb, 0x25,
             0x5c,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_client_hdr_key = [
             0x9f, 0x50, 0x44, 0x9e, 0x04, 0xa0, 0xe8, 0x10, 0x28, 0x3a, 0x1e,
             0x99, 0x33, 0xad, 0xed, 0xd2,
@@ -598,21 +767,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key = [
             0xcf, 0x3a, 0x53, 0x31, 0x65, 0x3c, 0x36, 0x4c, 0x88, 0xf0, 0xf3,
             0x79, 0xb6, 0x06, 0x7e, 0x37,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv = [
             0x0a, 0xc1, 0x49, 0x3c, 0xa1, 0x90, 0x58, 0x53, 0xb0, 0xbb, 0xa0,
             0x3e,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key = [
             0xc2, 0x06, 0xb8, 0xd9, 0xb9, 0xf0, 0xf3, 0x76, 0x44, 0x43, 0x0b,
             0x49, 0x0e, 0xea, 0xa3, 0x14,
@@ -620,6 +789,92 @@ mod tests {
         assert_eq!(&hdr_key, &expected_server_hdr_key);
     }
 
+    #[test]
+    fn derive_initial_secrets_v2() {
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut initial_secret = [0; 32];
+
+        let mut secret = [0; 32];
+        let mut pkt_key = [0; 16];
+        let mut pkt_iv = [0; 12];
+        let mut hdr_key = [0; 16];
+
+        let aead = Algorithm::AES128_GCM;
+        let version = crate::PROTOCOL_VERSION_V2;
+
+        assert!(
+            derive_initial_secret(&dcid, version, &mut initial_secret).is_ok()
+        );
+
+        // Client.
+        assert!(
+            derive_client_initial_secret(aead, &initial_secret, &mut secret)
+                .is_ok()
+        );
+        let expected_client_initial_secret = [
+            0x14, 0xec, 0x9d, 0x6e, 0xb9, 0xfd, 0x7a, 0xf8, 0x3b, 0xf5, 0xa6,
+            0x68, 0xbc, 0x17, 0xa7, 0xe2, 0x83, 0x76, 0x6a, 0xad, 0xe7, 0xec,
+            0xd0, 0x89, 0x1f, 0x70, 0xf9, 0xff, 0x7f, 0x4b, 0xf4, 0x7b,
+        ];
+        assert_eq!(&secret, &expected_client_initial_secret);
+
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_client_pkt_key = [
+            0x8b, 0x1a, 0x0b, 0xc1, 0x21, 0x28, 0x42, 0x90, 0xa2, 0x9e, 0x09,
+            0x71, 0xb5, 0xcd, 0x04, 0x5d,
+        ];
+        assert_eq!(&pkt_key, &expected_client_pkt_key);
+
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_client_pkt_iv = [
+            0x91, 0xf7, 0x3e, 0x23, 0x51, 0xd8, 0xfa, 0x91, 0x66, 0x0e, 0x90,
+            0x9f,
+        ];
+        assert_eq!(&pkt_iv, &expected_client_pkt_iv);
+
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_client_hdr_key = [
+            0x45, 0xb9, 0x5e, 0x15, 0x23, 0x5d, 0x6f, 0x45, 0xa6, 0xb1, 0x9c,
+            0xbc, 0xb0, 0x29, 0x4b, 0xa9,
+        ];
+        assert_eq!(&hdr_key, &expected_client_hdr_key);
+
+        // Server.
+        assert!(
+            derive_server_initial_secret(aead, &initial_secret, &mut secret)
+                .is_ok()
+        );
+
+        let expected_server_initial_secret = [
+            0x02, 0x63, 0xdb, 0x17, 0x82, 0x73, 0x1b, 0xf4, 0x58, 0x8e, 0x7e,
+            0x4d, 0x93, 0xb7, 0x46, 0x39, 0x07, 0xcb, 0x8c, 0xd8, 0x20, 0x0b,
+            0x5d, 0xa5, 0x5a, 0x8b, 0xd4, 0x88, 0xea, 0xfc, 0x37, 0xc1,
+        ];
+        assert_eq!(&secret, &expected_server_initial_secret);
+
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
+        let expected_server_pkt_key = [
+            0x82, 0xdb, 0x63, 0x78, 0x61, 0xd5, 0x5e, 0x1d, 0x01, 0x1f, 0x19,
+            0xea, 0x71, 0xd5, 0xd2, 0xa7,
+        ];
+        assert_eq!(&pkt_key, &expected_server_pkt_key);
+
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
+        let expected_server_pkt_iv = [
+            0xdd, 0x13, 0xc2, 0x76, 0x49, 0x9c, 0x02, 0x49, 0xd3, 0x31, 0x06,
+            0x52,
+        ];
+        assert_eq!(&pkt_iv, &expected_server_pkt_iv);
+
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
+        let expected_server_hdr_key = [
+            0xed, 0xf6, 0xd0, 0x5c, 0x83, 0x12, 0x12, 0x01, 0xb4, 0x36, 0xe1,
+            0x68, 0x77, 0x59, 0x3c, 0x3a,
+        ];
+        assert_eq!(&hdr_key, &expected_server_hdr_key);
+    }
+
     #[test]
     fn derive_chacha20_secrets() {
         let secret = [
@@ -629,12 +884,13 @@ mod tests {
         ];
 
         let aead = Algorithm::ChaCha20_Poly1305;
+        let version = crate::PROTOCOL_VERSION_V1;
 
         let mut pkt_key = [0; 32];
         let mut pkt_iv = [0; 12];
         let mut hdr_key = [0; 32];
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(aead, version, &secret, &mut pkt_key).is_ok());
         let expected_pkt_key = [
             0xc6, 0xd9, 0x8f, 0xf3, 0x44, 0x1c, 0x3f, 0xe1, 0xb2, 0x18, 0x20,
             0x94, 0xf6, 0x9c, 0xaa, 0x2e, 0xd4, 0xb7, 0x16, 0xb6, 0x54, 0x88,
@@ -642,14 +898,14 @@ mod tests {
         ];
         assert_eq!(&pkt_key, &expected_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(aead, version, &secret, &mut pkt_iv).is_ok());
         let expected_pkt_iv = [
             0xe0, 0x45, 0x9b, 0x34, 0x74, 0xbd, 0xd0, 0xe4, 0x4a, 0x41, 0xc1,
             0x44,
         ];
         assert_eq!(&pkt_iv, &expected_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(aead, version, &secret, &mut hdr_key).is_ok());
         let expected_hdr_key = [
             0x25, 0xa2, 0x82, 0xb9, 0xe8, 0x2f, 0x06, 0xf2, 0x1f, 0x48, 0x89,
             0x17, 0xa4, 0xfc, 0x8f, 0x1b, 0x73, 0x57, 0x36, 0x85, 0x60, 0x85,
@@ -657,7 +913,7 @@ mod tests {
         ];
         assert_eq!(&hdr_key, &expected_hdr_key);
 
-        let next_secret = derive_next_secret(aead, &secret).unwrap();
+        let next_secret = derive_next_secret(aead, version, &secret).unwrap();
         let expected_secret = [
             0x12, 0x23, 0x50, 0x47, 0x55, 0x03, 0x6d, 0x55, 0x63, 0x42, 0xee,
             0x93, 0x61, 0xd2, 0x53, 0x42, 0x1a, 0x82, 0x6c, 0x9e, 0xcd, 0xf3,
@@ -64,21 +64,23 @@ impl PacketKey {
         })
     }
 
-    pub fn from_secret(aead: Algorithm, secret: &[u8], enc: u32) -> Result<Self> {
+    pub fn from_secret(
+        aead: Algorithm, version: u32, secret: &[u8], enc: u32,
+    ) -> Result<Self> {
         let key_len = aead.key_len();
         let nonce_len = aead.nonce_len();
 
         let mut key = vec![0; key_len];
         let mut iv = vec![0; nonce_len];
 
-        derive_pkt_key(aead, secret, &mut key)?;
-        derive_pkt_iv(aead, secret, &mut iv)?;
+        derive_pkt_key(aead, version, secret, &mut key)?;
+        derive_pkt_iv(aead, version, secret, &mut iv)?;
 
         Self::new(aead, key, iv, enc)
     }
 
     pub fn open_with_u64_counter(
-        &self, counter: u64, ad: &[u8], buf: &mut [u8],
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
     ) -> Result<usize> {
         let tag_len = self.alg.tag_len();
 
@@ -86,7 +88,7 @@ impl PacketKey {
 
         let mut cipher_len = buf.len();
 
-        let nonce = make_nonce(&self.nonce, counter);
+        let nonce = make_nonce(&self.nonce, path_id, counter);
 
         // Set the IV len.
         const EVP_CTRL_AEAD_SET_IVLEN: i32 = 0x9;
@@ -187,15 +189,15 @@ impl PacketKey {
     }
 
     pub fn seal_with_u64_counter(
-        &self, counter: u64, ad: &[u8], buf: &mut [u8], in_len: usize,
-        _extra_in: Option<&[u8]>,
+        &self, path_id: u32, counter: u64, ad: &[u8], buf: &mut [u8],
+        in_len: usize, _extra_in: Option<&[u8]>,
     ) -> Result<usize> {
         let tag_len = self.alg.tag_len();
 
         // TODO: replace this with something more efficient.
         let in_buf = buf.to_owned();
 
-        let nonce = make_nonce(&self.nonce, counter);
+        let nonce = make_nonce(&self.nonce, path_id, counter);
 
         // Set the IV len.
         const EVP_CTRL_AEAD_SET_IVLEN: i32 = 0x9;
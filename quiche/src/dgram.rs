@@ -24,15 +24,73 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use crate::snapshot;
 use crate::Error;
 use crate::Result;
 
 use std::collections::VecDeque;
+use std::time::Instant;
+
+/// The default urgency of DATAGRAM frames.
+pub const DEFAULT_URGENCY: u8 = 127;
+
+/// An event about the fate of an outgoing DATAGRAM frame.
+///
+/// Events are only generated for frames queued with
+/// [`dgram_send_tracked()`], and each tracked frame generates exactly one
+/// event.
+///
+/// [`dgram_send_tracked()`]: struct.Connection.html#method.dgram_send_tracked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DatagramEvent {
+    /// The packet carrying the frame with the given ID was acknowledged.
+    Acked(u64),
+
+    /// The packet carrying the frame with the given ID was declared lost.
+    ///
+    /// DATAGRAM frames are never retransmitted, so it is up to the
+    /// application to decide whether to send the data again.
+    Lost(u64),
+
+    /// The frame with the given ID was dropped before being sent, either
+    /// because it expired, because it could never fit in a packet, or because
+    /// it was purged by the application.
+    Dropped(u64),
+}
+
+/// A queued DATAGRAM frame.
+struct Datagram {
+    data: Vec<u8>,
+
+    /// The datagram's urgency (lower is better).
+    urgency: u8,
+
+    /// The time after which the datagram is dropped instead of sent.
+    expiry: Option<Instant>,
+
+    /// The ID the datagram is tracked with, if any.
+    id: Option<u64>,
+}
+
+impl Datagram {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expiry.is_some_and(|expiry| expiry <= now)
+    }
+
+    fn on_dropped(&self, events: &mut VecDeque<DatagramEvent>) {
+        if let Some(id) = self.id {
+            events.push_back(DatagramEvent::Dropped(id));
+        }
+    }
+}
 
 /// Keeps track of DATAGRAM frames.
+///
+/// Frames are ordered by urgency, and in FIFO order among frames with the
+/// same urgency.
 #[derive(Default)]
 pub struct DatagramQueue {
-    queue: Option<VecDeque<Vec<u8>>>,
+    queue: Option<VecDeque<Datagram>>,
     queue_max_len: usize,
     queue_bytes_size: usize,
 }
@@ -47,31 +105,82 @@ impl DatagramQueue {
     }
 
     pub fn push(&mut self, data: Vec<u8>) -> Result<()> {
-        if self.is_full() {
+        self.push_with_priority(
+            data,
+            DEFAULT_URGENCY,
+            None,
+            None,
+            &mut VecDeque::new(),
+        )
+    }
+
+    /// Queues a frame after all frames of the same or lower urgency.
+    ///
+    /// If the queue is full, the least urgent and most recently queued frame
+    /// is evicted to make room, but only if it is less urgent than the new
+    /// frame. Otherwise [`Done`] is returned.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn push_with_priority(
+        &mut self, data: Vec<u8>, urgency: u8, expiry: Option<Instant>,
+        id: Option<u64>, events: &mut VecDeque<DatagramEvent>,
+    ) -> Result<()> {
+        if self.is_full() && !self.evict(urgency, events) {
             return Err(Error::Done);
         }
 
         self.queue_bytes_size += data.len();
-        self.queue
-            .get_or_insert_with(Default::default)
-            .push_back(data);
+
+        let q = self.queue.get_or_insert_with(Default::default);
+        let idx = q.partition_point(|d| d.urgency <= urgency);
+
+        q.insert(idx, Datagram {
+            data,
+            urgency,
+            expiry,
+            id,
+        });
 
         Ok(())
     }
 
+    /// Drops the back frame if it is less urgent than `urgency`, and returns
+    /// whether a frame was dropped.
+    fn evict(
+        &mut self, urgency: u8, events: &mut VecDeque<DatagramEvent>,
+    ) -> bool {
+        let Some(q) = self.queue.as_mut() else {
+            return false;
+        };
+
+        if q.back().is_none_or(|d| d.urgency <= urgency) {
+            return false;
+        }
+
+        if let Some(d) = q.pop_back() {
+            self.queue_bytes_size =
+                self.queue_bytes_size.saturating_sub(d.data.len());
+            d.on_dropped(events);
+        }
+
+        true
+    }
+
     pub fn peek_front_len(&self) -> Option<usize> {
-        self.queue.as_ref().and_then(|q| q.front().map(|d| d.len()))
+        self.queue
+            .as_ref()
+            .and_then(|q| q.front().map(|d| d.data.len()))
     }
 
     pub fn peek_front_bytes(&self, buf: &mut [u8], len: usize) -> Result<usize> {
         match self.queue.as_ref().and_then(|q| q.front()) {
             Some(d) => {
-                let len = std::cmp::min(len, d.len());
+                let len = std::cmp::min(len, d.data.len());
                 if buf.len() < len {
                     return Err(Error::BufferTooShort);
                 }
 
-                buf[..len].copy_from_slice(&d[..len]);
+                buf[..len].copy_from_slice(&d.data[..len]);
                 Ok(len)
             },
 
@@ -80,9 +189,15 @@ impl DatagramQueue {
     }
 
     pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.pop_with_id().map(|(data, _)| data)
+    }
+
+    /// Removes the front frame, returning it along with its tracking ID.
+    pub fn pop_with_id(&mut self) -> Option<(Vec<u8>, Option<u64>)> {
         if let Some(d) = self.queue.as_mut().and_then(|q| q.pop_front()) {
-            self.queue_bytes_size = self.queue_bytes_size.saturating_sub(d.len());
-            return Some(d);
+            self.queue_bytes_size =
+                self.queue_bytes_size.saturating_sub(d.data.len());
+            return Some((d.data, d.id));
         }
 
         None
@@ -92,13 +207,53 @@ impl DatagramQueue {
         !self.queue.as_ref().map(|q| q.is_empty()).unwrap_or(true)
     }
 
-    pub fn purge<F: Fn(&[u8]) -> bool>(&mut self, f: F) {
+    pub fn purge<F: Fn(&[u8]) -> bool>(
+        &mut self, f: F, events: &mut VecDeque<DatagramEvent>,
+    ) {
         if let Some(q) = self.queue.as_mut() {
-            q.retain(|d| !f(d));
-            self.queue_bytes_size = q.iter().fold(0, |total, d| total + d.len());
+            q.retain(|d| {
+                if f(&d.data) {
+                    d.on_dropped(events);
+                    return false;
+                }
+
+                true
+            });
+            self.queue_bytes_size =
+                q.iter().fold(0, |total, d| total + d.data.len());
         }
     }
 
+    /// Removes all frames whose expiry is at or before `now`, and returns
+    /// the number of removed frames.
+    pub fn purge_expired(
+        &mut self, now: Instant, events: &mut VecDeque<DatagramEvent>,
+    ) -> usize {
+        let Some(q) = self.queue.as_mut() else {
+            return 0;
+        };
+
+        let len = q.len();
+
+        q.retain(|d| {
+            if d.is_expired(now) {
+                d.on_dropped(events);
+                return false;
+            }
+
+            true
+        });
+
+        let purged = len - q.len();
+
+        if purged > 0 {
+            self.queue_bytes_size =
+                q.iter().fold(0, |total, d| total + d.data.len());
+        }
+
+        purged
+    }
+
     pub fn is_full(&self) -> bool {
         self.len() == self.queue_max_len
     }
@@ -114,4 +269,55 @@ impl DatagramQueue {
     pub fn byte_size(&self) -> usize {
         self.queue_bytes_size
     }
+
+    /// Writes the queued frames to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer, now: Instant) {
+        let Some(q) = self.queue.as_ref() else {
+            w.put_u64(0);
+            return;
+        };
+
+        w.put_u64(q.len() as u64);
+        for d in q {
+            w.put_bytes(&d.data);
+            w.put_u8(d.urgency);
+            w.put_bool(d.expiry.is_some());
+            w.put_instant(d.expiry.unwrap_or(now), now);
+            w.put_opt_u64(d.id);
+        }
+    }
+
+    /// Reads queued frames written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.DatagramQueue.html#method.to_snapshot
+    pub fn from_snapshot(
+        r: &mut snapshot::Reader, queue_max_len: usize, now: Instant,
+    ) -> Result<DatagramQueue> {
+        let mut queue = DatagramQueue::new(queue_max_len);
+
+        let count = r.get_count(1)?;
+        if count > queue_max_len {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        for _ in 0..count {
+            let data = r.get_bytes()?.to_vec();
+            let urgency = r.get_u8()?;
+            let has_expiry = r.get_bool()?;
+            let expiry = r.get_instant(now)?;
+            let id = r.get_opt_u64()?;
+
+            queue.queue_bytes_size += data.len();
+            queue.queue.get_or_insert_with(Default::default).push_back(
+                Datagram {
+                    data,
+                    urgency,
+                    expiry: has_expiry.then_some(expiry),
+                    id,
+                },
+            );
+        }
+
+        Ok(queue)
+    }
 }
@@ -0,0 +1,522 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Explicit Congestion Notification ([RFC 9000 Section 13.4]).
+//!
+//! Outgoing packets are marked with the ECT(0) codepoint while the path is
+//! being validated, and validation succeeds once the peer reports the marked
+//! packets in the ECN counts of its ACK frames. Marking stops if the ECN
+//! counts are missing or inconsistent, or if all the marked packets are lost.
+//!
+//! When the congestion controller supports L4S ([RFC 9330]), packets are
+//! marked with the ECT(1) codepoint instead, so that L4S queues on the path
+//! can tell them apart from classic traffic.
+//!
+//! [RFC 9000 Section 13.4]: https://www.rfc-editor.org/rfc/rfc9000.html#section-13.4
+//! [RFC 9330]: https://www.rfc-editor.org/rfc/rfc9330.html
+
+use crate::frame::EcnCounts;
+use crate::snapshot;
+use crate::Error;
+use crate::Result;
+
+/// The number of packets marked before waiting for the validation to complete.
+///
+/// https://www.rfc-editor.org/rfc/rfc9000.html#appendix-A.4
+const ECN_TESTING_PACKETS: usize = 10;
+
+/// The ECN codepoint of an IP packet, as defined in [RFC 3168].
+///
+/// [RFC 3168]: https://www.rfc-editor.org/rfc/rfc3168.html#section-5
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ecn {
+    /// Not ECN-Capable Transport.
+    #[default]
+    NotEct = 0b00,
+
+    /// ECN-Capable Transport, ECT(1).
+    Ect1   = 0b01,
+
+    /// ECN-Capable Transport, ECT(0).
+    Ect0   = 0b10,
+
+    /// Congestion Experienced.
+    Ce     = 0b11,
+}
+
+impl From<u8> for Ecn {
+    /// Extracts the ECN codepoint from the IPv4 TOS or IPv6 Traffic Class
+    /// byte.
+    fn from(tos: u8) -> Self {
+        match tos & 0b11 {
+            0b01 => Ecn::Ect1,
+
+            0b10 => Ecn::Ect0,
+
+            0b11 => Ecn::Ce,
+
+            _ => Ecn::NotEct,
+        }
+    }
+}
+
+impl From<Ecn> for u8 {
+    fn from(ecn: Ecn) -> Self {
+        ecn as u8
+    }
+}
+
+impl Ecn {
+    /// Returns whether the codepoint marks the packet as ECN-capable.
+    pub fn is_ect(self) -> bool {
+        self != Ecn::NotEct
+    }
+}
+
+/// The ECN validation state of a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ValidationState {
+    /// ECN is disabled by the local configuration.
+    Disabled,
+
+    /// The first packets on the path are being marked.
+    Testing,
+
+    /// Marking is suspended until the marked packets are acknowledged.
+    Unknown,
+
+    /// The path supports ECN.
+    Capable,
+
+    /// The path or the peer doesn't support ECN.
+    Failed,
+}
+
+/// Tracks the ECN validation of a path.
+#[derive(Debug)]
+pub struct EcnState {
+    state: ValidationState,
+
+    /// Whether packets are marked with ECT(1) rather than ECT(0).
+    l4s: bool,
+
+    /// The number of packets sent with ECT(0) on the path.
+    ect0_sent: usize,
+
+    /// The number of packets sent with ECT(1) on the path.
+    ect1_sent: usize,
+
+    /// The number of ECN-CE marks reported by the peer for packets sent on the
+    /// path.
+    ce_reported: u64,
+}
+
+impl EcnState {
+    pub fn new(enabled: bool, l4s: bool) -> Self {
+        let state = if enabled {
+            ValidationState::Testing
+        } else {
+            ValidationState::Disabled
+        };
+
+        EcnState {
+            state,
+            l4s,
+            ect0_sent: 0,
+            ect1_sent: 0,
+            ce_reported: 0,
+        }
+    }
+
+    /// Sets whether packets are marked with ECT(1) rather than ECT(0).
+    pub fn set_l4s(&mut self, v: bool) {
+        self.l4s = v;
+    }
+
+    /// Returns the ECT codepoint used to mark packets on the path.
+    pub fn ect(&self) -> Ecn {
+        if self.l4s {
+            Ecn::Ect1
+        } else {
+            Ecn::Ect0
+        }
+    }
+
+    /// Returns the codepoint outgoing packets should be marked with.
+    pub fn codepoint(&self) -> Ecn {
+        match self.state {
+            ValidationState::Testing | ValidationState::Capable => self.ect(),
+
+            _ => Ecn::NotEct,
+        }
+    }
+
+    /// Records that a packet was sent with the given codepoint.
+    pub fn on_packet_sent(&mut self, ecn: Ecn) {
+        match ecn {
+            Ecn::Ect0 => self.ect0_sent += 1,
+
+            Ecn::Ect1 => self.ect1_sent += 1,
+
+            _ => return,
+        }
+
+        if self.state == ValidationState::Testing &&
+            self.marked_sent() >= ECN_TESTING_PACKETS
+        {
+            self.state = ValidationState::Unknown;
+        }
+    }
+
+    /// Records that newly acknowledged marked packets were correctly reported
+    /// by the peer.
+    pub fn on_validated(&mut self) {
+        if matches!(
+            self.state,
+            ValidationState::Testing | ValidationState::Unknown
+        ) {
+            trace!("ECN validation succeeded");
+
+            self.state = ValidationState::Capable;
+        }
+    }
+
+    /// Records that the peer didn't correctly report the marked packets, so
+    /// marking needs to stop.
+    pub fn on_validation_failed(&mut self) {
+        if self.state != ValidationState::Disabled &&
+            self.state != ValidationState::Failed
+        {
+            trace!("ECN validation failed");
+
+            self.state = ValidationState::Failed;
+        }
+    }
+
+    /// Checks whether all the marked packets were lost, given the total number
+    /// of marked packets declared lost and acknowledged on the path.
+    pub fn on_marked_packets_lost(&mut self, lost: usize, acked: usize) {
+        // Packets sent on a path that doesn't support ECN might be dropped, so
+        // stop marking if none of the testing packets made it through.
+        if self.state == ValidationState::Unknown &&
+            acked == 0 &&
+            lost >= self.marked_sent()
+        {
+            self.on_validation_failed();
+        }
+    }
+
+    /// Records ECN-CE marks reported by the peer.
+    pub fn on_ce_reported(&mut self, count: u64) {
+        self.ce_reported += count;
+    }
+
+    /// Returns whether ECN validation succeeded on the path.
+    pub fn is_capable(&self) -> bool {
+        self.state == ValidationState::Capable
+    }
+
+    /// Returns the number of packets sent with ECT(0) on the path.
+    pub fn ect0_sent(&self) -> usize {
+        self.ect0_sent
+    }
+
+    /// Returns the number of packets sent with ECT(1) on the path.
+    pub fn ect1_sent(&self) -> usize {
+        self.ect1_sent
+    }
+
+    fn marked_sent(&self) -> usize {
+        self.ect0_sent + self.ect1_sent
+    }
+
+    /// Returns the number of ECN-CE marks reported by the peer.
+    pub fn ce_reported(&self) -> u64 {
+        self.ce_reported
+    }
+
+    /// Writes the ECN validation state to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_u8(match self.state {
+            ValidationState::Disabled => 0,
+
+            ValidationState::Testing => 1,
+
+            ValidationState::Unknown => 2,
+
+            ValidationState::Capable => 3,
+
+            ValidationState::Failed => 4,
+        });
+
+        w.put_u64(self.ect0_sent as u64);
+        w.put_u64(self.ect1_sent as u64);
+        w.put_u64(self.ce_reported);
+    }
+
+    /// Reads ECN validation state written by [`to_snapshot()`].
+    ///
+    /// The validation state is only restored if ECN is `enabled` by the local
+    /// configuration.
+    ///
+    /// [`to_snapshot()`]: struct.EcnState.html#method.to_snapshot
+    pub fn from_snapshot(
+        r: &mut snapshot::Reader, enabled: bool, l4s: bool,
+    ) -> Result<EcnState> {
+        let state = match r.get_u8()? {
+            _ if !enabled => ValidationState::Disabled,
+
+            0 => ValidationState::Disabled,
+
+            1 => ValidationState::Testing,
+
+            2 => ValidationState::Unknown,
+
+            3 => ValidationState::Capable,
+
+            4 => ValidationState::Failed,
+
+            _ => return Err(Error::InvalidSnapshot),
+        };
+
+        Ok(EcnState {
+            state,
+            l4s,
+            ect0_sent: r.get_u64()? as usize,
+            ect1_sent: r.get_u64()? as usize,
+            ce_reported: r.get_u64()?,
+        })
+    }
+}
+
+/// Validates the ECN counts of an ACK frame that newly acknowledged
+/// `newly_acked_marked` packets marked with the `ect` codepoint, against the
+/// counts previously reported by the peer in the same packet number space.
+///
+/// Returns the increase of the ECN-CE count, or `None` if validation failed.
+pub fn validate_counts(
+    prev: &EcnCounts, counts: Option<&EcnCounts>, newly_acked_marked: usize,
+    ect: Ecn,
+) -> Option<u64> {
+    let counts = match counts {
+        Some(v) => v,
+
+        // The peer doesn't report ECN counts, but it acknowledged marked
+        // packets, so either the peer or the path doesn't support ECN.
+        None if newly_acked_marked > 0 => return None,
+
+        None => return Some(0),
+    };
+
+    if counts.ect0_count < prev.ect0_count ||
+        counts.ect1_count < prev.ect1_count ||
+        counts.ecn_ce_count < prev.ecn_ce_count
+    {
+        return None;
+    }
+
+    let ect0_increase = counts.ect0_count - prev.ect0_count;
+    let ect1_increase = counts.ect1_count - prev.ect1_count;
+    let ce_increase = counts.ecn_ce_count - prev.ecn_ce_count;
+
+    let (ect_increase, other_ect_increase) = if ect == Ecn::Ect1 {
+        (ect1_increase, ect0_increase)
+    } else {
+        (ect0_increase, ect1_increase)
+    };
+
+    // Packets are never sent with the other ECT codepoint, so the path is
+    // re-marking them.
+    if other_ect_increase > 0 {
+        return None;
+    }
+
+    // Marks were removed from the packets on the path.
+    if ect_increase + ce_increase < newly_acked_marked as u64 {
+        return None;
+    }
+
+    Some(ce_increase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(ect0_count: u64, ect1_count: u64, ecn_ce_count: u64) -> EcnCounts {
+        EcnCounts {
+            ect0_count,
+            ect1_count,
+            ecn_ce_count,
+        }
+    }
+
+    #[test]
+    fn codepoint_from_tos() {
+        assert_eq!(Ecn::from(0x00), Ecn::NotEct);
+        assert_eq!(Ecn::from(0x01), Ecn::Ect1);
+        assert_eq!(Ecn::from(0x02), Ecn::Ect0);
+        assert_eq!(Ecn::from(0x03), Ecn::Ce);
+
+        // DSCP bits are ignored.
+        assert_eq!(Ecn::from(0xb8), Ecn::NotEct);
+        assert_eq!(Ecn::from(0xba), Ecn::Ect0);
+
+        assert_eq!(u8::from(Ecn::Ect0), 0x02);
+        assert_eq!(u8::from(Ecn::Ce), 0x03);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut ecn = EcnState::new(false, false);
+        assert_eq!(ecn.codepoint(), Ecn::NotEct);
+
+        ecn.on_validated();
+        assert!(!ecn.is_capable());
+        assert_eq!(ecn.codepoint(), Ecn::NotEct);
+    }
+
+    #[test]
+    fn testing_then_capable() {
+        let mut ecn = EcnState::new(true, false);
+
+        for _ in 0..ECN_TESTING_PACKETS {
+            assert_eq!(ecn.codepoint(), Ecn::Ect0);
+            ecn.on_packet_sent(Ecn::Ect0);
+        }
+
+        // Marking is suspended until validation completes.
+        assert_eq!(ecn.codepoint(), Ecn::NotEct);
+        assert_eq!(ecn.ect0_sent(), ECN_TESTING_PACKETS);
+
+        ecn.on_validated();
+        assert!(ecn.is_capable());
+        assert_eq!(ecn.codepoint(), Ecn::Ect0);
+    }
+
+    #[test]
+    fn l4s() {
+        let mut ecn = EcnState::new(true, true);
+
+        for _ in 0..ECN_TESTING_PACKETS {
+            assert_eq!(ecn.codepoint(), Ecn::Ect1);
+            ecn.on_packet_sent(Ecn::Ect1);
+        }
+
+        assert_eq!(ecn.codepoint(), Ecn::NotEct);
+        assert_eq!(ecn.ect0_sent(), 0);
+        assert_eq!(ecn.ect1_sent(), ECN_TESTING_PACKETS);
+
+        ecn.on_validated();
+        assert_eq!(ecn.codepoint(), Ecn::Ect1);
+
+        ecn.set_l4s(false);
+        assert_eq!(ecn.codepoint(), Ecn::Ect0);
+    }
+
+    #[test]
+    fn all_marked_packets_lost() {
+        let mut ecn = EcnState::new(true, false);
+
+        for _ in 0..ECN_TESTING_PACKETS {
+            ecn.on_packet_sent(Ecn::Ect0);
+        }
+
+        ecn.on_marked_packets_lost(ECN_TESTING_PACKETS - 1, 0);
+        assert_eq!(ecn.codepoint(), Ecn::NotEct);
+        assert!(ecn.state == ValidationState::Unknown);
+
+        ecn.on_marked_packets_lost(ECN_TESTING_PACKETS, 0);
+        assert!(ecn.state == ValidationState::Failed);
+
+        // Failure is final.
+        ecn.on_validated();
+        assert!(!ecn.is_capable());
+        assert_eq!(ecn.codepoint(), Ecn::NotEct);
+    }
+
+    #[test]
+    fn validate() {
+        let prev = counts(5, 0, 1);
+
+        // Nothing to validate.
+        assert_eq!(validate_counts(&prev, None, 0, Ecn::Ect0), Some(0));
+
+        // Missing counts.
+        assert_eq!(validate_counts(&prev, None, 1, Ecn::Ect0), None);
+
+        // All marked packets are accounted for.
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(7, 0, 1)), 2, Ecn::Ect0),
+            Some(0)
+        );
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(6, 0, 2)), 2, Ecn::Ect0),
+            Some(1)
+        );
+
+        // Some marks were cleared.
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(6, 0, 1)), 2, Ecn::Ect0),
+            None
+        );
+
+        // Re-marked as ECT(1).
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(6, 1, 1)), 1, Ecn::Ect0),
+            None
+        );
+
+        // Counts can't decrease.
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(4, 0, 3)), 0, Ecn::Ect0),
+            None
+        );
+    }
+
+    #[test]
+    fn validate_l4s() {
+        let prev = counts(0, 5, 1);
+
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(0, 6, 2)), 2, Ecn::Ect1),
+            Some(1)
+        );
+
+        // Some marks were cleared.
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(0, 6, 1)), 2, Ecn::Ect1),
+            None
+        );
+
+        // Re-marked as ECT(0).
+        assert_eq!(
+            validate_counts(&prev, Some(&counts(1, 6, 1)), 1, Ecn::Ect1),
+            None
+        );
+    }
+}
@@ -115,6 +115,18 @@ pub enum Error {
 
     /// An invalid DCID was used when connecting to a remote peer.
     InvalidDcidInitialization,
+
+    /// The version information received from the peer doesn't match the
+    /// version negotiation outcome.
+    VersionNegotiation,
+
+    /// The confidentiality or integrity limit of the AEAD algorithm used by
+    /// the connection was reached.
+    AeadLimitReached,
+
+    /// The provided connection snapshot cannot be decoded, because it is
+    /// malformed or was created by an incompatible version of the library.
+    InvalidSnapshot,
 }
 
 /// QUIC error codes sent on the wire.
@@ -179,6 +191,9 @@ pub enum WireErrorCode {
     /// CONNECTION_CLOSE frame carrying this code except when the path does
     /// not support a large enough MTU.
     NoViablePath         = 0x10,
+    /// An endpoint detected an error during version negotiation, as defined
+    /// in [RFC9368](https://www.rfc-editor.org/rfc/rfc9368.html#section-10.2).
+    VersionNegotiationError = 0x11,
 }
 
 impl Error {
@@ -197,6 +212,9 @@ impl Error {
             Error::CryptoBufferExceeded =>
                 WireErrorCode::CryptoBufferExceeded as u64,
             Error::KeyUpdate => WireErrorCode::KeyUpdateError as u64,
+            Error::VersionNegotiation =>
+                WireErrorCode::VersionNegotiationError as u64,
+            Error::AeadLimitReached => WireErrorCode::AeadLimitReached as u64,
             _ => WireErrorCode::ProtocolViolation as u64,
         }
     }
@@ -227,6 +245,9 @@ impl Error {
             Error::InvalidAckRange => -21,
             Error::OptimisticAckDetected => -22,
             Error::InvalidDcidInitialization => -23,
+            Error::VersionNegotiation => -24,
+            Error::AeadLimitReached => -25,
+            Error::InvalidSnapshot => -26,
         }
     }
 }
@@ -215,6 +215,11 @@ pub extern "C" fn quiche_config_discover_pmtu(config: &mut Config, v: bool) {
     config.discover_pmtu(v);
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_config_enable_ecn(config: &mut Config, v: bool) {
+    config.enable_ecn(v);
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_config_set_pmtud_max_probes(
     config: &mut Config, max_probes: u8,
@@ -232,6 +237,16 @@ pub extern "C" fn quiche_config_enable_early_data(config: &mut Config) {
     config.enable_early_data();
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_config_enable_anti_replay(
+    config: &mut Config, window_ms: u64, capacity: size_t,
+) {
+    config.set_anti_replay(Arc::new(BloomAntiReplay::new(
+        Duration::from_millis(window_ms),
+        capacity,
+    )));
+}
+
 #[no_mangle]
 /// Corresponds to the `Config::set_application_protos_wire_format` Rust
 /// function.
@@ -247,6 +262,19 @@ pub extern "C" fn quiche_config_set_application_protos(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_config_set_available_versions(
+    config: &mut Config, versions: *const u32, versions_len: size_t,
+) -> c_int {
+    let versions = unsafe { slice::from_raw_parts(versions, versions_len) };
+
+    match config.set_available_versions(versions) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_config_set_max_amplification_factor(
     config: &mut Config, v: usize,
@@ -254,6 +282,13 @@ pub extern "C" fn quiche_config_set_max_amplification_factor(
     config.set_max_amplification_factor(v);
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_config_set_max_packets_per_key(
+    config: &mut Config, v: u64,
+) {
+    config.set_max_packets_per_key(v);
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_config_set_max_idle_timeout(
     config: &mut Config, v: u64,
@@ -322,6 +357,11 @@ pub extern "C" fn quiche_config_set_max_ack_delay(config: &mut Config, v: u64) {
     config.set_max_ack_delay(v);
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_config_set_min_ack_delay(config: &mut Config, v: u64) {
+    config.set_min_ack_delay(v);
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_config_set_disable_active_migration(
     config: &mut Config, v: bool,
@@ -399,6 +439,20 @@ pub extern "C" fn quiche_config_set_max_stream_window(
     config.set_max_stream_window(v);
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_config_set_initial_max_path_id(
+    config: &mut Config, v: u64,
+) {
+    config.set_initial_max_path_id(v);
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_config_enable_reset_stream_at(
+    config: &mut Config, v: bool,
+) {
+    config.enable_reset_stream_at(v);
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_config_set_active_connection_id_limit(
     config: &mut Config, v: u64,
@@ -561,6 +615,19 @@ pub extern "C" fn quiche_connect(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_restore(
+    state: *const u8, state_len: size_t, config: &mut Config,
+) -> *mut Connection {
+    let state = unsafe { slice::from_raw_parts(state, state_len) };
+
+    match restore(state, config) {
+        Ok(c) => Box::into_raw(Box::new(c)),
+
+        Err(_) => ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_negotiate_version(
     scid: *const u8, scid_len: size_t, dcid: *const u8, dcid_len: size_t,
@@ -806,12 +873,57 @@ pub extern "C" fn quiche_conn_set_max_idle_timeout(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_set_preferred_address(
+    conn: &mut Connection, addr_v4: *const sockaddr, addr_v4_len: socklen_t,
+    addr_v6: *const sockaddr, addr_v6_len: socklen_t, scid: *const u8,
+    scid_len: size_t, reset_token: *const u8,
+) -> c_int {
+    let addr_v4 = match unsafe { addr_v4.as_ref() } {
+        Some(addr) => match std_addr_from_c(addr, addr_v4_len) {
+            SocketAddr::V4(addr) => Some(addr),
+
+            SocketAddr::V6(_) => return Error::InvalidState.to_c() as c_int,
+        },
+
+        None => None,
+    };
+
+    let addr_v6 = match unsafe { addr_v6.as_ref() } {
+        Some(addr) => match std_addr_from_c(addr, addr_v6_len) {
+            SocketAddr::V6(addr) => Some(addr),
+
+            SocketAddr::V4(_) => return Error::InvalidState.to_c() as c_int,
+        },
+
+        None => None,
+    };
+
+    let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
+    let scid = ConnectionId::from_ref(scid);
+
+    let reset_token = unsafe { slice::from_raw_parts(reset_token, 16) };
+    let reset_token = match reset_token.try_into() {
+        Ok(rt) => rt,
+        Err(_) => unreachable!(),
+    };
+    let reset_token = u128::from_be_bytes(reset_token);
+
+    match conn.set_preferred_address(addr_v4, addr_v6, &scid, reset_token) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[repr(C)]
 pub struct RecvInfo<'a> {
     from: &'a sockaddr,
     from_len: socklen_t,
     to: &'a sockaddr,
     to_len: socklen_t,
+
+    ecn: u8,
 }
 
 impl From<&RecvInfo<'_>> for crate::RecvInfo {
@@ -819,6 +931,7 @@ impl From<&RecvInfo<'_>> for crate::RecvInfo {
         crate::RecvInfo {
             from: std_addr_from_c(info.from, info.from_len),
             to: std_addr_from_c(info.to, info.to_len),
+            ecn: info.ecn.into(),
         }
     }
 }
@@ -848,6 +961,8 @@ pub struct SendInfo {
     to_len: socklen_t,
 
     at: timespec,
+
+    ecn: u8,
 }
 
 #[no_mangle]
@@ -867,6 +982,8 @@ pub extern "C" fn quiche_conn_send(
 
             std_time_to_c(&info.at, &mut out_info.at);
 
+            out_info.ecn = info.ecn.into();
+
             v as ssize_t
         },
 
@@ -895,6 +1012,8 @@ pub extern "C" fn quiche_conn_send_on_path(
 
             std_time_to_c(&info.at, &mut out_info.at);
 
+            out_info.ecn = info.ecn.into();
+
             v as ssize_t
         },
 
@@ -983,6 +1102,17 @@ pub extern "C" fn quiche_conn_stream_shutdown(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_stream_reset_at(
+    conn: &mut Connection, stream_id: u64, err: u64, reliable_size: u64,
+) -> c_int {
+    match conn.stream_reset_at(stream_id, err, reliable_size) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_conn_stream_capacity(
     conn: &mut Connection, stream_id: u64,
@@ -1031,6 +1161,13 @@ pub extern "C" fn quiche_conn_stream_finished(
     conn.stream_finished(stream_id)
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_stream_received_early_data(
+    conn: &Connection, stream_id: u64,
+) -> bool {
+    conn.stream_received_early_data(stream_id)
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_conn_readable(conn: &Connection) -> *mut StreamIter {
     Box::into_raw(Box::new(conn.readable()))
@@ -1072,6 +1209,17 @@ pub extern "C" fn quiche_conn_close(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_initiate_key_update(
+    conn: &mut Connection,
+) -> c_int {
+    match conn.initiate_key_update() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_conn_timeout_as_nanos(conn: &Connection) -> u64 {
     match conn.timeout() {
@@ -1212,6 +1360,31 @@ pub extern "C" fn quiche_conn_session(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_export_state(
+    conn: &Connection, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    if out_len > <ssize_t>::MAX as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    let state = match conn.export_state() {
+        Ok(v) => v,
+
+        Err(e) => return e.to_c(),
+    };
+
+    if state.len() > out.len() {
+        return Error::BufferTooShort.to_c();
+    }
+
+    out[..state.len()].copy_from_slice(&state);
+
+    state.len() as ssize_t
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_conn_server_name(
     conn: &Connection, out: &mut *const u8, out_len: &mut size_t,
@@ -1325,6 +1498,9 @@ pub struct Stats {
     stream_retrans_bytes: u64,
     dgram_recv: usize,
     dgram_sent: usize,
+    dgram_expired: usize,
+    dgram_send_dropped: usize,
+    dgram_recv_dropped: usize,
     paths_count: usize,
     reset_stream_count_local: u64,
     stopped_stream_count_local: u64,
@@ -1337,6 +1513,9 @@ pub struct Stats {
     path_challenge_rx_count: u64,
     bytes_in_flight_duration_msec: u64,
     tx_buffered_inconsistent: bool,
+    ecn_ect0_recv_count: u64,
+    ecn_ect1_recv_count: u64,
+    ecn_ce_recv_count: u64,
 }
 
 pub struct TransportParams {
@@ -1371,6 +1550,9 @@ pub extern "C" fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
     out.stream_retrans_bytes = stats.stream_retrans_bytes;
     out.dgram_recv = stats.dgram_recv;
     out.dgram_sent = stats.dgram_sent;
+    out.dgram_expired = stats.dgram_expired;
+    out.dgram_send_dropped = stats.dgram_send_dropped;
+    out.dgram_recv_dropped = stats.dgram_recv_dropped;
     out.paths_count = stats.paths_count;
     out.reset_stream_count_local = stats.reset_stream_count_local;
     out.stopped_stream_count_local = stats.stopped_stream_count_local;
@@ -1385,6 +1567,9 @@ pub extern "C" fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
         stats.bytes_in_flight_duration.as_millis() as u64;
     out.tx_buffered_inconsistent =
         stats.tx_buffered_state != TxBufferTrackingState::Ok;
+    out.ecn_ect0_recv_count = stats.ecn_ect0_recv_count;
+    out.ecn_ect1_recv_count = stats.ecn_ect1_recv_count;
+    out.ecn_ce_recv_count = stats.ecn_ce_recv_count;
 }
 
 #[no_mangle]
@@ -1447,6 +1632,10 @@ pub struct PathStats {
     delivery_rate: u64,
     max_bandwidth: u64,
     startup_exit_cwnd: u64,
+    ecn_capable: bool,
+    ecn_ect0_sent_count: usize,
+    ecn_ect1_sent_count: usize,
+    ecn_ce_reported_count: u64,
 }
 
 #[no_mangle]
@@ -1482,6 +1671,10 @@ pub extern "C" fn quiche_conn_path_stats(
     out.max_bandwidth = stats.max_bandwidth.unwrap_or(0);
     out.startup_exit_cwnd =
         stats.startup_exit.map(|s| s.cwnd as u64).unwrap_or(0);
+    out.ecn_capable = stats.ecn_capable;
+    out.ecn_ect0_sent_count = stats.ecn_ect0_sent_count;
+    out.ecn_ect1_sent_count = stats.ecn_ect1_sent_count;
+    out.ecn_ce_reported_count = stats.ecn_ce_reported_count;
 
     0
 }
@@ -1491,6 +1684,16 @@ pub extern "C" fn quiche_conn_is_server(conn: &Connection) -> bool {
     conn.is_server()
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_version(conn: &Connection) -> u32 {
+    conn.version()
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_conn_original_version(conn: &Connection) -> u32 {
+    conn.original_version()
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_conn_dgram_max_writable_len(
     conn: &Connection,
@@ -1552,6 +1755,47 @@ pub extern "C" fn quiche_conn_dgram_send(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_conn_dgram_send_tracked(
+    conn: &mut Connection, buf: *const u8, buf_len: size_t, urgency: u8,
+    out_id: *mut u64,
+) -> ssize_t {
+    if buf_len > <ssize_t>::MAX as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let buf = unsafe { slice::from_raw_parts(buf, buf_len) };
+
+    match conn.dgram_send_tracked(buf, urgency, None) {
+        Ok(id) => {
+            unsafe { *out_id = id };
+
+            buf_len as ssize_t
+        },
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_conn_dgram_event_next(
+    conn: &mut Connection, out_id: *mut u64,
+) -> c_int {
+    let (ty, id) = match conn.dgram_event_next() {
+        Some(DatagramEvent::Acked(id)) => (0, id),
+
+        Some(DatagramEvent::Lost(id)) => (1, id),
+
+        Some(DatagramEvent::Dropped(id)) => (2, id),
+
+        None => return Error::Done.to_c() as c_int,
+    };
+
+    unsafe { *out_id = id };
+
+    ty
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_conn_dgram_recv(
     conn: &mut Connection, out: *mut u8, out_len: size_t,
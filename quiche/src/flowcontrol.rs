@@ -130,6 +130,33 @@ impl FlowControl {
             self.window = min_window;
         }
     }
+
+    /// Writes the flow control state to a connection snapshot.
+    pub(crate) fn to_snapshot(&self, w: &mut crate::snapshot::Writer) {
+        w.put_u64(self.consumed);
+        w.put_u64(self.max_data);
+        w.put_u64(self.window);
+        w.put_u64(self.max_window);
+    }
+
+    /// Reads flow control state written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.FlowControl.html#method.to_snapshot
+    pub(crate) fn from_snapshot(
+        r: &mut crate::snapshot::Reader,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            consumed: r.get_u64()?,
+
+            max_data: r.get_u64()?,
+
+            window: r.get_u64()?,
+
+            max_window: r.get_u64()?,
+
+            last_update: None,
+        })
+    }
 }
 
 #[cfg(test)]
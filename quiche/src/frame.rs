@@ -47,11 +47,31 @@ pub const MAX_DGRAM_OVERHEAD: usize = 2;
 pub const MAX_STREAM_OVERHEAD: usize = 12;
 pub const MAX_STREAM_SIZE: u64 = 1 << 62;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct EcnCounts {
-    ect0_count: u64,
-    ect1_count: u64,
-    ecn_ce_count: u64,
+    pub ect0_count: u64,
+    pub ect1_count: u64,
+    pub ecn_ce_count: u64,
+}
+
+impl EcnCounts {
+    /// Counts a received packet marked with the given ECN codepoint.
+    pub fn on_packet_received(&mut self, ecn: crate::Ecn) {
+        match ecn {
+            crate::Ecn::Ect0 => self.ect0_count += 1,
+
+            crate::Ecn::Ect1 => self.ect1_count += 1,
+
+            crate::Ecn::Ce => self.ecn_ce_count += 1,
+
+            crate::Ecn::NotEct => (),
+        }
+    }
+
+    /// Returns whether no ECN-marked packet was counted.
+    pub fn is_empty(&self) -> bool {
+        self.ect0_count == 0 && self.ect1_count == 0 && self.ecn_ce_count == 0
+    }
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -81,6 +101,13 @@ pub enum Frame {
         final_size: u64,
     },
 
+    ResetStreamAt {
+        stream_id: u64,
+        error_code: u64,
+        final_size: u64,
+        reliable_size: u64,
+    },
+
     StopSending {
         stream_id: u64,
         error_code: u64,
@@ -177,12 +204,70 @@ pub enum Frame {
 
     HandshakeDone,
 
+    AckFrequency {
+        seq_num: u64,
+        ack_eliciting_threshold: u64,
+        request_max_ack_delay: u64,
+        reordering_threshold: u64,
+    },
+
+    ImmediateAck,
+
+    PathAck {
+        path_id: u64,
+        ack_delay: u64,
+        ranges: ranges::RangeSet,
+        ecn_counts: Option<EcnCounts>,
+    },
+
+    PathAbandon {
+        path_id: u64,
+        error_code: u64,
+    },
+
+    PathStatusBackup {
+        path_id: u64,
+        seq_num: u64,
+    },
+
+    PathStatusAvailable {
+        path_id: u64,
+        seq_num: u64,
+    },
+
+    PathNewConnectionId {
+        path_id: u64,
+        seq_num: u64,
+        retire_prior_to: u64,
+        conn_id: Vec<u8>,
+        reset_token: [u8; 16],
+    },
+
+    PathRetireConnectionId {
+        path_id: u64,
+        seq_num: u64,
+    },
+
+    MaxPathId {
+        max: u64,
+    },
+
+    PathsBlocked {
+        max: u64,
+    },
+
+    PathCidsBlocked {
+        path_id: u64,
+        next_seq_num: u64,
+    },
+
     Datagram {
         data: Vec<u8>,
     },
 
     DatagramHeader {
         length: usize,
+        id: Option<u64>,
     },
 }
 
@@ -329,8 +414,105 @@ impl Frame {
 
             0x1e => Frame::HandshakeDone,
 
+            0x1f => Frame::ImmediateAck,
+
+            0x24 => {
+                let stream_id = b.get_varint()?;
+                let error_code = b.get_varint()?;
+                let final_size = b.get_varint()?;
+                let reliable_size = b.get_varint()?;
+
+                if reliable_size > final_size {
+                    return Err(Error::InvalidFrame);
+                }
+
+                Frame::ResetStreamAt {
+                    stream_id,
+                    error_code,
+                    final_size,
+                    reliable_size,
+                }
+            },
+
+            0xaf => Frame::AckFrequency {
+                seq_num: b.get_varint()?,
+                ack_eliciting_threshold: b.get_varint()?,
+                request_max_ack_delay: b.get_varint()?,
+                reordering_threshold: b.get_varint()?,
+            },
+
             0x30 | 0x31 => parse_datagram_frame(frame_type, b)?,
 
+            0x15228c00..=0x15228c01 => {
+                let path_id = b.get_varint()?;
+
+                let (ack_delay, ranges, ecn_counts) =
+                    parse_ack_body(frame_type, b)?;
+
+                Frame::PathAck {
+                    path_id,
+                    ack_delay,
+                    ranges,
+                    ecn_counts,
+                }
+            },
+
+            0x15228c05 => Frame::PathAbandon {
+                path_id: b.get_varint()?,
+                error_code: b.get_varint()?,
+            },
+
+            0x15228c07 => Frame::PathStatusBackup {
+                path_id: b.get_varint()?,
+                seq_num: b.get_varint()?,
+            },
+
+            0x15228c08 => Frame::PathStatusAvailable {
+                path_id: b.get_varint()?,
+                seq_num: b.get_varint()?,
+            },
+
+            0x15228c09 => {
+                let path_id = b.get_varint()?;
+                let seq_num = b.get_varint()?;
+                let retire_prior_to = b.get_varint()?;
+                let conn_id_len = b.get_u8()?;
+
+                if !(1..=packet::MAX_CID_LEN).contains(&conn_id_len) {
+                    return Err(Error::InvalidFrame);
+                }
+
+                Frame::PathNewConnectionId {
+                    path_id,
+                    seq_num,
+                    retire_prior_to,
+                    conn_id: b.get_bytes(conn_id_len as usize)?.to_vec(),
+                    reset_token: b
+                        .get_bytes(16)?
+                        .buf()
+                        .try_into()
+                        .map_err(|_| Error::BufferTooShort)?,
+                }
+            },
+
+            0x15228c0a => Frame::PathRetireConnectionId {
+                path_id: b.get_varint()?,
+                seq_num: b.get_varint()?,
+            },
+
+            0x15228c0c => Frame::MaxPathId {
+                max: b.get_varint()?,
+            },
+
+            0x15228c0d => Frame::PathsBlocked {
+                max: b.get_varint()?,
+            },
+
+            0x15228c0e => Frame::PathCidsBlocked {
+                path_id: b.get_varint()?,
+                next_seq_num: b.get_varint()?,
+            },
+
             _ => return Err(Error::InvalidFrame),
         };
 
@@ -348,6 +530,10 @@ impl Frame {
             (packet::Type::ZeroRTT, Frame::RetireConnectionId { .. }) => false,
             (packet::Type::ZeroRTT, Frame::ConnectionClose { .. }) => false,
 
+            // Multipath frames can only be sent on 1-RTT packets.
+            (packet::Type::Short, f) if f.is_multipath() => true,
+            (_, f) if f.is_multipath() => false,
+
             // ACK, CRYPTO and CONNECTION_CLOSE can be sent on all other packet
             // types.
             (_, Frame::ACK { .. }) => true,
@@ -398,33 +584,7 @@ impl Frame {
                     b.put_varint(0x03)?;
                 }
 
-                let mut it = ranges.iter().rev();
-
-                let first = it.next().unwrap();
-                let ack_block = (first.end - 1) - first.start;
-
-                b.put_varint(first.end - 1)?;
-                b.put_varint(*ack_delay)?;
-                b.put_varint(it.len() as u64)?;
-                b.put_varint(ack_block)?;
-
-                let mut smallest_ack = first.start;
-
-                for block in it {
-                    let gap = smallest_ack - block.end - 1;
-                    let ack_block = (block.end - 1) - block.start;
-
-                    b.put_varint(gap)?;
-                    b.put_varint(ack_block)?;
-
-                    smallest_ack = block.start;
-                }
-
-                if let Some(ecn) = ecn_counts {
-                    b.put_varint(ecn.ect0_count)?;
-                    b.put_varint(ecn.ect1_count)?;
-                    b.put_varint(ecn.ecn_ce_count)?;
-                }
+                encode_ack_body(*ack_delay, ranges, ecn_counts.as_ref(), b)?;
             },
 
             Frame::ResetStream {
@@ -439,6 +599,20 @@ impl Frame {
                 b.put_varint(*final_size)?;
             },
 
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                b.put_varint(0x24)?;
+
+                b.put_varint(*stream_id)?;
+                b.put_varint(*error_code)?;
+                b.put_varint(*final_size)?;
+                b.put_varint(*reliable_size)?;
+            },
+
             Frame::StopSending {
                 stream_id,
                 error_code,
@@ -586,6 +760,111 @@ impl Frame {
                 b.put_varint(0x1e)?;
             },
 
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                b.put_varint(0xaf)?;
+
+                b.put_varint(*seq_num)?;
+                b.put_varint(*ack_eliciting_threshold)?;
+                b.put_varint(*request_max_ack_delay)?;
+                b.put_varint(*reordering_threshold)?;
+            },
+
+            Frame::ImmediateAck => {
+                b.put_varint(0x1f)?;
+            },
+
+            Frame::PathAck {
+                path_id,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                if ecn_counts.is_none() {
+                    b.put_varint(0x15228c00)?;
+                } else {
+                    b.put_varint(0x15228c01)?;
+                }
+
+                b.put_varint(*path_id)?;
+
+                encode_ack_body(*ack_delay, ranges, ecn_counts.as_ref(), b)?;
+            },
+
+            Frame::PathAbandon {
+                path_id,
+                error_code,
+            } => {
+                b.put_varint(0x15228c05)?;
+
+                b.put_varint(*path_id)?;
+                b.put_varint(*error_code)?;
+            },
+
+            Frame::PathStatusBackup { path_id, seq_num } => {
+                b.put_varint(0x15228c07)?;
+
+                b.put_varint(*path_id)?;
+                b.put_varint(*seq_num)?;
+            },
+
+            Frame::PathStatusAvailable { path_id, seq_num } => {
+                b.put_varint(0x15228c08)?;
+
+                b.put_varint(*path_id)?;
+                b.put_varint(*seq_num)?;
+            },
+
+            Frame::PathNewConnectionId {
+                path_id,
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                b.put_varint(0x15228c09)?;
+
+                b.put_varint(*path_id)?;
+                b.put_varint(*seq_num)?;
+                b.put_varint(*retire_prior_to)?;
+                b.put_u8(conn_id.len() as u8)?;
+                b.put_bytes(conn_id.as_ref())?;
+                b.put_bytes(reset_token.as_ref())?;
+            },
+
+            Frame::PathRetireConnectionId { path_id, seq_num } => {
+                b.put_varint(0x15228c0a)?;
+
+                b.put_varint(*path_id)?;
+                b.put_varint(*seq_num)?;
+            },
+
+            Frame::MaxPathId { max } => {
+                b.put_varint(0x15228c0c)?;
+
+                b.put_varint(*max)?;
+            },
+
+            Frame::PathsBlocked { max } => {
+                b.put_varint(0x15228c0d)?;
+
+                b.put_varint(*max)?;
+            },
+
+            Frame::PathCidsBlocked {
+                path_id,
+                next_seq_num,
+            } => {
+                b.put_varint(0x15228c0e)?;
+
+                b.put_varint(*path_id)?;
+                b.put_varint(*next_seq_num)?;
+            },
+
             Frame::Datagram { data } => {
                 encode_dgram_header(data.len() as u64, b)?;
 
@@ -609,36 +888,8 @@ impl Frame {
                 ranges,
                 ecn_counts,
             } => {
-                let mut it = ranges.iter().rev();
-
-                let first = it.next().unwrap();
-                let ack_block = (first.end - 1) - first.start;
-
-                let mut len = 1 + // frame type
-                    octets::varint_len(first.end - 1) + // largest_ack
-                    octets::varint_len(*ack_delay) + // ack_delay
-                    octets::varint_len(it.len() as u64) + // block_count
-                    octets::varint_len(ack_block); // first_block
-
-                let mut smallest_ack = first.start;
-
-                for block in it {
-                    let gap = smallest_ack - block.end - 1;
-                    let ack_block = (block.end - 1) - block.start;
-
-                    len += octets::varint_len(gap) + // gap
-                           octets::varint_len(ack_block); // ack_block
-
-                    smallest_ack = block.start;
-                }
-
-                if let Some(ecn) = ecn_counts {
-                    len += octets::varint_len(ecn.ect0_count) +
-                        octets::varint_len(ecn.ect1_count) +
-                        octets::varint_len(ecn.ecn_ce_count);
-                }
-
-                len
+                1 + // frame type
+                ack_body_len(*ack_delay, ranges, ecn_counts.as_ref())
             },
 
             Frame::ResetStream {
@@ -652,6 +903,19 @@ impl Frame {
                 octets::varint_len(*final_size) // final_size
             },
 
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                1 + // frame type
+                octets::varint_len(*stream_id) + // stream_id
+                octets::varint_len(*error_code) + // error_code
+                octets::varint_len(*final_size) + // final_size
+                octets::varint_len(*reliable_size) // reliable_size
+            },
+
             Frame::StopSending {
                 stream_id,
                 error_code,
@@ -797,13 +1061,88 @@ impl Frame {
                 1 // frame type
             },
 
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                2 + // frame type
+                octets::varint_len(*seq_num) + // seq_num
+                octets::varint_len(*ack_eliciting_threshold) + // threshold
+                octets::varint_len(*request_max_ack_delay) + // max_ack_delay
+                octets::varint_len(*reordering_threshold) // reordering
+            },
+
+            Frame::ImmediateAck => {
+                1 // frame type
+            },
+
+            Frame::PathAck {
+                path_id,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                4 + // frame type
+                octets::varint_len(*path_id) + // path_id
+                ack_body_len(*ack_delay, ranges, ecn_counts.as_ref())
+            },
+
+            Frame::PathAbandon {
+                path_id,
+                error_code,
+            } => {
+                4 + // frame type
+                octets::varint_len(*path_id) + // path_id
+                octets::varint_len(*error_code) // error_code
+            },
+
+            Frame::PathStatusBackup { path_id, seq_num } |
+            Frame::PathStatusAvailable { path_id, seq_num } |
+            Frame::PathRetireConnectionId { path_id, seq_num } => {
+                4 + // frame type
+                octets::varint_len(*path_id) + // path_id
+                octets::varint_len(*seq_num) // seq_num
+            },
+
+            Frame::PathNewConnectionId {
+                path_id,
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                4 + // frame type
+                octets::varint_len(*path_id) + // path_id
+                octets::varint_len(*seq_num) + // seq_num
+                octets::varint_len(*retire_prior_to) + // retire_prior_to
+                1 + // conn_id length
+                conn_id.len() + // conn_id
+                reset_token.len() // reset_token
+            },
+
+            Frame::MaxPathId { max } | Frame::PathsBlocked { max } => {
+                4 + // frame type
+                octets::varint_len(*max) // max
+            },
+
+            Frame::PathCidsBlocked {
+                path_id,
+                next_seq_num,
+            } => {
+                4 + // frame type
+                octets::varint_len(*path_id) + // path_id
+                octets::varint_len(*next_seq_num) // next_seq_num
+            },
+
             Frame::Datagram { data } => {
                 1 + // frame type
                 2 + // length, always encode as 2-byte varint
                 data.len() // data
             },
 
-            Frame::DatagramHeader { length } => {
+            Frame::DatagramHeader { length, .. } => {
                 1 + // frame type
                 2 + // length, always encode as 2-byte varint
                 *length // data
@@ -817,6 +1156,7 @@ impl Frame {
             self,
             Frame::Padding { .. } |
                 Frame::ACK { .. } |
+                Frame::PathAck { .. } |
                 Frame::ApplicationClose { .. } |
                 Frame::ConnectionClose { .. }
         )
@@ -827,11 +1167,28 @@ impl Frame {
             self,
             Frame::Padding { .. } |
                 Frame::NewConnectionId { .. } |
+                Frame::PathNewConnectionId { .. } |
                 Frame::PathChallenge { .. } |
                 Frame::PathResponse { .. }
         )
     }
 
+    /// Returns whether the frame belongs to the multipath extension.
+    pub fn is_multipath(&self) -> bool {
+        matches!(
+            self,
+            Frame::PathAck { .. } |
+                Frame::PathAbandon { .. } |
+                Frame::PathStatusBackup { .. } |
+                Frame::PathStatusAvailable { .. } |
+                Frame::PathNewConnectionId { .. } |
+                Frame::PathRetireConnectionId { .. } |
+                Frame::MaxPathId { .. } |
+                Frame::PathsBlocked { .. } |
+                Frame::PathCidsBlocked { .. }
+        )
+    }
+
     #[cfg(feature = "qlog")]
     pub fn to_qlog(&self) -> QuicFrame {
         match self {
@@ -887,6 +1244,20 @@ impl Frame {
                 payload_length: None,
             },
 
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => QuicFrame::ResetStreamAt {
+                stream_id: *stream_id,
+                error_code: *error_code,
+                final_size: *final_size,
+                reliable_size: *reliable_size,
+                length: None,
+                payload_length: None,
+            },
+
             Frame::StopSending {
                 stream_id,
                 error_code,
@@ -1024,12 +1395,115 @@ impl Frame {
 
             Frame::HandshakeDone => QuicFrame::HandshakeDone,
 
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => QuicFrame::AckFrequency {
+                sequence_number: *seq_num,
+                ack_eliciting_threshold: *ack_eliciting_threshold,
+                request_max_ack_delay: *request_max_ack_delay as f32 / 1000.0,
+                reordering_threshold: *reordering_threshold,
+            },
+
+            Frame::ImmediateAck => QuicFrame::ImmediateAck,
+
+            Frame::PathAck {
+                path_id,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                let ack_ranges = AckedRanges::Double(
+                    ranges.iter().map(|r| (r.start, r.end - 1)).collect(),
+                );
+
+                let (ect0, ect1, ce) = match ecn_counts {
+                    Some(ecn) => (
+                        Some(ecn.ect0_count),
+                        Some(ecn.ect1_count),
+                        Some(ecn.ecn_ce_count),
+                    ),
+
+                    None => (None, None, None),
+                };
+
+                QuicFrame::PathAck {
+                    path_id: *path_id,
+                    ack_delay: Some(*ack_delay as f32 / 1000.0),
+                    acked_ranges: Some(ack_ranges),
+                    ect1,
+                    ect0,
+                    ce,
+                }
+            },
+
+            Frame::PathAbandon {
+                path_id,
+                error_code,
+            } => QuicFrame::PathAbandon {
+                path_id: *path_id,
+                error_code: *error_code,
+            },
+
+            Frame::PathStatusBackup { path_id, seq_num } =>
+                QuicFrame::PathStatusBackup {
+                    path_id: *path_id,
+                    path_status_sequence_number: *seq_num,
+                },
+
+            Frame::PathStatusAvailable { path_id, seq_num } =>
+                QuicFrame::PathStatusAvailable {
+                    path_id: *path_id,
+                    path_status_sequence_number: *seq_num,
+                },
+
+            Frame::PathNewConnectionId {
+                path_id,
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => QuicFrame::PathNewConnectionId {
+                path_id: *path_id,
+                sequence_number: *seq_num as u32,
+                retire_prior_to: *retire_prior_to as u32,
+                connection_id_length: Some(conn_id.len() as u8),
+                connection_id: format!("{}", qlog::HexSlice::new(conn_id)),
+                stateless_reset_token: qlog::HexSlice::maybe_string(Some(
+                    reset_token,
+                )),
+            },
+
+            Frame::PathRetireConnectionId { path_id, seq_num } =>
+                QuicFrame::PathRetireConnectionId {
+                    path_id: *path_id,
+                    sequence_number: *seq_num as u32,
+                },
+
+            Frame::MaxPathId { max } => QuicFrame::MaxPathId {
+                maximum_path_id: *max,
+            },
+
+            Frame::PathsBlocked { max } => QuicFrame::PathsBlocked {
+                maximum_path_id: *max,
+            },
+
+            Frame::PathCidsBlocked {
+                path_id,
+                next_seq_num,
+            } => QuicFrame::PathCidsBlocked {
+                path_id: *path_id,
+                next_sequence_number: *next_seq_num,
+            },
+
             Frame::Datagram { data } => QuicFrame::Datagram {
                 length: data.len() as u64,
                 raw: None,
             },
 
-            Frame::DatagramHeader { length } => QuicFrame::Datagram {
+            Frame::DatagramHeader { length, .. } => QuicFrame::Datagram {
                 length: *length as u64,
                 raw: None,
             },
@@ -1070,6 +1544,18 @@ impl std::fmt::Debug for Frame {
                 )?;
             },
 
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                write!(
+                    f,
+                    "RESET_STREAM_AT stream={stream_id} err={error_code:x} size={final_size} reliable_size={reliable_size}"
+                )?;
+            },
+
             Frame::StopSending {
                 stream_id,
                 error_code,
@@ -1143,11 +1629,107 @@ impl std::fmt::Debug for Frame {
                 write!(f, "STREAMS_BLOCKED type=bidi limit={limit}")?;
             },
 
-            Frame::StreamsBlockedUni { limit } => {
-                write!(f, "STREAMS_BLOCKED type=uni limit={limit}")?;
+            Frame::StreamsBlockedUni { limit } => {
+                write!(f, "STREAMS_BLOCKED type=uni limit={limit}")?;
+            },
+
+            Frame::NewConnectionId {
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                write!(
+                    f,
+                    "NEW_CONNECTION_ID seq_num={seq_num} retire_prior_to={retire_prior_to} conn_id={conn_id:02x?} reset_token={reset_token:02x?}",
+                )?;
+            },
+
+            Frame::RetireConnectionId { seq_num } => {
+                write!(f, "RETIRE_CONNECTION_ID seq_num={seq_num}")?;
+            },
+
+            Frame::PathChallenge { data } => {
+                write!(f, "PATH_CHALLENGE data={data:02x?}")?;
+            },
+
+            Frame::PathResponse { data } => {
+                write!(f, "PATH_RESPONSE data={data:02x?}")?;
+            },
+
+            Frame::ConnectionClose {
+                error_code,
+                frame_type,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "CONNECTION_CLOSE err={error_code:x} frame={frame_type:x} reason={reason:x?}"
+                )?;
+            },
+
+            Frame::ApplicationClose { error_code, reason } => {
+                write!(
+                    f,
+                    "APPLICATION_CLOSE err={error_code:x} reason={reason:x?}"
+                )?;
+            },
+
+            Frame::HandshakeDone => {
+                write!(f, "HANDSHAKE_DONE")?;
+            },
+
+            Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                write!(
+                    f,
+                    "ACK_FREQUENCY seq_num={seq_num} threshold={ack_eliciting_threshold} max_ack_delay={request_max_ack_delay} reordering={reordering_threshold}"
+                )?;
+            },
+
+            Frame::ImmediateAck => {
+                write!(f, "IMMEDIATE_ACK")?;
+            },
+
+            Frame::PathAck {
+                path_id,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                write!(
+                    f,
+                    "PATH_ACK path_id={path_id} delay={ack_delay} blocks={ranges:?} ecn_counts={ecn_counts:?}"
+                )?;
+            },
+
+            Frame::PathAbandon {
+                path_id,
+                error_code,
+            } => {
+                write!(f, "PATH_ABANDON path_id={path_id} err={error_code:x}")?;
+            },
+
+            Frame::PathStatusBackup { path_id, seq_num } => {
+                write!(
+                    f,
+                    "PATH_STATUS_BACKUP path_id={path_id} seq_num={seq_num}"
+                )?;
+            },
+
+            Frame::PathStatusAvailable { path_id, seq_num } => {
+                write!(
+                    f,
+                    "PATH_STATUS_AVAILABLE path_id={path_id} seq_num={seq_num}"
+                )?;
             },
 
-            Frame::NewConnectionId {
+            Frame::PathNewConnectionId {
+                path_id,
                 seq_num,
                 retire_prior_to,
                 conn_id,
@@ -1155,49 +1737,40 @@ impl std::fmt::Debug for Frame {
             } => {
                 write!(
                     f,
-                    "NEW_CONNECTION_ID seq_num={seq_num} retire_prior_to={retire_prior_to} conn_id={conn_id:02x?} reset_token={reset_token:02x?}",
+                    "PATH_NEW_CONNECTION_ID path_id={path_id} seq_num={seq_num} retire_prior_to={retire_prior_to} conn_id={conn_id:02x?} reset_token={reset_token:02x?}",
                 )?;
             },
 
-            Frame::RetireConnectionId { seq_num } => {
-                write!(f, "RETIRE_CONNECTION_ID seq_num={seq_num}")?;
+            Frame::PathRetireConnectionId { path_id, seq_num } => {
+                write!(
+                    f,
+                    "PATH_RETIRE_CONNECTION_ID path_id={path_id} seq_num={seq_num}"
+                )?;
             },
 
-            Frame::PathChallenge { data } => {
-                write!(f, "PATH_CHALLENGE data={data:02x?}")?;
+            Frame::MaxPathId { max } => {
+                write!(f, "MAX_PATH_ID max={max}")?;
             },
 
-            Frame::PathResponse { data } => {
-                write!(f, "PATH_RESPONSE data={data:02x?}")?;
+            Frame::PathsBlocked { max } => {
+                write!(f, "PATHS_BLOCKED max={max}")?;
             },
 
-            Frame::ConnectionClose {
-                error_code,
-                frame_type,
-                reason,
+            Frame::PathCidsBlocked {
+                path_id,
+                next_seq_num,
             } => {
                 write!(
                     f,
-                    "CONNECTION_CLOSE err={error_code:x} frame={frame_type:x} reason={reason:x?}"
-                )?;
-            },
-
-            Frame::ApplicationClose { error_code, reason } => {
-                write!(
-                    f,
-                    "APPLICATION_CLOSE err={error_code:x} reason={reason:x?}"
+                    "PATH_CIDS_BLOCKED path_id={path_id} next_seq_num={next_seq_num}"
                 )?;
             },
 
-            Frame::HandshakeDone => {
-                write!(f, "HANDSHAKE_DONE")?;
-            },
-
             Frame::Datagram { data } => {
                 write!(f, "DATAGRAM len={}", data.len())?;
             },
 
-            Frame::DatagramHeader { length } => {
+            Frame::DatagramHeader { length, .. } => {
                 write!(f, "DATAGRAM len={length}")?;
             },
         }
@@ -1207,6 +1780,20 @@ impl std::fmt::Debug for Frame {
 }
 
 fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+    let (ack_delay, ranges, ecn_counts) = parse_ack_body(ty, b)?;
+
+    Ok(Frame::ACK {
+        ack_delay,
+        ranges,
+        ecn_counts,
+    })
+}
+
+/// Parses the fields shared by ACK and PATH_ACK frames, i.e. everything
+/// following the frame type and, for PATH_ACK, the path identifier.
+fn parse_ack_body(
+    ty: u64, b: &mut octets::Octets,
+) -> Result<(u64, ranges::RangeSet, Option<EcnCounts>)> {
     let first = ty as u8;
 
     let largest_ack = b.get_varint()?;
@@ -1255,11 +1842,76 @@ fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
         None
     };
 
-    Ok(Frame::ACK {
-        ack_delay,
-        ranges,
-        ecn_counts,
-    })
+    Ok((ack_delay, ranges, ecn_counts))
+}
+
+fn encode_ack_body(
+    ack_delay: u64, ranges: &ranges::RangeSet, ecn_counts: Option<&EcnCounts>,
+    b: &mut octets::OctetsMut,
+) -> Result<()> {
+    let mut it = ranges.iter().rev();
+
+    let first = it.next().unwrap();
+    let ack_block = (first.end - 1) - first.start;
+
+    b.put_varint(first.end - 1)?;
+    b.put_varint(ack_delay)?;
+    b.put_varint(it.len() as u64)?;
+    b.put_varint(ack_block)?;
+
+    let mut smallest_ack = first.start;
+
+    for block in it {
+        let gap = smallest_ack - block.end - 1;
+        let ack_block = (block.end - 1) - block.start;
+
+        b.put_varint(gap)?;
+        b.put_varint(ack_block)?;
+
+        smallest_ack = block.start;
+    }
+
+    if let Some(ecn) = ecn_counts {
+        b.put_varint(ecn.ect0_count)?;
+        b.put_varint(ecn.ect1_count)?;
+        b.put_varint(ecn.ecn_ce_count)?;
+    }
+
+    Ok(())
+}
+
+fn ack_body_len(
+    ack_delay: u64, ranges: &ranges::RangeSet, ecn_counts: Option<&EcnCounts>,
+) -> usize {
+    let mut it = ranges.iter().rev();
+
+    let first = it.next().unwrap();
+    let ack_block = (first.end - 1) - first.start;
+
+    let mut len = octets::varint_len(first.end - 1) + // largest_ack
+        octets::varint_len(ack_delay) + // ack_delay
+        octets::varint_len(it.len() as u64) + // block_count
+        octets::varint_len(ack_block); // first_block
+
+    let mut smallest_ack = first.start;
+
+    for block in it {
+        let gap = smallest_ack - block.end - 1;
+        let ack_block = (block.end - 1) - block.start;
+
+        len += octets::varint_len(gap) + // gap
+               octets::varint_len(ack_block); // ack_block
+
+        smallest_ack = block.start;
+    }
+
+    if let Some(ecn) = ecn_counts {
+        len += octets::varint_len(ecn.ect0_count) +
+            octets::varint_len(ecn.ect1_count) +
+            octets::varint_len(ecn.ecn_ce_count);
+    }
+
+    len
 }
 
 pub fn encode_crypto_header(
@@ -1526,6 +2178,56 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
+    #[test]
+    fn reset_stream_at() {
+        let mut d = [42; 128];
+
+        let frame = Frame::ResetStreamAt {
+            stream_id: 123_213,
+            error_code: 21_123_767,
+            final_size: 21_123_767,
+            reliable_size: 1_000,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 15);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+
+        // The reliable size can't exceed the final size.
+        let frame = Frame::ResetStreamAt {
+            stream_id: 123_213,
+            error_code: 21_123_767,
+            final_size: 1_000,
+            reliable_size: 1_001,
+        };
+
+        {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap();
+        }
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Err(Error::InvalidFrame)
+        );
+    }
+
     #[test]
     fn stop_sending() {
         let mut d = [42; 128];
@@ -2081,6 +2783,198 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
+    #[test]
+    fn ack_frequency() {
+        let mut d = [42; 128];
+
+        let frame = Frame::AckFrequency {
+            seq_num: 3,
+            ack_eliciting_threshold: 10,
+            request_max_ack_delay: 25_000,
+            reordering_threshold: 1,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 9);
+        assert_eq!(frame.wire_len(), wire_len);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(
+            Frame::from_bytes(&mut b, packet::Type::Short),
+            Ok(frame.clone())
+        );
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn immediate_ack() {
+        let mut d = [42; 128];
+
+        let frame = Frame::ImmediateAck;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 1);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    fn assert_multipath_frame(frame: Frame, expected_len: usize) {
+        let mut d = [42; 128];
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, expected_len);
+        assert_eq!(frame.wire_len(), wire_len);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn path_ack() {
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(4..7);
+        ranges.insert(9..12);
+        ranges.insert(15..19);
+        ranges.insert(3000..5000);
+
+        let frame = Frame::PathAck {
+            path_id: 3,
+            ack_delay: 874_656_534,
+            ranges,
+            ecn_counts: None,
+        };
+
+        assert_multipath_frame(frame, 21);
+    }
+
+    #[test]
+    fn path_ack_ecn() {
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(4..7);
+        ranges.insert(3000..5000);
+
+        let ecn_counts = Some(EcnCounts {
+            ect0_count: 100,
+            ect1_count: 200,
+            ecn_ce_count: 300,
+        });
+
+        let frame = Frame::PathAck {
+            path_id: 1,
+            ack_delay: 874_656_534,
+            ranges,
+            ecn_counts,
+        };
+
+        assert_multipath_frame(frame, 23);
+    }
+
+    #[test]
+    fn path_abandon() {
+        let frame = Frame::PathAbandon {
+            path_id: 2,
+            error_code: 0x1234,
+        };
+
+        assert_multipath_frame(frame, 7);
+    }
+
+    #[test]
+    fn path_status() {
+        let frame = Frame::PathStatusBackup {
+            path_id: 2,
+            seq_num: 7,
+        };
+
+        assert_multipath_frame(frame, 6);
+
+        let frame = Frame::PathStatusAvailable {
+            path_id: 2,
+            seq_num: 8,
+        };
+
+        assert_multipath_frame(frame, 6);
+    }
+
+    #[test]
+    fn path_new_connection_id() {
+        let frame = Frame::PathNewConnectionId {
+            path_id: 1,
+            seq_num: 123_213,
+            retire_prior_to: 122_211,
+            conn_id: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+            reset_token: [0x42; 16],
+        };
+
+        assert_multipath_frame(frame, 45);
+    }
+
+    #[test]
+    fn path_retire_connection_id() {
+        let frame = Frame::PathRetireConnectionId {
+            path_id: 1,
+            seq_num: 123_213,
+        };
+
+        assert_multipath_frame(frame, 9);
+    }
+
+    #[test]
+    fn max_path_id() {
+        assert_multipath_frame(Frame::MaxPathId { max: 16 }, 5);
+    }
+
+    #[test]
+    fn paths_blocked() {
+        assert_multipath_frame(Frame::PathsBlocked { max: 16 }, 5);
+    }
+
+    #[test]
+    fn path_cids_blocked() {
+        let frame = Frame::PathCidsBlocked {
+            path_id: 4,
+            next_seq_num: 3,
+        };
+
+        assert_multipath_frame(frame, 6);
+    }
+
     #[test]
     fn datagram() {
         let mut d = [42; 128];
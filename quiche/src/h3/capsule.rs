@@ -0,0 +1,926 @@
+// Copyright (C) 2025, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! HTTP Capsule Protocol, as defined in [RFC 9297], along with the capsules
+//! used by [WebTransport over HTTP/3] and [CONNECT-IP].
+//!
+//! [RFC 9297]: https://www.rfc-editor.org/rfc/rfc9297.html#section-3.2
+//! [WebTransport over HTTP/3]: https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3
+//! [CONNECT-IP]: https://www.rfc-editor.org/rfc/rfc9484.html#section-4.7
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use super::Error;
+use super::Result;
+
+use crate::snapshot;
+
+pub const DATAGRAM_CAPSULE_TYPE_ID: u64 = 0x00;
+pub const ADDRESS_ASSIGN_CAPSULE_TYPE_ID: u64 = 0x01;
+pub const ADDRESS_REQUEST_CAPSULE_TYPE_ID: u64 = 0x02;
+pub const ROUTE_ADVERTISEMENT_CAPSULE_TYPE_ID: u64 = 0x03;
+pub const WT_CLOSE_SESSION_CAPSULE_TYPE_ID: u64 = 0x2843;
+pub const WT_DRAIN_SESSION_CAPSULE_TYPE_ID: u64 = 0x78ae;
+
+/// The maximum length of the reason of a WT_CLOSE_SESSION capsule.
+pub const MAX_WT_CLOSE_SESSION_REASON_LEN: usize = 1024;
+
+/// The default maximum size of a capsule's payload that will be buffered.
+pub const DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE: usize = 65_535;
+
+/// An IP address prefix, as carried by ADDRESS_ASSIGN and ADDRESS_REQUEST
+/// capsules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressEntry {
+    /// The ID of the address request this entry relates to. Zero is only
+    /// allowed in ADDRESS_ASSIGN capsules, for unsolicited assignments.
+    pub request_id: u64,
+
+    /// The IP address.
+    pub address: IpAddr,
+
+    /// The number of bits of `address` that make up the prefix.
+    pub prefix_len: u8,
+}
+
+/// A range of IP addresses, as carried by ROUTE_ADVERTISEMENT capsules.
+///
+/// Both ends of the range must be of the same IP version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpAddressRange {
+    /// The first IP address of the range.
+    pub start: IpAddr,
+
+    /// The last IP address of the range, inclusive.
+    pub end: IpAddr,
+
+    /// The IP protocol number of the traffic the route applies to, or zero
+    /// for all protocols.
+    pub ip_protocol: u8,
+}
+
+/// An HTTP capsule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Capsule {
+    /// A DATAGRAM capsule, carrying the payload of an HTTP Datagram.
+    Datagram {
+        /// The HTTP Datagram payload.
+        payload: Vec<u8>,
+    },
+
+    /// A WT_CLOSE_SESSION capsule, closing a WebTransport session.
+    WebTransportCloseSession {
+        /// The application error code.
+        error_code: u32,
+
+        /// The UTF-8 encoded reason for closing the session.
+        reason: Vec<u8>,
+    },
+
+    /// A WT_DRAIN_SESSION capsule, asking the peer to gracefully finish a
+    /// WebTransport session.
+    WebTransportDrainSession,
+
+    /// An ADDRESS_ASSIGN capsule, assigning IP addresses to the peer.
+    AddressAssign {
+        /// The assigned addresses.
+        addresses: Vec<AddressEntry>,
+    },
+
+    /// An ADDRESS_REQUEST capsule, requesting IP addresses from the peer.
+    AddressRequest {
+        /// The requested addresses.
+        addresses: Vec<AddressEntry>,
+    },
+
+    /// A ROUTE_ADVERTISEMENT capsule, advertising the IP address ranges that
+    /// are reachable through the sender.
+    RouteAdvertisement {
+        /// The advertised ranges, in the order mandated by RFC 9484.
+        ranges: Vec<IpAddressRange>,
+    },
+}
+
+impl Capsule {
+    /// Returns the capsule type.
+    pub fn capsule_type(&self) -> u64 {
+        match self {
+            Capsule::Datagram { .. } => DATAGRAM_CAPSULE_TYPE_ID,
+
+            Capsule::WebTransportCloseSession { .. } =>
+                WT_CLOSE_SESSION_CAPSULE_TYPE_ID,
+
+            Capsule::WebTransportDrainSession => WT_DRAIN_SESSION_CAPSULE_TYPE_ID,
+
+            Capsule::AddressAssign { .. } => ADDRESS_ASSIGN_CAPSULE_TYPE_ID,
+
+            Capsule::AddressRequest { .. } => ADDRESS_REQUEST_CAPSULE_TYPE_ID,
+
+            Capsule::RouteAdvertisement { .. } =>
+                ROUTE_ADVERTISEMENT_CAPSULE_TYPE_ID,
+        }
+    }
+
+    fn payload_len(&self) -> usize {
+        match self {
+            Capsule::Datagram { payload } => payload.len(),
+
+            Capsule::WebTransportCloseSession { reason, .. } => 4 + reason.len(),
+
+            Capsule::WebTransportDrainSession => 0,
+
+            Capsule::AddressAssign { addresses } |
+            Capsule::AddressRequest { addresses } => addresses
+                .iter()
+                .map(|a| {
+                    octets::varint_len(a.request_id) + 1 + ip_len(&a.address) + 1
+                })
+                .sum(),
+
+            Capsule::RouteAdvertisement { ranges } => ranges
+                .iter()
+                .map(|r| 1 + ip_len(&r.start) + ip_len(&r.end) + 1)
+                .sum(),
+        }
+    }
+
+    /// Returns the size of the capsule on the wire.
+    pub fn wire_len(&self) -> usize {
+        let len = self.payload_len();
+
+        octets::varint_len(self.capsule_type()) +
+            octets::varint_len(len as u64) +
+            len
+    }
+
+    /// Returns whether capsules of type `capsule_type` are supported.
+    fn is_known_type(capsule_type: u64) -> bool {
+        matches!(
+            capsule_type,
+            DATAGRAM_CAPSULE_TYPE_ID |
+                WT_CLOSE_SESSION_CAPSULE_TYPE_ID |
+                WT_DRAIN_SESSION_CAPSULE_TYPE_ID |
+                ADDRESS_ASSIGN_CAPSULE_TYPE_ID |
+                ADDRESS_REQUEST_CAPSULE_TYPE_ID |
+                ROUTE_ADVERTISEMENT_CAPSULE_TYPE_ID
+        )
+    }
+
+    /// Parses a capsule of type `capsule_type` from its payload.
+    ///
+    /// `None` is returned when the capsule type is not known, and the
+    /// [`MessageError`] error when the payload is malformed.
+    ///
+    /// [`MessageError`]: super::Error::MessageError
+    fn from_bytes(capsule_type: u64, payload: &[u8]) -> Result<Option<Capsule>> {
+        let capsule = match capsule_type {
+            DATAGRAM_CAPSULE_TYPE_ID => Capsule::Datagram {
+                payload: payload.to_vec(),
+            },
+
+            WT_CLOSE_SESSION_CAPSULE_TYPE_ID => {
+                let mut b = octets::Octets::with_slice(payload);
+
+                let error_code = b.get_u32().map_err(|_| Error::MessageError)?;
+
+                if b.cap() > MAX_WT_CLOSE_SESSION_REASON_LEN {
+                    return Err(Error::MessageError);
+                }
+
+                Capsule::WebTransportCloseSession {
+                    error_code,
+                    reason: b.to_vec(),
+                }
+            },
+
+            WT_DRAIN_SESSION_CAPSULE_TYPE_ID => {
+                if !payload.is_empty() {
+                    return Err(Error::MessageError);
+                }
+
+                Capsule::WebTransportDrainSession
+            },
+
+            ADDRESS_ASSIGN_CAPSULE_TYPE_ID => Capsule::AddressAssign {
+                addresses: parse_address_entries(payload, false)?,
+            },
+
+            ADDRESS_REQUEST_CAPSULE_TYPE_ID => Capsule::AddressRequest {
+                addresses: parse_address_entries(payload, true)?,
+            },
+
+            ROUTE_ADVERTISEMENT_CAPSULE_TYPE_ID => Capsule::RouteAdvertisement {
+                ranges: parse_ip_address_ranges(payload)?,
+            },
+
+            _ => return Ok(None),
+        };
+
+        Ok(Some(capsule))
+    }
+
+    /// Serializes the capsule into the given buffer.
+    ///
+    /// On success the number of bytes written is returned.
+    pub fn to_bytes(&self, b: &mut octets::OctetsMut) -> Result<usize> {
+        let before = b.cap();
+
+        match self {
+            Capsule::Datagram { payload } => {
+                b.put_varint(DATAGRAM_CAPSULE_TYPE_ID)?;
+                b.put_varint(payload.len() as u64)?;
+
+                b.put_bytes(payload)?;
+            },
+
+            Capsule::WebTransportCloseSession { error_code, reason } => {
+                b.put_varint(WT_CLOSE_SESSION_CAPSULE_TYPE_ID)?;
+                b.put_varint(4 + reason.len() as u64)?;
+
+                b.put_u32(*error_code)?;
+                b.put_bytes(reason)?;
+            },
+
+            Capsule::WebTransportDrainSession => {
+                b.put_varint(WT_DRAIN_SESSION_CAPSULE_TYPE_ID)?;
+                b.put_varint(0)?;
+            },
+
+            Capsule::AddressAssign { addresses } |
+            Capsule::AddressRequest { addresses } => {
+                b.put_varint(self.capsule_type())?;
+                b.put_varint(self.payload_len() as u64)?;
+
+                for a in addresses {
+                    b.put_varint(a.request_id)?;
+                    put_ip(b, &a.address)?;
+                    b.put_u8(a.prefix_len)?;
+                }
+            },
+
+            Capsule::RouteAdvertisement { ranges } => {
+                b.put_varint(ROUTE_ADVERTISEMENT_CAPSULE_TYPE_ID)?;
+                b.put_varint(self.payload_len() as u64)?;
+
+                for r in ranges {
+                    if r.start.is_ipv4() != r.end.is_ipv4() {
+                        return Err(Error::MessageError);
+                    }
+
+                    put_ip(b, &r.start)?;
+                    b.put_bytes(&ip_octets(&r.end))?;
+                    b.put_u8(r.ip_protocol)?;
+                }
+            },
+        }
+
+        Ok(before - b.cap())
+    }
+}
+
+/// Returns the length of the given IP address on the wire, excluding the IP
+/// version.
+fn ip_len(ip: &IpAddr) -> usize {
+    match ip {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 16,
+    }
+}
+
+/// Returns the maximum prefix length of the given IP address.
+fn ip_bits(ip: &IpAddr) -> u8 {
+    ip_len(ip) as u8 * 8
+}
+
+fn ip_octets(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v) => v.octets().to_vec(),
+        IpAddr::V6(v) => v.octets().to_vec(),
+    }
+}
+
+/// Writes the IP version followed by the IP address.
+fn put_ip(b: &mut octets::OctetsMut, ip: &IpAddr) -> Result<()> {
+    let version = match ip {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 6,
+    };
+
+    b.put_u8(version)?;
+    b.put_bytes(&ip_octets(ip))?;
+
+    Ok(())
+}
+
+/// Reads an IP address of the given IP version.
+fn get_ip(b: &mut octets::Octets, version: u8) -> Result<IpAddr> {
+    let ip = match version {
+        4 => {
+            let mut v = [0; 4];
+            v.copy_from_slice(b.get_bytes(4)?.buf());
+
+            IpAddr::V4(Ipv4Addr::from(v))
+        },
+
+        6 => {
+            let mut v = [0; 16];
+            v.copy_from_slice(b.get_bytes(16)?.buf());
+
+            IpAddr::V6(Ipv6Addr::from(v))
+        },
+
+        _ => return Err(Error::MessageError),
+    };
+
+    Ok(ip)
+}
+
+fn get_address_entry(b: &mut octets::Octets) -> Result<AddressEntry> {
+    let request_id = b.get_varint()?;
+    let version = b.get_u8()?;
+    let address = get_ip(b, version)?;
+    let prefix_len = b.get_u8()?;
+
+    Ok(AddressEntry {
+        request_id,
+        address,
+        prefix_len,
+    })
+}
+
+fn get_ip_address_range(b: &mut octets::Octets) -> Result<IpAddressRange> {
+    let version = b.get_u8()?;
+    let start = get_ip(b, version)?;
+    let end = get_ip(b, version)?;
+    let ip_protocol = b.get_u8()?;
+
+    Ok(IpAddressRange {
+        start,
+        end,
+        ip_protocol,
+    })
+}
+
+/// Parses the address entries of ADDRESS_ASSIGN and ADDRESS_REQUEST capsules.
+///
+/// Requests must have a non-zero and unique request ID.
+fn parse_address_entries(
+    payload: &[u8], is_request: bool,
+) -> Result<Vec<AddressEntry>> {
+    let mut b = octets::Octets::with_slice(payload);
+    let mut addresses: Vec<AddressEntry> = Vec::new();
+
+    while b.cap() > 0 {
+        let entry = get_address_entry(&mut b).map_err(|_| Error::MessageError)?;
+
+        if entry.prefix_len > ip_bits(&entry.address) {
+            return Err(Error::MessageError);
+        }
+
+        if is_request &&
+            (entry.request_id == 0 ||
+                addresses.iter().any(|a| a.request_id == entry.request_id))
+        {
+            return Err(Error::MessageError);
+        }
+
+        addresses.push(entry);
+    }
+
+    Ok(addresses)
+}
+
+/// Parses the IP address ranges of ROUTE_ADVERTISEMENT capsules.
+///
+/// Ranges must be ordered by IP version, then IP protocol, and must not
+/// overlap.
+fn parse_ip_address_ranges(payload: &[u8]) -> Result<Vec<IpAddressRange>> {
+    let mut b = octets::Octets::with_slice(payload);
+    let mut ranges: Vec<IpAddressRange> = Vec::new();
+
+    while b.cap() > 0 {
+        let range =
+            get_ip_address_range(&mut b).map_err(|_| Error::MessageError)?;
+
+        if range.start > range.end {
+            return Err(Error::MessageError);
+        }
+
+        if let Some(prev) = ranges.last() {
+            let ordered = match (prev.start.is_ipv4(), range.start.is_ipv4()) {
+                (true, false) => true,
+
+                (false, true) => false,
+
+                _ =>
+                    prev.ip_protocol < range.ip_protocol ||
+                        (prev.ip_protocol == range.ip_protocol &&
+                            prev.end < range.start),
+            };
+
+            if !ordered {
+                return Err(Error::MessageError);
+            }
+        }
+
+        ranges.push(range);
+    }
+
+    Ok(ranges)
+}
+
+/// Parses capsules out of the data of a request stream.
+///
+/// Capsules of unknown type are skipped without being buffered, as required
+/// by RFC 9297.
+#[derive(Debug)]
+pub struct CapsuleDecoder {
+    /// Data received but not yet decoded.
+    buf: Vec<u8>,
+
+    /// Remaining bytes of an unknown capsule being skipped.
+    skip_len: u64,
+
+    max_payload_size: usize,
+}
+
+impl CapsuleDecoder {
+    pub fn new(max_payload_size: usize) -> Self {
+        CapsuleDecoder {
+            buf: Vec::new(),
+            skip_len: 0,
+            max_payload_size,
+        }
+    }
+
+    /// Writes the data not yet decoded to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_bytes(&self.buf);
+        w.put_u64(self.skip_len);
+    }
+
+    /// Reads a decoder written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.CapsuleDecoder.html#method.to_snapshot
+    pub fn from_snapshot(
+        r: &mut snapshot::Reader, max_payload_size: usize,
+    ) -> crate::Result<Self> {
+        Ok(CapsuleDecoder {
+            buf: r.get_bytes()?.to_vec(),
+            skip_len: r.get_u64()?,
+            max_payload_size,
+        })
+    }
+
+    /// Appends data received on the stream.
+    pub fn push(&mut self, mut data: &[u8]) {
+        if self.skip_len > 0 {
+            let skip = std::cmp::min(self.skip_len, data.len() as u64);
+
+            self.skip_len -= skip;
+            data = &data[skip as usize..];
+        }
+
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Returns the next fully received capsule.
+    ///
+    /// `None` is returned when more data is needed. The [`ExcessiveLoad`]
+    /// error is returned when a capsule's payload exceeds the configured
+    /// maximum size.
+    ///
+    /// [`ExcessiveLoad`]: super::Error::ExcessiveLoad
+    pub fn decode(&mut self) -> Result<Option<Capsule>> {
+        while self.skip_len == 0 && !self.buf.is_empty() {
+            let mut b = octets::Octets::with_slice(&self.buf);
+
+            let (capsule_type, payload_len) =
+                match (b.get_varint(), b.get_varint()) {
+                    (Ok(ty), Ok(len)) => (ty, len),
+
+                    // Wait for the rest of the capsule header.
+                    _ => return Ok(None),
+                };
+
+            let hdr_len = b.off();
+
+            if !Capsule::is_known_type(capsule_type) {
+                let skip = std::cmp::min(payload_len, b.cap() as u64);
+
+                self.buf.drain(..hdr_len + skip as usize);
+                self.skip_len = payload_len - skip;
+
+                continue;
+            }
+
+            if payload_len > self.max_payload_size as u64 {
+                return Err(Error::ExcessiveLoad);
+            }
+
+            if b.cap() < payload_len as usize {
+                return Ok(None);
+            }
+
+            let capsule = Capsule::from_bytes(
+                capsule_type,
+                b.get_bytes(payload_len as usize)?.buf(),
+            );
+
+            self.buf.drain(..b.off());
+
+            return capsule;
+        }
+
+        Ok(None)
+    }
+
+    /// Returns whether a partially received capsule is buffered.
+    pub fn has_partial_capsule(&self) -> bool {
+        self.skip_len > 0 || !self.buf.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datagram() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Datagram {
+            payload: vec![1, 2, 3, 4, 5],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            capsule.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 7);
+        assert_eq!(wire_len, capsule.wire_len());
+        assert_eq!(&d[..2], &[0x00, 0x05]);
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+
+        assert_eq!(decoder.decode(), Ok(Some(capsule)));
+        assert_eq!(decoder.decode(), Ok(None));
+        assert!(!decoder.has_partial_capsule());
+    }
+
+    #[test]
+    fn datagram_partial() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Datagram {
+            payload: vec![1, 2, 3, 4, 5],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            capsule.to_bytes(&mut b).unwrap()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+
+        for i in 0..wire_len - 1 {
+            decoder.push(&d[i..i + 1]);
+            assert_eq!(decoder.decode(), Ok(None));
+            assert!(decoder.has_partial_capsule());
+        }
+
+        decoder.push(&d[wire_len - 1..wire_len]);
+        assert_eq!(decoder.decode(), Ok(Some(capsule)));
+        assert!(!decoder.has_partial_capsule());
+    }
+
+    #[test]
+    fn unknown_capsules_skipped() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Datagram {
+            payload: vec![1, 2, 3],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            // Unknown capsule with a 10 bytes payload.
+            b.put_varint(0x2a).unwrap();
+            b.put_varint(10).unwrap();
+            b.put_bytes(&[0xff; 10]).unwrap();
+
+            capsule.to_bytes(&mut b).unwrap();
+
+            // Unknown capsule with an empty payload.
+            b.put_varint(0x2b).unwrap();
+            b.put_varint(0).unwrap();
+
+            b.off()
+        };
+
+        // Split the input in the middle of the first unknown capsule.
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..5]);
+        assert_eq!(decoder.decode(), Ok(None));
+        assert!(decoder.has_partial_capsule());
+
+        decoder.push(&d[5..wire_len]);
+        assert_eq!(decoder.decode(), Ok(Some(capsule)));
+        assert_eq!(decoder.decode(), Ok(None));
+        assert!(!decoder.has_partial_capsule());
+    }
+
+    #[test]
+    fn datagram_too_large() {
+        let mut d = [42; 128];
+
+        let capsule = Capsule::Datagram {
+            payload: vec![0; 11],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            capsule.to_bytes(&mut b).unwrap()
+        };
+
+        let mut decoder = CapsuleDecoder::new(10);
+
+        // The size is checked as soon as the capsule header is received.
+        decoder.push(&d[..2]);
+        assert_eq!(decoder.decode(), Err(Error::ExcessiveLoad));
+
+        // Unknown capsules are not subject to the limit.
+        let mut decoder = CapsuleDecoder::new(10);
+        d[0] = 0x2a;
+        decoder.push(&d[..wire_len]);
+        assert_eq!(decoder.decode(), Ok(None));
+        assert!(!decoder.has_partial_capsule());
+    }
+
+    #[test]
+    fn webtransport_session() {
+        let mut d = [42; 128];
+
+        let close = Capsule::WebTransportCloseSession {
+            error_code: 0xbeef,
+            reason: b"bye".to_vec(),
+        };
+
+        let drain = Capsule::WebTransportDrainSession;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            let close_len = close.to_bytes(&mut b).unwrap();
+            assert_eq!(close_len, close.wire_len());
+
+            let drain_len = drain.to_bytes(&mut b).unwrap();
+            assert_eq!(drain_len, drain.wire_len());
+
+            b.off()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+
+        assert_eq!(decoder.decode(), Ok(Some(close)));
+        assert_eq!(decoder.decode(), Ok(Some(drain)));
+        assert_eq!(decoder.decode(), Ok(None));
+        assert!(!decoder.has_partial_capsule());
+    }
+
+    #[test]
+    fn webtransport_session_malformed() {
+        let mut d = [42; 2048];
+
+        // WT_CLOSE_SESSION without a complete error code.
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            b.put_varint(WT_CLOSE_SESSION_CAPSULE_TYPE_ID).unwrap();
+            b.put_varint(2).unwrap();
+            b.put_u16(0).unwrap();
+            b.off()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+        assert_eq!(decoder.decode(), Err(Error::MessageError));
+
+        // WT_CLOSE_SESSION with a reason that is too long.
+        let close = Capsule::WebTransportCloseSession {
+            error_code: 0,
+            reason: vec![b'a'; MAX_WT_CLOSE_SESSION_REASON_LEN + 1],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            close.to_bytes(&mut b).unwrap()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+        assert_eq!(decoder.decode(), Err(Error::MessageError));
+
+        // WT_DRAIN_SESSION with a non-empty payload.
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            b.put_varint(WT_DRAIN_SESSION_CAPSULE_TYPE_ID).unwrap();
+            b.put_varint(1).unwrap();
+            b.put_u8(0).unwrap();
+            b.off()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+        assert_eq!(decoder.decode(), Err(Error::MessageError));
+    }
+
+    #[test]
+    fn connect_ip() {
+        let mut d = [42; 256];
+
+        let assign = Capsule::AddressAssign {
+            addresses: vec![
+                AddressEntry {
+                    request_id: 1,
+                    address: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                    prefix_len: 32,
+                },
+                AddressEntry {
+                    request_id: 0,
+                    address: "2001:db8::".parse().unwrap(),
+                    prefix_len: 64,
+                },
+            ],
+        };
+
+        let request = Capsule::AddressRequest {
+            addresses: vec![AddressEntry {
+                request_id: 1,
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                prefix_len: 0,
+            }],
+        };
+
+        let routes = Capsule::RouteAdvertisement {
+            ranges: vec![
+                IpAddressRange {
+                    start: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)),
+                    end: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 255)),
+                    ip_protocol: 0,
+                },
+                IpAddressRange {
+                    start: IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)),
+                    end: IpAddr::V4(Ipv4Addr::new(198, 51, 100, 255)),
+                    ip_protocol: 0,
+                },
+                IpAddressRange {
+                    start: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)),
+                    end: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 255)),
+                    ip_protocol: 17,
+                },
+                IpAddressRange {
+                    start: "2001:db8::".parse().unwrap(),
+                    end: "2001:db8::ffff".parse().unwrap(),
+                    ip_protocol: 0,
+                },
+            ],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            for capsule in [&assign, &request, &routes] {
+                let len = capsule.to_bytes(&mut b).unwrap();
+                assert_eq!(len, capsule.wire_len());
+            }
+
+            b.off()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+
+        assert_eq!(decoder.decode(), Ok(Some(assign)));
+        assert_eq!(decoder.decode(), Ok(Some(request)));
+        assert_eq!(decoder.decode(), Ok(Some(routes)));
+        assert_eq!(decoder.decode(), Ok(None));
+        assert!(!decoder.has_partial_capsule());
+    }
+
+    #[test]
+    fn connect_ip_malformed() {
+        let v4 = |last| IpAddr::V4(Ipv4Addr::new(192, 0, 2, last));
+
+        let entry = |request_id, prefix_len| AddressEntry {
+            request_id,
+            address: v4(0),
+            prefix_len,
+        };
+
+        let range = |start, end, ip_protocol| IpAddressRange {
+            start: v4(start),
+            end: v4(end),
+            ip_protocol,
+        };
+
+        let malformed = [
+            // Prefix longer than the address.
+            Capsule::AddressAssign {
+                addresses: vec![entry(1, 33)],
+            },
+            // Request with a zero request ID.
+            Capsule::AddressRequest {
+                addresses: vec![entry(0, 24)],
+            },
+            // Request with a duplicate request ID.
+            Capsule::AddressRequest {
+                addresses: vec![entry(1, 24), entry(1, 32)],
+            },
+            // Range with start after end.
+            Capsule::RouteAdvertisement {
+                ranges: vec![range(10, 1, 0)],
+            },
+            // Overlapping ranges.
+            Capsule::RouteAdvertisement {
+                ranges: vec![range(0, 10, 0), range(10, 20, 0)],
+            },
+            // Ranges not ordered by IP protocol.
+            Capsule::RouteAdvertisement {
+                ranges: vec![range(0, 10, 17), range(20, 30, 6)],
+            },
+        ];
+
+        for capsule in malformed {
+            let mut d = [42; 128];
+
+            let wire_len = {
+                let mut b = octets::OctetsMut::with_slice(&mut d);
+                capsule.to_bytes(&mut b).unwrap()
+            };
+
+            let mut decoder =
+                CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+            decoder.push(&d[..wire_len]);
+            assert_eq!(decoder.decode(), Err(Error::MessageError));
+        }
+
+        // Unknown IP version.
+        let mut d = [42; 128];
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            b.put_varint(ADDRESS_ASSIGN_CAPSULE_TYPE_ID).unwrap();
+            b.put_varint(7).unwrap();
+            b.put_varint(1).unwrap();
+            b.put_u8(5).unwrap();
+            b.put_bytes(&[0; 4]).unwrap();
+            b.put_u8(32).unwrap();
+            b.off()
+        };
+
+        let mut decoder = CapsuleDecoder::new(DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE);
+        decoder.push(&d[..wire_len]);
+        assert_eq!(decoder.decode(), Err(Error::MessageError));
+
+        // Route with mismatched IP versions can't be serialized.
+        let capsule = Capsule::RouteAdvertisement {
+            ranges: vec![IpAddressRange {
+                start: v4(0),
+                end: "2001:db8::".parse().unwrap(),
+                ip_protocol: 0,
+            }],
+        };
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(capsule.to_bytes(&mut b), Err(Error::MessageError));
+    }
+}
@@ -70,6 +70,13 @@ pub extern "C" fn quiche_h3_config_set_qpack_blocked_streams(
     config.set_qpack_blocked_streams(v);
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_config_set_qpack_encoder_max_table_capacity(
+    config: &mut h3::Config, v: u64,
+) {
+    config.set_qpack_encoder_max_table_capacity(v);
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_h3_config_enable_extended_connect(
     config: &mut h3::Config, enabled: bool,
@@ -77,6 +84,13 @@ pub extern "C" fn quiche_h3_config_enable_extended_connect(
     config.enable_extended_connect(enabled);
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_config_set_webtransport_max_sessions(
+    config: &mut h3::Config, v: u64,
+) {
+    config.set_webtransport_max_sessions(v);
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_h3_config_free(config: *mut h3::Config) {
     drop(unsafe { Box::from_raw(config) });
@@ -93,6 +107,40 @@ pub extern "C" fn quiche_h3_conn_new_with_transport(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_conn_restore(
+    quic_conn: &Connection, state: *const u8, state_len: size_t,
+) -> *mut h3::Connection {
+    let state = unsafe { slice::from_raw_parts(state, state_len) };
+
+    match h3::Connection::restore(quic_conn, state) {
+        Ok(c) => Box::into_raw(Box::new(c)),
+
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_conn_export_state(
+    conn: &h3::Connection, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    if out_len > <ssize_t>::MAX as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    let state = conn.export_state();
+
+    if state.len() > out.len() {
+        return h3::Error::BufferTooShort.to_c();
+    }
+
+    out[..state.len()].copy_from_slice(&state);
+
+    state.len() as ssize_t
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_h3_for_each_setting(
     conn: &h3::Connection,
@@ -148,6 +196,12 @@ pub extern "C" fn quiche_h3_event_type(ev: &h3::Event) -> u32 {
         h3::Event::Reset { .. } => 4,
 
         h3::Event::PriorityUpdate => 5,
+
+        h3::Event::PushPromise { .. } => 6,
+
+        h3::Event::CancelPush => 7,
+
+        h3::Event::WebTransportStream { .. } => 8,
     }
 }
 
@@ -166,7 +220,7 @@ pub extern "C" fn quiche_h3_event_for_each_header(
     argp: *mut c_void,
 ) -> c_int {
     match ev {
-        h3::Event::Headers { list, .. } =>
+        h3::Event::Headers { list, .. } | h3::Event::PushPromise { list, .. } =>
             for h in list {
                 let rc = cb(
                     h.name().as_ptr(),
@@ -198,6 +252,24 @@ pub extern "C" fn quiche_h3_event_headers_has_more_frames(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_event_push_promise_id(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::PushPromise { push_id, .. } => *push_id,
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_event_webtransport_session_id(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::WebTransportStream { session_id } => *session_id,
+
+        _ => unreachable!(),
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_h3_extended_connect_enabled_by_peer(
     conn: &h3::Connection,
@@ -205,6 +277,13 @@ pub extern "C" fn quiche_h3_extended_connect_enabled_by_peer(
     conn.extended_connect_enabled_by_peer()
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_webtransport_enabled_by_peer(
+    conn: &h3::Connection,
+) -> bool {
+    conn.webtransport_enabled_by_peer()
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_h3_event_free(ev: *mut h3::Event) {
     drop(unsafe { Box::from_raw(ev) });
@@ -233,6 +312,18 @@ pub extern "C" fn quiche_h3_send_request(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_open_webtransport_stream(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, session_id: u64,
+    bidirectional: bool,
+) -> i64 {
+    match conn.open_webtransport_stream(quic_conn, session_id, bidirectional) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn quiche_h3_send_response(
     conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
@@ -335,6 +426,77 @@ pub extern "C" fn quiche_h3_send_goaway(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn quiche_h3_send_max_push_id(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.send_max_push_id(quic_conn, push_id) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_send_push_promise(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    headers: *const Header, headers_len: size_t,
+) -> i64 {
+    let req_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_push_promise(quic_conn, stream_id, &req_headers) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_send_push_response(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+    headers: *const Header, headers_len: size_t, fin: bool,
+) -> i64 {
+    let resp_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_push_response(quic_conn, push_id, &resp_headers, fin) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_cancel_push(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.cancel_push(quic_conn, push_id) {
+        Ok(()) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_push_id(
+    conn: &h3::Connection, stream_id: u64, out: &mut u64,
+) -> bool {
+    match conn.push_id(stream_id) {
+        Some(v) => {
+            *out = v;
+            true
+        },
+
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn quiche_h3_is_early_data_request(
+    conn: &h3::Connection, stream_id: u64,
+) -> bool {
+    conn.is_early_data_request(stream_id)
+}
+
 #[no_mangle]
 #[cfg(feature = "sfv")]
 pub extern "C" fn quiche_h3_parse_extensible_priority(
@@ -178,6 +178,14 @@
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((push_id, quiche::h3::Event::CancelPush)) => {
+//!             // Client doesn't want the pushed response, handle it.
+//!         },
+//!
+//!         Ok((_, quiche::h3::Event::PushPromise { .. })) => unreachable!(),
+//!
+//!         Ok((_, quiche::h3::Event::WebTransportStream { .. })) => unreachable!(),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -239,6 +247,16 @@
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((stream_id, quiche::h3::Event::PushPromise { push_id, list })) => {
+//!             // Server promised to push a response, handle it.
+//!         },
+//!
+//!         Ok((push_id, quiche::h3::Event::CancelPush)) => {
+//!             // Server cancelled the push, handle it.
+//!         },
+//!
+//!         Ok((_, quiche::h3::Event::WebTransportStream { .. })) => unreachable!(),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -260,6 +278,112 @@
 //! repeatedly will generate an [`Event`] for each of these. The application may
 //! use these event to do additional HTTP semantic validation.
 //!
+//! ## Server push
+//!
+//! Server push is disabled until the client allows it with
+//! [`send_max_push_id()`]. The server can then promise responses on a request
+//! stream using [`send_push_promise()`], and send them on dedicated push
+//! streams using [`send_push_response()`] and [`send_body()`]:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config).unwrap();
+//! # let h3_config = quiche::h3::Config::new()?;
+//! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//! # let stream_id = 0;
+//! let req = vec![
+//!     quiche::h3::Header::new(b":method", b"GET"),
+//!     quiche::h3::Header::new(b":scheme", b"https"),
+//!     quiche::h3::Header::new(b":authority", b"quic.tech"),
+//!     quiche::h3::Header::new(b":path", b"/style.css"),
+//! ];
+//!
+//! let push_id = h3_conn.send_push_promise(&mut conn, stream_id, &req)?;
+//!
+//! let resp = vec![
+//!     quiche::h3::Header::new(b":status", b"200"),
+//!     quiche::h3::Header::new(b"content-type", b"text/css"),
+//! ];
+//!
+//! let push_stream_id =
+//!     h3_conn.send_push_response(&mut conn, push_id, &resp, false)?;
+//! h3_conn.send_body(&mut conn, push_stream_id, b"body { }", true)?;
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
+//! Clients receive a [`PushPromise`] event on the request stream, and the
+//! pushed response as regular events on the push stream. The [`push_id()`]
+//! method returns the push ID of a push stream. Either endpoint can cancel a
+//! push using [`cancel_push()`].
+//!
+//! ## Capsules
+//!
+//! Streams using the [Capsule Protocol], such as extended CONNECT streams, can
+//! exchange capsules with [`send_capsule()`] and [`recv_capsule()`]. HTTP
+//! Datagrams can be sent with [`send_http_datagram()`], which falls back to
+//! DATAGRAM capsules when the peer doesn't support QUIC DATAGRAM frames:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config).unwrap();
+//! # let h3_config = quiche::h3::Config::new()?;
+//! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//! # let stream_id = 0;
+//! h3_conn.send_http_datagram(&mut conn, stream_id, b"payload")?;
+//!
+//! while let Ok(capsule) = h3_conn.recv_capsule(&mut conn, stream_id) {
+//!     match capsule {
+//!         quiche::h3::Capsule::Datagram { payload } => {
+//!             // Handle the HTTP Datagram.
+//!         },
+//!
+//!         _ => (),
+//!     }
+//! }
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
+//! ## WebTransport
+//!
+//! [WebTransport] support is enabled with
+//! [`set_webtransport_max_sessions()`]. Sessions are established using
+//! extended CONNECT requests with the `webtransport` protocol, and identified
+//! by the ID of their CONNECT stream. Servers reject sessions beyond the
+//! configured limit, as well as streams associated with unknown sessions.
+//!
+//! Streams associated with a session are opened with
+//! [`open_webtransport_stream()`], and the ones opened by the peer are
+//! reported by the [`WebTransportStream`] event. Data is exchanged on them
+//! with [`send_body()`] and [`recv_body()`]. Session-scoped datagrams use
+//! [`send_http_datagram()`] and [`recv_http_datagram()`], while
+//! [`close_webtransport_session()`] and [`drain_webtransport_session()`] send
+//! the corresponding capsules on the CONNECT stream:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:1234".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config).unwrap();
+//! let mut h3_config = quiche::h3::Config::new()?;
+//! h3_config.enable_extended_connect(true);
+//! h3_config.set_webtransport_max_sessions(16);
+//!
+//! let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//! # let session_id = 0;
+//! let stream_id = h3_conn.open_webtransport_stream(&mut conn, session_id, false)?;
+//! h3_conn.send_body(&mut conn, stream_id, b"hello", true)?;
+//!
+//! h3_conn.close_webtransport_session(&mut conn, session_id, 0, b"done")?;
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
 //! ## HTTP/3 protocol errors
 //!
 //! Quiche is responsible for managing the HTTP/3 connection, ensuring it is in
@@ -281,7 +405,30 @@
 //! [`send_request()`]: struct.Connection.html#method.send_response
 //! [`send_response()`]: struct.Connection.html#method.send_response
 //! [`send_body()`]: struct.Connection.html#method.send_body
-
+//! [`send_max_push_id()`]: struct.Connection.html#method.send_max_push_id
+//! [`send_push_promise()`]: struct.Connection.html#method.send_push_promise
+//! [`send_push_response()`]: struct.Connection.html#method.send_push_response
+//! [`push_id()`]: struct.Connection.html#method.push_id
+//! [`cancel_push()`]: struct.Connection.html#method.cancel_push
+//! [`PushPromise`]: enum.Event.html#variant.PushPromise
+//! [Capsule Protocol]: https://www.rfc-editor.org/rfc/rfc9297.html#section-3
+//! [`send_capsule()`]: struct.Connection.html#method.send_capsule
+//! [`recv_capsule()`]: struct.Connection.html#method.recv_capsule
+//! [`send_http_datagram()`]: struct.Connection.html#method.send_http_datagram
+//! [WebTransport]: https://datatracker.ietf.org/doc/html/draft-ietf-webtrans-http3
+//! [`set_webtransport_max_sessions()`]:
+//! struct.Config.html#method.set_webtransport_max_sessions
+//! [`open_webtransport_stream()`]:
+//! struct.Connection.html#method.open_webtransport_stream
+//! [`WebTransportStream`]: enum.Event.html#variant.WebTransportStream
+//! [`recv_body()`]: struct.Connection.html#method.recv_body
+//! [`recv_http_datagram()`]: struct.Connection.html#method.recv_http_datagram
+//! [`close_webtransport_session()`]:
+//! struct.Connection.html#method.close_webtransport_session
+//! [`drain_webtransport_session()`]:
+//! struct.Connection.html#method.drain_webtransport_session
+
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
@@ -325,6 +472,14 @@ use crate::BufSplit;
 /// ../struct.Config.html#method.set_application_protos
 pub const APPLICATION_PROTOCOL: &[&[u8]] = &[b"h3"];
 
+/// The application error code used to reset the streams of a WebTransport
+/// session when the session is closed.
+pub const WEBTRANSPORT_SESSION_GONE: u64 = 0x170d7b68;
+
+/// The application error code used to reject WebTransport streams that are
+/// associated with an unknown session.
+pub const WEBTRANSPORT_BUFFERED_STREAM_REJECTED: u64 = 0x3994bd84;
+
 // The offset used when converting HTTP/3 urgency to quiche urgency.
 const PRIORITY_URGENCY_OFFSET: u8 = 124;
 
@@ -394,6 +549,12 @@ pub enum Error {
     /// QPACK Header block decompression failure.
     QpackDecompressionFailed,
 
+    /// Error on the QPACK encoder stream.
+    QpackEncoderStreamError,
+
+    /// Error on the QPACK decoder stream.
+    QpackDecoderStreamError,
+
     /// Error originated from the transport layer.
     TransportError(crate::Error),
 
@@ -494,6 +655,8 @@ impl Error {
             Error::IdError => WireErrorCode::IdError as u64,
             Error::MissingSettings => WireErrorCode::MissingSettings as u64,
             Error::QpackDecompressionFailed => 0x200,
+            Error::QpackEncoderStreamError => 0x201,
+            Error::QpackDecoderStreamError => 0x202,
             Error::BufferTooShort => 0x999,
             Error::TransportError { .. } | Error::StreamBlocked => 0xFF,
             Error::SettingsError => WireErrorCode::SettingsError as u64,
@@ -529,6 +692,8 @@ impl Error {
             Error::MessageError => -18,
             Error::ConnectError => -19,
             Error::VersionFallback => -20,
+            Error::QpackEncoderStreamError => -21,
+            Error::QpackDecoderStreamError => -22,
 
             Error::TransportError(quic_error) => quic_error.to_c() - 1000,
         }
@@ -568,10 +733,13 @@ pub struct Config {
     max_field_section_size: Option<u64>,
     qpack_max_table_capacity: Option<u64>,
     qpack_blocked_streams: Option<u64>,
+    qpack_encoder_max_table_capacity: u64,
     connect_protocol_enabled: Option<u64>,
     /// additional settings are settings that are not part of the H3
     /// settings explicitly handled above
     additional_settings: Option<Vec<(u64, u64)>>,
+    max_capsule_payload_size: usize,
+    wt_max_sessions: Option<u64>,
 }
 
 impl Config {
@@ -581,8 +749,11 @@ impl Config {
             max_field_section_size: None,
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
+            qpack_encoder_max_table_capacity: 0,
             connect_protocol_enabled: None,
             additional_settings: None,
+            max_capsule_payload_size: capsule::DEFAULT_MAX_CAPSULE_PAYLOAD_SIZE,
+            wt_max_sessions: None,
         })
     }
 
@@ -601,6 +772,9 @@ impl Config {
 
     /// Sets the `SETTINGS_QPACK_MAX_TABLE_CAPACITY` setting.
     ///
+    /// This is the maximum capacity of the dynamic table that the peer's
+    /// encoder is allowed to use when compressing headers sent to us.
+    ///
     /// The default value is `0`.
     pub fn set_qpack_max_table_capacity(&mut self, v: u64) {
         self.qpack_max_table_capacity = Some(v);
@@ -608,11 +782,27 @@ impl Config {
 
     /// Sets the `SETTINGS_QPACK_BLOCKED_STREAMS` setting.
     ///
+    /// This is the maximum number of streams that can be blocked waiting for
+    /// the peer's encoder to insert dynamic table entries.
+    ///
     /// The default value is `0`.
     pub fn set_qpack_blocked_streams(&mut self, v: u64) {
         self.qpack_blocked_streams = Some(v);
     }
 
+    /// Sets the maximum capacity of the dynamic table used by the local QPACK
+    /// encoder.
+    ///
+    /// The dynamic table is only used when the peer also advertises a
+    /// non-zero `SETTINGS_QPACK_MAX_TABLE_CAPACITY`, in which case the
+    /// capacity is the smallest of the two values.
+    ///
+    /// The default value is `0`, meaning that headers are only compressed
+    /// using the static table.
+    pub fn set_qpack_encoder_max_table_capacity(&mut self, v: u64) {
+        self.qpack_encoder_max_table_capacity = v;
+    }
+
     /// Sets or omits the `SETTINGS_ENABLE_CONNECT_PROTOCOL` setting.
     ///
     /// The default value is `false`.
@@ -624,6 +814,43 @@ impl Config {
         }
     }
 
+    /// Sets the maximum size of the payload of capsules received on request
+    /// streams.
+    ///
+    /// When a capsule exceeding the limit is received, the call to the
+    /// [`recv_capsule()`] method will return the [`Error::ExcessiveLoad`]
+    /// error. Capsules of unknown type are skipped and not subject to the
+    /// limit.
+    ///
+    /// The default value is `65535`.
+    ///
+    /// [`recv_capsule()`]: struct.Connection.html#method.recv_capsule
+    /// [`Error::ExcessiveLoad`]: enum.Error.html#variant.ExcessiveLoad
+    pub fn set_max_capsule_payload_size(&mut self, v: usize) {
+        self.max_capsule_payload_size = v;
+    }
+
+    /// Sets the `SETTINGS_WT_MAX_SESSIONS` setting.
+    ///
+    /// A non-zero value enables support for [WebTransport] sessions, which
+    /// also requires extended CONNECT to be enabled with
+    /// [`enable_extended_connect()`] on servers. The value is the number of
+    /// concurrent sessions the peer is allowed to establish, though enforcing
+    /// it is left to the application.
+    ///
+    /// The default value is `0`, meaning that WebTransport is disabled.
+    ///
+    /// [WebTransport]: index.html#webtransport
+    /// [`enable_extended_connect()`]:
+    /// struct.Config.html#method.enable_extended_connect
+    pub fn set_webtransport_max_sessions(&mut self, v: u64) {
+        if v > 0 {
+            self.wt_max_sessions = Some(v);
+        } else {
+            self.wt_max_sessions = None;
+        }
+    }
+
     /// Sets additional HTTP/3 settings.
     ///
     /// The default value is no additional settings.
@@ -635,6 +862,7 @@ impl Config {
     /// - SETTINGS_QPACK_BLOCKED_STREAMS
     /// - SETTINGS_ENABLE_CONNECT_PROTOCOL
     /// - SETTINGS_H3_DATAGRAM
+    /// - SETTINGS_WT_MAX_SESSIONS
     ///
     /// If such a setting is present in the `additional_settings`,
     /// the method will return the [`Error::SettingsError`] error.
@@ -653,6 +881,7 @@ impl Config {
             frame::SETTINGS_ENABLE_CONNECT_PROTOCOL,
             frame::SETTINGS_H3_DATAGRAM,
             frame::SETTINGS_H3_DATAGRAM_00,
+            frame::SETTINGS_WT_MAX_SESSIONS,
         ]);
 
         let dedup_settings: HashSet<u64> =
@@ -803,6 +1032,41 @@ pub enum Event {
 
     /// GOAWAY was received.
     GoAway,
+
+    /// PUSH_PROMISE was received.
+    ///
+    /// This event only occurs at clients. The associated stream ID is the ID
+    /// of the request stream the push was promised on. The pushed response is
+    /// delivered on a separate push stream, which can be associated with the
+    /// promise using the [`push_id()`] method.
+    ///
+    /// [`push_id()`]: struct.Connection.html#method.push_id
+    PushPromise {
+        /// The push ID of the promised response.
+        push_id: u64,
+
+        /// The list of received header fields of the promised request.
+        list: Vec<Header>,
+    },
+
+    /// CANCEL_PUSH was received.
+    ///
+    /// The associated ID is the push ID of the cancelled push.
+    CancelPush,
+
+    /// A WebTransport stream was opened by the peer.
+    ///
+    /// Data received on the stream is reported with further [`Data`] events,
+    /// and can be read using the [`recv_body()`] method. Unlike on request
+    /// streams, the data is not carried in DATA frames.
+    ///
+    /// [`Data`]: enum.Event.html#variant.Data
+    /// [`recv_body()`]: struct.Connection.html#method.recv_body
+    WebTransportStream {
+        /// The ID of the session the stream belongs to, that is the ID of the
+        /// extended CONNECT stream that established it.
+        session_id: u64,
+    },
 }
 
 /// Extensible Priorities parameters.
@@ -909,6 +1173,7 @@ struct ConnectionSettings {
     pub qpack_blocked_streams: Option<u64>,
     pub connect_protocol_enabled: Option<u64>,
     pub h3_datagram: Option<u64>,
+    pub wt_max_sessions: Option<u64>,
     pub additional_settings: Option<Vec<(u64, u64)>>,
     pub raw: Option<Vec<(u64, u64)>>,
 }
@@ -946,6 +1211,17 @@ fn close_conn_critical_stream<F: BufFactory>(
     Err(Error::ClosedCriticalStream)
 }
 
+/// Returns whether the given request headers establish a WebTransport
+/// session.
+fn is_webtransport_connect<T: NameValue>(headers: &[T]) -> bool {
+    headers
+        .iter()
+        .any(|h| h.name() == b":method" && h.value() == b"CONNECT") &&
+        headers
+            .iter()
+            .any(|h| h.name() == b":protocol" && h.value() == b"webtransport")
+}
+
 fn close_conn_if_critical_stream_finished<F: BufFactory>(
     conn: &mut super::Connection<F>, stream_id: u64,
 ) -> Result<()> {
@@ -974,13 +1250,40 @@ pub struct Connection {
     qpack_encoder: qpack::Encoder,
     qpack_decoder: qpack::Decoder,
 
+    qpack_encoder_max_table_capacity: u64,
+
     local_qpack_streams: QpackStreams,
     peer_qpack_streams: QpackStreams,
 
-    max_push_id: u64,
+    /// Request streams whose headers are waiting on QPACK encoder
+    /// instructions to be decoded.
+    qpack_blocked_streams: VecDeque<u64>,
+
+    /// The maximum push ID the server is allowed to use. Servers track the
+    /// limit advertised by the client, clients the one they advertised.
+    max_push_id: Option<u64>,
+
+    next_push_id: u64,
+
+    /// Push IDs promised by the server that don't have a push stream yet.
+    promised_push_ids: HashSet<u64>,
+
+    /// Push IDs cancelled by the client before their push stream arrived.
+    cancelled_push_ids: HashSet<u64>,
+
+    /// The push streams that are still open, indexed by push ID.
+    push_streams: HashMap<u64, u64>,
+
+    /// The IDs of the established WebTransport sessions.
+    webtransport_sessions: HashSet<u64>,
 
     finished_streams: VecDeque<u64>,
 
+    /// Capsules being received on request streams.
+    capsule_decoders: crate::stream::StreamIdHashMap<capsule::CapsuleDecoder>,
+
+    max_capsule_payload_size: usize,
+
     frames_greased: bool,
 
     local_goaway_id: Option<u64>,
@@ -991,13 +1294,20 @@ impl Connection {
     fn new(
         config: &Config, is_server: bool, enable_dgram: bool,
     ) -> Result<Connection> {
+        let initial_bidi_stream_id = if is_server { 0x1 } else { 0x0 };
         let initial_uni_stream_id = if is_server { 0x3 } else { 0x2 };
         let h3_datagram = if enable_dgram { Some(1) } else { None };
 
+        let mut qpack_decoder = qpack::Decoder::new();
+        qpack_decoder
+            .set_max_table_capacity(config.qpack_max_table_capacity.unwrap_or(0));
+        qpack_decoder
+            .set_max_blocked_streams(config.qpack_blocked_streams.unwrap_or(0));
+
         Ok(Connection {
             is_server,
 
-            next_request_stream_id: 0,
+            next_request_stream_id: initial_bidi_stream_id,
 
             next_uni_stream_id: initial_uni_stream_id,
 
@@ -1009,6 +1319,7 @@ impl Connection {
                 qpack_blocked_streams: config.qpack_blocked_streams,
                 connect_protocol_enabled: config.connect_protocol_enabled,
                 h3_datagram,
+                wt_max_sessions: config.wt_max_sessions,
                 additional_settings: config.additional_settings.clone(),
                 raw: Default::default(),
             },
@@ -1019,6 +1330,7 @@ impl Connection {
                 qpack_blocked_streams: None,
                 h3_datagram: None,
                 connect_protocol_enabled: None,
+                wt_max_sessions: None,
                 additional_settings: Default::default(),
                 raw: Default::default(),
             },
@@ -1027,15 +1339,34 @@ impl Connection {
             peer_control_stream_id: None,
 
             qpack_encoder: qpack::Encoder::new(),
-            qpack_decoder: qpack::Decoder::new(),
+            qpack_decoder,
+
+            qpack_encoder_max_table_capacity: config
+                .qpack_encoder_max_table_capacity,
 
             local_qpack_streams: Default::default(),
             peer_qpack_streams: Default::default(),
 
-            max_push_id: 0,
+            qpack_blocked_streams: VecDeque::new(),
+
+            max_push_id: None,
+
+            next_push_id: 0,
+
+            promised_push_ids: HashSet::new(),
+
+            cancelled_push_ids: HashSet::new(),
+
+            push_streams: HashMap::new(),
+
+            webtransport_sessions: HashSet::new(),
 
             finished_streams: VecDeque::new(),
 
+            capsule_decoders: Default::default(),
+
+            max_capsule_payload_size: config.max_capsule_payload_size,
+
             frames_greased: false,
 
             local_goaway_id: None,
@@ -1094,6 +1425,40 @@ impl Connection {
         Ok(http3_conn)
     }
 
+    /// Serializes the HTTP/3 connection's state, so that it can be restored
+    /// later using [`restore()`].
+    ///
+    /// This is meant to be used along with the underlying QUIC connection's
+    /// [`export_state()`], and needs to be called at the same time, before
+    /// any further use of either connection.
+    ///
+    /// [`restore()`]: struct.Connection.html#method.restore
+    /// [`export_state()`]: ../struct.Connection.html#method.export_state
+    pub fn export_state(&self) -> Vec<u8> {
+        self.to_snapshot()
+    }
+
+    /// Restores an HTTP/3 connection from the state exported by
+    /// [`export_state()`], on top of the QUIC connection restored from the
+    /// state exported alongside it.
+    ///
+    /// The [`InvalidSnapshot`] transport error is returned if the state can't
+    /// be decoded, or if it doesn't match `conn`.
+    ///
+    /// [`export_state()`]: struct.Connection.html#method.export_state
+    /// [`InvalidSnapshot`]: ../enum.Error.html#variant.InvalidSnapshot
+    pub fn restore<F: BufFactory>(
+        conn: &super::Connection<F>, state: &[u8],
+    ) -> Result<Connection> {
+        let http3_conn = Connection::from_snapshot(state)?;
+
+        if http3_conn.is_server != conn.is_server {
+            return Err(Error::TransportError(super::Error::InvalidSnapshot));
+        }
+
+        Ok(http3_conn)
+    }
+
     /// Sends an HTTP/3 request.
     ///
     /// The request is encoded from the provided list of headers without a
@@ -1129,7 +1494,7 @@ impl Connection {
         // the creation of the QUIC stream state, without actually writing
         // anything.
         if let Err(e) = conn.stream_send(stream_id, b"", false) {
-            self.streams.remove(&stream_id);
+            self.remove_stream(stream_id);
 
             if e == super::Error::Done {
                 return Err(Error::StreamBlocked);
@@ -1140,6 +1505,10 @@ impl Connection {
 
         self.send_headers(conn, stream_id, headers, fin)?;
 
+        if is_webtransport_connect(headers) {
+            self.webtransport_sessions.insert(stream_id);
+        }
+
         // To avoid skipping stream IDs, we only calculate the next available
         // stream ID when a request has been successfully buffered.
         self.next_request_stream_id = self
@@ -1394,7 +1763,7 @@ impl Connection {
     }
 
     fn encode_header_block<T: NameValue>(
-        &mut self, headers: &[T],
+        &mut self, stream_id: u64, headers: &[T],
     ) -> Result<Vec<u8>> {
         let headers_len = headers
             .iter()
@@ -1403,7 +1772,7 @@ impl Connection {
         let mut header_block = vec![0; headers_len];
         let len = self
             .qpack_encoder
-            .encode(headers, &mut header_block)
+            .encode_field_section(stream_id, headers, &mut header_block)
             .map_err(|_| Error::InternalError)?;
 
         header_block.truncate(len);
@@ -1423,7 +1792,7 @@ impl Connection {
             self.frames_greased = true;
         }
 
-        let header_block = self.encode_header_block(headers)?;
+        let header_block = self.encode_header_block(stream_id, headers)?;
 
         let overhead = octets::varint_len(frame::HEADERS_FRAME_TYPE_ID) +
             octets::varint_len(header_block.len() as u64);
@@ -1437,13 +1806,17 @@ impl Connection {
 
             Err(e) => {
                 if conn.stream_finished(stream_id) {
-                    self.streams.remove(&stream_id);
+                    self.remove_stream(stream_id);
                 }
 
                 return Err(e.into());
             },
         };
 
+        // Send any dynamic table insertions the header block depends on
+        // before the header block itself.
+        self.flush_qpack_encoder_stream(conn)?;
+
         b.put_varint(frame::HEADERS_FRAME_TYPE_ID)?;
         b.put_varint(header_block.len() as u64)?;
         let off = b.off();
@@ -1452,6 +1825,8 @@ impl Connection {
         // Sending header block separately avoids unnecessary copy.
         conn.stream_send(stream_id, &header_block, fin)?;
 
+        self.qpack_encoder.commit_field_section();
+
         trace!(
             "{} tx frm HEADERS stream={} len={} fin={}",
             conn.trace_id(),
@@ -1487,7 +1862,7 @@ impl Connection {
         }
 
         if fin && conn.stream_finished(stream_id) {
-            self.streams.remove(&stream_id);
+            self.remove_stream(stream_id);
         }
 
         Ok(())
@@ -1495,6 +1870,8 @@ impl Connection {
 
     /// Sends an HTTP/3 body chunk on the given stream.
     ///
+    /// On WebTransport streams the data is sent as is, without DATA framing.
+    ///
     /// On success the number of bytes written is returned, or [`Done`] if no
     /// bytes could be written (e.g. because the stream is blocked).
     ///
@@ -1615,8 +1992,13 @@ impl Connection {
 
         let len = body.as_ref().len();
 
+        let is_webtransport = self.is_webtransport_stream(stream_id);
+
         // Validate that it is sane to send data on the stream.
-        if stream_id % 4 != 0 {
+        if stream_id % 4 != 0 &&
+            !self.is_push_stream(stream_id) &&
+            !is_webtransport
+        {
             return Err(Error::FrameUnexpected);
         }
 
@@ -1641,15 +2023,20 @@ impl Connection {
             return Err(Error::Done);
         }
 
-        let overhead = octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
-            octets::varint_len(len as u64);
+        // WebTransport streams carry application data without any framing.
+        let overhead = if is_webtransport {
+            0
+        } else {
+            octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
+                octets::varint_len(len as u64)
+        };
 
         let stream_cap = match conn.stream_capacity(stream_id) {
             Ok(v) => v,
 
             Err(e) => {
                 if conn.stream_finished(stream_id) {
-                    self.streams.remove(&stream_id);
+                    self.remove_stream(stream_id);
                 }
 
                 return Err(e.into());
@@ -1675,8 +2062,11 @@ impl Connection {
             return Err(Error::Done);
         }
 
-        b.put_varint(frame::DATA_FRAME_TYPE_ID)?;
-        b.put_varint(body_len as u64)?;
+        if !is_webtransport {
+            b.put_varint(frame::DATA_FRAME_TYPE_ID)?;
+            b.put_varint(body_len as u64)?;
+        }
+
         let off = b.off();
 
         // Return how many bytes were written, excluding the frame header.
@@ -1684,25 +2074,35 @@ impl Connection {
         let (written, ret) =
             write_fn(conn, &d[..off], stream_id, body, body_len, fin)?;
 
-        trace!(
-            "{} tx frm DATA stream={} len={} fin={}",
-            conn.trace_id(),
-            stream_id,
-            written,
-            fin
-        );
-
-        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
-            let frame = Http3Frame::Data { raw: None };
-            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+        if is_webtransport {
+            trace!(
+                "{} tx WebTransport data stream={} len={} fin={}",
+                conn.trace_id(),
                 stream_id,
-                length: Some(written as u64),
-                frame,
-                ..Default::default()
-            });
+                written,
+                fin
+            );
+        } else {
+            trace!(
+                "{} tx frm DATA stream={} len={} fin={}",
+                conn.trace_id(),
+                stream_id,
+                written,
+                fin
+            );
 
-            q.add_event_data_now(ev_data).ok();
-        });
+            qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+                let frame = Http3Frame::Data { raw: None };
+                let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                    stream_id,
+                    length: Some(written as u64),
+                    frame,
+                    ..Default::default()
+                });
+
+                q.add_event_data_now(ev_data).ok();
+            });
+        }
 
         if written < len {
             // Ensure the peer is notified that the connection or stream is
@@ -1715,7 +2115,7 @@ impl Connection {
         }
 
         if fin && written == len && conn.stream_finished(stream_id) {
-            self.streams.remove(&stream_id);
+            self.remove_stream(stream_id);
         }
 
         Ok(ret)
@@ -1746,571 +2146,584 @@ impl Connection {
         self.peer_settings.connect_protocol_enabled == Some(1)
     }
 
-    /// Reads request or response body data into the provided buffer.
+    /// Returns whether the peer enabled WebTransport support.
     ///
-    /// Applications should call this method whenever the [`poll()`] method
-    /// returns a [`Data`] event.
+    /// This requires the peer to allow WebTransport sessions, and to enable
+    /// HTTP/3 DATAGRAMs and, in the case of servers, extended CONNECT.
     ///
-    /// On success the amount of bytes read is returned, or [`Done`] if there
-    /// is no data to read.
+    /// Support is signalled by the peer's SETTINGS, so this method always
+    /// returns false until they have been processed using the [`poll()`]
+    /// method.
     ///
     /// [`poll()`]: struct.Connection.html#method.poll
-    /// [`Data`]: enum.Event.html#variant.Data
-    /// [`Done`]: enum.Error.html#variant.Done
-    pub fn recv_body<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
-        out: &mut [u8],
-    ) -> Result<usize> {
-        let mut total = 0;
-
-        // Try to consume all buffered data for the stream, even across multiple
-        // DATA frames.
-        while total < out.len() {
-            let stream = self.streams.get_mut(&stream_id).ok_or(Error::Done)?;
-
-            if stream.state() != stream::State::Data {
-                break;
-            }
-
-            let (read, fin) =
-                match stream.try_consume_data(conn, &mut out[total..]) {
-                    Ok(v) => v,
-
-                    Err(Error::Done) => break,
-
-                    Err(e) => return Err(e),
-                };
-
-            total += read;
-
-            // No more data to read, we are done.
-            if read == 0 || fin {
-                break;
-            }
-
-            // Process incoming data from the stream. For example, if a whole
-            // DATA frame was consumed, and another one is queued behind it,
-            // this will ensure the additional data will also be returned to
-            // the application.
-            match self.process_readable_stream(conn, stream_id, false) {
-                Ok(_) => unreachable!(),
-
-                Err(Error::Done) => (),
-
-                Err(e) => return Err(e),
-            };
-
-            if conn.stream_finished(stream_id) {
-                break;
-            }
-        }
-
-        // While body is being received, the stream is marked as finished only
-        // when all data is read by the application.
-        if conn.stream_finished(stream_id) {
-            self.process_finished_stream(stream_id);
-        }
-
-        if total == 0 {
-            return Err(Error::Done);
-        }
-
-        Ok(total)
+    pub fn webtransport_enabled_by_peer(&self) -> bool {
+        // Only servers need to enable extended CONNECT, as sessions are
+        // always established by clients.
+        self.peer_settings.wt_max_sessions.is_some_and(|v| v > 0) &&
+            (self.is_server || self.extended_connect_enabled_by_peer()) &&
+            self.peer_settings.h3_datagram == Some(1)
     }
 
-    /// Sends a PRIORITY_UPDATE frame on the control stream with specified
-    /// request stream ID and priority.
+    /// Sends a capsule on the given request stream.
     ///
-    /// The `priority` parameter represents [Extensible Priority]
-    /// parameters. If the urgency is outside the range 0-7, it will be clamped
-    /// to 7.
+    /// The capsule is sent in a DATA frame, so it must only be used on streams
+    /// that use the [Capsule Protocol], such as extended CONNECT streams,
+    /// after the request or response headers have been sent.
     ///
     /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
     /// doesn't have enough capacity for the operation to complete. When this
     /// happens the application should retry the operation once the stream is
     /// reported as writable again.
     ///
+    /// [Capsule Protocol]: https://www.rfc-editor.org/rfc/rfc9297.html#section-3
     /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
-    /// [Extensible Priority]: https://www.rfc-editor.org/rfc/rfc9218.html#section-4.
-    pub fn send_priority_update_for_request<F: BufFactory>(
+    pub fn send_capsule<F: BufFactory>(
         &mut self, conn: &mut super::Connection<F>, stream_id: u64,
-        priority: &Priority,
+        capsule: &Capsule,
     ) -> Result<()> {
-        let mut d = [42; 20];
+        let mut d = vec![0; capsule.wire_len()];
         let mut b = octets::OctetsMut::with_slice(&mut d);
+        capsule.to_bytes(&mut b)?;
 
-        // Validate that it is sane to send PRIORITY_UPDATE.
-        if self.is_server {
-            return Err(Error::FrameUnexpected);
-        }
-
-        if stream_id % 4 != 0 {
-            return Err(Error::FrameUnexpected);
-        }
-
-        let control_stream_id =
-            self.control_stream_id.ok_or(Error::FrameUnexpected)?;
-
-        let urgency = priority
-            .urgency
-            .clamp(PRIORITY_URGENCY_LOWER_BOUND, PRIORITY_URGENCY_UPPER_BOUND);
-
-        let mut field_value = format!("u={urgency}");
-
-        if priority.incremental {
-            field_value.push_str(",i");
-        }
-
-        let priority_field_value = field_value.as_bytes();
-        let frame_payload_len =
-            octets::varint_len(stream_id) + priority_field_value.len();
-
-        let overhead =
-            octets::varint_len(frame::PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID) +
-                octets::varint_len(stream_id) +
-                octets::varint_len(frame_payload_len as u64);
+        let overhead = octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
+            octets::varint_len(d.len() as u64);
 
-        // Make sure the control stream has enough capacity.
-        match conn.stream_writable(
-            control_stream_id,
-            overhead + priority_field_value.len(),
-        ) {
+        // Capsules need to be sent atomically, so make sure the stream has
+        // enough capacity.
+        match conn.stream_writable(stream_id, overhead + d.len()) {
             Ok(true) => (),
 
             Ok(false) => return Err(Error::StreamBlocked),
 
             Err(e) => {
+                if conn.stream_finished(stream_id) {
+                    self.remove_stream(stream_id);
+                }
+
                 return Err(e.into());
             },
-        }
-
-        b.put_varint(frame::PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID)?;
-        b.put_varint(frame_payload_len as u64)?;
-        b.put_varint(stream_id)?;
-        let off = b.off();
-        conn.stream_send(control_stream_id, &d[..off], false)?;
+        };
 
-        // Sending field value separately avoids unnecessary copy.
-        conn.stream_send(control_stream_id, priority_field_value, false)?;
+        self.send_body(conn, stream_id, &d, false)?;
 
         trace!(
-            "{} tx frm PRIORITY_UPDATE request_stream={} priority_field_value={}",
+            "{} tx capsule type={} stream={} len={}",
             conn.trace_id(),
+            capsule.capsule_type(),
             stream_id,
-            field_value,
+            d.len()
         );
 
-        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
-            let frame = Http3Frame::PriorityUpdate {
-                target_stream_type: H3PriorityTargetStreamType::Request,
-                prioritized_element_id: stream_id,
-                priority_field_value: field_value.clone(),
-            };
-
-            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
-                stream_id,
-                length: Some(priority_field_value.len() as u64),
-                frame,
-                ..Default::default()
-            });
-
-            q.add_event_data_now(ev_data).ok();
-        });
-
         Ok(())
     }
 
-    /// Take the last PRIORITY_UPDATE for a prioritized element ID.
+    /// Reads the next capsule received on the given request stream.
     ///
-    /// When the [`poll()`] method returns a [`PriorityUpdate`] event for a
-    /// prioritized element, the event has triggered and will not rearm until
-    /// applications call this method. It is recommended that applications defer
-    /// taking the PRIORITY_UPDATE until after [`poll()`] returns [`Done`].
+    /// Applications using the [Capsule Protocol] on a stream should call this
+    /// method instead of [`recv_body()`] whenever the [`poll()`] method
+    /// returns a [`Data`] event. Capsules of unknown type are skipped.
     ///
-    /// On success the Priority Field Value is returned, or [`Done`] if there is
-    /// no PRIORITY_UPDATE to read (either because there is no value to take, or
-    /// because the prioritized element does not exist).
+    /// On success the capsule is returned, or [`Done`] if no complete capsule
+    /// was received yet.
+    ///
+    /// The [`ExcessiveLoad`] error is returned when the payload of the capsule
+    /// exceeds the limit set with [`set_max_capsule_payload_size()`], and the
+    /// [`MessageError`] error is returned when the stream ends in the middle
+    /// of a capsule. In both cases the stream can't be used to receive
+    /// capsules anymore.
     ///
+    /// [Capsule Protocol]: https://www.rfc-editor.org/rfc/rfc9297.html#section-3
+    /// [`recv_body()`]: struct.Connection.html#method.recv_body
     /// [`poll()`]: struct.Connection.html#method.poll
-    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    /// [`Data`]: enum.Event.html#variant.Data
     /// [`Done`]: enum.Error.html#variant.Done
-    pub fn take_last_priority_update(
-        &mut self, prioritized_element_id: u64,
-    ) -> Result<Vec<u8>> {
-        if let Some(stream) = self.streams.get_mut(&prioritized_element_id) {
-            return stream.take_last_priority_update().ok_or(Error::Done);
-        }
+    /// [`ExcessiveLoad`]: enum.Error.html#variant.ExcessiveLoad
+    /// [`MessageError`]: enum.Error.html#variant.MessageError
+    /// [`set_max_capsule_payload_size()`]:
+    /// struct.Config.html#method.set_max_capsule_payload_size
+    pub fn recv_capsule<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+    ) -> Result<Capsule> {
+        let mut buf = [0; 4096];
 
-        Err(Error::Done)
-    }
+        loop {
+            let decoder =
+                self.capsule_decoders.entry(stream_id).or_insert_with(|| {
+                    capsule::CapsuleDecoder::new(self.max_capsule_payload_size)
+                });
 
-    /// Processes HTTP/3 data received from the peer.
-    ///
-    /// On success it returns an [`Event`] and an ID, or [`Done`] when there are
-    /// no events to report.
-    ///
-    /// Note that all events are edge-triggered, meaning that once reported they
-    /// will not be reported again by calling this method again, until the event
-    /// is re-armed.
-    ///
-    /// The events [`Headers`], [`Data`] and [`Finished`] return a stream ID,
-    /// which is used in methods [`recv_body()`], [`send_response()`] or
-    /// [`send_body()`].
-    ///
-    /// The event [`GoAway`] returns an ID that depends on the connection role.
-    /// A client receives the largest processed stream ID. A server receives the
-    /// the largest permitted push ID.
-    ///
-    /// The event [`PriorityUpdate`] only occurs at servers. It returns a
-    /// prioritized element ID that is used in the method
-    /// [`take_last_priority_update()`], which rearms the event for that ID.
-    ///
-    /// If an error occurs while processing data, the connection is closed with
-    /// the appropriate error code, using the transport's [`close()`] method.
-    ///
-    /// [`Event`]: enum.Event.html
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`Headers`]: enum.Event.html#variant.Headers
-    /// [`Data`]: enum.Event.html#variant.Data
-    /// [`Finished`]: enum.Event.html#variant.Finished
-    /// [`GoAway`]: enum.Event.html#variant.GoAWay
-    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
-    /// [`recv_body()`]: struct.Connection.html#method.recv_body
-    /// [`send_response()`]: struct.Connection.html#method.send_response
-    /// [`send_body()`]: struct.Connection.html#method.send_body
-    /// [`recv_dgram()`]: struct.Connection.html#method.recv_dgram
-    /// [`take_last_priority_update()`]: struct.Connection.html#method.take_last_priority_update
-    /// [`close()`]: ../struct.Connection.html#method.close
-    pub fn poll<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>,
-    ) -> Result<(u64, Event)> {
-        // When connection close is initiated by the local application (e.g. due
-        // to a protocol error), the connection itself might be in a broken
-        // state, so return early.
-        if conn.local_error.is_some() {
-            return Err(Error::Done);
-        }
+            match decoder.decode() {
+                Ok(Some(capsule)) => {
+                    trace!(
+                        "{} rx capsule type={} stream={}",
+                        conn.trace_id(),
+                        capsule.capsule_type(),
+                        stream_id,
+                    );
 
-        // Process control streams first.
-        if let Some(stream_id) = self.peer_control_stream_id {
-            match self.process_control_stream(conn, stream_id) {
-                Ok(ev) => return Ok(ev),
+                    return Ok(capsule);
+                },
 
-                Err(Error::Done) => (),
+                Ok(None) => (),
 
-                Err(e) => return Err(e),
+                Err(e) => {
+                    self.capsule_decoders.remove(&stream_id);
+                    return Err(e);
+                },
             };
-        }
-
-        if let Some(stream_id) = self.peer_qpack_streams.encoder_stream_id {
-            match self.process_control_stream(conn, stream_id) {
-                Ok(ev) => return Ok(ev),
 
-                Err(Error::Done) => (),
+            let read = match self.recv_body(conn, stream_id, &mut buf) {
+                Ok(v) => v,
 
-                Err(e) => return Err(e),
-            };
-        }
+                Err(Error::Done) => {
+                    if conn.stream_finished(stream_id) {
+                        let decoder = self.capsule_decoders.remove(&stream_id);
 
-        if let Some(stream_id) = self.peer_qpack_streams.decoder_stream_id {
-            match self.process_control_stream(conn, stream_id) {
-                Ok(ev) => return Ok(ev),
+                        if decoder.is_some_and(|d| d.has_partial_capsule()) {
+                            return Err(Error::MessageError);
+                        }
+                    }
 
-                Err(Error::Done) => (),
+                    return Err(Error::Done);
+                },
 
-                Err(e) => return Err(e),
+                Err(e) => {
+                    self.capsule_decoders.remove(&stream_id);
+                    return Err(e);
+                },
             };
-        }
 
-        // Process finished streams list.
-        if let Some(finished) = self.finished_streams.pop_front() {
-            return Ok((finished, Event::Finished));
+            if let Some(decoder) = self.capsule_decoders.get_mut(&stream_id) {
+                decoder.push(&buf[..read]);
+            }
         }
+    }
 
-        // Process HTTP/3 data from readable streams.
-        for s in conn.readable() {
-            trace!("{} stream id {} is readable", conn.trace_id(), s);
-
-            let ev = match self.process_readable_stream(conn, s, true) {
-                Ok(v) => Some(v),
+    /// Sends an HTTP Datagram associated with the given request stream.
+    ///
+    /// When the peer enabled HTTP/3 DATAGRAM frame support the payload is sent
+    /// in a QUIC DATAGRAM frame, prefixed with the quarter stream ID.
+    /// Otherwise it falls back to a DATAGRAM capsule sent on the request
+    /// stream using [`send_capsule()`], which the peer receives with
+    /// [`recv_capsule()`].
+    ///
+    /// [`send_capsule()`]: struct.Connection.html#method.send_capsule
+    /// [`recv_capsule()`]: struct.Connection.html#method.recv_capsule
+    pub fn send_http_datagram<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+        payload: &[u8],
+    ) -> Result<()> {
+        if stream_id % 4 != 0 {
+            return Err(Error::FrameUnexpected);
+        }
 
-                Err(Error::Done) => None,
+        if !self.dgram_enabled_by_peer(conn) {
+            let capsule = Capsule::Datagram {
+                payload: payload.to_vec(),
+            };
 
-                // Return early if the stream was reset, to avoid returning
-                // a Finished event later as well.
-                Err(Error::TransportError(crate::Error::StreamReset(e))) =>
-                    return Ok((s, Event::Reset(e))),
+            return self.send_capsule(conn, stream_id, &capsule);
+        }
 
-                Err(e) => return Err(e),
-            };
+        let quarter_stream_id = stream_id / 4;
 
-            if conn.stream_finished(s) {
-                self.process_finished_stream(s);
-            }
+        let mut d =
+            vec![0; octets::varint_len(quarter_stream_id) + payload.len()];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-            // TODO: check if stream is completed so it can be freed
-            if let Some(ev) = ev {
-                return Ok(ev);
-            }
-        }
+        b.put_varint(quarter_stream_id)?;
+        b.put_bytes(payload)?;
 
-        // Process finished streams list once again, to make sure `Finished`
-        // events are returned when receiving empty stream frames with the fin
-        // flag set.
-        if let Some(finished) = self.finished_streams.pop_front() {
-            if conn.stream_readable(finished) {
-                // The stream is finished, but is still readable, it may
-                // indicate that there is a pending error, such as reset.
-                if let Err(crate::Error::StreamReset(e)) =
-                    conn.stream_recv(finished, &mut [])
-                {
-                    return Ok((finished, Event::Reset(e)));
-                }
-            }
-            return Ok((finished, Event::Finished));
-        }
+        conn.dgram_send_vec(d)?;
 
-        Err(Error::Done)
+        Ok(())
     }
 
-    /// Sends a GOAWAY frame to initiate graceful connection closure.
+    /// Reads an HTTP Datagram received in a QUIC DATAGRAM frame.
     ///
-    /// When quiche is used in the server role, the `id` parameter is the stream
-    /// ID of the highest processed request. This can be any valid ID between 0
-    /// and 2^62-4. However, the ID cannot be increased. Failure to satisfy
-    /// these conditions will return an error.
+    /// The payload is written at the start of the provided buffer. On success
+    /// the ID of the request stream the datagram is associated with and the
+    /// payload's length are returned, or [`Done`] if there is no datagram to
+    /// read.
     ///
-    /// This method does not close the QUIC connection. Applications are
-    /// required to call [`close()`] themselves.
+    /// The [`FrameError`] error is returned when the datagram doesn't start
+    /// with a valid quarter stream ID.
     ///
-    /// [`close()`]: ../struct.Connection.html#method.close
-    pub fn send_goaway<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, id: u64,
-    ) -> Result<()> {
-        let mut id = id;
-
-        // TODO: server push
-        //
-        // In the meantime always send 0 from client.
-        if !self.is_server {
-            id = 0;
-        }
-
-        if self.is_server && id % 4 != 0 {
-            return Err(Error::IdError);
-        }
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`FrameError`]: enum.Error.html#variant.FrameError
+    pub fn recv_http_datagram<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, buf: &mut [u8],
+    ) -> Result<(u64, usize)> {
+        let len = conn.dgram_recv(buf)?;
 
-        if let Some(sent_id) = self.local_goaway_id {
-            if id > sent_id {
-                return Err(Error::IdError);
-            }
-        }
+        let mut b = octets::Octets::with_slice(&buf[..len]);
 
-        if let Some(stream_id) = self.control_stream_id {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        let stream_id = b
+            .get_varint()
+            .ok()
+            .and_then(|quarter_stream_id| quarter_stream_id.checked_mul(4))
+            .ok_or(Error::FrameError)?;
 
-            let frame = frame::Frame::GoAway { id };
+        let off = b.off();
 
-            let wire_len = frame.to_bytes(&mut b)?;
-            let stream_cap = conn.stream_capacity(stream_id)?;
+        buf.copy_within(off..len, 0);
 
-            if stream_cap < wire_len {
-                return Err(Error::StreamBlocked);
-            }
+        Ok((stream_id, len - off))
+    }
 
-            trace!("{} tx frm {:?}", conn.trace_id(), frame);
-
-            qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
-                let ev_data = EventData::H3FrameCreated(H3FrameCreated {
-                    stream_id,
-                    length: Some(octets::varint_len(id) as u64),
-                    frame: frame.to_qlog(),
-                    ..Default::default()
-                });
+    /// Opens a WebTransport stream associated with the given session.
+    ///
+    /// The `session_id` parameter is the ID of the extended CONNECT stream that
+    /// established the session. Data can then be sent on the new stream using
+    /// [`send_body()`]. On bidirectional streams, data sent by the peer is
+    /// reported with [`Data`] events.
+    ///
+    /// On success the newly allocated stream ID is returned.
+    ///
+    /// The [`FrameUnexpected`] error is returned when the peer didn't enable
+    /// WebTransport support, or when `session_id` is not the ID of an
+    /// established session.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    /// [`Data`]: enum.Event.html#variant.Data
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn open_webtransport_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, session_id: u64,
+        bidirectional: bool,
+    ) -> Result<u64> {
+        if !self.webtransport_enabled_by_peer() ||
+            !self.webtransport_sessions.contains(&session_id)
+        {
+            return Err(Error::FrameUnexpected);
+        }
 
-                q.add_event_data_now(ev_data).ok();
-            });
+        let mut d = [0; 16];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-            let off = b.off();
-            conn.stream_send(stream_id, &d[..off], false)?;
+        let stream_id = if bidirectional {
+            b.put_varint(frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID)?;
+            self.next_request_stream_id
+        } else {
+            b.put_varint(stream::WEBTRANSPORT_STREAM_TYPE_ID)?;
+            self.next_uni_stream_id
+        };
 
-            self.local_goaway_id = Some(id);
-        }
+        b.put_varint(session_id)?;
+        let off = b.off();
 
-        Ok(())
-    }
+        // The underlying QUIC stream does not exist yet, so calls to e.g.
+        // stream_writable() will fail. By writing a 0-length buffer, we force
+        // the creation of the QUIC stream state, without actually writing
+        // anything.
+        if let Err(e) = conn.stream_send(stream_id, b"", false) {
+            if e == super::Error::Done {
+                return Err(Error::StreamBlocked);
+            }
 
-    /// Gets the raw settings from peer including unknown and reserved types.
-    ///
-    /// The order of settings is the same as received in the SETTINGS frame.
-    pub fn peer_settings_raw(&self) -> Option<&[(u64, u64)]> {
-        self.peer_settings.raw.as_deref()
-    }
+            return Err(e.into());
+        };
 
-    fn open_uni_stream<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, ty: u64,
-    ) -> Result<u64> {
-        let stream_id = self.next_uni_stream_id;
+        // The stream's header needs to be sent atomically.
+        if !conn.stream_writable(stream_id, off)? {
+            return Err(Error::StreamBlocked);
+        }
 
-        let mut d = [0; 8];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, &d[..off], false)?;
 
-        match ty {
-            // Control and QPACK streams are the most important to schedule.
-            stream::HTTP3_CONTROL_STREAM_TYPE_ID |
-            stream::QPACK_ENCODER_STREAM_TYPE_ID |
-            stream::QPACK_DECODER_STREAM_TYPE_ID => {
-                conn.stream_priority(stream_id, 0, false)?;
-            },
+        let mut stream = <stream::Stream>::new(stream_id, true);
+        stream.initialize_local_webtransport(session_id);
 
-            // TODO: Server push
-            stream::HTTP3_PUSH_STREAM_TYPE_ID => (),
+        self.streams.insert(stream_id, stream);
 
-            // Anything else is a GREASE stream, so make it the least important.
-            _ => {
-                conn.stream_priority(stream_id, 255, false)?;
-            },
+        // To avoid skipping stream IDs, we only calculate the next available
+        // stream ID when the header has been successfully buffered.
+        if bidirectional {
+            self.next_request_stream_id = self
+                .next_request_stream_id
+                .checked_add(4)
+                .ok_or(Error::IdError)?;
+        } else {
+            self.next_uni_stream_id = self
+                .next_uni_stream_id
+                .checked_add(4)
+                .ok_or(Error::IdError)?;
         }
 
-        conn.stream_send(stream_id, b.put_varint(ty)?, false)?;
-
-        // To avoid skipping stream IDs, we only calculate the next available
-        // stream ID when data has been successfully buffered.
-        self.next_uni_stream_id = self
-            .next_uni_stream_id
-            .checked_add(4)
-            .ok_or(Error::IdError)?;
+        trace!(
+            "{} open WebTransport stream {} session={}",
+            conn.trace_id(),
+            stream_id,
+            session_id
+        );
 
         Ok(stream_id)
     }
 
-    fn open_qpack_encoder_stream<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>,
+    /// Returns the ID of the WebTransport session the specified stream
+    /// belongs to, if any.
+    pub fn webtransport_session_id(&self, stream_id: u64) -> Option<u64> {
+        self.streams.get(&stream_id).and_then(|s| s.session_id())
+    }
+
+    /// Closes a WebTransport session.
+    ///
+    /// This sends a WT_CLOSE_SESSION capsule carrying the application
+    /// `error_code` and `reason` on the session's extended CONNECT stream, and
+    /// finishes the stream. The streams associated with the session are reset
+    /// with the [`WEBTRANSPORT_SESSION_GONE`] error code.
+    ///
+    /// The [`MessageError`] error is returned when `reason` is longer than
+    /// 1024 bytes.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`WEBTRANSPORT_SESSION_GONE`]: constant.WEBTRANSPORT_SESSION_GONE.html
+    /// [`MessageError`]: enum.Error.html#variant.MessageError
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn close_webtransport_session<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, session_id: u64,
+        error_code: u32, reason: &[u8],
     ) -> Result<()> {
-        let stream_id =
-            self.open_uni_stream(conn, stream::QPACK_ENCODER_STREAM_TYPE_ID)?;
+        if reason.len() > capsule::MAX_WT_CLOSE_SESSION_REASON_LEN {
+            return Err(Error::MessageError);
+        }
 
-        self.local_qpack_streams.encoder_stream_id = Some(stream_id);
+        let capsule = Capsule::WebTransportCloseSession {
+            error_code,
+            reason: reason.to_vec(),
+        };
 
-        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
-            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
-                stream_id,
-                owner: Some(H3Owner::Local),
-                stream_type: H3StreamType::QpackEncode,
-                ..Default::default()
-            });
+        self.send_capsule(conn, session_id, &capsule)?;
 
-            q.add_event_data_now(ev_data).ok();
-        });
+        conn.stream_send(session_id, b"", true)?;
 
-        Ok(())
-    }
+        self.webtransport_sessions.remove(&session_id);
 
-    fn open_qpack_decoder_stream<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>,
-    ) -> Result<()> {
-        let stream_id =
-            self.open_uni_stream(conn, stream::QPACK_DECODER_STREAM_TYPE_ID)?;
+        let session_streams: Vec<u64> = self
+            .streams
+            .iter()
+            .filter(|(_, s)| s.session_id() == Some(session_id))
+            .map(|(id, _)| *id)
+            .collect();
 
-        self.local_qpack_streams.decoder_stream_id = Some(stream_id);
+        for stream_id in session_streams {
+            let is_local = crate::stream::is_local(stream_id, self.is_server);
+            let is_bidi = crate::stream::is_bidi(stream_id);
 
-        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
-            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
-                stream_id,
-                owner: Some(H3Owner::Local),
-                stream_type: H3StreamType::QpackDecode,
-                ..Default::default()
-            });
+            if is_bidi || !is_local {
+                conn.stream_shutdown(
+                    stream_id,
+                    crate::Shutdown::Read,
+                    WEBTRANSPORT_SESSION_GONE,
+                )
+                .ok();
+            }
 
-            q.add_event_data_now(ev_data).ok();
-        });
+            if is_bidi || is_local {
+                conn.stream_shutdown(
+                    stream_id,
+                    crate::Shutdown::Write,
+                    WEBTRANSPORT_SESSION_GONE,
+                )
+                .ok();
+            }
+
+            self.remove_stream(stream_id);
+        }
+
+        if conn.stream_finished(session_id) {
+            self.remove_stream(session_id);
+        }
 
         Ok(())
     }
 
-    /// Send GREASE frames on the provided stream ID.
-    fn send_grease_frames<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+    /// Asks the peer to gracefully finish a WebTransport session.
+    ///
+    /// This sends a WT_DRAIN_SESSION capsule on the session's extended
+    /// CONNECT stream, using [`send_capsule()`].
+    ///
+    /// [`send_capsule()`]: struct.Connection.html#method.send_capsule
+    pub fn drain_webtransport_session<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, session_id: u64,
     ) -> Result<()> {
-        let mut d = [0; 8];
+        self.send_capsule(conn, session_id, &Capsule::WebTransportDrainSession)
+    }
 
-        let stream_cap = match conn.stream_capacity(stream_id) {
-            Ok(v) => v,
+    /// Reads request or response body data into the provided buffer.
+    ///
+    /// Applications should call this method whenever the [`poll()`] method
+    /// returns a [`Data`] event. On WebTransport streams the data received
+    /// from the peer is returned as is.
+    ///
+    /// On success the amount of bytes read is returned, or [`Done`] if there
+    /// is no data to read.
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    /// [`Data`]: enum.Event.html#variant.Data
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn recv_body<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+        out: &mut [u8],
+    ) -> Result<usize> {
+        let mut total = 0;
 
-            Err(e) => {
-                if conn.stream_finished(stream_id) {
-                    self.streams.remove(&stream_id);
-                }
+        // Try to consume all buffered data for the stream, even across multiple
+        // DATA frames.
+        while total < out.len() {
+            let stream = self.streams.get_mut(&stream_id).ok_or(Error::Done)?;
 
-                return Err(e.into());
-            },
-        };
+            if stream.state() != stream::State::Data {
+                break;
+            }
 
-        let grease_frame1 = grease_value();
-        let grease_frame2 = grease_value();
-        let grease_payload = b"GREASE is the word";
+            let (read, fin) =
+                match stream.try_consume_data(conn, &mut out[total..]) {
+                    Ok(v) => v,
 
-        let overhead = octets::varint_len(grease_frame1) + // frame type
-            1 + // payload len
-            octets::varint_len(grease_frame2) + // frame type
-            1 + // payload len
-            grease_payload.len(); // payload
+                    Err(Error::Done) => break,
 
-        // Don't send GREASE if there is not enough capacity for it. Greasing
-        // will _not_ be attempted again later on.
-        if stream_cap < overhead {
-            return Ok(());
-        }
+                    Err(e) => return Err(e),
+                };
 
-        // Empty GREASE frame.
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(grease_frame1)?, false)?;
+            total += read;
 
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(0)?, false)?;
+            // No more data to read, we are done.
+            if read == 0 || fin {
+                break;
+            }
 
-        trace!(
-            "{} tx frm GREASE stream={} len=0",
-            conn.trace_id(),
-            stream_id
-        );
+            // Process incoming data from the stream. For example, if a whole
+            // DATA frame was consumed, and another one is queued behind it,
+            // this will ensure the additional data will also be returned to
+            // the application.
+            match self.process_readable_stream(conn, stream_id, false) {
+                Ok(_) => unreachable!(),
 
-        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
-            let frame = Http3Frame::Reserved { length: Some(0) };
-            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
-                stream_id,
-                length: Some(0),
-                frame,
-                ..Default::default()
-            });
+                Err(Error::Done) => (),
 
-            q.add_event_data_now(ev_data).ok();
-        });
+                Err(e) => return Err(e),
+            };
 
-        // GREASE frame with payload.
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(grease_frame2)?, false)?;
+            if conn.stream_finished(stream_id) {
+                break;
+            }
+        }
 
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(18)?, false)?;
+        // While body is being received, the stream is marked as finished only
+        // when all data is read by the application.
+        if conn.stream_finished(stream_id) {
+            self.process_finished_stream(stream_id);
+        }
 
-        conn.stream_send(stream_id, grease_payload, false)?;
+        if total == 0 {
+            return Err(Error::Done);
+        }
 
-        trace!(
-            "{} tx frm GREASE stream={} len={}",
-            conn.trace_id(),
-            stream_id,
-            grease_payload.len()
+        Ok(total)
+    }
+
+    /// Sends a PRIORITY_UPDATE frame on the control stream with specified
+    /// request stream ID and priority.
+    ///
+    /// The `priority` parameter represents [Extensible Priority]
+    /// parameters. If the urgency is outside the range 0-7, it will be clamped
+    /// to 7.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    /// [Extensible Priority]: https://www.rfc-editor.org/rfc/rfc9218.html#section-4.
+    pub fn send_priority_update_for_request<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+        priority: &Priority,
+    ) -> Result<()> {
+        let mut d = [42; 20];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        // Validate that it is sane to send PRIORITY_UPDATE.
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
+
+        if stream_id % 4 != 0 {
+            return Err(Error::FrameUnexpected);
+        }
+
+        let control_stream_id =
+            self.control_stream_id.ok_or(Error::FrameUnexpected)?;
+
+        let urgency = priority
+            .urgency
+            .clamp(PRIORITY_URGENCY_LOWER_BOUND, PRIORITY_URGENCY_UPPER_BOUND);
+
+        let mut field_value = format!("u={urgency}");
+
+        if priority.incremental {
+            field_value.push_str(",i");
+        }
+
+        let priority_field_value = field_value.as_bytes();
+        let frame_payload_len =
+            octets::varint_len(stream_id) + priority_field_value.len();
+
+        let overhead =
+            octets::varint_len(frame::PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID) +
+                octets::varint_len(stream_id) +
+                octets::varint_len(frame_payload_len as u64);
+
+        // Make sure the control stream has enough capacity.
+        match conn.stream_writable(
+            control_stream_id,
+            overhead + priority_field_value.len(),
+        ) {
+            Ok(true) => (),
+
+            Ok(false) => return Err(Error::StreamBlocked),
+
+            Err(e) => {
+                return Err(e.into());
+            },
+        }
+
+        b.put_varint(frame::PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID)?;
+        b.put_varint(frame_payload_len as u64)?;
+        b.put_varint(stream_id)?;
+        let off = b.off();
+        conn.stream_send(control_stream_id, &d[..off], false)?;
+
+        // Sending field value separately avoids unnecessary copy.
+        conn.stream_send(control_stream_id, priority_field_value, false)?;
+
+        trace!(
+            "{} tx frm PRIORITY_UPDATE request_stream={} priority_field_value={}",
+            conn.trace_id(),
+            stream_id,
+            field_value,
         );
 
         qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
-            let frame = Http3Frame::Reserved {
-                length: Some(grease_payload.len() as u64),
+            let frame = Http3Frame::PriorityUpdate {
+                target_stream_type: H3PriorityTargetStreamType::Request,
+                prioritized_element_id: stream_id,
+                priority_field_value: field_value.clone(),
             };
+
             let ev_data = EventData::H3FrameCreated(H3FrameCreated {
                 stream_id,
-                length: Some(grease_payload.len() as u64),
+                length: Some(priority_field_value.len() as u64),
                 frame,
                 ..Default::default()
             });
@@ -2321,1288 +2734,3640 @@ impl Connection {
         Ok(())
     }
 
-    /// Opens a new unidirectional stream with a GREASE type and sends some
-    /// unframed payload.
-    fn open_grease_stream<F: BufFactory>(
+    /// Take the last PRIORITY_UPDATE for a prioritized element ID.
+    ///
+    /// When the [`poll()`] method returns a [`PriorityUpdate`] event for a
+    /// prioritized element, the event has triggered and will not rearm until
+    /// applications call this method. It is recommended that applications defer
+    /// taking the PRIORITY_UPDATE until after [`poll()`] returns [`Done`].
+    ///
+    /// On success the Priority Field Value is returned, or [`Done`] if there is
+    /// no PRIORITY_UPDATE to read (either because there is no value to take, or
+    /// because the prioritized element does not exist).
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn take_last_priority_update(
+        &mut self, prioritized_element_id: u64,
+    ) -> Result<Vec<u8>> {
+        if let Some(stream) = self.streams.get_mut(&prioritized_element_id) {
+            return stream.take_last_priority_update().ok_or(Error::Done);
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Processes HTTP/3 data received from the peer.
+    ///
+    /// On success it returns an [`Event`] and an ID, or [`Done`] when there are
+    /// no events to report.
+    ///
+    /// Note that all events are edge-triggered, meaning that once reported they
+    /// will not be reported again by calling this method again, until the event
+    /// is re-armed.
+    ///
+    /// The events [`Headers`], [`Data`] and [`Finished`] return a stream ID,
+    /// which is used in methods [`recv_body()`], [`send_response()`] or
+    /// [`send_body()`].
+    ///
+    /// The event [`GoAway`] returns an ID that depends on the connection role.
+    /// A client receives the largest processed stream ID. A server receives the
+    /// the largest permitted push ID.
+    ///
+    /// The event [`PriorityUpdate`] only occurs at servers. It returns a
+    /// prioritized element ID that is used in the method
+    /// [`take_last_priority_update()`], which rearms the event for that ID.
+    ///
+    /// The event [`PushPromise`] only occurs at clients. It returns the ID of
+    /// the request stream the push was promised on. The event [`CancelPush`]
+    /// returns the push ID of the cancelled push.
+    ///
+    /// The event [`WebTransportStream`] returns the ID of a WebTransport stream
+    /// opened by the peer, which is then used in method [`recv_body()`].
+    ///
+    /// If an error occurs while processing data, the connection is closed with
+    /// the appropriate error code, using the transport's [`close()`] method.
+    ///
+    /// [`Event`]: enum.Event.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`Headers`]: enum.Event.html#variant.Headers
+    /// [`Data`]: enum.Event.html#variant.Data
+    /// [`Finished`]: enum.Event.html#variant.Finished
+    /// [`GoAway`]: enum.Event.html#variant.GoAWay
+    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    /// [`PushPromise`]: enum.Event.html#variant.PushPromise
+    /// [`CancelPush`]: enum.Event.html#variant.CancelPush
+    /// [`WebTransportStream`]: enum.Event.html#variant.WebTransportStream
+    /// [`recv_body()`]: struct.Connection.html#method.recv_body
+    /// [`send_response()`]: struct.Connection.html#method.send_response
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    /// [`recv_dgram()`]: struct.Connection.html#method.recv_dgram
+    /// [`take_last_priority_update()`]: struct.Connection.html#method.take_last_priority_update
+    /// [`close()`]: ../struct.Connection.html#method.close
+    pub fn poll<F: BufFactory>(
         &mut self, conn: &mut super::Connection<F>,
-    ) -> Result<()> {
-        let ty = grease_value();
-        match self.open_uni_stream(conn, ty) {
-            Ok(stream_id) => {
-                conn.stream_send(stream_id, b"GREASE is the word", true)?;
+    ) -> Result<(u64, Event)> {
+        // When connection close is initiated by the local application (e.g. due
+        // to a protocol error), the connection itself might be in a broken
+        // state, so return early.
+        if conn.local_error.is_some() {
+            return Err(Error::Done);
+        }
 
-                trace!("{} open GREASE stream {}", conn.trace_id(), stream_id);
+        // Process control streams first.
+        if let Some(stream_id) = self.peer_control_stream_id {
+            match self.process_control_stream(conn, stream_id) {
+                Ok(ev) => return Ok(ev),
 
-                qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
-                    let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
-                        stream_id,
-                        owner: Some(H3Owner::Local),
-                        stream_type: H3StreamType::Unknown,
-                        stream_type_value: Some(ty),
-                        ..Default::default()
-                    });
+                Err(Error::Done) => (),
 
-                    q.add_event_data_now(ev_data).ok();
-                });
-            },
+                Err(e) => return Err(e),
+            };
+        }
 
-            Err(Error::IdError) => {
-                trace!("{} GREASE stream blocked", conn.trace_id(),);
+        if let Some(stream_id) = self.peer_qpack_streams.encoder_stream_id {
+            match self.process_control_stream(conn, stream_id) {
+                Ok(ev) => return Ok(ev),
 
-                return Ok(());
-            },
+                Err(Error::Done) => (),
 
-            Err(e) => return Err(e),
-        };
+                Err(e) => return Err(e),
+            };
+        }
 
-        Ok(())
-    }
+        if let Some(stream_id) = self.peer_qpack_streams.decoder_stream_id {
+            match self.process_control_stream(conn, stream_id) {
+                Ok(ev) => return Ok(ev),
 
-    /// Sends SETTINGS frame based on HTTP/3 configuration.
-    fn send_settings<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>,
-    ) -> Result<()> {
-        let stream_id = match self
-            .open_uni_stream(conn, stream::HTTP3_CONTROL_STREAM_TYPE_ID)
-        {
-            Ok(v) => v,
+                Err(Error::Done) => (),
 
-            Err(e) => {
-                trace!("{} Control stream blocked", conn.trace_id(),);
+                Err(e) => return Err(e),
+            };
+        }
 
-                if e == Error::Done {
-                    return Err(Error::InternalError);
-                }
+        // Process streams that might have been unblocked by QPACK encoder
+        // instructions.
+        match self.process_qpack_blocked_streams(conn) {
+            Ok(ev) => return Ok(ev),
 
-                return Err(e);
-            },
+            Err(Error::Done) => (),
+
+            Err(e) => return Err(e),
         };
 
-        self.control_stream_id = Some(stream_id);
+        // Send QPACK instructions that couldn't be sent before, e.g. due to
+        // flow control.
+        self.flush_qpack_encoder_stream(conn)?;
+        self.flush_qpack_decoder_stream(conn)?;
 
-        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
-            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
-                stream_id,
-                owner: Some(H3Owner::Local),
-                stream_type: H3StreamType::Control,
-                ..Default::default()
-            });
+        // Process finished streams list.
+        if let Some(finished) = self.finished_streams.pop_front() {
+            return Ok((finished, Event::Finished));
+        }
 
-            q.add_event_data_now(ev_data).ok();
-        });
+        // Process HTTP/3 data from readable streams.
+        for s in conn.readable() {
+            trace!("{} stream id {} is readable", conn.trace_id(), s);
 
-        let grease = if conn.grease {
-            Some((grease_value(), grease_value()))
-        } else {
-            None
-        };
+            let ev = match self.process_readable_stream(conn, s, true) {
+                Ok(v) => Some(v),
 
-        let frame = frame::Frame::Settings {
-            max_field_section_size: self.local_settings.max_field_section_size,
-            qpack_max_table_capacity: self
-                .local_settings
-                .qpack_max_table_capacity,
-            qpack_blocked_streams: self.local_settings.qpack_blocked_streams,
-            connect_protocol_enabled: self
-                .local_settings
-                .connect_protocol_enabled,
-            h3_datagram: self.local_settings.h3_datagram,
-            grease,
-            additional_settings: self.local_settings.additional_settings.clone(),
-            raw: Default::default(),
-        };
+                Err(Error::Done) => None,
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+                // Return early if the stream was reset, to avoid returning
+                // a Finished event later as well.
+                Err(Error::TransportError(crate::Error::StreamReset(e))) => {
+                    self.qpack_decoder.cancel_stream(s);
+                    self.qpack_blocked_streams.retain(|id| *id != s);
+                    self.capsule_decoders.remove(&s);
+                    self.webtransport_sessions.remove(&s);
+                    self.flush_qpack_decoder_stream(conn)?;
+
+                    return Ok((s, Event::Reset(e)));
+                },
 
-        frame.to_bytes(&mut b)?;
+                Err(e) => return Err(e),
+            };
 
-        let off = b.off();
+            if conn.stream_finished(s) {
+                self.process_finished_stream(s);
+            }
 
-        if let Some(id) = self.control_stream_id {
-            conn.stream_send(id, &d[..off], false)?;
+            // TODO: check if stream is completed so it can be freed
+            if let Some(ev) = ev {
+                return Ok(ev);
+            }
+        }
 
-            trace!(
-                "{} tx frm SETTINGS stream={} len={}",
-                conn.trace_id(),
-                id,
-                off
-            );
+        // Process finished streams list once again, to make sure `Finished`
+        // events are returned when receiving empty stream frames with the fin
+        // flag set.
+        if let Some(finished) = self.finished_streams.pop_front() {
+            if conn.stream_readable(finished) {
+                // The stream is finished, but is still readable, it may
+                // indicate that there is a pending error, such as reset.
+                if let Err(crate::Error::StreamReset(e)) =
+                    conn.stream_recv(finished, &mut [])
+                {
+                    return Ok((finished, Event::Reset(e)));
+                }
+            }
+            return Ok((finished, Event::Finished));
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Sends a GOAWAY frame to initiate graceful connection closure.
+    ///
+    /// When quiche is used in the server role, the `id` parameter is the stream
+    /// ID of the highest processed request. This can be any valid ID between 0
+    /// and 2^62-4. However, the ID cannot be increased. Failure to satisfy
+    /// these conditions will return an error.
+    ///
+    /// When quiche is used in the client role, the `id` parameter is the
+    /// smallest push ID the server is not allowed to use anymore. This ID
+    /// cannot be increased either.
+    ///
+    /// This method does not close the QUIC connection. Applications are
+    /// required to call [`close()`] themselves.
+    ///
+    /// [`close()`]: ../struct.Connection.html#method.close
+    pub fn send_goaway<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, id: u64,
+    ) -> Result<()> {
+        if self.is_server && id % 4 != 0 {
+            return Err(Error::IdError);
+        }
+
+        if let Some(sent_id) = self.local_goaway_id {
+            if id > sent_id {
+                return Err(Error::IdError);
+            }
+        }
+
+        if let Some(stream_id) = self.control_stream_id {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            let frame = frame::Frame::GoAway { id };
+
+            let wire_len = frame.to_bytes(&mut b)?;
+            let stream_cap = conn.stream_capacity(stream_id)?;
+
+            if stream_cap < wire_len {
+                return Err(Error::StreamBlocked);
+            }
+
+            trace!("{} tx frm {:?}", conn.trace_id(), frame);
 
             qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
-                let frame = frame.to_qlog();
                 let ev_data = EventData::H3FrameCreated(H3FrameCreated {
-                    stream_id: id,
-                    length: Some(off as u64),
-                    frame,
+                    stream_id,
+                    length: Some(octets::varint_len(id) as u64),
+                    frame: frame.to_qlog(),
                     ..Default::default()
                 });
 
                 q.add_event_data_now(ev_data).ok();
             });
+
+            let off = b.off();
+            conn.stream_send(stream_id, &d[..off], false)?;
+
+            self.local_goaway_id = Some(id);
         }
 
         Ok(())
     }
 
-    fn process_control_stream<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
-    ) -> Result<(u64, Event)> {
-        close_conn_if_critical_stream_finished(conn, stream_id)?;
-
-        if !conn.stream_readable(stream_id) {
-            return Err(Error::Done);
+    /// Sends a MAX_PUSH_ID frame to allow the server to push responses.
+    ///
+    /// The server will be able to promise pushes with push IDs up to, and
+    /// including, `push_id`. Until this method is called, the server can't
+    /// push any response.
+    ///
+    /// The [`FrameUnexpected`] error is returned when this method is called by
+    /// a server, as only clients can send MAX_PUSH_ID. The [`IdError`] error
+    /// is returned when `push_id` is lower than the previously sent limit.
+    ///
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn send_max_push_id<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, push_id: u64,
+    ) -> Result<()> {
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
         }
 
-        match self.process_readable_stream(conn, stream_id, true) {
-            Ok(ev) => return Ok(ev),
-
-            Err(Error::Done) => (),
+        if self.max_push_id.is_some_and(|max| push_id < max) {
+            return Err(Error::IdError);
+        }
 
-            Err(e) => return Err(e),
-        };
+        self.send_control_frame(
+            conn,
+            frame::Frame::MaxPushId { push_id },
+            octets::varint_len(push_id),
+        )?;
 
-        close_conn_if_critical_stream_finished(conn, stream_id)?;
+        self.max_push_id = Some(push_id);
 
-        Err(Error::Done)
+        Ok(())
     }
 
-    fn process_readable_stream<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, stream_id: u64, polling: bool,
-    ) -> Result<(u64, Event)> {
-        self.streams
-            .entry(stream_id)
-            .or_insert_with(|| <stream::Stream>::new(stream_id, false));
+    /// Sends a PUSH_PROMISE frame on the specified request stream.
+    ///
+    /// The promised request is encoded from the provided list of headers. The
+    /// pushed response can then be sent using [`send_push_response()`] with
+    /// the returned push ID.
+    ///
+    /// On success the newly allocated push ID is returned.
+    ///
+    /// The [`IdError`] error is returned when the client's MAX_PUSH_ID limit
+    /// doesn't allow any more pushes, or when the client sent a GOAWAY
+    /// frame that doesn't allow the next push ID.
+    ///
+    /// The [`FrameUnexpected`] error is returned when this method is called by
+    /// a client, or when `stream_id` is not an open request stream.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`send_push_response()`]: struct.Connection.html#method.send_push_response
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_push_promise<T: NameValue, F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64, headers: &[T],
+    ) -> Result<u64> {
+        if !self.is_server ||
+            stream_id % 4 != 0 ||
+            !self.streams.contains_key(&stream_id)
+        {
+            return Err(Error::FrameUnexpected);
+        }
 
-        // We need to get a fresh reference to the stream for each
-        // iteration, to avoid borrowing `self` for the entire duration
-        // of the loop, because we'll need to borrow it again in the
-        // `State::FramePayload` case below.
-        while let Some(stream) = self.streams.get_mut(&stream_id) {
-            match stream.state() {
-                stream::State::StreamType => {
-                    stream.try_fill_buffer(conn)?;
+        let push_id = self.next_push_id;
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+        if self.max_push_id.is_none_or(|max| push_id > max) {
+            return Err(Error::IdError);
+        }
 
-                        Err(_) => continue,
-                    };
+        // The client's GOAWAY carries the smallest push ID it won't accept.
+        if self.peer_goaway_id.is_some_and(|id| push_id >= id) {
+            return Err(Error::IdError);
+        }
 
-                    let ty = stream::Type::deserialize(varint)?;
+        let header_block = self.encode_header_block(stream_id, headers)?;
 
-                    if let Err(e) = stream.set_ty(ty) {
-                        conn.close(true, e.to_wire(), b"")?;
-                        return Err(e);
-                    }
+        let payload_len = octets::varint_len(push_id) + header_block.len();
 
-                    qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
-                        let ty_val = if matches!(ty, stream::Type::Unknown) {
-                            Some(varint)
-                        } else {
-                            None
-                        };
+        let overhead = octets::varint_len(frame::PUSH_PROMISE_FRAME_TYPE_ID) +
+            octets::varint_len(payload_len as u64) +
+            octets::varint_len(push_id);
 
-                        let ev_data =
-                            EventData::H3StreamTypeSet(H3StreamTypeSet {
-                                stream_id,
-                                owner: Some(H3Owner::Remote),
-                                stream_type: ty.to_qlog(),
-                                stream_type_value: ty_val,
-                                ..Default::default()
-                            });
+        // The frame needs to be sent atomically, so make sure the stream has
+        // enough capacity.
+        match conn.stream_writable(stream_id, overhead + header_block.len()) {
+            Ok(true) => (),
 
-                        q.add_event_data_now(ev_data).ok();
-                    });
+            Ok(false) => return Err(Error::StreamBlocked),
 
-                    match &ty {
-                        stream::Type::Control => {
-                            // Only one control stream allowed.
-                            if self.peer_control_stream_id.is_some() {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Received multiple control streams",
-                                )?;
+            Err(e) => return Err(e.into()),
+        };
 
-                                return Err(Error::StreamCreationError);
-                            }
+        // Send any dynamic table insertions the header block depends on
+        // before the header block itself.
+        self.flush_qpack_encoder_stream(conn)?;
 
-                            trace!(
-                                "{} open peer's control stream {}",
-                                conn.trace_id(),
-                                stream_id
-                            );
+        let mut d = [42; 24];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                            close_conn_if_critical_stream_finished(
-                                conn, stream_id,
-                            )?;
+        b.put_varint(frame::PUSH_PROMISE_FRAME_TYPE_ID)?;
+        b.put_varint(payload_len as u64)?;
+        b.put_varint(push_id)?;
+        let off = b.off();
+        conn.stream_send(stream_id, &d[..off], false)?;
 
-                            self.peer_control_stream_id = Some(stream_id);
-                        },
+        // Sending header block separately avoids unnecessary copy.
+        conn.stream_send(stream_id, &header_block, false)?;
 
-                        stream::Type::Push => {
-                            // Only clients can receive push stream.
-                            if self.is_server {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Server received push stream.",
-                                )?;
+        self.qpack_encoder.commit_field_section();
 
-                                return Err(Error::StreamCreationError);
-                            }
-                        },
+        trace!(
+            "{} tx frm PUSH_PROMISE stream={} push_id={} len={}",
+            conn.trace_id(),
+            stream_id,
+            push_id,
+            header_block.len(),
+        );
 
-                        stream::Type::QpackEncoder => {
-                            // Only one qpack encoder stream allowed.
-                            if self.peer_qpack_streams.encoder_stream_id.is_some()
-                            {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Received multiple QPACK encoder streams",
-                                )?;
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let qlog_headers = headers
+                .iter()
+                .map(|h| qlog::events::h3::HttpHeader {
+                    name: String::from_utf8_lossy(h.name()).into_owned(),
+                    value: String::from_utf8_lossy(h.value()).into_owned(),
+                })
+                .collect();
 
-                                return Err(Error::StreamCreationError);
-                            }
+            let frame = Http3Frame::PushPromise {
+                push_id,
+                headers: qlog_headers,
+            };
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: Some(payload_len as u64),
+                frame,
+                ..Default::default()
+            });
 
-                            close_conn_if_critical_stream_finished(
-                                conn, stream_id,
-                            )?;
+            q.add_event_data_now(ev_data).ok();
+        });
 
-                            self.peer_qpack_streams.encoder_stream_id =
-                                Some(stream_id);
-                        },
+        self.next_push_id = push_id.checked_add(1).ok_or(Error::IdError)?;
 
-                        stream::Type::QpackDecoder => {
-                            // Only one qpack decoder allowed.
-                            if self.peer_qpack_streams.decoder_stream_id.is_some()
-                            {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Received multiple QPACK decoder streams",
-                                )?;
+        self.promised_push_ids.insert(push_id);
 
-                                return Err(Error::StreamCreationError);
-                            }
+        Ok(push_id)
+    }
 
-                            close_conn_if_critical_stream_finished(
-                                conn, stream_id,
-                            )?;
+    /// Sends the response of a promised push on a new push stream.
+    ///
+    /// The response is encoded from the provided list of headers. To include
+    /// a body, set `fin` as `false` and subsequently call [`send_body()`] with
+    /// the returned push stream ID.
+    ///
+    /// On success the push stream ID is returned.
+    ///
+    /// The [`IdError`] error is returned when `push_id` was not promised using
+    /// [`send_push_promise()`], or when its response was already sent or
+    /// cancelled.
+    ///
+    /// The [`FrameUnexpected`] error is returned when this method is called by
+    /// a client.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    /// [`send_push_promise()`]: struct.Connection.html#method.send_push_promise
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_push_response<T: NameValue, F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, push_id: u64, headers: &[T],
+        fin: bool,
+    ) -> Result<u64> {
+        if !self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
 
-                            self.peer_qpack_streams.decoder_stream_id =
-                                Some(stream_id);
-                        },
+        if !self.promised_push_ids.contains(&push_id) {
+            return Err(Error::IdError);
+        }
 
-                        stream::Type::Unknown => {
-                            // Unknown stream types are ignored.
-                            // TODO: we MAY send STOP_SENDING
-                        },
+        // The push stream might have been opened by a previous attempt that
+        // couldn't send the headers.
+        let stream_id = match self.push_stream_id(push_id) {
+            Some(v) => v,
 
-                        stream::Type::Request => unreachable!(),
-                    }
-                },
+            None => self.open_push_stream(conn, push_id)?,
+        };
 
-                stream::State::PushId => {
-                    stream.try_fill_buffer(conn)?;
+        self.send_headers(conn, stream_id, headers, fin)?;
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+        self.promised_push_ids.remove(&push_id);
 
-                        Err(_) => continue,
-                    };
+        Ok(stream_id)
+    }
 
-                    if let Err(e) = stream.set_push_id(varint) {
-                        conn.close(true, e.to_wire(), b"")?;
-                        return Err(e);
-                    }
-                },
+    /// Cancels a server push.
+    ///
+    /// When called by a client, this indicates that it doesn't want to receive
+    /// the promised response. When called by a server, this indicates that
+    /// the promised response won't be sent. In both cases a CANCEL_PUSH frame
+    /// is sent to the peer, and the push stream is aborted if it was already
+    /// opened.
+    ///
+    /// The [`IdError`] error is returned when `push_id` was not promised by a
+    /// server, or is beyond the MAX_PUSH_ID limit sent by a client.
+    ///
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn cancel_push<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, push_id: u64,
+    ) -> Result<()> {
+        let valid = if self.is_server {
+            push_id < self.next_push_id
+        } else {
+            self.max_push_id.is_some_and(|max| push_id <= max)
+        };
 
-                stream::State::FrameType => {
-                    stream.try_fill_buffer(conn)?;
+        if !valid {
+            return Err(Error::IdError);
+        }
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+        self.send_control_frame(
+            conn,
+            frame::Frame::CancelPush { push_id },
+            octets::varint_len(push_id),
+        )?;
 
-                        Err(_) => continue,
-                    };
+        self.abort_push(conn, push_id);
 
-                    match stream.set_frame_type(varint) {
-                        Err(Error::FrameUnexpected) => {
-                            let msg = format!("Unexpected frame type {varint}");
+        Ok(())
+    }
 
-                            conn.close(
-                                true,
-                                Error::FrameUnexpected.to_wire(),
-                                msg.as_bytes(),
-                            )?;
+    /// Returns the push ID of the specified push stream.
+    ///
+    /// Clients can use this to associate a push stream, for example the one
+    /// a [`Headers`] event was returned for, with the corresponding
+    /// [`PushPromise`] event.
+    ///
+    /// [`Headers`]: enum.Event.html#variant.Headers
+    /// [`PushPromise`]: enum.Event.html#variant.PushPromise
+    pub fn push_id(&self, stream_id: u64) -> Option<u64> {
+        self.streams.get(&stream_id).and_then(|s| s.push_id())
+    }
 
-                            return Err(Error::FrameUnexpected);
-                        },
+    /// Returns true if the request on the specified stream was received in
+    /// early data (0-RTT).
+    ///
+    /// Early data can be replayed, so servers should only process requests
+    /// that are safe to repeat, such as `GET` requests without side effects,
+    /// and respond to others with a `425 Too Early` status code, asking the
+    /// client to retry the request after the handshake ([RFC 8470]).
+    ///
+    /// [RFC 8470]: https://www.rfc-editor.org/rfc/rfc8470
+    pub fn is_early_data_request(&self, stream_id: u64) -> bool {
+        self.streams
+            .get(&stream_id)
+            .is_some_and(|s| s.is_early_data())
+    }
 
-                        Err(e) => {
-                            conn.close(
-                                true,
-                                e.to_wire(),
-                                b"Error handling frame.",
-                            )?;
+    /// Gets the raw settings from peer including unknown and reserved types.
+    ///
+    /// The order of settings is the same as received in the SETTINGS frame.
+    pub fn peer_settings_raw(&self) -> Option<&[(u64, u64)]> {
+        self.peer_settings.raw.as_deref()
+    }
 
-                            return Err(e);
-                        },
+    fn open_uni_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, ty: u64,
+    ) -> Result<u64> {
+        let stream_id = self.next_uni_stream_id;
 
-                        _ => (),
-                    }
-                },
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                stream::State::FramePayloadLen => {
-                    stream.try_fill_buffer(conn)?;
+        match ty {
+            // Control and QPACK streams are the most important to schedule.
+            stream::HTTP3_CONTROL_STREAM_TYPE_ID |
+            stream::QPACK_ENCODER_STREAM_TYPE_ID |
+            stream::QPACK_DECODER_STREAM_TYPE_ID => {
+                conn.stream_priority(stream_id, 0, false)?;
+            },
 
-                    let payload_len = match stream.try_consume_varint() {
-                        Ok(v) => v,
+            // Push streams use the default priority, like request streams.
+            stream::HTTP3_PUSH_STREAM_TYPE_ID => (),
 
-                        Err(_) => continue,
-                    };
+            // Anything else is a GREASE stream, so make it the least important.
+            _ => {
+                conn.stream_priority(stream_id, 255, false)?;
+            },
+        }
 
-                    // DATA frames are handled uniquely. After this point we lose
-                    // visibility of DATA framing, so just log here.
-                    if Some(frame::DATA_FRAME_TYPE_ID) == stream.frame_type() {
-                        trace!(
-                            "{} rx frm DATA stream={} wire_payload_len={}",
-                            conn.trace_id(),
-                            stream_id,
-                            payload_len
-                        );
+        conn.stream_send(stream_id, b.put_varint(ty)?, false)?;
 
-                        qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
-                            let frame = Http3Frame::Data { raw: None };
+        // To avoid skipping stream IDs, we only calculate the next available
+        // stream ID when data has been successfully buffered.
+        self.next_uni_stream_id = self
+            .next_uni_stream_id
+            .checked_add(4)
+            .ok_or(Error::IdError)?;
 
-                            let ev_data =
-                                EventData::H3FrameParsed(H3FrameParsed {
-                                    stream_id,
-                                    length: Some(payload_len),
-                                    frame,
-                                    ..Default::default()
-                                });
+        Ok(stream_id)
+    }
 
-                            q.add_event_data_now(ev_data).ok();
-                        });
-                    }
+    fn open_push_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, push_id: u64,
+    ) -> Result<u64> {
+        let stream_id =
+            self.open_uni_stream(conn, stream::HTTP3_PUSH_STREAM_TYPE_ID)?;
 
-                    if let Err(e) = stream.set_frame_payload_len(payload_len) {
-                        conn.close(true, e.to_wire(), b"")?;
-                        return Err(e);
-                    }
-                },
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                stream::State::FramePayload => {
-                    // Do not emit events when not polling.
-                    if !polling {
-                        break;
-                    }
+        conn.stream_send(stream_id, b.put_varint(push_id)?, false)?;
 
-                    stream.try_fill_buffer(conn)?;
+        let mut stream = <stream::Stream>::new(stream_id, true);
+        stream.set_ty(stream::Type::Push)?;
+        stream.set_push_id(push_id)?;
 
-                    let (frame, payload_len) = match stream.try_consume_frame() {
-                        Ok(frame) => frame,
+        self.streams.insert(stream_id, stream);
+        self.push_streams.insert(push_id, stream_id);
 
-                        Err(Error::Done) => return Err(Error::Done),
+        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                stream_id,
+                owner: Some(H3Owner::Local),
+                stream_type: H3StreamType::Push,
+                associated_push_id: Some(push_id),
+                ..Default::default()
+            });
 
-                        Err(e) => {
-                            conn.close(
-                                true,
-                                e.to_wire(),
-                                b"Error handling frame.",
-                            )?;
+            q.add_event_data_now(ev_data).ok();
+        });
 
-                            return Err(e);
-                        },
-                    };
+        Ok(stream_id)
+    }
 
-                    match self.process_frame(conn, stream_id, frame, payload_len)
-                    {
-                        Ok(ev) => return Ok(ev),
+    fn is_push_stream(&self, stream_id: u64) -> bool {
+        self.streams
+            .get(&stream_id)
+            .is_some_and(|s| s.ty() == Some(stream::Type::Push))
+    }
 
-                        Err(Error::Done) => {
-                            // This might be a frame that is processed internally
-                            // without needing to bubble up to the user as an
-                            // event. Check whether the frame has FIN'd by QUIC
-                            // to prevent trying to read again on a closed stream.
-                            if conn.stream_finished(stream_id) {
-                                break;
-                            }
-                        },
+    fn is_webtransport_stream(&self, stream_id: u64) -> bool {
+        self.streams
+            .get(&stream_id)
+            .is_some_and(|s| s.ty() == Some(stream::Type::WebTransport))
+    }
 
-                        Err(e) => return Err(e),
-                    };
-                },
+    fn push_stream_id(&self, push_id: u64) -> Option<u64> {
+        self.push_streams.get(&push_id).copied()
+    }
 
-                stream::State::Data => {
-                    // Do not emit events when not polling.
-                    if !polling {
-                        break;
-                    }
+    /// Drops the state of a stream that is no longer needed.
+    fn remove_stream(&mut self, stream_id: u64) {
+        self.webtransport_sessions.remove(&stream_id);
 
-                    if !stream.try_trigger_data_event() {
-                        break;
-                    }
+        if let Some(push_id) =
+            self.streams.remove(&stream_id).and_then(|s| s.push_id())
+        {
+            self.push_streams.remove(&push_id);
+        }
+    }
 
-                    return Ok((stream_id, Event::Data));
-                },
-
-                stream::State::QpackInstruction => {
-                    let mut d = [0; 4096];
-
-                    // Read data from the stream and discard immediately.
-                    loop {
-                        let (recv, fin) = conn.stream_recv(stream_id, &mut d)?;
+    /// Stops sending (server) or receiving (client) the push stream of the
+    /// specified push, if any.
+    fn abort_push<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, push_id: u64,
+    ) {
+        if self.is_server {
+            self.promised_push_ids.remove(&push_id);
+        }
 
-                        match stream.ty() {
-                            Some(stream::Type::QpackEncoder) =>
-                                self.peer_qpack_streams.encoder_stream_bytes +=
-                                    recv as u64,
-                            Some(stream::Type::QpackDecoder) =>
-                                self.peer_qpack_streams.decoder_stream_bytes +=
-                                    recv as u64,
-                            _ => unreachable!(),
-                        };
+        match self.push_stream_id(push_id) {
+            Some(stream_id) => {
+                let direction = if self.is_server {
+                    crate::Shutdown::Write
+                } else {
+                    crate::Shutdown::Read
+                };
 
-                        if fin {
-                            close_conn_critical_stream(conn)?;
-                        }
-                    }
-                },
+                conn.stream_shutdown(
+                    stream_id,
+                    direction,
+                    Error::RequestCancelled.to_wire(),
+                )
+                .ok();
 
-                stream::State::Drain => {
-                    // Discard incoming data on the stream.
-                    conn.stream_shutdown(
-                        stream_id,
-                        crate::Shutdown::Read,
-                        0x100,
-                    )?;
+                if self.is_server {
+                    self.remove_stream(stream_id);
+                }
+            },
 
-                    break;
-                },
+            // The push stream might still arrive, in which case it will be
+            // discarded.
+            None if !self.is_server => {
+                self.cancelled_push_ids.insert(push_id);
+            },
 
-                stream::State::Finished => break,
-            }
+            None => (),
         }
-
-        Err(Error::Done)
     }
 
-    fn process_finished_stream(&mut self, stream_id: u64) {
-        let stream = match self.streams.get_mut(&stream_id) {
-            Some(v) => v,
-
-            None => return,
-        };
-
-        if stream.state() == stream::State::Finished {
-            return;
-        }
+    /// Sends a frame with the given payload length on the control stream.
+    fn send_control_frame<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, frame: frame::Frame,
+        payload_len: usize,
+    ) -> Result<()> {
+        let stream_id = self.control_stream_id.ok_or(Error::InternalError)?;
 
-        match stream.ty() {
-            Some(stream::Type::Request) | Some(stream::Type::Push) => {
-                stream.finished();
+        let mut d = [42; 10];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                self.finished_streams.push_back(stream_id);
-            },
+        let wire_len = frame.to_bytes(&mut b)?;
+        let stream_cap = conn.stream_capacity(stream_id)?;
 
-            _ => (),
-        };
-    }
+        if stream_cap < wire_len {
+            return Err(Error::StreamBlocked);
+        }
 
-    fn process_frame<F: BufFactory>(
-        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
-        frame: frame::Frame, payload_len: u64,
-    ) -> Result<(u64, Event)> {
         trace!(
-            "{} rx frm {:?} stream={} payload_len={}",
+            "{} tx frm {:?} stream={} len={}",
             conn.trace_id(),
             frame,
             stream_id,
             payload_len
         );
 
-        qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
-            // HEADERS frames are special case and will be logged below.
-            if !matches!(frame, frame::Frame::Headers { .. }) {
-                let frame = frame.to_qlog();
-                let ev_data = EventData::H3FrameParsed(H3FrameParsed {
-                    stream_id,
-                    length: Some(payload_len),
-                    frame,
-                    ..Default::default()
-                });
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: Some(payload_len as u64),
+                frame: frame.to_qlog(),
+                ..Default::default()
+            });
 
-                q.add_event_data_now(ev_data).ok();
-            }
+            q.add_event_data_now(ev_data).ok();
         });
 
-        match frame {
-            frame::Frame::Settings {
-                max_field_section_size,
-                qpack_max_table_capacity,
-                qpack_blocked_streams,
-                connect_protocol_enabled,
-                h3_datagram,
-                additional_settings,
-                raw,
-                ..
-            } => {
-                self.peer_settings = ConnectionSettings {
-                    max_field_section_size,
-                    qpack_max_table_capacity,
-                    qpack_blocked_streams,
-                    connect_protocol_enabled,
-                    h3_datagram,
-                    additional_settings,
-                    raw,
-                };
+        let off = b.off();
+        conn.stream_send(stream_id, &d[..off], false)?;
 
-                if let Some(1) = h3_datagram {
-                    // The peer MUST have also enabled DATAGRAM with a TP
-                    if conn.dgram_max_writable_len().is_none() {
-                        conn.close(
-                            true,
-                            Error::SettingsError.to_wire(),
-                            b"H3_DATAGRAM sent with value 1 but max_datagram_frame_size TP not set.",
-                        )?;
+        Ok(())
+    }
 
-                        return Err(Error::SettingsError);
-                    }
-                }
-            },
+    fn open_qpack_encoder_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<()> {
+        let stream_id =
+            self.open_uni_stream(conn, stream::QPACK_ENCODER_STREAM_TYPE_ID)?;
 
-            frame::Frame::Headers { header_block } => {
-                // Servers reject too many HEADERS frames.
-                if let Some(s) = self.streams.get_mut(&stream_id) {
-                    if self.is_server && s.headers_received_count() == 2 {
-                        conn.close(
-                            true,
-                            Error::FrameUnexpected.to_wire(),
-                            b"Too many HEADERS frames",
-                        )?;
-                        return Err(Error::FrameUnexpected);
-                    }
+        self.local_qpack_streams.encoder_stream_id = Some(stream_id);
 
-                    s.increment_headers_received();
-                }
+        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                stream_id,
+                owner: Some(H3Owner::Local),
+                stream_type: H3StreamType::QpackEncode,
+                ..Default::default()
+            });
 
-                // Use "infinite" as default value for max_field_section_size if
-                // it is not configured by the application.
-                let max_size = self
-                    .local_settings
-                    .max_field_section_size
-                    .unwrap_or(u64::MAX);
+            q.add_event_data_now(ev_data).ok();
+        });
 
-                let headers = match self
-                    .qpack_decoder
-                    .decode(&header_block[..], max_size)
-                {
-                    Ok(v) => v,
+        Ok(())
+    }
 
-                    Err(e) => {
-                        let e = match e {
-                            qpack::Error::HeaderListTooLarge =>
-                                Error::ExcessiveLoad,
+    fn open_qpack_decoder_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<()> {
+        let stream_id =
+            self.open_uni_stream(conn, stream::QPACK_DECODER_STREAM_TYPE_ID)?;
 
-                            _ => Error::QpackDecompressionFailed,
-                        };
+        self.local_qpack_streams.decoder_stream_id = Some(stream_id);
 
-                        conn.close(true, e.to_wire(), b"Error parsing headers.")?;
+        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                stream_id,
+                owner: Some(H3Owner::Local),
+                stream_type: H3StreamType::QpackDecode,
+                ..Default::default()
+            });
 
-                        return Err(e);
-                    },
-                };
+            q.add_event_data_now(ev_data).ok();
+        });
 
-                qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
-                    let qlog_headers = headers
-                        .iter()
-                        .map(|h| qlog::events::h3::HttpHeader {
-                            name: String::from_utf8_lossy(h.name()).into_owned(),
-                            value: String::from_utf8_lossy(h.value())
-                                .into_owned(),
-                        })
-                        .collect();
-
-                    let frame = Http3Frame::Headers {
-                        headers: qlog_headers,
-                    };
+        Ok(())
+    }
 
-                    let ev_data = EventData::H3FrameParsed(H3FrameParsed {
-                        stream_id,
-                        length: Some(payload_len),
-                        frame,
-                        ..Default::default()
-                    });
+    /// Sends pending QPACK encoder instructions on the local encoder stream.
+    fn flush_qpack_encoder_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<()> {
+        let stream_id = match self.local_qpack_streams.encoder_stream_id {
+            Some(v) => v,
 
-                    q.add_event_data_now(ev_data).ok();
-                });
+            None => return Ok(()),
+        };
 
-                let more_frames = !conn.stream_finished(stream_id);
+        if self.qpack_encoder.instructions().is_empty() {
+            return Ok(());
+        }
 
-                return Ok((stream_id, Event::Headers {
-                    list: headers,
-                    more_frames,
-                }));
-            },
+        let written = match conn.stream_send(
+            stream_id,
+            self.qpack_encoder.instructions(),
+            false,
+        ) {
+            Ok(v) => v,
 
-            frame::Frame::Data { .. } => {
-                // Do nothing. The Data event is returned separately.
-            },
+            Err(crate::Error::Done) => 0,
 
-            frame::Frame::GoAway { id } => {
-                if !self.is_server && id % 4 != 0 {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"GOAWAY received with ID of non-request stream",
-                    )?;
+            Err(e) => return Err(e.into()),
+        };
 
-                    return Err(Error::IdError);
-                }
+        trace!(
+            "{} tx QPACK encoder instructions stream={} len={}",
+            conn.trace_id(),
+            stream_id,
+            written
+        );
 
-                if let Some(received_id) = self.peer_goaway_id {
-                    if id > received_id {
-                        conn.close(
-                            true,
-                            Error::IdError.to_wire(),
-                            b"GOAWAY received with ID larger than previously received",
-                        )?;
+        self.qpack_encoder.consume_instructions(written);
 
-                        return Err(Error::IdError);
-                    }
-                }
+        Ok(())
+    }
 
-                self.peer_goaway_id = Some(id);
+    /// Sends pending QPACK decoder instructions on the local decoder stream.
+    fn flush_qpack_decoder_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<()> {
+        let stream_id = match self.local_qpack_streams.decoder_stream_id {
+            Some(v) => v,
 
-                return Ok((id, Event::GoAway));
-            },
+            None => return Ok(()),
+        };
 
-            frame::Frame::MaxPushId { push_id } => {
-                if !self.is_server {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"MAX_PUSH_ID received by client",
-                    )?;
+        if self.qpack_decoder.instructions().is_empty() {
+            return Ok(());
+        }
 
-                    return Err(Error::FrameUnexpected);
-                }
+        let written = match conn.stream_send(
+            stream_id,
+            self.qpack_decoder.instructions(),
+            false,
+        ) {
+            Ok(v) => v,
 
-                if push_id < self.max_push_id {
-                    conn.close(
-                        true,
-                        Error::IdError.to_wire(),
-                        b"MAX_PUSH_ID reduced limit",
-                    )?;
+            Err(crate::Error::Done) => 0,
 
-                    return Err(Error::IdError);
-                }
+            Err(e) => return Err(e.into()),
+        };
 
-                self.max_push_id = push_id;
-            },
+        trace!(
+            "{} tx QPACK decoder instructions stream={} len={}",
+            conn.trace_id(),
+            stream_id,
+            written
+        );
 
-            frame::Frame::PushPromise { .. } => {
-                if self.is_server {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PUSH_PROMISE received by server",
-                    )?;
+        self.qpack_decoder.consume_instructions(written);
 
-                    return Err(Error::FrameUnexpected);
-                }
+        Ok(())
+    }
 
-                if stream_id % 4 != 0 {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PUSH_PROMISE received on non-request stream",
-                    )?;
+    /// Send GREASE frames on the provided stream ID.
+    fn send_grease_frames<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+    ) -> Result<()> {
+        let mut d = [0; 8];
 
-                    return Err(Error::FrameUnexpected);
+        let stream_cap = match conn.stream_capacity(stream_id) {
+            Ok(v) => v,
+
+            Err(e) => {
+                if conn.stream_finished(stream_id) {
+                    self.remove_stream(stream_id);
                 }
 
-                // TODO: implement more checks and PUSH_PROMISE event
+                return Err(e.into());
             },
+        };
 
-            frame::Frame::CancelPush { .. } => {
-                // TODO: implement CANCEL_PUSH frame
-            },
+        let grease_frame1 = grease_value();
+        let grease_frame2 = grease_value();
+        let grease_payload = b"GREASE is the word";
 
-            frame::Frame::PriorityUpdateRequest {
-                prioritized_element_id,
-                priority_field_value,
-            } => {
-                if !self.is_server {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PRIORITY_UPDATE received by client",
-                    )?;
+        let overhead = octets::varint_len(grease_frame1) + // frame type
+            1 + // payload len
+            octets::varint_len(grease_frame2) + // frame type
+            1 + // payload len
+            grease_payload.len(); // payload
 
-                    return Err(Error::FrameUnexpected);
-                }
+        // Don't send GREASE if there is not enough capacity for it. Greasing
+        // will _not_ be attempted again later on.
+        if stream_cap < overhead {
+            return Ok(());
+        }
 
-                if prioritized_element_id % 4 != 0 {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PRIORITY_UPDATE for request stream type with wrong ID",
-                    )?;
+        // Empty GREASE frame.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(grease_frame1)?, false)?;
 
-                    return Err(Error::FrameUnexpected);
-                }
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(0)?, false)?;
 
-                if prioritized_element_id > conn.streams.max_streams_bidi() * 4 {
-                    conn.close(
-                        true,
-                        Error::IdError.to_wire(),
-                        b"PRIORITY_UPDATE for request stream beyond max streams limit",
-                    )?;
+        trace!(
+            "{} tx frm GREASE stream={} len=0",
+            conn.trace_id(),
+            stream_id
+        );
 
-                    return Err(Error::IdError);
-                }
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let frame = Http3Frame::Reserved { length: Some(0) };
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: Some(0),
+                frame,
+                ..Default::default()
+            });
 
-                // If the PRIORITY_UPDATE is valid, consider storing the latest
-                // contents. Due to reordering, it is possible that we might
-                // receive frames that reference streams that have not yet to
-                // been opened and that's OK because it's within our concurrency
-                // limit. However, we discard PRIORITY_UPDATE that refers to
-                // streams that we know have been collected.
-                if conn.streams.is_collected(prioritized_element_id) {
-                    return Err(Error::Done);
-                }
+            q.add_event_data_now(ev_data).ok();
+        });
 
-                // If the stream did not yet exist, create it and store.
-                let stream =
-                    self.streams.entry(prioritized_element_id).or_insert_with(
-                        || <stream::Stream>::new(prioritized_element_id, false),
-                    );
+        // GREASE frame with payload.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(grease_frame2)?, false)?;
 
-                let had_priority_update = stream.has_last_priority_update();
-                stream.set_last_priority_update(Some(priority_field_value));
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(18)?, false)?;
 
-                // Only trigger the event when there wasn't already a stored
-                // PRIORITY_UPDATE.
-                if !had_priority_update {
-                    return Ok((prioritized_element_id, Event::PriorityUpdate));
-                } else {
-                    return Err(Error::Done);
-                }
-            },
+        conn.stream_send(stream_id, grease_payload, false)?;
 
-            frame::Frame::PriorityUpdatePush {
-                prioritized_element_id,
-                ..
-            } => {
-                if !self.is_server {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PRIORITY_UPDATE received by client",
-                    )?;
+        trace!(
+            "{} tx frm GREASE stream={} len={}",
+            conn.trace_id(),
+            stream_id,
+            grease_payload.len()
+        );
 
-                    return Err(Error::FrameUnexpected);
-                }
+        qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+            let frame = Http3Frame::Reserved {
+                length: Some(grease_payload.len() as u64),
+            };
+            let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                stream_id,
+                length: Some(grease_payload.len() as u64),
+                frame,
+                ..Default::default()
+            });
 
-                if prioritized_element_id % 3 != 0 {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PRIORITY_UPDATE for push stream type with wrong ID",
-                    )?;
+            q.add_event_data_now(ev_data).ok();
+        });
 
-                    return Err(Error::FrameUnexpected);
-                }
+        Ok(())
+    }
 
-                // TODO: we only implement this if we implement server push
-            },
+    /// Opens a new unidirectional stream with a GREASE type and sends some
+    /// unframed payload.
+    fn open_grease_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<()> {
+        let ty = grease_value();
+        match self.open_uni_stream(conn, ty) {
+            Ok(stream_id) => {
+                conn.stream_send(stream_id, b"GREASE is the word", true)?;
 
-            frame::Frame::Unknown { .. } => (),
-        }
+                trace!("{} open GREASE stream {}", conn.trace_id(), stream_id);
 
-        Err(Error::Done)
-    }
+                qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+                    let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                        stream_id,
+                        owner: Some(H3Owner::Local),
+                        stream_type: H3StreamType::Unknown,
+                        stream_type_value: Some(ty),
+                        ..Default::default()
+                    });
 
-    /// Collects and returns statistics about the connection.
-    #[inline]
-    pub fn stats(&self) -> Stats {
-        Stats {
-            qpack_encoder_stream_recv_bytes: self
-                .peer_qpack_streams
-                .encoder_stream_bytes,
-            qpack_decoder_stream_recv_bytes: self
-                .peer_qpack_streams
-                .decoder_stream_bytes,
-        }
-    }
-}
+                    q.add_event_data_now(ev_data).ok();
+                });
+            },
 
-/// Generates an HTTP/3 GREASE variable length integer.
-pub fn grease_value() -> u64 {
-    let n = super::rand::rand_u64_uniform(148_764_065_110_560_899);
-    31 * n + 33
-}
+            Err(Error::IdError) => {
+                trace!("{} GREASE stream blocked", conn.trace_id(),);
 
-#[doc(hidden)]
-pub mod testing {
-    use super::*;
+                return Ok(());
+            },
 
-    use crate::test_utils;
+            Err(e) => return Err(e),
+        };
 
-    /// Session is an HTTP/3 test helper structure. It holds a client, server
-    /// and pipe that allows them to communicate.
-    ///
-    /// `default()` creates a session with some sensible default
-    /// configuration. `with_configs()` allows for providing a specific
-    /// configuration.
-    ///
-    /// `handshake()` performs all the steps needed to establish an HTTP/3
-    /// connection.
-    ///
-    /// Some utility functions are provided that make it less verbose to send
-    /// request, responses and individual headers. The full quiche API remains
-    /// available for any test that need to do unconventional things (such as
-    /// bad behaviour that triggers errors).
-    pub struct Session {
-        pub pipe: test_utils::Pipe,
-        pub client: Connection,
-        pub server: Connection,
+        Ok(())
     }
 
-    impl Session {
-        pub fn new() -> Result<Session> {
-            fn path_relative_to_manifest_dir(path: &str) -> String {
-                std::fs::canonicalize(
-                    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(path),
-                )
-                .unwrap()
-                .to_string_lossy()
-                .into_owned()
-            }
+    /// Sends SETTINGS frame based on HTTP/3 configuration.
+    fn send_settings<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<()> {
+        let stream_id = match self
+            .open_uni_stream(conn, stream::HTTP3_CONTROL_STREAM_TYPE_ID)
+        {
+            Ok(v) => v,
 
-            let mut config = crate::Config::new(crate::PROTOCOL_VERSION)?;
-            config.load_cert_chain_from_pem_file(
-                &path_relative_to_manifest_dir("examples/cert.crt"),
-            )?;
-            config.load_priv_key_from_pem_file(
-                &path_relative_to_manifest_dir("examples/cert.key"),
-            )?;
-            config.set_application_protos(&[b"h3"])?;
-            config.set_initial_max_data(1500);
-            config.set_initial_max_stream_data_bidi_local(150);
-            config.set_initial_max_stream_data_bidi_remote(150);
-            config.set_initial_max_stream_data_uni(150);
-            config.set_initial_max_streams_bidi(5);
-            config.set_initial_max_streams_uni(5);
-            config.verify_peer(false);
-            config.enable_dgram(true, 3, 3);
-            config.set_ack_delay_exponent(8);
+            Err(e) => {
+                trace!("{} Control stream blocked", conn.trace_id(),);
 
-            let h3_config = Config::new()?;
-            Session::with_configs(&mut config, &h3_config)
+                if e == Error::Done {
+                    return Err(Error::InternalError);
+                }
+
+                return Err(e);
+            },
+        };
+
+        self.control_stream_id = Some(stream_id);
+
+        qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+            let ev_data = EventData::H3StreamTypeSet(H3StreamTypeSet {
+                stream_id,
+                owner: Some(H3Owner::Local),
+                stream_type: H3StreamType::Control,
+                ..Default::default()
+            });
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        let grease = if conn.grease {
+            Some((grease_value(), grease_value()))
+        } else {
+            None
+        };
+
+        // WebTransport support is advertised along with any setting configured
+        // by the application.
+        let mut additional_settings =
+            self.local_settings.additional_settings.clone();
+
+        if let Some(v) = self.local_settings.wt_max_sessions {
+            additional_settings
+                .get_or_insert_with(Vec::new)
+                .push((frame::SETTINGS_WT_MAX_SESSIONS, v));
         }
 
-        pub fn with_configs(
-            config: &mut crate::Config, h3_config: &Config,
-        ) -> Result<Session> {
-            let pipe = test_utils::Pipe::with_config(config)?;
-            let client_dgram = pipe.client.dgram_enabled();
-            let server_dgram = pipe.server.dgram_enabled();
-            Ok(Session {
-                pipe,
-                client: Connection::new(h3_config, false, client_dgram)?,
-                server: Connection::new(h3_config, true, server_dgram)?,
-            })
+        let frame = frame::Frame::Settings {
+            max_field_section_size: self.local_settings.max_field_section_size,
+            qpack_max_table_capacity: self
+                .local_settings
+                .qpack_max_table_capacity,
+            qpack_blocked_streams: self.local_settings.qpack_blocked_streams,
+            connect_protocol_enabled: self
+                .local_settings
+                .connect_protocol_enabled,
+            h3_datagram: self.local_settings.h3_datagram,
+            grease,
+            additional_settings,
+            raw: Default::default(),
+        };
+
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        frame.to_bytes(&mut b)?;
+
+        let off = b.off();
+
+        if let Some(id) = self.control_stream_id {
+            conn.stream_send(id, &d[..off], false)?;
+
+            trace!(
+                "{} tx frm SETTINGS stream={} len={}",
+                conn.trace_id(),
+                id,
+                off
+            );
+
+            qlog_with_type!(QLOG_FRAME_CREATED, conn.qlog, q, {
+                let frame = frame.to_qlog();
+                let ev_data = EventData::H3FrameCreated(H3FrameCreated {
+                    stream_id: id,
+                    length: Some(off as u64),
+                    frame,
+                    ..Default::default()
+                });
+
+                q.add_event_data_now(ev_data).ok();
+            });
         }
 
-        /// Do the HTTP/3 handshake so both ends are in sane initial state.
-        pub fn handshake(&mut self) -> Result<()> {
-            self.pipe.handshake()?;
+        Ok(())
+    }
 
-            // Client streams.
-            self.client.send_settings(&mut self.pipe.client)?;
-            self.pipe.advance().ok();
+    fn process_control_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+    ) -> Result<(u64, Event)> {
+        close_conn_if_critical_stream_finished(conn, stream_id)?;
 
-            self.client
-                .open_qpack_encoder_stream(&mut self.pipe.client)?;
-            self.pipe.advance().ok();
+        if !conn.stream_readable(stream_id) {
+            return Err(Error::Done);
+        }
 
-            self.client
-                .open_qpack_decoder_stream(&mut self.pipe.client)?;
-            self.pipe.advance().ok();
+        match self.process_readable_stream(conn, stream_id, true) {
+            Ok(ev) => return Ok(ev),
 
-            if self.pipe.client.grease {
-                self.client.open_grease_stream(&mut self.pipe.client)?;
-            }
+            Err(Error::Done) => (),
 
-            self.pipe.advance().ok();
+            Err(e) => return Err(e),
+        };
 
-            // Server streams.
-            self.server.send_settings(&mut self.pipe.server)?;
-            self.pipe.advance().ok();
+        close_conn_if_critical_stream_finished(conn, stream_id)?;
 
-            self.server
-                .open_qpack_encoder_stream(&mut self.pipe.server)?;
-            self.pipe.advance().ok();
+        Err(Error::Done)
+    }
 
-            self.server
-                .open_qpack_decoder_stream(&mut self.pipe.server)?;
-            self.pipe.advance().ok();
+    fn process_readable_stream<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64, polling: bool,
+    ) -> Result<(u64, Event)> {
+        self.streams
+            .entry(stream_id)
+            .or_insert_with(|| <stream::Stream>::new(stream_id, false));
 
-            if self.pipe.server.grease {
-                self.server.open_grease_stream(&mut self.pipe.server)?;
-            }
+        // We need to get a fresh reference to the stream for each
+        // iteration, to avoid borrowing `self` for the entire duration
+        // of the loop, because we'll need to borrow it again in the
+        // `State::FramePayload` case below.
+        while let Some(stream) = self.streams.get_mut(&stream_id) {
+            match stream.state() {
+                stream::State::StreamType => {
+                    stream.try_fill_buffer(conn)?;
 
-            self.advance().ok();
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
 
-            while self.client.poll(&mut self.pipe.client).is_ok() {
-                // Do nothing.
-            }
+                        Err(_) => continue,
+                    };
 
-            while self.server.poll(&mut self.pipe.server).is_ok() {
-                // Do nothing.
-            }
+                    let ty = match stream::Type::deserialize(varint)? {
+                        // WebTransport streams are unknown stream types unless
+                        // support was enabled locally.
+                        stream::Type::WebTransport
+                            if self.local_settings.wt_max_sessions.is_none() =>
+                            stream::Type::Unknown,
 
-            Ok(())
-        }
+                        ty => ty,
+                    };
+
+                    if let Err(e) = stream.set_ty(ty) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+
+                    qlog_with_type!(QLOG_STREAM_TYPE_SET, conn.qlog, q, {
+                        let ty_val = if matches!(
+                            ty,
+                            stream::Type::WebTransport | stream::Type::Unknown
+                        ) {
+                            Some(varint)
+                        } else {
+                            None
+                        };
+
+                        let ev_data =
+                            EventData::H3StreamTypeSet(H3StreamTypeSet {
+                                stream_id,
+                                owner: Some(H3Owner::Remote),
+                                stream_type: ty.to_qlog(),
+                                stream_type_value: ty_val,
+                                ..Default::default()
+                            });
+
+                        q.add_event_data_now(ev_data).ok();
+                    });
+
+                    match &ty {
+                        stream::Type::Control => {
+                            // Only one control stream allowed.
+                            if self.peer_control_stream_id.is_some() {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Received multiple control streams",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+
+                            trace!(
+                                "{} open peer's control stream {}",
+                                conn.trace_id(),
+                                stream_id
+                            );
+
+                            close_conn_if_critical_stream_finished(
+                                conn, stream_id,
+                            )?;
+
+                            self.peer_control_stream_id = Some(stream_id);
+                        },
+
+                        stream::Type::Push => {
+                            // Only clients can receive push stream.
+                            if self.is_server {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Server received push stream.",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+                        },
+
+                        stream::Type::QpackEncoder => {
+                            // Only one qpack encoder stream allowed.
+                            if self.peer_qpack_streams.encoder_stream_id.is_some()
+                            {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Received multiple QPACK encoder streams",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+
+                            close_conn_if_critical_stream_finished(
+                                conn, stream_id,
+                            )?;
+
+                            self.peer_qpack_streams.encoder_stream_id =
+                                Some(stream_id);
+                        },
+
+                        stream::Type::QpackDecoder => {
+                            // Only one qpack decoder allowed.
+                            if self.peer_qpack_streams.decoder_stream_id.is_some()
+                            {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Received multiple QPACK decoder streams",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+
+                            close_conn_if_critical_stream_finished(
+                                conn, stream_id,
+                            )?;
+
+                            self.peer_qpack_streams.decoder_stream_id =
+                                Some(stream_id);
+                        },
+
+                        stream::Type::Unknown => {
+                            // Unknown stream types are ignored.
+                            // TODO: we MAY send STOP_SENDING
+                        },
+
+                        stream::Type::WebTransport => (),
+
+                        stream::Type::Request => unreachable!(),
+                    }
+                },
+
+                stream::State::PushId => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    if let Err(e) = stream.set_push_id(varint) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+
+                    if self.max_push_id.is_none_or(|max| varint > max) {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"Push stream received with push ID beyond limit",
+                        )?;
+
+                        return Err(Error::IdError);
+                    }
+
+                    if self.push_streams.contains_key(&varint) {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"Duplicate push stream",
+                        )?;
+
+                        return Err(Error::IdError);
+                    }
+
+                    self.push_streams.insert(varint, stream_id);
+
+                    // The push was cancelled before the push stream arrived,
+                    // so stop reading from it.
+                    if self.cancelled_push_ids.remove(&varint) {
+                        conn.stream_shutdown(
+                            stream_id,
+                            crate::Shutdown::Read,
+                            Error::RequestCancelled.to_wire(),
+                        )?;
+
+                        break;
+                    }
+                },
+
+                stream::State::SessionId => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    if let Err(e) = stream.set_session_id(varint) {
+                        conn.close(
+                            true,
+                            e.to_wire(),
+                            b"Invalid WebTransport session ID",
+                        )?;
+
+                        return Err(e);
+                    }
+
+                    if !self.webtransport_sessions.contains(&varint) {
+                        trace!(
+                            "{} reject WebTransport stream {} of unknown session {}",
+                            conn.trace_id(),
+                            stream_id,
+                            varint
+                        );
+
+                        conn.stream_shutdown(
+                            stream_id,
+                            crate::Shutdown::Read,
+                            WEBTRANSPORT_BUFFERED_STREAM_REJECTED,
+                        )
+                        .ok();
+
+                        if crate::stream::is_bidi(stream_id) {
+                            conn.stream_shutdown(
+                                stream_id,
+                                crate::Shutdown::Write,
+                                WEBTRANSPORT_BUFFERED_STREAM_REJECTED,
+                            )
+                            .ok();
+                        }
+
+                        self.remove_stream(stream_id);
+
+                        return Err(Error::Done);
+                    }
+
+                    // Bidirectional streams can also be used to send data
+                    // back to the peer.
+                    if crate::stream::is_bidi(stream_id) {
+                        stream.initialize_local();
+                    }
+
+                    trace!(
+                        "{} open peer's WebTransport stream {} session={}",
+                        conn.trace_id(),
+                        stream_id,
+                        varint
+                    );
+
+                    return Ok((stream_id, Event::WebTransportStream {
+                        session_id: varint,
+                    }));
+                },
+
+                stream::State::FrameType => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    // Bidirectional WebTransport streams are signalled in
+                    // place of the first frame.
+                    if varint == frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID &&
+                        self.local_settings.wt_max_sessions.is_some()
+                    {
+                        if let Err(e) = stream.set_webtransport() {
+                            conn.close(
+                                true,
+                                e.to_wire(),
+                                b"Unexpected WebTransport stream",
+                            )?;
+
+                            return Err(e);
+                        }
+
+                        continue;
+                    }
+
+                    match stream.set_frame_type(varint) {
+                        Err(Error::FrameUnexpected) => {
+                            let msg = format!("Unexpected frame type {varint}");
+
+                            conn.close(
+                                true,
+                                Error::FrameUnexpected.to_wire(),
+                                msg.as_bytes(),
+                            )?;
+
+                            return Err(Error::FrameUnexpected);
+                        },
+
+                        Err(e) => {
+                            conn.close(
+                                true,
+                                e.to_wire(),
+                                b"Error handling frame.",
+                            )?;
+
+                            return Err(e);
+                        },
+
+                        _ => (),
+                    }
+                },
+
+                stream::State::FramePayloadLen => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let payload_len = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    // DATA frames are handled uniquely. After this point we lose
+                    // visibility of DATA framing, so just log here.
+                    if Some(frame::DATA_FRAME_TYPE_ID) == stream.frame_type() {
+                        trace!(
+                            "{} rx frm DATA stream={} wire_payload_len={}",
+                            conn.trace_id(),
+                            stream_id,
+                            payload_len
+                        );
+
+                        qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
+                            let frame = Http3Frame::Data { raw: None };
+
+                            let ev_data =
+                                EventData::H3FrameParsed(H3FrameParsed {
+                                    stream_id,
+                                    length: Some(payload_len),
+                                    frame,
+                                    ..Default::default()
+                                });
+
+                            q.add_event_data_now(ev_data).ok();
+                        });
+                    }
+
+                    if let Err(e) = stream.set_frame_payload_len(payload_len) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+                },
+
+                stream::State::FramePayload => {
+                    // Do not emit events when not polling.
+                    if !polling {
+                        break;
+                    }
+
+                    stream.try_fill_buffer(conn)?;
+
+                    let (frame, payload_len) = match stream.try_consume_frame() {
+                        Ok(frame) => frame,
+
+                        Err(Error::Done) => return Err(Error::Done),
+
+                        Err(e) => {
+                            conn.close(
+                                true,
+                                e.to_wire(),
+                                b"Error handling frame.",
+                            )?;
+
+                            return Err(e);
+                        },
+                    };
+
+                    match self.process_frame(conn, stream_id, frame, payload_len)
+                    {
+                        Ok(ev) => return Ok(ev),
+
+                        Err(Error::Done) => {
+                            // This might be a frame that is processed internally
+                            // without needing to bubble up to the user as an
+                            // event. Check whether the frame has FIN'd by QUIC
+                            // to prevent trying to read again on a closed stream.
+                            if conn.stream_finished(stream_id) {
+                                break;
+                            }
+                        },
+
+                        Err(e) => return Err(e),
+                    };
+                },
+
+                stream::State::Data => {
+                    // Do not emit events when not polling.
+                    if !polling {
+                        break;
+                    }
+
+                    if !stream.try_trigger_data_event() {
+                        break;
+                    }
+
+                    return Ok((stream_id, Event::Data));
+                },
+
+                stream::State::QpackInstruction => {
+                    let mut d = [0; 4096];
+
+                    // Read data from the stream and process the instructions.
+                    loop {
+                        let (recv, fin) = conn.stream_recv(stream_id, &mut d)?;
+
+                        match stream.ty() {
+                            Some(stream::Type::QpackEncoder) => {
+                                self.peer_qpack_streams.encoder_stream_bytes +=
+                                    recv as u64;
+
+                                if self.qpack_decoder.control(&d[..recv]).is_err()
+                                {
+                                    let e = Error::QpackEncoderStreamError;
+
+                                    conn.close(
+                                        true,
+                                        e.to_wire(),
+                                        b"Error processing QPACK encoder stream.",
+                                    )?;
+
+                                    return Err(e);
+                                }
+                            },
+
+                            Some(stream::Type::QpackDecoder) => {
+                                self.peer_qpack_streams.decoder_stream_bytes +=
+                                    recv as u64;
+
+                                if self.qpack_encoder.control(&d[..recv]).is_err()
+                                {
+                                    let e = Error::QpackDecoderStreamError;
+
+                                    conn.close(
+                                        true,
+                                        e.to_wire(),
+                                        b"Error processing QPACK decoder stream.",
+                                    )?;
+
+                                    return Err(e);
+                                }
+                            },
+
+                            _ => unreachable!(),
+                        };
+
+                        if fin {
+                            close_conn_critical_stream(conn)?;
+                        }
+                    }
+                },
+
+                stream::State::QpackBlocked => break,
+
+                stream::State::Drain => {
+                    // Discard incoming data on the stream.
+                    conn.stream_shutdown(
+                        stream_id,
+                        crate::Shutdown::Read,
+                        0x100,
+                    )?;
+
+                    break;
+                },
+
+                stream::State::Finished => break,
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Decodes a HEADERS or PUSH_PROMISE frame's header block, and returns the
+    /// corresponding event.
+    ///
+    /// The `push_id` parameter is only set for PUSH_PROMISE frames.
+    ///
+    /// If the header block can't be decoded yet because it depends on QPACK
+    /// encoder instructions that haven't been received, the stream is marked
+    /// as blocked and [`Done`] is returned.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    fn process_header_block<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+        header_block: Vec<u8>, payload_len: u64, push_id: Option<u64>,
+    ) -> Result<(u64, Event)> {
+        // Use "infinite" as default value for max_field_section_size if
+        // it is not configured by the application.
+        let max_size = self
+            .local_settings
+            .max_field_section_size
+            .unwrap_or(u64::MAX);
+
+        let headers = match self.qpack_decoder.decode_field_section(
+            stream_id,
+            &header_block[..],
+            max_size,
+        ) {
+            Ok(v) => v,
+
+            Err(qpack::Error::Blocked) => {
+                trace!(
+                    "{} stream {} blocked on QPACK encoder stream",
+                    conn.trace_id(),
+                    stream_id
+                );
+
+                if let Some(s) = self.streams.get_mut(&stream_id) {
+                    s.set_qpack_blocked(header_block, payload_len, push_id);
+                }
+
+                self.qpack_blocked_streams.push_back(stream_id);
+
+                return Err(Error::Done);
+            },
+
+            Err(e) => {
+                let e = match e {
+                    qpack::Error::HeaderListTooLarge => Error::ExcessiveLoad,
+
+                    _ => Error::QpackDecompressionFailed,
+                };
+
+                conn.close(true, e.to_wire(), b"Error parsing headers.")?;
+
+                return Err(e);
+            },
+        };
+
+        // Acknowledge the header block, if needed.
+        self.flush_qpack_decoder_stream(conn)?;
+
+        if let Some(push_id) = push_id {
+            return Ok((stream_id, Event::PushPromise {
+                push_id,
+                list: headers,
+            }));
+        }
+
+        if self.is_server && is_webtransport_connect(&headers) {
+            if let Some(max) = self.local_settings.wt_max_sessions {
+                // Reject sessions beyond the limit we advertised.
+                if self.webtransport_sessions.len() as u64 >= max {
+                    trace!(
+                        "{} reject WebTransport session {}",
+                        conn.trace_id(),
+                        stream_id
+                    );
+
+                    for direction in
+                        [crate::Shutdown::Read, crate::Shutdown::Write]
+                    {
+                        conn.stream_shutdown(
+                            stream_id,
+                            direction,
+                            Error::RequestRejected.to_wire(),
+                        )
+                        .ok();
+                    }
+
+                    self.remove_stream(stream_id);
+
+                    return Err(Error::Done);
+                }
+
+                self.webtransport_sessions.insert(stream_id);
+            }
+        }
+
+        qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
+            let qlog_headers = headers
+                .iter()
+                .map(|h| qlog::events::h3::HttpHeader {
+                    name: String::from_utf8_lossy(h.name()).into_owned(),
+                    value: String::from_utf8_lossy(h.value()).into_owned(),
+                })
+                .collect();
+
+            let frame = Http3Frame::Headers {
+                headers: qlog_headers,
+            };
+
+            let ev_data = EventData::H3FrameParsed(H3FrameParsed {
+                stream_id,
+                length: Some(payload_len),
+                frame,
+                ..Default::default()
+            });
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        let more_frames = !conn.stream_finished(stream_id);
+
+        Ok((stream_id, Event::Headers {
+            list: headers,
+            more_frames,
+        }))
+    }
+
+    /// Decodes header blocks of streams that were blocked on QPACK encoder
+    /// instructions, if possible.
+    fn process_qpack_blocked_streams<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>,
+    ) -> Result<(u64, Event)> {
+        for _ in 0..self.qpack_blocked_streams.len() {
+            let stream_id = match self.qpack_blocked_streams.pop_front() {
+                Some(v) => v,
+
+                None => break,
+            };
+
+            let (header_block, payload_len, push_id) = match self
+                .streams
+                .get_mut(&stream_id)
+                .and_then(|s| s.take_qpack_blocked())
+            {
+                Some(v) => v,
+
+                None => continue,
+            };
+
+            match self.process_header_block(
+                conn,
+                stream_id,
+                header_block,
+                payload_len,
+                push_id,
+            ) {
+                Ok(ev) => {
+                    if conn.stream_finished(stream_id) {
+                        self.process_finished_stream(stream_id);
+                    }
+
+                    return Ok(ev);
+                },
+
+                Err(Error::Done) => (),
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    fn process_finished_stream(&mut self, stream_id: u64) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        // Streams blocked on QPACK are finished once their headers have been
+        // decoded.
+        if matches!(
+            stream.state(),
+            stream::State::Finished | stream::State::QpackBlocked
+        ) {
+            return;
+        }
+
+        match stream.ty() {
+            Some(stream::Type::Request) |
+            Some(stream::Type::Push) |
+            Some(stream::Type::WebTransport) => {
+                stream.finished();
+
+                self.finished_streams.push_back(stream_id);
+
+                // The peer terminated the session by finishing its stream.
+                self.webtransport_sessions.remove(&stream_id);
+            },
+
+            _ => (),
+        };
+    }
+
+    fn process_frame<F: BufFactory>(
+        &mut self, conn: &mut super::Connection<F>, stream_id: u64,
+        frame: frame::Frame, payload_len: u64,
+    ) -> Result<(u64, Event)> {
+        trace!(
+            "{} rx frm {:?} stream={} payload_len={}",
+            conn.trace_id(),
+            frame,
+            stream_id,
+            payload_len
+        );
+
+        qlog_with_type!(QLOG_FRAME_PARSED, conn.qlog, q, {
+            // HEADERS frames are special case and will be logged below.
+            if !matches!(frame, frame::Frame::Headers { .. }) {
+                let frame = frame.to_qlog();
+                let ev_data = EventData::H3FrameParsed(H3FrameParsed {
+                    stream_id,
+                    length: Some(payload_len),
+                    frame,
+                    ..Default::default()
+                });
+
+                q.add_event_data_now(ev_data).ok();
+            }
+        });
+
+        match frame {
+            frame::Frame::Settings {
+                max_field_section_size,
+                qpack_max_table_capacity,
+                qpack_blocked_streams,
+                connect_protocol_enabled,
+                h3_datagram,
+                additional_settings,
+                raw,
+                ..
+            } => {
+                let wt_max_sessions = additional_settings
+                    .iter()
+                    .flatten()
+                    .find(|(id, _)| *id == frame::SETTINGS_WT_MAX_SESSIONS)
+                    .map(|(_, v)| *v);
+
+                self.peer_settings = ConnectionSettings {
+                    max_field_section_size,
+                    qpack_max_table_capacity,
+                    qpack_blocked_streams,
+                    connect_protocol_enabled,
+                    h3_datagram,
+                    wt_max_sessions,
+                    additional_settings,
+                    raw,
+                };
+
+                self.qpack_encoder.set_max_table_capacity(
+                    qpack_max_table_capacity.unwrap_or(0),
+                );
+                self.qpack_encoder
+                    .set_max_blocked_streams(qpack_blocked_streams.unwrap_or(0));
+
+                // Only use the dynamic table if the peer allows it, and if
+                // instructions can be sent on the encoder stream.
+                let capacity = std::cmp::min(
+                    qpack_max_table_capacity.unwrap_or(0),
+                    self.qpack_encoder_max_table_capacity,
+                );
+
+                if capacity > 0 &&
+                    self.local_qpack_streams.encoder_stream_id.is_some()
+                {
+                    self.qpack_encoder
+                        .set_table_capacity(capacity)
+                        .map_err(|_| Error::InternalError)?;
+
+                    self.flush_qpack_encoder_stream(conn)?;
+                }
+
+                if let Some(1) = h3_datagram {
+                    // The peer MUST have also enabled DATAGRAM with a TP
+                    if conn.dgram_max_writable_len().is_none() {
+                        conn.close(
+                            true,
+                            Error::SettingsError.to_wire(),
+                            b"H3_DATAGRAM sent with value 1 but max_datagram_frame_size TP not set.",
+                        )?;
+
+                        return Err(Error::SettingsError);
+                    }
+                }
+            },
+
+            frame::Frame::Headers { header_block } => {
+                // Servers reject too many HEADERS frames.
+                if let Some(s) = self.streams.get_mut(&stream_id) {
+                    if self.is_server && s.headers_received_count() == 2 {
+                        conn.close(
+                            true,
+                            Error::FrameUnexpected.to_wire(),
+                            b"Too many HEADERS frames",
+                        )?;
+                        return Err(Error::FrameUnexpected);
+                    }
+
+                    s.increment_headers_received();
+
+                    if conn.stream_received_early_data(stream_id) {
+                        s.mark_early_data();
+                    }
+                }
+
+                return self.process_header_block(
+                    conn,
+                    stream_id,
+                    header_block,
+                    payload_len,
+                    None,
+                );
+            },
+
+            frame::Frame::Data { .. } => {
+                // Do nothing. The Data event is returned separately.
+            },
+
+            frame::Frame::GoAway { id } => {
+                if !self.is_server && id % 4 != 0 {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"GOAWAY received with ID of non-request stream",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                if let Some(received_id) = self.peer_goaway_id {
+                    if id > received_id {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"GOAWAY received with ID larger than previously received",
+                        )?;
+
+                        return Err(Error::IdError);
+                    }
+                }
+
+                self.peer_goaway_id = Some(id);
+
+                return Ok((id, Event::GoAway));
+            },
+
+            frame::Frame::MaxPushId { push_id } => {
+                if !self.is_server {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"MAX_PUSH_ID received by client",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if self.max_push_id.is_some_and(|max| push_id < max) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"MAX_PUSH_ID reduced limit",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                self.max_push_id = Some(push_id);
+            },
+
+            frame::Frame::PushPromise {
+                push_id,
+                header_block,
+            } => {
+                if self.is_server {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PUSH_PROMISE received by server",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if stream_id % 4 != 0 {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PUSH_PROMISE received on non-request stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if self.max_push_id.is_none_or(|max| push_id > max) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PUSH_PROMISE received with push ID beyond limit",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                return self.process_header_block(
+                    conn,
+                    stream_id,
+                    header_block,
+                    payload_len,
+                    Some(push_id),
+                );
+            },
+
+            frame::Frame::CancelPush { push_id } => {
+                // Servers can only receive CANCEL_PUSH for pushes they
+                // promised, clients for push IDs they allowed.
+                let valid = if self.is_server {
+                    push_id < self.next_push_id
+                } else {
+                    self.max_push_id.is_some_and(|max| push_id <= max)
+                };
+
+                if !valid {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"CANCEL_PUSH received with unknown push ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                if self.is_server {
+                    self.abort_push(conn, push_id);
+                }
+
+                return Ok((push_id, Event::CancelPush));
+            },
+
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                if !self.is_server {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PRIORITY_UPDATE received by client",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if prioritized_element_id % 4 != 0 {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PRIORITY_UPDATE for request stream type with wrong ID",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if prioritized_element_id > conn.streams.max_streams_bidi() * 4 {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PRIORITY_UPDATE for request stream beyond max streams limit",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                // If the PRIORITY_UPDATE is valid, consider storing the latest
+                // contents. Due to reordering, it is possible that we might
+                // receive frames that reference streams that have not yet to
+                // been opened and that's OK because it's within our concurrency
+                // limit. However, we discard PRIORITY_UPDATE that refers to
+                // streams that we know have been collected.
+                if conn.streams.is_collected(prioritized_element_id) {
+                    return Err(Error::Done);
+                }
+
+                // If the stream did not yet exist, create it and store.
+                let stream =
+                    self.streams.entry(prioritized_element_id).or_insert_with(
+                        || <stream::Stream>::new(prioritized_element_id, false),
+                    );
+
+                let had_priority_update = stream.has_last_priority_update();
+                stream.set_last_priority_update(Some(priority_field_value));
+
+                // Only trigger the event when there wasn't already a stored
+                // PRIORITY_UPDATE.
+                if !had_priority_update {
+                    return Ok((prioritized_element_id, Event::PriorityUpdate));
+                } else {
+                    return Err(Error::Done);
+                }
+            },
+
+            frame::Frame::PriorityUpdatePush {
+                prioritized_element_id,
+                ..
+            } => {
+                if !self.is_server {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PRIORITY_UPDATE received by client",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if prioritized_element_id % 3 != 0 {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PRIORITY_UPDATE for push stream type with wrong ID",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                // TODO: we only implement this if we implement server push
+            },
+
+            frame::Frame::Unknown { .. } => (),
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Collects and returns statistics about the connection.
+    #[inline]
+    pub fn stats(&self) -> Stats {
+        Stats {
+            qpack_encoder_stream_recv_bytes: self
+                .peer_qpack_streams
+                .encoder_stream_bytes,
+            qpack_decoder_stream_recv_bytes: self
+                .peer_qpack_streams
+                .decoder_stream_bytes,
+        }
+    }
+}
+
+/// Generates an HTTP/3 GREASE variable length integer.
+pub fn grease_value() -> u64 {
+    let n = super::rand::rand_u64_uniform(148_764_065_110_560_899);
+    31 * n + 33
+}
+
+#[doc(hidden)]
+pub mod testing {
+    use super::*;
+
+    use crate::test_utils;
+
+    /// Session is an HTTP/3 test helper structure. It holds a client, server
+    /// and pipe that allows them to communicate.
+    ///
+    /// `default()` creates a session with some sensible default
+    /// configuration. `with_configs()` allows for providing a specific
+    /// configuration.
+    ///
+    /// `handshake()` performs all the steps needed to establish an HTTP/3
+    /// connection.
+    ///
+    /// Some utility functions are provided that make it less verbose to send
+    /// request, responses and individual headers. The full quiche API remains
+    /// available for any test that need to do unconventional things (such as
+    /// bad behaviour that triggers errors).
+    pub struct Session {
+        pub pipe: test_utils::Pipe,
+        pub client: Connection,
+        pub server: Connection,
+    }
+
+    impl Session {
+        pub fn new() -> Result<Session> {
+            fn path_relative_to_manifest_dir(path: &str) -> String {
+                std::fs::canonicalize(
+                    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(path),
+                )
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+            }
+
+            let mut config = crate::Config::new(crate::PROTOCOL_VERSION)?;
+            config.load_cert_chain_from_pem_file(
+                &path_relative_to_manifest_dir("examples/cert.crt"),
+            )?;
+            config.load_priv_key_from_pem_file(
+                &path_relative_to_manifest_dir("examples/cert.key"),
+            )?;
+            config.set_application_protos(&[b"h3"])?;
+            config.set_initial_max_data(1500);
+            config.set_initial_max_stream_data_bidi_local(150);
+            config.set_initial_max_stream_data_bidi_remote(150);
+            config.set_initial_max_stream_data_uni(150);
+            config.set_initial_max_streams_bidi(5);
+            config.set_initial_max_streams_uni(5);
+            config.verify_peer(false);
+            config.enable_dgram(true, 3, 3);
+            config.set_ack_delay_exponent(8);
+
+            let h3_config = Config::new()?;
+            Session::with_configs(&mut config, &h3_config)
+        }
+
+        pub fn with_configs(
+            config: &mut crate::Config, h3_config: &Config,
+        ) -> Result<Session> {
+            let pipe = test_utils::Pipe::with_config(config)?;
+            let client_dgram = pipe.client.dgram_enabled();
+            let server_dgram = pipe.server.dgram_enabled();
+            Ok(Session {
+                pipe,
+                client: Connection::new(h3_config, false, client_dgram)?,
+                server: Connection::new(h3_config, true, server_dgram)?,
+            })
+        }
+
+        /// Do the HTTP/3 handshake so both ends are in sane initial state.
+        pub fn handshake(&mut self) -> Result<()> {
+            self.pipe.handshake()?;
+
+            // Client streams.
+            self.client.send_settings(&mut self.pipe.client)?;
+            self.pipe.advance().ok();
+
+            self.client
+                .open_qpack_encoder_stream(&mut self.pipe.client)?;
+            self.pipe.advance().ok();
+
+            self.client
+                .open_qpack_decoder_stream(&mut self.pipe.client)?;
+            self.pipe.advance().ok();
+
+            if self.pipe.client.grease {
+                self.client.open_grease_stream(&mut self.pipe.client)?;
+            }
+
+            self.pipe.advance().ok();
+
+            // Server streams.
+            self.server.send_settings(&mut self.pipe.server)?;
+            self.pipe.advance().ok();
+
+            self.server
+                .open_qpack_encoder_stream(&mut self.pipe.server)?;
+            self.pipe.advance().ok();
+
+            self.server
+                .open_qpack_decoder_stream(&mut self.pipe.server)?;
+            self.pipe.advance().ok();
+
+            if self.pipe.server.grease {
+                self.server.open_grease_stream(&mut self.pipe.server)?;
+            }
+
+            self.advance().ok();
+
+            while self.client.poll(&mut self.pipe.client).is_ok() {
+                // Do nothing.
+            }
+
+            while self.server.poll(&mut self.pipe.server).is_ok() {
+                // Do nothing.
+            }
+
+            Ok(())
+        }
+
+        /// Advances the session pipe over the buffer.
+        pub fn advance(&mut self) -> crate::Result<()> {
+            self.pipe.advance()
+        }
+
+        /// Polls the client for events.
+        pub fn poll_client(&mut self) -> Result<(u64, Event)> {
+            self.client.poll(&mut self.pipe.client)
+        }
+
+        /// Polls the server for events.
+        pub fn poll_server(&mut self) -> Result<(u64, Event)> {
+            self.server.poll(&mut self.pipe.server)
+        }
+
+        /// Sends a request from client with default headers.
+        ///
+        /// On success it returns the newly allocated stream and the headers.
+        pub fn send_request(&mut self, fin: bool) -> Result<(u64, Vec<Header>)> {
+            let req = vec![
+                Header::new(b":method", b"GET"),
+                Header::new(b":scheme", b"https"),
+                Header::new(b":authority", b"quic.tech"),
+                Header::new(b":path", b"/test"),
+                Header::new(b"user-agent", b"quiche-test"),
+            ];
+
+            let stream =
+                self.client.send_request(&mut self.pipe.client, &req, fin)?;
+
+            self.advance().ok();
+
+            Ok((stream, req))
+        }
+
+        /// Sends a response from server with default headers.
+        ///
+        /// On success it returns the headers.
+        pub fn send_response(
+            &mut self, stream: u64, fin: bool,
+        ) -> Result<Vec<Header>> {
+            let resp = vec![
+                Header::new(b":status", b"200"),
+                Header::new(b"server", b"quiche-test"),
+            ];
+
+            self.server.send_response(
+                &mut self.pipe.server,
+                stream,
+                &resp,
+                fin,
+            )?;
+
+            self.advance().ok();
+
+            Ok(resp)
+        }
+
+        /// Sends some default payload from client.
+        ///
+        /// On success it returns the payload.
+        pub fn send_body_client(
+            &mut self, stream: u64, fin: bool,
+        ) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+            self.client
+                .send_body(&mut self.pipe.client, stream, &bytes, fin)?;
+
+            self.advance().ok();
+
+            Ok(bytes)
+        }
+
+        /// Fetches DATA payload from the server.
+        ///
+        /// On success it returns the number of bytes received.
+        pub fn recv_body_client(
+            &mut self, stream: u64, buf: &mut [u8],
+        ) -> Result<usize> {
+            self.client.recv_body(&mut self.pipe.client, stream, buf)
+        }
+
+        /// Sends some default payload from server.
+        ///
+        /// On success it returns the payload.
+        pub fn send_body_server(
+            &mut self, stream: u64, fin: bool,
+        ) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+            self.server
+                .send_body(&mut self.pipe.server, stream, &bytes, fin)?;
+
+            self.advance().ok();
+
+            Ok(bytes)
+        }
+
+        /// Fetches DATA payload from the client.
+        ///
+        /// On success it returns the number of bytes received.
+        pub fn recv_body_server(
+            &mut self, stream: u64, buf: &mut [u8],
+        ) -> Result<usize> {
+            self.server.recv_body(&mut self.pipe.server, stream, buf)
+        }
+
+        /// Sends a single HTTP/3 frame from the client.
+        pub fn send_frame_client(
+            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            let mut d = [42; 65535];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            frame.to_bytes(&mut b)?;
+
+            let off = b.off();
+            self.pipe.client.stream_send(stream_id, &d[..off], fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+
+        /// Send an HTTP/3 DATAGRAM with default data from the client.
+        ///
+        /// On success it returns the data.
+        pub fn send_dgram_client(&mut self, flow_id: u64) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+            let len = octets::varint_len(flow_id) + bytes.len();
+            let mut d = vec![0; len];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            b.put_varint(flow_id)?;
+            b.put_bytes(&bytes)?;
+
+            self.pipe.client.dgram_send(&d)?;
+
+            self.advance().ok();
+
+            Ok(bytes)
+        }
+
+        /// Receives an HTTP/3 DATAGRAM from the server.
+        ///
+        /// On success it returns the DATAGRAM length, flow ID and flow ID
+        /// length.
+        pub fn recv_dgram_client(
+            &mut self, buf: &mut [u8],
+        ) -> Result<(usize, u64, usize)> {
+            let len = self.pipe.client.dgram_recv(buf)?;
+            let mut b = octets::Octets::with_slice(buf);
+            let flow_id = b.get_varint()?;
+
+            Ok((len, flow_id, b.off()))
+        }
+
+        /// Send an HTTP/3 DATAGRAM with default data from the server
+        ///
+        /// On success it returns the data.
+        pub fn send_dgram_server(&mut self, flow_id: u64) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+            let len = octets::varint_len(flow_id) + bytes.len();
+            let mut d = vec![0; len];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            b.put_varint(flow_id)?;
+            b.put_bytes(&bytes)?;
+
+            self.pipe.server.dgram_send(&d)?;
+
+            self.advance().ok();
+
+            Ok(bytes)
+        }
+
+        /// Receives an HTTP/3 DATAGRAM from the client.
+        ///
+        /// On success it returns the DATAGRAM length, flow ID and flow ID
+        /// length.
+        pub fn recv_dgram_server(
+            &mut self, buf: &mut [u8],
+        ) -> Result<(usize, u64, usize)> {
+            let len = self.pipe.server.dgram_recv(buf)?;
+            let mut b = octets::Octets::with_slice(buf);
+            let flow_id = b.get_varint()?;
+
+            Ok((len, flow_id, b.off()))
+        }
+
+        /// Sends a single HTTP/3 frame from the server.
+        pub fn send_frame_server(
+            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            let mut d = [42; 65535];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            frame.to_bytes(&mut b)?;
+
+            let off = b.off();
+            self.pipe.server.stream_send(stream_id, &d[..off], fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+
+        /// Sends an arbitrary buffer of HTTP/3 stream data from the client.
+        pub fn send_arbitrary_stream_data_client(
+            &mut self, data: &[u8], stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            self.pipe.client.stream_send(stream_id, data, fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+
+        /// Sends an arbitrary buffer of HTTP/3 stream data from the server.
+        pub fn send_arbitrary_stream_data_server(
+            &mut self, data: &[u8], stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            self.pipe.server.stream_send(stream_id, data, fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::testing::*;
+
+    #[test]
+    /// Make sure that random GREASE values is within the specified limit.
+    fn grease_value_in_varint_limit() {
+        assert!(grease_value() < 2u64.pow(62) - 1);
+    }
+
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
+    #[test]
+    fn h3_handshake_0rtt() {
+        let mut buf = [0; 65535];
+
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_stream_data_uni(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_initial_max_streams_uni(3);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        let h3_config = Config::new().unwrap();
+
+        // Perform initial handshake.
+        let mut pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection.
+        let mut pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Can't create an H3 connection until the QUIC connection is determined
+        // to have made sufficient early data progress.
+        assert!(matches!(
+            Connection::with_transport(&mut pipe.client, &h3_config),
+            Err(Error::InternalError)
+        ));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Now an H3 connection can be created.
+        assert!(Connection::with_transport(&mut pipe.client, &h3_config).is_ok());
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        // Client sends 0-RTT packet.
+        let pkt_type = crate::packet::Type::ZeroRTT;
+
+        let frames = [crate::frame::Frame::Stream {
+            stream_id: 6,
+            data: <crate::range_buf::RangeBuf>::from(b"aaaaa", 0, true),
+        }];
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(1200)
+        );
+
+        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+
+        // 0-RTT stream data is readable.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(6));
+        assert_eq!(r.next(), None);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(6, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"aaaaa");
+    }
+
+    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
+    #[test]
+    /// Send a request in early data, then one after the handshake.
+    fn request_in_early_data() {
+        let mut buf = [0; 65535];
+
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.enable_early_data();
+        config.verify_peer(false);
+
+        let h3_config = Config::new().unwrap();
+
+        // Perform initial handshake.
+        let mut pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Extract session,
+        let session = pipe.client.session().unwrap();
+
+        // Configure session on new connection.
+        let mut pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_session(session), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+
+        let mut client =
+            Connection::with_transport(&mut pipe.client, &h3_config).unwrap();
+        let mut server =
+            Connection::with_transport(&mut pipe.server, &h3_config).unwrap();
+
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
+
+        // Client sends the request in 0-RTT packets.
+        let early_stream =
+            client.send_request(&mut pipe.client, &req, true).unwrap();
+
+        assert!(pipe.client.is_in_early_data());
+
+        while let Ok((len, _)) = pipe.client.send(&mut buf) {
+            assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        }
+
+        let poll_headers =
+            |server: &mut Connection, conn: &mut crate::Connection| loop {
+                match server.poll(conn) {
+                    Ok((stream, Event::Headers { .. })) => break stream,
+
+                    Ok(_) => (),
+
+                    Err(e) => panic!("unexpected error {e:?}"),
+                }
+            };
+
+        assert_eq!(poll_headers(&mut server, &mut pipe.server), early_stream);
+        assert!(server.is_early_data_request(early_stream));
+
+        // Complete the handshake, and send another request.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert!(pipe.client.is_established());
+
+        let stream = client.send_request(&mut pipe.client, &req, true).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(poll_headers(&mut server, &mut pipe.server), stream);
+        assert!(!server.is_early_data_request(stream));
+        assert!(server.is_early_data_request(early_stream));
+    }
+
+    #[test]
+    /// Send a request with no body, get a response with no body.
+    fn request_no_body_response_no_body() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        assert_eq!(stream, 0);
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a request with no body, get a response with one DATA frame.
+    fn request_no_body_response_one_chunk() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+        assert_eq!(stream, 0);
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a request with no body, get a response with multiple DATA frames.
+    fn request_no_body_response_many_chunks() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let total_data_frames = 4;
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        for _ in 0..total_data_frames - 1 {
+            s.send_body_server(stream, false).unwrap();
+        }
+
+        let body = s.send_body_server(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        for _ in 0..total_data_frames {
+            assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        }
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a request with one DATA frame, get a response with no body.
+    fn request_one_chunk_response_no_body() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let body = s.send_body_client(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+    }
+
+    #[test]
+    /// Send a request with multiple DATA frames, get a response with no body.
+    fn request_many_chunks_response_no_body() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let total_data_frames = 4;
+
+        for _ in 0..total_data_frames - 1 {
+            s.send_body_client(stream, false).unwrap();
+        }
+
+        let body = s.send_body_client(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        for _ in 0..total_data_frames {
+            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        }
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+    }
+
+    #[test]
+    /// Send a request with multiple DATA frames, get a response with one DATA
+    /// frame.
+    fn many_requests_many_chunks_response_one_chunk() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let mut reqs = Vec::new();
+
+        let (stream1, req1) = s.send_request(false).unwrap();
+        assert_eq!(stream1, 0);
+        reqs.push(req1);
+
+        let (stream2, req2) = s.send_request(false).unwrap();
+        assert_eq!(stream2, 4);
+        reqs.push(req2);
+
+        let (stream3, req3) = s.send_request(false).unwrap();
+        assert_eq!(stream3, 8);
+        reqs.push(req3);
+
+        let body = s.send_body_client(stream1, false).unwrap();
+        s.send_body_client(stream2, false).unwrap();
+        s.send_body_client(stream3, false).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        // Reverse order of writes.
+
+        s.send_body_client(stream3, true).unwrap();
+        s.send_body_client(stream2, true).unwrap();
+        s.send_body_client(stream1, true).unwrap();
+
+        let (_, ev) = s.poll_server().unwrap();
+        let ev_headers = Event::Headers {
+            list: reqs[0].clone(),
+            more_frames: true,
+        };
+        assert_eq!(ev, ev_headers);
+
+        let (_, ev) = s.poll_server().unwrap();
+        let ev_headers = Event::Headers {
+            list: reqs[1].clone(),
+            more_frames: true,
+        };
+        assert_eq!(ev, ev_headers);
+
+        let (_, ev) = s.poll_server().unwrap();
+        let ev_headers = Event::Headers {
+            list: reqs[2].clone(),
+            more_frames: true,
+        };
+        assert_eq!(ev, ev_headers);
+
+        assert_eq!(s.poll_server(), Ok((0, Event::Data)));
+        assert_eq!(s.recv_body_server(0, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_body_server(0, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((0, Event::Finished)));
+
+        assert_eq!(s.poll_server(), Ok((4, Event::Data)));
+        assert_eq!(s.recv_body_server(4, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_body_server(4, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((4, Event::Finished)));
+
+        assert_eq!(s.poll_server(), Ok((8, Event::Data)));
+        assert_eq!(s.recv_body_server(8, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_body_server(8, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((8, Event::Finished)));
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let mut resps = Vec::new();
+
+        let resp1 = s.send_response(stream1, true).unwrap();
+        resps.push(resp1);
+
+        let resp2 = s.send_response(stream2, true).unwrap();
+        resps.push(resp2);
+
+        let resp3 = s.send_response(stream3, true).unwrap();
+        resps.push(resp3);
+
+        for _ in 0..resps.len() {
+            let (stream, ev) = s.poll_client().unwrap();
+            let ev_headers = Event::Headers {
+                list: resps[(stream / 4) as usize].clone(),
+                more_frames: false,
+            };
+            assert_eq!(ev, ev_headers);
+            assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        }
+
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a request with no body, get a response with one DATA frame and an
+    /// empty FIN after reception from the client.
+    fn request_no_body_response_one_chunk_empty_fin() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, false).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.pipe.server.stream_send(stream, &[], true), Ok(0));
+        s.advance().ok();
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a request with no body, get a response with no body followed by
+    /// GREASE that is STREAM frame with a FIN.
+    fn request_no_body_response_no_body_with_grease() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        assert_eq!(stream, 0);
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
+
+        // Inject a GREASE frame
+        let mut d = [42; 10];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
+        s.pipe.server.stream_send(0, frame_type, false).unwrap();
+
+        let frame_len = b.put_varint(10).unwrap();
+        s.pipe.server.stream_send(0, frame_len, false).unwrap();
+
+        s.pipe.server.stream_send(0, &d, true).unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Try to send DATA frames before HEADERS.
+    fn body_response_before_headers() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+        assert_eq!(stream, 0);
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        assert_eq!(
+            s.send_body_server(stream, true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Try to send DATA frames on wrong streams, ensure the API returns an
+    /// error before anything hits the transport layer.
+    fn send_body_invalid_client_stream() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        assert_eq!(s.send_body_client(0, true), Err(Error::FrameUnexpected));
+
+        assert_eq!(
+            s.send_body_client(s.client.control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(
+                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(
+                s.client.local_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(s.client.peer_control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(
+                s.client.peer_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(
+                s.client.peer_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+    }
+
+    #[test]
+    /// Try to send DATA frames on wrong streams, ensure the API returns an
+    /// error before anything hits the transport layer.
+    fn send_body_invalid_server_stream() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        assert_eq!(s.send_body_server(0, true), Err(Error::FrameUnexpected));
+
+        assert_eq!(
+            s.send_body_server(s.server.control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.local_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.local_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(s.server.peer_control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.peer_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.peer_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+    }
+
+    #[test]
+    /// Client sends request with body and trailers.
+    fn trailers() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let body = s.send_body_client(stream, false).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let req_trailers = vec![Header::new(b"foo", b"bar")];
+
+        s.client
+            .send_additional_headers(
+                &mut s.pipe.client,
+                stream,
+                &req_trailers,
+                true,
+                true,
+            )
+            .unwrap();
+
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
+
+        let ev_trailers = Event::Headers {
+            list: req_trailers,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+    }
+
+    #[test]
+    /// Server responds with a 103, then a 200 with no body.
+    fn informational_response() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        assert_eq!(stream, 0);
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let info_resp = vec![
+            Header::new(b":status", b"103"),
+            Header::new(b"link", b"<https://example.com>; rel=\"preconnect\""),
+        ];
+
+        let resp = vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"server", b"quiche-test"),
+        ];
+
+        s.server
+            .send_response(&mut s.pipe.server, stream, &info_resp, false)
+            .unwrap();
+
+        s.server
+            .send_additional_headers(
+                &mut s.pipe.server,
+                stream,
+                &resp,
+                false,
+                true,
+            )
+            .unwrap();
+
+        s.advance().ok();
+
+        let ev_info_headers = Event::Headers {
+            list: info_resp,
+            more_frames: true,
+        };
 
-        /// Advances the session pipe over the buffer.
-        pub fn advance(&mut self) -> crate::Result<()> {
-            self.pipe.advance()
-        }
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
 
-        /// Polls the client for events.
-        pub fn poll_client(&mut self) -> Result<(u64, Event)> {
-            self.client.poll(&mut self.pipe.client)
-        }
+        assert_eq!(s.poll_client(), Ok((stream, ev_info_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-        /// Polls the server for events.
-        pub fn poll_server(&mut self) -> Result<(u64, Event)> {
-            self.server.poll(&mut self.pipe.server)
-        }
+    #[test]
+    /// Server responds with a 103, then attempts to send a 200 using
+    /// send_response again, which should fail.
+    fn no_multiple_response() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        /// Sends a request from client with default headers.
-        ///
-        /// On success it returns the newly allocated stream and the headers.
-        pub fn send_request(&mut self, fin: bool) -> Result<(u64, Vec<Header>)> {
-            let req = vec![
-                Header::new(b":method", b"GET"),
-                Header::new(b":scheme", b"https"),
-                Header::new(b":authority", b"quic.tech"),
-                Header::new(b":path", b"/test"),
-                Header::new(b"user-agent", b"quiche-test"),
-            ];
+        let (stream, req) = s.send_request(true).unwrap();
 
-            let stream =
-                self.client.send_request(&mut self.pipe.client, &req, fin)?;
+        assert_eq!(stream, 0);
 
-            self.advance().ok();
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
 
-            Ok((stream, req))
-        }
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        /// Sends a response from server with default headers.
-        ///
-        /// On success it returns the headers.
-        pub fn send_response(
-            &mut self, stream: u64, fin: bool,
-        ) -> Result<Vec<Header>> {
-            let resp = vec![
-                Header::new(b":status", b"200"),
-                Header::new(b"server", b"quiche-test"),
-            ];
+        let info_resp = vec![
+            Header::new(b":status", b"103"),
+            Header::new(b"link", b"<https://example.com>; rel=\"preconnect\""),
+        ];
 
-            self.server.send_response(
-                &mut self.pipe.server,
-                stream,
-                &resp,
-                fin,
-            )?;
+        let resp = vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"server", b"quiche-test"),
+        ];
 
-            self.advance().ok();
+        s.server
+            .send_response(&mut s.pipe.server, stream, &info_resp, false)
+            .unwrap();
 
-            Ok(resp)
-        }
+        assert_eq!(
+            Err(Error::FrameUnexpected),
+            s.server
+                .send_response(&mut s.pipe.server, stream, &resp, true)
+        );
 
-        /// Sends some default payload from client.
-        ///
-        /// On success it returns the payload.
-        pub fn send_body_client(
-            &mut self, stream: u64, fin: bool,
-        ) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        s.advance().ok();
 
-            self.client
-                .send_body(&mut self.pipe.client, stream, &bytes, fin)?;
+        let ev_info_headers = Event::Headers {
+            list: info_resp,
+            more_frames: true,
+        };
 
-            self.advance().ok();
+        assert_eq!(s.poll_client(), Ok((stream, ev_info_headers)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-            Ok(bytes)
-        }
+    #[test]
+    /// Server attempts to use send_additional_headers before initial response.
+    fn no_send_additional_before_initial_response() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        /// Fetches DATA payload from the server.
-        ///
-        /// On success it returns the number of bytes received.
-        pub fn recv_body_client(
-            &mut self, stream: u64, buf: &mut [u8],
-        ) -> Result<usize> {
-            self.client.recv_body(&mut self.pipe.client, stream, buf)
-        }
+        let (stream, req) = s.send_request(true).unwrap();
 
-        /// Sends some default payload from server.
-        ///
-        /// On success it returns the payload.
-        pub fn send_body_server(
-            &mut self, stream: u64, fin: bool,
-        ) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(stream, 0);
 
-            self.server
-                .send_body(&mut self.pipe.server, stream, &bytes, fin)?;
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
 
-            self.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-            Ok(bytes)
-        }
+        let info_resp = vec![
+            Header::new(b":status", b"103"),
+            Header::new(b"link", b"<https://example.com>; rel=\"preconnect\""),
+        ];
 
-        /// Fetches DATA payload from the client.
-        ///
-        /// On success it returns the number of bytes received.
-        pub fn recv_body_server(
-            &mut self, stream: u64, buf: &mut [u8],
-        ) -> Result<usize> {
-            self.server.recv_body(&mut self.pipe.server, stream, buf)
-        }
+        assert_eq!(
+            Err(Error::FrameUnexpected),
+            s.server.send_additional_headers(
+                &mut s.pipe.server,
+                stream,
+                &info_resp,
+                false,
+                false
+            )
+        );
 
-        /// Sends a single HTTP/3 frame from the client.
-        pub fn send_frame_client(
-            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            let mut d = [42; 65535];
+        s.advance().ok();
 
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-            frame.to_bytes(&mut b)?;
+    #[test]
+    /// Client sends multiple HEADERS before data.
+    fn additional_headers_before_data_client() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-            let off = b.off();
-            self.pipe.client.stream_send(stream_id, &d[..off], fin)?;
+        let (stream, req) = s.send_request(false).unwrap();
 
-            self.advance().ok();
+        let req_trailer = vec![Header::new(b"goodbye", b"world")];
 
+        assert_eq!(
+            s.client.send_additional_headers(
+                &mut s.pipe.client,
+                stream,
+                &req_trailer,
+                true,
+                false
+            ),
             Ok(())
-        }
+        );
 
-        /// Send an HTTP/3 DATAGRAM with default data from the client.
-        ///
-        /// On success it returns the data.
-        pub fn send_dgram_client(&mut self, flow_id: u64) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-            let len = octets::varint_len(flow_id) + bytes.len();
-            let mut d = vec![0; len];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        s.advance().ok();
 
-            b.put_varint(flow_id)?;
-            b.put_bytes(&bytes)?;
+        let ev_initial_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-            self.pipe.client.dgram_send(&d)?;
+        let ev_trailing_headers = Event::Headers {
+            list: req_trailer,
+            more_frames: true,
+        };
 
-            self.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_initial_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, ev_trailing_headers)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-            Ok(bytes)
-        }
+    #[test]
+    /// Client sends multiple HEADERS before data.
+    fn data_after_trailers_client() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        /// Receives an HTTP/3 DATAGRAM from the server.
-        ///
-        /// On success it returns the DATAGRAM length, flow ID and flow ID
-        /// length.
-        pub fn recv_dgram_client(
-            &mut self, buf: &mut [u8],
-        ) -> Result<(usize, u64, usize)> {
-            let len = self.pipe.client.dgram_recv(buf)?;
-            let mut b = octets::Octets::with_slice(buf);
-            let flow_id = b.get_varint()?;
+        let (stream, req) = s.send_request(false).unwrap();
 
-            Ok((len, flow_id, b.off()))
-        }
+        let body = s.send_body_client(stream, false).unwrap();
 
-        /// Send an HTTP/3 DATAGRAM with default data from the server
-        ///
-        /// On success it returns the data.
-        pub fn send_dgram_server(&mut self, flow_id: u64) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-            let len = octets::varint_len(flow_id) + bytes.len();
-            let mut d = vec![0; len];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        let mut recv_buf = vec![0; body.len()];
 
-            b.put_varint(flow_id)?;
-            b.put_bytes(&bytes)?;
+        let req_trailers = vec![Header::new(b"foo", b"bar")];
+
+        s.client
+            .send_additional_headers(
+                &mut s.pipe.client,
+                stream,
+                &req_trailers,
+                true,
+                false,
+            )
+            .unwrap();
 
-            self.pipe.server.dgram_send(&d)?;
+        s.advance().ok();
 
-            self.advance().ok();
+        s.send_frame_client(
+            frame::Frame::Data {
+                payload: vec![1, 2, 3, 4],
+            },
+            stream,
+            true,
+        )
+        .unwrap();
 
-            Ok(bytes)
-        }
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-        /// Receives an HTTP/3 DATAGRAM from the client.
-        ///
-        /// On success it returns the DATAGRAM length, flow ID and flow ID
-        /// length.
-        pub fn recv_dgram_server(
-            &mut self, buf: &mut [u8],
-        ) -> Result<(usize, u64, usize)> {
-            let len = self.pipe.server.dgram_recv(buf)?;
-            let mut b = octets::Octets::with_slice(buf);
-            let flow_id = b.get_varint()?;
+        let ev_trailers = Event::Headers {
+            list: req_trailers,
+            more_frames: true,
+        };
 
-            Ok((len, flow_id, b.off()))
-        }
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-        /// Sends a single HTTP/3 frame from the server.
-        pub fn send_frame_server(
-            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            let mut d = [42; 65535];
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the client on a valid stream.
+    fn max_push_id_from_client_good() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-            frame.to_bytes(&mut b)?;
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-            let off = b.off();
-            self.pipe.server.stream_send(stream_id, &d[..off], fin)?;
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the client on an invalid stream.
+    fn max_push_id_from_client_bad_stream() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-            self.advance().ok();
+        let (stream, req) = s.send_request(false).unwrap();
 
-            Ok(())
-        }
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 2 },
+            stream,
+            false,
+        )
+        .unwrap();
 
-        /// Sends an arbitrary buffer of HTTP/3 stream data from the client.
-        pub fn send_arbitrary_stream_data_client(
-            &mut self, data: &[u8], stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            self.pipe.client.stream_send(stream_id, data, fin)?;
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-            self.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-            Ok(())
-        }
+    #[test]
+    /// Send a sequence of MAX_PUSH_ID frames from the client that attempt to
+    /// reduce the limit.
+    fn max_push_id_from_client_limit_reduction() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        /// Sends an arbitrary buffer of HTTP/3 stream data from the server.
-        pub fn send_arbitrary_stream_data_server(
-            &mut self, data: &[u8], stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            self.pipe.server.stream_send(stream_id, data, fin)?;
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 2 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-            self.advance().ok();
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-            Ok(())
-        }
+        assert_eq!(s.poll_server(), Err(Error::IdError));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the server, which is forbidden.
+    fn max_push_id_from_server() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-    use super::testing::*;
+        s.send_frame_server(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-    #[test]
-    /// Make sure that random GREASE values is within the specified limit.
-    fn grease_value_in_varint_limit() {
-        assert!(grease_value() < 2u64.pow(62) - 1);
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
     }
 
-    #[cfg(not(feature = "openssl"))] // 0-RTT not supported when using openssl/quictls
     #[test]
-    fn h3_handshake_0rtt() {
-        let mut buf = [0; 65535];
+    /// Send a PUSH_PROMISE frame from the client, which is forbidden.
+    fn push_promise_from_client() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.set_initial_max_data(30);
-        config.set_initial_max_stream_data_bidi_local(15);
-        config.set_initial_max_stream_data_bidi_remote(15);
-        config.set_initial_max_stream_data_uni(15);
-        config.set_initial_max_streams_bidi(3);
-        config.set_initial_max_streams_uni(3);
-        config.enable_early_data();
-        config.verify_peer(false);
+        let (stream, req) = s.send_request(false).unwrap();
 
-        let h3_config = Config::new().unwrap();
+        let header_block = s.client.encode_header_block(stream, &req).unwrap();
 
-        // Perform initial handshake.
-        let mut pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        s.send_frame_client(
+            frame::Frame::PushPromise {
+                push_id: 1,
+                header_block,
+            },
+            stream,
+            false,
+        )
+        .unwrap();
 
-        // Extract session,
-        let session = pipe.client.session().unwrap();
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-        // Configure session on new connection.
-        let mut pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
-        assert_eq!(pipe.client.set_session(session), Ok(()));
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-        // Can't create an H3 connection until the QUIC connection is determined
-        // to have made sufficient early data progress.
-        assert!(matches!(
-            Connection::with_transport(&mut pipe.client, &h3_config),
-            Err(Error::InternalError)
-        ));
+    #[test]
+    /// Send a CANCEL_PUSH frame from the client for a push that wasn't
+    /// promised.
+    fn cancel_push_from_client() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        s.send_frame_client(
+            frame::Frame::CancelPush { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        // Now an H3 connection can be created.
-        assert!(Connection::with_transport(&mut pipe.client, &h3_config).is_ok());
-        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(s.poll_server(), Err(Error::IdError));
+    }
 
-        // Client sends 0-RTT packet.
-        let pkt_type = crate::packet::Type::ZeroRTT;
+    #[test]
+    /// Send a CANCEL_PUSH frame from the client on an invalid stream.
+    fn cancel_push_from_client_bad_stream() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        let frames = [crate::frame::Frame::Stream {
-            stream_id: 6,
-            data: <crate::range_buf::RangeBuf>::from(b"aaaaa", 0, true),
-        }];
+        let (stream, req) = s.send_request(false).unwrap();
 
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(1200)
-        );
+        s.send_frame_client(
+            frame::Frame::CancelPush { push_id: 2 },
+            stream,
+            false,
+        )
+        .unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
+
+    #[test]
+    /// Send a CANCEL_PUSH frame from the server for a push ID beyond the
+    /// client's limit.
+    fn cancel_push_from_server() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(pipe.server.undecryptable_pkts.len(), 0);
+        s.send_frame_server(
+            frame::Frame::CancelPush { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        // 0-RTT stream data is readable.
-        let mut r = pipe.server.readable();
-        assert_eq!(r.next(), Some(6));
-        assert_eq!(r.next(), None);
+        assert_eq!(s.poll_client(), Err(Error::IdError));
+    }
 
-        let mut b = [0; 15];
-        assert_eq!(pipe.server.stream_recv(6, &mut b), Ok((5, true)));
-        assert_eq!(&b[..5], b"aaaaa");
+    fn push_request() -> Vec<Header> {
+        vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/style.css"),
+        ]
     }
 
     #[test]
-    /// Send a request with no body, get a response with no body.
-    fn request_no_body_response_no_body() {
+    /// Push a response from the server.
+    fn server_push() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        assert_eq!(stream, 0);
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let (stream, req) = s.send_request(true).unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
@@ -3612,831 +6377,1056 @@ mod tests {
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
+        let push_req = push_request();
+
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Ok(0)
+        );
+        s.advance().ok();
+
+        let ev_push_promise = Event::PushPromise {
+            push_id: 0,
+            list: push_req,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_push_promise)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
         let resp = s.send_response(stream, true).unwrap();
 
         let ev_headers = Event::Headers {
-            list: resp,
+            list: resp.clone(),
             more_frames: false,
         };
 
         assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-    }
-
-    #[test]
-    /// Send a request with no body, get a response with one DATA frame.
-    fn request_no_body_response_one_chunk() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
-        assert_eq!(stream, 0);
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, 0, &resp, false)
+            .unwrap();
+        s.advance().ok();
 
         let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
+            list: resp.clone(),
+            more_frames: true,
         };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Ok((push_stream, ev_headers)));
+        assert_eq!(s.client.push_id(push_stream), Some(0));
 
-        let resp = s.send_response(stream, false).unwrap();
+        // The push can only be fulfilled once.
+        assert_eq!(
+            s.server
+                .send_push_response(&mut s.pipe.server, 0, &resp, false),
+            Err(Error::IdError)
+        );
 
-        let body = s.send_body_server(stream, true).unwrap();
+        let body = s.send_body_server(push_stream, true).unwrap();
 
         let mut recv_buf = vec![0; body.len()];
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: true,
-        };
-
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Data)));
+        assert_eq!(
+            s.recv_body_client(push_stream, &mut recv_buf),
+            Ok(body.len())
+        );
+        assert_eq!(recv_buf, body);
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Send a request with no body, get a response with multiple DATA frames.
-    fn request_no_body_response_many_chunks() {
+    /// Server pushes are bounded by the client's MAX_PUSH_ID.
+    fn server_push_limit() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        let (stream, _) = s.send_request(false).unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
+        s.poll_server().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        let push_req = push_request();
 
-        let total_data_frames = 4;
+        // Pushes are not allowed until the client sends MAX_PUSH_ID.
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Err(Error::IdError)
+        );
 
-        let resp = s.send_response(stream, false).unwrap();
+        assert_eq!(
+            s.server.send_max_push_id(&mut s.pipe.server, 0),
+            Err(Error::FrameUnexpected)
+        );
 
-        for _ in 0..total_data_frames - 1 {
-            s.send_body_server(stream, false).unwrap();
-        }
+        s.client.send_max_push_id(&mut s.pipe.client, 0).unwrap();
+        s.advance().ok();
 
-        let body = s.send_body_server(stream, true).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let mut recv_buf = vec![0; body.len()];
+        assert_eq!(
+            s.client
+                .send_push_promise(&mut s.pipe.client, stream, &push_req),
+            Err(Error::FrameUnexpected)
+        );
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: true,
-        };
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Ok(0)
+        );
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Err(Error::IdError)
+        );
 
-        for _ in 0..total_data_frames {
-            assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
-        }
+        // Pushes that weren't promised can't be sent.
+        assert_eq!(
+            s.server
+                .send_push_response(&mut s.pipe.server, 1, &push_req, true),
+            Err(Error::IdError)
+        );
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(
+            s.client.send_max_push_id(&mut s.pipe.client, 0),
+            Err(Error::IdError)
+        );
+
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Ok(1)
+        );
     }
 
     #[test]
-    /// Send a request with one DATA frame, get a response with no body.
-    fn request_one_chunk_response_no_body() {
+    /// Send a PUSH_PROMISE frame from the server with a push ID beyond the
+    /// client's limit.
+    fn push_promise_beyond_limit() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
-
-        let body = s.send_body_client(stream, true).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
-
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
-
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        s.client.send_max_push_id(&mut s.pipe.client, 0).unwrap();
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        let (stream, _) = s.send_request(true).unwrap();
 
-        let resp = s.send_response(stream, true).unwrap();
+        let push_req = push_request();
+        let header_block =
+            s.server.encode_header_block(stream, &push_req).unwrap();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: false,
-        };
+        s.send_frame_server(
+            frame::Frame::PushPromise {
+                push_id: 1,
+                header_block,
+            },
+            stream,
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::IdError));
     }
 
     #[test]
-    /// Send a request with multiple DATA frames, get a response with no body.
-    fn request_many_chunks_response_no_body() {
+    /// Cancel a push from the client before it's fulfilled.
+    fn cancel_push_by_client() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
-
-        let total_data_frames = 4;
-
-        for _ in 0..total_data_frames - 1 {
-            s.send_body_client(stream, false).unwrap();
-        }
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        let body = s.send_body_client(stream, true).unwrap();
+        let (stream, _) = s.send_request(true).unwrap();
+        s.poll_server().unwrap();
 
-        let mut recv_buf = vec![0; body.len()];
+        let push_req = push_request();
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &push_req)
+            .unwrap();
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
+        let ev_push_promise = Event::PushPromise {
+            push_id,
+            list: push_req.clone(),
         };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        for _ in 0..total_data_frames {
-            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        }
+        assert_eq!(s.poll_client(), Ok((stream, ev_push_promise)));
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        // The push ID must be allowed by MAX_PUSH_ID.
+        assert_eq!(
+            s.client.cancel_push(&mut s.pipe.client, 2),
+            Err(Error::IdError)
+        );
 
-        let resp = s.send_response(stream, true).unwrap();
+        assert_eq!(s.client.cancel_push(&mut s.pipe.client, push_id), Ok(()));
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: false,
-        };
+        assert_eq!(s.poll_server(), Ok((push_id, Event::CancelPush)));
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(
+            s.server.send_push_response(
+                &mut s.pipe.server,
+                push_id,
+                &push_req,
+                true
+            ),
+            Err(Error::IdError)
+        );
     }
 
     #[test]
-    /// Send a request with multiple DATA frames, get a response with one DATA
-    /// frame.
-    fn many_requests_many_chunks_response_one_chunk() {
+    /// Cancel a push from the client after the push stream was opened.
+    fn cancel_push_by_client_push_stream_open() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let mut reqs = Vec::new();
-
-        let (stream1, req1) = s.send_request(false).unwrap();
-        assert_eq!(stream1, 0);
-        reqs.push(req1);
-
-        let (stream2, req2) = s.send_request(false).unwrap();
-        assert_eq!(stream2, 4);
-        reqs.push(req2);
-
-        let (stream3, req3) = s.send_request(false).unwrap();
-        assert_eq!(stream3, 8);
-        reqs.push(req3);
-
-        let body = s.send_body_client(stream1, false).unwrap();
-        s.send_body_client(stream2, false).unwrap();
-        s.send_body_client(stream3, false).unwrap();
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        let mut recv_buf = vec![0; body.len()];
+        let (stream, _) = s.send_request(true).unwrap();
+        s.poll_server().unwrap();
 
-        // Reverse order of writes.
+        let push_req = push_request();
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &push_req)
+            .unwrap();
 
-        s.send_body_client(stream3, true).unwrap();
-        s.send_body_client(stream2, true).unwrap();
-        s.send_body_client(stream1, true).unwrap();
+        let resp = vec![Header::new(b":status", b"200")];
 
-        let (_, ev) = s.poll_server().unwrap();
-        let ev_headers = Event::Headers {
-            list: reqs[0].clone(),
-            more_frames: true,
-        };
-        assert_eq!(ev, ev_headers);
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, push_id, &resp, false)
+            .unwrap();
+        s.advance().ok();
 
-        let (_, ev) = s.poll_server().unwrap();
-        let ev_headers = Event::Headers {
-            list: reqs[1].clone(),
-            more_frames: true,
-        };
-        assert_eq!(ev, ev_headers);
+        while s.poll_client().is_ok() {
+            // Do nothing.
+        }
 
-        let (_, ev) = s.poll_server().unwrap();
-        let ev_headers = Event::Headers {
-            list: reqs[2].clone(),
-            more_frames: true,
-        };
-        assert_eq!(ev, ev_headers);
+        assert_eq!(s.client.push_id(push_stream), Some(push_id));
+        assert_eq!(s.client.push_stream_id(push_id), Some(push_stream));
+        assert_eq!(s.server.push_stream_id(push_id), Some(push_stream));
 
-        assert_eq!(s.poll_server(), Ok((0, Event::Data)));
-        assert_eq!(s.recv_body_server(0, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_body_server(0, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((0, Event::Finished)));
+        assert_eq!(s.client.cancel_push(&mut s.pipe.client, push_id), Ok(()));
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((4, Event::Data)));
-        assert_eq!(s.recv_body_server(4, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_body_server(4, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((4, Event::Finished)));
+        assert_eq!(s.poll_server(), Ok((push_id, Event::CancelPush)));
+        assert_eq!(s.server.push_stream_id(push_id), None);
 
-        assert_eq!(s.poll_server(), Ok((8, Event::Data)));
-        assert_eq!(s.recv_body_server(8, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_body_server(8, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((8, Event::Finished)));
+        // The push stream was reset.
+        assert_eq!(
+            s.send_body_server(push_stream, true),
+            Err(Error::FrameUnexpected)
+        );
+    }
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+    #[test]
+    /// A second push stream for the same push ID is an error.
+    fn duplicate_push_stream() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(10);
+        config.verify_peer(false);
 
-        let mut resps = Vec::new();
+        let h3_config = Config::new().unwrap();
 
-        let resp1 = s.send_response(stream1, true).unwrap();
-        resps.push(resp1);
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        s.handshake().unwrap();
 
-        let resp2 = s.send_response(stream2, true).unwrap();
-        resps.push(resp2);
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        let resp3 = s.send_response(stream3, true).unwrap();
-        resps.push(resp3);
+        let (stream, _) = s.send_request(true).unwrap();
+        s.poll_server().unwrap();
 
-        for _ in 0..resps.len() {
-            let (stream, ev) = s.poll_client().unwrap();
-            let ev_headers = Event::Headers {
-                list: resps[(stream / 4) as usize].clone(),
-                more_frames: false,
-            };
-            assert_eq!(ev, ev_headers);
-            assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        let push_req = push_request();
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &push_req)
+            .unwrap();
+
+        let resp = vec![Header::new(b":status", b"200")];
+
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, push_id, &resp, false)
+            .unwrap();
+        s.advance().ok();
+
+        while s.poll_client().is_ok() {
+            // Do nothing.
         }
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Open another push stream that reuses the same push ID.
+        let mut d = [42; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        b.put_varint(stream::HTTP3_PUSH_STREAM_TYPE_ID).unwrap();
+        b.put_varint(push_id).unwrap();
+        let off = b.off();
+
+        s.pipe
+            .server
+            .stream_send(push_stream + 4, &d[..off], false)
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_client(), Err(Error::IdError));
+        assert_eq!(s.client.push_stream_id(push_id), Some(push_stream));
     }
 
     #[test]
-    /// Send a request with no body, get a response with one DATA frame and an
-    /// empty FIN after reception from the client.
-    fn request_no_body_response_one_chunk_empty_fin() {
+    /// Cancel a push from the server before it's fulfilled.
+    fn cancel_push_by_server() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
-
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
-
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        let resp = s.send_response(stream, false).unwrap();
+        let (stream, _) = s.send_request(true).unwrap();
+        s.poll_server().unwrap();
 
-        let body = s.send_body_server(stream, false).unwrap();
+        // Pushes that weren't promised can't be cancelled.
+        assert_eq!(
+            s.server.cancel_push(&mut s.pipe.server, 0),
+            Err(Error::IdError)
+        );
 
-        let mut recv_buf = vec![0; body.len()];
+        let push_req = push_request();
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &push_req)
+            .unwrap();
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: true,
+        let ev_push_promise = Event::PushPromise {
+            push_id,
+            list: push_req.clone(),
         };
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Ok((stream, ev_push_promise)));
 
-        assert_eq!(s.pipe.server.stream_send(stream, &[], true), Ok(0));
+        assert_eq!(s.server.cancel_push(&mut s.pipe.server, push_id), Ok(()));
         s.advance().ok();
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_client(), Ok((push_id, Event::CancelPush)));
+
+        assert_eq!(
+            s.server.send_push_response(
+                &mut s.pipe.server,
+                push_id,
+                &push_req,
+                true
+            ),
+            Err(Error::IdError)
+        );
     }
 
     #[test]
-    /// Send a request with no body, get a response with no body followed by
-    /// GREASE that is STREAM frame with a FIN.
-    fn request_no_body_response_no_body_with_grease() {
+    /// A client GOAWAY limits the push IDs the server can use.
+    fn goaway_from_client_limits_push() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        s.client.send_max_push_id(&mut s.pipe.client, 5).unwrap();
+        s.client.send_goaway(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        assert_eq!(stream, 0);
+        assert_eq!(s.poll_server(), Ok((1, Event::GoAway)));
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
+        let (stream, _) = s.send_request(false).unwrap();
+        s.poll_server().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        let push_req = push_request();
 
-        let resp = s.send_response(stream, false).unwrap();
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Ok(0)
+        );
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: true,
-        };
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Err(Error::IdError)
+        );
+    }
 
-        // Inject a GREASE frame
-        let mut d = [42; 10];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+    #[test]
+    /// Send a GOAWAY frame from the client.
+    fn goaway_from_client_good() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
-        s.pipe.server.stream_send(0, frame_type, false).unwrap();
+        s.client.send_goaway(&mut s.pipe.client, 100).unwrap();
 
-        let frame_len = b.put_varint(10).unwrap();
-        s.pipe.server.stream_send(0, frame_len, false).unwrap();
+        s.advance().ok();
 
-        s.pipe.server.stream_send(0, &d, true).unwrap();
+        assert_eq!(s.poll_server(), Ok((100, Event::GoAway)));
+    }
+
+    #[test]
+    /// Send a GOAWAY frame from the server.
+    fn goaway_from_server_good() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.server.send_goaway(&mut s.pipe.server, 4000).unwrap();
 
         s.advance().ok();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_client(), Ok((4000, Event::GoAway)));
     }
 
     #[test]
-    /// Try to send DATA frames before HEADERS.
-    fn body_response_before_headers() {
+    /// A client MUST NOT send a request after it receives GOAWAY.
+    fn client_request_after_goaway() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
-        assert_eq!(stream, 0);
+        s.server.send_goaway(&mut s.pipe.server, 4000).unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((4000, Event::GoAway)));
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.send_request(true), Err(Error::FrameUnexpected));
+    }
 
-        assert_eq!(
-            s.send_body_server(stream, true),
-            Err(Error::FrameUnexpected)
-        );
+    #[test]
+    /// Send a GOAWAY frame from the server, using an invalid goaway ID.
+    fn goaway_from_server_invalid_id() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        s.send_frame_server(
+            frame::Frame::GoAway { id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_client(), Err(Error::IdError));
     }
 
     #[test]
-    /// Try to send DATA frames on wrong streams, ensure the API returns an
-    /// error before anything hits the transport layer.
-    fn send_body_invalid_client_stream() {
+    /// Send multiple GOAWAY frames from the server, that increase the goaway
+    /// ID.
+    fn goaway_from_server_increase_id() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        assert_eq!(s.send_body_client(0, true), Err(Error::FrameUnexpected));
+        s.send_frame_server(
+            frame::Frame::GoAway { id: 0 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        s.send_frame_server(
+            frame::Frame::GoAway { id: 4 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_client(), Ok((0, Event::GoAway)));
+
+        assert_eq!(s.poll_client(), Err(Error::IdError));
+    }
 
+    #[test]
+    #[cfg(feature = "sfv")]
+    fn parse_priority_field_value() {
+        // Legal dicts
         assert_eq!(
-            s.send_body_client(s.client.control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(0, false)),
+            Priority::try_from(b"u=0".as_slice())
+        );
+        assert_eq!(
+            Ok(Priority::new(3, false)),
+            Priority::try_from(b"u=3".as_slice())
+        );
+        assert_eq!(
+            Ok(Priority::new(7, false)),
+            Priority::try_from(b"u=7".as_slice())
+        );
+
+        assert_eq!(
+            Ok(Priority::new(0, true)),
+            Priority::try_from(b"u=0, i".as_slice())
+        );
+        assert_eq!(
+            Ok(Priority::new(3, true)),
+            Priority::try_from(b"u=3, i".as_slice())
+        );
+        assert_eq!(
+            Ok(Priority::new(7, true)),
+            Priority::try_from(b"u=7, i".as_slice())
         );
 
         assert_eq!(
-            s.send_body_client(
-                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(0, true)),
+            Priority::try_from(b"u=0, i=?1".as_slice())
         );
-
         assert_eq!(
-            s.send_body_client(
-                s.client.local_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(3, true)),
+            Priority::try_from(b"u=3, i=?1".as_slice())
         );
-
         assert_eq!(
-            s.send_body_client(s.client.peer_control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(7, true)),
+            Priority::try_from(b"u=7, i=?1".as_slice())
         );
 
         assert_eq!(
-            s.send_body_client(
-                s.client.peer_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(3, false)),
+            Priority::try_from(b"".as_slice())
         );
 
         assert_eq!(
-            s.send_body_client(
-                s.client.peer_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(0, true)),
+            Priority::try_from(b"u=0;foo, i;bar".as_slice())
         );
-    }
-
-    #[test]
-    /// Try to send DATA frames on wrong streams, ensure the API returns an
-    /// error before anything hits the transport layer.
-    fn send_body_invalid_server_stream() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
-
-        assert_eq!(s.send_body_server(0, true), Err(Error::FrameUnexpected));
-
         assert_eq!(
-            s.send_body_server(s.server.control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(3, true)),
+            Priority::try_from(b"u=3;hello, i;world".as_slice())
         );
-
         assert_eq!(
-            s.send_body_server(
-                s.server.local_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(7, true)),
+            Priority::try_from(b"u=7;croeso, i;gymru".as_slice())
         );
 
         assert_eq!(
-            s.send_body_server(
-                s.server.local_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(0, true)),
+            Priority::try_from(b"u=0, i, spinaltap=11".as_slice())
         );
 
+        // Illegal formats
+        assert_eq!(Err(Error::Done), Priority::try_from(b"0".as_slice()));
         assert_eq!(
-            s.send_body_server(s.server.peer_control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(7, false)),
+            Priority::try_from(b"u=-1".as_slice())
         );
-
+        assert_eq!(Err(Error::Done), Priority::try_from(b"u=0.2".as_slice()));
         assert_eq!(
-            s.send_body_server(
-                s.server.peer_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Ok(Priority::new(7, false)),
+            Priority::try_from(b"u=100".as_slice())
         );
-
         assert_eq!(
-            s.send_body_server(
-                s.server.peer_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            Err(Error::Done),
+            Priority::try_from(b"u=3, i=true".as_slice())
         );
+
+        // Trailing comma in dict is malformed
+        assert_eq!(Err(Error::Done), Priority::try_from(b"u=7, ".as_slice()));
     }
 
     #[test]
-    /// Client sends request with body and trailers.
-    fn trailers() {
+    /// Send a PRIORITY_UPDATE for request stream from the client.
+    fn priority_update_request() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
 
-        let body = s.send_body_client(stream, false).unwrap();
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-        let mut recv_buf = vec![0; body.len()];
+    #[test]
+    /// Send a PRIORITY_UPDATE for request stream from the client.
+    fn priority_update_single_stream_rearm() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        let req_trailers = vec![Header::new(b"foo", b"bar")];
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
         s.client
-            .send_additional_headers(
-                &mut s.pipe.client,
-                stream,
-                &req_trailers,
-                true,
-                true,
-            )
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 5,
+                incremental: false,
+            })
             .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // There is only one PRIORITY_UPDATE frame to read. Once read, the event
+        // will rearm ready for more.
+        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=5".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
 
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 7,
+                incremental: false,
+            })
+            .unwrap();
         s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=7".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send multiple PRIORITY_UPDATE frames for different streams from the
+    /// client across multiple flights of exchange.
+    fn priority_update_request_multiple_stream_arm_multiple_flights() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 4, &Priority {
+                urgency: 1,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((4, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 8, &Priority {
+                urgency: 2,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((8, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(4), Ok(b"u=1".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(8), Ok(b"u=2".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send multiple PRIORITY_UPDATE frames for different streams from the
+    /// client across a single flight.
+    fn priority_update_request_multiple_stream_arm_single_flight() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let mut d = [42; 65535];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let p1 = frame::Frame::PriorityUpdateRequest {
+            prioritized_element_id: 0,
+            priority_field_value: b"u=3".to_vec(),
         };
 
-        let ev_trailers = Event::Headers {
-            list: req_trailers,
-            more_frames: false,
+        let p2 = frame::Frame::PriorityUpdateRequest {
+            prioritized_element_id: 4,
+            priority_field_value: b"u=3".to_vec(),
         };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        let p3 = frame::Frame::PriorityUpdateRequest {
+            prioritized_element_id: 8,
+            priority_field_value: b"u=3".to_vec(),
+        };
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        p1.to_bytes(&mut b).unwrap();
+        p2.to_bytes(&mut b).unwrap();
+        p3.to_bytes(&mut b).unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        let off = b.off();
+        s.pipe
+            .client
+            .stream_send(s.client.control_stream_id.unwrap(), &d[..off], false)
+            .unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Ok((4, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Ok((8, Event::PriorityUpdate)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(4), Ok(b"u=3".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(8), Ok(b"u=3".to_vec()));
+
+        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
     }
 
     #[test]
-    /// Server responds with a 103, then a 200 with no body.
-    fn informational_response() {
+    /// Send a PRIORITY_UPDATE for a request stream, before and after the stream
+    /// has been completed.
+    fn priority_update_request_collected_completed() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
-
-        assert_eq!(stream, 0);
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
 
+        let (stream, req) = s.send_request(true).unwrap();
         let ev_headers = Event::Headers {
             list: req,
             more_frames: false,
         };
 
+        // Priority event is generated before request headers.
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let info_resp = vec![
-            Header::new(b":status", b"103"),
-            Header::new(b"link", b"<https://example.com>; rel=\"preconnect\""),
-        ];
-
-        let resp = vec![
-            Header::new(b":status", b"200"),
-            Header::new(b"server", b"quiche-test"),
-        ];
-
-        s.server
-            .send_response(&mut s.pipe.server, stream, &info_resp, false)
-            .unwrap();
-
-        s.server
-            .send_additional_headers(
-                &mut s.pipe.server,
-                stream,
-                &resp,
-                false,
-                true,
-            )
-            .unwrap();
-
-        s.advance().ok();
+        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
 
-        let ev_info_headers = Event::Headers {
-            list: info_resp,
-            more_frames: true,
-        };
+        let resp = s.send_response(stream, true).unwrap();
 
         let ev_headers = Event::Headers {
             list: resp,
             more_frames: false,
         };
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_info_headers)));
         assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
+
+        // Now send a PRIORITY_UPDATE for the completed request stream.
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
+
+        // No event generated at server
+        assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Server responds with a 103, then attempts to send a 200 using
-    /// send_response again, which should fail.
-    fn no_multiple_response() {
+    /// Send a PRIORITY_UPDATE for a request stream, before and after the stream
+    /// has been stopped.
+    fn priority_update_request_collected_stopped() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
-
-        assert_eq!(stream, 0);
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
+        s.advance().ok();
 
+        let (stream, req) = s.send_request(false).unwrap();
         let ev_headers = Event::Headers {
             list: req,
-            more_frames: false,
+            more_frames: true,
         };
 
+        // Priority event is generated before request headers.
+        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        let info_resp = vec![
-            Header::new(b":status", b"103"),
-            Header::new(b"link", b"<https://example.com>; rel=\"preconnect\""),
-        ];
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let resp = vec![
-            Header::new(b":status", b"200"),
-            Header::new(b"server", b"quiche-test"),
-        ];
+        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
+        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
 
-        s.server
-            .send_response(&mut s.pipe.server, stream, &info_resp, false)
+        s.pipe
+            .client
+            .stream_shutdown(stream, crate::Shutdown::Write, 0x100)
+            .unwrap();
+        s.pipe
+            .client
+            .stream_shutdown(stream, crate::Shutdown::Read, 0x100)
             .unwrap();
 
-        assert_eq!(
-            Err(Error::FrameUnexpected),
-            s.server
-                .send_response(&mut s.pipe.server, stream, &resp, true)
-        );
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::Reset(0x100))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
+        // Now send a PRIORITY_UPDATE for the closed request stream.
+        s.client
+            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
+                urgency: 3,
+                incremental: false,
+            })
+            .unwrap();
         s.advance().ok();
 
-        let ev_info_headers = Event::Headers {
-            list: info_resp,
-            more_frames: true,
-        };
+        // No event generated at server
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_info_headers)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert!(s.pipe.server.streams.is_collected(0));
+        assert!(s.pipe.client.streams.is_collected(0));
     }
 
     #[test]
-    /// Server attempts to use send_additional_headers before initial response.
-    fn no_send_additional_before_initial_response() {
+    /// Send a PRIORITY_UPDATE for push stream from the client.
+    fn priority_update_push() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        s.send_frame_client(
+            frame::Frame::PriorityUpdatePush {
+                prioritized_element_id: 3,
+                priority_field_value: b"u=3".to_vec(),
+            },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(stream, 0);
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
+    #[test]
+    /// Send a PRIORITY_UPDATE for request stream from the client but for an
+    /// incorrect stream type.
+    fn priority_update_request_bad_stream() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        s.send_frame_client(
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: 5,
+                priority_field_value: b"u=3".to_vec(),
+            },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        let info_resp = vec![
-            Header::new(b":status", b"103"),
-            Header::new(b"link", b"<https://example.com>; rel=\"preconnect\""),
-        ];
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-        assert_eq!(
-            Err(Error::FrameUnexpected),
-            s.server.send_additional_headers(
-                &mut s.pipe.server,
-                stream,
-                &info_resp,
-                false,
-                false
-            )
-        );
+    #[test]
+    /// Send a PRIORITY_UPDATE for push stream from the client but for an
+    /// incorrect stream type.
+    fn priority_update_push_bad_stream() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        s.advance().ok();
+        s.send_frame_client(
+            frame::Frame::PriorityUpdatePush {
+                prioritized_element_id: 5,
+                priority_field_value: b"u=3".to_vec(),
+            },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
     }
 
     #[test]
-    /// Client sends multiple HEADERS before data.
-    fn additional_headers_before_data_client() {
+    /// Send a PRIORITY_UPDATE for request stream from the server.
+    fn priority_update_request_from_server() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        s.send_frame_server(
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: 0,
+                priority_field_value: b"u=3".to_vec(),
+            },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        let req_trailer = vec![Header::new(b"goodbye", b"world")];
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+    }
 
-        assert_eq!(
-            s.client.send_additional_headers(
-                &mut s.pipe.client,
-                stream,
-                &req_trailer,
-                true,
-                false
-            ),
-            Ok(())
-        );
+    #[test]
+    /// Send a PRIORITY_UPDATE for request stream from the server.
+    fn priority_update_push_from_server() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        s.advance().ok();
+        s.send_frame_server(
+            frame::Frame::PriorityUpdatePush {
+                prioritized_element_id: 0,
+                priority_field_value: b"u=3".to_vec(),
+            },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        let ev_initial_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+    }
+
+    #[test]
+    /// Ensure quiche allocates streams for client and server roles as expected.
+    fn uni_stream_local_counting() {
+        let config = Config::new().unwrap();
 
-        let ev_trailing_headers = Event::Headers {
-            list: req_trailer,
-            more_frames: true,
-        };
+        let h3_cln = Connection::new(&config, false, false).unwrap();
+        assert_eq!(h3_cln.next_uni_stream_id, 2);
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_initial_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, ev_trailing_headers)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let h3_srv = Connection::new(&config, true, false).unwrap();
+        assert_eq!(h3_srv.next_uni_stream_id, 3);
     }
 
     #[test]
-    /// Client sends multiple HEADERS before data.
-    fn data_after_trailers_client() {
+    /// Client opens multiple control streams, which is forbidden.
+    fn open_multiple_control_streams() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
-
-        let body = s.send_body_client(stream, false).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
+        let stream_id = s.client.next_uni_stream_id;
 
-        let req_trailers = vec![Header::new(b"foo", b"bar")];
+        let mut d = [42; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        s.client
-            .send_additional_headers(
-                &mut s.pipe.client,
-                stream,
-                &req_trailers,
-                true,
+        s.pipe
+            .client
+            .stream_send(
+                stream_id,
+                b.put_varint(stream::HTTP3_CONTROL_STREAM_TYPE_ID).unwrap(),
                 false,
             )
             .unwrap();
 
         s.advance().ok();
 
-        s.send_frame_client(
-            frame::Frame::Data {
-                payload: vec![1, 2, 3, 4],
-            },
-            stream,
-            true,
-        )
-        .unwrap();
+        assert_eq!(s.poll_server(), Err(Error::StreamCreationError));
+    }
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+    #[test]
+    /// Client closes the control stream, which is forbidden.
+    fn close_control_stream_after_type() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        let ev_trailers = Event::Headers {
-            list: req_trailers,
-            more_frames: true,
-        };
+        s.pipe
+            .client
+            .stream_send(s.client.control_stream_id.unwrap(), &[], true)
+            .unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        s.advance().ok();
+
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the client on a valid stream.
-    fn max_push_id_from_client_good() {
+    /// Client closes the control stream after a frame is sent, which is
+    /// forbidden.
+    fn close_control_stream_after_frame() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
         s.send_frame_client(
             frame::Frame::MaxPushId { push_id: 1 },
             s.client.control_stream_id.unwrap(),
-            false,
+            true,
         )
         .unwrap();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the client on an invalid stream.
-    fn max_push_id_from_client_bad_stream() {
+    /// Client resets the control stream, which is forbidden.
+    fn reset_control_stream_after_type() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
-
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 2 },
-            stream,
-            false,
-        )
-        .unwrap();
+        s.pipe
+            .client
+            .stream_shutdown(
+                s.client.control_stream_id.unwrap(),
+                crate::Shutdown::Write,
+                0,
+            )
+            .unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a sequence of MAX_PUSH_ID frames from the client that attempt to
-    /// reduce the limit.
-    fn max_push_id_from_client_limit_reduction() {
+    /// Client resets the control stream after a frame is sent, which is
+    /// forbidden.
+    fn reset_control_stream_after_frame() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 2 },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
-
         s.send_frame_client(
             frame::Frame::MaxPushId { push_id: 1 },
             s.client.control_stream_id.unwrap(),
@@ -4444,1017 +7434,1197 @@ mod tests {
         )
         .unwrap();
 
-        assert_eq!(s.poll_server(), Err(Error::IdError));
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+
+        s.pipe
+            .client
+            .stream_shutdown(
+                s.client.control_stream_id.unwrap(),
+                crate::Shutdown::Write,
+                0,
+            )
+            .unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the server, which is forbidden.
-    fn max_push_id_from_server() {
+    /// Client closes QPACK stream, which is forbidden.
+    fn close_qpack_stream_after_type() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        s.pipe
+            .client
+            .stream_send(
+                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
+                &[],
+                true,
+            )
+            .unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+        s.advance().ok();
+
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a PUSH_PROMISE frame from the client, which is forbidden.
-    fn push_promise_from_client() {
+    /// Client closes QPACK stream after sending some stuff, which is forbidden.
+    fn close_qpack_stream_after_data() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
 
-        let header_block = s.client.encode_header_block(&req).unwrap();
+        // Set Dynamic Table Capacity instruction with a value of 0.
+        let d = [0x20; 1];
 
-        s.send_frame_client(
-            frame::Frame::PushPromise {
-                push_id: 1,
-                header_block,
-            },
-            stream,
-            false,
-        )
-        .unwrap();
+        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
+        s.pipe.client.stream_send(stream_id, &d, true).unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a CANCEL_PUSH frame from the client.
-    fn cancel_push_from_client() {
+    /// Client resets QPACK stream, which is forbidden.
+    fn reset_qpack_stream_after_type() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::CancelPush { push_id: 1 },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        s.pipe
+            .client
+            .stream_shutdown(
+                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
+                crate::Shutdown::Write,
+                0,
+            )
+            .unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+    }
+
+    #[test]
+    /// Client resets QPACK stream after sending some stuff, which is forbidden.
+    fn reset_qpack_stream_after_data() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
+
+        // Set Dynamic Table Capacity instruction with a value of 0.
+        let d = [0x20; 1];
+
+        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
+        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+
+        s.pipe
+            .client
+            .stream_shutdown(stream_id, crate::Shutdown::Write, 0)
+            .unwrap();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.advance().ok();
+
+        assert_eq!(
+            Err(Error::ClosedCriticalStream),
+            s.server.poll(&mut s.pipe.server)
+        );
+        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Send a CANCEL_PUSH frame from the client on an invalid stream.
-    fn cancel_push_from_client_bad_stream() {
+    /// Client sends QPACK data.
+    fn qpack_data() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        let e_stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
+        let d_stream_id = s.client.local_qpack_streams.decoder_stream_id.unwrap();
 
-        s.send_frame_client(
-            frame::Frame::CancelPush { push_id: 2 },
-            stream,
-            false,
-        )
-        .unwrap();
+        // Set Dynamic Table Capacity instructions with a value of 0.
+        let d = [0x20; 20];
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        s.pipe.client.stream_send(e_stream_id, &d, false).unwrap();
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
-    }
+        // Stream Cancellation instructions for stream 0.
+        let d = [0x40; 20];
 
-    #[test]
-    /// Send a CANCEL_PUSH frame from the client.
-    fn cancel_push_from_server() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        s.pipe.client.stream_send(d_stream_id, &d, false).unwrap();
+        s.advance().ok();
 
-        s.send_frame_server(
-            frame::Frame::CancelPush { push_id: 1 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        match s.server.poll(&mut s.pipe.server) {
+            Ok(_) => panic!(),
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+            Err(Error::Done) => {
+                assert_eq!(s.server.peer_qpack_streams.encoder_stream_bytes, 20);
+                assert_eq!(s.server.peer_qpack_streams.decoder_stream_bytes, 20);
+            },
+
+            Err(_) => {
+                panic!();
+            },
+        }
+
+        let stats = s.server.stats();
+        assert_eq!(stats.qpack_encoder_stream_recv_bytes, 20);
+        assert_eq!(stats.qpack_decoder_stream_recv_bytes, 20);
     }
 
     #[test]
-    /// Send a GOAWAY frame from the client.
-    fn goaway_from_client_good() {
+    /// Client sends an invalid instruction on the QPACK encoder stream.
+    fn qpack_encoder_stream_error() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.client.send_goaway(&mut s.pipe.client, 100).unwrap();
+        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
 
+        // Set Dynamic Table Capacity instruction exceeding the server's
+        // maximum of 0.
+        let d = [0x21; 1];
+
+        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
         s.advance().ok();
 
-        // TODO: server push
-        assert_eq!(s.poll_server(), Ok((0, Event::GoAway)));
+        assert_eq!(
+            Err(Error::QpackEncoderStreamError),
+            s.server.poll(&mut s.pipe.server)
+        );
     }
 
     #[test]
-    /// Send a GOAWAY frame from the server.
-    fn goaway_from_server_good() {
+    /// Client sends an invalid instruction on the QPACK decoder stream.
+    fn qpack_decoder_stream_error() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.server.send_goaway(&mut s.pipe.server, 4000).unwrap();
+        let stream_id = s.client.local_qpack_streams.decoder_stream_id.unwrap();
 
+        // Section Acknowledgement for a stream that has no field sections.
+        let d = [0x80; 1];
+
+        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
         s.advance().ok();
 
-        assert_eq!(s.poll_client(), Ok((4000, Event::GoAway)));
+        assert_eq!(
+            Err(Error::QpackDecoderStreamError),
+            s.server.poll(&mut s.pipe.server)
+        );
     }
 
     #[test]
-    /// A client MUST NOT send a request after it receives GOAWAY.
-    fn client_request_after_goaway() {
-        let mut s = Session::new().unwrap();
+    /// Headers are compressed using the QPACK dynamic table in both
+    /// directions.
+    fn qpack_dynamic_table() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(10000);
+        config.set_initial_max_stream_data_bidi_local(1000);
+        config.set_initial_max_stream_data_bidi_remote(1000);
+        config.set_initial_max_stream_data_uni(1000);
+        config.set_initial_max_streams_bidi(10);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_qpack_max_table_capacity(4096);
+        h3_config.set_qpack_blocked_streams(16);
+        h3_config.set_qpack_encoder_max_table_capacity(1024);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
         s.handshake().unwrap();
 
-        s.server.send_goaway(&mut s.pipe.server, 4000).unwrap();
+        assert_eq!(s.client.qpack_encoder.table_capacity(), 1024);
+        assert_eq!(s.server.qpack_encoder.table_capacity(), 1024);
 
-        s.advance().ok();
+        for _ in 0..3 {
+            let (stream, req) = s.send_request(true).unwrap();
 
-        assert_eq!(s.poll_client(), Ok((4000, Event::GoAway)));
+            let ev_headers = Event::Headers {
+                list: req,
+                more_frames: false,
+            };
 
-        assert_eq!(s.send_request(true), Err(Error::FrameUnexpected));
-    }
+            assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+            assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+            assert_eq!(s.poll_server(), Err(Error::Done));
 
-    #[test]
-    /// Send a GOAWAY frame from the server, using an invalid goaway ID.
-    fn goaway_from_server_invalid_id() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+            let resp = s.send_response(stream, true).unwrap();
 
-        s.send_frame_server(
-            frame::Frame::GoAway { id: 1 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+            let ev_headers = Event::Headers {
+                list: resp,
+                more_frames: false,
+            };
 
-        assert_eq!(s.poll_client(), Err(Error::IdError));
+            assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+            assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+            assert_eq!(s.poll_client(), Err(Error::Done));
+
+            s.advance().ok();
+
+            // Process acknowledgements.
+            assert_eq!(s.poll_server(), Err(Error::Done));
+        }
+
+        // Only the first request and response inserted entries.
+        assert_eq!(s.client.qpack_encoder.insert_count(), 3);
+        assert_eq!(s.server.qpack_decoder.insert_count(), 3);
+        assert_eq!(s.client.qpack_encoder.known_received_count(), 3);
+
+        assert_eq!(s.server.qpack_encoder.insert_count(), 1);
+        assert_eq!(s.client.qpack_decoder.insert_count(), 1);
+        assert_eq!(s.server.qpack_encoder.known_received_count(), 1);
     }
 
     #[test]
-    /// Send multiple GOAWAY frames from the server, that increase the goaway
-    /// ID.
-    fn goaway_from_server_increase_id() {
-        let mut s = Session::new().unwrap();
+    /// The server's HTTP/3 state, including the QPACK dynamic tables, is
+    /// exported and restored along with its QUIC connection.
+    fn export_and_restore_state() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(10000);
+        config.set_initial_max_stream_data_bidi_local(1000);
+        config.set_initial_max_stream_data_bidi_remote(1000);
+        config.set_initial_max_stream_data_uni(1000);
+        config.set_initial_max_streams_bidi(10);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_qpack_max_table_capacity(4096);
+        h3_config.set_qpack_blocked_streams(16);
+        h3_config.set_qpack_encoder_max_table_capacity(1024);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::GoAway { id: 0 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        // Populate the dynamic tables in both directions.
+        let (stream, req) = s.send_request(true).unwrap();
 
-        s.send_frame_server(
-            frame::Frame::GoAway { id: 4 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
 
-        assert_eq!(s.poll_client(), Ok((0, Event::GoAway)));
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        assert_eq!(s.poll_client(), Err(Error::IdError));
-    }
+        s.send_response(stream, true).unwrap();
+        assert!(s.poll_client().is_ok());
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
 
-    #[test]
-    #[cfg(feature = "sfv")]
-    fn parse_priority_field_value() {
-        // Legal dicts
-        assert_eq!(
-            Ok(Priority::new(0, false)),
-            Priority::try_from(b"u=0".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(3, false)),
-            Priority::try_from(b"u=3".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(7, false)),
-            Priority::try_from(b"u=7".as_slice())
-        );
+        s.advance().ok();
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(
-            Ok(Priority::new(0, true)),
-            Priority::try_from(b"u=0, i".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(3, true)),
-            Priority::try_from(b"u=3, i".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(7, true)),
-            Priority::try_from(b"u=7, i".as_slice())
-        );
+        // The server reads the request's headers, but not its body.
+        let (stream, req) = s.send_request(false).unwrap();
+        let body = s.send_body_client(stream, true).unwrap();
 
-        assert_eq!(
-            Ok(Priority::new(0, true)),
-            Priority::try_from(b"u=0, i=?1".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(3, true)),
-            Priority::try_from(b"u=3, i=?1".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(7, true)),
-            Priority::try_from(b"u=7, i=?1".as_slice())
-        );
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            more_frames: true,
+        };
 
-        assert_eq!(
-            Ok(Priority::new(3, false)),
-            Priority::try_from(b"".as_slice())
-        );
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        assert_eq!(
-            Ok(Priority::new(0, true)),
-            Priority::try_from(b"u=0;foo, i;bar".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(3, true)),
-            Priority::try_from(b"u=3;hello, i;world".as_slice())
-        );
-        assert_eq!(
-            Ok(Priority::new(7, true)),
-            Priority::try_from(b"u=7;croeso, i;gymru".as_slice())
-        );
+        let quic_state = s.pipe.server.export_state().unwrap();
+        let h3_state = s.server.export_state();
+
+        s.pipe.server = crate::restore(&quic_state, &mut config).unwrap();
+        s.server = Connection::restore(&s.pipe.server, &h3_state).unwrap();
+
+        assert_eq!(s.server.qpack_decoder.insert_count(), 3);
+        assert_eq!(s.server.qpack_encoder.insert_count(), 1);
+
+        let mut recv_buf = vec![0; body.len()];
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(recv_buf, body);
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Both dynamic tables keep working.
+        let resp = s.send_response(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        assert_eq!(
-            Ok(Priority::new(0, true)),
-            Priority::try_from(b"u=0, i, spinaltap=11".as_slice())
-        );
+        assert_eq!(s.server.qpack_decoder.insert_count(), 3);
 
-        // Illegal formats
-        assert_eq!(Err(Error::Done), Priority::try_from(b"0".as_slice()));
+        // The state can only be restored on top of a matching connection.
+        let h3_state = s.client.export_state();
         assert_eq!(
-            Ok(Priority::new(7, false)),
-            Priority::try_from(b"u=-1".as_slice())
-        );
-        assert_eq!(Err(Error::Done), Priority::try_from(b"u=0.2".as_slice()));
-        assert_eq!(
-            Ok(Priority::new(7, false)),
-            Priority::try_from(b"u=100".as_slice())
+            Connection::restore(&s.pipe.server, &h3_state).err(),
+            Some(Error::TransportError(crate::Error::InvalidSnapshot))
         );
+
         assert_eq!(
-            Err(Error::Done),
-            Priority::try_from(b"u=3, i=true".as_slice())
+            Connection::restore(&s.pipe.server, &quic_state).err(),
+            Some(Error::TransportError(crate::Error::InvalidSnapshot))
         );
-
-        // Trailing comma in dict is malformed
-        assert_eq!(Err(Error::Done), Priority::try_from(b"u=7, ".as_slice()));
     }
 
     #[test]
-    /// Send a PRIORITY_UPDATE for request stream from the client.
-    fn priority_update_request() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
-
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
+    /// Headers referencing QPACK dynamic table entries that haven't been
+    /// received yet are delivered once the encoder instructions arrive.
+    fn qpack_blocked_stream() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
-        s.advance().ok();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-    }
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_qpack_max_table_capacity(4096);
+        h3_config.set_qpack_blocked_streams(16);
+        h3_config.set_qpack_encoder_max_table_capacity(1024);
 
-    #[test]
-    /// Send a PRIORITY_UPDATE for request stream from the client.
-    fn priority_update_single_stream_rearm() {
-        let mut s = Session::new().unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
         s.handshake().unwrap();
 
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
-            .unwrap();
-        s.advance().ok();
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
 
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // Send the header block without the encoder instructions.
+        let stream = 0;
+        let header_block = s.client.encode_header_block(stream, &req).unwrap();
+        s.client.qpack_encoder.commit_field_section();
 
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 5,
-                incremental: false,
-            })
+        s.send_frame_client(frame::Frame::Headers { header_block }, stream, true)
             .unwrap();
-        s.advance().ok();
 
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // There is only one PRIORITY_UPDATE frame to read. Once read, the event
-        // will rearm ready for more.
-        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=5".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
-
         s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 7,
-                incremental: false,
-            })
+            .flush_qpack_encoder_stream(&mut s.pipe.client)
             .unwrap();
         s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=7".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
+        // The server acknowledged the header block.
+        s.advance().ok();
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.client.qpack_encoder.known_received_count(), 2);
     }
 
     #[test]
-    /// Send multiple PRIORITY_UPDATE frames for different streams from the
-    /// client across multiple flights of exchange.
-    fn priority_update_request_multiple_stream_arm_multiple_flights() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
-
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
+    /// The dynamic table is not used unless both endpoints enable it.
+    fn qpack_dynamic_table_disabled_by_peer() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
             .unwrap();
-        s.advance().ok();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_qpack_encoder_max_table_capacity(1024);
 
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 4, &Priority {
-                urgency: 1,
-                incremental: false,
-            })
-            .unwrap();
-        s.advance().ok();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((4, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.client.qpack_encoder.table_capacity(), 0);
+        assert_eq!(s.server.qpack_encoder.table_capacity(), 0);
 
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 8, &Priority {
-                urgency: 2,
-                incremental: false,
-            })
-            .unwrap();
-        s.advance().ok();
+        let (stream, req) = s.send_request(true).unwrap();
 
-        assert_eq!(s.poll_server(), Ok((8, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
 
-        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(4), Ok(b"u=1".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(8), Ok(b"u=2".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.client.qpack_encoder.insert_count(), 0);
     }
 
     #[test]
-    /// Send multiple PRIORITY_UPDATE frames for different streams from the
-    /// client across a single flight.
-    fn priority_update_request_multiple_stream_arm_single_flight() {
+    /// Tests limits for the stream state buffer maximum size.
+    fn max_state_buf_size() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let mut d = [42; 65535];
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+            Header::new(b"user-agent", b"quiche-test"),
+        ];
 
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, false),
+            Ok(0)
+        );
 
-        let p1 = frame::Frame::PriorityUpdateRequest {
-            prioritized_element_id: 0,
-            priority_field_value: b"u=3".to_vec(),
-        };
+        s.advance().ok();
 
-        let p2 = frame::Frame::PriorityUpdateRequest {
-            prioritized_element_id: 4,
-            priority_field_value: b"u=3".to_vec(),
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
         };
 
-        let p3 = frame::Frame::PriorityUpdateRequest {
-            prioritized_element_id: 8,
-            priority_field_value: b"u=3".to_vec(),
-        };
+        assert_eq!(s.server.poll(&mut s.pipe.server), Ok((0, ev_headers)));
 
-        p1.to_bytes(&mut b).unwrap();
-        p2.to_bytes(&mut b).unwrap();
-        p3.to_bytes(&mut b).unwrap();
+        // DATA frames don't consume the state buffer, so can be of any size.
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        let off = b.off();
-        s.pipe
-            .client
-            .stream_send(s.client.control_stream_id.unwrap(), &d[..off], false)
-            .unwrap();
+        let frame_type = b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+        s.pipe.client.stream_send(0, frame_type, false).unwrap();
 
-        s.advance().ok();
+        let frame_len = b.put_varint(1 << 24).unwrap();
+        s.pipe.client.stream_send(0, frame_len, false).unwrap();
 
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Ok((4, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Ok((8, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.pipe.client.stream_send(0, &d, false).unwrap();
 
-        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(4), Ok(b"u=3".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(8), Ok(b"u=3".to_vec()));
+        s.advance().ok();
 
-        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
-    }
+        assert_eq!(s.server.poll(&mut s.pipe.server), Ok((0, Event::Data)));
 
-    #[test]
-    /// Send a PRIORITY_UPDATE for a request stream, before and after the stream
-    /// has been completed.
-    fn priority_update_request_collected_completed() {
+        // GREASE frames consume the state buffer, so need to be limited.
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
-            .unwrap();
-        s.advance().ok();
-
-        let (stream, req) = s.send_request(true).unwrap();
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
-
-        // Priority event is generated before request headers.
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        let resp = s.send_response(stream, true).unwrap();
+        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
+        s.pipe.client.stream_send(0, frame_type, false).unwrap();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: false,
-        };
+        let frame_len = b.put_varint(1 << 24).unwrap();
+        s.pipe.client.stream_send(0, frame_len, false).unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        s.pipe.client.stream_send(0, &d, false).unwrap();
 
-        // Now send a PRIORITY_UPDATE for the completed request stream.
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
-            .unwrap();
         s.advance().ok();
 
-        // No event generated at server
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::ExcessiveLoad));
     }
 
     #[test]
-    /// Send a PRIORITY_UPDATE for a request stream, before and after the stream
-    /// has been stopped.
-    fn priority_update_request_collected_stopped() {
+    /// Tests that DATA frames are properly truncated depending on the request
+    /// stream's outgoing flow control capacity.
+    fn stream_backpressure() {
+        let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
-            .unwrap();
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let total_data_frames = 6;
+
+        for _ in 0..total_data_frames {
+            assert_eq!(
+                s.client
+                    .send_body(&mut s.pipe.client, stream, &bytes, false),
+                Ok(bytes.len())
+            );
+
+            s.advance().ok();
+        }
+
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, stream, &bytes, true),
+            Ok(bytes.len() - 2)
+        );
+
         s.advance().ok();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        let mut recv_buf = vec![0; bytes.len()];
+
         let ev_headers = Event::Headers {
             list: req,
             more_frames: true,
         };
 
-        // Priority event is generated before request headers.
-        assert_eq!(s.poll_server(), Ok((0, Event::PriorityUpdate)));
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.server.take_last_priority_update(0), Ok(b"u=3".to_vec()));
-        assert_eq!(s.server.take_last_priority_update(0), Err(Error::Done));
-
-        s.pipe
-            .client
-            .stream_shutdown(stream, crate::Shutdown::Write, 0x100)
-            .unwrap();
-        s.pipe
-            .client
-            .stream_shutdown(stream, crate::Shutdown::Read, 0x100)
-            .unwrap();
+        for _ in 0..total_data_frames {
+            assert_eq!(
+                s.recv_body_server(stream, &mut recv_buf),
+                Ok(bytes.len())
+            );
+        }
 
-        s.advance().ok();
+        assert_eq!(
+            s.recv_body_server(stream, &mut recv_buf),
+            Ok(bytes.len() - 2)
+        );
 
-        assert_eq!(s.poll_server(), Ok((0, Event::Reset(0x100))));
+        // Fin flag from last send_body() call was not sent as the buffer was
+        // only partially written.
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Now send a PRIORITY_UPDATE for the closed request stream.
-        s.client
-            .send_priority_update_for_request(&mut s.pipe.client, 0, &Priority {
-                urgency: 3,
-                incremental: false,
-            })
-            .unwrap();
-        s.advance().ok();
-
-        // No event generated at server
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.pipe.server.data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.server.stream_data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.server.data_blocked_recv_count, 0);
+        assert_eq!(s.pipe.server.stream_data_blocked_recv_count, 1);
 
-        assert!(s.pipe.server.streams.is_collected(0));
-        assert!(s.pipe.client.streams.is_collected(0));
+        assert_eq!(s.pipe.client.data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.client.stream_data_blocked_sent_count, 1);
+        assert_eq!(s.pipe.client.data_blocked_recv_count, 0);
+        assert_eq!(s.pipe.client.stream_data_blocked_recv_count, 0);
     }
 
     #[test]
-    /// Send a PRIORITY_UPDATE for push stream from the client.
-    fn priority_update_push() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Tests that the max header list size setting is enforced.
+    fn request_max_header_size_limit() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        s.send_frame_client(
-            frame::Frame::PriorityUpdatePush {
-                prioritized_element_id: 3,
-                priority_field_value: b"u=3".to_vec(),
-            },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_max_field_section_size(65);
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-    }
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
 
-    #[test]
-    /// Send a PRIORITY_UPDATE for request stream from the client but for an
-    /// incorrect stream type.
-    fn priority_update_request_bad_stream() {
-        let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::PriorityUpdateRequest {
-                prioritized_element_id: 5,
-                priority_field_value: b"u=3".to_vec(),
-            },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+            Header::new(b"aaaaaaa", b"aaaaaaaa"),
+        ];
 
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
-    }
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, true)
+            .unwrap();
 
-    #[test]
-    /// Send a PRIORITY_UPDATE for push stream from the client but for an
-    /// incorrect stream type.
-    fn priority_update_push_bad_stream() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        s.advance().ok();
 
-        s.send_frame_client(
-            frame::Frame::PriorityUpdatePush {
-                prioritized_element_id: 5,
-                priority_field_value: b"u=3".to_vec(),
-            },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        assert_eq!(stream, 0);
 
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        assert_eq!(s.poll_server(), Err(Error::ExcessiveLoad));
+
+        assert_eq!(
+            s.pipe.server.local_error.as_ref().unwrap().error_code,
+            Error::to_wire(Error::ExcessiveLoad)
+        );
     }
 
     #[test]
-    /// Send a PRIORITY_UPDATE for request stream from the server.
-    fn priority_update_request_from_server() {
+    /// Tests that Error::TransportError contains a transport error.
+    fn transport_error() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::PriorityUpdateRequest {
-                prioritized_element_id: 0,
-                priority_field_value: b"u=3".to_vec(),
-            },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+            Header::new(b"user-agent", b"quiche-test"),
+        ];
 
-        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+        // We need to open all streams in the same flight, so we can't use the
+        // Session::send_request() method because it also calls advance(),
+        // otherwise the server would send a MAX_STREAMS frame and the client
+        // wouldn't hit the streams limit.
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(4));
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(8));
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Ok(12)
+        );
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Ok(16)
+        );
+
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Err(Error::TransportError(crate::Error::StreamLimit))
+        );
     }
 
     #[test]
-    /// Send a PRIORITY_UPDATE for request stream from the server.
-    fn priority_update_push_from_server() {
+    /// Tests that sending DATA before HEADERS causes an error.
+    fn data_before_headers() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::PriorityUpdatePush {
-                prioritized_element_id: 0,
-                priority_field_value: b"u=3".to_vec(),
-            },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
-    }
+        let frame_type = b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+        s.pipe.client.stream_send(0, frame_type, false).unwrap();
 
-    #[test]
-    /// Ensure quiche allocates streams for client and server roles as expected.
-    fn uni_stream_local_counting() {
-        let config = Config::new().unwrap();
+        let frame_len = b.put_varint(5).unwrap();
+        s.pipe.client.stream_send(0, frame_len, false).unwrap();
 
-        let h3_cln = Connection::new(&config, false, false).unwrap();
-        assert_eq!(h3_cln.next_uni_stream_id, 2);
+        s.pipe.client.stream_send(0, b"hello", false).unwrap();
+
+        s.advance().ok();
 
-        let h3_srv = Connection::new(&config, true, false).unwrap();
-        assert_eq!(h3_srv.next_uni_stream_id, 3);
+        assert_eq!(
+            s.server.poll(&mut s.pipe.server),
+            Err(Error::FrameUnexpected)
+        );
     }
 
     #[test]
-    /// Client opens multiple control streams, which is forbidden.
-    fn open_multiple_control_streams() {
+    /// Tests that calling poll() after an error occurred does nothing.
+    fn poll_after_error() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let stream_id = s.client.next_uni_stream_id;
-
-        let mut d = [42; 8];
+        let mut d = [42; 128];
         let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        s.pipe
-            .client
-            .stream_send(
-                stream_id,
-                b.put_varint(stream::HTTP3_CONTROL_STREAM_TYPE_ID).unwrap(),
-                false,
-            )
-            .unwrap();
+        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
+        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+
+        let frame_len = b.put_varint(1 << 24).unwrap();
+        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+
+        s.pipe.client.stream_send(0, &d, false).unwrap();
 
         s.advance().ok();
 
-        assert_eq!(s.poll_server(), Err(Error::StreamCreationError));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::ExcessiveLoad));
+
+        // Try to call poll() again after an error occurred.
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
     }
 
     #[test]
-    /// Client closes the control stream, which is forbidden.
-    fn close_control_stream_after_type() {
-        let mut s = Session::new().unwrap();
+    /// Tests that we limit sending HEADERS based on the stream capacity.
+    fn headers_blocked() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let h3_config = Config::new().unwrap();
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+
         s.handshake().unwrap();
 
-        s.pipe
-            .client
-            .stream_send(s.client.control_stream_id.unwrap(), &[], true)
-            .unwrap();
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
 
-        s.advance().ok();
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
 
         assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Err(Error::StreamBlocked)
         );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+
+        // Clear the writable stream queue.
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(2));
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(6));
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(10));
+        assert_eq!(s.pipe.client.stream_writable_next(), None);
+
+        s.advance().ok();
+
+        // Once the server gives flow control credits back, we can send the
+        // request.
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(4));
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(4));
+
+        assert_eq!(s.pipe.server.data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.server.stream_data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.server.data_blocked_recv_count, 1);
+        assert_eq!(s.pipe.server.stream_data_blocked_recv_count, 0);
+
+        assert_eq!(s.pipe.client.data_blocked_sent_count, 1);
+        assert_eq!(s.pipe.client.stream_data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.client.data_blocked_recv_count, 0);
+        assert_eq!(s.pipe.client.stream_data_blocked_recv_count, 0);
     }
 
     #[test]
-    /// Client closes the control stream after a frame is sent, which is
-    /// forbidden.
-    fn close_control_stream_after_frame() {
-        let mut s = Session::new().unwrap();
+    /// Ensure StreamBlocked when connection flow control prevents headers.
+    fn headers_blocked_on_conn() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let h3_config = Config::new().unwrap();
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.client.control_stream_id.unwrap(),
-            true,
-        )
-        .unwrap();
+        // After the HTTP handshake, some bytes of connection flow control have
+        // been consumed. Fill the connection with more grease data on the control
+        // stream.
+        let d = [42; 28];
+        assert_eq!(s.pipe.client.stream_send(2, &d, false), Ok(23));
 
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
+
+        // There is 0 connection-level flow control, so sending a request is
+        // blocked.
         assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Err(Error::StreamBlocked)
         );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+        assert_eq!(s.pipe.client.stream_writable_next(), None);
+
+        // Emit the control stream data and drain it at the server via poll() to
+        // consumes it via poll() and gives back flow control.
+        s.advance().ok();
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.advance().ok();
+
+        // Now we can send the request.
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(2));
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(6));
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
+
+        assert_eq!(s.pipe.server.data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.server.stream_data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.server.data_blocked_recv_count, 1);
+        assert_eq!(s.pipe.server.stream_data_blocked_recv_count, 0);
+
+        assert_eq!(s.pipe.client.data_blocked_sent_count, 1);
+        assert_eq!(s.pipe.client.stream_data_blocked_sent_count, 0);
+        assert_eq!(s.pipe.client.data_blocked_recv_count, 0);
+        assert_eq!(s.pipe.client.stream_data_blocked_recv_count, 0);
     }
 
     #[test]
-    /// Client resets the control stream, which is forbidden.
-    fn reset_control_stream_after_type() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Ensure STREAM_DATA_BLOCKED is not emitted multiple times with the same
+    /// offset when trying to send large bodies.
+    fn send_body_truncation_stream_blocked() {
+        use crate::test_utils::decode_pkt;
 
-        s.pipe
-            .client
-            .stream_shutdown(
-                s.client.control_stream_id.unwrap(),
-                crate::Shutdown::Write,
-                0,
-            )
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(10000); // large connection-level flow control
+        config.set_initial_max_stream_data_bidi_local(80);
+        config.set_initial_max_stream_data_bidi_remote(80);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        s.advance().ok();
+        let h3_config = Config::new().unwrap();
 
-        assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
-        );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
-    }
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
 
-    #[test]
-    /// Client resets the control stream after a frame is sent, which is
-    /// forbidden.
-    fn reset_control_stream_after_frame() {
-        let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let (stream, req) = s.send_request(true).unwrap();
 
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
 
-        s.pipe
-            .client
-            .stream_shutdown(
-                s.client.control_stream_id.unwrap(),
-                crate::Shutdown::Write,
-                0,
-            )
-            .unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        s.advance().ok();
+        let _ = s.send_response(stream, false).unwrap();
+
+        assert_eq!(s.pipe.server.streams.blocked().len(), 0);
+
+        // The body must be larger than the stream window would allow
+        let d = [42; 500];
+        let mut off = 0;
+
+        let sent = s
+            .server
+            .send_body(&mut s.pipe.server, stream, &d, true)
+            .unwrap();
+        assert_eq!(sent, 25);
+        off += sent;
 
+        // send_body wrote as much as it could (sent < size of buff).
+        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
         assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
+            s.server
+                .send_body(&mut s.pipe.server, stream, &d[off..], true),
+            Err(Error::Done)
         );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
-    }
+        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
 
-    #[test]
-    /// Client closes QPACK stream, which is forbidden.
-    fn close_qpack_stream_after_type() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        // Now read raw frames to see what the QUIC layer did
+        let mut buf = [0; 65535];
+        let (len, _) = s.pipe.server.send(&mut buf).unwrap();
 
-        s.pipe
-            .client
-            .stream_send(
-                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
-                &[],
-                true,
-            )
-            .unwrap();
+        let frames = decode_pkt(&mut s.pipe.client, &mut buf[..len]).unwrap();
 
-        s.advance().ok();
+        let mut iter = frames.iter();
 
         assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
+            iter.next(),
+            Some(&crate::frame::Frame::StreamDataBlocked {
+                stream_id: 0,
+                limit: 80,
+            })
         );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
-    }
 
-    #[test]
-    /// Client closes QPACK stream after sending some stuff, which is forbidden.
-    fn close_qpack_stream_after_data() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        // At the server, after sending the STREAM_DATA_BLOCKED frame, we clear
+        // the mark.
+        assert_eq!(s.pipe.server.streams.blocked().len(), 0);
 
-        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
-        let d = [0; 1];
+        // Don't read any data from the client, so stream flow control is never
+        // given back in the form of changing the stream's max offset.
+        // Subsequent body send operations will still fail but no more
+        // STREAM_DATA_BLOCKED frames should be submitted since the limit didn't
+        // change. No frames means no packet to send.
+        assert_eq!(
+            s.server
+                .send_body(&mut s.pipe.server, stream, &d[off..], true),
+            Err(Error::Done)
+        );
+        assert_eq!(s.pipe.server.streams.blocked().len(), 0);
+        assert_eq!(s.pipe.server.send(&mut buf), Err(crate::Error::Done));
 
-        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
-        s.pipe.client.stream_send(stream_id, &d, true).unwrap();
+        // Now update the client's max offset manually.
+        let frames = [crate::frame::Frame::MaxStreamData {
+            stream_id: 0,
+            max: 100,
+        }];
 
-        s.advance().ok();
+        let pkt_type = crate::packet::Type::Short;
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39),
+        );
+
+        let sent = s
+            .server
+            .send_body(&mut s.pipe.server, stream, &d[off..], true)
+            .unwrap();
+        assert_eq!(sent, 18);
 
+        // Same thing here...
+        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
         assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
+            s.server
+                .send_body(&mut s.pipe.server, stream, &d[off..], true),
+            Err(Error::Done)
         );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
-    }
+        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
 
-    #[test]
-    /// Client resets QPACK stream, which is forbidden.
-    fn reset_qpack_stream_after_type() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        let (len, _) = s.pipe.server.send(&mut buf).unwrap();
 
-        s.pipe
-            .client
-            .stream_shutdown(
-                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
-                crate::Shutdown::Write,
-                0,
-            )
-            .unwrap();
+        let frames = decode_pkt(&mut s.pipe.client, &mut buf[..len]).unwrap();
 
-        s.advance().ok();
+        let mut iter = frames.iter();
 
         assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
+            iter.next(),
+            Some(&crate::frame::Frame::StreamDataBlocked {
+                stream_id: 0,
+                limit: 100,
+            })
         );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
     }
 
     #[test]
-    /// Client resets QPACK stream after sending some stuff, which is forbidden.
-    fn reset_qpack_stream_after_data() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Ensure stream doesn't hang due to small cwnd.
+    fn send_body_stream_blocked_by_small_cwnd() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(100000); // large connection-level flow control
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
-        let d = [0; 1];
+        let h3_config = Config::new().unwrap();
 
-        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
-        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
 
-        s.advance().ok();
+        s.handshake().unwrap();
 
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
+        let (stream, req) = s.send_request(true).unwrap();
 
-        s.pipe
-            .client
-            .stream_shutdown(stream_id, crate::Shutdown::Write, 0)
-            .unwrap();
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
+        };
 
-        s.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        assert_eq!(
-            Err(Error::ClosedCriticalStream),
-            s.server.poll(&mut s.pipe.server)
-        );
-        assert_eq!(Err(Error::Done), s.server.poll(&mut s.pipe.server));
-    }
+        let _ = s.send_response(stream, false).unwrap();
 
-    #[test]
-    /// Client sends QPACK data.
-    fn qpack_data() {
-        // TODO: QPACK instructions are ignored until dynamic table support is
-        // added so we just test that the data is safely ignored.
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        // Clear the writable stream queue.
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(3));
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(7));
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(11));
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(stream));
+        assert_eq!(s.pipe.server.stream_writable_next(), None);
 
-        let e_stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
-        let d_stream_id = s.client.local_qpack_streams.decoder_stream_id.unwrap();
-        let d = [0; 20];
+        // The body must be larger than the cwnd would allow.
+        let send_buf = [42; 80000];
 
-        s.pipe.client.stream_send(e_stream_id, &d, false).unwrap();
-        s.advance().ok();
+        let sent = s
+            .server
+            .send_body(&mut s.pipe.server, stream, &send_buf, true)
+            .unwrap();
+
+        // send_body wrote as much as it could (sent < size of buff).
+        assert_eq!(sent, 11995);
 
-        s.pipe.client.stream_send(d_stream_id, &d, false).unwrap();
         s.advance().ok();
 
-        match s.server.poll(&mut s.pipe.server) {
-            Ok(_) => panic!(),
+        // Client reads received headers and body.
+        let mut recv_buf = [42; 80000];
+        assert!(s.poll_client().is_ok());
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(11995));
 
-            Err(Error::Done) => {
-                assert_eq!(s.server.peer_qpack_streams.encoder_stream_bytes, 20);
-                assert_eq!(s.server.peer_qpack_streams.decoder_stream_bytes, 20);
-            },
+        s.advance().ok();
 
-            Err(_) => {
-                panic!();
-            },
-        }
+        // Server send cap is smaller than remaining body buffer.
+        assert!(s.pipe.server.tx_cap < send_buf.len() - sent);
 
-        let stats = s.server.stats();
-        assert_eq!(stats.qpack_encoder_stream_recv_bytes, 20);
-        assert_eq!(stats.qpack_decoder_stream_recv_bytes, 20);
+        // Once the server cwnd opens up, we can send more body.
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(0));
     }
 
     #[test]
-    /// Tests limits for the stream state buffer maximum size.
-    fn max_state_buf_size() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Ensure stream doesn't hang due to small cwnd.
+    fn send_body_stream_blocked_zero_length() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(100000); // large connection-level flow control
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
-            Header::new(b"user-agent", b"quiche-test"),
-        ];
+        let h3_config = Config::new().unwrap();
 
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, false),
-            Ok(0)
-        );
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
 
-        s.advance().ok();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
-            more_frames: true,
+            more_frames: false,
         };
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Ok((0, ev_headers)));
-
-        // DATA frames don't consume the state buffer, so can be of any size.
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-
-        let frame_type = b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-        s.pipe.client.stream_send(0, frame_type, false).unwrap();
-
-        let frame_len = b.put_varint(1 << 24).unwrap();
-        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        s.pipe.client.stream_send(0, &d, false).unwrap();
+        let _ = s.send_response(stream, false).unwrap();
 
-        s.advance().ok();
+        // Clear the writable stream queue.
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(3));
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(7));
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(11));
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(stream));
+        assert_eq!(s.pipe.server.stream_writable_next(), None);
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Ok((0, Event::Data)));
+        // The body is large enough to fill the cwnd, except for enough bytes
+        // for another DATA frame header (but no payload).
+        let send_buf = [42; 11994];
 
-        // GREASE frames consume the state buffer, so need to be limited.
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        let sent = s
+            .server
+            .send_body(&mut s.pipe.server, stream, &send_buf, false)
+            .unwrap();
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(sent, 11994);
 
-        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
-        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+        // There is only enough capacity left for the DATA frame header, but
+        // no payload.
+        assert_eq!(s.pipe.server.stream_capacity(stream).unwrap(), 3);
+        assert_eq!(
+            s.server
+                .send_body(&mut s.pipe.server, stream, &send_buf, false),
+            Err(Error::Done)
+        );
 
-        let frame_len = b.put_varint(1 << 24).unwrap();
-        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+        s.advance().ok();
 
-        s.pipe.client.stream_send(0, &d, false).unwrap();
+        // Client reads received headers and body.
+        let mut recv_buf = [42; 80000];
+        assert!(s.poll_client().is_ok());
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(11994));
 
         s.advance().ok();
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::ExcessiveLoad));
+        // Once the server cwnd opens up, we can send more body.
+        assert_eq!(s.pipe.server.stream_writable_next(), Some(0));
     }
 
     #[test]
-    /// Tests that DATA frames are properly truncated depending on the request
-    /// stream's outgoing flow control capacity.
-    fn stream_backpressure() {
-        let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-
+    /// Test handling of 0-length DATA writes with and without fin.
+    fn zero_length_data() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(false).unwrap();
 
-        let total_data_frames = 6;
-
-        for _ in 0..total_data_frames {
-            assert_eq!(
-                s.client
-                    .send_body(&mut s.pipe.client, stream, &bytes, false),
-                Ok(bytes.len())
-            );
-
-            s.advance().ok();
-        }
-
         assert_eq!(
-            s.client.send_body(&mut s.pipe.client, stream, &bytes, true),
-            Ok(bytes.len() - 2)
+            s.client.send_body(&mut s.pipe.client, 0, b"", false),
+            Err(Error::Done)
         );
+        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
 
         s.advance().ok();
 
-        let mut recv_buf = vec![0; bytes.len()];
+        let mut recv_buf = vec![0; 100];
 
         let ev_headers = Event::Headers {
             list: req,
@@ -5462,39 +8632,40 @@ mod tests {
         };
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        for _ in 0..total_data_frames {
-            assert_eq!(
-                s.recv_body_server(stream, &mut recv_buf),
-                Ok(bytes.len())
-            );
-        }
+        let resp = s.send_response(stream, false).unwrap();
 
         assert_eq!(
-            s.recv_body_server(stream, &mut recv_buf),
-            Ok(bytes.len() - 2)
+            s.server.send_body(&mut s.pipe.server, 0, b"", false),
+            Err(Error::Done)
         );
+        assert_eq!(s.server.send_body(&mut s.pipe.server, 0, b"", true), Ok(0));
 
-        // Fin flag from last send_body() call was not sent as the buffer was
-        // only partially written.
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.advance().ok();
 
-        assert_eq!(s.pipe.server.data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.server.stream_data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.server.data_blocked_recv_count, 0);
-        assert_eq!(s.pipe.server.stream_data_blocked_recv_count, 1);
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
 
-        assert_eq!(s.pipe.client.data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.client.stream_data_blocked_sent_count, 1);
-        assert_eq!(s.pipe.client.data_blocked_recv_count, 0);
-        assert_eq!(s.pipe.client.stream_data_blocked_recv_count, 0);
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Err(Error::Done));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that the max header list size setting is enforced.
-    fn request_max_header_size_limit() {
+    /// Tests that blocked 0-length DATA writes are reported correctly.
+    fn zero_length_data_blocked() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -5503,16 +8674,15 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(1500);
+        config.set_initial_max_data(69);
         config.set_initial_max_stream_data_bidi_local(150);
         config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
 
-        let mut h3_config = Config::new().unwrap();
-        h3_config.set_max_field_section_size(65);
+        let h3_config = Config::new().unwrap();
 
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
 
@@ -5523,115 +8693,161 @@ mod tests {
             Header::new(b":scheme", b"https"),
             Header::new(b":authority", b"quic.tech"),
             Header::new(b":path", b"/test"),
-            Header::new(b"aaaaaaa", b"aaaaaaaa"),
         ];
 
-        let stream = s
-            .client
-            .send_request(&mut s.pipe.client, &req, true)
-            .unwrap();
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, false),
+            Ok(0)
+        );
 
-        s.advance().ok();
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, 0, b"", true),
+            Err(Error::Done)
+        );
 
-        assert_eq!(stream, 0);
+        // Clear the writable stream queue.
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(2));
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(6));
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(10));
+        assert_eq!(s.pipe.client.stream_writable_next(), None);
 
-        assert_eq!(s.poll_server(), Err(Error::ExcessiveLoad));
+        s.advance().ok();
 
-        assert_eq!(
-            s.pipe.server.local_error.as_ref().unwrap().error_code,
-            Error::to_wire(Error::ExcessiveLoad)
-        );
+        // Once the server gives flow control credits back, we can send the body.
+        assert_eq!(s.pipe.client.stream_writable_next(), Some(0));
+        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
     }
 
     #[test]
-    /// Tests that Error::TransportError contains a transport error.
-    fn transport_error() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Tests that receiving an empty SETTINGS frame is handled and reported.
+    fn empty_settings() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+        config.set_ack_delay_exponent(8);
+        config.grease(false);
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
-            Header::new(b"user-agent", b"quiche-test"),
-        ];
+        let h3_config = Config::new().unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
 
-        // We need to open all streams in the same flight, so we can't use the
-        // Session::send_request() method because it also calls advance(),
-        // otherwise the server would send a MAX_STREAMS frame and the client
-        // wouldn't hit the streams limit.
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(4));
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(8));
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, true),
-            Ok(12)
-        );
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, true),
-            Ok(16)
-        );
+        s.handshake().unwrap();
 
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, true),
-            Err(Error::TransportError(crate::Error::StreamLimit))
-        );
+        assert!(s.client.peer_settings_raw().is_some());
+        assert!(s.server.peer_settings_raw().is_some());
     }
 
     #[test]
-    /// Tests that sending DATA before HEADERS causes an error.
-    fn data_before_headers() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Tests that receiving a H3_DATAGRAM setting is ok.
+    fn dgram_setting() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.enable_dgram(true, 1000, 1000);
+        config.verify_peer(false);
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        let h3_config = Config::new().unwrap();
 
-        let frame_type = b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        let frame_len = b.put_varint(5).unwrap();
-        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+        s.client.send_settings(&mut s.pipe.client).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        s.pipe.client.stream_send(0, b"hello", false).unwrap();
+        // Before processing SETTINGS (via poll), HTTP/3 DATAGRAMS are not
+        // enabled.
+        assert!(!s.server.dgram_enabled_by_peer(&s.pipe.server));
 
-        s.advance().ok();
+        // When everything is ok, poll returns Done and DATAGRAM is enabled.
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
+        assert!(s.server.dgram_enabled_by_peer(&s.pipe.server));
 
-        assert_eq!(
-            s.server.poll(&mut s.pipe.server),
-            Err(Error::FrameUnexpected)
-        );
+        // Now detect things on the client
+        s.server.send_settings(&mut s.pipe.server).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
+        assert!(!s.client.dgram_enabled_by_peer(&s.pipe.client));
+        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::Done));
+        assert!(s.client.dgram_enabled_by_peer(&s.pipe.client));
     }
 
     #[test]
-    /// Tests that calling poll() after an error occurred does nothing.
-    fn poll_after_error() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    /// Tests that receiving a H3_DATAGRAM setting when no TP is set generates
+    /// an error.
+    fn dgram_setting_no_tp() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        let h3_config = Config::new().unwrap();
 
-        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
-        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        let frame_len = b.put_varint(1 << 24).unwrap();
-        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+        s.client.control_stream_id = Some(
+            s.client
+                .open_uni_stream(
+                    &mut s.pipe.client,
+                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
+                )
+                .unwrap(),
+        );
 
-        s.pipe.client.stream_send(0, &d, false).unwrap();
+        let settings = frame::Frame::Settings {
+            max_field_section_size: None,
+            qpack_max_table_capacity: None,
+            qpack_blocked_streams: None,
+            connect_protocol_enabled: None,
+            h3_datagram: Some(1),
+            grease: None,
+            additional_settings: Default::default(),
+            raw: Default::default(),
+        };
 
-        s.advance().ok();
+        s.send_frame_client(settings, s.client.control_stream_id.unwrap(), false)
+            .unwrap();
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::ExcessiveLoad));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Try to call poll() again after an error occurred.
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
     }
 
     #[test]
-    /// Tests that we limit sending HEADERS based on the stream capacity.
-    fn headers_blocked() {
+    /// Tests that receiving SETTINGS with prohibited values generates an error.
+    fn settings_h2_prohibited() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -5651,50 +8867,97 @@ mod tests {
         let h3_config = Config::new().unwrap();
 
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        s.handshake().unwrap();
+        s.client.control_stream_id = Some(
+            s.client
+                .open_uni_stream(
+                    &mut s.pipe.client,
+                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
+                )
+                .unwrap(),
+        );
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
-        ];
+        s.server.control_stream_id = Some(
+            s.server
+                .open_uni_stream(
+                    &mut s.pipe.server,
+                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
+                )
+                .unwrap(),
+        );
 
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
+        let frame_payload_len = 2u64;
+        let settings = [
+            frame::SETTINGS_FRAME_TYPE_ID as u8,
+            frame_payload_len as u8,
+            0x2, // 0x2 is a reserved setting type
+            1,
+        ];
 
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, true),
-            Err(Error::StreamBlocked)
-        );
+        s.send_arbitrary_stream_data_client(
+            &settings,
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        // Clear the writable stream queue.
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(2));
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(6));
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(10));
-        assert_eq!(s.pipe.client.stream_writable_next(), None);
+        s.send_arbitrary_stream_data_server(
+            &settings,
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        s.advance().ok();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Once the server gives flow control credits back, we can send the
-        // request.
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(4));
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(4));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
 
-        assert_eq!(s.pipe.server.data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.server.stream_data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.server.data_blocked_recv_count, 1);
-        assert_eq!(s.pipe.server.stream_data_blocked_recv_count, 0);
+        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::SettingsError));
+    }
 
-        assert_eq!(s.pipe.client.data_blocked_sent_count, 1);
-        assert_eq!(s.pipe.client.stream_data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.client.data_blocked_recv_count, 0);
-        assert_eq!(s.pipe.client.stream_data_blocked_recv_count, 0);
+    #[test]
+    /// Tests that setting SETTINGS with prohibited values generates an error.
+    fn set_prohibited_additional_settings() {
+        let mut h3_config = Config::new().unwrap();
+        assert_eq!(
+            h3_config.set_additional_settings(vec![(
+                frame::SETTINGS_QPACK_MAX_TABLE_CAPACITY,
+                43
+            )]),
+            Err(Error::SettingsError)
+        );
+        assert_eq!(
+            h3_config.set_additional_settings(vec![(
+                frame::SETTINGS_MAX_FIELD_SECTION_SIZE,
+                43
+            )]),
+            Err(Error::SettingsError)
+        );
+        assert_eq!(
+            h3_config.set_additional_settings(vec![(
+                frame::SETTINGS_QPACK_BLOCKED_STREAMS,
+                43
+            )]),
+            Err(Error::SettingsError)
+        );
+        assert_eq!(
+            h3_config.set_additional_settings(vec![(
+                frame::SETTINGS_ENABLE_CONNECT_PROTOCOL,
+                43
+            )]),
+            Err(Error::SettingsError)
+        );
+        assert_eq!(
+            h3_config
+                .set_additional_settings(vec![(frame::SETTINGS_H3_DATAGRAM, 43)]),
+            Err(Error::SettingsError)
+        );
     }
 
     #[test]
-    /// Ensure StreamBlocked when connection flow control prevents headers.
-    fn headers_blocked_on_conn() {
+    /// Tests additional settings are actually exchanged by the peers.
+    fn set_additional_settings() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -5710,62 +8973,115 @@ mod tests {
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
+        config.grease(false);
 
-        let h3_config = Config::new().unwrap();
+        let mut h3_config = Config::new().unwrap();
+        h3_config
+            .set_additional_settings(vec![(42, 43), (44, 45)])
+            .unwrap();
 
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        s.handshake().unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // After the HTTP handshake, some bytes of connection flow control have
-        // been consumed. Fill the connection with more grease data on the control
-        // stream.
-        let d = [42; 28];
-        assert_eq!(s.pipe.client.stream_send(2, &d, false), Ok(23));
+        s.client.send_settings(&mut s.pipe.client).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
-        ];
+        s.server.send_settings(&mut s.pipe.server).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
+        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::Done));
 
-        // There is 0 connection-level flow control, so sending a request is
-        // blocked.
         assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, true),
-            Err(Error::StreamBlocked)
+            s.server.peer_settings_raw(),
+            Some(&[(42, 43), (44, 45)][..])
         );
-        assert_eq!(s.pipe.client.stream_writable_next(), None);
+        assert_eq!(
+            s.client.peer_settings_raw(),
+            Some(&[(42, 43), (44, 45)][..])
+        );
+    }
+
+    #[test]
+    /// Send a single DATAGRAM.
+    fn single_dgram() {
+        let mut buf = [0; 65535];
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
+
+        s.send_dgram_client(0).unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+
+        s.send_dgram_server(0).unwrap();
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+    }
+
+    #[test]
+    /// Send multiple DATAGRAMs.
+    fn multiple_dgram() {
+        let mut buf = [0; 65535];
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
+
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
+
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
 
-        // Emit the control stream data and drain it at the server via poll() to
-        // consumes it via poll() and gives back flow control.
-        s.advance().ok();
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_client(&mut buf), Err(Error::Done));
+    }
 
-        // Now we can send the request.
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(2));
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(6));
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
+    #[test]
+    /// Send more DATAGRAMs than the send queue allows.
+    fn multiple_dgram_overflow() {
+        let mut buf = [0; 65535];
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.pipe.server.data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.server.stream_data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.server.data_blocked_recv_count, 1);
-        assert_eq!(s.pipe.server.stream_data_blocked_recv_count, 0);
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
 
-        assert_eq!(s.pipe.client.data_blocked_sent_count, 1);
-        assert_eq!(s.pipe.client.stream_data_blocked_sent_count, 0);
-        assert_eq!(s.pipe.client.data_blocked_recv_count, 0);
-        assert_eq!(s.pipe.client.stream_data_blocked_recv_count, 0);
+        // Five DATAGRAMs
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+
+        // Only 3 independent DATAGRAMs to read events will fire.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
     }
 
     #[test]
-    /// Ensure STREAM_DATA_BLOCKED is not emitted multiple times with the same
-    /// offset when trying to send large bodies.
-    fn send_body_truncation_stream_blocked() {
-        use crate::test_utils::decode_pkt;
-
+    /// Send a single DATAGRAM and request.
+    fn poll_datagram_cycling_no_read() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -5774,132 +9090,42 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(10000); // large connection-level flow control
-        config.set_initial_max_stream_data_bidi_local(80);
-        config.set_initial_max_stream_data_bidi_remote(80);
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
+        config.enable_dgram(true, 100, 100);
 
         let h3_config = Config::new().unwrap();
-
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        // Send request followed by DATAGRAM on client side.
+        let (stream, req) = s.send_request(false).unwrap();
+
+        s.send_body_client(stream, true).unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
-            more_frames: false,
+            more_frames: true,
         };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        let _ = s.send_response(stream, false).unwrap();
-
-        assert_eq!(s.pipe.server.streams.blocked().len(), 0);
-
-        // The body must be larger than the stream window would allow
-        let d = [42; 500];
-        let mut off = 0;
-
-        let sent = s
-            .server
-            .send_body(&mut s.pipe.server, stream, &d, true)
-            .unwrap();
-        assert_eq!(sent, 25);
-        off += sent;
-
-        // send_body wrote as much as it could (sent < size of buff).
-        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
-        assert_eq!(
-            s.server
-                .send_body(&mut s.pipe.server, stream, &d[off..], true),
-            Err(Error::Done)
-        );
-        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
-
-        // Now read raw frames to see what the QUIC layer did
-        let mut buf = [0; 65535];
-        let (len, _) = s.pipe.server.send(&mut buf).unwrap();
-
-        let frames = decode_pkt(&mut s.pipe.client, &mut buf[..len]).unwrap();
-
-        let mut iter = frames.iter();
-
-        assert_eq!(
-            iter.next(),
-            Some(&crate::frame::Frame::StreamDataBlocked {
-                stream_id: 0,
-                limit: 80,
-            })
-        );
-
-        // At the server, after sending the STREAM_DATA_BLOCKED frame, we clear
-        // the mark.
-        assert_eq!(s.pipe.server.streams.blocked().len(), 0);
-
-        // Don't read any data from the client, so stream flow control is never
-        // given back in the form of changing the stream's max offset.
-        // Subsequent body send operations will still fail but no more
-        // STREAM_DATA_BLOCKED frames should be submitted since the limit didn't
-        // change. No frames means no packet to send.
-        assert_eq!(
-            s.server
-                .send_body(&mut s.pipe.server, stream, &d[off..], true),
-            Err(Error::Done)
-        );
-        assert_eq!(s.pipe.server.streams.blocked().len(), 0);
-        assert_eq!(s.pipe.server.send(&mut buf), Err(crate::Error::Done));
-
-        // Now update the client's max offset manually.
-        let frames = [crate::frame::Frame::MaxStreamData {
-            stream_id: 0,
-            max: 100,
-        }];
-
-        let pkt_type = crate::packet::Type::Short;
-        assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39),
-        );
-
-        let sent = s
-            .server
-            .send_body(&mut s.pipe.server, stream, &d[off..], true)
-            .unwrap();
-        assert_eq!(sent, 18);
-
-        // Same thing here...
-        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
-        assert_eq!(
-            s.server
-                .send_body(&mut s.pipe.server, stream, &d[off..], true),
-            Err(Error::Done)
-        );
-        assert_eq!(s.pipe.server.streams.blocked().len(), 1);
-
-        let (len, _) = s.pipe.server.send(&mut buf).unwrap();
-
-        let frames = decode_pkt(&mut s.pipe.client, &mut buf[..len]).unwrap();
+        s.send_dgram_client(0).unwrap();
 
-        let mut iter = frames.iter();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        assert_eq!(
-            iter.next(),
-            Some(&crate::frame::Frame::StreamDataBlocked {
-                stream_id: 0,
-                limit: 100,
-            })
-        );
+        assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Ensure stream doesn't hang due to small cwnd.
-    fn send_body_stream_blocked_by_small_cwnd() {
+    /// Send a single DATAGRAM and request.
+    fn poll_datagram_single_read() {
+        let mut buf = [0; 65535];
+
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -5908,70 +9134,83 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(100000); // large connection-level flow control
-        config.set_initial_max_stream_data_bidi_local(100000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
+        config.enable_dgram(true, 100, 100);
 
         let h3_config = Config::new().unwrap();
-
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
+
+        // Send request followed by DATAGRAM on client side.
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let body = s.send_body_client(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
 
         let ev_headers = Event::Headers {
             list: req,
-            more_frames: false,
+            more_frames: true,
         };
 
+        s.send_dgram_client(0).unwrap();
+
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        let _ = s.send_response(stream, false).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Clear the writable stream queue.
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(3));
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(7));
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(11));
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(stream));
-        assert_eq!(s.pipe.server.stream_writable_next(), None);
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
 
-        // The body must be larger than the cwnd would allow.
-        let send_buf = [42; 80000];
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let sent = s
-            .server
-            .send_body(&mut s.pipe.server, stream, &send_buf, true)
-            .unwrap();
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // send_body wrote as much as it could (sent < size of buff).
-        assert_eq!(sent, 11995);
+        // Send response followed by DATAGRAM on server side
+        let resp = s.send_response(stream, false).unwrap();
 
-        s.advance().ok();
+        let body = s.send_body_server(stream, true).unwrap();
 
-        // Client reads received headers and body.
-        let mut recv_buf = [42; 80000];
-        assert!(s.poll_client().is_ok());
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
+
+        s.send_dgram_server(0).unwrap();
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(11995));
 
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Err(Error::Done));
 
-        // Server send cap is smaller than remaining body buffer.
-        assert!(s.pipe.server.tx_cap < send_buf.len() - sent);
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
 
-        // Once the server cwnd opens up, we can send more body.
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(0));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Ensure stream doesn't hang due to small cwnd.
-    fn send_body_stream_blocked_zero_length() {
+    /// Send multiple DATAGRAMs and requests.
+    fn poll_datagram_multi_read() {
+        let mut buf = [0; 65535];
+
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -5980,90 +9219,165 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(100000); // large connection-level flow control
-        config.set_initial_max_stream_data_bidi_local(100000);
-        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
+        config.enable_dgram(true, 100, 100);
 
         let h3_config = Config::new().unwrap();
-
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        // 10 bytes on flow ID 0 and 2.
+        let flow_0_result = (11, 0, 1);
+        let flow_2_result = (11, 2, 1);
+
+        // Send requests followed by DATAGRAMs on client side.
+        let (stream, req) = s.send_request(false).unwrap();
+
+        let body = s.send_body_client(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
 
         let ev_headers = Event::Headers {
             list: req,
-            more_frames: false,
+            more_frames: true,
         };
 
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Second cycle, start to read
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        let _ = s.send_response(stream, false).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Clear the writable stream queue.
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(3));
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(7));
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(11));
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(stream));
-        assert_eq!(s.pipe.server.stream_writable_next(), None);
+        // Third cycle.
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // The body is large enough to fill the cwnd, except for enough bytes
-        // for another DATA frame header (but no payload).
-        let send_buf = [42; 11994];
+        // Send response followed by DATAGRAM on server side
+        let resp = s.send_response(stream, false).unwrap();
 
-        let sent = s
-            .server
-            .send_body(&mut s.pipe.server, stream, &send_buf, false)
-            .unwrap();
+        let body = s.send_body_server(stream, true).unwrap();
 
-        assert_eq!(sent, 11994);
+        let mut recv_buf = vec![0; body.len()];
 
-        // There is only enough capacity left for the DATA frame header, but
-        // no payload.
-        assert_eq!(s.pipe.server.stream_capacity(stream).unwrap(), 3);
-        assert_eq!(
-            s.server
-                .send_body(&mut s.pipe.server, stream, &send_buf, false),
-            Err(Error::Done)
-        );
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
 
-        s.advance().ok();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
 
-        // Client reads received headers and body.
-        let mut recv_buf = [42; 80000];
-        assert!(s.poll_client().is_ok());
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(11994));
 
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Err(Error::Done));
 
-        // Once the server cwnd opens up, we can send more body.
-        assert_eq!(s.pipe.server.stream_writable_next(), Some(0));
+        // Second cycle, start to read
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        // Third cycle.
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Test handling of 0-length DATA writes with and without fin.
-    fn zero_length_data() {
+    /// Tests that the Finished event is not issued for streams of unknown type
+    /// (e.g. GREASE).
+    fn finished_is_for_requests() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(
-            s.client.send_body(&mut s.pipe.client, 0, b"", false),
-            Err(Error::Done)
-        );
-        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
+        assert_eq!(s.client.open_grease_stream(&mut s.pipe.client), Ok(()));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-        let mut recv_buf = vec![0; 100];
+    #[test]
+    /// Tests that streams are marked as finished only once.
+    fn finished_once() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+        let body = s.send_body_client(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
 
         let ev_headers = Event::Headers {
             list: req,
@@ -6071,332 +9385,207 @@ mod tests {
         };
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
 
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
         assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-        let resp = s.send_response(stream, false).unwrap();
+    #[test]
+    /// Tests that the Data event is properly re-armed.
+    fn data_event_rearm() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
 
-        assert_eq!(
-            s.server.send_body(&mut s.pipe.server, 0, b"", false),
-            Err(Error::Done)
-        );
-        assert_eq!(s.server.send_body(&mut s.pipe.server, 0, b"", true), Ok(0));
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        s.advance().ok();
+        let (r1_id, r1_hdrs) = s.send_request(false).unwrap();
 
-        let ev_headers = Event::Headers {
-            list: resp,
+        let mut recv_buf = vec![0; bytes.len()];
+
+        let r1_ev_headers = Event::Headers {
+            list: r1_hdrs,
             more_frames: true,
         };
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        // Manually send an incomplete DATA frame (i.e. the frame size is longer
+        // than the actual data sent).
+        {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Err(Error::Done));
+            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+            b.put_varint(bytes.len() as u64).unwrap();
+            let off = b.off();
+            s.pipe.client.stream_send(r1_id, &d[..off], false).unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-    }
+            assert_eq!(
+                s.pipe.client.stream_send(r1_id, &bytes[..5], false),
+                Ok(5)
+            );
 
-    #[test]
-    /// Tests that blocked 0-length DATA writes are reported correctly.
-    fn zero_length_data_blocked() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(69);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
+            s.advance().ok();
+        }
 
-        let h3_config = Config::new().unwrap();
+        assert_eq!(s.poll_server(), Ok((r1_id, r1_ev_headers)));
+        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        // Read the available body data.
+        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(5));
 
-        s.handshake().unwrap();
+        // Send the remaining DATA payload.
+        assert_eq!(s.pipe.client.stream_send(r1_id, &bytes[5..], false), Ok(5));
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Read the rest of the body data.
+        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(5));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
-        ];
+        // Send more data.
+        let r1_body = s.send_body_client(r1_id, false).unwrap();
 
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, false),
-            Ok(0)
-        );
+        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(
-            s.client.send_body(&mut s.pipe.client, 0, b"", true),
-            Err(Error::Done)
-        );
+        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(r1_body.len()));
 
-        // Clear the writable stream queue.
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(2));
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(6));
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(10));
-        assert_eq!(s.pipe.client.stream_writable_next(), None);
+        // Send a new request to ensure cross-stream events don't break rearming.
+        let (r2_id, r2_hdrs) = s.send_request(false).unwrap();
+        let r2_ev_headers = Event::Headers {
+            list: r2_hdrs,
+            more_frames: true,
+        };
+        let r2_body = s.send_body_client(r2_id, false).unwrap();
 
         s.advance().ok();
 
-        // Once the server gives flow control credits back, we can send the body.
-        assert_eq!(s.pipe.client.stream_writable_next(), Some(0));
-        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
-    }
+        assert_eq!(s.poll_server(), Ok((r2_id, r2_ev_headers)));
+        assert_eq!(s.poll_server(), Ok((r2_id, Event::Data)));
+        assert_eq!(s.recv_body_server(r2_id, &mut recv_buf), Ok(r2_body.len()));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-    #[test]
-    /// Tests that receiving an empty SETTINGS frame is handled and reported.
-    fn empty_settings() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
+        // Send more data on request 1, then trailing HEADERS.
+        let r1_body = s.send_body_client(r1_id, false).unwrap();
+
+        let trailers = vec![Header::new(b"hello", b"world")];
+
+        s.client
+            .send_headers(&mut s.pipe.client, r1_id, &trailers, true)
             .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(5);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.set_ack_delay_exponent(8);
-        config.grease(false);
 
-        let h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        let r1_ev_trailers = Event::Headers {
+            list: trailers.clone(),
+            more_frames: false,
+        };
 
-        s.handshake().unwrap();
+        s.advance().ok();
 
-        assert!(s.client.peer_settings_raw().is_some());
-        assert!(s.server.peer_settings_raw().is_some());
-    }
+        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
+        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(r1_body.len()));
 
-    #[test]
-    /// Tests that receiving a H3_DATAGRAM setting is ok.
-    fn dgram_setting() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(70);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.enable_dgram(true, 1000, 1000);
-        config.verify_peer(false);
+        assert_eq!(s.poll_server(), Ok((r1_id, r1_ev_trailers)));
+        assert_eq!(s.poll_server(), Ok((r1_id, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let h3_config = Config::new().unwrap();
+        // Send more data on request 2, then trailing HEADERS.
+        let r2_body = s.send_body_client(r2_id, false).unwrap();
 
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
+        s.client
+            .send_headers(&mut s.pipe.client, r2_id, &trailers, false)
+            .unwrap();
 
-        s.client.send_settings(&mut s.pipe.client).unwrap();
-        assert_eq!(s.pipe.advance(), Ok(()));
+        let r2_ev_trailers = Event::Headers {
+            list: trailers,
+            more_frames: true,
+        };
 
-        // Before processing SETTINGS (via poll), HTTP/3 DATAGRAMS are not
-        // enabled.
-        assert!(!s.server.dgram_enabled_by_peer(&s.pipe.server));
+        s.advance().ok();
 
-        // When everything is ok, poll returns Done and DATAGRAM is enabled.
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
-        assert!(s.server.dgram_enabled_by_peer(&s.pipe.server));
+        assert_eq!(s.poll_server(), Ok((r2_id, Event::Data)));
+        assert_eq!(s.recv_body_server(r2_id, &mut recv_buf), Ok(r2_body.len()));
+        assert_eq!(s.poll_server(), Ok((r2_id, r2_ev_trailers)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Now detect things on the client
-        s.server.send_settings(&mut s.pipe.server).unwrap();
-        assert_eq!(s.pipe.advance(), Ok(()));
-        assert!(!s.client.dgram_enabled_by_peer(&s.pipe.client));
-        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::Done));
-        assert!(s.client.dgram_enabled_by_peer(&s.pipe.client));
-    }
+        let (r3_id, r3_hdrs) = s.send_request(false).unwrap();
 
-    #[test]
-    /// Tests that receiving a H3_DATAGRAM setting when no TP is set generates
-    /// an error.
-    fn dgram_setting_no_tp() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(70);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
+        let r3_ev_headers = Event::Headers {
+            list: r3_hdrs,
+            more_frames: true,
+        };
 
-        let h3_config = Config::new().unwrap();
+        // Manually send an incomplete DATA frame (i.e. only the header is sent).
+        {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
+            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+            b.put_varint(bytes.len() as u64).unwrap();
+            let off = b.off();
+            s.pipe.client.stream_send(r3_id, &d[..off], false).unwrap();
 
-        s.client.control_stream_id = Some(
-            s.client
-                .open_uni_stream(
-                    &mut s.pipe.client,
-                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
-                )
-                .unwrap(),
-        );
+            s.advance().ok();
+        }
 
-        let settings = frame::Frame::Settings {
-            max_field_section_size: None,
-            qpack_max_table_capacity: None,
-            qpack_blocked_streams: None,
-            connect_protocol_enabled: None,
-            h3_datagram: Some(1),
-            grease: None,
-            additional_settings: Default::default(),
-            raw: Default::default(),
-        };
+        assert_eq!(s.poll_server(), Ok((r3_id, r3_ev_headers)));
+        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        s.send_frame_client(settings, s.client.control_stream_id.unwrap(), false)
-            .unwrap();
+        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Err(Error::Done));
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        assert_eq!(s.pipe.client.stream_send(r3_id, &bytes[..5], false), Ok(5));
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
-    }
+        s.advance().ok();
 
-    #[test]
-    /// Tests that receiving SETTINGS with prohibited values generates an error.
-    fn settings_h2_prohibited() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(70);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
+        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let h3_config = Config::new().unwrap();
+        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Ok(5));
 
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
+        assert_eq!(s.pipe.client.stream_send(r3_id, &bytes[5..], false), Ok(5));
+        s.advance().ok();
 
-        s.client.control_stream_id = Some(
-            s.client
-                .open_uni_stream(
-                    &mut s.pipe.client,
-                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
-                )
-                .unwrap(),
-        );
+        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        s.server.control_stream_id = Some(
-            s.server
-                .open_uni_stream(
-                    &mut s.pipe.server,
-                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
-                )
-                .unwrap(),
-        );
+        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Ok(5));
 
-        let frame_payload_len = 2u64;
-        let settings = [
-            frame::SETTINGS_FRAME_TYPE_ID as u8,
-            frame_payload_len as u8,
-            0x2, // 0x2 is a reserved setting type
-            1,
-        ];
+        // Buffer multiple data frames.
+        let body = s.send_body_client(r3_id, false).unwrap();
+        s.send_body_client(r3_id, false).unwrap();
+        s.send_body_client(r3_id, false).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        s.send_arbitrary_stream_data_client(
-            &settings,
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        s.send_arbitrary_stream_data_server(
-            &settings,
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+            b.put_varint(0).unwrap();
+            let off = b.off();
+            s.pipe.client.stream_send(r3_id, &d[..off], true).unwrap();
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+            s.advance().ok();
+        }
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
+        let mut recv_buf = vec![0; bytes.len() * 3];
 
-        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::SettingsError));
+        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Ok(body.len() * 3));
     }
 
     #[test]
-    /// Tests that setting SETTINGS with prohibited values generates an error.
-    fn set_prohibited_additional_settings() {
-        let mut h3_config = Config::new().unwrap();
-        assert_eq!(
-            h3_config.set_additional_settings(vec![(
-                frame::SETTINGS_QPACK_MAX_TABLE_CAPACITY,
-                43
-            )]),
-            Err(Error::SettingsError)
-        );
-        assert_eq!(
-            h3_config.set_additional_settings(vec![(
-                frame::SETTINGS_MAX_FIELD_SECTION_SIZE,
-                43
-            )]),
-            Err(Error::SettingsError)
-        );
-        assert_eq!(
-            h3_config.set_additional_settings(vec![(
-                frame::SETTINGS_QPACK_BLOCKED_STREAMS,
-                43
-            )]),
-            Err(Error::SettingsError)
-        );
-        assert_eq!(
-            h3_config.set_additional_settings(vec![(
-                frame::SETTINGS_ENABLE_CONNECT_PROTOCOL,
-                43
-            )]),
-            Err(Error::SettingsError)
-        );
-        assert_eq!(
-            h3_config
-                .set_additional_settings(vec![(frame::SETTINGS_H3_DATAGRAM, 43)]),
-            Err(Error::SettingsError)
-        );
-    }
+    /// Tests that the Datagram event is properly re-armed.
+    fn dgram_event_rearm() {
+        let mut buf = [0; 65535];
 
-    #[test]
-    /// Tests additional settings are actually exchanged by the peers.
-    fn set_additional_settings() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -6405,626 +9594,548 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(70);
+        config.set_initial_max_data(1500);
         config.set_initial_max_stream_data_bidi_local(150);
         config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
-        config.grease(false);
-
-        let mut h3_config = Config::new().unwrap();
-        h3_config
-            .set_additional_settings(vec![(42, 43), (44, 45)])
-            .unwrap();
+        config.enable_dgram(true, 100, 100);
 
+        let h3_config = Config::new().unwrap();
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
-
-        assert_eq!(s.pipe.advance(), Ok(()));
+        s.handshake().unwrap();
 
-        s.client.send_settings(&mut s.pipe.client).unwrap();
-        assert_eq!(s.pipe.advance(), Ok(()));
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
+        // 10 bytes on flow ID 0 and 2.
+        let flow_0_result = (11, 0, 1);
+        let flow_2_result = (11, 2, 1);
 
-        s.server.send_settings(&mut s.pipe.server).unwrap();
-        assert_eq!(s.pipe.advance(), Ok(()));
-        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::Done));
+        // Send requests followed by DATAGRAMs on client side.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        assert_eq!(
-            s.server.peer_settings_raw(),
-            Some(&[(42, 43), (44, 45)][..])
-        );
-        assert_eq!(
-            s.client.peer_settings_raw(),
-            Some(&[(42, 43), (44, 45)][..])
-        );
-    }
+        let body = s.send_body_client(stream, true).unwrap();
 
-    #[test]
-    /// Send a single DATAGRAM.
-    fn single_dgram() {
-        let mut buf = [0; 65535];
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        let mut recv_buf = vec![0; body.len()];
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
         s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
 
-        s.send_dgram_server(0).unwrap();
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
-    }
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
 
-    #[test]
-    /// Send multiple DATAGRAMs.
-    fn multiple_dgram() {
-        let mut buf = [0; 65535];
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
         s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(2).unwrap();
 
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
 
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_client(&mut buf), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        // Verify that dgram counts are incremented.
+        assert_eq!(s.pipe.client.dgram_sent_count, 6);
+        assert_eq!(s.pipe.client.dgram_recv_count, 0);
+        assert_eq!(s.pipe.server.dgram_sent_count, 0);
+        assert_eq!(s.pipe.server.dgram_recv_count, 6);
+
+        let server_path = s.pipe.server.paths.get_active().expect("no active");
+        let client_path = s.pipe.client.paths.get_active().expect("no active");
+        assert_eq!(client_path.dgram_sent_count, 6);
+        assert_eq!(client_path.dgram_recv_count, 0);
+        assert_eq!(server_path.dgram_sent_count, 0);
+        assert_eq!(server_path.dgram_recv_count, 6);
     }
 
     #[test]
-    /// Send more DATAGRAMs than the send queue allows.
-    fn multiple_dgram_overflow() {
+    fn reset_stream() {
         let mut buf = [0; 65535];
+
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        // Client sends request.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        // Five DATAGRAMs
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-        // Only 3 independent DATAGRAMs to read events will fire.
+        // Server sends response and closes stream.
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
-    }
 
-    #[test]
-    /// Send a single DATAGRAM and request.
-    fn poll_datagram_cycling_no_read() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
+        let resp = s.send_response(stream, true).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        // Client sends RESET_STREAM, closing stream.
+        let frames = [crate::frame::Frame::ResetStream {
+            stream_id: stream,
+            error_code: 42,
+            final_size: 68,
+        }];
+
+        let pkt_type = crate::packet::Type::Short;
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39)
+        );
+
+        // Server issues Reset event for the stream.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Sending RESET_STREAM again shouldn't trigger another Reset event.
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39)
+        );
 
-        let h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
+    /// The client shuts down the stream's write direction, the server
+    /// shuts down its side with fin
+    #[test]
+    fn client_shutdown_write_server_fin() {
+        let mut buf = [0; 65535];
+        let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        // Send request followed by DATAGRAM on client side.
+        // Client sends request.
         let (stream, req) = s.send_request(false).unwrap();
 
-        s.send_body_client(stream, true).unwrap();
-
         let ev_headers = Event::Headers {
             list: req,
             more_frames: true,
         };
 
-        s.send_dgram_client(0).unwrap();
-
+        // Server sends response and closes stream.
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-
         assert_eq!(s.poll_server(), Err(Error::Done));
-    }
 
-    #[test]
-    /// Send a single DATAGRAM and request.
-    fn poll_datagram_single_read() {
-        let mut buf = [0; 65535];
+        let resp = s.send_response(stream, true).unwrap();
 
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
 
-        let h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        s.handshake().unwrap();
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        // Client shuts down stream ==> sends RESET_STREAM
+        assert_eq!(
+            s.pipe
+                .client
+                .stream_shutdown(stream, crate::Shutdown::Write, 42),
+            Ok(())
+        );
+        assert_eq!(s.advance(), Ok(()));
 
-        // Send request followed by DATAGRAM on client side.
-        let (stream, req) = s.send_request(false).unwrap();
+        // Server sees the Reset event for the stream.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let body = s.send_body_client(stream, true).unwrap();
+        // Streams have been collected by quiche
+        assert!(s.pipe.server.streams.is_collected(stream));
+        assert!(s.pipe.client.streams.is_collected(stream));
 
-        let mut recv_buf = vec![0; body.len()];
+        // Client sends another request, server sends response without fin
+        //
+        let (stream, req) = s.send_request(false).unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
             more_frames: true,
         };
 
-        s.send_dgram_client(0).unwrap();
-
+        // Check that server has received the request.
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Send response followed by DATAGRAM on server side
+        // Server sends reponse without closing the stream.
         let resp = s.send_response(stream, false).unwrap();
 
-        let body = s.send_body_server(stream, true).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
-
         let ev_headers = Event::Headers {
             list: resp,
             more_frames: true,
         };
 
-        s.send_dgram_server(0).unwrap();
-
         assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-
         assert_eq!(s.poll_client(), Err(Error::Done));
 
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        // Client shuts down stream ==> sends RESET_STREAM
+        assert_eq!(
+            s.pipe
+                .client
+                .stream_shutdown(stream, crate::Shutdown::Write, 42),
+            Ok(())
+        );
+        assert_eq!(s.advance(), Ok(()));
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Server sees the Reset event for the stream.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        // Server sends body and closes the stream.
+        s.send_body_server(stream, true).unwrap();
 
+        // Stream has been collected on server by quiche
+        assert!(s.pipe.server.streams.is_collected(stream));
+        // Client stream has not been collected, the client needs to
+        // read the fin from the stream first.
+        assert!(!s.pipe.client.streams.is_collected(stream));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        s.recv_body_client(stream, &mut buf).unwrap();
         assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
+        assert!(s.pipe.client.streams.is_collected(stream));
     }
 
     #[test]
-    /// Send multiple DATAGRAMs and requests.
-    fn poll_datagram_multi_read() {
+    fn client_shutdown_read() {
         let mut buf = [0; 65535];
-
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(&[b"h3"]).unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
-
-        let h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        // 10 bytes on flow ID 0 and 2.
-        let flow_0_result = (11, 0, 1);
-        let flow_2_result = (11, 2, 1);
-
-        // Send requests followed by DATAGRAMs on client side.
+        // Client sends request and leaves stream open.
         let (stream, req) = s.send_request(false).unwrap();
 
-        let body = s.send_body_client(stream, true).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
-
         let ev_headers = Event::Headers {
             list: req,
             more_frames: true,
         };
 
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
-
+        // Server sends response and leaves stream open.
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        // Second cycle, start to read
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        // Third cycle.
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Send response followed by DATAGRAM on server side
         let resp = s.send_response(stream, false).unwrap();
 
-        let body = s.send_body_server(stream, true).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
-
         let ev_headers = Event::Headers {
             list: resp,
             more_frames: true,
         };
 
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-
         assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-
-        assert_eq!(s.poll_client(), Err(Error::Done));
-
-        // Second cycle, start to read
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_client(), Err(Error::Done));
+        // Client shuts down read
+        assert_eq!(
+            s.pipe
+                .client
+                .stream_shutdown(stream, crate::Shutdown::Read, 42),
+            Ok(())
+        );
+        assert_eq!(s.advance(), Ok(()));
 
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        // Stream is writable on server side, but returns StreamStopped
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        let writables: Vec<u64> = s.pipe.server.writable().collect();
+        assert!(writables.contains(&stream));
+        assert_eq!(
+            s.send_body_server(stream, false),
+            Err(Error::TransportError(crate::Error::StreamStopped(42)))
+        );
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Client needs to finish its side by sending a fin
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, stream, &[], true),
+            Ok(0)
+        );
+        assert_eq!(s.advance(), Ok(()));
+        // Note, we get an Event::Data for an empty buffer today. But it
+        // would also be fine to not get it.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut buf), Err(Error::Done));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Third cycle.
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Since the client has already send a fin, the stream is collected
+        // on both client and server
+        assert!(s.pipe.client.streams.is_collected(stream));
+        assert!(s.pipe.server.streams.is_collected(stream));
     }
 
     #[test]
-    /// Tests that the Finished event is not issued for streams of unknown type
-    /// (e.g. GREASE).
-    fn finished_is_for_requests() {
+    fn reset_finished_at_server() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // Client sends HEADERS and doesn't fin
+        let (stream, _req) = s.send_request(false).unwrap();
+
+        // ..then Client sends RESET_STREAM
+        assert_eq!(
+            s.pipe.client.stream_shutdown(0, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
-        assert_eq!(s.client.open_grease_stream(&mut s.pipe.client), Ok(()));
         assert_eq!(s.pipe.advance(), Ok(()));
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Server receives just a reset
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(0))));
         assert_eq!(s.poll_server(), Err(Error::Done));
-    }
-
-    #[test]
-    /// Tests that streams are marked as finished only once.
-    fn finished_once() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
-        let body = s.send_body_client(stream, true).unwrap();
+        // Client sends HEADERS and fin
+        let (stream, req) = s.send_request(true).unwrap();
 
-        let mut recv_buf = vec![0; body.len()];
+        // ..then Client sends RESET_STREAM
+        assert_eq!(
+            s.pipe.client.stream_shutdown(4, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
         let ev_headers = Event::Headers {
             list: req,
-            more_frames: true,
+            more_frames: false,
         };
 
+        // Server receives headers and fin.
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
         assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that the Data event is properly re-armed.
-    fn data_event_rearm() {
-        let bytes = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-
+    fn reset_finished_at_server_with_data_pending() {
         let mut s = Session::new().unwrap();
         s.handshake().unwrap();
 
-        let (r1_id, r1_hdrs) = s.send_request(false).unwrap();
+        // Client sends HEADERS and doesn't fin.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        let mut recv_buf = vec![0; bytes.len()];
+        assert!(s.send_body_client(stream, false).is_ok());
 
-        let r1_ev_headers = Event::Headers {
-            list: r1_hdrs,
+        assert_eq!(s.pipe.advance(), Ok(()));
+
+        let ev_headers = Event::Headers {
+            list: req,
             more_frames: true,
         };
 
-        // Manually send an incomplete DATA frame (i.e. the frame size is longer
-        // than the actual data sent).
-        {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
-
-            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-            b.put_varint(bytes.len() as u64).unwrap();
-            let off = b.off();
-            s.pipe.client.stream_send(r1_id, &d[..off], false).unwrap();
-
-            assert_eq!(
-                s.pipe.client.stream_send(r1_id, &bytes[..5], false),
-                Ok(5)
-            );
-
-            s.advance().ok();
-        }
-
-        assert_eq!(s.poll_server(), Ok((r1_id, r1_ev_headers)));
-        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // Server receives headers and data...
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        // Read the available body data.
-        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(5));
+        // ..then Client sends RESET_STREAM.
+        assert_eq!(
+            s.pipe
+                .client
+                .stream_shutdown(stream, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
-        // Send the remaining DATA payload.
-        assert_eq!(s.pipe.client.stream_send(r1_id, &bytes[5..], false), Ok(5));
-        s.advance().ok();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
+        // The server does *not* attempt to read from the stream,
+        // but polls and receives the reset and there are no more
+        // readable streams.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(0))));
         assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.pipe.server.readable().len(), 0);
+    }
 
-        // Read the rest of the body data.
-        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(5));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+    #[test]
+    fn reset_finished_at_server_with_data_pending_2() {
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        // Send more data.
-        let r1_body = s.send_body_client(r1_id, false).unwrap();
+        // Client sends HEADERS and doesn't fin.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(s.send_body_client(stream, false).is_ok());
 
-        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(r1_body.len()));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Send a new request to ensure cross-stream events don't break rearming.
-        let (r2_id, r2_hdrs) = s.send_request(false).unwrap();
-        let r2_ev_headers = Event::Headers {
-            list: r2_hdrs,
+        let ev_headers = Event::Headers {
+            list: req,
             more_frames: true,
         };
-        let r2_body = s.send_body_client(r2_id, false).unwrap();
 
-        s.advance().ok();
+        // Server receives headers and data...
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        assert_eq!(s.poll_server(), Ok((r2_id, r2_ev_headers)));
-        assert_eq!(s.poll_server(), Ok((r2_id, Event::Data)));
-        assert_eq!(s.recv_body_server(r2_id, &mut recv_buf), Ok(r2_body.len()));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // ..then Client sends RESET_STREAM.
+        assert_eq!(
+            s.pipe
+                .client
+                .stream_shutdown(stream, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
-        // Send more data on request 1, then trailing HEADERS.
-        let r1_body = s.send_body_client(r1_id, false).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        let trailers = vec![Header::new(b"hello", b"world")];
+        // Server reads from the stream and receives the reset while
+        // attempting to read.
+        assert_eq!(
+            s.recv_body_server(stream, &mut [0; 100]),
+            Err(Error::TransportError(crate::Error::StreamReset(0)))
+        );
 
-        s.client
-            .send_headers(&mut s.pipe.client, r1_id, &trailers, true)
-            .unwrap();
+        // No more events and there are no more readable streams.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.pipe.server.readable().len(), 0);
+    }
 
-        let r1_ev_trailers = Event::Headers {
-            list: trailers.clone(),
-            more_frames: false,
-        };
+    #[test]
+    fn reset_finished_at_client() {
+        let mut buf = [0; 65535];
+        let mut s = Session::new().unwrap();
+        s.handshake().unwrap();
 
-        s.advance().ok();
+        // Client sends HEADERS and doesn't fin
+        let (stream, req) = s.send_request(false).unwrap();
 
-        assert_eq!(s.poll_server(), Ok((r1_id, Event::Data)));
-        assert_eq!(s.recv_body_server(r1_id, &mut recv_buf), Ok(r1_body.len()));
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-        assert_eq!(s.poll_server(), Ok((r1_id, r1_ev_trailers)));
-        assert_eq!(s.poll_server(), Ok((r1_id, Event::Finished)));
+        // Server receives headers.
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Send more data on request 2, then trailing HEADERS.
-        let r2_body = s.send_body_client(r2_id, false).unwrap();
+        // Server sends response and doesn't fin
+        s.send_response(stream, false).unwrap();
 
-        s.client
-            .send_headers(&mut s.pipe.client, r2_id, &trailers, false)
-            .unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        let r2_ev_trailers = Event::Headers {
-            list: trailers,
-            more_frames: true,
-        };
+        // .. then Server sends RESET_STREAM
+        assert_eq!(
+            s.pipe
+                .server
+                .stream_shutdown(stream, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
-        s.advance().ok();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        assert_eq!(s.poll_server(), Ok((r2_id, Event::Data)));
-        assert_eq!(s.recv_body_server(r2_id, &mut recv_buf), Ok(r2_body.len()));
-        assert_eq!(s.poll_server(), Ok((r2_id, r2_ev_trailers)));
+        // Client receives Reset only
+        assert_eq!(s.poll_client(), Ok((stream, Event::Reset(0))));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let (r3_id, r3_hdrs) = s.send_request(false).unwrap();
+        // Client sends headers and fin.
+        let (stream, req) = s.send_request(true).unwrap();
 
-        let r3_ev_headers = Event::Headers {
-            list: r3_hdrs,
-            more_frames: true,
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: false,
         };
 
-        // Manually send an incomplete DATA frame (i.e. only the header is sent).
-        {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
-
-            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-            b.put_varint(bytes.len() as u64).unwrap();
-            let off = b.off();
-            s.pipe.client.stream_send(r3_id, &d[..off], false).unwrap();
-
-            s.advance().ok();
-        }
-
-        assert_eq!(s.poll_server(), Ok((r3_id, r3_ev_headers)));
-        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
+        // Server receives headers and fin.
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Err(Error::Done));
+        // Server sends response and fin
+        let resp = s.send_response(stream, true).unwrap();
 
-        assert_eq!(s.pipe.client.stream_send(r3_id, &bytes[..5], false), Ok(5));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        s.advance().ok();
+        // ..then Server sends RESET_STREAM
+        let frames = [crate::frame::Frame::ResetStream {
+            stream_id: stream,
+            error_code: 42,
+            final_size: 68,
+        }];
 
-        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let pkt_type = crate::packet::Type::Short;
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39)
+        );
 
-        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Ok(5));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        assert_eq!(s.pipe.client.stream_send(r3_id, &bytes[5..], false), Ok(5));
-        s.advance().ok();
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: false,
+        };
 
-        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // Client receives headers and fin.
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Ok(5));
+    /// Sends an extended CONNECT request from the client and a response from
+    /// the server, leaving the stream open in both directions.
+    fn open_connect_stream(s: &mut Session) -> u64 {
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"connect-udp"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/.well-known/masque/udp/quic.tech/443/"),
+            Header::new(b"capsule-protocol", b"?1"),
+        ];
 
-        // Buffer multiple data frames.
-        let body = s.send_body_client(r3_id, false).unwrap();
-        s.send_body_client(r3_id, false).unwrap();
-        s.send_body_client(r3_id, false).unwrap();
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, false)
+            .unwrap();
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((r3_id, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let ev_headers = Event::Headers {
+            list: req,
+            more_frames: true,
+        };
 
-        {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
 
-            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-            b.put_varint(0).unwrap();
-            let off = b.off();
-            s.pipe.client.stream_send(r3_id, &d[..off], true).unwrap();
+        let resp = s.send_response(stream, false).unwrap();
 
-            s.advance().ok();
-        }
+        let ev_headers = Event::Headers {
+            list: resp,
+            more_frames: true,
+        };
 
-        let mut recv_buf = vec![0; bytes.len() * 3];
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
 
-        assert_eq!(s.recv_body_server(r3_id, &mut recv_buf), Ok(body.len() * 3));
+        stream
     }
 
-    #[test]
-    /// Tests that the Datagram event is properly re-armed.
-    fn dgram_event_rearm() {
-        let mut buf = [0; 65535];
-
+    fn capsule_session(
+        enable_dgram: bool, max_capsule_payload_size: usize,
+    ) -> Session {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -7037,505 +10148,860 @@ mod tests {
         config.set_initial_max_stream_data_bidi_local(150);
         config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_bidi(5);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
+        config.enable_dgram(enable_dgram, 10, 10);
+
+        let mut h3_config = Config::new().unwrap();
+        h3_config.enable_extended_connect(true);
+        h3_config.set_max_capsule_payload_size(max_capsule_payload_size);
 
-        let h3_config = Config::new().unwrap();
         let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
         s.handshake().unwrap();
 
-        // 10 bytes on flow ID 0 and 2.
-        let flow_0_result = (11, 0, 1);
-        let flow_2_result = (11, 2, 1);
-
-        // Send requests followed by DATAGRAMs on client side.
-        let (stream, req) = s.send_request(false).unwrap();
+        s
+    }
 
-        let body = s.send_body_client(stream, true).unwrap();
+    #[test]
+    fn capsules() {
+        let mut s = capsule_session(true, 100);
 
-        let mut recv_buf = vec![0; body.len()];
+        let stream = open_connect_stream(&mut s);
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
+        let capsule = Capsule::Datagram {
+            payload: vec![1, 2, 3, 4, 5],
         };
 
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
+        // Client to server.
+        assert_eq!(
+            s.client.send_capsule(&mut s.pipe.client, stream, &capsule),
+            Ok(())
+        );
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Ok(capsule.clone())
+        );
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Err(Error::Done)
+        );
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        // Server to client.
+        assert_eq!(
+            s.server.send_capsule(&mut s.pipe.server, stream, &capsule),
+            Ok(())
+        );
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.client.recv_capsule(&mut s.pipe.client, stream),
+            Ok(capsule)
+        );
+        assert_eq!(
+            s.client.recv_capsule(&mut s.pipe.client, stream),
+            Err(Error::Done)
+        );
+    }
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+    #[test]
+    fn capsules_unknown_skipped() {
+        let mut s = capsule_session(true, 100);
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        let stream = open_connect_stream(&mut s);
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // An unknown capsule split across DATA frames, followed by two
+        // DATAGRAM capsules.
+        let unknown = [0x2a, 0x05, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let datagrams = [0x00, 0x01, 0x01, 0x00, 0x02, 0x02, 0x03];
 
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(2).unwrap();
+        assert_eq!(
+            s.client
+                .send_body(&mut s.pipe.client, stream, &unknown[..4], false),
+            Ok(4)
+        );
+        assert_eq!(
+            s.client
+                .send_body(&mut s.pipe.client, stream, &unknown[4..], false),
+            Ok(3)
+        );
+        assert_eq!(
+            s.client
+                .send_body(&mut s.pipe.client, stream, &datagrams, true),
+            Ok(7)
+        );
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Ok(Capsule::Datagram { payload: vec![1] })
+        );
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Ok(Capsule::Datagram {
+                payload: vec![2, 3]
+            })
+        );
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Err(Error::Done)
+        );
 
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
+    #[test]
+    fn capsule_too_large() {
+        let mut s = capsule_session(true, 4);
+
+        let stream = open_connect_stream(&mut s);
+
+        let capsule = Capsule::Datagram {
+            payload: vec![1, 2, 3, 4, 5],
+        };
+
+        assert_eq!(
+            s.client.send_capsule(&mut s.pipe.client, stream, &capsule),
+            Ok(())
+        );
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Err(Error::ExcessiveLoad)
+        );
+    }
+
+    #[test]
+    fn capsule_truncated() {
+        let mut s = capsule_session(true, 100);
+
+        let stream = open_connect_stream(&mut s);
+
+        // DATAGRAM capsule announcing 5 bytes of payload, but only carrying 2.
+        assert_eq!(
+            s.client.send_body(
+                &mut s.pipe.client,
+                stream,
+                &[0x00, 0x05, 0x01, 0x02],
+                true
+            ),
+            Ok(4)
+        );
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Err(Error::MessageError)
+        );
+    }
+
+    #[test]
+    fn capsule_send_blocked() {
+        let mut s = capsule_session(true, 1000);
+
+        let stream = open_connect_stream(&mut s);
+
+        // The capsule doesn't fit in the stream's flow control window.
+        let capsule = Capsule::Datagram {
+            payload: vec![0; 200],
+        };
+
+        assert_eq!(
+            s.client.send_capsule(&mut s.pipe.client, stream, &capsule),
+            Err(Error::StreamBlocked)
+        );
+    }
+
+    #[test]
+    fn http_datagram() {
+        let mut buf = [0; 65535];
+
+        let mut s = capsule_session(true, 100);
+
+        let stream = open_connect_stream(&mut s);
+
+        assert!(s.client.dgram_enabled_by_peer(&s.pipe.client));
+
+        assert_eq!(
+            s.client
+                .send_http_datagram(&mut s.pipe.client, stream, &[1, 2, 3]),
+            Ok(())
+        );
+        s.advance().ok();
+
+        // Sent in a QUIC DATAGRAM, prefixed with the quarter stream ID.
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok((4, stream / 4, 1)));
+        assert_eq!(&buf[1..4], &[1, 2, 3]);
 
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+    #[test]
+    fn http_datagram_capsule_fallback() {
+        let mut s = capsule_session(false, 100);
 
-        // Verify that dgram counts are incremented.
-        assert_eq!(s.pipe.client.dgram_sent_count, 6);
-        assert_eq!(s.pipe.client.dgram_recv_count, 0);
-        assert_eq!(s.pipe.server.dgram_sent_count, 0);
-        assert_eq!(s.pipe.server.dgram_recv_count, 6);
+        let stream = open_connect_stream(&mut s);
 
-        let server_path = s.pipe.server.paths.get_active().expect("no active");
-        let client_path = s.pipe.client.paths.get_active().expect("no active");
-        assert_eq!(client_path.dgram_sent_count, 6);
-        assert_eq!(client_path.dgram_recv_count, 0);
-        assert_eq!(server_path.dgram_sent_count, 0);
-        assert_eq!(server_path.dgram_recv_count, 6);
+        assert!(!s.client.dgram_enabled_by_peer(&s.pipe.client));
+
+        assert_eq!(
+            s.client
+                .send_http_datagram(&mut s.pipe.client, stream, &[1, 2, 3]),
+            Ok(())
+        );
+        s.advance().ok();
+
+        // Sent in a DATAGRAM capsule on the request stream.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.server.recv_capsule(&mut s.pipe.server, stream),
+            Ok(Capsule::Datagram {
+                payload: vec![1, 2, 3]
+            })
+        );
+
+        // Only request streams can carry HTTP Datagrams.
+        assert_eq!(
+            s.client
+                .send_http_datagram(&mut s.pipe.client, 2, &[1, 2, 3]),
+            Err(Error::FrameUnexpected)
+        );
+    }
+
+    fn webtransport_session(
+        client_enabled: bool, server_enabled: bool,
+    ) -> Session {
+        let mut client_config = Config::new().unwrap();
+        let mut server_config = Config::new().unwrap();
+        server_config.enable_extended_connect(true);
+
+        if client_enabled {
+            client_config.set_webtransport_max_sessions(1);
+        }
+
+        if server_enabled {
+            server_config.set_webtransport_max_sessions(1);
+        }
+
+        webtransport_session_with_config(&client_config, &server_config, true)
     }
 
-    #[test]
-    fn reset_stream() {
-        let mut buf = [0; 65535];
+    fn webtransport_session_with_config(
+        client_config: &Config, server_config: &Config, enable_dgram: bool,
+    ) -> Session {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"h3"]).unwrap();
+        config.set_initial_max_data(1500);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(5);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+        config.enable_dgram(enable_dgram, 10, 10);
+
+        let pipe = crate::test_utils::Pipe::with_config(&mut config).unwrap();
 
-        let mut s = Session::new().unwrap();
+        let mut s = Session {
+            client: Connection::new(client_config, false, enable_dgram).unwrap(),
+            server: Connection::new(server_config, true, enable_dgram).unwrap(),
+            pipe,
+        };
         s.handshake().unwrap();
 
-        // Client sends request.
-        let (stream, req) = s.send_request(false).unwrap();
+        s
+    }
+
+    fn open_webtransport_session(s: &mut Session) -> u64 {
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"webtransport"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
+
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, false)
+            .unwrap();
+        s.advance().ok();
 
         let ev_headers = Event::Headers {
             list: req,
             more_frames: true,
         };
 
-        // Server sends response and closes stream.
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let resp = s.send_response(stream, true).unwrap();
+        let resp = s.send_response(stream, false).unwrap();
 
         let ev_headers = Event::Headers {
             list: resp,
-            more_frames: false,
+            more_frames: true,
         };
 
         assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
 
-        // Client sends RESET_STREAM, closing stream.
-        let frames = [crate::frame::Frame::ResetStream {
-            stream_id: stream,
-            error_code: 42,
-            final_size: 68,
-        }];
+        stream
+    }
 
-        let pkt_type = crate::packet::Type::Short;
-        assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39)
-        );
+    #[test]
+    fn webtransport_settings() {
+        let s = webtransport_session(true, true);
 
-        // Server issues Reset event for the stream.
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(s.client.webtransport_enabled_by_peer());
+        assert!(s.server.webtransport_enabled_by_peer());
+        assert!(s.client.extended_connect_enabled_by_peer());
+
+        let raw = s.client.peer_settings_raw().unwrap();
+        assert!(raw.contains(&(frame::SETTINGS_WT_MAX_SESSIONS, 1)));
+
+        let mut s = webtransport_session(true, false);
+
+        assert!(!s.client.webtransport_enabled_by_peer());
+        assert!(s.server.webtransport_enabled_by_peer());
+
+        // Streams can't be opened unless the peer supports WebTransport.
+        let session_id = open_webtransport_session(&mut s);
 
-        // Sending RESET_STREAM again shouldn't trigger another Reset event.
         assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39)
+            s.client.open_webtransport_stream(
+                &mut s.pipe.client,
+                session_id,
+                false
+            ),
+            Err(Error::FrameUnexpected)
         );
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // The setting is handled by the library.
+        let mut h3_config = Config::new().unwrap();
+        assert_eq!(
+            h3_config.set_additional_settings(vec![(
+                frame::SETTINGS_WT_MAX_SESSIONS,
+                1
+            )]),
+            Err(Error::SettingsError)
+        );
     }
 
-    /// The client shuts down the stream's write direction, the server
-    /// shuts down its side with fin
     #[test]
-    fn client_shutdown_write_server_fin() {
-        let mut buf = [0; 65535];
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    fn webtransport_uni_stream() {
+        let mut s = webtransport_session(true, true);
 
-        // Client sends request.
-        let (stream, req) = s.send_request(false).unwrap();
+        let session_id = open_webtransport_session(&mut s);
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        let stream = s
+            .client
+            .open_webtransport_stream(&mut s.pipe.client, session_id, false)
+            .unwrap();
+        assert!(!crate::stream::is_bidi(stream));
 
-        // Server sends response and closes stream.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(
+            s.client
+                .send_body(&mut s.pipe.client, stream, b"hello", true),
+            Ok(5)
+        );
+        s.advance().ok();
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::WebTransportStream { session_id }))
+        );
+        assert_eq!(s.server.webtransport_session_id(stream), Some(session_id));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        // The data is received without any framing.
+        let mut buf = [0; 100];
+        assert_eq!(s.recv_body_server(stream, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
+    }
 
-        let resp = s.send_response(stream, true).unwrap();
+    #[test]
+    fn webtransport_bidi_stream() {
+        let mut s = webtransport_session(true, true);
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: false,
-        };
+        let session_id = open_webtransport_session(&mut s);
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        let stream = s
+            .client
+            .open_webtransport_stream(&mut s.pipe.client, session_id, true)
+            .unwrap();
+        assert_eq!(stream, session_id + 4);
 
-        // Client shuts down stream ==> sends RESET_STREAM
         assert_eq!(
-            s.pipe
-                .client
-                .stream_shutdown(stream, crate::Shutdown::Write, 42),
-            Ok(())
+            s.client
+                .send_body(&mut s.pipe.client, stream, b"ping", false),
+            Ok(4)
         );
-        assert_eq!(s.advance(), Ok(()));
+        s.advance().ok();
 
-        // Server sees the Reset event for the stream.
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::WebTransportStream { session_id }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        // Streams have been collected by quiche
-        assert!(s.pipe.server.streams.is_collected(stream));
-        assert!(s.pipe.client.streams.is_collected(stream));
+        let mut buf = [0; 100];
+        assert_eq!(s.recv_body_server(stream, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"ping");
 
-        // Client sends another request, server sends response without fin
-        //
-        let (stream, req) = s.send_request(false).unwrap();
+        // The server can reply on the same stream.
+        assert_eq!(
+            s.server
+                .send_body(&mut s.pipe.server, stream, b"pong", true),
+            Ok(4)
+        );
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"pong");
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+    }
 
-        // Check that server has received the request.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+    #[test]
+    fn webtransport_server_bidi_stream() {
+        let mut s = webtransport_session(true, true);
 
-        // Server sends reponse without closing the stream.
-        let resp = s.send_response(stream, false).unwrap();
+        let session_id = open_webtransport_session(&mut s);
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: true,
-        };
+        let stream = s
+            .server
+            .open_webtransport_stream(&mut s.pipe.server, session_id, true)
+            .unwrap();
+        assert_eq!(stream, 1);
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(
+            s.server
+                .send_body(&mut s.pipe.server, stream, b"ping", false),
+            Ok(4)
+        );
+        s.advance().ok();
 
-        // Client shuts down stream ==> sends RESET_STREAM
         assert_eq!(
-            s.pipe
-                .client
-                .stream_shutdown(stream, crate::Shutdown::Write, 42),
-            Ok(())
+            s.poll_client(),
+            Ok((stream, Event::WebTransportStream { session_id }))
         );
-        assert_eq!(s.advance(), Ok(()));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
 
-        // Server sees the Reset event for the stream.
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let mut buf = [0; 100];
+        assert_eq!(s.recv_body_client(stream, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"ping");
 
-        // Server sends body and closes the stream.
-        s.send_body_server(stream, true).unwrap();
+        assert_eq!(
+            s.client
+                .send_body(&mut s.pipe.client, stream, b"pong", true),
+            Ok(4)
+        );
+        s.advance().ok();
 
-        // Stream has been collected on server by quiche
-        assert!(s.pipe.server.streams.is_collected(stream));
-        // Client stream has not been collected, the client needs to
-        // read the fin from the stream first.
-        assert!(!s.pipe.client.streams.is_collected(stream));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        s.recv_body_client(stream, &mut buf).unwrap();
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert!(s.pipe.client.streams.is_collected(stream));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"pong");
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
     }
 
     #[test]
-    fn client_shutdown_read() {
-        let mut buf = [0; 65535];
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    fn webtransport_invalid_session_id() {
+        let mut s = webtransport_session(true, true);
 
-        // Client sends request and leaves stream open.
-        let (stream, req) = s.send_request(false).unwrap();
+        let stream = s.client.next_uni_stream_id;
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        b.put_varint(stream::WEBTRANSPORT_STREAM_TYPE_ID).unwrap();
+        // Not the ID of a client-initiated bidirectional stream.
+        b.put_varint(2).unwrap();
+        let off = b.off();
+
+        s.pipe.client.stream_send(stream, &d[..off], false).unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::IdError));
+    }
+
+    #[test]
+    fn webtransport_disabled() {
+        let mut s = webtransport_session(true, false);
+
+        let session_id = open_webtransport_session(&mut s);
+
+        // Uni streams are ignored.
+        let stream = s.client.next_uni_stream_id;
+
+        let mut d = [0; 16];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        b.put_varint(stream::WEBTRANSPORT_STREAM_TYPE_ID).unwrap();
+        b.put_varint(session_id).unwrap();
+        b.put_bytes(b"hello").unwrap();
+        let off = b.off();
+
+        s.pipe.client.stream_send(stream, &d[..off], true).unwrap();
+        s.advance().ok();
 
-        // Server sends response and leaves stream open.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let resp = s.send_response(stream, false).unwrap();
+        // The bidi stream signal is treated like an unknown frame type, so the
+        // stream can't be used for requests anymore.
+        let stream = s.client.next_request_stream_id;
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: true,
-        };
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        b.put_varint(frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID)
+            .unwrap();
+        b.put_varint(session_id).unwrap();
+        let off = b.off();
+
+        s.pipe.client.stream_send(stream, &d[..off], false).unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
+    #[test]
+    fn webtransport_close_session() {
+        let mut s = webtransport_session(true, true);
+
+        let session_id = open_webtransport_session(&mut s);
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        // Client shuts down read
         assert_eq!(
-            s.pipe
-                .client
-                .stream_shutdown(stream, crate::Shutdown::Read, 42),
+            s.server
+                .drain_webtransport_session(&mut s.pipe.server, session_id),
+            Ok(())
+        );
+        s.advance().ok();
+
+        assert_eq!(s.poll_client(), Ok((session_id, Event::Data)));
+        assert_eq!(
+            s.client.recv_capsule(&mut s.pipe.client, session_id),
+            Ok(Capsule::WebTransportDrainSession)
+        );
+
+        assert_eq!(
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                42,
+                &[b'a'; 1025]
+            ),
+            Err(Error::MessageError)
+        );
+
+        assert_eq!(
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                42,
+                b"bye"
+            ),
             Ok(())
         );
-        assert_eq!(s.advance(), Ok(()));
+        s.advance().ok();
 
-        // Stream is writable on server side, but returns StreamStopped
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        let writables: Vec<u64> = s.pipe.server.writable().collect();
-        assert!(writables.contains(&stream));
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Data)));
         assert_eq!(
-            s.send_body_server(stream, false),
-            Err(Error::TransportError(crate::Error::StreamStopped(42)))
+            s.server.recv_capsule(&mut s.pipe.server, session_id),
+            Ok(Capsule::WebTransportCloseSession {
+                error_code: 42,
+                reason: b"bye".to_vec(),
+            })
         );
-
-        // Client needs to finish its side by sending a fin
         assert_eq!(
-            s.client.send_body(&mut s.pipe.client, stream, &[], true),
-            Ok(0)
+            s.server.recv_capsule(&mut s.pipe.server, session_id),
+            Err(Error::Done)
         );
-        assert_eq!(s.advance(), Ok(()));
-        // Note, we get an Event::Data for an empty buffer today. But it
-        // would also be fine to not get it.
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut buf), Err(Error::Done));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        // Since the client has already send a fin, the stream is collected
-        // on both client and server
-        assert!(s.pipe.client.streams.is_collected(stream));
-        assert!(s.pipe.server.streams.is_collected(stream));
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Finished)));
     }
 
     #[test]
-    fn reset_finished_at_server() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    fn webtransport_requires_extended_connect_and_datagrams() {
+        let mut client_config = Config::new().unwrap();
+        client_config.set_webtransport_max_sessions(1);
 
-        // Client sends HEADERS and doesn't fin
-        let (stream, _req) = s.send_request(false).unwrap();
+        let mut server_config = Config::new().unwrap();
+        server_config.set_webtransport_max_sessions(1);
 
-        // ..then Client sends RESET_STREAM
-        assert_eq!(
-            s.pipe.client.stream_shutdown(0, crate::Shutdown::Write, 0),
-            Ok(())
+        // The server didn't enable extended CONNECT.
+        let s = webtransport_session_with_config(
+            &client_config,
+            &server_config,
+            true,
         );
 
-        assert_eq!(s.pipe.advance(), Ok(()));
-
-        // Server receives just a reset
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(0))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(!s.client.webtransport_enabled_by_peer());
+        assert!(s.server.webtransport_enabled_by_peer());
 
-        // Client sends HEADERS and fin
-        let (stream, req) = s.send_request(true).unwrap();
+        // Neither endpoint enabled HTTP/3 DATAGRAMs.
+        server_config.enable_extended_connect(true);
 
-        // ..then Client sends RESET_STREAM
-        assert_eq!(
-            s.pipe.client.stream_shutdown(4, crate::Shutdown::Write, 0),
-            Ok(())
+        let s = webtransport_session_with_config(
+            &client_config,
+            &server_config,
+            false,
         );
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
-
-        // Server receives headers and fin.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(s.client.extended_connect_enabled_by_peer());
+        assert!(!s.client.webtransport_enabled_by_peer());
+        assert!(!s.server.webtransport_enabled_by_peer());
     }
 
     #[test]
-    fn reset_finished_at_server_with_data_pending() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    fn webtransport_max_sessions() {
+        let mut s = webtransport_session(true, true);
 
-        // Client sends HEADERS and doesn't fin.
-        let (stream, req) = s.send_request(false).unwrap();
+        let session_id = open_webtransport_session(&mut s);
 
-        assert!(s.send_body_client(stream, false).is_ok());
+        // The server only allows one session at a time.
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"webtransport"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        let stream = s
+            .client
+            .send_request(&mut s.pipe.client, &req, false)
+            .unwrap();
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(!s.server.streams.contains_key(&stream));
+        s.advance().ok();
 
-        // Server receives headers and data...
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(
+            s.poll_client(),
+            Ok((stream, Event::Reset(Error::RequestRejected.to_wire())))
+        );
 
-        // ..then Client sends RESET_STREAM.
+        // Once the first session is closed, a new one can be established.
         assert_eq!(
-            s.pipe
-                .client
-                .stream_shutdown(stream, crate::Shutdown::Write, 0),
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                0,
+                b""
+            ),
             Ok(())
         );
+        s.advance().ok();
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Data)));
+        assert!(s
+            .server
+            .recv_capsule(&mut s.pipe.server, session_id)
+            .is_ok());
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Finished)));
 
-        // The server does *not* attempt to read from the stream,
-        // but polls and receives the reset and there are no more
-        // readable streams.
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(0))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.pipe.server.readable().len(), 0);
+        open_webtransport_session(&mut s);
     }
 
     #[test]
-    fn reset_finished_at_server_with_data_pending_2() {
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    fn webtransport_unknown_session() {
+        let mut s = webtransport_session(true, true);
 
-        // Client sends HEADERS and doesn't fin.
-        let (stream, req) = s.send_request(false).unwrap();
+        let session_id = open_webtransport_session(&mut s);
+        let unknown_session_id = session_id + 4;
 
-        assert!(s.send_body_client(stream, false).is_ok());
+        // Streams can't be opened for sessions that don't exist.
+        assert_eq!(
+            s.client.open_webtransport_stream(
+                &mut s.pipe.client,
+                unknown_session_id,
+                false
+            ),
+            Err(Error::FrameUnexpected)
+        );
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        // Peer's uni stream for an unknown session.
+        let uni_stream = s.client.next_uni_stream_id;
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        let mut d = [0; 16];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        b.put_varint(stream::WEBTRANSPORT_STREAM_TYPE_ID).unwrap();
+        b.put_varint(unknown_session_id).unwrap();
+        b.put_bytes(b"hello").unwrap();
+        let off = b.off();
 
-        // Server receives headers and data...
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        s.pipe
+            .client
+            .stream_send(uni_stream, &d[..off], false)
+            .unwrap();
 
-        // ..then Client sends RESET_STREAM.
-        assert_eq!(
-            s.pipe
-                .client
-                .stream_shutdown(stream, crate::Shutdown::Write, 0),
-            Ok(())
-        );
+        // Peer's bidi stream for an unknown session.
+        let bidi_stream = unknown_session_id + 4;
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        b.put_varint(frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID)
+            .unwrap();
+        b.put_varint(unknown_session_id).unwrap();
+        b.put_bytes(b"hello").unwrap();
+        let off = b.off();
 
-        // Server reads from the stream and receives the reset while
-        // attempting to read.
-        assert_eq!(
-            s.recv_body_server(stream, &mut [0; 100]),
-            Err(Error::TransportError(crate::Error::StreamReset(0)))
-        );
+        s.pipe
+            .client
+            .stream_send(bidi_stream, &d[..off], false)
+            .unwrap();
+        s.advance().ok();
 
-        // No more events and there are no more readable streams.
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.pipe.server.readable().len(), 0);
+        assert!(!s.server.streams.contains_key(&uni_stream));
+        assert!(!s.server.streams.contains_key(&bidi_stream));
+        s.advance().ok();
+
+        // Both streams were stopped by the server.
+        for stream in [uni_stream, bidi_stream] {
+            assert_eq!(
+                s.pipe.client.stream_send(stream, b"a", false),
+                Err(crate::Error::StreamStopped(
+                    WEBTRANSPORT_BUFFERED_STREAM_REJECTED
+                ))
+            );
+        }
+
+        assert_eq!(
+            s.poll_client(),
+            Ok((
+                bidi_stream,
+                Event::Reset(WEBTRANSPORT_BUFFERED_STREAM_REJECTED)
+            ))
+        );
     }
 
     #[test]
-    fn reset_finished_at_client() {
-        let mut buf = [0; 65535];
-        let mut s = Session::new().unwrap();
-        s.handshake().unwrap();
+    fn webtransport_close_session_resets_streams() {
+        let mut s = webtransport_session(true, true);
 
-        // Client sends HEADERS and doesn't fin
-        let (stream, req) = s.send_request(false).unwrap();
+        let session_id = open_webtransport_session(&mut s);
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: true,
-        };
+        let uni_stream = s
+            .client
+            .open_webtransport_stream(&mut s.pipe.client, session_id, false)
+            .unwrap();
+        let bidi_stream = s
+            .client
+            .open_webtransport_stream(&mut s.pipe.client, session_id, true)
+            .unwrap();
 
-        // Server receives headers.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        for stream in [uni_stream, bidi_stream] {
+            assert_eq!(
+                s.client
+                    .send_body(&mut s.pipe.client, stream, b"hello", false),
+                Ok(5)
+            );
+        }
+        s.advance().ok();
 
-        // Server sends response and doesn't fin
-        s.send_response(stream, false).unwrap();
+        let mut opened = Vec::new();
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        loop {
+            match s.poll_server() {
+                Ok((stream, Event::WebTransportStream { session_id: id })) => {
+                    assert_eq!(id, session_id);
+                    opened.push(stream);
+                },
+
+                Ok((stream, Event::Data)) => {
+                    let mut buf = [0; 100];
+                    assert_eq!(s.recv_body_server(stream, &mut buf), Ok(5));
+                },
+
+                Err(Error::Done) => break,
+
+                ev => panic!("unexpected event {ev:?}"),
+            }
+        }
+
+        opened.sort();
+        assert_eq!(opened, vec![bidi_stream, uni_stream]);
 
-        // .. then Server sends RESET_STREAM
         assert_eq!(
-            s.pipe
-                .server
-                .stream_shutdown(stream, crate::Shutdown::Write, 0),
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                0,
+                b""
+            ),
             Ok(())
         );
 
-        assert_eq!(s.pipe.advance(), Ok(()));
-
-        // Client receives Reset only
-        assert_eq!(s.poll_client(), Ok((stream, Event::Reset(0))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.client.webtransport_session_id(uni_stream), None);
+        assert_eq!(s.client.webtransport_session_id(bidi_stream), None);
+        assert_eq!(
+            s.client.open_webtransport_stream(
+                &mut s.pipe.client,
+                session_id,
+                false
+            ),
+            Err(Error::FrameUnexpected)
+        );
 
-        // Client sends headers and fin.
-        let (stream, req) = s.send_request(true).unwrap();
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            more_frames: false,
-        };
+        // Both streams were reset.
+        let mut buf = [0; 100];
 
-        // Server receives headers and fin.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        for stream in [uni_stream, bidi_stream] {
+            assert_eq!(
+                s.pipe.server.stream_recv(stream, &mut buf),
+                Err(crate::Error::StreamReset(WEBTRANSPORT_SESSION_GONE))
+            );
+        }
+    }
 
-        // Server sends response and fin
-        let resp = s.send_response(stream, true).unwrap();
+    #[test]
+    fn webtransport_datagram() {
+        let mut buf = [0; 65535];
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        let mut s = webtransport_session(true, true);
 
-        // ..then Server sends RESET_STREAM
-        let frames = [crate::frame::Frame::ResetStream {
-            stream_id: stream,
-            error_code: 42,
-            final_size: 68,
-        }];
+        let session_id = open_webtransport_session(&mut s);
 
-        let pkt_type = crate::packet::Type::Short;
         assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39)
+            s.client
+                .send_http_datagram(&mut s.pipe.client, session_id, b"hello"),
+            Ok(())
         );
+        s.advance().ok();
 
-        assert_eq!(s.pipe.advance(), Ok(()));
-
-        let ev_headers = Event::Headers {
-            list: resp,
-            more_frames: false,
-        };
+        assert_eq!(
+            s.server.recv_http_datagram(&mut s.pipe.server, &mut buf),
+            Ok((session_id, 5))
+        );
+        assert_eq!(&buf[..5], b"hello");
 
-        // Client receives headers and fin.
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(
+            s.server.recv_http_datagram(&mut s.pipe.server, &mut buf),
+            Err(Error::Done)
+        );
     }
 }
 
+pub use capsule::AddressEntry;
+pub use capsule::Capsule;
+pub use capsule::IpAddressRange;
+
+mod capsule;
 #[cfg(feature = "ffi")]
 mod ffi;
 #[cfg(feature = "internal")]
@@ -7545,4 +11011,5 @@ pub mod frame;
 mod frame;
 #[doc(hidden)]
 pub mod qpack;
+mod snapshot;
 mod stream;
@@ -24,15 +24,32 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::collections::HashSet;
+
 use super::Error;
 use super::Result;
 
 use crate::h3::Header;
+use crate::snapshot;
+
+use super::dynamic_table::DynamicTable;
+
+use super::encoder::encode_int;
 
 use super::INDEXED;
 use super::INDEXED_WITH_POST_BASE;
+use super::INSERT_COUNT_INCREMENT;
+use super::INSERT_WITH_LITERAL_NAME;
+use super::INSERT_WITH_NAME_REF;
 use super::LITERAL;
 use super::LITERAL_WITH_NAME_REF;
+use super::SECTION_ACKNOWLEDGEMENT;
+use super::SET_DYNAMIC_TABLE_CAPACITY;
+use super::STREAM_CANCELLATION;
+
+/// The maximum overhead of an encoder instruction, besides the name and value
+/// of the inserted entry.
+const MAX_ENCODER_INSTRUCTION_OVERHEAD: usize = 32;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum Representation {
@@ -67,7 +84,25 @@ impl Representation {
 
 /// A QPACK decoder.
 #[derive(Default)]
-pub struct Decoder {}
+pub struct Decoder {
+    /// The decoder's view of the dynamic table.
+    table: DynamicTable,
+
+    /// The maximum number of streams that can be blocked.
+    max_blocked_streams: u64,
+
+    /// The streams currently blocked on encoder instructions.
+    blocked_streams: HashSet<u64>,
+
+    /// The number of insertions the encoder knows were received.
+    acked_insert_count: u64,
+
+    /// Partial encoder instruction received on the encoder stream.
+    encoder_buf: Vec<u8>,
+
+    /// Decoder instructions that need to be sent on the decoder stream.
+    instructions: Vec<u8>,
+}
 
 impl Decoder {
     /// Creates a new QPACK decoder.
@@ -75,25 +110,358 @@ impl Decoder {
         Decoder::default()
     }
 
+    /// Writes the decoder state to a connection snapshot.
+    pub(crate) fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        self.table.to_snapshot(w);
+
+        w.put_u64(self.max_blocked_streams);
+
+        w.put_u64(self.blocked_streams.len() as u64);
+        for stream_id in &self.blocked_streams {
+            w.put_u64(*stream_id);
+        }
+
+        w.put_u64(self.acked_insert_count);
+        w.put_bytes(&self.encoder_buf);
+        w.put_bytes(&self.instructions);
+    }
+
+    /// Reads decoder state written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.Decoder.html#method.to_snapshot
+    pub(crate) fn from_snapshot(
+        r: &mut snapshot::Reader,
+    ) -> crate::Result<Decoder> {
+        let table = DynamicTable::from_snapshot(r)?;
+        let max_blocked_streams = r.get_u64()?;
+
+        let mut blocked_streams = HashSet::new();
+        for _ in 0..r.get_count(8)? {
+            blocked_streams.insert(r.get_u64()?);
+        }
+
+        Ok(Decoder {
+            table,
+            max_blocked_streams,
+            blocked_streams,
+            acked_insert_count: r.get_u64()?,
+            encoder_buf: r.get_bytes()?.to_vec(),
+            instructions: r.get_bytes()?.to_vec(),
+        })
+    }
+
+    /// Sets the maximum capacity of the dynamic table, as advertised to the
+    /// peer's encoder with `SETTINGS_QPACK_MAX_TABLE_CAPACITY`.
+    pub fn set_max_table_capacity(&mut self, v: u64) {
+        self.table.set_max_capacity(v);
+    }
+
+    /// Sets the maximum number of streams that can be blocked, as advertised
+    /// to the peer's encoder with `SETTINGS_QPACK_BLOCKED_STREAMS`.
+    pub fn set_max_blocked_streams(&mut self, v: u64) {
+        self.max_blocked_streams = v;
+    }
+
+    /// Returns the number of insertions into the dynamic table.
+    pub fn insert_count(&self) -> u64 {
+        self.table.insert_count()
+    }
+
+    /// Returns the number of streams currently blocked.
+    pub fn blocked_streams(&self) -> usize {
+        self.blocked_streams.len()
+    }
+
     /// Processes control instructions from the encoder.
-    pub fn control(&mut self, _buf: &mut [u8]) -> Result<()> {
-        // TODO: process control instructions
+    ///
+    /// Partial instructions are buffered until the rest of the data is
+    /// provided by later calls.
+    pub fn control(&mut self, buf: &[u8]) -> Result<()> {
+        // Take the buffer out to process instructions, as those need to
+        // modify the dynamic table.
+        let mut encoder_buf = std::mem::take(&mut self.encoder_buf);
+        encoder_buf.extend_from_slice(buf);
+
+        let mut b = octets::Octets::with_slice(&encoder_buf);
+
+        let mut consumed = 0;
+
+        while b.cap() > 0 {
+            match self.process_instruction(&mut b) {
+                Ok(()) => consumed = b.off(),
+
+                // Wait for the rest of the instruction.
+                Err(Error::BufferTooShort) => break,
+
+                Err(_) => return Err(Error::EncoderStreamError),
+            }
+        }
+
+        encoder_buf.drain(..consumed);
+        self.encoder_buf = encoder_buf;
+
+        if self.encoder_buf.len() >
+            self.table.max_capacity() as usize +
+                MAX_ENCODER_INSTRUCTION_OVERHEAD
+        {
+            return Err(Error::EncoderStreamError);
+        }
+
+        // Let the encoder know about the new insertions.
+        let increment = self.table.insert_count() - self.acked_insert_count;
+
+        if increment > 0 {
+            self.put_instruction(increment, INSERT_COUNT_INCREMENT, 6);
+            self.acked_insert_count = self.table.insert_count();
+        }
+
         Ok(())
     }
 
     /// Decodes a QPACK header block into a list of headers.
+    ///
+    /// Unlike [`decode_field_section()`], this doesn't acknowledge the header
+    /// block, and fails with [`Blocked`] if it references dynamic table
+    /// entries that haven't been received yet.
+    ///
+    /// [`decode_field_section()`]: struct.Decoder.html#method.decode_field_section
+    /// [`Blocked`]: enum.Error.html#variant.Blocked
     pub fn decode(&mut self, buf: &[u8], max_size: u64) -> Result<Vec<Header>> {
         let mut b = octets::Octets::with_slice(buf);
 
+        let req_insert_count = self.decode_required_insert_count(&mut b)?;
+
+        if req_insert_count > self.table.insert_count() {
+            return Err(Error::Blocked);
+        }
+
+        self.decode_field_lines(&mut b, req_insert_count, max_size)
+    }
+
+    /// Decodes a QPACK field section received on the given stream into a list
+    /// of headers.
+    ///
+    /// When the field section references dynamic table entries that haven't
+    /// been received yet, the stream is marked as blocked and the
+    /// [`Blocked`] error is returned. Decoding needs to be retried after more
+    /// encoder instructions have been processed with [`control()`].
+    ///
+    /// [`Blocked`]: enum.Error.html#variant.Blocked
+    /// [`control()`]: struct.Decoder.html#method.control
+    pub fn decode_field_section(
+        &mut self, stream_id: u64, buf: &[u8], max_size: u64,
+    ) -> Result<Vec<Header>> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        let req_insert_count = self.decode_required_insert_count(&mut b)?;
+
+        if req_insert_count > self.table.insert_count() {
+            if !self.blocked_streams.contains(&stream_id) {
+                if self.blocked_streams.len() as u64 >= self.max_blocked_streams {
+                    return Err(Error::TooManyBlockedStreams);
+                }
+
+                self.blocked_streams.insert(stream_id);
+            }
+
+            return Err(Error::Blocked);
+        }
+
+        self.blocked_streams.remove(&stream_id);
+
+        let headers =
+            self.decode_field_lines(&mut b, req_insert_count, max_size)?;
+
+        if req_insert_count > 0 {
+            self.put_instruction(stream_id, SECTION_ACKNOWLEDGEMENT, 7);
+
+            self.acked_insert_count =
+                self.acked_insert_count.max(req_insert_count);
+        }
+
+        Ok(headers)
+    }
+
+    /// Abandons decoding of field sections on the given stream, e.g. because
+    /// the stream was reset.
+    pub fn cancel_stream(&mut self, stream_id: u64) {
+        self.blocked_streams.remove(&stream_id);
+
+        // Stream cancellations are only needed when the dynamic table is
+        // enabled.
+        if self.table.max_capacity() > 0 {
+            self.put_instruction(stream_id, STREAM_CANCELLATION, 6);
+        }
+    }
+
+    /// Returns the pending instructions that need to be sent on the decoder
+    /// stream.
+    pub fn instructions(&self) -> &[u8] {
+        &self.instructions
+    }
+
+    /// Removes the first `len` bytes of pending instructions, after they have
+    /// been sent on the decoder stream.
+    pub fn consume_instructions(&mut self, len: usize) {
+        self.instructions.drain(..len.min(self.instructions.len()));
+    }
+
+    fn put_instruction(&mut self, v: u64, first: u8, prefix: usize) {
+        let mut d = [0; 10];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        // An integer always fits in 10 bytes.
+        encode_int(v, first, prefix, &mut b).unwrap();
+
+        let off = b.off();
+        self.instructions.extend_from_slice(&d[..off]);
+    }
+
+    /// Processes a single encoder instruction.
+    fn process_instruction(&mut self, b: &mut octets::Octets) -> Result<()> {
+        let first = b.peek_u8()?;
+
+        if first & INSERT_WITH_NAME_REF == INSERT_WITH_NAME_REF {
+            const STATIC: u8 = 0x40;
+
+            let s = first & STATIC == STATIC;
+            let name_idx = decode_int(b, 6)?;
+            let value = decode_str(b)?;
+
+            trace!("Insert With Name Reference name_idx={name_idx} static={s} value={value:?}");
+
+            let name = if s {
+                lookup_static(name_idx)?.0.to_vec()
+            } else {
+                let idx = self
+                    .table
+                    .insert_count()
+                    .checked_sub(name_idx + 1)
+                    .ok_or(Error::InvalidDynamicTableIndex)?;
+
+                self.lookup_dynamic(idx)?.0.to_vec()
+            };
+
+            self.insert(name, value)?;
+        } else if first & INSERT_WITH_LITERAL_NAME == INSERT_WITH_LITERAL_NAME {
+            let name_huff = first & 0x20 == 0x20;
+            let name_len = decode_int(b, 5)? as usize;
+
+            let mut name = b.get_bytes(name_len)?;
+
+            let name = if name_huff {
+                name.get_huffman_decoded()?
+            } else {
+                name.to_vec()
+            };
+
+            let value = decode_str(b)?;
+
+            trace!("Insert With Literal Name name={name:?} value={value:?}");
+
+            self.insert(name, value)?;
+        } else if first & SET_DYNAMIC_TABLE_CAPACITY == SET_DYNAMIC_TABLE_CAPACITY
+        {
+            let capacity = decode_int(b, 5)?;
+
+            trace!("Set Dynamic Table Capacity capacity={capacity}");
+
+            if !self.table.set_capacity(capacity) {
+                return Err(Error::InvalidDynamicTableCapacity);
+            }
+        } else {
+            let idx = decode_int(b, 5)?;
+
+            trace!("Duplicate idx={idx}");
+
+            let idx = self
+                .table
+                .insert_count()
+                .checked_sub(idx + 1)
+                .ok_or(Error::InvalidDynamicTableIndex)?;
+
+            let (name, value) = self.lookup_dynamic(idx)?;
+            let (name, value) = (name.to_vec(), value.to_vec());
+
+            self.insert(name, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.table
+            .insert(name, value)
+            .ok_or(Error::InvalidDynamicTableCapacity)?;
+
+        Ok(())
+    }
+
+    /// Decodes the encoded Required Insert Count of a header block.
+    ///
+    /// See [RFC 9204 Section 4.5.1.1](https://www.rfc-editor.org/rfc/rfc9204.html#section-4.5.1.1).
+    fn decode_required_insert_count(
+        &self, b: &mut octets::Octets,
+    ) -> Result<u64> {
+        let encoded = decode_int(b, 8)?;
+
+        if encoded == 0 {
+            return Ok(0);
+        }
+
+        let max_entries = self.table.max_entries();
+        let full_range = 2 * max_entries;
+
+        if encoded > full_range {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
+        let max_value = self.table.insert_count() + max_entries;
+        let max_wrapped = (max_value / full_range) * full_range;
+
+        let mut req_insert_count = max_wrapped + encoded - 1;
+
+        if req_insert_count > max_value {
+            if req_insert_count <= full_range {
+                return Err(Error::InvalidDynamicTableIndex);
+            }
+
+            req_insert_count -= full_range;
+        }
+
+        if req_insert_count == 0 {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
+        Ok(req_insert_count)
+    }
+
+    fn decode_field_lines(
+        &self, b: &mut octets::Octets, req_insert_count: u64, max_size: u64,
+    ) -> Result<Vec<Header>> {
         let mut out = Vec::new();
 
         let mut left = max_size;
 
-        let req_insert_count = decode_int(&mut b, 8)?;
-        let base = decode_int(&mut b, 7)?;
+        let sign = b.peek_u8()? & 0x80 == 0x80;
+        let delta_base = decode_int(b, 7)?;
+
+        let base = if sign {
+            req_insert_count
+                .checked_sub(delta_base + 1)
+                .ok_or(Error::InvalidDynamicTableIndex)?
+        } else {
+            req_insert_count
+                .checked_add(delta_base)
+                .ok_or(Error::InvalidDynamicTableIndex)?
+        };
 
         trace!("Header count={req_insert_count} base={base}");
 
+        // The largest absolute index referenced, used to validate the
+        // Required Insert Count.
+        let mut max_index = None;
+
         while b.cap() > 0 {
             let first = b.peek_u8()?;
 
@@ -102,16 +470,19 @@ impl Decoder {
                     const STATIC: u8 = 0x40;
 
                     let s = first & STATIC == STATIC;
-                    let index = decode_int(&mut b, 6)?;
+                    let index = decode_int(b, 6)?;
 
                     trace!("Indexed index={index} static={s}");
 
-                    if !s {
-                        // TODO: implement dynamic table
-                        return Err(Error::InvalidHeaderValue);
-                    }
-
-                    let (name, value) = lookup_static(index)?;
+                    let (name, value) = if s {
+                        lookup_static(index)?
+                    } else {
+                        self.lookup_field_line(
+                            base.checked_sub(index + 1),
+                            req_insert_count,
+                            &mut max_index,
+                        )?
+                    };
 
                     left = left
                         .checked_sub((name.len() + value.len()) as u64)
@@ -122,17 +493,27 @@ impl Decoder {
                 },
 
                 Representation::IndexedWithPostBase => {
-                    let index = decode_int(&mut b, 4)?;
+                    let index = decode_int(b, 4)?;
 
                     trace!("Indexed With Post Base index={index}");
 
-                    // TODO: implement dynamic table
-                    return Err(Error::InvalidHeaderValue);
+                    let (name, value) = self.lookup_field_line(
+                        base.checked_add(index),
+                        req_insert_count,
+                        &mut max_index,
+                    )?;
+
+                    left = left
+                        .checked_sub((name.len() + value.len()) as u64)
+                        .ok_or(Error::HeaderListTooLarge)?;
+
+                    let hdr = Header::new(name, value);
+                    out.push(hdr);
                 },
 
                 Representation::Literal => {
                     let name_huff = b.as_ref()[0] & 0x08 == 0x08;
-                    let name_len = decode_int(&mut b, 3)? as usize;
+                    let name_len = decode_int(b, 3)? as usize;
 
                     let mut name = b.get_bytes(name_len)?;
 
@@ -143,7 +524,7 @@ impl Decoder {
                     };
 
                     let name = name.to_vec();
-                    let value = decode_str(&mut b)?;
+                    let value = decode_str(b)?;
 
                     trace!(
                         "Literal Without Name Reference name={name:?} value={value:?}",
@@ -163,19 +544,22 @@ impl Decoder {
                     const STATIC: u8 = 0x10;
 
                     let s = first & STATIC == STATIC;
-                    let name_idx = decode_int(&mut b, 4)?;
-                    let value = decode_str(&mut b)?;
+                    let name_idx = decode_int(b, 4)?;
+                    let value = decode_str(b)?;
 
                     trace!(
                         "Literal name_idx={name_idx} static={s} value={value:?}"
                     );
 
-                    if !s {
-                        // TODO: implement dynamic table
-                        return Err(Error::InvalidHeaderValue);
-                    }
-
-                    let (name, _) = lookup_static(name_idx)?;
+                    let (name, _) = if s {
+                        lookup_static(name_idx)?
+                    } else {
+                        self.lookup_field_line(
+                            base.checked_sub(name_idx + 1),
+                            req_insert_count,
+                            &mut max_index,
+                        )?
+                    };
 
                     left = left
                         .checked_sub((name.len() + value.len()) as u64)
@@ -189,16 +573,57 @@ impl Decoder {
                 },
 
                 Representation::LiteralWithPostBase => {
-                    trace!("Literal With Post Base");
+                    let name_idx = decode_int(b, 3)?;
+                    let value = decode_str(b)?;
+
+                    trace!(
+                        "Literal With Post Base name_idx={name_idx} value={value:?}"
+                    );
+
+                    let (name, _) = self.lookup_field_line(
+                        base.checked_add(name_idx),
+                        req_insert_count,
+                        &mut max_index,
+                    )?;
+
+                    left = left
+                        .checked_sub((name.len() + value.len()) as u64)
+                        .ok_or(Error::HeaderListTooLarge)?;
 
-                    // TODO: implement dynamic table
-                    return Err(Error::InvalidHeaderValue);
+                    let hdr = Header(name.to_vec(), value);
+                    out.push(hdr);
                 },
             }
         }
 
+        // The Required Insert Count must match the largest referenced entry.
+        if req_insert_count != max_index.map_or(0, |idx| idx + 1) {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
         Ok(out)
     }
+
+    /// Looks up a dynamic table entry referenced by a field line, making sure
+    /// it's covered by the header block's Required Insert Count.
+    fn lookup_field_line(
+        &self, idx: Option<u64>, req_insert_count: u64,
+        max_index: &mut Option<u64>,
+    ) -> Result<(&[u8], &[u8])> {
+        let idx = idx.ok_or(Error::InvalidDynamicTableIndex)?;
+
+        if idx >= req_insert_count {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
+        *max_index = (*max_index).max(Some(idx));
+
+        self.lookup_dynamic(idx)
+    }
+
+    fn lookup_dynamic(&self, idx: u64) -> Result<(&[u8], &[u8])> {
+        self.table.get(idx).ok_or(Error::InvalidDynamicTableIndex)
+    }
 }
 
 fn lookup_static(idx: u64) -> Result<(&'static [u8], &'static [u8])> {
@@ -209,7 +634,7 @@ fn lookup_static(idx: u64) -> Result<(&'static [u8], &'static [u8])> {
     Ok(super::static_table::STATIC_DECODE_TABLE[idx as usize])
 }
 
-fn decode_int(b: &mut octets::Octets, prefix: usize) -> Result<u64> {
+pub fn decode_int(b: &mut octets::Octets, prefix: usize) -> Result<u64> {
     let mask = 2u64.pow(prefix as u32) - 1;
 
     let mut val = u64::from(b.get_u8()?);
@@ -0,0 +1,338 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::VecDeque;
+
+use crate::snapshot;
+
+/// The per-entry overhead used when calculating the size of an entry.
+///
+/// See [RFC 9204 Section 3.2.1](https://www.rfc-editor.org/rfc/rfc9204.html#section-3.2.1).
+pub const ENTRY_OVERHEAD: u64 = 32;
+
+/// Returns the size of a dynamic table entry with the given name and value.
+pub fn entry_size(name: &[u8], value: &[u8]) -> u64 {
+    (name.len() + value.len()) as u64 + ENTRY_OVERHEAD
+}
+
+/// The QPACK dynamic table.
+///
+/// Entries are addressed using their absolute index, that is, the number of
+/// entries inserted into the table before them. The oldest entry is at the
+/// front of the queue, and is the first one to be evicted.
+#[derive(Default)]
+pub struct DynamicTable {
+    /// The entries currently in the table, oldest first.
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+
+    /// The sum of the size of all the entries in the table.
+    size: u64,
+
+    /// The current capacity of the table.
+    capacity: u64,
+
+    /// The maximum capacity the table can be set to.
+    max_capacity: u64,
+
+    /// The total number of insertions into the table.
+    insert_count: u64,
+}
+
+impl DynamicTable {
+    /// Writes the table to a connection snapshot.
+    pub(crate) fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_u64(self.capacity);
+        w.put_u64(self.max_capacity);
+        w.put_u64(self.insert_count);
+
+        w.put_u64(self.entries.len() as u64);
+        for (name, value) in &self.entries {
+            w.put_bytes(name);
+            w.put_bytes(value);
+        }
+    }
+
+    /// Reads a table written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.DynamicTable.html#method.to_snapshot
+    pub(crate) fn from_snapshot(
+        r: &mut snapshot::Reader,
+    ) -> crate::Result<DynamicTable> {
+        let mut table = DynamicTable {
+            capacity: r.get_u64()?,
+            max_capacity: r.get_u64()?,
+            insert_count: r.get_u64()?,
+            ..Default::default()
+        };
+
+        for _ in 0..r.get_count(16)? {
+            let name = r.get_bytes()?.to_vec();
+            let value = r.get_bytes()?.to_vec();
+
+            table.size += entry_size(&name, &value);
+            table.entries.push_back((name, value));
+        }
+
+        if table.size > table.capacity ||
+            table.capacity > table.max_capacity ||
+            table.entries.len() as u64 > table.insert_count
+        {
+            return Err(crate::Error::InvalidSnapshot);
+        }
+
+        Ok(table)
+    }
+
+    /// Returns the maximum capacity of the table.
+    pub fn max_capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    /// Sets the maximum capacity of the table.
+    pub fn set_max_capacity(&mut self, v: u64) {
+        self.max_capacity = v;
+    }
+
+    /// Returns the maximum number of entries the table can hold.
+    pub fn max_entries(&self) -> u64 {
+        self.max_capacity / ENTRY_OVERHEAD
+    }
+
+    /// Returns the current capacity of the table.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Returns the total number of insertions into the table.
+    pub fn insert_count(&self) -> u64 {
+        self.insert_count
+    }
+
+    /// Returns the absolute index of the oldest entry still in the table.
+    pub fn dropped_count(&self) -> u64 {
+        self.insert_count - self.entries.len() as u64
+    }
+
+    /// Updates the capacity of the table, evicting entries as needed.
+    ///
+    /// Returns `false` if the new capacity exceeds the maximum capacity.
+    pub fn set_capacity(&mut self, v: u64) -> bool {
+        if v > self.max_capacity {
+            return false;
+        }
+
+        self.capacity = v;
+
+        while self.size > self.capacity {
+            self.evict_one();
+        }
+
+        true
+    }
+
+    /// Returns whether an entry of the given size can be inserted without
+    /// evicting any entry with an absolute index equal to or larger than
+    /// `pinned`.
+    pub fn can_insert(&self, size: u64, pinned: u64) -> bool {
+        size <= self.capacity && self.can_evict_to(self.capacity - size, pinned)
+    }
+
+    /// Returns whether the table's size can be reduced to `size` without
+    /// evicting any entry with an absolute index equal to or larger than
+    /// `pinned`.
+    pub fn can_evict_to(&self, size: u64, pinned: u64) -> bool {
+        let mut left = self.size;
+
+        for (i, (name, value)) in self.entries.iter().enumerate() {
+            if left <= size {
+                break;
+            }
+
+            if self.dropped_count() + i as u64 >= pinned {
+                return false;
+            }
+
+            left -= entry_size(name, value);
+        }
+
+        left <= size
+    }
+
+    /// Inserts a new entry, evicting older entries as needed.
+    ///
+    /// Returns the absolute index of the new entry, or `None` if the entry
+    /// is larger than the table's capacity.
+    pub fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) -> Option<u64> {
+        let size = entry_size(&name, &value);
+
+        if size > self.capacity {
+            return None;
+        }
+
+        while self.size + size > self.capacity {
+            self.evict_one();
+        }
+
+        self.size += size;
+        self.entries.push_back((name, value));
+
+        self.insert_count += 1;
+
+        Some(self.insert_count - 1)
+    }
+
+    /// Returns the entry with the given absolute index, if it's still in the
+    /// table.
+    pub fn get(&self, index: u64) -> Option<(&[u8], &[u8])> {
+        let idx = index.checked_sub(self.dropped_count())?;
+
+        self.entries
+            .get(idx as usize)
+            .map(|(n, v)| (n.as_slice(), v.as_slice()))
+    }
+
+    /// Looks for an entry matching the given name and value, starting from
+    /// the most recent one, and only considering entries with an absolute
+    /// index lower than `limit`.
+    ///
+    /// Returns the absolute index of the entry, and whether the value
+    /// matched as well, with full matches being preferred over name-only
+    /// ones.
+    pub fn find(
+        &self, name: &[u8], value: &[u8], limit: u64,
+    ) -> Option<(u64, bool)> {
+        let dropped = self.dropped_count();
+
+        let mut name_match = None;
+
+        for (i, (n, v)) in self.entries.iter().enumerate().rev() {
+            let index = dropped + i as u64;
+
+            if index >= limit || !n.eq_ignore_ascii_case(name) {
+                continue;
+            }
+
+            if v == value {
+                return Some((index, true));
+            }
+
+            if name_match.is_none() {
+                name_match = Some((index, false));
+            }
+        }
+
+        name_match
+    }
+
+    fn evict_one(&mut self) {
+        if let Some((name, value)) = self.entries.pop_front() {
+            self.size -= entry_size(&name, &value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(capacity: u64) -> DynamicTable {
+        let mut t = DynamicTable::default();
+        t.set_max_capacity(capacity);
+        assert!(t.set_capacity(capacity));
+        t
+    }
+
+    #[test]
+    fn insert_and_evict() {
+        let mut t = table(100);
+
+        assert_eq!(t.insert(b"foo".to_vec(), b"bar".to_vec()), Some(0));
+        assert_eq!(t.insert(b"abc".to_vec(), b"def".to_vec()), Some(1));
+        assert_eq!(t.dropped_count(), 0);
+
+        // The third entry doesn't fit, so the first one is evicted.
+        assert_eq!(t.insert(b"ghi".to_vec(), b"jkl".to_vec()), Some(2));
+        assert_eq!(t.dropped_count(), 1);
+        assert_eq!(t.get(0), None);
+        assert_eq!(t.get(1), Some((&b"abc"[..], &b"def"[..])));
+        assert_eq!(t.get(2), Some((&b"ghi"[..], &b"jkl"[..])));
+        assert_eq!(t.get(3), None);
+
+        // Entries larger than the capacity are rejected.
+        assert_eq!(t.insert(vec![b'a'; 60], vec![b'b'; 60]), None);
+        assert_eq!(t.insert_count(), 3);
+    }
+
+    #[test]
+    fn capacity() {
+        let mut t = table(100);
+
+        t.insert(b"foo".to_vec(), b"bar".to_vec());
+        t.insert(b"abc".to_vec(), b"def".to_vec());
+
+        assert!(!t.set_capacity(101));
+
+        assert!(t.set_capacity(40));
+        assert_eq!(t.dropped_count(), 1);
+        assert_eq!(t.get(1), Some((&b"abc"[..], &b"def"[..])));
+
+        assert!(t.set_capacity(0));
+        assert_eq!(t.dropped_count(), 2);
+        assert_eq!(t.get(1), None);
+    }
+
+    #[test]
+    fn can_insert_pinned() {
+        let mut t = table(100);
+
+        t.insert(b"foo".to_vec(), b"bar".to_vec());
+        t.insert(b"abc".to_vec(), b"def".to_vec());
+
+        assert!(t.can_insert(24, 0));
+        assert!(t.can_insert(38, 1));
+        assert!(!t.can_insert(38, 0));
+        assert!(!t.can_insert(101, 2));
+
+        assert!(t.can_evict_to(38, 1));
+        assert!(!t.can_evict_to(37, 1));
+        assert!(t.can_evict_to(0, 2));
+    }
+
+    #[test]
+    fn find() {
+        let mut t = table(200);
+
+        t.insert(b"foo".to_vec(), b"bar".to_vec());
+        t.insert(b"foo".to_vec(), b"baz".to_vec());
+        t.insert(b"abc".to_vec(), b"def".to_vec());
+
+        assert_eq!(t.find(b"foo", b"bar", u64::MAX), Some((0, true)));
+        assert_eq!(t.find(b"FOO", b"qux", u64::MAX), Some((1, false)));
+        assert_eq!(t.find(b"foo", b"baz", 1), Some((0, false)));
+        assert_eq!(t.find(b"abc", b"def", 2), None);
+        assert_eq!(t.find(b"xyz", b"def", u64::MAX), None);
+    }
+}
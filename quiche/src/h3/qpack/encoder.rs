@@ -24,17 +24,84 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use super::Error;
 use super::Result;
 
 use crate::h3::NameValue;
+use crate::snapshot;
+
+use super::dynamic_table::entry_size;
+use super::dynamic_table::DynamicTable;
+
+use super::decoder::decode_int;
 
 use super::INDEXED;
+use super::INSERT_COUNT_INCREMENT;
+use super::INSERT_WITH_LITERAL_NAME;
+use super::INSERT_WITH_NAME_REF;
 use super::LITERAL;
 use super::LITERAL_WITH_NAME_REF;
+use super::SECTION_ACKNOWLEDGEMENT;
+use super::SET_DYNAMIC_TABLE_CAPACITY;
+use super::STREAM_CANCELLATION;
+
+/// The maximum size of a partially received decoder stream instruction.
+const MAX_DECODER_INSTRUCTION_LEN: usize = 16;
+
+/// How a single field line is represented in a field section.
+enum FieldLine<'a> {
+    /// Indexed field line referencing the static table.
+    Static(u64),
+
+    /// Literal field line with a name reference to the static table.
+    StaticNameRef(u64, &'a [u8]),
+
+    /// Indexed field line referencing the dynamic table.
+    Dynamic(u64),
+
+    /// Literal field line with a name reference to the dynamic table.
+    DynamicNameRef(u64, &'a [u8]),
+
+    /// Literal field line with a literal name.
+    Literal(&'a [u8], &'a [u8]),
+}
+
+/// A field section that hasn't been acknowledged by the decoder yet.
+struct Section {
+    /// The Required Insert Count of the section.
+    required_insert_count: u64,
+
+    /// The lowest absolute index referenced by the section.
+    min_index: u64,
+}
 
 /// A QPACK encoder.
 #[derive(Default)]
-pub struct Encoder {}
+pub struct Encoder {
+    /// The encoder's view of the dynamic table.
+    table: DynamicTable,
+
+    /// The maximum number of streams that can be blocked on the decoder.
+    max_blocked_streams: u64,
+
+    /// The number of insertions acknowledged by the decoder.
+    known_received_count: u64,
+
+    /// Field sections that haven't been acknowledged yet, by stream ID.
+    sections: HashMap<u64, VecDeque<Section>>,
+
+    /// The last encoded field section, until committed.
+    pending_section: Option<(u64, Section)>,
+
+    /// Encoder instructions that need to be sent on the encoder stream.
+    instructions: Vec<u8>,
+
+    /// Partial decoder instruction received on the decoder stream.
+    decoder_buf: Vec<u8>,
+}
 
 impl Encoder {
     /// Creates a new QPACK encoder.
@@ -42,7 +109,126 @@ impl Encoder {
         Encoder::default()
     }
 
+    /// Writes the encoder state to a connection snapshot.
+    ///
+    /// A field section that was never committed is not part of the snapshot.
+    pub(crate) fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        self.table.to_snapshot(w);
+
+        w.put_u64(self.max_blocked_streams);
+        w.put_u64(self.known_received_count);
+
+        w.put_u64(self.sections.len() as u64);
+        for (stream_id, sections) in &self.sections {
+            w.put_u64(*stream_id);
+
+            w.put_u64(sections.len() as u64);
+            for section in sections {
+                w.put_u64(section.required_insert_count);
+                w.put_u64(section.min_index);
+            }
+        }
+
+        w.put_bytes(&self.instructions);
+        w.put_bytes(&self.decoder_buf);
+    }
+
+    /// Reads encoder state written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.Encoder.html#method.to_snapshot
+    pub(crate) fn from_snapshot(
+        r: &mut snapshot::Reader,
+    ) -> crate::Result<Encoder> {
+        let table = DynamicTable::from_snapshot(r)?;
+        let max_blocked_streams = r.get_u64()?;
+        let known_received_count = r.get_u64()?;
+
+        let mut sections = HashMap::new();
+
+        for _ in 0..r.get_count(16)? {
+            let stream_id = r.get_u64()?;
+
+            let mut stream_sections = VecDeque::new();
+            for _ in 0..r.get_count(16)? {
+                stream_sections.push_back(Section {
+                    required_insert_count: r.get_u64()?,
+                    min_index: r.get_u64()?,
+                });
+            }
+
+            sections.insert(stream_id, stream_sections);
+        }
+
+        Ok(Encoder {
+            table,
+            max_blocked_streams,
+            known_received_count,
+            sections,
+            pending_section: None,
+            instructions: r.get_bytes()?.to_vec(),
+            decoder_buf: r.get_bytes()?.to_vec(),
+        })
+    }
+
+    /// Sets the maximum capacity of the dynamic table, as advertised by the
+    /// peer's decoder with `SETTINGS_QPACK_MAX_TABLE_CAPACITY`.
+    pub fn set_max_table_capacity(&mut self, v: u64) {
+        self.table.set_max_capacity(v);
+    }
+
+    /// Sets the maximum number of streams that can be blocked on the peer's
+    /// decoder, as advertised with `SETTINGS_QPACK_BLOCKED_STREAMS`.
+    pub fn set_max_blocked_streams(&mut self, v: u64) {
+        self.max_blocked_streams = v;
+    }
+
+    /// Updates the capacity of the dynamic table.
+    ///
+    /// This queues a Set Dynamic Table Capacity instruction that needs to be
+    /// sent on the encoder stream.
+    ///
+    /// The [`InvalidDynamicTableCapacity`] error is returned when the value
+    /// exceeds the maximum capacity, or when reducing the capacity would
+    /// require evicting entries still referenced by unacknowledged field
+    /// sections.
+    ///
+    /// [`InvalidDynamicTableCapacity`]: enum.Error.html#variant.InvalidDynamicTableCapacity
+    pub fn set_table_capacity(&mut self, v: u64) -> Result<()> {
+        if v > self.table.max_capacity() {
+            return Err(Error::InvalidDynamicTableCapacity);
+        }
+
+        if !self.table.can_evict_to(v, self.pinned_index()) {
+            return Err(Error::InvalidDynamicTableCapacity);
+        }
+
+        self.table.set_capacity(v);
+
+        let mut b = InstructionWriter::new(&mut self.instructions);
+        b.put_int(v, SET_DYNAMIC_TABLE_CAPACITY, 5);
+
+        Ok(())
+    }
+
+    /// Returns the current capacity of the dynamic table.
+    pub fn table_capacity(&self) -> u64 {
+        self.table.capacity()
+    }
+
+    /// Returns the number of insertions into the dynamic table.
+    pub fn insert_count(&self) -> u64 {
+        self.table.insert_count()
+    }
+
+    /// Returns the number of insertions acknowledged by the decoder.
+    pub fn known_received_count(&self) -> u64 {
+        self.known_received_count
+    }
+
     /// Encodes a list of headers into a QPACK header block.
+    ///
+    /// Only the static table is used, so the header block can be decoded
+    /// independently of any other.
     pub fn encode<T: NameValue>(
         &mut self, headers: &[T], out: &mut [u8],
     ) -> Result<usize> {
@@ -55,35 +241,415 @@ impl Encoder {
         encode_int(0, 0, 7, &mut b)?;
 
         for h in headers {
-            match lookup_static(h) {
+            let line = match lookup_static(h) {
+                Some((idx, true)) => FieldLine::Static(idx),
+
+                Some((idx, false)) => FieldLine::StaticNameRef(idx, h.value()),
+
+                None => FieldLine::Literal(h.name(), h.value()),
+            };
+
+            encode_field_line(&line, 0, &mut b)?;
+        }
+
+        Ok(b.off())
+    }
+
+    /// Encodes a list of headers sent on the given stream into a QPACK field
+    /// section, using the dynamic table when enabled.
+    ///
+    /// This might queue instructions that need to be sent on the encoder
+    /// stream before the field section. Once the field section has been sent
+    /// on the stream, [`commit_field_section()`] must be called so that the
+    /// decoder's acknowledgement can be tracked.
+    ///
+    /// [`commit_field_section()`]: struct.Encoder.html#method.commit_field_section
+    pub fn encode_field_section<T: NameValue>(
+        &mut self, stream_id: u64, headers: &[T], out: &mut [u8],
+    ) -> Result<usize> {
+        // Discard the previous field section, if it was never committed.
+        self.pending_section = None;
+
+        if self.table.capacity() == 0 {
+            return self.encode(headers, out);
+        }
+
+        // Entries that haven't been acknowledged can only be referenced if
+        // the stream is allowed to block.
+        let reference_limit = if self.can_block(stream_id) {
+            u64::MAX
+        } else {
+            self.known_received_count
+        };
+
+        let mut pinned = self.pinned_index();
+
+        let mut lines = Vec::with_capacity(headers.len());
+
+        let mut required_insert_count = 0;
+        let mut min_index = u64::MAX;
+
+        for h in headers {
+            let line = self.encode_field_line_plan(
+                h.name(),
+                h.value(),
+                reference_limit,
+                &mut pinned,
+            );
+
+            match line {
+                FieldLine::Dynamic(idx) | FieldLine::DynamicNameRef(idx, _) => {
+                    required_insert_count = required_insert_count.max(idx + 1);
+                    min_index = min_index.min(idx);
+                },
+
+                _ => (),
+            }
+
+            lines.push(line);
+        }
+
+        let mut b = octets::OctetsMut::with_slice(out);
+
+        if required_insert_count == 0 {
+            // Required Insert Count.
+            encode_int(0, 0, 8, &mut b)?;
+
+            // Base.
+            encode_int(0, 0, 7, &mut b)?;
+        } else {
+            let full_range = 2 * self.table.max_entries();
+
+            // Required Insert Count.
+            encode_int(required_insert_count % full_range + 1, 0, 8, &mut b)?;
+
+            // Base, using the current insert count so that all references
+            // use relative indexing.
+            let base = self.table.insert_count();
+            encode_int(base - required_insert_count, 0, 7, &mut b)?;
+
+            self.pending_section = Some((stream_id, Section {
+                required_insert_count,
+                min_index,
+            }));
+        }
+
+        let base = self.table.insert_count();
+
+        for line in &lines {
+            encode_field_line(line, base, &mut b)?;
+        }
+
+        Ok(b.off())
+    }
+
+    /// Starts tracking the last field section returned by
+    /// [`encode_field_section()`], after it has been sent on its stream.
+    ///
+    /// [`encode_field_section()`]: struct.Encoder.html#method.encode_field_section
+    pub fn commit_field_section(&mut self) {
+        if let Some((stream_id, section)) = self.pending_section.take() {
+            self.sections
+                .entry(stream_id)
+                .or_default()
+                .push_back(section);
+        }
+    }
+
+    /// Processes instructions received from the decoder on the decoder stream.
+    ///
+    /// Partial instructions are buffered until the rest of the data is
+    /// provided by later calls.
+    pub fn control(&mut self, buf: &[u8]) -> Result<()> {
+        self.decoder_buf.extend_from_slice(buf);
+
+        let mut b = octets::Octets::with_slice(&self.decoder_buf);
+
+        let mut consumed = 0;
+
+        while b.cap() > 0 {
+            let first = b.peek_u8()?;
+
+            let res =
+                if first & SECTION_ACKNOWLEDGEMENT == SECTION_ACKNOWLEDGEMENT {
+                    decode_int(&mut b, 7).map(|v| (SECTION_ACKNOWLEDGEMENT, v))
+                } else if first & STREAM_CANCELLATION == STREAM_CANCELLATION {
+                    decode_int(&mut b, 6).map(|v| (STREAM_CANCELLATION, v))
+                } else {
+                    decode_int(&mut b, 6).map(|v| (INSERT_COUNT_INCREMENT, v))
+                };
+
+            let (instruction, v) = match res {
+                Ok(v) => v,
+
+                // Wait for the rest of the instruction.
+                Err(Error::BufferTooShort) => break,
+
+                Err(_) => return Err(Error::DecoderStreamError),
+            };
+
+            consumed = b.off();
+
+            match instruction {
+                SECTION_ACKNOWLEDGEMENT => {
+                    trace!("Section Acknowledgement stream={v}");
+
+                    let sections = self
+                        .sections
+                        .get_mut(&v)
+                        .ok_or(Error::DecoderStreamError)?;
+
+                    let section =
+                        sections.pop_front().ok_or(Error::DecoderStreamError)?;
+
+                    if sections.is_empty() {
+                        self.sections.remove(&v);
+                    }
+
+                    self.known_received_count = self
+                        .known_received_count
+                        .max(section.required_insert_count);
+                },
+
+                STREAM_CANCELLATION => {
+                    trace!("Stream Cancellation stream={v}");
+
+                    self.sections.remove(&v);
+                },
+
+                _ => {
+                    trace!("Insert Count Increment increment={v}");
+
+                    let count = self
+                        .known_received_count
+                        .checked_add(v)
+                        .ok_or(Error::DecoderStreamError)?;
+
+                    if v == 0 || count > self.table.insert_count() {
+                        return Err(Error::DecoderStreamError);
+                    }
+
+                    self.known_received_count = count;
+                },
+            }
+        }
+
+        self.decoder_buf.drain(..consumed);
+
+        if self.decoder_buf.len() > MAX_DECODER_INSTRUCTION_LEN {
+            return Err(Error::DecoderStreamError);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the pending instructions that need to be sent on the encoder
+    /// stream.
+    pub fn instructions(&self) -> &[u8] {
+        &self.instructions
+    }
+
+    /// Removes the first `len` bytes of pending instructions, after they have
+    /// been sent on the encoder stream.
+    pub fn consume_instructions(&mut self, len: usize) {
+        self.instructions.drain(..len.min(self.instructions.len()));
+    }
+
+    /// Returns whether a field section on the given stream is allowed to
+    /// reference entries that the decoder might not have received yet.
+    fn can_block(&self, stream_id: u64) -> bool {
+        let is_blocking = |sections: &VecDeque<Section>| {
+            sections
+                .iter()
+                .any(|s| s.required_insert_count > self.known_received_count)
+        };
+
+        if self.sections.get(&stream_id).is_some_and(is_blocking) {
+            return true;
+        }
+
+        let blocked = self.sections.values().filter(|s| is_blocking(s)).count();
+
+        (blocked as u64) < self.max_blocked_streams
+    }
+
+    /// Returns the lowest absolute index that can't be evicted, because it's
+    /// referenced by unacknowledged field sections.
+    fn pinned_index(&self) -> u64 {
+        self.sections
+            .values()
+            .flatten()
+            .map(|s| s.min_index)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Decides how to represent a single field line, inserting it into the
+    /// dynamic table if possible.
+    ///
+    /// `pinned` is the lowest absolute index that can't be evicted, and is
+    /// updated with the entries referenced by the field line.
+    fn encode_field_line_plan<'a>(
+        &mut self, name: &'a [u8], value: &'a [u8], reference_limit: u64,
+        pinned: &mut u64,
+    ) -> FieldLine<'a> {
+        let static_match = lookup_static(&(name, value));
+
+        if let Some((idx, true)) = static_match {
+            return FieldLine::Static(idx);
+        }
+
+        let dynamic_match = self.table.find(name, value, reference_limit);
+
+        if let Some((idx, true)) = dynamic_match {
+            *pinned = (*pinned).min(idx);
+            return FieldLine::Dynamic(idx);
+        }
+
+        // Try inserting the field line into the dynamic table, unless the
+        // encoder stream is already backed up.
+        let size = entry_size(name, value);
+
+        if self.instructions.len() as u64 <= self.table.capacity() &&
+            size <= self.table.capacity() / 2 &&
+            self.table.can_insert(size, *pinned) &&
+            !matches!(self.table.find(name, value, u64::MAX), Some((_, true)))
+        {
+            // Prefer referencing names in the static table, as those can't
+            // be evicted.
+            let name_ref = match static_match {
+                Some((idx, _)) => Some((idx, true)),
+
+                None => self
+                    .table
+                    .find(name, value, u64::MAX)
+                    .map(|(idx, _)| (idx, false)),
+            };
+
+            let insert_count = self.table.insert_count();
+
+            let mut b = InstructionWriter::new(&mut self.instructions);
+
+            match name_ref {
                 Some((idx, true)) => {
                     const STATIC: u8 = 0x40;
 
-                    // Encode as statically indexed.
-                    encode_int(idx, INDEXED | STATIC, 6, &mut b)?;
+                    b.put_int(idx, INSERT_WITH_NAME_REF | STATIC, 6);
+                    b.put_str::<false>(value, 0, 7);
                 },
 
                 Some((idx, false)) => {
-                    const STATIC: u8 = 0x10;
-
-                    // Encode value as literal with static name reference.
-                    encode_int(idx, LITERAL_WITH_NAME_REF | STATIC, 4, &mut b)?;
-                    encode_str::<false>(h.value(), 0, 7, &mut b)?;
+                    b.put_int(insert_count - idx - 1, INSERT_WITH_NAME_REF, 6);
+                    b.put_str::<false>(value, 0, 7);
                 },
 
                 None => {
-                    // Encode as fully literal.
-
-                    encode_str::<true>(h.name(), LITERAL, 3, &mut b)?;
-                    encode_str::<false>(h.value(), 0, 7, &mut b)?;
+                    b.put_str::<true>(name, INSERT_WITH_LITERAL_NAME, 5);
+                    b.put_str::<false>(value, 0, 7);
                 },
-            };
+            }
+
+            let idx = self
+                .table
+                .insert(name.to_ascii_lowercase(), value.to_vec())
+                .unwrap_or(insert_count);
+
+            if idx < reference_limit {
+                *pinned = (*pinned).min(idx);
+                return FieldLine::Dynamic(idx);
+            }
         }
 
-        Ok(b.off())
+        match (static_match, dynamic_match) {
+            (Some((idx, _)), _) => FieldLine::StaticNameRef(idx, value),
+
+            (None, Some((idx, _))) if self.table.get(idx).is_some() => {
+                *pinned = (*pinned).min(idx);
+                FieldLine::DynamicNameRef(idx, value)
+            },
+
+            _ => FieldLine::Literal(name, value),
+        }
+    }
+}
+
+/// Helper for writing encoder instructions into a growable buffer.
+struct InstructionWriter<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl<'a> InstructionWriter<'a> {
+    fn new(buf: &'a mut Vec<u8>) -> Self {
+        InstructionWriter { buf }
+    }
+
+    fn put_int(&mut self, v: u64, first: u8, prefix: usize) {
+        let mut d = [0; 10];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        // An integer always fits in 10 bytes.
+        encode_int(v, first, prefix, &mut b).unwrap();
+
+        let off = b.off();
+        self.buf.extend_from_slice(&d[..off]);
+    }
+
+    fn put_str<const LOWER_CASE: bool>(
+        &mut self, v: &[u8], first: u8, prefix: usize,
+    ) {
+        let mut d = vec![0; v.len() + 10];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        // A string always fits in its own length plus the length prefix.
+        encode_str::<LOWER_CASE>(v, first, prefix, &mut b).unwrap();
+
+        let off = b.off();
+        self.buf.extend_from_slice(&d[..off]);
     }
 }
 
+/// Encodes a single field line, with dynamic table references relative to
+/// `base`.
+fn encode_field_line(
+    line: &FieldLine, base: u64, b: &mut octets::OctetsMut,
+) -> Result<()> {
+    match line {
+        FieldLine::Static(idx) => {
+            const STATIC: u8 = 0x40;
+
+            // Encode as statically indexed.
+            encode_int(*idx, INDEXED | STATIC, 6, b)?;
+        },
+
+        FieldLine::StaticNameRef(idx, value) => {
+            const STATIC: u8 = 0x10;
+
+            // Encode value as literal with static name reference.
+            encode_int(*idx, LITERAL_WITH_NAME_REF | STATIC, 4, b)?;
+            encode_str::<false>(value, 0, 7, b)?;
+        },
+
+        FieldLine::Dynamic(idx) => {
+            // Encode as dynamically indexed.
+            encode_int(base - idx - 1, INDEXED, 6, b)?;
+        },
+
+        FieldLine::DynamicNameRef(idx, value) => {
+            // Encode value as literal with dynamic name reference.
+            encode_int(base - idx - 1, LITERAL_WITH_NAME_REF, 4, b)?;
+            encode_str::<false>(value, 0, 7, b)?;
+        },
+
+        FieldLine::Literal(name, value) => {
+            // Encode as fully literal.
+            encode_str::<true>(name, LITERAL, 3, b)?;
+            encode_str::<false>(value, 0, 7, b)?;
+        },
+    }
+
+    Ok(())
+}
+
 fn lookup_static<T: NameValue>(h: &T) -> Option<(u64, bool)> {
     // Fetch the right encoding table for this header length.
     let table_for_len =
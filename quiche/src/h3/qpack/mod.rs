@@ -34,6 +34,16 @@ pub const INDEXED_WITH_POST_BASE: u8 = 0b0001_0000;
 pub const LITERAL: u8 = 0b0010_0000;
 pub const LITERAL_WITH_NAME_REF: u8 = 0b0100_0000;
 
+// Encoder stream instructions.
+const INSERT_WITH_NAME_REF: u8 = 0b1000_0000;
+const INSERT_WITH_LITERAL_NAME: u8 = 0b0100_0000;
+const SET_DYNAMIC_TABLE_CAPACITY: u8 = 0b0010_0000;
+
+// Decoder stream instructions.
+const SECTION_ACKNOWLEDGEMENT: u8 = 0b1000_0000;
+const STREAM_CANCELLATION: u8 = 0b0100_0000;
+const INSERT_COUNT_INCREMENT: u8 = 0b0000_0000;
+
 /// A specialized [`Result`] type for quiche QPACK operations.
 ///
 /// This type is used throughout quiche's QPACK public API for any operation
@@ -59,6 +69,28 @@ pub enum Error {
 
     /// The decoded header list exceeded the size limit.
     HeaderListTooLarge,
+
+    /// The QPACK dynamic table index provided doesn't exist.
+    InvalidDynamicTableIndex,
+
+    /// The QPACK dynamic table capacity provided exceeds the maximum, or
+    /// would require evicting entries that are still referenced.
+    InvalidDynamicTableCapacity,
+
+    /// The QPACK header block references dynamic table entries that haven't
+    /// been received yet. Decoding needs to be retried once more encoder
+    /// instructions have been processed.
+    Blocked,
+
+    /// Decoding the QPACK header block would exceed the limit of blocked
+    /// streams.
+    TooManyBlockedStreams,
+
+    /// An instruction received on the encoder stream is invalid.
+    EncoderStreamError,
+
+    /// An instruction received on the decoder stream is invalid.
+    DecoderStreamError,
 }
 
 impl std::fmt::Display for Error {
@@ -85,6 +117,8 @@ mod tests {
 
     use super::*;
 
+    use super::Error;
+
     #[test]
     fn encode_decode() {
         let mut encoded = [0u8; 240];
@@ -192,11 +226,317 @@ mod tests {
         let headers3 = vec![h3::Header::new(value.as_bytes(), b"hello")];
         assert_eq!(enc.encode(&headers3, &mut encoded), Ok(39));
     }
+
+    fn dynamic_pair(capacity: u64, blocked_streams: u64) -> (Encoder, Decoder) {
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(capacity);
+        enc.set_max_blocked_streams(blocked_streams);
+        enc.set_table_capacity(capacity).unwrap();
+
+        let mut dec = Decoder::new();
+        dec.set_max_table_capacity(capacity);
+        dec.set_max_blocked_streams(blocked_streams);
+
+        (enc, dec)
+    }
+
+    /// Delivers pending encoder instructions to the decoder.
+    fn deliver_encoder_instructions(enc: &mut Encoder, dec: &mut Decoder) {
+        let len = enc.instructions().len();
+        assert_eq!(dec.control(enc.instructions()), Ok(()));
+        enc.consume_instructions(len);
+    }
+
+    /// Delivers pending decoder instructions to the encoder.
+    fn deliver_decoder_instructions(dec: &mut Decoder, enc: &mut Encoder) {
+        let len = dec.instructions().len();
+        assert_eq!(enc.control(dec.instructions()), Ok(()));
+        dec.consume_instructions(len);
+    }
+
+    #[test]
+    fn encode_decode_dynamic() {
+        let mut encoded = [0u8; 240];
+
+        let headers = vec![
+            h3::Header::new(b":method", b"GET"),
+            h3::Header::new(b":authority", b"static.xx.fbcdn.net"),
+            h3::Header::new(b":path", b"/rsrc.php/v3/yn/r/rIPZ9Qkrdd9.png"),
+            h3::Header::new(
+                b"user-agent",
+                b"Mozilla/5.0 (Windows NT 10.0; Win64; x64)",
+            ),
+            h3::Header::new(b"x-custom", b"value"),
+        ];
+
+        let (mut enc, mut dec) = dynamic_pair(4096, 16);
+
+        let first = enc.encode_field_section(0, &headers, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        // All headers except the fully static one were inserted.
+        assert_eq!(enc.insert_count(), 4);
+
+        deliver_encoder_instructions(&mut enc, &mut dec);
+        assert_eq!(dec.insert_count(), 4);
+
+        assert_eq!(
+            dec.decode_field_section(0, &encoded[..first], u64::MAX),
+            Ok(headers.clone())
+        );
+
+        deliver_decoder_instructions(&mut dec, &mut enc);
+        assert_eq!(enc.known_received_count(), 4);
+
+        // The same headers are now fully indexed.
+        let second = enc.encode_field_section(4, &headers, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        assert_eq!(second, 7);
+        assert!(enc.instructions().is_empty());
+
+        assert_eq!(
+            dec.decode_field_section(4, &encoded[..second], u64::MAX),
+            Ok(headers)
+        );
+
+        deliver_decoder_instructions(&mut dec, &mut enc);
+    }
+
+    #[test]
+    fn decode_blocked() {
+        let mut encoded = [0u8; 100];
+
+        let headers = vec![h3::Header::new(b"x-custom", b"value")];
+
+        let (mut enc, mut dec) = dynamic_pair(4096, 1);
+
+        let len = enc.encode_field_section(0, &headers, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        // The header block can't be decoded until the encoder instructions
+        // are received.
+        assert_eq!(
+            dec.decode_field_section(0, &encoded[..len], u64::MAX),
+            Err(Error::Blocked)
+        );
+        assert_eq!(dec.blocked_streams(), 1);
+
+        // Only one stream can be blocked at a time.
+        assert_eq!(
+            dec.decode_field_section(4, &encoded[..len], u64::MAX),
+            Err(Error::TooManyBlockedStreams)
+        );
+
+        deliver_encoder_instructions(&mut enc, &mut dec);
+
+        assert_eq!(
+            dec.decode_field_section(0, &encoded[..len], u64::MAX),
+            Ok(headers)
+        );
+        assert_eq!(dec.blocked_streams(), 0);
+    }
+
+    #[test]
+    fn encode_without_blocking() {
+        let mut encoded = [0u8; 100];
+
+        let headers = vec![h3::Header::new(b"x-custom", b"value")];
+
+        let (mut enc, mut dec) = dynamic_pair(4096, 0);
+
+        // The entry is inserted, but can't be referenced until it has been
+        // acknowledged.
+        let len = enc.encode_field_section(0, &headers, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        assert_eq!(enc.insert_count(), 1);
+
+        assert_eq!(
+            dec.decode_field_section(0, &encoded[..len], u64::MAX),
+            Ok(headers.clone())
+        );
+
+        // Nothing to acknowledge yet.
+        assert!(dec.instructions().is_empty());
+
+        deliver_encoder_instructions(&mut enc, &mut dec);
+        deliver_decoder_instructions(&mut dec, &mut enc);
+
+        assert_eq!(enc.known_received_count(), 1);
+
+        let len = enc.encode_field_section(4, &headers, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        assert_eq!(len, 3);
+
+        assert_eq!(
+            dec.decode_field_section(4, &encoded[..len], u64::MAX),
+            Ok(headers)
+        );
+    }
+
+    #[test]
+    fn required_insert_count_wraps() {
+        let mut encoded = [0u8; 100];
+
+        // Only fits 2 entries, so the encoded Required Insert Count wraps
+        // around every 4 insertions.
+        let (mut enc, mut dec) = dynamic_pair(96, 16);
+
+        for i in 0..20u64 {
+            let value = format!("{i}");
+            let headers = vec![h3::Header::new(b"x", value.as_bytes())];
+
+            let len = enc
+                .encode_field_section(i * 4, &headers, &mut encoded)
+                .unwrap();
+            enc.commit_field_section();
+
+            deliver_encoder_instructions(&mut enc, &mut dec);
+
+            assert_eq!(
+                dec.decode_field_section(i * 4, &encoded[..len], u64::MAX),
+                Ok(headers)
+            );
+
+            deliver_decoder_instructions(&mut dec, &mut enc);
+        }
+
+        assert_eq!(enc.insert_count(), 20);
+        assert_eq!(enc.known_received_count(), 20);
+    }
+
+    #[test]
+    fn unacknowledged_entries_not_evicted() {
+        let mut encoded = [0u8; 100];
+
+        // Only fits 2 entries.
+        let (mut enc, mut dec) = dynamic_pair(96, 16);
+
+        let h1 = vec![h3::Header::new(b"x", b"1")];
+        let h2 = vec![h3::Header::new(b"x", b"2")];
+        let h3 = vec![h3::Header::new(b"x", b"3")];
+
+        enc.encode_field_section(0, &h1, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        enc.encode_field_section(4, &h2, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        // The first entry is still referenced by an unacknowledged field
+        // section, so no new entry can be inserted.
+        let len = enc.encode_field_section(8, &h3, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        assert_eq!(enc.insert_count(), 2);
+
+        deliver_encoder_instructions(&mut enc, &mut dec);
+
+        assert_eq!(
+            dec.decode_field_section(8, &encoded[..len], u64::MAX),
+            Ok(h3.clone())
+        );
+
+        // Cancelling the first stream allows its entry to be evicted.
+        dec.cancel_stream(0);
+        deliver_decoder_instructions(&mut dec, &mut enc);
+
+        enc.encode_field_section(12, &h3, &mut encoded).unwrap();
+        enc.commit_field_section();
+
+        assert_eq!(enc.insert_count(), 3);
+    }
+
+    #[test]
+    fn invalid_table_capacity() {
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(100);
+
+        assert_eq!(
+            enc.set_table_capacity(101),
+            Err(Error::InvalidDynamicTableCapacity)
+        );
+
+        let mut dec = Decoder::new();
+        dec.set_max_table_capacity(100);
+
+        // Set Dynamic Table Capacity with a value of 101.
+        assert_eq!(
+            dec.control(&[SET_DYNAMIC_TABLE_CAPACITY | 0x1f, 70]),
+            Err(Error::EncoderStreamError)
+        );
+    }
+
+    #[test]
+    fn invalid_encoder_instruction() {
+        let (_, mut dec) = dynamic_pair(4096, 16);
+
+        // Insert With Name Reference to a non-existent dynamic table entry.
+        assert_eq!(
+            dec.control(&[INSERT_WITH_NAME_REF, 0x01, b'a']),
+            Err(Error::EncoderStreamError)
+        );
+    }
+
+    #[test]
+    fn partial_encoder_instruction() {
+        let mut encoded = [0u8; 100];
+
+        let headers = vec![h3::Header::new(b"x-custom", b"value")];
+
+        let (mut enc, mut dec) = dynamic_pair(4096, 16);
+
+        let len = enc.encode_field_section(0, &headers, &mut encoded).unwrap();
+
+        // Deliver the instructions one byte at a time.
+        for b in enc.instructions() {
+            assert_eq!(dec.control(&[*b]), Ok(()));
+        }
+
+        assert_eq!(dec.insert_count(), 1);
+
+        assert_eq!(
+            dec.decode_field_section(0, &encoded[..len], u64::MAX),
+            Ok(headers)
+        );
+    }
+
+    #[test]
+    fn invalid_decoder_instruction() {
+        let (mut enc, _) = dynamic_pair(4096, 16);
+
+        // Section Acknowledgement for a stream without field sections.
+        assert_eq!(
+            enc.control(&[SECTION_ACKNOWLEDGEMENT]),
+            Err(Error::DecoderStreamError)
+        );
+
+        let (mut enc, _) = dynamic_pair(4096, 16);
+
+        // Insert Count Increment beyond the number of insertions.
+        assert_eq!(
+            enc.control(&[INSERT_COUNT_INCREMENT | 1]),
+            Err(Error::DecoderStreamError)
+        );
+    }
+
+    #[test]
+    fn invalid_required_insert_count() {
+        let mut dec = Decoder::new();
+
+        // Dynamic table references are not allowed with zero capacity.
+        assert_eq!(
+            dec.decode(&[0x01, 0x00, INDEXED], u64::MAX),
+            Err(Error::InvalidDynamicTableIndex)
+        );
+    }
 }
 
 pub use decoder::Decoder;
 pub use encoder::Encoder;
 
 mod decoder;
+mod dynamic_table;
 mod encoder;
 mod static_table;
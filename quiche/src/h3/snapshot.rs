@@ -0,0 +1,284 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! HTTP/3 connection state snapshots.
+//!
+//! This complements the QUIC connection snapshots, so that an HTTP/3
+//! connection can be handed off along with its QUIC connection. The QPACK
+//! dynamic tables and the partially parsed state of every stream are
+//! captured, so the restored connection carries on reading from the streams
+//! at the same point.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use crate::snapshot;
+
+use super::capsule;
+use super::qpack;
+use super::stream;
+
+use super::Connection;
+use super::ConnectionSettings;
+use super::QpackStreams;
+use super::Result;
+
+/// Marker identifying HTTP/3 connection snapshots.
+const MAGIC: &[u8; 4] = b"QHSS";
+
+fn put_opt_settings(w: &mut snapshot::Writer, v: &Option<Vec<(u64, u64)>>) {
+    w.put_bool(v.is_some());
+
+    let v = v.as_deref().unwrap_or_default();
+
+    w.put_u64(v.len() as u64);
+    for (id, value) in v {
+        w.put_u64(*id);
+        w.put_u64(*value);
+    }
+}
+
+fn get_opt_settings(
+    r: &mut snapshot::Reader,
+) -> crate::Result<Option<Vec<(u64, u64)>>> {
+    let is_some = r.get_bool()?;
+
+    let mut v = Vec::new();
+    for _ in 0..r.get_count(16)? {
+        v.push((r.get_u64()?, r.get_u64()?));
+    }
+
+    Ok(is_some.then_some(v))
+}
+
+impl ConnectionSettings {
+    fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_opt_u64(self.max_field_section_size);
+        w.put_opt_u64(self.qpack_max_table_capacity);
+        w.put_opt_u64(self.qpack_blocked_streams);
+        w.put_opt_u64(self.connect_protocol_enabled);
+        w.put_opt_u64(self.h3_datagram);
+        w.put_opt_u64(self.wt_max_sessions);
+        put_opt_settings(w, &self.additional_settings);
+        put_opt_settings(w, &self.raw);
+    }
+
+    fn from_snapshot(r: &mut snapshot::Reader) -> crate::Result<Self> {
+        Ok(ConnectionSettings {
+            max_field_section_size: r.get_opt_u64()?,
+            qpack_max_table_capacity: r.get_opt_u64()?,
+            qpack_blocked_streams: r.get_opt_u64()?,
+            connect_protocol_enabled: r.get_opt_u64()?,
+            h3_datagram: r.get_opt_u64()?,
+            wt_max_sessions: r.get_opt_u64()?,
+            additional_settings: get_opt_settings(r)?,
+            raw: get_opt_settings(r)?,
+        })
+    }
+}
+
+impl QpackStreams {
+    fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_opt_u64(self.encoder_stream_id);
+        w.put_u64(self.encoder_stream_bytes);
+        w.put_opt_u64(self.decoder_stream_id);
+        w.put_u64(self.decoder_stream_bytes);
+    }
+
+    fn from_snapshot(r: &mut snapshot::Reader) -> crate::Result<Self> {
+        Ok(QpackStreams {
+            encoder_stream_id: r.get_opt_u64()?,
+            encoder_stream_bytes: r.get_u64()?,
+            decoder_stream_id: r.get_opt_u64()?,
+            decoder_stream_bytes: r.get_u64()?,
+        })
+    }
+}
+
+fn put_ids<'a>(
+    w: &mut snapshot::Writer, ids: impl ExactSizeIterator<Item = &'a u64>,
+) {
+    w.put_u64(ids.len() as u64);
+    for id in ids {
+        w.put_u64(*id);
+    }
+}
+
+fn get_ids<T: FromIterator<u64>>(r: &mut snapshot::Reader) -> crate::Result<T> {
+    (0..r.get_count(8)?).map(|_| r.get_u64()).collect()
+}
+
+impl Connection {
+    /// Serializes the connection's state, see [`Connection::export_state()`].
+    pub(crate) fn to_snapshot(&self) -> Vec<u8> {
+        let mut w = snapshot::Writer::with_magic(MAGIC);
+
+        w.put_bool(self.is_server);
+        w.put_u64(self.next_request_stream_id);
+        w.put_u64(self.next_uni_stream_id);
+
+        w.put_u64(self.streams.len() as u64);
+        for stream in self.streams.values() {
+            stream.to_snapshot(&mut w);
+        }
+
+        self.local_settings.to_snapshot(&mut w);
+        self.peer_settings.to_snapshot(&mut w);
+
+        w.put_opt_u64(self.control_stream_id);
+        w.put_opt_u64(self.peer_control_stream_id);
+
+        self.qpack_encoder.to_snapshot(&mut w);
+        self.qpack_decoder.to_snapshot(&mut w);
+        w.put_u64(self.qpack_encoder_max_table_capacity);
+
+        self.local_qpack_streams.to_snapshot(&mut w);
+        self.peer_qpack_streams.to_snapshot(&mut w);
+        put_ids(&mut w, self.qpack_blocked_streams.iter());
+
+        w.put_opt_u64(self.max_push_id);
+        w.put_u64(self.next_push_id);
+        put_ids(&mut w, self.promised_push_ids.iter());
+        put_ids(&mut w, self.cancelled_push_ids.iter());
+
+        w.put_u64(self.push_streams.len() as u64);
+        for (push_id, stream_id) in &self.push_streams {
+            w.put_u64(*push_id);
+            w.put_u64(*stream_id);
+        }
+
+        put_ids(&mut w, self.webtransport_sessions.iter());
+        put_ids(&mut w, self.finished_streams.iter());
+
+        w.put_u64(self.max_capsule_payload_size as u64);
+
+        w.put_u64(self.capsule_decoders.len() as u64);
+        for (stream_id, decoder) in &self.capsule_decoders {
+            w.put_u64(*stream_id);
+            decoder.to_snapshot(&mut w);
+        }
+
+        w.put_bool(self.frames_greased);
+        w.put_opt_u64(self.local_goaway_id);
+        w.put_opt_u64(self.peer_goaway_id);
+
+        w.into_vec()
+    }
+
+    /// Restores a connection from a snapshot, see [`Connection::restore()`].
+    pub(crate) fn from_snapshot(state: &[u8]) -> Result<Connection> {
+        let mut r = snapshot::Reader::with_magic(state, MAGIC)?;
+
+        let is_server = r.get_bool()?;
+        let next_request_stream_id = r.get_u64()?;
+        let next_uni_stream_id = r.get_u64()?;
+
+        let mut streams = crate::stream::StreamIdHashMap::default();
+        for _ in 0..r.get_count(1)? {
+            let stream = stream::Stream::from_snapshot(&mut r)?;
+            streams.insert(stream.id(), stream);
+        }
+
+        let local_settings = ConnectionSettings::from_snapshot(&mut r)?;
+        let peer_settings = ConnectionSettings::from_snapshot(&mut r)?;
+
+        let control_stream_id = r.get_opt_u64()?;
+        let peer_control_stream_id = r.get_opt_u64()?;
+
+        let qpack_encoder = qpack::Encoder::from_snapshot(&mut r)?;
+        let qpack_decoder = qpack::Decoder::from_snapshot(&mut r)?;
+        let qpack_encoder_max_table_capacity = r.get_u64()?;
+
+        let local_qpack_streams = QpackStreams::from_snapshot(&mut r)?;
+        let peer_qpack_streams = QpackStreams::from_snapshot(&mut r)?;
+        let qpack_blocked_streams: VecDeque<u64> = get_ids(&mut r)?;
+
+        let max_push_id = r.get_opt_u64()?;
+        let next_push_id = r.get_u64()?;
+        let promised_push_ids: HashSet<u64> = get_ids(&mut r)?;
+        let cancelled_push_ids: HashSet<u64> = get_ids(&mut r)?;
+
+        let mut push_streams = HashMap::new();
+        for _ in 0..r.get_count(16)? {
+            let push_id = r.get_u64()?;
+            let stream_id = r.get_u64()?;
+
+            push_streams.insert(push_id, stream_id);
+        }
+
+        let webtransport_sessions: HashSet<u64> = get_ids(&mut r)?;
+        let finished_streams: VecDeque<u64> = get_ids(&mut r)?;
+
+        let max_capsule_payload_size = r.get_u64()? as usize;
+
+        let mut capsule_decoders = crate::stream::StreamIdHashMap::default();
+        for _ in 0..r.get_count(17)? {
+            let stream_id = r.get_u64()?;
+            let decoder = capsule::CapsuleDecoder::from_snapshot(
+                &mut r,
+                max_capsule_payload_size,
+            )?;
+
+            capsule_decoders.insert(stream_id, decoder);
+        }
+
+        let frames_greased = r.get_bool()?;
+        let local_goaway_id = r.get_opt_u64()?;
+        let peer_goaway_id = r.get_opt_u64()?;
+
+        r.finish()?;
+
+        Ok(Connection {
+            is_server,
+            next_request_stream_id,
+            next_uni_stream_id,
+            streams,
+            local_settings,
+            peer_settings,
+            control_stream_id,
+            peer_control_stream_id,
+            qpack_encoder,
+            qpack_decoder,
+            qpack_encoder_max_table_capacity,
+            local_qpack_streams,
+            peer_qpack_streams,
+            qpack_blocked_streams,
+            max_push_id,
+            next_push_id,
+            promised_push_ids,
+            cancelled_push_ids,
+            push_streams,
+            webtransport_sessions,
+            finished_streams,
+            capsule_decoders,
+            max_capsule_payload_size,
+            frames_greased,
+            local_goaway_id,
+            peer_goaway_id,
+        })
+    }
+}
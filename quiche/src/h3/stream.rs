@@ -25,6 +25,7 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::range_buf::BufFactory;
+use crate::snapshot;
 
 use super::Error;
 use super::Result;
@@ -35,9 +36,36 @@ pub const HTTP3_CONTROL_STREAM_TYPE_ID: u64 = 0x0;
 pub const HTTP3_PUSH_STREAM_TYPE_ID: u64 = 0x1;
 pub const QPACK_ENCODER_STREAM_TYPE_ID: u64 = 0x2;
 pub const QPACK_DECODER_STREAM_TYPE_ID: u64 = 0x3;
+pub const WEBTRANSPORT_STREAM_TYPE_ID: u64 = 0x54;
 
 const MAX_STATE_BUF_SIZE: usize = (1 << 24) - 1;
 
+/// All stream types, indexed by their position in a snapshot.
+const TYPES: [Type; 7] = [
+    Type::Control,
+    Type::Request,
+    Type::Push,
+    Type::QpackEncoder,
+    Type::QpackDecoder,
+    Type::WebTransport,
+    Type::Unknown,
+];
+
+/// All stream states, indexed by their position in a snapshot.
+const STATES: [State; 11] = [
+    State::StreamType,
+    State::FrameType,
+    State::FramePayloadLen,
+    State::FramePayload,
+    State::Data,
+    State::PushId,
+    State::SessionId,
+    State::QpackInstruction,
+    State::QpackBlocked,
+    State::Drain,
+    State::Finished,
+];
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Type {
     Control,
@@ -45,6 +73,7 @@ pub enum Type {
     Push,
     QpackEncoder,
     QpackDecoder,
+    WebTransport,
     Unknown,
 }
 
@@ -57,7 +86,8 @@ impl Type {
             Type::Push => qlog::events::h3::H3StreamType::Push,
             Type::QpackEncoder => qlog::events::h3::H3StreamType::QpackEncode,
             Type::QpackDecoder => qlog::events::h3::H3StreamType::QpackDecode,
-            Type::Unknown => qlog::events::h3::H3StreamType::Unknown,
+            Type::WebTransport | Type::Unknown =>
+                qlog::events::h3::H3StreamType::Unknown,
         }
     }
 }
@@ -82,9 +112,15 @@ pub enum State {
     /// Reading the push ID.
     PushId,
 
+    /// Reading the WebTransport session ID.
+    SessionId,
+
     /// Reading a QPACK instruction.
     QpackInstruction,
 
+    /// Waiting for QPACK encoder instructions to decode a header block.
+    QpackBlocked,
+
     /// Reading and discarding data.
     Drain,
 
@@ -99,6 +135,7 @@ impl Type {
             HTTP3_PUSH_STREAM_TYPE_ID => Ok(Type::Push),
             QPACK_ENCODER_STREAM_TYPE_ID => Ok(Type::QpackEncoder),
             QPACK_DECODER_STREAM_TYPE_ID => Ok(Type::QpackDecoder),
+            WEBTRANSPORT_STREAM_TYPE_ID => Ok(Type::WebTransport),
 
             _ => Ok(Type::Unknown),
         }
@@ -144,6 +181,12 @@ pub struct Stream {
     /// The type of the frame currently being parsed.
     frame_type: Option<u64>,
 
+    /// The push ID carried by a push stream, once known.
+    push_id: Option<u64>,
+
+    /// The session ID carried by a WebTransport stream, once known.
+    session_id: Option<u64>,
+
     /// Whether the stream was created locally, or by the peer.
     is_local: bool,
 
@@ -162,6 +205,9 @@ pub struct Stream {
     /// The count of HEADERS frames that have been received.
     headers_received_count: usize,
 
+    /// Whether the request was received in early data.
+    early_data: bool,
+
     /// Whether a DATA frame has been received.
     data_received: bool,
 
@@ -170,6 +216,11 @@ pub struct Stream {
 
     /// Whether a trailing HEADER field has been received.
     trailers_received: bool,
+
+    /// The header block (along with its payload length and, for PUSH_PROMISE
+    /// frames, the push ID) waiting on QPACK encoder instructions to be
+    /// decoded.
+    qpack_blocked_header_block: Option<(Vec<u8>, u64, Option<u64>)>,
 }
 
 impl Stream {
@@ -203,6 +254,10 @@ impl Stream {
 
             frame_type: None,
 
+            push_id: None,
+
+            session_id: None,
+
             is_local,
             remote_initialized: false,
             local_initialized: false,
@@ -213,11 +268,132 @@ impl Stream {
 
             headers_received_count: 0,
 
+            early_data: false,
+
             data_received: false,
 
             trailers_sent: false,
             trailers_received: false,
+
+            qpack_blocked_header_block: None,
+        }
+    }
+
+    /// Writes the stream to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_u64(self.id);
+        w.put_bool(self.ty.is_some());
+        w.put_u8(self.ty.map_or(0, |ty| ty as u8));
+        w.put_u8(self.state as u8);
+        w.put_bytes(&self.state_buf);
+        w.put_u64(self.state_len as u64);
+        w.put_u64(self.state_off as u64);
+        w.put_opt_u64(self.frame_type);
+        w.put_opt_u64(self.push_id);
+        w.put_opt_u64(self.session_id);
+        w.put_bool(self.is_local);
+        w.put_bool(self.remote_initialized);
+        w.put_bool(self.local_initialized);
+        w.put_bool(self.data_event_triggered);
+
+        w.put_bool(self.last_priority_update.is_some());
+        w.put_bytes(self.last_priority_update.as_deref().unwrap_or_default());
+
+        w.put_u64(self.headers_received_count as u64);
+        w.put_bool(self.early_data);
+        w.put_bool(self.data_received);
+        w.put_bool(self.trailers_sent);
+        w.put_bool(self.trailers_received);
+
+        w.put_bool(self.qpack_blocked_header_block.is_some());
+        if let Some((block, payload_len, push_id)) =
+            &self.qpack_blocked_header_block
+        {
+            w.put_bytes(block);
+            w.put_u64(*payload_len);
+            w.put_opt_u64(*push_id);
+        }
+    }
+
+    /// Reads a stream written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.Stream.html#method.to_snapshot
+    pub fn from_snapshot(r: &mut snapshot::Reader) -> crate::Result<Stream> {
+        let id = r.get_u64()?;
+
+        let has_ty = r.get_bool()?;
+        let ty = TYPES
+            .get(r.get_u8()? as usize)
+            .copied()
+            .ok_or(crate::Error::InvalidSnapshot)?;
+
+        let state = STATES
+            .get(r.get_u8()? as usize)
+            .copied()
+            .ok_or(crate::Error::InvalidSnapshot)?;
+
+        let state_buf = r.get_bytes()?.to_vec();
+        let state_len = r.get_u64()? as usize;
+        let state_off = r.get_u64()? as usize;
+
+        // Only some states are backed by the state buffer.
+        let buffered =
+            !matches!(state, State::Data | State::QpackBlocked | State::Finished);
+
+        if state_off > state_len || (buffered && state_len > state_buf.len()) {
+            return Err(crate::Error::InvalidSnapshot);
         }
+
+        let frame_type = r.get_opt_u64()?;
+        let push_id = r.get_opt_u64()?;
+        let session_id = r.get_opt_u64()?;
+        let is_local = r.get_bool()?;
+        let remote_initialized = r.get_bool()?;
+        let local_initialized = r.get_bool()?;
+        let data_event_triggered = r.get_bool()?;
+
+        let has_priority_update = r.get_bool()?;
+        let priority_update = r.get_bytes()?;
+
+        let headers_received_count = r.get_u64()? as usize;
+        let early_data = r.get_bool()?;
+        let data_received = r.get_bool()?;
+        let trailers_sent = r.get_bool()?;
+        let trailers_received = r.get_bool()?;
+
+        let qpack_blocked_header_block = if r.get_bool()? {
+            Some((r.get_bytes()?.to_vec(), r.get_u64()?, r.get_opt_u64()?))
+        } else {
+            None
+        };
+
+        Ok(Stream {
+            id,
+            ty: has_ty.then_some(ty),
+            state,
+            state_buf,
+            state_len,
+            state_off,
+            frame_type,
+            push_id,
+            session_id,
+            is_local,
+            remote_initialized,
+            local_initialized,
+            data_event_triggered,
+            last_priority_update: has_priority_update
+                .then(|| priority_update.to_vec()),
+            headers_received_count,
+            early_data,
+            data_received,
+            trailers_sent,
+            trailers_received,
+            qpack_blocked_header_block,
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
     }
 
     pub fn ty(&self) -> Option<Type> {
@@ -239,6 +415,8 @@ impl Stream {
 
             Type::Push => State::PushId,
 
+            Type::WebTransport => State::SessionId,
+
             Type::QpackEncoder | Type::QpackDecoder => {
                 self.remote_initialized = true;
 
@@ -254,16 +432,78 @@ impl Stream {
     }
 
     /// Sets the push ID and transitions to the next state.
-    pub fn set_push_id(&mut self, _id: u64) -> Result<()> {
+    pub fn set_push_id(&mut self, id: u64) -> Result<()> {
         assert_eq!(self.state, State::PushId);
 
-        // TODO: implement push ID.
+        self.push_id = Some(id);
 
         self.state_transition(State::FrameType, 1, true)?;
 
         Ok(())
     }
 
+    /// Returns the push ID of a push stream, if known.
+    pub fn push_id(&self) -> Option<u64> {
+        self.push_id
+    }
+
+    /// Turns a bidirectional stream opened by the peer into a WebTransport
+    /// stream, after its signal value was received in place of the first
+    /// frame type.
+    pub fn set_webtransport(&mut self) -> Result<()> {
+        assert_eq!(self.state, State::FrameType);
+
+        if self.ty != Some(Type::Request) ||
+            self.is_local ||
+            self.remote_initialized
+        {
+            return Err(Error::FrameUnexpected);
+        }
+
+        self.ty = Some(Type::WebTransport);
+
+        self.state_transition(State::SessionId, 1, true)?;
+
+        Ok(())
+    }
+
+    /// Sets the WebTransport session ID and transitions to the next state.
+    pub fn set_session_id(&mut self, id: u64) -> Result<()> {
+        assert_eq!(self.state, State::SessionId);
+
+        // Sessions are established by extended CONNECT requests, so their ID
+        // must be the one of a client-initiated bidirectional stream.
+        if id % 4 != 0 {
+            return Err(Error::IdError);
+        }
+
+        self.session_id = Some(id);
+        self.remote_initialized = true;
+
+        // The rest of the stream carries application data without any
+        // framing, so treat it as a single DATA payload of unbounded length.
+        self.state_transition(State::Data, usize::MAX, false)?;
+
+        Ok(())
+    }
+
+    /// Initializes a WebTransport stream created by the local endpoint.
+    ///
+    /// Data received on it, in the case of bidirectional streams, is not
+    /// prefixed by the session ID, so it can be read right away.
+    pub fn initialize_local_webtransport(&mut self, session_id: u64) {
+        self.ty = Some(Type::WebTransport);
+        self.session_id = Some(session_id);
+        self.local_initialized = true;
+
+        let _ = self.state_transition(State::Data, usize::MAX, false);
+    }
+
+    /// Returns the session ID of a WebTransport stream, if known.
+    pub fn session_id(&self) -> Option<u64> {
+        self.session_id
+    }
+
     /// Sets the frame type and transitions to the next state.
     pub fn set_frame_type(&mut self, ty: u64) -> Result<()> {
         assert_eq!(self.state, State::FrameType);
@@ -364,7 +604,15 @@ impl Stream {
 
             Some(Type::Push) => {
                 match ty {
-                    // Frames that can never be received on request streams.
+                    // Push stream starts uninitialized and only HEADERS is
+                    // accepted.
+                    frame::HEADERS_FRAME_TYPE_ID =>
+                        self.remote_initialized = true,
+
+                    frame::DATA_FRAME_TYPE_ID if !self.remote_initialized =>
+                        return Err(Error::FrameUnexpected),
+
+                    // Frames that can never be received on push streams.
                     frame::CANCEL_PUSH_FRAME_TYPE_ID =>
                         return Err(Error::FrameUnexpected),
 
@@ -527,6 +775,14 @@ impl Stream {
         self.headers_received_count
     }
 
+    pub fn mark_early_data(&mut self) {
+        self.early_data = true;
+    }
+
+    pub fn is_early_data(&self) -> bool {
+        self.early_data
+    }
+
     pub fn mark_trailers_sent(&mut self) {
         self.trailers_sent = true;
     }
@@ -633,6 +889,27 @@ impl Stream {
         Ok((len, fin))
     }
 
+    /// Marks the stream as blocked on QPACK encoder instructions, storing the
+    /// header block until it can be decoded.
+    pub fn set_qpack_blocked(
+        &mut self, header_block: Vec<u8>, payload_len: u64, push_id: Option<u64>,
+    ) {
+        self.qpack_blocked_header_block =
+            Some((header_block, payload_len, push_id));
+
+        let _ = self.state_transition(State::QpackBlocked, 0, false);
+    }
+
+    /// Takes the header block stored when the stream was blocked on QPACK
+    /// encoder instructions, and resumes reading frames.
+    pub fn take_qpack_blocked(&mut self) -> Option<(Vec<u8>, u64, Option<u64>)> {
+        let header_block = self.qpack_blocked_header_block.take()?;
+
+        let _ = self.state_transition(State::FrameType, 1, true);
+
+        Some(header_block)
+    }
+
     /// Marks the stream as finished.
     pub fn finished(&mut self) {
         let _ = self.state_transition(State::Finished, 0, false);
@@ -1160,6 +1437,7 @@ mod tests {
 
         stream.set_push_id(push_id).unwrap();
         assert_eq!(stream.state, State::FrameType);
+        assert_eq!(stream.push_id(), Some(1));
 
         // Parse the HEADERS frame type.
         stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
@@ -1214,6 +1492,92 @@ mod tests {
         assert_eq!(stream.state, State::FrameType);
     }
 
+    #[test]
+    fn webtransport_uni() {
+        let mut d = vec![42; 40];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let payload = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+        let mut stream = open_uni(&mut b, WEBTRANSPORT_STREAM_TYPE_ID).unwrap();
+        b.put_varint(4).unwrap();
+        b.put_bytes(&payload).unwrap();
+
+        let mut cursor = std::io::Cursor::new(d);
+
+        // Parse stream type, which is encoded on 2 bytes.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+        assert_eq!(stream.try_consume_varint(), Err(Error::Done));
+
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let stream_ty = stream.try_consume_varint().unwrap();
+        assert_eq!(stream_ty, WEBTRANSPORT_STREAM_TYPE_ID);
+
+        stream
+            .set_ty(Type::deserialize(stream_ty).unwrap())
+            .unwrap();
+        assert_eq!(stream.state, State::SessionId);
+
+        // Parse session ID.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let session_id = stream.try_consume_varint().unwrap();
+        assert_eq!(session_id, 4);
+
+        stream.set_session_id(session_id).unwrap();
+        assert_eq!(stream.state, State::Data);
+        assert_eq!(stream.session_id(), Some(4));
+
+        // The rest of the stream is unframed data.
+        let mut recv_buf = vec![0; payload.len()];
+        assert_eq!(
+            stream.try_consume_data_for_tests(&mut cursor, &mut recv_buf),
+            Ok(payload.len())
+        );
+        assert_eq!(payload, recv_buf);
+
+        assert_eq!(stream.state, State::Data);
+    }
+
+    #[test]
+    fn webtransport_bidi() {
+        let mut d = vec![42; 40];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        b.put_varint(WEBTRANSPORT_STREAM_FRAME_TYPE_ID).unwrap();
+        b.put_varint(1).unwrap();
+
+        let mut stream = <Stream>::new(0, false);
+
+        let mut cursor = std::io::Cursor::new(d);
+
+        // Parse the signal value, which is encoded on 2 bytes.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+        assert_eq!(stream.try_consume_varint(), Err(Error::Done));
+
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let frame_ty = stream.try_consume_varint().unwrap();
+        assert_eq!(frame_ty, WEBTRANSPORT_STREAM_FRAME_TYPE_ID);
+
+        stream.set_webtransport().unwrap();
+        assert_eq!(stream.ty, Some(Type::WebTransport));
+        assert_eq!(stream.state, State::SessionId);
+
+        // Session IDs must be client-initiated bidirectional stream IDs.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let session_id = stream.try_consume_varint().unwrap();
+        assert_eq!(stream.set_session_id(session_id), Err(Error::IdError));
+
+        // The signal is only allowed in place of the first frame.
+        let mut stream = <Stream>::new(0, false);
+        stream.remote_initialized = true;
+
+        assert_eq!(stream.set_webtransport(), Err(Error::FrameUnexpected));
+    }
+
     #[test]
     fn grease() {
         let mut d = vec![42; 20];
@@ -1258,6 +1622,39 @@ mod tests {
         assert_eq!(stream.set_frame_type(frame_ty), Err(Error::FrameUnexpected));
     }
 
+    #[test]
+    fn push_data_before_headers() {
+        let mut d = vec![42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let data = Frame::Data {
+            payload: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        };
+
+        let mut stream = open_uni(&mut b, HTTP3_PUSH_STREAM_TYPE_ID).unwrap();
+        b.put_varint(1).unwrap();
+        data.to_bytes(&mut b).unwrap();
+
+        let mut cursor = std::io::Cursor::new(d);
+
+        parse_uni(&mut stream, HTTP3_PUSH_STREAM_TYPE_ID, &mut cursor).unwrap();
+        assert_eq!(stream.state, State::PushId);
+
+        // Parse push ID.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let push_id = stream.try_consume_varint().unwrap();
+        stream.set_push_id(push_id).unwrap();
+
+        // Parse the DATA frame type.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let frame_ty = stream.try_consume_varint().unwrap();
+        assert_eq!(frame_ty, DATA_FRAME_TYPE_ID);
+
+        assert_eq!(stream.set_frame_type(frame_ty), Err(Error::FrameUnexpected));
+    }
+
     #[test]
     fn additional_headers() {
         let mut stream = Stream::new(0, false);
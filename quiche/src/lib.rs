@@ -122,7 +122,7 @@
 //! loop {
 //!     let (read, from) = socket.recv_from(&mut buf).unwrap();
 //!
-//!     let recv_info = quiche::RecvInfo { from, to };
+//!     let recv_info = quiche::RecvInfo::new(from, to);
 //!
 //!     let read = match conn.recv(&mut buf[..read], recv_info) {
 //!         Ok(v) => v,
@@ -349,10 +349,16 @@
 //! config.set_cc_algorithm_name("reno").unwrap();
 //! ```
 //!
+//! Applications can also provide their own algorithm by implementing the
+//! [`CongestionController`] trait and registering it with
+//! [`set_cc_algorithm_custom()`].
+//!
 //! Note that the CC algorithm should be configured before calling [`connect()`]
 //! or [`accept()`]. Otherwise the connection will use a default CC algorithm.
 //!
 //! [`CongestionControlAlgorithm`]: enum.CongestionControlAlgorithm.html
+//! [`CongestionController`]: trait.CongestionController.html
+//! [`set_cc_algorithm_custom()`]: struct.Config.html#method.set_cc_algorithm_custom
 //!
 //! ## Feature flags
 //!
@@ -388,6 +394,8 @@ use std::cmp;
 use std::collections::VecDeque;
 
 use std::net::SocketAddr;
+use std::net::SocketAddrV4;
+use std::net::SocketAddrV6;
 
 use std::str::FromStr;
 
@@ -432,8 +440,13 @@ use crate::stream::StreamPriorityKey;
 /// The current QUIC wire version.
 pub const PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_V1;
 
-/// Supported QUIC versions.
-const PROTOCOL_VERSION_V1: u32 = 0x0000_0001;
+/// The QUIC version 1 wire version.
+pub const PROTOCOL_VERSION_V1: u32 = 0x0000_0001;
+
+/// The QUIC version 2 wire version, as defined in [RFC 9369].
+///
+/// [RFC 9369]: https://www.rfc-editor.org/rfc/rfc9369.html
+pub const PROTOCOL_VERSION_V2: u32 = 0x6b33_43cf;
 
 /// The maximum length of a connection ID.
 pub const MAX_CONN_ID_LEN: usize = packet::MAX_CID_LEN as usize;
@@ -511,6 +524,35 @@ pub struct RecvInfo {
 
     /// The local address the packet was received on.
     pub to: SocketAddr,
+
+    /// The ECN codepoint of the IP packet carrying the QUIC packet.
+    ///
+    /// Applications that don't have access to the IP header should use
+    /// [`Ecn::NotEct`].
+    pub ecn: Ecn,
+}
+
+impl RecvInfo {
+    /// Creates ancillary information for a packet received from `from` on the
+    /// local address `to`.
+    ///
+    /// The ECN codepoint is set to [`Ecn::NotEct`], use [`with_ecn()`] to set
+    /// the one of the IP packet instead.
+    ///
+    /// [`with_ecn()`]: struct.RecvInfo.html#method.with_ecn
+    pub fn new(from: SocketAddr, to: SocketAddr) -> Self {
+        RecvInfo {
+            from,
+            to,
+            ecn: Ecn::NotEct,
+        }
+    }
+
+    /// Sets the ECN codepoint of the IP packet carrying the QUIC packet.
+    pub fn with_ecn(mut self, ecn: Ecn) -> Self {
+        self.ecn = ecn;
+        self
+    }
 }
 
 /// Ancillary information about outgoing packets.
@@ -528,6 +570,11 @@ pub struct SendInfo {
     ///
     /// [Pacing]: index.html#pacing
     pub at: Instant,
+
+    /// The ECN codepoint to set on the IP packet.
+    ///
+    /// See [`Config::enable_ecn()`] for more details.
+    pub ecn: Ecn,
 }
 
 /// The side of the stream to be shut down.
@@ -573,6 +620,7 @@ pub struct Config {
     grease: bool,
 
     cc_algorithm: CongestionControlAlgorithm,
+    custom_cc: Option<recovery::CustomCongestionControl>,
     custom_bbr_params: Option<BbrParams>,
     initial_congestion_window_packets: usize,
     enable_relaxed_loss_threshold: bool,
@@ -580,6 +628,8 @@ pub struct Config {
     pmtud: bool,
     pmtud_max_probes: u8,
 
+    ecn: bool,
+
     hystart: bool,
 
     pacing: bool,
@@ -600,11 +650,17 @@ pub struct Config {
 
     max_amplification_factor: usize,
 
+    max_packets_per_key: u64,
+
     disable_dcid_reuse: bool,
 
     track_unknown_transport_params: Option<usize>,
 
     initial_rtt: Duration,
+
+    anti_replay: Option<Arc<dyn AntiReplay>>,
+
+    clock: Arc<dyn Clock>,
 }
 
 // See https://quicwg.org/base-drafts/rfc9000.html#section-15
@@ -652,12 +708,14 @@ impl Config {
             application_protos: Vec::new(),
             grease: true,
             cc_algorithm: CongestionControlAlgorithm::CUBIC,
+            custom_cc: None,
             custom_bbr_params: None,
             initial_congestion_window_packets:
                 DEFAULT_INITIAL_CONGESTION_WINDOW_PACKETS,
             enable_relaxed_loss_threshold: false,
             pmtud: false,
             pmtud_max_probes: pmtud::MAX_PROBES_DEFAULT,
+            ecn: false,
             hystart: true,
             pacing: true,
             max_pacing_rate: None,
@@ -676,11 +734,14 @@ impl Config {
             max_stream_window: stream::MAX_STREAM_WINDOW,
 
             max_amplification_factor: MAX_AMPLIFICATION_FACTOR,
+            max_packets_per_key: u64::MAX,
 
             disable_dcid_reuse: false,
 
             track_unknown_transport_params: None,
             initial_rtt: DEFAULT_INITIAL_RTT,
+            anti_replay: None,
+            clock: Arc::new(SystemClock),
         })
     }
 
@@ -784,6 +845,26 @@ impl Config {
         self.pmtud_max_probes = max_probes;
     }
 
+    /// Configures whether to mark outgoing packets as ECN-capable.
+    ///
+    /// When enabled, the ECN codepoint that the application needs to set on
+    /// outgoing packets is returned in [`SendInfo`]. Marking stops on a path
+    /// if the peer fails to correctly report the marked packets back, and
+    /// Congestion Experienced marks reported by the peer are handled by the
+    /// congestion controller.
+    ///
+    /// Packets are marked with ECT(0), unless the congestion control algorithm
+    /// is [`CongestionControlAlgorithm::Prague`], in which case they are marked
+    /// with ECT(1) so that they are handled by L4S queues on the path.
+    ///
+    /// Regardless of this setting, the ECN codepoints provided in
+    /// [`RecvInfo`] are reported back to the peer.
+    ///
+    /// The default value is `false`.
+    pub fn enable_ecn(&mut self, v: bool) {
+        self.ecn = v;
+    }
+
     /// Configures whether to send GREASE values.
     ///
     /// The default value is `true`.
@@ -818,10 +899,42 @@ impl Config {
     }
 
     /// Enables sending or receiving early data.
+    ///
+    /// Servers should also configure an anti-replay store with
+    /// [`set_anti_replay()`], as early data can otherwise be replayed by an
+    /// attacker.
+    ///
+    /// [`set_anti_replay()`]: struct.Config.html#method.set_anti_replay
     pub fn enable_early_data(&mut self) {
         self.tls_ctx.set_early_data_enabled(true);
     }
 
+    /// Configures the store used by servers to detect replayed early data.
+    ///
+    /// ClientHellos offering early data are checked against the store before
+    /// being processed, and early data is rejected for replayed ones. The
+    /// same store is shared by all connections created with this config.
+    ///
+    /// The default is that no anti-replay protection is performed.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// config.enable_early_data();
+    /// config.set_anti_replay(Arc::new(quiche::BloomAntiReplay::new(
+    ///     Duration::from_secs(60 * 60),
+    ///     100_000,
+    /// )));
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_anti_replay(&mut self, anti_replay: Arc<dyn AntiReplay>) {
+        self.anti_replay = Some(anti_replay);
+    }
+
     /// Configures the list of supported application protocols.
     ///
     /// On the client this configures the list of protocols to send to the
@@ -878,6 +991,52 @@ impl Config {
         self.set_application_protos(&protos_list)
     }
 
+    /// Configures the list of QUIC versions supported for compatible version
+    /// negotiation, in order of preference.
+    ///
+    /// When set, the list is advertised to the peer using the
+    /// `version_information` transport parameter defined in [RFC 9368]. A
+    /// server will then switch a connection to the first version in the list
+    /// that is also supported by the client and compatible with the version
+    /// the client started the connection with, without requiring an extra
+    /// round trip. A client will accept switching to any of the listed
+    /// versions.
+    ///
+    /// All versions in the list must be supported, see
+    /// [`version_is_supported()`].
+    ///
+    /// The default is an empty list, in which case compatible version
+    /// negotiation is disabled.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION_V1)?;
+    /// config.set_available_versions(&[
+    ///     quiche::PROTOCOL_VERSION_V2,
+    ///     quiche::PROTOCOL_VERSION_V1,
+    /// ])?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [RFC 9368]: https://www.rfc-editor.org/rfc/rfc9368.html
+    pub fn set_available_versions(&mut self, versions: &[u32]) -> Result<()> {
+        if versions.iter().any(|&v| !version_is_supported(v)) {
+            return Err(Error::UnknownVersion);
+        }
+
+        self.local_transport_params.version_information = if versions.is_empty() {
+            None
+        } else {
+            Some(VersionInformation {
+                chosen_version: self.version,
+                available_versions: versions.to_vec(),
+            })
+        };
+
+        Ok(())
+    }
+
     /// Sets the anti-amplification limit factor.
     ///
     /// The default value is `3`.
@@ -885,6 +1044,19 @@ impl Config {
         self.max_amplification_factor = v;
     }
 
+    /// Sets the maximum number of packets protected with the same 1-RTT keys.
+    ///
+    /// A key update is automatically initiated once this many packets have
+    /// been sent since the previous key update. Regardless of this setting,
+    /// key updates are initiated before the confidentiality limit of the
+    /// negotiated AEAD algorithm is reached.
+    ///
+    /// The default value is `u64::MAX`, meaning that only the AEAD limit is
+    /// enforced.
+    pub fn set_max_packets_per_key(&mut self, v: u64) {
+        self.max_packets_per_key = v;
+    }
+
     /// Sets the send capacity factor.
     ///
     /// The default value is `1`.
@@ -899,6 +1071,21 @@ impl Config {
         self.initial_rtt = v;
     }
 
+    /// Sets the clock connections read the current time from.
+    ///
+    /// All of the connection's timers, RTT samples, pacing decisions and qlog
+    /// timestamps are based on this clock, so deadlines passed to the
+    /// connection by the application need to be based on it too. See the
+    /// [`ManualClock`] for controlling time in tests and simulations.
+    ///
+    /// The default clock is the [`SystemClock`].
+    ///
+    /// [`ManualClock`]: struct.ManualClock.html
+    /// [`SystemClock`]: struct.SystemClock.html
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
     /// Sets the `max_idle_timeout` transport parameter, in milliseconds.
     ///
     /// The default value is infinite, that is, no timeout is used.
@@ -1052,6 +1239,50 @@ impl Config {
             cmp::min(v, octets::MAX_VAR_INT);
     }
 
+    /// Sets the `min_ack_delay` transport parameter, in microseconds, which
+    /// enables the [ACK frequency extension].
+    ///
+    /// When both endpoints enable the extension, the peer can ask for ACKs to
+    /// be delayed with ACK_FREQUENCY frames, and the congestion controller can
+    /// ask the same of the peer. The value must not exceed `max_ack_delay`.
+    ///
+    /// The extension is disabled by default.
+    ///
+    /// [ACK frequency extension]: https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency
+    pub fn set_min_ack_delay(&mut self, v: u64) {
+        self.local_transport_params.min_ack_delay =
+            Some(cmp::min(v, (1 << 24) - 1));
+    }
+
+    /// Sets the `initial_max_path_id` transport parameter, which enables the
+    /// [multipath extension].
+    ///
+    /// When both endpoints enable the extension, application data can be sent
+    /// on several validated paths at the same time. Path identifiers up to
+    /// the given value can be used by the peer to open new paths.
+    ///
+    /// The extension is disabled by default.
+    ///
+    /// [multipath extension]: https://datatracker.ietf.org/doc/html/draft-ietf-quic-multipath
+    pub fn set_initial_max_path_id(&mut self, v: u64) {
+        self.local_transport_params.initial_max_path_id =
+            Some(cmp::min(v, multipath::MAX_PATH_ID));
+    }
+
+    /// Configures whether to enable the [reliable stream reset extension].
+    ///
+    /// When both endpoints enable the extension, streams can be reset with
+    /// [`stream_reset_at()`] while still delivering data up to a given offset
+    /// to the peer.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [reliable stream reset extension]: https://datatracker.ietf.org/doc/html/draft-ietf-quic-reliable-stream-reset
+    /// [`stream_reset_at()`]: struct.Connection.html#method.stream_reset_at
+    pub fn enable_reset_stream_at(&mut self, v: bool) {
+        self.local_transport_params.reset_stream_at = v;
+    }
+
     /// Sets the `active_connection_id_limit` transport parameter.
     ///
     /// The default value is `2`. Lower values will be ignored.
@@ -1074,6 +1305,55 @@ impl Config {
     /// The default value is `CongestionControlAlgorithm::CUBIC`.
     pub fn set_cc_algorithm(&mut self, algo: CongestionControlAlgorithm) {
         self.cc_algorithm = algo;
+        self.custom_cc = None;
+    }
+
+    /// Sets a custom congestion control algorithm.
+    ///
+    /// The `factory` closure is called to create a new [`CongestionController`]
+    /// for every path of every connection created with this configuration.
+    /// It takes precedence over the algorithm set with [`set_cc_algorithm()`]
+    /// until that is called again.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # use std::time::Instant;
+    /// # use quiche::{AckedPacket, CongestionController, LostPacket, RttEstimate};
+    /// #[derive(Debug)]
+    /// struct FixedWindow(usize);
+    ///
+    /// impl CongestionController for FixedWindow {
+    ///     fn on_packet_sent(&mut self, _: Instant, _: u64, _: usize, _: usize) {}
+    ///
+    ///     fn on_packets_acked(
+    ///         &mut self, _: Instant, _: &[AckedPacket], _: usize, _: &RttEstimate,
+    ///     ) {
+    ///     }
+    ///
+    ///     fn on_packets_lost(&mut self, _: Instant, _: &[LostPacket], _: usize) {}
+    ///
+    ///     fn congestion_window(&self) -> usize {
+    ///         self.0
+    ///     }
+    /// }
+    ///
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cc_algorithm_custom(|params| {
+    ///     Box::new(FixedWindow(20 * params.max_datagram_size))
+    /// });
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`set_cc_algorithm()`]: struct.Config.html#method.set_cc_algorithm
+    pub fn set_cc_algorithm_custom<F>(&mut self, factory: F)
+    where
+        F: Fn(&CongestionControllerParams) -> Box<dyn CongestionController>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.custom_cc = Some(recovery::CustomCongestionControl::new(factory));
     }
 
     /// Sets custom BBR settings.
@@ -1081,7 +1361,8 @@ impl Config {
     /// This API is experimental and will be removed in the future.
     ///
     /// Currently this only applies if cc_algorithm is
-    /// `CongestionControlAlgorithm::Bbr2Gcongestion` is set.
+    /// `CongestionControlAlgorithm::Bbr2Gcongestion` or
+    /// `CongestionControlAlgorithm::Bbr3Gcongestion`.
     ///
     /// The default value is `None`.
     #[cfg(feature = "internal")]
@@ -1104,6 +1385,7 @@ impl Config {
     /// ```
     pub fn set_cc_algorithm_name(&mut self, name: &str) -> Result<()> {
         self.cc_algorithm = CongestionControlAlgorithm::from_str(name)?;
+        self.custom_cc = None;
 
         Ok(())
     }
@@ -1242,6 +1524,15 @@ where
     /// QUIC wire version used for the connection.
     version: u32,
 
+    /// QUIC wire version the connection was started with. This differs from
+    /// `version` only when compatible version negotiation was performed.
+    original_version: u32,
+
+    /// The key used by the server to open Initial packets still using the
+    /// original version, after switching to a compatible version. It is kept
+    /// until the client is known to have switched as well.
+    original_initial_open: Option<crypto::Open>,
+
     /// Connection Identifiers.
     ids: cid::ConnectionIdentifiers,
 
@@ -1317,6 +1608,18 @@ where
     /// Total number of received DATAGRAM frames.
     dgram_recv_count: usize,
 
+    /// Total number of outgoing DATAGRAM frames dropped because their
+    /// expiry passed before they could be sent.
+    dgram_expired_count: usize,
+
+    /// Total number of outgoing DATAGRAM frames dropped because they could
+    /// never fit in a packet.
+    dgram_send_dropped_count: usize,
+
+    /// Total number of received DATAGRAM frames dropped because the receive
+    /// queue was full.
+    dgram_recv_dropped_count: usize,
+
     /// Total number of bytes received from the peer.
     rx_data: u64,
 
@@ -1422,6 +1725,13 @@ where
     /// Whether the peer's transport parameters were parsed.
     parsed_peer_transport_params: bool,
 
+    /// The server's preferred address that the client should migrate to once
+    /// the handshake is confirmed.
+    pending_preferred_address: Option<SocketAddr>,
+
+    /// The path being validated towards the server's preferred address.
+    preferred_address_path_id: Option<usize>,
+
     /// Whether the connection handshake has been completed.
     handshake_completed: bool,
 
@@ -1431,12 +1741,45 @@ where
     /// Whether the HANDSHAKE_DONE frame has been acked.
     handshake_done_acked: bool,
 
+    /// ACK frequency state.
+    ack_freq: ack_freq::AckFrequency,
+
+    /// Multipath state.
+    multipath: multipath::Multipath,
+
     /// Whether the connection handshake has been confirmed.
     handshake_confirmed: bool,
 
+    /// Whether the connection was restored from a snapshot, in which case no
+    /// TLS state is available.
+    restored: bool,
+
+    /// Store used to detect replayed early data.
+    anti_replay: Option<Arc<dyn AntiReplay>>,
+
+    /// The ClientHello being buffered until it can be checked against the
+    /// anti-replay store and used for compatible version negotiation.
+    client_hello: Option<Vec<u8>>,
+
+    /// The source of the current time.
+    clock: Arc<dyn Clock>,
+
     /// Key phase bit used for outgoing protected packets.
     key_phase: bool,
 
+    /// The number of packets protected with the current 1-RTT keys.
+    key_phase_sent_count: u64,
+
+    /// The first packet number protected with the current 1-RTT keys, until a
+    /// packet protected with them is acknowledged.
+    key_update_first_pn: Option<u64>,
+
+    /// The number of 1-RTT key updates.
+    key_update_count: u64,
+
+    /// The number of received 1-RTT packets that failed authentication.
+    auth_fail_count: u64,
+
     /// Whether an ack-eliciting packet has been sent since last receiving a
     /// packet.
     ack_eliciting_sent: bool,
@@ -1460,6 +1803,12 @@ where
     dgram_recv_queue: dgram::DatagramQueue,
     dgram_send_queue: dgram::DatagramQueue,
 
+    /// Events about the fate of tracked outgoing DATAGRAM frames.
+    dgram_events: VecDeque<DatagramEvent>,
+
+    /// The ID to assign to the next tracked outgoing DATAGRAM frame.
+    next_dgram_id: u64,
+
     /// Whether to emit DATAGRAM frames in the next packet.
     emit_dgram: bool,
 
@@ -1496,6 +1845,9 @@ where
 
     /// The anti-amplification limit factor.
     max_amplification_factor: usize,
+
+    /// The maximum number of packets protected with the same 1-RTT keys.
+    max_packets_per_key: u64,
 }
 
 /// Creates a new server-side connection.
@@ -1682,6 +2034,44 @@ pub fn connect_with_dcid_and_buffer_factory<F: BufFactory>(
     Ok(conn)
 }
 
+/// Restores a connection from the state exported by
+/// [`Connection::export_state()`].
+///
+/// The `config` object is used for the local settings that are not part of
+/// the exported state, such as the congestion control algorithm and the
+/// datagram queue sizes. Addresses, connection IDs and transport parameters
+/// are restored from the exported state.
+///
+/// The [`InvalidSnapshot`] error is returned if the state can't be decoded.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// # let state = vec![];
+/// let conn = quiche::restore(&state, &mut config)?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
+///
+/// [`Connection::export_state()`]: struct.Connection.html#method.export_state
+/// [`InvalidSnapshot`]: enum.Error.html#variant.InvalidSnapshot
+#[inline]
+pub fn restore(state: &[u8], config: &mut Config) -> Result<Connection> {
+    restore_with_buf_factory(state, config)
+}
+
+/// Restores a connection from an exported state, with a custom buffer
+/// generation method.
+///
+/// The buffers generated can be anything that can be drereferenced as a byte
+/// slice. See [`restore`] and [`BufFactory`] for more info.
+#[inline]
+pub fn restore_with_buf_factory<F: BufFactory>(
+    state: &[u8], config: &mut Config,
+) -> Result<Connection<F>> {
+    Connection::from_snapshot(state, config, config.clock.now())
+}
+
 /// Writes a version negotiation packet.
 ///
 /// The `scid` and `dcid` parameters are the source connection ID and the
@@ -1782,7 +2172,28 @@ pub fn retry(
 /// Returns true if the given protocol version is supported.
 #[inline]
 pub fn version_is_supported(version: u32) -> bool {
-    matches!(version, PROTOCOL_VERSION_V1)
+    matches!(version, PROTOCOL_VERSION_V1 | PROTOCOL_VERSION_V2)
+}
+
+/// Returns true if a connection started using the `original` version can be
+/// switched to the `negotiated` version using compatible version negotiation.
+fn version_is_compatible(original: u32, negotiated: u32) -> bool {
+    // QUIC version 1 and version 2 are compatible with each other, see
+    // https://www.rfc-editor.org/rfc/rfc9369.html#section-3.
+    version_is_supported(original) && version_is_supported(negotiated)
+}
+
+/// Selects the version to switch a connection started using the `original`
+/// version to, based on the local and peer's lists of available versions.
+///
+/// The local list takes precedence, as only the server performs this
+/// selection.
+pub(crate) fn select_compatible_version(
+    original: u32, local_versions: &[u32], peer_versions: &[u32],
+) -> Option<u32> {
+    local_versions.iter().copied().find(|&v| {
+        peer_versions.contains(&v) && version_is_compatible(original, v)
+    })
 }
 
 /// Pushes a frame to the output packet if there is enough space.
@@ -1838,6 +2249,10 @@ const QLOG_PACKET_TX: EventType =
 const QLOG_DATA_MV: EventType =
     EventType::TransportEventType(TransportEventType::DataMoved);
 
+#[cfg(feature = "qlog")]
+const QLOG_VERSION_INFO: EventType =
+    EventType::TransportEventType(TransportEventType::VersionInformation);
+
 #[cfg(feature = "qlog")]
 const QLOG_METRICS: EventType =
     EventType::RecoveryEventType(RecoveryEventType::MetricsUpdated);
@@ -1953,6 +2368,10 @@ impl<F: BufFactory> Connection<F> {
         let mut conn = Connection {
             version: config.version,
 
+            original_version: config.version,
+
+            original_initial_open: None,
+
             ids,
 
             trace_id: scid_as_hex.join(""),
@@ -2000,6 +2419,9 @@ impl<F: BufFactory> Connection<F> {
             retrans_count: 0,
             dgram_sent_count: 0,
             dgram_recv_count: 0,
+            dgram_expired_count: 0,
+            dgram_send_dropped_count: 0,
+            dgram_recv_dropped_count: 0,
             sent_bytes: 0,
             recv_bytes: 0,
             acked_bytes: 0,
@@ -2066,15 +2488,45 @@ impl<F: BufFactory> Connection<F> {
 
             parsed_peer_transport_params: false,
 
+            pending_preferred_address: None,
+
+            preferred_address_path_id: None,
+
             handshake_completed: false,
 
             handshake_done_sent: false,
             handshake_done_acked: false,
 
+            ack_freq: ack_freq::AckFrequency::default(),
+
+            multipath: multipath::Multipath::default(),
+
             handshake_confirmed: false,
 
+            restored: false,
+
+            anti_replay: config.anti_replay.clone(),
+
+            client_hello: (is_server &&
+                (config.anti_replay.is_some() ||
+                    config
+                        .local_transport_params
+                        .version_information
+                        .is_some()))
+            .then(Vec::new),
+
+            clock: config.clock.clone(),
+
             key_phase: false,
 
+            key_phase_sent_count: 0,
+
+            key_update_first_pn: None,
+
+            key_update_count: 0,
+
+            auth_fail_count: 0,
+
             ack_eliciting_sent: false,
 
             closed: false,
@@ -2096,6 +2548,10 @@ impl<F: BufFactory> Connection<F> {
                 config.dgram_send_max_queue_len,
             ),
 
+            dgram_events: VecDeque::new(),
+
+            next_dgram_id: 0,
+
             emit_dgram: true,
 
             disable_dcid_reuse: config.disable_dcid_reuse,
@@ -2111,6 +2567,8 @@ impl<F: BufFactory> Connection<F> {
             stream_data_blocked_recv_count: 0,
 
             max_amplification_factor: config.max_amplification_factor,
+
+            max_packets_per_key: config.max_packets_per_key,
         };
 
         if let Some(retry_cids) = retry_cids {
@@ -2130,7 +2588,7 @@ impl<F: BufFactory> Connection<F> {
         conn.handshake.init(is_server)?;
 
         conn.handshake
-            .use_legacy_codepoint(config.version != PROTOCOL_VERSION_V1);
+            .use_legacy_codepoint(!version_is_supported(config.version));
 
         conn.encode_transport_params()?;
 
@@ -2250,7 +2708,7 @@ impl<F: BufFactory> Connection<F> {
             Some(title),
             Some(description),
             None,
-            Instant::now(),
+            self.clock.now(),
             trace,
             self.qlog.level,
             writer,
@@ -2321,12 +2779,76 @@ impl<F: BufFactory> Connection<F> {
         self.encode_transport_params()
     }
 
+    /// Advertises a preferred address to the client, using the
+    /// `preferred_address` transport parameter.
+    ///
+    /// Once the handshake is confirmed, the client validates the path towards
+    /// the preferred address matching the address family it uses, and then
+    /// migrates the connection to it. The client uses the provided `scid` and
+    /// `reset_token` when sending packets to the preferred address, so the
+    /// application needs to route packets carrying `scid` that are received
+    /// on the preferred address to this connection.
+    ///
+    /// Note that migration requires the client to have spare connection IDs,
+    /// so it only happens if the client provides additional ones using
+    /// [`new_scid()`].
+    ///
+    /// This must only be called by servers, immediately after creating a
+    /// connection, that is, before any packet is sent or received. Otherwise,
+    /// or if the server uses zero-length Source Connection IDs, or no address
+    /// is provided, this returns [`InvalidState`].
+    ///
+    /// [`new_scid()`]: Self::new_scid
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_preferred_address(
+        &mut self, addr_v4: Option<SocketAddrV4>, addr_v6: Option<SocketAddrV6>,
+        scid: &ConnectionId, reset_token: u128,
+    ) -> Result<()> {
+        if !self.is_server ||
+            self.recv_count > 0 ||
+            self.ids.zero_length_scid() ||
+            (addr_v4.is_none() && addr_v6.is_none())
+        {
+            return Err(Error::InvalidState);
+        }
+
+        // The connection ID carried in the preferred_address transport
+        // parameter has sequence number 1.
+        if self.ids.active_source_cids() != 1 {
+            return Err(Error::InvalidState);
+        }
+
+        // The connection ID is provided to the client as part of the transport
+        // parameter, so there is no need to advertise it in a
+        // NEW_CONNECTION_ID frame.
+        self.ids.new_scid(
+            scid.to_vec().into(),
+            Some(reset_token),
+            false,
+            None,
+            false,
+        )?;
+
+        self.local_transport_params.preferred_address = Some(PreferredAddress {
+            addr_v4,
+            addr_v6,
+            connection_id: scid.to_vec().into(),
+            stateless_reset_token: reset_token,
+        });
+
+        self.encode_transport_params()
+    }
+
     /// Sets the congestion control algorithm used.
     ///
     /// This function can only be called inside one of BoringSSL's handshake
     /// callbacks, before any packet has been sent. Calling this function any
     /// other time will have no effect.
     ///
+    /// This allows selecting the algorithm for each connection, for example
+    /// [`CongestionControlAlgorithm::Ledbat`] for connections carrying
+    /// background transfers, based on the SNI or ALPN offered by the client.
+    ///
     /// See [`Config::set_cc_algorithm()`].
     ///
     /// [`Config::set_cc_algorithm()`]: struct.Config.html#method.set_cc_algorithm
@@ -2338,6 +2860,7 @@ impl<F: BufFactory> Connection<F> {
         let ex_data = tls::ExData::from_ssl_ref(ssl).ok_or(Error::TlsFail)?;
 
         ex_data.recovery_config.cc_algorithm = algo;
+        ex_data.recovery_config.custom_cc = None;
 
         Ok(())
     }
@@ -2347,7 +2870,8 @@ impl<F: BufFactory> Connection<F> {
     /// This API is experimental and will be removed in the future.
     ///
     /// Currently this only applies if cc_algorithm is
-    /// `CongestionControlAlgorithm::Bbr2Gcongestion` is set.
+    /// `CongestionControlAlgorithm::Bbr2Gcongestion` or
+    /// `CongestionControlAlgorithm::Bbr3Gcongestion`.
     ///
     /// This function can only be called inside one of BoringSSL's handshake
     /// callbacks, before any packet has been sent. Calling this function any
@@ -2656,10 +3180,7 @@ impl<F: BufFactory> Connection<F> {
     /// loop {
     ///     let (read, from) = socket.recv_from(&mut buf).unwrap();
     ///
-    ///     let recv_info = quiche::RecvInfo {
-    ///         from,
-    ///         to: local,
-    ///     };
+    ///     let recv_info = quiche::RecvInfo::new(from, local);
     ///
     ///     let read = match conn.recv(&mut buf[..read], recv_info) {
     ///         Ok(v) => v,
@@ -2673,6 +3194,16 @@ impl<F: BufFactory> Connection<F> {
     /// # Ok::<(), quiche::Error>(())
     /// ```
     pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
+        self.recv_at(buf, info, self.clock.now())
+    }
+
+    /// Same as [`recv()`], but processes the packets as if they were received
+    /// at the given time.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    fn recv_at(
+        &mut self, buf: &mut [u8], info: RecvInfo, now: Instant,
+    ) -> Result<usize> {
         let len = buf.len();
 
         if len == 0 {
@@ -2719,6 +3250,7 @@ impl<F: BufFactory> Connection<F> {
                 &mut buf[len - left..len],
                 &info,
                 recv_pid,
+                now,
             ) {
                 Ok(v) => v,
 
@@ -2749,12 +3281,12 @@ impl<F: BufFactory> Connection<F> {
         // Even though the packet was previously "accepted", it
         // should be safe to forward the error, as it also comes
         // from the `recv()` method.
-        self.process_undecrypted_0rtt_packets()?;
+        self.process_undecrypted_0rtt_packets(now)?;
 
         Ok(done)
     }
 
-    fn process_undecrypted_0rtt_packets(&mut self) -> Result<()> {
+    fn process_undecrypted_0rtt_packets(&mut self, now: Instant) -> Result<()> {
         // Process previously undecryptable 0-RTT packets if the decryption key
         // is now available.
         if self.crypto_ctx[packet::Epoch::Application]
@@ -2763,7 +3295,7 @@ impl<F: BufFactory> Connection<F> {
         {
             while let Some((mut pkt, info)) = self.undecryptable_pkts.pop_front()
             {
-                if let Err(e) = self.recv(&mut pkt, info) {
+                if let Err(e) = self.recv_at(&mut pkt, info, now) {
                     self.undecryptable_pkts.clear();
 
                     return Err(e);
@@ -2814,9 +3346,8 @@ impl<F: BufFactory> Connection<F> {
     /// [`Done`]: enum.Error.html#variant.Done
     fn recv_single(
         &mut self, buf: &mut [u8], info: &RecvInfo, recv_pid: Option<usize>,
+        now: Instant,
     ) -> Result<usize> {
-        let now = Instant::now();
-
         if buf.is_empty() {
             return Err(Error::Done);
         }
@@ -2908,8 +3439,11 @@ impl<F: BufFactory> Connection<F> {
                 return Err(Error::UnknownVersion);
             }
 
+            self.original_version = self.version;
             self.did_version_negotiation = true;
 
+            self.set_chosen_version();
+
             // Derive Initial secrets based on the new version.
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
                 &self.destination_id(),
@@ -2927,7 +3461,7 @@ impl<F: BufFactory> Connection<F> {
             self.crypto_ctx[packet::Epoch::Initial].crypto_seal = Some(aead_seal);
 
             self.handshake
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+                .use_legacy_codepoint(!version_is_supported(self.version));
 
             // Encode transport parameters again, as the new version might be
             // using a different format.
@@ -2999,17 +3533,57 @@ impl<F: BufFactory> Connection<F> {
             }
 
             self.version = hdr.version;
+            self.original_version = hdr.version;
             self.did_version_negotiation = true;
 
+            self.set_chosen_version();
+
             self.handshake
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+                .use_legacy_codepoint(!version_is_supported(self.version));
 
             // Encode transport parameters again, as the new version might be
             // using a different format.
             self.encode_transport_params()?;
         }
 
-        if hdr.ty != Type::Short && hdr.version != self.version {
+        // The server might have switched to a different compatible version,
+        // in which case its Initial packets use the negotiated version. The
+        // server might still have acknowledged some of the client's Initial
+        // packets using the original version before switching.
+        if !self.is_server &&
+            hdr.ty == Type::Initial &&
+            self.version == self.original_version &&
+            hdr.version != self.version &&
+            self.compatible_version_offered(hdr.version)
+        {
+            // Initial keys are derived from the destination connection ID of
+            // the client's first Initial packet, or of the one sent after a
+            // Retry, which might have been replaced by the server's already.
+            let dcid = self
+                .rscid
+                .clone()
+                .or_else(|| self.odcid.clone())
+                .unwrap_or_else(|| self.destination_id().into_owned());
+
+            self.switch_to_compatible_version(hdr.version, &dcid)?;
+        }
+
+        // 0-RTT packets keep using the original version even after compatible
+        // version negotiation.
+        let is_original_0rtt =
+            hdr.ty == Type::ZeroRTT && hdr.version == self.original_version;
+
+        // Initial packets sent by the client before it received any packet in
+        // the negotiated version keep using the original version.
+        let is_original_initial = hdr.ty == Type::Initial &&
+            hdr.version == self.original_version &&
+            self.original_initial_open.is_some();
+
+        if hdr.ty != Type::Short &&
+            hdr.version != self.version &&
+            !is_original_0rtt &&
+            !is_original_initial
+        {
             // At this point version negotiation was already performed, so
             // ignore packets that don't match the connection's version.
             return Err(Error::Done);
@@ -3063,6 +3637,8 @@ impl<F: BufFactory> Connection<F> {
         let aead = if hdr.ty == Type::ZeroRTT {
             // Only use 0-RTT key if incoming packet is 0-RTT.
             self.crypto_ctx[epoch].crypto_0rtt_open.as_ref()
+        } else if is_original_initial {
+            self.original_initial_open.as_ref()
         } else {
             // Otherwise use the packet number space's main key.
             self.crypto_ctx[epoch].crypto_open.as_ref()
@@ -3106,8 +3682,25 @@ impl<F: BufFactory> Connection<F> {
             drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
         })?;
 
+        // With multipath, the Destination Connection ID tells which path
+        // identifier, and thus which packet number space, the packet belongs
+        // to.
+        let mp_id = if hdr.ty == Type::Short && self.multipath.enabled() {
+            self.multipath
+                .find_scid(&ConnectionId::from_ref(&hdr.dcid))
+                .map_or(0, |(mp_id, ..)| mp_id)
+        } else {
+            0
+        };
+
+        let largest_rx_pkt_num = if mp_id == 0 {
+            self.pkt_num_spaces[epoch].largest_rx_pkt_num
+        } else {
+            self.multipath.pkt_num_space(mp_id)?.largest_rx_pkt_num
+        };
+
         let pn = packet::decode_pkt_num(
-            self.pkt_num_spaces[epoch].largest_rx_pkt_num,
+            largest_rx_pkt_num,
             hdr.pkt_num,
             hdr.pkt_num_len,
         );
@@ -3134,11 +3727,15 @@ impl<F: BufFactory> Connection<F> {
             hdr.key_phase != self.key_phase
         {
             // Check if this packet arrived before key update.
+            // Packet numbers of other path identifiers can't be compared
+            // with the one the key update happened at, so assume packets
+            // using the previous key phase were sent before the update.
             if let Some(key_update) = self.crypto_ctx[epoch]
                 .key_update
                 .as_ref()
                 .and_then(|key_update| {
-                    (pn < key_update.pn_on_update).then_some(key_update)
+                    (mp_id != 0 || pn < key_update.pn_on_update)
+                        .then_some(key_update)
                 })
             {
                 aead = &key_update.crypto_open;
@@ -3164,18 +3761,54 @@ impl<F: BufFactory> Connection<F> {
             }
         }
 
-        let mut payload = packet::decrypt_pkt(
+        let aead_alg = aead.alg();
+
+        let mut payload = match packet::decrypt_pkt_on_path(
             &mut b,
+            mp_id as u32,
             pn,
             pn_len,
             payload_len,
             aead,
-        )
-        .map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
+        ) {
+            Ok(v) => v,
+
+            Err(e) => {
+                if epoch == packet::Epoch::Application {
+                    self.auth_fail_count += 1;
+
+                    // Stop using the connection once too many forged packets
+                    // were received.
+                    if self.auth_fail_count > aead_alg.integrity_limit() {
+                        return Err(Error::AeadLimitReached);
+                    }
+                }
+
+                return Err(drop_pkt_on_err(
+                    e,
+                    self.recv_count,
+                    self.is_server,
+                    &self.trace_id,
+                ));
+            },
+        };
+
+        // The client switched to the negotiated version, so it won't send any
+        // more Initial packets using the original version.
+        if self.original_initial_open.is_some() &&
+            !is_original_initial &&
+            !is_original_0rtt
+        {
+            self.original_initial_open = None;
+        }
+
+        let recv_pkt_num = if mp_id == 0 {
+            &mut self.pkt_num_spaces[epoch].recv_pkt_num
+        } else {
+            &mut self.multipath.pkt_num_space_mut(mp_id)?.recv_pkt_num
+        };
 
-        if self.pkt_num_spaces[epoch].recv_pkt_num.contains(pn) {
+        if recv_pkt_num.contains(pn) {
             trace!("{} ignored duplicate packet {}", self.trace_id, pn);
             return Err(Error::Done);
         }
@@ -3195,6 +3828,34 @@ impl<F: BufFactory> Connection<F> {
             self.paths.get_active_path_id()?
         };
 
+        // The packet number the key phase changed at, in the packet number
+        // space of path 0.
+        let key_phase_pn = if mp_id == 0 {
+            pn
+        } else {
+            self.pkt_num_spaces[epoch].largest_rx_pkt_num + 1
+        };
+
+        // The peer started using the keys of a locally initiated key update,
+        // so the previous keys are only needed for packets sent before this
+        // one. Packets still using the previous key phase don't tell anything
+        // about that.
+        if hdr.ty == Type::Short &&
+            hdr.key_phase == self.key_phase &&
+            aead_next.is_none()
+        {
+            let pto = self.paths.get(recv_pid)?.recovery.pto();
+
+            if let Some(key_update) = self.crypto_ctx[epoch]
+                .key_update
+                .as_mut()
+                .filter(|key_update| key_update.pn_on_update == u64::MAX)
+            {
+                key_update.pn_on_update = key_phase_pn;
+                key_update.timer = now + (pto * 3);
+            }
+        }
+
         // The key update is verified once a packet is successfully decrypted
         // using the new keys.
         if let Some((open_next, seal_next)) = aead_next {
@@ -3220,12 +3881,15 @@ impl<F: BufFactory> Connection<F> {
 
             self.crypto_ctx[epoch].key_update = Some(packet::KeyUpdate {
                 crypto_open: open_prev,
-                pn_on_update: pn,
+                pn_on_update: key_phase_pn,
                 update_acked: false,
                 timer: now + (recv_path.recovery.pto() * 3),
             });
 
             self.key_phase = !self.key_phase;
+            self.key_phase_sent_count = 0;
+            self.key_update_first_pn = Some(self.next_pkt_num);
+            self.key_update_count += 1;
 
             qlog_with_type!(QLOG_PACKET_RX, self.qlog, q, {
                 let trigger = Some(
@@ -3436,6 +4100,14 @@ impl<F: BufFactory> Connection<F> {
                         }
                     },
 
+                    frame::Frame::PathAck {
+                        path_id, ranges, ..
+                    } =>
+                        if let Some(largest_acked) = ranges.last() {
+                            self.multipath
+                                .on_path_ack_acked(path_id, largest_acked);
+                        },
+
                     frame::Frame::CryptoHeader { offset, length } => {
                         self.crypto_ctx[epoch]
                             .crypto_stream
@@ -3521,8 +4193,13 @@ impl<F: BufFactory> Connection<F> {
                         self.handshake_done_acked = true;
                     },
 
-                    frame::Frame::ResetStream { stream_id, .. } => {
-                        let stream = match self.streams.get_mut(stream_id) {
+                    frame::Frame::DatagramHeader { id: Some(id), .. } => {
+                        self.dgram_events.push_back(DatagramEvent::Acked(id));
+                    },
+
+                    frame::Frame::ResetStream { stream_id, .. } |
+                    frame::Frame::ResetStreamAt { stream_id, .. } => {
+                        let stream = match self.streams.get_mut(stream_id) {
                             Some(v) => v,
 
                             None => continue,
@@ -3575,6 +4252,13 @@ impl<F: BufFactory> Connection<F> {
             .filter(|(_, p)| p.active_dcid_seq.is_none());
 
         for (pid, p) in no_dcid {
+            if p.multipath_id != 0 {
+                p.active_dcid_seq = self
+                    .multipath
+                    .link_lowest_available_dcid(p.multipath_id, pid);
+                continue;
+            }
+
             if self.ids.zero_length_dcid() {
                 p.active_dcid_seq = Some(0);
                 continue;
@@ -3590,34 +4274,56 @@ impl<F: BufFactory> Connection<F> {
             p.active_dcid_seq = Some(dcid_seq);
         }
 
+        // Application packets might not need to be acknowledged right away,
+        // depending on the ACK frequency requested by the peer. Packets of
+        // other multipath path identifiers are always acknowledged.
+        if epoch == packet::Epoch::Application && mp_id == 0 {
+            ack_elicited = self.ack_freq.on_packet_received(
+                pn,
+                self.pkt_num_spaces[epoch].largest_rx_pkt_num,
+                ack_elicited,
+                info.ecn == Ecn::Ce,
+                now,
+            );
+        }
+
+        let pkt_space = if mp_id == 0 {
+            &mut self.pkt_num_spaces[epoch]
+        } else {
+            self.multipath.pkt_num_space_mut(mp_id)?
+        };
+
         // We only record the time of arrival of the largest packet number
         // that still needs to be acked, to be used for ACK delay calculation.
-        if self.pkt_num_spaces[epoch].recv_pkt_need_ack.last() < Some(pn) {
-            self.pkt_num_spaces[epoch].largest_rx_pkt_time = now;
+        if pkt_space.recv_pkt_need_ack.last() < Some(pn) {
+            pkt_space.largest_rx_pkt_time = Some(now);
         }
 
-        self.pkt_num_spaces[epoch].recv_pkt_num.insert(pn);
+        pkt_space.recv_pkt_num.insert(pn);
 
-        self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
+        pkt_space.recv_pkt_need_ack.push_item(pn);
 
-        self.pkt_num_spaces[epoch].ack_elicited =
-            cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
+        pkt_space.recv_ecn_counts.on_packet_received(info.ecn);
 
-        self.pkt_num_spaces[epoch].largest_rx_pkt_num =
-            cmp::max(self.pkt_num_spaces[epoch].largest_rx_pkt_num, pn);
+        pkt_space.ack_elicited = cmp::max(pkt_space.ack_elicited, ack_elicited);
+
+        pkt_space.largest_rx_pkt_num = cmp::max(pkt_space.largest_rx_pkt_num, pn);
 
         if !probing {
-            self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num = cmp::max(
-                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num,
-                pn,
-            );
+            pkt_space.largest_rx_non_probing_pkt_num =
+                cmp::max(pkt_space.largest_rx_non_probing_pkt_num, pn);
+
+            let migrated = pkt_space.largest_rx_non_probing_pkt_num == pn;
 
-            // Did the peer migrated to another path?
+            // Did the peer migrated to another path? With multipath, this
+            // only applies to the path identifier 0.
             let active_path_id = self.paths.get_active_path_id()?;
 
             if self.is_server &&
+                mp_id == 0 &&
                 recv_pid != active_path_id &&
-                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num == pn
+                self.paths.get(recv_pid)?.multipath_id == 0 &&
+                migrated
             {
                 self.on_peer_migrated(recv_pid, self.disable_dcid_reuse, now)?;
             }
@@ -3803,6 +4509,17 @@ impl<F: BufFactory> Connection<F> {
     pub fn send_on_path(
         &mut self, out: &mut [u8], from: Option<SocketAddr>,
         to: Option<SocketAddr>,
+    ) -> Result<(usize, SendInfo)> {
+        self.send_on_path_at(out, from, to, self.clock.now())
+    }
+
+    /// Same as [`send_on_path()`], but writes the packet as if it was sent at
+    /// the given time.
+    ///
+    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
+    fn send_on_path_at(
+        &mut self, out: &mut [u8], from: Option<SocketAddr>,
+        to: Option<SocketAddr>, now: Instant,
     ) -> Result<(usize, SendInfo)> {
         if out.is_empty() {
             return Err(Error::BufferTooShort);
@@ -3812,19 +4529,21 @@ impl<F: BufFactory> Connection<F> {
             return Err(Error::Done);
         }
 
-        let now = Instant::now();
-
         if self.local_error.is_none() {
             self.do_handshake(now)?;
         }
 
+        self.migrate_to_preferred_address(now)?;
+
+        self.expire_stream_deadlines(now);
+
         // Forwarding the error value here could confuse
         // applications, as they may not expect getting a `recv()`
         // error when calling `send()`.
         //
         // We simply fall-through to sending packets, which should
         // take care of terminating the connection as needed.
-        let _ = self.process_undecrypted_0rtt_packets();
+        let _ = self.process_undecrypted_0rtt_packets(now);
 
         // There's no point in trying to send a packet if the Initial secrets
         // have not been derived yet, so return early.
@@ -3874,12 +4593,16 @@ impl<F: BufFactory> Connection<F> {
             left = cmp::min(left, send_path.max_send_bytes);
         }
 
+        // All the coalesced packets share the ECN codepoint of the datagram.
+        let ecn = send_path.ecn.codepoint();
+
         // Generate coalesced packets.
         while left > 0 {
             let (ty, written) = match self.send_single(
                 &mut out[done..done + left],
                 send_pid,
                 has_initial,
+                ecn,
                 now,
             ) {
                 Ok(v) => v,
@@ -3940,13 +4663,15 @@ impl<F: BufFactory> Connection<F> {
             to: send_path.peer_addr(),
 
             at: send_path.recovery.get_packet_send_time(now),
+
+            ecn,
         };
 
         Ok((done, info))
     }
 
     fn send_single(
-        &mut self, out: &mut [u8], send_pid: usize, has_initial: bool,
+        &mut self, out: &mut [u8], send_pid: usize, has_initial: bool, ecn: Ecn,
         now: Instant,
     ) -> Result<(Type, usize)> {
         if out.is_empty() {
@@ -4066,6 +4791,20 @@ impl<F: BufFactory> Connection<F> {
                             .insert_reset(stream_id, error_code, final_size);
                     },
 
+                    frame::Frame::ResetStreamAt {
+                        stream_id,
+                        error_code,
+                        final_size,
+                        reliable_size,
+                    } => {
+                        self.streams.insert_reset_at(
+                            stream_id,
+                            error_code,
+                            final_size,
+                            reliable_size,
+                        );
+                    },
+
                     frame::Frame::StopSending {
                         stream_id,
                         error_code,
@@ -4087,6 +4826,14 @@ impl<F: BufFactory> Connection<F> {
                         self.handshake_done_sent = false;
                     },
 
+                    frame::Frame::AckFrequency { seq_num, .. } => {
+                        self.ack_freq.on_frame_lost(seq_num);
+                    },
+
+                    frame::Frame::DatagramHeader { id: Some(id), .. } => {
+                        self.dgram_events.push_back(DatagramEvent::Lost(id));
+                    },
+
                     frame::Frame::MaxStreamData { stream_id, .. } => {
                         if self.streams.get(stream_id).is_some() {
                             self.streams.insert_almost_full(stream_id);
@@ -4113,6 +4860,8 @@ impl<F: BufFactory> Connection<F> {
                             pmtud.failed_probe(failed_probe);
                         },
 
+                    f if f.is_multipath() => self.multipath.on_frame_lost(&f),
+
                     _ => (),
                 }
             }
@@ -4134,11 +4883,24 @@ impl<F: BufFactory> Connection<F> {
             b.cap()
         };
 
-        if pkt_num_manager.should_skip_pn(self.handshake_completed) {
-            pkt_num_manager.set_skip_pn(Some(self.next_pkt_num));
-            self.next_pkt_num += 1;
+        // Paths with a multipath path identifier other than 0 have their own
+        // packet number space, in which packet numbers are not skipped.
+        let mp_id = path.multipath_id;
+
+        // Multipath paths can carry application data even when they are not
+        // the active path.
+        let path_usable = path.active() || (mp_id != 0 && path.usable());
+
+        let pn = if mp_id == 0 {
+            if pkt_num_manager.should_skip_pn(self.handshake_completed) {
+                pkt_num_manager.set_skip_pn(Some(self.next_pkt_num));
+                self.next_pkt_num += 1;
+            };
+
+            self.next_pkt_num
+        } else {
+            self.multipath.next_pkt_num(mp_id)?
         };
-        let pn = self.next_pkt_num;
 
         let largest_acked_pkt =
             path.recovery.get_largest_acked_on_epoch(epoch).unwrap_or(0);
@@ -4149,10 +4911,18 @@ impl<F: BufFactory> Connection<F> {
 
         let dcid_seq = path.active_dcid_seq.ok_or(Error::OutOfIdentifiers)?;
 
-        let dcid =
-            ConnectionId::from_ref(self.ids.get_dcid(dcid_seq)?.cid.as_ref());
+        let dcid = if mp_id == 0 {
+            self.ids.get_dcid(dcid_seq)?
+        } else {
+            self.multipath.get_dcid(mp_id, dcid_seq)?
+        };
+        let dcid = ConnectionId::from_ref(dcid.cid.as_ref());
 
-        let scid = if let Some(scid_seq) = path.active_scid_seq {
+        // Only short header packets are sent on multipath paths, so their
+        // Source Connection ID is not needed.
+        let scid = if mp_id != 0 {
+            ConnectionId::default()
+        } else if let Some(scid_seq) = path.active_scid_seq {
             ConnectionId::from_ref(self.ids.get_scid(scid_seq)?.cid.as_ref())
         } else if pkt_type == Type::Short {
             ConnectionId::default()
@@ -4160,10 +4930,18 @@ impl<F: BufFactory> Connection<F> {
             return Err(Error::InvalidState);
         };
 
+        // 0-RTT packets are always sent using the original version, as they
+        // might be sent before compatible version negotiation completes.
+        let version = if pkt_type == Type::ZeroRTT {
+            self.original_version
+        } else {
+            self.version
+        };
+
         let hdr = Header {
             ty: pkt_type,
 
-            version: self.version,
+            version,
 
             dcid,
             scid,
@@ -4273,17 +5051,24 @@ impl<F: BufFactory> Connection<F> {
         // generate an ACK (if there's anything to ACK) since we're going to
         // send a packet with PING anyways, even if we haven't received anything
         // ACK eliciting.
+        //
+        // ACKs that are being delayed are also bundled with other frames.
         if pkt_space.recv_pkt_need_ack.len() > 0 &&
-            (pkt_space.ack_elicited || ack_elicit_required) &&
+            (pkt_space.ack_elicited ||
+                ack_elicit_required ||
+                (epoch == packet::Epoch::Application &&
+                    self.ack_freq.ack_pending())) &&
             (!is_closing ||
                 (pkt_type == Type::Handshake &&
                     self.local_error
                         .as_ref()
                         .is_some_and(|le| le.is_app))) &&
-            path.active()
+            path_usable
         {
             #[cfg(not(feature = "fuzzing"))]
-            let ack_delay = pkt_space.largest_rx_pkt_time.elapsed();
+            let ack_delay = pkt_space
+                .largest_rx_pkt_time
+                .map_or(Duration::ZERO, |t| now.saturating_duration_since(t));
 
             #[cfg(not(feature = "fuzzing"))]
             let ack_delay = ack_delay.as_micros() as u64 /
@@ -4294,10 +5079,18 @@ impl<F: BufFactory> Connection<F> {
             #[cfg(feature = "fuzzing")]
             let ack_delay = rand::rand_u8() as u64 + 1;
 
+            // Only report ECN counts once ECN-marked packets were received, as
+            // the application might not have access to the codepoints.
+            let ecn_counts = if pkt_space.recv_ecn_counts.is_empty() {
+                None
+            } else {
+                Some(pkt_space.recv_ecn_counts.clone())
+            };
+
             let frame = frame::Frame::ACK {
                 ack_delay,
                 ranges: pkt_space.recv_pkt_need_ack.clone(),
-                ecn_counts: None, // sending ECN is not supported at this time
+                ecn_counts,
             };
 
             // When a PING frame needs to be sent, avoid sending the ACK if
@@ -4310,6 +5103,26 @@ impl<F: BufFactory> Connection<F> {
                 // available cwnd.
                 if push_frame_to_pkt!(b, frames, frame, left) {
                     pkt_space.ack_elicited = false;
+
+                    if epoch == packet::Epoch::Application {
+                        self.ack_freq.on_ack_sent();
+                    }
+                }
+            }
+        }
+
+        // Create PATH_ACK frames.
+        if pkt_type == Type::Short && !is_closing && path_usable {
+            let ack_delay_exponent =
+                self.local_transport_params.ack_delay_exponent;
+
+            for frame in self.multipath.ack_frames(ack_delay_exponent, now) {
+                if !push_frame_to_pkt!(b, frames, frame, left) {
+                    break;
+                }
+
+                if let Some(frame) = frames.last() {
+                    self.multipath.on_frame_sent(frame);
                 }
             }
         }
@@ -4453,7 +5266,25 @@ impl<F: BufFactory> Connection<F> {
             }
         }
 
-        if pkt_type == Type::Short && !is_closing && path.active() {
+        if pkt_type == Type::Short && !is_closing && path_usable {
+            // Create multipath control frames.
+            let dcid = (mp_id, dcid_seq);
+
+            for frame in self.multipath.frames(dcid) {
+                if !push_frame_to_pkt!(b, frames, frame, left) {
+                    break;
+                }
+
+                if let Some(frame) = frames.last() {
+                    self.multipath.on_frame_sent(frame);
+                }
+
+                ack_eliciting = true;
+                in_flight = true;
+            }
+        }
+
+        if pkt_type == Type::Short && !is_closing && path_usable {
             // Create HANDSHAKE_DONE frame.
             // self.should_send_handshake_done() but without the need to borrow
             if self.handshake_completed &&
@@ -4470,6 +5301,31 @@ impl<F: BufFactory> Connection<F> {
                 }
             }
 
+            // Create ACK_FREQUENCY frame.
+            if self.local_transport_params.min_ack_delay.is_some() {
+                if let Some(peer_min_ack_delay) =
+                    self.peer_transport_params.min_ack_delay
+                {
+                    self.ack_freq.update_request(
+                        path.recovery.ack_eliciting_threshold(),
+                        path.recovery.min_rtt(),
+                        Duration::from_micros(peer_min_ack_delay),
+                        Duration::from_millis(
+                            self.peer_transport_params.max_ack_delay,
+                        ),
+                    );
+                }
+            }
+
+            if let Some(frame) = self.ack_freq.frame() {
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.ack_freq.on_frame_sent();
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
             // Create MAX_STREAMS_BIDI frame.
             if self.streams.should_update_max_streams_bidi() {
                 let frame = frame::Frame::MaxStreamsBidi {
@@ -4597,17 +5453,26 @@ impl<F: BufFactory> Connection<F> {
                 }
             }
 
-            // Create RESET_STREAM frames as needed.
-            for (stream_id, (error_code, final_size)) in self
+            // Create RESET_STREAM and RESET_STREAM_AT frames as needed.
+            for (stream_id, (error_code, final_size, reliable_size)) in self
                 .streams
                 .reset()
                 .map(|(&k, &v)| (k, v))
-                .collect::<Vec<(u64, (u64, u64))>>()
+                .collect::<Vec<(u64, (u64, u64, u64))>>()
             {
-                let frame = frame::Frame::ResetStream {
-                    stream_id,
-                    error_code,
-                    final_size,
+                let frame = if reliable_size > 0 {
+                    frame::Frame::ResetStreamAt {
+                        stream_id,
+                        error_code,
+                        final_size,
+                        reliable_size,
+                    }
+                } else {
+                    frame::Frame::ResetStream {
+                        stream_id,
+                        error_code,
+                        final_size,
+                    }
                 };
 
                 if push_frame_to_pkt!(b, frames, frame, left) {
@@ -4788,10 +5653,16 @@ impl<F: BufFactory> Connection<F> {
         if (pkt_type == Type::Short || pkt_type == Type::ZeroRTT) &&
             left > frame::MAX_DGRAM_OVERHEAD &&
             !is_closing &&
-            path.active() &&
+            path_usable &&
             do_dgram
         {
             if let Some(max_dgram_payload) = max_dgram_len {
+                let expired = self
+                    .dgram_send_queue
+                    .purge_expired(now, &mut self.dgram_events);
+                self.dgram_expired_count =
+                    self.dgram_expired_count.saturating_add(expired);
+
                 while let Some(len) = self.dgram_send_queue.peek_front_len() {
                     let hdr_off = b.off();
                     let hdr_len = 1 + // frame type
@@ -4799,8 +5670,8 @@ impl<F: BufFactory> Connection<F> {
 
                     if (hdr_len + len) <= left {
                         // Front of the queue fits this packet, send it.
-                        match self.dgram_send_queue.pop() {
-                            Some(data) => {
+                        match self.dgram_send_queue.pop_with_id() {
+                            Some((data, id)) => {
                                 // Encode the frame.
                                 //
                                 // Instead of creating a `frame::Frame` object,
@@ -4840,8 +5711,10 @@ impl<F: BufFactory> Connection<F> {
                                 // Advance the packet buffer's offset.
                                 b.skip(hdr_len + len)?;
 
-                                let frame =
-                                    frame::Frame::DatagramHeader { length: len };
+                                let frame = frame::Frame::DatagramHeader {
+                                    length: len,
+                                    id,
+                                };
 
                                 if push_frame_to_pkt!(b, frames, frame, left) {
                                     ack_eliciting = true;
@@ -4858,7 +5731,15 @@ impl<F: BufFactory> Connection<F> {
                         };
                     } else if len > max_dgram_payload {
                         // This dgram frame will never fit. Let's purge it.
-                        self.dgram_send_queue.pop();
+                        if let Some((_, Some(id))) =
+                            self.dgram_send_queue.pop_with_id()
+                        {
+                            self.dgram_events
+                                .push_back(DatagramEvent::Dropped(id));
+                        }
+
+                        self.dgram_send_dropped_count =
+                            self.dgram_send_dropped_count.saturating_add(1);
                     } else {
                         break;
                     }
@@ -4870,7 +5751,7 @@ impl<F: BufFactory> Connection<F> {
         if (pkt_type == Type::Short || pkt_type == Type::ZeroRTT) &&
             left > frame::MAX_STREAM_OVERHEAD &&
             !is_closing &&
-            path.active() &&
+            path_usable &&
             !dgram_emitted
         {
             while let Some(priority_key) = self.streams.peek_flushable() {
@@ -4964,10 +5845,11 @@ impl<F: BufFactory> Connection<F> {
                 } else if stream.incremental {
                     // Shuffle the incremental stream to the back of the
                     // queue.
-                    self.streams.remove_flushable(&priority_key);
-                    self.streams.insert_flushable(&priority_key);
+                    self.streams.requeue_flushable(&priority_key);
                 }
 
+                self.streams.on_stream_sent(stream_id, len);
+
                 #[cfg(feature = "fuzzing")]
                 // Coalesce STREAM frames when fuzzing.
                 if left > frame::MAX_STREAM_OVERHEAD {
@@ -5118,8 +6000,9 @@ impl<F: BufFactory> Connection<F> {
             None => return Err(Error::InvalidState),
         };
 
-        let written = packet::encrypt_pkt(
+        let written = packet::encrypt_pkt_on_path(
             &mut b,
+            mp_id as u32,
             pn,
             pn_len,
             payload_len,
@@ -5151,13 +6034,16 @@ impl<F: BufFactory> Connection<F> {
             lost: 0,
             has_data: sent_pkt_has_data,
             is_pmtud_probe,
+            ecn_marked: ecn.is_ect(),
         };
 
         if in_flight && is_app_limited {
             path.recovery.delivery_rate_update_app_limited(true);
         }
 
-        self.next_pkt_num += 1;
+        if mp_id == 0 {
+            self.next_pkt_num += 1;
+        }
 
         let handshake_status = recovery::HandshakeStatus {
             has_handshake_keys: self.crypto_ctx[packet::Epoch::Handshake]
@@ -5168,11 +6054,17 @@ impl<F: BufFactory> Connection<F> {
 
         self.on_packet_sent(send_pid, sent_pkt, epoch, handshake_status, now)?;
 
+        if pkt_type == Type::Short {
+            self.on_1rtt_packet_sent(now);
+        }
+
         let path = self.paths.get_mut(send_pid)?;
         qlog_with_type!(QLOG_METRICS, self.qlog, q, {
             path.recovery.maybe_qlog(q, now);
         });
 
+        path.ecn.on_packet_sent(ecn);
+
         // Record sent packet size if we probe the path.
         if let Some(data) = challenge_data {
             path.add_challenge_sent(data, written, now);
@@ -5216,15 +6108,21 @@ impl<F: BufFactory> Connection<F> {
     ) -> Result<()> {
         let path = self.paths.get_mut(send_pid)?;
 
-        // It's fine to set the skip counter based on a non-active path's values.
-        let cwnd = path.recovery.cwnd();
-        let max_datagram_size = path.recovery.max_datagram_size();
-        self.pkt_num_spaces[epoch].on_packet_sent(&sent_pkt);
-        self.pkt_num_manager.on_packet_sent(
-            cwnd,
-            max_datagram_size,
-            self.handshake_completed,
-        );
+        if path.multipath_id == 0 {
+            // It's fine to set the skip counter based on a non-active path's
+            // values.
+            let cwnd = path.recovery.cwnd();
+            let max_datagram_size = path.recovery.max_datagram_size();
+            self.pkt_num_spaces[epoch].on_packet_sent(&sent_pkt);
+            self.pkt_num_manager.on_packet_sent(
+                cwnd,
+                max_datagram_size,
+                self.handshake_completed,
+            );
+        } else {
+            self.multipath
+                .on_packet_sent(path.multipath_id, &sent_pkt)?;
+        }
 
         path.recovery.on_packet_sent(
             sent_pkt,
@@ -5475,7 +6373,7 @@ impl<F: BufFactory> Connection<F> {
                 ..Default::default()
             });
 
-            let now = Instant::now();
+            let now = self.clock.now();
             q.add_event_data_with_instant(ev_data, now).ok();
         });
 
@@ -5555,6 +6453,173 @@ impl<F: BufFactory> Connection<F> {
         )
     }
 
+    /// Writes data to a stream, with a deadline by which it must be acked.
+    ///
+    /// If the data written by this call is not fully acked by the peer when
+    /// `deadline` is reached, the stream is reset with `error_code` as if
+    /// [`stream_shutdown()`] had been called with [`Shutdown::Write`], so that
+    /// stale data is not retransmitted forever.
+    ///
+    /// Otherwise this behaves like [`stream_send()`].
+    ///
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # use std::time::{Duration, Instant};
+    /// # let stream_id = 0;
+    /// let deadline = Instant::now() + Duration::from_millis(500);
+    /// conn.stream_send_with_deadline(stream_id, b"frame", false, deadline, 42)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_send_with_deadline(
+        &mut self, stream_id: u64, buf: &[u8], fin: bool, deadline: Instant,
+        error_code: u64,
+    ) -> Result<usize> {
+        let written = self.stream_send(stream_id, buf, fin)?;
+
+        if let Some(stream) = self.streams.get(stream_id) {
+            let end_off = stream.send.off_back();
+
+            self.streams
+                .insert_send_deadline(stream_id, stream::SendDeadline {
+                    end_off,
+                    at: deadline,
+                    error_code,
+                    reliable_size: None,
+                });
+        }
+
+        Ok(written)
+    }
+
+    /// Sets a deadline by which all of a stream's data must be acked.
+    ///
+    /// If the stream's send side is not complete (that is, not all data up to
+    /// the final size was acked by the peer) when `deadline` is reached, the
+    /// stream is reset with `error_code` as if [`stream_shutdown()`] had been
+    /// called with [`Shutdown::Write`].
+    ///
+    /// The deadline also covers data written after calling this method. It
+    /// replaces any deadline previously set with this method, and a deadline
+    /// of `None` removes it.
+    ///
+    /// If the specified stream doesn't exist (including when it has already
+    /// been completed and closed), or is a remotely-initiated unidirectional
+    /// stream, the [`InvalidStreamState`] error will be returned.
+    ///
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_deadline(
+        &mut self, stream_id: u64, deadline: Option<Instant>, error_code: u64,
+    ) -> Result<()> {
+        self.set_stream_deadline(stream_id, deadline, error_code, None)
+    }
+
+    /// Sets a deadline by which all of a stream's data must be acked, and
+    /// after which the stream is reset reliably.
+    ///
+    /// This is the same as [`stream_deadline()`], except that when the
+    /// deadline is reached and the reliable stream reset extension was
+    /// negotiated, the stream is reset as if [`stream_reset_at()`] had been
+    /// called with `reliable_size`. The data up to `reliable_size`, or all of
+    /// the stream's data if less was written, is still delivered to the peer.
+    ///
+    /// If the extension was not negotiated by the time the deadline is
+    /// reached, the stream is reset as with [`stream_deadline()`].
+    ///
+    /// [`stream_deadline()`]: struct.Connection.html#method.stream_deadline
+    /// [`stream_reset_at()`]: struct.Connection.html#method.stream_reset_at
+    pub fn stream_deadline_with_reliable_size(
+        &mut self, stream_id: u64, deadline: Instant, error_code: u64,
+        reliable_size: u64,
+    ) -> Result<()> {
+        self.set_stream_deadline(
+            stream_id,
+            Some(deadline),
+            error_code,
+            Some(reliable_size),
+        )
+    }
+
+    /// Sets or removes the deadline covering all of a stream's data.
+    fn set_stream_deadline(
+        &mut self, stream_id: u64, deadline: Option<Instant>, error_code: u64,
+        reliable_size: Option<u64>,
+    ) -> Result<()> {
+        if !stream::is_local(stream_id, self.is_server) &&
+            !stream::is_bidi(stream_id)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        if self.streams.get(stream_id).is_none() {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        match deadline {
+            Some(at) => self.streams.insert_send_deadline(
+                stream_id,
+                stream::SendDeadline {
+                    end_off: u64::MAX,
+                    at,
+                    error_code,
+                    reliable_size,
+                },
+            ),
+
+            None => self.streams.remove_stream_deadline(stream_id),
+        }
+
+        Ok(())
+    }
+
+    /// Resets the streams whose send deadline expired.
+    fn expire_stream_deadlines(&mut self, now: Instant) {
+        for (stream_id, deadline) in self.streams.expire_send_deadlines(now) {
+            trace!(
+                "{} stream {} send deadline expired",
+                self.trace_id,
+                stream_id
+            );
+
+            let reliable_size = deadline
+                .reliable_size
+                .filter(|_| self.is_reset_stream_at_enabled());
+
+            if let Some(reliable_size) = reliable_size {
+                let reliable_size = self
+                    .streams
+                    .get(stream_id)
+                    .map_or(0, |s| reliable_size.min(s.send.off_back()));
+
+                if self
+                    .stream_reset_at(
+                        stream_id,
+                        deadline.error_code,
+                        reliable_size,
+                    )
+                    .is_ok()
+                {
+                    continue;
+                }
+            }
+
+            self.stream_shutdown(stream_id, Shutdown::Write, deadline.error_code)
+                .ok();
+        }
+    }
+
     /// Writes data to a stream with zero copying, instead, it appends the
     /// provided buffer directly to the send queue if the capacity allows
     /// it.
@@ -5735,7 +6800,7 @@ impl<F: BufFactory> Connection<F> {
                 ..Default::default()
             });
 
-            let now = Instant::now();
+            let now = self.clock.now();
             q.add_event_data_with_instant(ev_data, now).ok();
         });
 
@@ -5796,6 +6861,63 @@ impl<F: BufFactory> Connection<F> {
         Ok(())
     }
 
+    /// Sets the weight of a stream.
+    ///
+    /// The weight is not used by the default RFC 9218 scheduler, but is
+    /// passed to custom schedulers set with [`set_stream_scheduler()`], for
+    /// example to share bandwidth between streams. Streams are created with a
+    /// default weight of `1`.
+    ///
+    /// The target stream is created if it did not exist before calling this
+    /// method.
+    ///
+    /// [`set_stream_scheduler()`]:
+    /// struct.Connection.html#method.set_stream_scheduler
+    pub fn stream_weight(&mut self, stream_id: u64, weight: u32) -> Result<()> {
+        // Get existing stream or create a new one, but if the stream
+        // has already been closed and collected, ignore the weight.
+        match self.get_or_create_stream(stream_id, true) {
+            Ok(_) => (),
+
+            Err(Error::Done) => return Ok(()),
+
+            Err(e) => return Err(e),
+        };
+
+        self.streams.set_weight(stream_id, weight);
+
+        Ok(())
+    }
+
+    /// Sets the scheduler selecting which stream to send data from next.
+    ///
+    /// By default, streams are scheduled following the RFC 9218 urgency and
+    /// incremental parameters set with [`stream_priority()`].
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// conn.set_stream_scheduler(Box::new(
+    ///     quiche::DeficitRoundRobinScheduler::default(),
+    /// ));
+    ///
+    /// // Stream 4 gets three times the bandwidth of stream 0.
+    /// conn.stream_weight(0, 1)?;
+    /// conn.stream_weight(4, 3)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`stream_priority()`]: struct.Connection.html#method.stream_priority
+    pub fn set_stream_scheduler(&mut self, scheduler: Box<dyn StreamScheduler>) {
+        self.streams.set_scheduler(scheduler);
+    }
+
     /// Shuts down reading or writing from/to the specified stream.
     ///
     /// When the `direction` argument is set to [`Shutdown::Read`], outstanding
@@ -5891,7 +7013,8 @@ impl<F: BufFactory> Connection<F> {
                             ..Default::default()
                         });
 
-                    q.add_event_data_with_instant(ev_data, Instant::now()).ok();
+                    q.add_event_data_with_instant(ev_data, self.clock.now())
+                        .ok();
                 });
 
                 // Update send capacity.
@@ -5910,19 +7033,103 @@ impl<F: BufFactory> Connection<F> {
         Ok(())
     }
 
-    /// Returns the stream's send capacity in bytes.
+    /// Resets the specified stream, while still delivering the data up to
+    /// `reliable_size` to the peer.
     ///
-    /// If the specified stream doesn't exist (including when it has already
-    /// been completed and closed), the [`InvalidStreamState`] error will be
-    /// returned.
+    /// This behaves like [`stream_shutdown()`] in the [`Shutdown::Write`]
+    /// direction, except that data buffered below `reliable_size` is still
+    /// sent (and retransmitted if lost), and a `RESET_STREAM_AT` frame is sent
+    /// to the peer instead of `RESET_STREAM`. The peer's application reads
+    /// the data up to `reliable_size` before being notified of the reset.
     ///
-    /// In addition, if the peer has signalled that it doesn't want to receive
-    /// any more data from this stream by sending the `STOP_SENDING` frame, the
-    /// [`StreamStopped`] error will be returned.
+    /// This requires the reliable stream reset extension to have been
+    /// negotiated, otherwise the [`InvalidState`] error is returned. See
+    /// [`Config::enable_reset_stream_at()`].
     ///
+    /// The [`InvalidStreamState`] error is returned if the stream is a
+    /// remotely-initiated unidirectional stream, or if `reliable_size` is
+    /// larger than the amount of data written to the stream. If the stream
+    /// was already reset, [`Done`] is returned.
+    ///
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`Config::enable_reset_stream_at()`]: struct.Config.html#method.enable_reset_stream_at
     /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
-    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
-    #[inline]
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_reset_at(
+        &mut self, stream_id: u64, err: u64, reliable_size: u64,
+    ) -> Result<()> {
+        if !self.is_reset_stream_at_enabled() {
+            return Err(Error::InvalidState);
+        }
+
+        // Don't try to reset a remote unidirectional stream.
+        if !stream::is_local(stream_id, self.is_server) &&
+            !stream::is_bidi(stream_id)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Get existing stream.
+        let stream = self.streams.get_mut(stream_id).ok_or(Error::Done)?;
+
+        if reliable_size > stream.send.off_back() {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        let (final_size, unsent) = stream.send.reset_at(reliable_size)?;
+
+        // Claw back some flow control allowance from data that was buffered
+        // but will never be sent.
+        self.tx_data = self.tx_data.saturating_sub(unsent);
+
+        self.tx_buffered = self.tx_buffered.saturating_sub(unsent as usize);
+
+        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
+            let ev_data = EventData::DataMoved(qlog::events::quic::DataMoved {
+                stream_id: Some(stream_id),
+                offset: Some(final_size),
+                length: Some(unsent),
+                from: Some(DataRecipient::Transport),
+                to: Some(DataRecipient::Dropped),
+                ..Default::default()
+            });
+
+            q.add_event_data_with_instant(ev_data, self.clock.now())
+                .ok();
+        });
+
+        // Update send capacity.
+        self.update_tx_cap();
+
+        self.streams
+            .insert_reset_at(stream_id, err, final_size, reliable_size);
+
+        // Once reset, the stream is guaranteed to be non-writable.
+        self.streams.remove_writable(&priority_key);
+
+        self.reset_stream_local_count =
+            self.reset_stream_local_count.saturating_add(1);
+
+        Ok(())
+    }
+
+    /// Returns the stream's send capacity in bytes.
+    ///
+    /// If the specified stream doesn't exist (including when it has already
+    /// been completed and closed), the [`InvalidStreamState`] error will be
+    /// returned.
+    ///
+    /// In addition, if the peer has signalled that it doesn't want to receive
+    /// any more data from this stream by sending the `STOP_SENDING` frame, the
+    /// [`StreamStopped`] error will be returned.
+    ///
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
+    #[inline]
     pub fn stream_capacity(&mut self, stream_id: u64) -> Result<usize> {
         if let Some(stream) = self.streams.get(stream_id) {
             let stream_cap = match stream.send.cap() {
@@ -6097,6 +7304,23 @@ impl<F: BufFactory> Connection<F> {
         Ok(false)
     }
 
+    /// Returns true if data was received on the specified stream in 0-RTT
+    /// packets.
+    ///
+    /// Such data may have been replayed by an attacker, so applications
+    /// should only act on it if doing so is idempotent, even when an
+    /// anti-replay store is configured with [`set_anti_replay()`], as that
+    /// cannot catch all replays (e.g. those sent to servers that don't share
+    /// the store).
+    ///
+    /// [`set_anti_replay()`]: struct.Config.html#method.set_anti_replay
+    #[inline]
+    pub fn stream_received_early_data(&self, stream_id: u64) -> bool {
+        self.streams
+            .get(stream_id)
+            .is_some_and(|stream| stream.early_data)
+    }
+
     /// Returns true if all the data has been read from the specified stream.
     ///
     /// This instructs the application that all the data received from the
@@ -6441,47 +7665,179 @@ impl<F: BufFactory> Connection<F> {
     /// # Ok::<(), quiche::Error>(())
     /// ```
     pub fn dgram_send(&mut self, buf: &[u8]) -> Result<()> {
-        let max_payload_len = match self.dgram_max_writable_len() {
-            Some(v) => v,
+        self.dgram_send_with_priority(buf, dgram::DEFAULT_URGENCY, None)
+    }
 
-            None => return Err(Error::InvalidState),
-        };
+    /// Sends data in a DATAGRAM frame.
+    ///
+    /// This is the same as [`dgram_send()`] but takes a `Vec<u8>` instead of
+    /// a slice.
+    ///
+    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    pub fn dgram_send_vec(&mut self, buf: Vec<u8>) -> Result<()> {
+        self.dgram_send_vec_with_priority(buf, dgram::DEFAULT_URGENCY, None)
+    }
 
-        if buf.len() > max_payload_len {
-            return Err(Error::BufferTooShort);
-        }
+    /// Sends data in a DATAGRAM frame with the given urgency and expiry.
+    ///
+    /// Queued DATAGRAM frames are sent in order of urgency (lower is better),
+    /// so a frame bypasses all queued frames with a higher urgency value.
+    /// Frames with the same urgency are sent in the order they were queued.
+    /// Frames sent with [`dgram_send()`] have an urgency of `127`.
+    ///
+    /// When the send queue is full, expired frames are dropped first. If
+    /// that doesn't free any space, the least urgent and most recently queued
+    /// frame is dropped instead, as long as it is less urgent than the new
+    /// frame. Otherwise [`Done`] is returned.
+    ///
+    /// If `expiry` is set and the frame is still queued at that time, it is
+    /// dropped instead of sent. Dropped frames are counted in the
+    /// [`dgram_expired`] stat.
+    ///
+    /// Otherwise this behaves like [`dgram_send()`].
+    ///
+    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    /// [`dgram_expired`]: struct.Stats.html#structfield.dgram_expired
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # use std::time::{Duration, Instant};
+    /// let expiry = Instant::now() + Duration::from_millis(50);
+    /// conn.dgram_send_with_priority(b"video frame", 0, Some(expiry))?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn dgram_send_with_priority(
+        &mut self, buf: &[u8], urgency: u8, expiry: Option<Instant>,
+    ) -> Result<()> {
+        self.dgram_check_len(buf.len())?;
 
-        self.dgram_send_queue.push(buf.to_vec())?;
+        self.dgram_queue(buf.to_vec(), urgency, expiry, None)
+    }
 
-        let active_path = self.paths.get_active_mut()?;
+    /// Sends data in a DATAGRAM frame with the given urgency and expiry.
+    ///
+    /// This is the same as [`dgram_send_with_priority()`] but takes a
+    /// `Vec<u8>` instead of a slice.
+    ///
+    /// [`dgram_send_with_priority()`]:
+    /// struct.Connection.html#method.dgram_send_with_priority
+    pub fn dgram_send_vec_with_priority(
+        &mut self, buf: Vec<u8>, urgency: u8, expiry: Option<Instant>,
+    ) -> Result<()> {
+        self.dgram_check_len(buf.len())?;
 
-        if self.dgram_send_queue.byte_size() >
-            active_path.recovery.cwnd_available()
-        {
-            active_path.recovery.update_app_limited(false);
-        }
+        self.dgram_queue(buf, urgency, expiry, None)
+    }
 
-        Ok(())
+    /// Sends data in a DATAGRAM frame, tracking its fate.
+    ///
+    /// On success the ID assigned to the frame is returned. Exactly one
+    /// [`DatagramEvent`] carrying this ID will later be reported by
+    /// [`dgram_event_next()`], once the frame is acknowledged, declared lost
+    /// or dropped before being sent.
+    ///
+    /// Otherwise this behaves like [`dgram_send_with_priority()`].
+    ///
+    /// [`DatagramEvent`]: enum.DatagramEvent.html
+    /// [`dgram_event_next()`]: struct.Connection.html#method.dgram_event_next
+    /// [`dgram_send_with_priority()`]:
+    /// struct.Connection.html#method.dgram_send_with_priority
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let id = conn.dgram_send_tracked(b"hello", 127, None)?;
+    ///
+    /// while let Some(ev) = conn.dgram_event_next() {
+    ///     match ev {
+    ///         quiche::DatagramEvent::Lost(lost_id) if lost_id == id => {
+    ///             // Maybe send the data again.
+    ///         },
+    ///
+    ///         _ => (),
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn dgram_send_tracked(
+        &mut self, buf: &[u8], urgency: u8, expiry: Option<Instant>,
+    ) -> Result<u64> {
+        self.dgram_check_len(buf.len())?;
+
+        let id = self.next_dgram_id;
+
+        self.dgram_queue(buf.to_vec(), urgency, expiry, Some(id))?;
+
+        self.next_dgram_id += 1;
+
+        Ok(id)
     }
 
-    /// Sends data in a DATAGRAM frame.
+    /// Processes events about tracked DATAGRAM frames.
     ///
-    /// This is the same as [`dgram_send()`] but takes a `Vec<u8>` instead of
-    /// a slice.
+    /// On success it returns a [`DatagramEvent`], or `None` when there are no
+    /// events to report. Events are only generated for frames sent with
+    /// [`dgram_send_tracked()`].
     ///
-    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
-    pub fn dgram_send_vec(&mut self, buf: Vec<u8>) -> Result<()> {
+    /// [`DatagramEvent`]: enum.DatagramEvent.html
+    /// [`dgram_send_tracked()`]:
+    /// struct.Connection.html#method.dgram_send_tracked
+    pub fn dgram_event_next(&mut self) -> Option<DatagramEvent> {
+        self.dgram_events.pop_front()
+    }
+
+    /// Checks that a DATAGRAM frame with a payload of `len` bytes can be
+    /// sent.
+    fn dgram_check_len(&self, len: usize) -> Result<()> {
         let max_payload_len = match self.dgram_max_writable_len() {
             Some(v) => v,
 
             None => return Err(Error::InvalidState),
         };
 
-        if buf.len() > max_payload_len {
+        if len > max_payload_len {
             return Err(Error::BufferTooShort);
         }
 
-        self.dgram_send_queue.push(buf)?;
+        Ok(())
+    }
+
+    /// Queues a DATAGRAM frame for sending.
+    fn dgram_queue(
+        &mut self, buf: Vec<u8>, urgency: u8, expiry: Option<Instant>,
+        id: Option<u64>,
+    ) -> Result<()> {
+        // Make room for the new frame by dropping the ones that won't be sent
+        // anyway.
+        if self.dgram_send_queue.is_full() {
+            let expired = self
+                .dgram_send_queue
+                .purge_expired(self.clock.now(), &mut self.dgram_events);
+            self.dgram_expired_count =
+                self.dgram_expired_count.saturating_add(expired);
+        }
+
+        self.dgram_send_queue.push_with_priority(
+            buf,
+            urgency,
+            expiry,
+            id,
+            &mut self.dgram_events,
+        )?;
 
         let active_path = self.paths.get_active_mut()?;
 
@@ -6512,7 +7868,7 @@ impl<F: BufFactory> Connection<F> {
     /// ```
     #[inline]
     pub fn dgram_purge_outgoing<FN: Fn(&[u8]) -> bool>(&mut self, f: FN) {
-        self.dgram_send_queue.purge(f);
+        self.dgram_send_queue.purge(f, &mut self.dgram_events);
     }
 
     /// Returns the maximum DATAGRAM payload that can be sent.
@@ -6603,7 +7959,13 @@ impl<F: BufFactory> Connection<F> {
                 .as_ref()
                 .map(|key_update| key_update.timer);
 
-            let timers = [self.idle_timer, path_timer, key_update_timer];
+            let timers = [
+                self.idle_timer,
+                path_timer,
+                key_update_timer,
+                self.ack_freq.ack_timer(),
+                self.streams.next_send_deadline(),
+            ];
 
             timers.iter().filter_map(|&x| x).min()
         }
@@ -6617,7 +7979,7 @@ impl<F: BufFactory> Connection<F> {
     /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
     pub fn timeout(&self) -> Option<Duration> {
         self.timeout_instant().map(|timeout| {
-            let now = Instant::now();
+            let now = self.clock.now();
 
             if timeout <= now {
                 Duration::ZERO
@@ -6631,8 +7993,14 @@ impl<F: BufFactory> Connection<F> {
     ///
     /// If no timeout has occurred it does nothing.
     pub fn on_timeout(&mut self) {
-        let now = Instant::now();
+        self.on_timeout_at(self.clock.now())
+    }
 
+    /// Same as [`on_timeout()`], but processes the timers as if the given time
+    /// was the current time.
+    ///
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    fn on_timeout_at(&mut self, now: Instant) {
         if let Some(draining_timer) = self.draining_timer {
             if draining_timer <= now {
                 trace!("{} draining timeout expired", self.trace_id);
@@ -6669,6 +8037,13 @@ impl<F: BufFactory> Connection<F> {
             }
         }
 
+        if self.ack_freq.on_timeout(now) {
+            trace!("{} ack delay timeout expired", self.trace_id);
+
+            // Send the ACK that was being delayed.
+            self.pkt_num_spaces[packet::Epoch::Application].ack_elicited = true;
+        }
+
         let handshake_status = self.handshake_status();
 
         for (_, p) in self.paths.iter_mut() {
@@ -6696,6 +8071,8 @@ impl<F: BufFactory> Connection<F> {
             }
         }
 
+        self.expire_stream_deadlines(now);
+
         // Notify timeout events to the application.
         self.paths.notify_failed_validations();
 
@@ -6846,7 +8223,7 @@ impl<F: BufFactory> Connection<F> {
         };
 
         // Change the active path.
-        self.set_active_path(pid, Instant::now())?;
+        self.set_active_path(pid, self.clock.now())?;
 
         Ok(dcid_seq)
     }
@@ -6997,7 +8374,7 @@ impl<F: BufFactory> Connection<F> {
 
     /// Returns the number of source Connection IDs that are retired.
     pub fn retired_scids(&self) -> usize {
-        self.ids.retired_source_cids()
+        self.ids.retired_source_cids() + self.multipath.retired_scids()
     }
 
     /// Returns a source `ConnectionId` that has been retired.
@@ -7007,7 +8384,9 @@ impl<F: BufFactory> Connection<F> {
     ///
     /// [`ConnectionId`]: struct.ConnectionId.html
     pub fn retired_scid_next(&mut self) -> Option<ConnectionId<'static>> {
-        self.ids.pop_retired_scid()
+        self.ids
+            .pop_retired_scid()
+            .or_else(|| self.multipath.pop_retired_scid())
     }
 
     /// Returns the number of spare Destination Connection IDs, i.e.,
@@ -7019,6 +8398,124 @@ impl<F: BufFactory> Connection<F> {
         self.ids.available_dcids()
     }
 
+    /// Returns whether the multipath extension was negotiated.
+    ///
+    /// See [`Config::set_initial_max_path_id()`].
+    ///
+    /// [`Config::set_initial_max_path_id()`]: struct.Config.html#method.set_initial_max_path_id
+    #[inline]
+    pub fn is_multipath_enabled(&self) -> bool {
+        self.multipath.enabled()
+    }
+
+    /// Returns whether the reliable stream reset extension was negotiated.
+    ///
+    /// See [`Config::enable_reset_stream_at()`].
+    ///
+    /// [`Config::enable_reset_stream_at()`]: struct.Config.html#method.enable_reset_stream_at
+    #[inline]
+    pub fn is_reset_stream_at_enabled(&self) -> bool {
+        self.local_transport_params.reset_stream_at &&
+            self.peer_transport_params.reset_stream_at
+    }
+
+    /// Provides an additional source Connection ID for the multipath path
+    /// identifier `path_id`.
+    ///
+    /// This triggers sending PATH_NEW_CONNECTION_ID frames. Both endpoints
+    /// need to issue Connection IDs for a path identifier before the client
+    /// can open a new path with [`probe_path()`] using that identifier.
+    ///
+    /// Path identifier 0 uses the Connection IDs provided with
+    /// [`new_scid()`], so calling this method with it, with a path identifier
+    /// larger than the maximum allowed by the peer, or when multipath was not
+    /// negotiated returns an [`InvalidState`]. If the peer would have more
+    /// Connection IDs for `path_id` than its active Connection ID limit, an
+    /// [`IdLimit`] is returned.
+    ///
+    /// Returns the sequence number associated to the provided Connection ID.
+    ///
+    /// [`probe_path()`]: struct.Connection.html#method.probe_path
+    /// [`new_scid()`]: struct.Connection.html#method.new_scid
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`IdLimit`]: enum.Error.html#IdLimit
+    pub fn new_path_scid(
+        &mut self, path_id: u64, scid: &ConnectionId, reset_token: u128,
+    ) -> Result<u64> {
+        self.multipath.new_scid(
+            path_id,
+            scid.to_vec().into(),
+            reset_token,
+            self.peer_transport_params.active_conn_id_limit,
+        )
+    }
+
+    /// Abandons the network path between `local_addr` and `peer_addr`.
+    ///
+    /// This triggers sending a PATH_ABANDON frame with the given
+    /// `error_code`, after which the path is not used anymore. Frames that
+    /// were in flight on the path are retransmitted on the remaining ones.
+    ///
+    /// Only paths using a multipath path identifier other than 0 can be
+    /// abandoned. Otherwise, or if the path does not exist, this method
+    /// returns an [`InvalidState`].
+    ///
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    pub fn abandon_path(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr, error_code: u64,
+    ) -> Result<()> {
+        let pid = self
+            .paths
+            .path_id_from_addrs(&(local_addr, peer_addr))
+            .ok_or(Error::InvalidState)?;
+
+        let mp_id = self.paths.get(pid)?.multipath_id;
+
+        if self.multipath.abandon(mp_id, error_code)? {
+            self.on_multipath_id_abandoned(mp_id, self.clock.now())?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the status of the network path between `local_addr` and
+    /// `peer_addr`, and advertises it to the peer.
+    ///
+    /// The path scheduler only uses backup paths when no other path can be
+    /// used.
+    ///
+    /// If the path does not exist, or when multipath was not negotiated, this
+    /// method returns an [`InvalidState`].
+    ///
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    pub fn set_path_status(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+        status: PathStatus,
+    ) -> Result<()> {
+        if !self.multipath.enabled() {
+            return Err(Error::InvalidState);
+        }
+
+        let pid = self
+            .paths
+            .path_id_from_addrs(&(local_addr, peer_addr))
+            .ok_or(Error::InvalidState)?;
+
+        let mp_id = self.paths.get(pid)?.multipath_id;
+
+        self.multipath.set_status(mp_id, status)
+    }
+
+    /// Sets the scheduler selecting the path on which packets are sent when
+    /// multipath is enabled.
+    ///
+    /// By default, [`MinRttScheduler`] is used.
+    ///
+    /// [`MinRttScheduler`]: struct.MinRttScheduler.html
+    pub fn set_path_scheduler(&mut self, scheduler: Box<dyn PathScheduler>) {
+        self.multipath.set_scheduler(scheduler);
+    }
+
     /// Returns an iterator over destination `SockAddr`s whose association
     /// with `from` forms a known QUIC path on which packets can be sent to.
     ///
@@ -7083,44 +8580,187 @@ impl<F: BufFactory> Connection<F> {
         }
     }
 
-    /// Closes the connection with the given error and reason.
-    ///
-    /// The `app` parameter specifies whether an application close should be
-    /// sent to the peer. Otherwise a normal connection close is sent.
-    ///
-    /// If `app` is true but the connection is not in a state that is safe to
-    /// send an application error (not established nor in early data), in
-    /// accordance with [RFC
-    /// 9000](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.3-3), the
-    /// error code is changed to APPLICATION_ERROR and the reason phrase is
-    /// cleared.
+    /// Initiates an update of the 1-RTT packet protection keys.
     ///
-    /// Returns [`Done`] if the connection had already been closed.
+    /// Packets sent after this call are protected with the new keys. Key
+    /// updates are also initiated automatically before the confidentiality
+    /// limit of the negotiated AEAD algorithm is reached, see
+    /// [`set_max_packets_per_key()`].
     ///
-    /// Note that the connection will not be closed immediately. An application
-    /// should continue calling the [`recv()`], [`send()`], [`timeout()`] and
-    /// [`on_timeout()`] methods as normal, until the [`is_closed()`] method
-    /// returns `true`.
+    /// Returns [`InvalidState`] if the handshake is not confirmed yet, and
+    /// [`Done`] if the previous key update has not been acknowledged by the
+    /// peer yet.
     ///
+    /// [`set_max_packets_per_key()`]: struct.Config.html#method.set_max_packets_per_key
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
     /// [`Done`]: enum.Error.html#variant.Done
-    /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`send()`]: struct.Connection.html#method.send
-    /// [`timeout()`]: struct.Connection.html#method.timeout
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    pub fn close(&mut self, app: bool, err: u64, reason: &[u8]) -> Result<()> {
-        if self.is_closed() || self.is_draining() {
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        self.initiate_key_update_at(self.clock.now())
+    }
+
+    fn initiate_key_update_at(&mut self, now: Instant) -> Result<()> {
+        if !self.handshake_confirmed || self.is_closed() || self.is_draining() {
+            return Err(Error::InvalidState);
+        }
+
+        // A new key update can't be initiated until the peer acknowledged a
+        // packet protected with the current keys.
+        if self.key_update_first_pn.is_some() {
             return Err(Error::Done);
         }
 
-        if self.local_error.is_some() {
+        let pto = self.paths.get_active()?.recovery.pto();
+
+        let crypto_ctx = &mut self.crypto_ctx[packet::Epoch::Application];
+
+        // Don't update the keys again before acknowledging the peer's key
+        // update, if any.
+        if !crypto_ctx
+            .key_update
+            .as_ref()
+            .is_none_or(|prev| prev.update_acked)
+        {
             return Err(Error::Done);
         }
 
-        let is_safe_to_send_app_data =
-            self.is_established() || self.is_in_early_data();
+        let (open, seal) = match (
+            crypto_ctx.crypto_open.take(),
+            crypto_ctx.crypto_seal.take(),
+        ) {
+            (Some(open), Some(seal)) => (open, seal),
 
-        if app && !is_safe_to_send_app_data {
+            _ => return Err(Error::InvalidState),
+        };
+
+        let next_keys = open.derive_next_packet_key().and_then(|open_next| {
+            Ok((open_next, seal.derive_next_packet_key()?))
+        });
+
+        let (open_next, seal_next) = match next_keys {
+            Ok(v) => v,
+
+            Err(e) => {
+                crypto_ctx.crypto_open = Some(open);
+                crypto_ctx.crypto_seal = Some(seal);
+
+                return Err(e);
+            },
+        };
+
+        crypto_ctx.crypto_open = Some(open_next);
+        crypto_ctx.crypto_seal = Some(seal_next);
+
+        crypto_ctx.key_update = Some(packet::KeyUpdate {
+            crypto_open: open,
+            // Until the peer starts using the new keys, all its packets using
+            // the previous key phase are protected with the previous keys.
+            pn_on_update: u64::MAX,
+            update_acked: true,
+            timer: now + (pto * 3),
+        });
+
+        trace!("{} initiated key update", self.trace_id);
+
+        self.key_phase = !self.key_phase;
+        self.key_phase_sent_count = 0;
+        self.key_update_first_pn = Some(self.next_pkt_num);
+        self.key_update_count += 1;
+
+        qlog_with_type!(QLOG_PACKET_TX, self.qlog, q, {
+            let trigger = Some(
+                qlog::events::security::KeyUpdateOrRetiredTrigger::LocalUpdate,
+            );
+
+            let ev_data_client =
+                EventData::KeyUpdated(qlog::events::security::KeyUpdated {
+                    key_type: qlog::events::security::KeyType::Client1RttSecret,
+                    trigger: trigger.clone(),
+                    ..Default::default()
+                });
+
+            q.add_event_data_with_instant(ev_data_client, now).ok();
+
+            let ev_data_server =
+                EventData::KeyUpdated(qlog::events::security::KeyUpdated {
+                    key_type: qlog::events::security::KeyType::Server1RttSecret,
+                    trigger,
+                    ..Default::default()
+                });
+
+            q.add_event_data_with_instant(ev_data_server, now).ok();
+        });
+
+        Ok(())
+    }
+
+    /// Initiates a key update before the current 1-RTT keys protected too
+    /// many packets.
+    fn on_1rtt_packet_sent(&mut self, now: Instant) {
+        self.key_phase_sent_count += 1;
+
+        let limit = match &self.crypto_ctx[packet::Epoch::Application].crypto_seal
+        {
+            Some(seal) => seal.alg().confidentiality_limit(),
+
+            None => return,
+        };
+
+        // Leave some headroom, as the previous key update might need to be
+        // acknowledged before a new one can be initiated.
+        let threshold = cmp::min(self.max_packets_per_key, limit - limit / 8);
+
+        if self.key_phase_sent_count < threshold {
+            return;
+        }
+
+        if self.initiate_key_update_at(now).is_err() &&
+            self.key_phase_sent_count >= limit
+        {
+            trace!("{} AEAD confidentiality limit reached", self.trace_id);
+
+            self.close(false, Error::AeadLimitReached.to_wire(), b"")
+                .ok();
+        }
+    }
+
+    /// Closes the connection with the given error and reason.
+    ///
+    /// The `app` parameter specifies whether an application close should be
+    /// sent to the peer. Otherwise a normal connection close is sent.
+    ///
+    /// If `app` is true but the connection is not in a state that is safe to
+    /// send an application error (not established nor in early data), in
+    /// accordance with [RFC
+    /// 9000](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.3-3), the
+    /// error code is changed to APPLICATION_ERROR and the reason phrase is
+    /// cleared.
+    ///
+    /// Returns [`Done`] if the connection had already been closed.
+    ///
+    /// Note that the connection will not be closed immediately. An application
+    /// should continue calling the [`recv()`], [`send()`], [`timeout()`] and
+    /// [`on_timeout()`] methods as normal, until the [`is_closed()`] method
+    /// returns `true`.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    pub fn close(&mut self, app: bool, err: u64, reason: &[u8]) -> Result<()> {
+        if self.is_closed() || self.is_draining() {
+            return Err(Error::Done);
+        }
+
+        if self.local_error.is_some() {
+            return Err(Error::Done);
+        }
+
+        let is_safe_to_send_app_data =
+            self.is_established() || self.is_in_early_data();
+
+        if app && !is_safe_to_send_app_data {
             // Clear error information.
             self.local_error = Some(ConnectionError {
                 is_app: false,
@@ -7194,6 +8834,43 @@ impl<F: BufFactory> Connection<F> {
         self.session.as_deref()
     }
 
+    /// Serializes the connection's state, so that it can be restored later
+    /// using the [`restore()`] function.
+    ///
+    /// This can be used to hand off established connections to another
+    /// process, e.g. during a graceful restart. The returned state includes
+    /// the connection's traffic secrets, so it needs to be handled as
+    /// carefully as the TLS private key. Once the state is exported, the
+    /// connection must not be used anymore, or the two copies of the
+    /// connection would reuse the same packet numbers.
+    ///
+    /// The state of an HTTP/3 connection running on top of this connection is
+    /// exported separately with [`h3::Connection::export_state()`].
+    ///
+    /// The following is not part of the exported state: the connection's
+    /// statistics, the congestion controller and pacer state (which start
+    /// over using the RTT estimate from the exported connection), the stream
+    /// scheduler, and the TLS state (including the information exposed by
+    /// [`peer_cert()`], [`server_name()`] and [`is_resumed()`]). Since packets
+    /// in flight are not tracked either, the restored connection retransmits
+    /// all stream data that was not acked yet, and reports tracked DATAGRAM
+    /// frames in flight as [`Lost`].
+    ///
+    /// The [`InvalidState`] error is returned if the handshake is not
+    /// confirmed, if the connection is closing, draining or closed, or if the
+    /// multipath extension is in use.
+    ///
+    /// [`restore()`]: fn.restore.html
+    /// [`peer_cert()`]: struct.Connection.html#method.peer_cert
+    /// [`server_name()`]: struct.Connection.html#method.server_name
+    /// [`is_resumed()`]: struct.Connection.html#method.is_resumed
+    /// [`h3::Connection::export_state()`]: h3/struct.Connection.html#method.export_state
+    /// [`Lost`]: enum.DatagramEvent.html#variant.Lost
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn export_state(&self) -> Result<Vec<u8>> {
+        self.to_snapshot(self.clock.now())
+    }
+
     /// Returns the source connection ID.
     ///
     /// When there are multiple IDs, and if there is an active path, the ID used
@@ -7221,7 +8898,7 @@ impl<F: BufFactory> Connection<F> {
     /// been explicitly retired yet).
     #[inline]
     pub fn source_ids(&self) -> impl Iterator<Item = &ConnectionId<'_>> {
-        self.ids.scids_iter()
+        self.ids.scids_iter().chain(self.multipath.scids_iter())
     }
 
     /// Returns the destination connection ID.
@@ -7384,6 +9061,9 @@ impl<F: BufFactory> Connection<F> {
     /// Collects and returns statistics about the connection.
     #[inline]
     pub fn stats(&self) -> Stats {
+        let recv_ecn_counts =
+            self.pkt_num_spaces.iter().map(|s| &s.recv_ecn_counts);
+
         Stats {
             recv: self.recv_count,
             sent: self.sent_count,
@@ -7397,6 +9077,9 @@ impl<F: BufFactory> Connection<F> {
             stream_retrans_bytes: self.stream_retrans_bytes,
             dgram_recv: self.dgram_recv_count,
             dgram_sent: self.dgram_sent_count,
+            dgram_expired: self.dgram_expired_count,
+            dgram_send_dropped: self.dgram_send_dropped_count,
+            dgram_recv_dropped: self.dgram_recv_dropped_count,
             paths_count: self.paths.len(),
             reset_stream_count_local: self.reset_stream_local_count,
             stopped_stream_count_local: self.stopped_stream_local_count,
@@ -7409,6 +9092,16 @@ impl<F: BufFactory> Connection<F> {
             path_challenge_rx_count: self.path_challenge_rx_count,
             bytes_in_flight_duration: self.bytes_in_flight_duration(),
             tx_buffered_state: self.tx_buffered_state,
+            key_update_count: self.key_update_count,
+            ecn_ect0_recv_count: recv_ecn_counts
+                .clone()
+                .map(|c| c.ect0_count)
+                .sum(),
+            ecn_ect1_recv_count: recv_ecn_counts
+                .clone()
+                .map(|c| c.ect1_count)
+                .sum(),
+            ecn_ce_recv_count: recv_ecn_counts.map(|c| c.ecn_ce_count).sum(),
         }
     }
 
@@ -7447,6 +9140,137 @@ impl<F: BufFactory> Connection<F> {
         self.is_server
     }
 
+    /// Returns the QUIC version used by the connection.
+    ///
+    /// This might differ from the version the connection was started with
+    /// (see [`original_version()`]) if compatible version negotiation was
+    /// performed.
+    ///
+    /// [`original_version()`]: Self::original_version
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the QUIC version the connection was started with.
+    pub fn original_version(&self) -> u32 {
+        self.original_version
+    }
+
+    /// Returns true if `version` was offered to the peer for compatible
+    /// version negotiation.
+    fn compatible_version_offered(&self, version: u32) -> bool {
+        self.local_transport_params
+            .version_information
+            .as_ref()
+            .is_some_and(|vi| vi.available_versions.contains(&version)) &&
+            version_is_compatible(self.original_version, version)
+    }
+
+    /// Updates the chosen version advertised in the local transport
+    /// parameters to the current version.
+    fn set_chosen_version(&mut self) {
+        if let Some(vi) = &mut self.local_transport_params.version_information {
+            vi.chosen_version = self.version;
+        }
+    }
+
+    /// Switches the connection to a version selected by compatible version
+    /// negotiation, deriving Initial keys again based on the new version.
+    fn switch_to_compatible_version(
+        &mut self, version: u32, initial_dcid: &[u8],
+    ) -> Result<()> {
+        trace!(
+            "{} switching from version {:x} to {:x}",
+            self.trace_id,
+            self.version,
+            version
+        );
+
+        self.version = version;
+
+        self.set_chosen_version();
+
+        let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+            initial_dcid,
+            self.version,
+            self.is_server,
+            true,
+        )?;
+
+        let initial = &mut self.crypto_ctx[packet::Epoch::Initial];
+
+        // The client keeps sending Initial packets using the original version
+        // until it receives the server's first packet, so the server needs to
+        // be able to open those until then.
+        if self.is_server {
+            self.original_initial_open = initial.crypto_open.take();
+        }
+
+        initial.crypto_open = Some(aead_open);
+        initial.crypto_seal = Some(aead_seal);
+
+        Ok(())
+    }
+
+    /// Performs compatible version negotiation on the server as per RFC 9368,
+    /// based on the version information sent by the client in its
+    /// ClientHello.
+    ///
+    /// This is done before the ClientHello is handed to TLS, so that the
+    /// server's transport parameters and handshake keys already use the
+    /// negotiated version.
+    fn negotiate_compatible_version(
+        &mut self, client_hello: &[u8],
+    ) -> Result<()> {
+        let Some(local) = &self.local_transport_params.version_information else {
+            return Ok(());
+        };
+
+        let Some(raw_params) = client_hello::extension(
+            client_hello,
+            client_hello::QUIC_TRANSPORT_PARAMETERS,
+        ) else {
+            return Ok(());
+        };
+
+        // Invalid transport parameters are reported later on, once TLS
+        // processed the ClientHello.
+        let version = match TransportParams::decode(raw_params, true, None) {
+            Ok(TransportParams {
+                version_information: Some(peer),
+                ..
+            }) if peer.chosen_version == self.version =>
+                select_compatible_version(
+                    self.version,
+                    &local.available_versions,
+                    &peer.available_versions,
+                ),
+
+            _ => None,
+        };
+
+        let version = match version {
+            Some(v) if v != self.version => v,
+
+            _ => return Ok(()),
+        };
+
+        let initial_dcid = self
+            .local_transport_params
+            .retry_source_connection_id
+            .clone()
+            .or_else(|| {
+                self.local_transport_params
+                    .original_destination_connection_id
+                    .clone()
+            })
+            .ok_or(Error::InvalidState)?;
+
+        self.switch_to_compatible_version(version, &initial_dcid)?;
+
+        self.encode_transport_params()
+    }
+
     fn encode_transport_params(&mut self) -> Result<()> {
         self.handshake.set_quic_transport_params(
             &self.local_transport_params,
@@ -7500,6 +9324,23 @@ impl<F: BufFactory> Connection<F> {
             }
         }
 
+        self.validate_peer_version_information(&peer_params)?;
+
+        if let Some(pa) = &peer_params.preferred_address {
+            self.on_peer_preferred_address(pa)?;
+        }
+
+        // Multipath can only be used if both endpoints enabled it, and with
+        // non-zero length Connection IDs.
+        if let (Some(local_max), Some(peer_max)) = (
+            self.local_transport_params.initial_max_path_id,
+            peer_params.initial_max_path_id,
+        ) {
+            if !self.ids.zero_length_scid() && !self.ids.zero_length_dcid() {
+                self.multipath.enable(local_max, peer_max);
+            }
+        }
+
         self.process_peer_transport_params(peer_params)?;
 
         self.parsed_peer_transport_params = true;
@@ -7507,6 +9348,164 @@ impl<F: BufFactory> Connection<F> {
         Ok(())
     }
 
+    /// Records the server's preferred address, so that the client can migrate
+    /// to it once the handshake is confirmed.
+    fn on_peer_preferred_address(&mut self, pa: &PreferredAddress) -> Result<()> {
+        // A server using zero-length connection IDs can't provide a preferred
+        // address.
+        if self.ids.zero_length_dcid() {
+            return Err(Error::InvalidTransportParam);
+        }
+
+        let mut retired_path_ids = SmallVec::new();
+
+        // The connection ID has sequence number 1, and can be used like the
+        // ones provided by NEW_CONNECTION_ID frames.
+        self.ids
+            .new_dcid(
+                pa.connection_id.clone(),
+                1,
+                pa.stateless_reset_token,
+                0,
+                &mut retired_path_ids,
+            )
+            .map_err(|_| Error::InvalidTransportParam)?;
+
+        // Only migrate to the preferred address of the address family currently
+        // in use.
+        self.pending_preferred_address =
+            match self.paths.get_active()?.local_addr() {
+                SocketAddr::V4(_) => pa.addr_v4.map(SocketAddr::V4),
+
+                SocketAddr::V6(_) => pa.addr_v6.map(SocketAddr::V6),
+            };
+
+        Ok(())
+    }
+
+    /// Migrates the client to the server's preferred address, if any.
+    ///
+    /// The path towards the preferred address is first validated, and then
+    /// used as the active path.
+    fn migrate_to_preferred_address(&mut self, now: Instant) -> Result<()> {
+        if self.is_server || !self.handshake_confirmed {
+            return Ok(());
+        }
+
+        if let Some(peer_addr) = self.pending_preferred_address {
+            let local_addr = self.paths.get_active()?.local_addr();
+
+            match self.probe_path(local_addr, peer_addr) {
+                Ok(_) => {
+                    trace!(
+                        "{} probing preferred address {}",
+                        self.trace_id,
+                        peer_addr
+                    );
+
+                    self.preferred_address_path_id =
+                        self.paths.path_id_from_addrs(&(local_addr, peer_addr));
+                },
+
+                // Retry later, once spare connection IDs are available.
+                Err(Error::OutOfIdentifiers) => return Ok(()),
+
+                // The preferred address can't be used.
+                Err(_) => (),
+            }
+
+            self.pending_preferred_address = None;
+        }
+
+        if let Some(pid) = self.preferred_address_path_id {
+            let (validated, failed) = match self.paths.get(pid) {
+                Ok(p) => (p.validated(), p.validation_failed()),
+
+                Err(_) => (false, true),
+            };
+
+            if validated {
+                trace!("{} migrating to preferred address", self.trace_id);
+
+                self.preferred_address_path_id = None;
+
+                self.set_active_path(pid, now)?;
+            } else if failed {
+                // Keep using the original server address.
+                self.preferred_address_path_id = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the peer's version_information transport parameter against
+    /// the outcome of compatible version negotiation, as per RFC 9368.
+    fn validate_peer_version_information(
+        &mut self, peer_params: &TransportParams,
+    ) -> Result<()> {
+        // Compatible version negotiation is disabled, so there is nothing to
+        // validate.
+        if self.local_transport_params.version_information.is_none() {
+            return Ok(());
+        }
+
+        match &peer_params.version_information {
+            // The client's chosen version must match the version of the
+            // Initial packets it sent.
+            Some(peer) if self.is_server =>
+                if peer.chosen_version != self.original_version {
+                    return Err(Error::VersionNegotiation);
+                },
+
+            // The server's chosen version must match the version the
+            // connection switched to.
+            Some(peer) =>
+                if peer.chosen_version != self.version {
+                    return Err(Error::VersionNegotiation);
+                },
+
+            // The version can't have changed without the peer taking part in
+            // the negotiation.
+            None =>
+                if self.version != self.original_version {
+                    return Err(Error::VersionNegotiation);
+                },
+        }
+
+        qlog_with_type!(QLOG_VERSION_INFO, self.qlog, q, {
+            let versions = |vi: Option<&VersionInformation>| {
+                vi.map(|vi| {
+                    vi.available_versions
+                        .iter()
+                        .map(|v| format!("{v:08x}"))
+                        .collect()
+                })
+            };
+
+            let local = self.local_transport_params.version_information.as_ref();
+            let peer = peer_params.version_information.as_ref();
+
+            let (client, server) = if self.is_server {
+                (peer, local)
+            } else {
+                (local, peer)
+            };
+
+            let ev_data = EventData::VersionInformation(
+                qlog::events::quic::VersionInformation {
+                    server_versions: versions(server),
+                    client_versions: versions(client),
+                    chosen_version: Some(format!("{:08x}", self.version)),
+                },
+            );
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        Ok(())
+    }
+
     fn process_peer_transport_params(
         &mut self, peer_params: TransportParams,
     ) -> Result<()> {
@@ -7557,10 +9556,56 @@ impl<F: BufFactory> Connection<F> {
         Ok(())
     }
 
+    /// Processes the buffered ClientHello once it was fully received, and
+    /// passes it on to TLS.
+    ///
+    /// Early data is rejected if the ClientHello was already seen.
+    fn process_client_hello(&mut self, now: Instant) -> Result<()> {
+        // Anything that isn't a ClientHello is passed on as soon as the
+        // message header is received, for TLS to fail on.
+        let complete = match &self.client_hello {
+            Some(buf) =>
+                buf.len() >= 4 &&
+                    client_hello::len(buf).is_none_or(|len| buf.len() >= len),
+
+            None => false,
+        };
+
+        if !complete {
+            return Ok(());
+        }
+
+        let Some(client_hello) = self.client_hello.take() else {
+            return Ok(());
+        };
+
+        self.negotiate_compatible_version(&client_hello)?;
+
+        if let Some(anti_replay) = &self.anti_replay {
+            if let Some(random) =
+                anti_replay::early_data_client_random(&client_hello)
+            {
+                if !anti_replay.check(random, now) {
+                    trace!("{} rejecting replayed early data", self.trace_id);
+
+                    self.handshake.set_early_data_enabled(false);
+                }
+            }
+        }
+
+        self.handshake
+            .provide_data(crypto::Level::Initial, &client_hello)
+    }
+
     /// Continues the handshake.
     ///
     /// If the connection is already established, it does nothing.
     fn do_handshake(&mut self, now: Instant) -> Result<()> {
+        // There is no TLS state to drive after restoring from a snapshot.
+        if self.restored {
+            return Ok(());
+        }
+
         let mut ex_data = tls::ExData {
             application_protos: &self.application_protos,
 
@@ -7574,9 +9619,13 @@ impl<F: BufFactory> Connection<F> {
 
             trace_id: &self.trace_id,
 
+            version: self.version,
+
+            original_version: self.original_version,
+
             local_transport_params: self.local_transport_params.clone(),
 
-            recovery_config: self.recovery_config,
+            recovery_config: self.recovery_config.clone(),
 
             tx_cap_factor: self.tx_cap_factor,
 
@@ -7711,6 +9760,7 @@ impl<F: BufFactory> Connection<F> {
             .is_some_and(|conn_err| !conn_err.is_app)
         {
             let epoch = match self.handshake.write_level() {
+                _ if self.restored => packet::Epoch::Application,
                 crypto::Level::Initial => packet::Epoch::Initial,
                 crypto::Level::ZeroRTT => unreachable!(),
                 crypto::Level::Handshake => packet::Epoch::Handshake,
@@ -7785,6 +9835,8 @@ impl<F: BufFactory> Connection<F> {
                 self.streams.has_stopped() ||
                 self.ids.has_new_scids() ||
                 self.ids.has_retire_dcids() ||
+                self.ack_freq.should_send() ||
+                self.multipath.has_pending_frames() ||
                 send_path
                     .pmtud
                     .as_ref()
@@ -7830,86 +9882,86 @@ impl<F: BufFactory> Connection<F> {
             frame::Frame::Ping { .. } => (),
 
             frame::Frame::ACK {
-                ranges, ack_delay, ..
+                ranges,
+                ack_delay,
+                ecn_counts,
+            } => self.process_ack_frame(
+                0, ranges, ack_delay, ecn_counts, epoch, now,
+            )?,
+
+            frame::Frame::ResetStream {
+                stream_id,
+                error_code,
+                final_size,
             } => {
-                let ack_delay = ack_delay
-                    .checked_mul(2_u64.pow(
-                        self.peer_transport_params.ack_delay_exponent as u32,
-                    ))
-                    .ok_or(Error::InvalidFrame)?;
-
-                if epoch == packet::Epoch::Handshake ||
-                    (epoch == packet::Epoch::Application &&
-                        self.is_established())
+                // Peer can't send on our unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    stream::is_local(stream_id, self.is_server)
                 {
-                    self.peer_verified_initial_address = true;
+                    return Err(Error::InvalidStreamState(stream_id));
                 }
 
-                let handshake_status = self.handshake_status();
+                let max_rx_data_left = self.max_rx_data() - self.rx_data;
 
-                let is_app_limited = self.delivery_rate_check_if_app_limited();
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                //
+                // This can happen if e.g. an ACK frame is lost, and the peer
+                // retransmits another frame before it realizes that the stream
+                // is gone.
+                //
+                // Note that it makes it impossible to check if the frame is
+                // illegal, since we have no state, but since we ignore the
+                // frame, it should be fine.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
 
-                let largest_acked = ranges.last().expect(
-                    "ACK frames should always have at least one ack range",
-                );
+                    Err(Error::Done) => return Ok(()),
 
-                for (_, p) in self.paths.iter_mut() {
-                    if self.pkt_num_spaces[epoch]
-                        .largest_tx_pkt_num
-                        .is_some_and(|largest_sent| largest_sent < largest_acked)
-                    {
-                        // https://www.rfc-editor.org/rfc/rfc9000#section-13.1
-                        // An endpoint SHOULD treat receipt of an acknowledgment
-                        // for a packet it did not send as
-                        // a connection error of type PROTOCOL_VIOLATION
-                        return Err(Error::InvalidAckRange);
-                    }
+                    Err(e) => return Err(e),
+                };
 
-                    if is_app_limited {
-                        p.recovery.delivery_rate_update_app_limited(true);
-                    }
+                let was_readable = stream.is_readable();
+                let priority_key = Arc::clone(&stream.priority_key);
 
-                    let OnAckReceivedOutcome {
-                        lost_packets,
-                        lost_bytes,
-                        acked_bytes,
-                        spurious_losses,
-                    } = p.recovery.on_ack_received(
-                        &ranges,
-                        ack_delay,
-                        epoch,
-                        handshake_status,
-                        now,
-                        self.pkt_num_manager.skip_pn(),
-                        &self.trace_id,
-                    )?;
+                let stream::RecvBufResetReturn {
+                    max_data_delta,
+                    consumed_flowcontrol,
+                } = stream.recv.reset(error_code, final_size)?;
 
-                    let skip_pn = self.pkt_num_manager.skip_pn();
-                    let largest_acked =
-                        p.recovery.get_largest_acked_on_epoch(epoch);
+                if max_data_delta > max_rx_data_left {
+                    return Err(Error::FlowControl);
+                }
 
-                    // Consider the skip_pn validated if the peer has sent an ack
-                    // for a larger pkt number.
-                    if let Some((largest_acked, skip_pn)) =
-                        largest_acked.zip(skip_pn)
-                    {
-                        if largest_acked > skip_pn {
-                            self.pkt_num_manager.set_skip_pn(None);
-                        }
-                    }
+                if !was_readable && stream.is_readable() {
+                    self.streams.insert_readable(&priority_key);
+                }
 
-                    self.lost_count += lost_packets;
-                    self.lost_bytes += lost_bytes as u64;
-                    self.acked_bytes += acked_bytes as u64;
-                    self.spurious_lost_count += spurious_losses;
+                self.rx_data += max_data_delta;
+                // We dropped the receive buffer, return connection level
+                // flow-control
+                self.flow_control.add_consumed(consumed_flowcontrol);
+
+                // ... and check if need to send an updated MAX_DATA frame
+                if self.should_update_max_data() {
+                    self.almost_full = true;
                 }
+
+                self.reset_stream_remote_count =
+                    self.reset_stream_remote_count.saturating_add(1);
             },
 
-            frame::Frame::ResetStream {
+            frame::Frame::ResetStreamAt {
                 stream_id,
                 error_code,
                 final_size,
+                reliable_size,
             } => {
+                // The frame can only be sent if we enabled the extension.
+                if !self.local_transport_params.reset_stream_at {
+                    return Err(Error::InvalidFrame);
+                }
+
                 // Peer can't send on our unidirectional streams.
                 if !stream::is_bidi(stream_id) &&
                     stream::is_local(stream_id, self.is_server)
@@ -7921,14 +9973,6 @@ impl<F: BufFactory> Connection<F> {
 
                 // Get existing stream or create a new one, but if the stream
                 // has already been closed and collected, ignore the frame.
-                //
-                // This can happen if e.g. an ACK frame is lost, and the peer
-                // retransmits another frame before it realizes that the stream
-                // is gone.
-                //
-                // Note that it makes it impossible to check if the frame is
-                // illegal, since we have no state, but since we ignore the
-                // frame, it should be fine.
                 let stream = match self.get_or_create_stream(stream_id, false) {
                     Ok(v) => v,
 
@@ -7943,7 +9987,11 @@ impl<F: BufFactory> Connection<F> {
                 let stream::RecvBufResetReturn {
                     max_data_delta,
                     consumed_flowcontrol,
-                } = stream.recv.reset(error_code, final_size)?;
+                } = stream.recv.reset_at(
+                    error_code,
+                    final_size,
+                    reliable_size,
+                )?;
 
                 if max_data_delta > max_rx_data_left {
                     return Err(Error::FlowControl);
@@ -7954,8 +10002,8 @@ impl<F: BufFactory> Connection<F> {
                 }
 
                 self.rx_data += max_data_delta;
-                // We dropped the receive buffer, return connection level
-                // flow-control
+                // Data past the reliable size was dropped, return connection
+                // level flow-control
                 self.flow_control.add_consumed(consumed_flowcontrol);
 
                 // ... and check if need to send an updated MAX_DATA frame
@@ -8051,6 +10099,12 @@ impl<F: BufFactory> Connection<F> {
                     return Err(Error::CryptoBufferExceeded);
                 }
 
+                // Post-handshake messages can't be processed without the TLS
+                // state, so just ignore them.
+                if self.restored {
+                    return Ok(());
+                }
+
                 // Push the data to the stream so it can be re-ordered.
                 self.crypto_ctx[epoch].crypto_stream.recv.write(data)?;
 
@@ -8064,9 +10118,19 @@ impl<F: BufFactory> Connection<F> {
 
                 while let Ok((read, _)) = stream.recv.emit(&mut crypto_buf) {
                     let recv_buf = &crypto_buf[..read];
+
+                    // Hold on to the ClientHello until it can be checked for
+                    // replays.
+                    if let Some(client_hello) = self.client_hello.as_mut() {
+                        client_hello.extend_from_slice(recv_buf);
+                        continue;
+                    }
+
                     self.handshake.provide_data(level, recv_buf)?;
                 }
 
+                self.process_client_hello(now)?;
+
                 self.do_handshake(now)?;
             },
 
@@ -8121,6 +10185,10 @@ impl<F: BufFactory> Connection<F> {
 
                 stream.recv.write(data)?;
 
+                if hdr.ty == Type::ZeroRTT {
+                    stream.early_data = true;
+                }
+
                 if !was_readable && stream.is_readable() {
                     self.streams.insert_readable(&priority_key);
                 }
@@ -8353,6 +10421,43 @@ impl<F: BufFactory> Connection<F> {
                 self.drop_epoch_state(packet::Epoch::Handshake, now);
             },
 
+            frame::Frame::AckFrequency {
+                seq_num,
+                ack_eliciting_threshold,
+                request_max_ack_delay,
+                reordering_threshold,
+            } => {
+                // The peer can't send ACK_FREQUENCY frames unless we advertised
+                // support for them, and it can't request a delay lower than
+                // the one we advertised.
+                match self.local_transport_params.min_ack_delay {
+                    Some(min_ack_delay)
+                        if request_max_ack_delay >= min_ack_delay =>
+                        (),
+
+                    _ => return Err(Error::InvalidState),
+                }
+
+                self.ack_freq.on_ack_frequency(
+                    seq_num,
+                    ack_freq::AckFrequencyParams {
+                        ack_eliciting_threshold,
+                        max_ack_delay: Duration::from_micros(
+                            request_max_ack_delay,
+                        ),
+                        reordering_threshold,
+                    },
+                );
+            },
+
+            frame::Frame::ImmediateAck => {
+                if self.local_transport_params.min_ack_delay.is_none() {
+                    return Err(Error::InvalidState);
+                }
+
+                self.ack_freq.on_immediate_ack();
+            },
+
             frame::Frame::Datagram { data } => {
                 // Close the connection if DATAGRAMs are not enabled.
                 // quiche always advertises support for 64K sized DATAGRAM
@@ -8365,6 +10470,8 @@ impl<F: BufFactory> Connection<F> {
                 // If recv queue is full, discard oldest
                 if self.dgram_recv_queue.is_full() {
                     self.dgram_recv_queue.pop();
+                    self.dgram_recv_dropped_count =
+                        self.dgram_recv_dropped_count.saturating_add(1);
                 }
 
                 self.dgram_recv_queue.push(data)?;
@@ -8376,6 +10483,379 @@ impl<F: BufFactory> Connection<F> {
             },
 
             frame::Frame::DatagramHeader { .. } => unreachable!(),
+
+            frame::Frame::PathAck {
+                path_id,
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                self.multipath.check_frame(path_id)?;
+
+                self.process_ack_frame(
+                    path_id, ranges, ack_delay, ecn_counts, epoch, now,
+                )?;
+            },
+
+            frame::Frame::PathAbandon {
+                path_id,
+                error_code,
+            } => {
+                trace!(
+                    "{} path ID {} abandoned by peer: error_code={}",
+                    self.trace_id,
+                    path_id,
+                    error_code
+                );
+
+                if self.multipath.on_abandon_received(path_id)? {
+                    self.on_multipath_id_abandoned(path_id, now)?;
+                }
+            },
+
+            frame::Frame::PathStatusBackup { path_id, seq_num } => {
+                self.multipath.on_status_received(
+                    path_id,
+                    seq_num,
+                    PathStatus::Backup,
+                )?;
+            },
+
+            frame::Frame::PathStatusAvailable { path_id, seq_num } => {
+                self.multipath.on_status_received(
+                    path_id,
+                    seq_num,
+                    PathStatus::Available,
+                )?;
+            },
+
+            frame::Frame::PathNewConnectionId {
+                path_id,
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                let mut retired_path_ids = SmallVec::new();
+
+                // Retire pending path IDs before propagating the error code to
+                // make sure retired connection IDs are not in use anymore.
+                let new_dcid_res = self.multipath.new_dcid(
+                    path_id,
+                    conn_id.into(),
+                    seq_num,
+                    u128::from_be_bytes(reset_token),
+                    retire_prior_to,
+                    self.local_transport_params.active_conn_id_limit,
+                    &mut retired_path_ids,
+                );
+
+                for (dcid_seq, pid) in retired_path_ids {
+                    let path = self.paths.get_mut(pid)?;
+
+                    // Maybe the path already switched to another DCID.
+                    if path.active_dcid_seq == Some(dcid_seq) {
+                        path.active_dcid_seq = None;
+                    }
+                }
+
+                // Paths without a DCID can be used again.
+                for (pid, p) in self.paths.iter_mut() {
+                    if p.multipath_id == path_id &&
+                        p.active_dcid_seq.is_none() &&
+                        !p.abandoned()
+                    {
+                        p.active_dcid_seq = self
+                            .multipath
+                            .link_lowest_available_dcid(path_id, pid);
+                    }
+                }
+
+                // Propagate error (if any) now...
+                new_dcid_res?;
+            },
+
+            frame::Frame::PathRetireConnectionId { path_id, seq_num } => {
+                if let Some(pid) =
+                    self.multipath.retire_scid(path_id, seq_num, &hdr.dcid)?
+                {
+                    let path = self.paths.get_mut(pid)?;
+
+                    // Maybe we already linked a new SCID to that path.
+                    if path.active_scid_seq == Some(seq_num) {
+                        path.active_scid_seq = None;
+                    }
+                }
+            },
+
+            frame::Frame::MaxPathId { max } => {
+                self.multipath.on_max_path_id(max)?;
+            },
+
+            frame::Frame::PathsBlocked { max } => {
+                self.multipath.check_frame(0)?;
+
+                trace!("{} peer blocked on path IDs: max={}", self.trace_id, max);
+            },
+
+            frame::Frame::PathCidsBlocked {
+                path_id,
+                next_seq_num,
+            } => {
+                self.multipath.check_frame(path_id)?;
+
+                trace!(
+                    "{} peer blocked on path ID {} CIDs: next_seq_num={}",
+                    self.trace_id,
+                    path_id,
+                    next_seq_num
+                );
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Stops using the path with the multipath path identifier `mp_id`, after
+    /// either endpoint abandoned it.
+    fn on_multipath_id_abandoned(
+        &mut self, mp_id: u64, now: Instant,
+    ) -> Result<()> {
+        let pid = match self
+            .paths
+            .iter()
+            .find(|(_, p)| p.multipath_id == mp_id && !p.abandoned())
+        {
+            Some((pid, _)) => pid,
+
+            None => return Ok(()),
+        };
+
+        let handshake_status = self.handshake_status();
+
+        let path = self.paths.get_mut(pid)?;
+        let was_active = path.active();
+
+        path.abandon();
+        path.active_dcid_seq = None;
+
+        // Packets sent on the path will never be acknowledged, so their
+        // frames need to be retransmitted on other paths.
+        path.recovery.on_path_abandoned(
+            packet::Epoch::Application,
+            handshake_status,
+            now,
+        );
+
+        let local_addr = path.local_addr();
+        let peer_addr = path.peer_addr();
+
+        self.paths
+            .notify_event(PathEvent::Closed(local_addr, peer_addr));
+
+        if was_active {
+            if let Some(new_pid) = self.paths.find_candidate_path() {
+                self.paths.set_active_path(new_pid)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Processes an ACK or PATH_ACK frame acknowledging packets in the
+    /// packet number space of the multipath path identifier `mp_id`.
+    fn process_ack_frame(
+        &mut self, mp_id: u64, ranges: ranges::RangeSet, ack_delay: u64,
+        ecn_counts: Option<frame::EcnCounts>, epoch: packet::Epoch, now: Instant,
+    ) -> Result<()> {
+        let ack_delay = ack_delay
+            .checked_mul(
+                2_u64.pow(self.peer_transport_params.ack_delay_exponent as u32),
+            )
+            .ok_or(Error::InvalidFrame)?;
+
+        if epoch == packet::Epoch::Handshake ||
+            (epoch == packet::Epoch::Application && self.is_established())
+        {
+            self.peer_verified_initial_address = true;
+        }
+
+        let handshake_status = self.handshake_status();
+
+        let is_app_limited = self.delivery_rate_check_if_app_limited();
+
+        let largest_acked = ranges
+            .last()
+            .expect("ACK frames should always have at least one ack range");
+
+        let largest_tx_pkt_num = if mp_id == 0 {
+            self.pkt_num_spaces[epoch].largest_tx_pkt_num
+        } else {
+            self.multipath.pkt_num_space(mp_id)?.largest_tx_pkt_num
+        };
+
+        // https://www.rfc-editor.org/rfc/rfc9000#section-13.1
+        // An endpoint SHOULD treat receipt of an acknowledgment for a packet it
+        // did not send as a connection error of type PROTOCOL_VIOLATION
+        if largest_tx_pkt_num
+            .is_some_and(|largest_sent| largest_sent < largest_acked)
+        {
+            return Err(Error::InvalidAckRange);
+        }
+
+        // Packet numbers are only skipped in the packet number space of path
+        // identifier 0.
+        let skip_pn = if mp_id == 0 {
+            self.pkt_num_manager.skip_pn()
+        } else {
+            None
+        };
+
+        // The number of newly acknowledged ECN-marked packets on each path.
+        let mut ecn_marked_acked: SmallVec<[(usize, usize); 1]> = SmallVec::new();
+        let mut largest_acked_increased = false;
+
+        for (pid, p) in self.paths.iter_mut() {
+            // Each path only tracks packets sent in the packet number space of
+            // its own path identifier.
+            if p.multipath_id != mp_id {
+                continue;
+            }
+
+            if is_app_limited {
+                p.recovery.delivery_rate_update_app_limited(true);
+            }
+
+            let prev_largest_acked = p.recovery.get_largest_acked_on_epoch(epoch);
+            let prev_ecn_marked_acked = p.recovery.ecn_marked_acked();
+
+            let OnAckReceivedOutcome {
+                lost_packets,
+                lost_bytes,
+                acked_bytes,
+                spurious_losses,
+            } = p.recovery.on_ack_received(
+                &ranges,
+                ack_delay,
+                epoch,
+                handshake_status,
+                now,
+                skip_pn,
+                &self.trace_id,
+            )?;
+
+            let largest_acked = p.recovery.get_largest_acked_on_epoch(epoch);
+
+            largest_acked_increased |= largest_acked > prev_largest_acked;
+
+            let newly_ecn_marked_acked =
+                p.recovery.ecn_marked_acked() - prev_ecn_marked_acked;
+
+            if newly_ecn_marked_acked > 0 {
+                ecn_marked_acked.push((pid, newly_ecn_marked_acked));
+            }
+
+            p.on_ecn_marked_packets_lost();
+
+            // Consider the skip_pn validated if the peer has sent an ack for a
+            // larger pkt number.
+            if let Some((largest_acked, skip_pn)) = largest_acked.zip(skip_pn) {
+                if largest_acked > skip_pn {
+                    self.pkt_num_manager.set_skip_pn(None);
+                }
+            }
+
+            self.lost_count += lost_packets;
+            self.lost_bytes += lost_bytes as u64;
+            self.acked_bytes += acked_bytes as u64;
+            self.spurious_lost_count += spurious_losses;
+        }
+
+        // A packet protected with the current keys was acknowledged, so a new
+        // key update can be initiated.
+        if mp_id == 0 &&
+            epoch == packet::Epoch::Application &&
+            self.key_update_first_pn
+                .is_some_and(|first_pn| largest_acked >= first_pn)
+        {
+            self.key_update_first_pn = None;
+        }
+
+        // Reordered ACK frames could carry stale ECN counts, so only the ones
+        // acknowledging new packets are processed.
+        //
+        // https://www.rfc-editor.org/rfc/rfc9000#section-13.4.2.1
+        if largest_acked_increased {
+            self.process_ecn_counts(
+                mp_id,
+                epoch,
+                ecn_counts,
+                &ecn_marked_acked,
+                now,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Validates the ECN counts reported by the peer in an ACK frame, and
+    /// reacts to new ECN-CE marks.
+    ///
+    /// `ecn_marked_acked` contains the number of ECN-marked packets newly
+    /// acknowledged by the frame on each path.
+    fn process_ecn_counts(
+        &mut self, mp_id: u64, epoch: packet::Epoch,
+        ecn_counts: Option<frame::EcnCounts>,
+        ecn_marked_acked: &[(usize, usize)], now: Instant,
+    ) -> Result<()> {
+        let newly_acked_marked = ecn_marked_acked.iter().map(|(_, n)| n).sum();
+
+        // All paths use the same congestion control algorithm, so they mark
+        // packets with the same codepoint.
+        let ect = if self.recovery_config.l4s() {
+            Ecn::Ect1
+        } else {
+            Ecn::Ect0
+        };
+
+        let pkt_space = if mp_id == 0 {
+            &mut self.pkt_num_spaces[epoch]
+        } else {
+            self.multipath.pkt_num_space_mut(mp_id)?
+        };
+
+        let ce_count = ecn::validate_counts(
+            &pkt_space.peer_ecn_counts,
+            ecn_counts.as_ref(),
+            newly_acked_marked,
+            ect,
+        );
+
+        if let Some(ecn_counts) = ecn_counts {
+            pkt_space.peer_ecn_counts = ecn_counts;
+        }
+
+        for (pid, _) in ecn_marked_acked {
+            let p = self.paths.get_mut(*pid)?;
+
+            match ce_count {
+                Some(ce_count) => {
+                    p.ecn.on_validated();
+
+                    if ce_count > 0 {
+                        trace!(
+                            "{} peer reported {} ECN-CE marks",
+                            self.trace_id,
+                            ce_count
+                        );
+
+                        p.ecn.on_ce_reported(ce_count);
+                        p.recovery.on_ecn_ce(epoch, ce_count, now);
+                    }
+                },
+
+                None => p.ecn.on_validation_failed(),
+            }
         }
 
         Ok(())
@@ -8390,6 +10870,10 @@ impl<F: BufFactory> Connection<F> {
         crypto_ctx.clear();
         self.pkt_num_spaces[epoch].clear();
 
+        if epoch == packet::Epoch::Initial {
+            self.original_initial_open = None;
+        }
+
         let handshake_status = self.handshake_status();
         for (_, p) in self.paths.iter_mut() {
             p.recovery
@@ -8543,6 +11027,20 @@ impl<F: BufFactory> Connection<F> {
         &mut self, recv_pid: Option<usize>, dcid: &ConnectionId, buf_len: usize,
         info: &RecvInfo,
     ) -> Result<usize> {
+        if let Some((mp_id, scid_seq, _)) = self.multipath.find_scid(dcid) {
+            return self.get_or_create_recv_multipath_id(
+                recv_pid, mp_id, scid_seq, buf_len, info,
+            );
+        }
+
+        // Packets of the path identifier 0 might be received on the 4-tuple of
+        // another multipath path, which keeps using its own CIDs.
+        if let Some(recv_pid) = recv_pid {
+            if self.paths.get(recv_pid)?.multipath_id != 0 {
+                return Ok(recv_pid);
+            }
+        }
+
         let ids = &mut self.ids;
 
         let (in_scid_seq, mut in_scid_pid) =
@@ -8648,9 +11146,70 @@ impl<F: BufFactory> Connection<F> {
         Ok(pid)
     }
 
+    /// Selects the path of a packet received with a Source Connection ID
+    /// issued for the multipath path identifier `mp_id`.
+    fn get_or_create_recv_multipath_id(
+        &mut self, recv_pid: Option<usize>, mp_id: u64, scid_seq: u64,
+        buf_len: usize, info: &RecvInfo,
+    ) -> Result<usize> {
+        if let Some(recv_pid) = recv_pid {
+            let recv_path = self.paths.get_mut(recv_pid)?;
+
+            // If the path observes a change of SCID used, note it.
+            if recv_path.multipath_id == mp_id &&
+                recv_path.active_scid_seq != Some(scid_seq)
+            {
+                trace!(
+                    "{} path ID {} now see SCID with seq num {} of path {}",
+                    self.trace_id,
+                    recv_pid,
+                    scid_seq,
+                    mp_id
+                );
+
+                recv_path.active_scid_seq = Some(scid_seq);
+                self.multipath.link_scid(mp_id, scid_seq, recv_pid)?;
+            }
+
+            return Ok(recv_pid);
+        }
+
+        // Abandoned paths can't be used anymore.
+        if self.multipath.is_abandoned(mp_id) {
+            return Err(Error::Done);
+        }
+
+        // This is a new 4-tuple opened by the peer with a new path
+        // identifier.
+        let mut path = path::Path::new(
+            info.to,
+            info.from,
+            &self.recovery_config,
+            self.path_challenge_recv_max_queue_len,
+            false,
+            None,
+        );
+
+        path.max_send_bytes = buf_len * self.max_amplification_factor;
+        path.multipath_id = mp_id;
+        path.active_scid_seq = Some(scid_seq);
+
+        // Automatically probes the new path.
+        path.request_validation();
+
+        let pid = self.paths.insert_path(path, self.is_server)?;
+
+        self.multipath.link_scid(mp_id, scid_seq, pid)?;
+
+        self.paths.get_mut(pid)?.active_dcid_seq =
+            self.multipath.link_lowest_available_dcid(mp_id, pid);
+
+        Ok(pid)
+    }
+
     /// Selects the path on which the next packet must be sent.
     fn get_send_path_id(
-        &self, from: Option<SocketAddr>, to: Option<SocketAddr>,
+        &mut self, from: Option<SocketAddr>, to: Option<SocketAddr>,
     ) -> Result<usize> {
         // A probing packet must be sent, but only if the connection is fully
         // established.
@@ -8669,6 +11228,44 @@ impl<F: BufFactory> Connection<F> {
             }
         }
 
+        // With multipath, any usable path can carry application data, so let
+        // the path scheduler pick one.
+        if self.multipath.enabled() && self.handshake_confirmed {
+            let mut pids: SmallVec<[usize; 4]> = SmallVec::new();
+            let mut candidates: SmallVec<[PathCandidate; 4]> = SmallVec::new();
+
+            for (pid, p) in self.paths.iter() {
+                if (from.is_some() && Some(p.local_addr()) != from) ||
+                    (to.is_some() && Some(p.peer_addr()) != to) ||
+                    p.active_dcid_seq.is_none() ||
+                    !(p.active() || (p.multipath_id != 0 && p.usable()))
+                {
+                    continue;
+                }
+
+                // PTO probes must be sent on the path that timed out.
+                if p.recovery.loss_probes(packet::Epoch::Application) > 0 {
+                    return Ok(pid);
+                }
+
+                pids.push(pid);
+                candidates.push(PathCandidate {
+                    path_id: p.multipath_id,
+                    local_addr: p.local_addr(),
+                    peer_addr: p.peer_addr(),
+                    rtt: p.recovery.rtt(),
+                    cwnd_available: p.recovery.cwnd_available(),
+                    backup: self.multipath.is_backup(p.multipath_id),
+                });
+            }
+
+            if candidates.len() > 1 {
+                if let Some(i) = self.multipath.select_path(&candidates) {
+                    return pids.get(i).copied().ok_or(Error::InvalidState);
+                }
+            }
+        }
+
         if let Some((pid, p)) = self.paths.get_active_with_pid() {
             if from.is_some() && Some(p.local_addr()) != from {
                 return Err(Error::Done);
@@ -8733,6 +11330,12 @@ impl<F: BufFactory> Connection<F> {
             return Err(Error::InvalidState);
         }
 
+        // With multipath, new paths use their own path identifier, for which
+        // both endpoints need to have issued Connection IDs.
+        if self.multipath.enabled() {
+            return self.create_multipath_on_client(local_addr, peer_addr);
+        }
+
         // If we use zero-length SCID and go over our local active CID limit,
         // the `insert_path()` call will raise an error.
         if !self.ids.zero_length_scid() && self.ids.available_scids() == 0 {
@@ -8769,6 +11372,41 @@ impl<F: BufFactory> Connection<F> {
         Ok(pid)
     }
 
+    /// Creates a new client-side path using a new multipath path identifier.
+    fn create_multipath_on_client(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<usize> {
+        let mp_id = self.multipath.next_client_path_id()?;
+
+        let scid_seq = self
+            .multipath
+            .lowest_available_scid_seq(mp_id)
+            .ok_or(Error::OutOfIdentifiers)?;
+
+        let mut path = path::Path::new(
+            local_addr,
+            peer_addr,
+            &self.recovery_config,
+            self.path_challenge_recv_max_queue_len,
+            false,
+            None,
+        );
+        path.multipath_id = mp_id;
+        path.active_scid_seq = Some(scid_seq);
+
+        let pid = self
+            .paths
+            .insert_path(path, false)
+            .map_err(|_| Error::OutOfIdentifiers)?;
+
+        self.multipath.link_scid(mp_id, scid_seq, pid)?;
+
+        self.paths.get_mut(pid)?.active_dcid_seq =
+            self.multipath.link_lowest_available_dcid(mp_id, pid);
+
+        Ok(pid)
+    }
+
     // Marks the connection as closed and does any related tidyup.
     fn mark_closed(&mut self) {
         #[cfg(feature = "qlog")]
@@ -8958,6 +11596,18 @@ pub struct Stats {
     /// The number of DATAGRAM frames sent.
     pub dgram_sent: usize,
 
+    /// The number of outgoing DATAGRAM frames dropped because their expiry
+    /// passed before they could be sent.
+    pub dgram_expired: usize,
+
+    /// The number of outgoing DATAGRAM frames dropped because they were too
+    /// large to ever fit in a packet.
+    pub dgram_send_dropped: usize,
+
+    /// The number of received DATAGRAM frames dropped because the receive
+    /// queue was full.
+    pub dgram_recv_dropped: usize,
+
     /// The number of known paths for the connection.
     pub paths_count: usize,
 
@@ -8996,6 +11646,19 @@ pub struct Stats {
 
     /// Health state of the connection's tx_buffered.
     pub tx_buffered_state: TxBufferTrackingState,
+
+    /// The number of 1-RTT key updates, whether initiated locally or by the
+    /// peer.
+    pub key_update_count: u64,
+
+    /// The number of received QUIC packets marked with ECT(0).
+    pub ecn_ect0_recv_count: u64,
+
+    /// The number of received QUIC packets marked with ECT(1).
+    pub ecn_ect1_recv_count: u64,
+
+    /// The number of received QUIC packets marked with ECN-CE.
+    pub ecn_ce_recv_count: u64,
 }
 
 impl std::fmt::Debug for Stats {
@@ -9017,32 +11680,63 @@ impl std::fmt::Debug for Stats {
     }
 }
 
+#[cfg(any(test, feature = "netsim"))]
+#[doc(hidden)]
+pub mod netsim;
+
 #[doc(hidden)]
 pub mod test_utils;
 
 #[cfg(test)]
 mod tests;
 
+pub use crate::anti_replay::AntiReplay;
+pub use crate::anti_replay::BloomAntiReplay;
+
+pub use crate::clock::Clock;
+pub use crate::clock::ManualClock;
+pub use crate::clock::SystemClock;
+
+pub use crate::dgram::DatagramEvent;
+
+pub use crate::ecn::Ecn;
+
 pub use crate::packet::ConnectionId;
 pub use crate::packet::Header;
 pub use crate::packet::Type;
 
+pub use crate::multipath::MinRttScheduler;
+pub use crate::multipath::PathCandidate;
+pub use crate::multipath::PathScheduler;
+pub use crate::multipath::PathStatus;
+pub use crate::multipath::RoundRobinScheduler;
+
 pub use crate::path::PathEvent;
 pub use crate::path::PathStats;
 pub use crate::path::SocketAddrIter;
 
+pub use crate::recovery::AckedPacket;
 pub use crate::recovery::BbrBwLoReductionStrategy;
 pub use crate::recovery::BbrParams;
 pub use crate::recovery::CongestionControlAlgorithm;
+pub use crate::recovery::CongestionController;
+pub use crate::recovery::CongestionControllerParams;
+pub use crate::recovery::LostPacket;
+pub use crate::recovery::RttEstimate;
 pub use crate::recovery::StartupExit;
 pub use crate::recovery::StartupExitReason;
 
+pub use crate::stream::DeficitRoundRobinScheduler;
+pub use crate::stream::StreamCandidate;
 pub use crate::stream::StreamIter;
+pub use crate::stream::StreamScheduler;
 
+pub use crate::transport_params::PreferredAddress;
 pub use crate::transport_params::TransportParams;
 pub use crate::transport_params::UnknownTransportParameter;
 pub use crate::transport_params::UnknownTransportParameterIterator;
 pub use crate::transport_params::UnknownTransportParameters;
+pub use crate::transport_params::VersionInformation;
 
 pub use crate::range_buf::BufFactory;
 pub use crate::range_buf::BufSplit;
@@ -9052,9 +11746,14 @@ pub use crate::error::Error;
 pub use crate::error::Result;
 pub use crate::error::WireErrorCode;
 
+mod ack_freq;
+mod anti_replay;
 mod cid;
+mod client_hello;
+mod clock;
 mod crypto;
 mod dgram;
+mod ecn;
 mod error;
 #[cfg(feature = "ffi")]
 mod ffi;
@@ -9062,6 +11761,7 @@ mod flowcontrol;
 mod frame;
 pub mod h3;
 mod minmax;
+mod multipath;
 mod packet;
 mod path;
 mod pmtud;
@@ -9069,6 +11769,7 @@ mod rand;
 mod range_buf;
 mod ranges;
 mod recovery;
+mod snapshot;
 mod stream;
 mod tls;
 mod transport_params;
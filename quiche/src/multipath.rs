@@ -0,0 +1,1310 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Multipath extension ([draft-ietf-quic-multipath]).
+//!
+//! Once both endpoints advertised the `initial_max_path_id` transport
+//! parameter, every network path gets a path identifier. Path 0 is the path
+//! the handshake took place on, and uses the connection's regular Connection
+//! IDs and application packet number space. Other paths use Connection IDs
+//! issued for their path identifier with PATH_NEW_CONNECTION_ID frames, and
+//! have their own packet number space, acknowledged with PATH_ACK frames.
+//!
+//! Application data can then be sent on all validated paths at the same
+//! time. Which path carries the next packet is decided by a
+//! [`PathScheduler`].
+//!
+//! [draft-ietf-quic-multipath]: https://datatracker.ietf.org/doc/html/draft-ietf-quic-multipath
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+use smallvec::SmallVec;
+
+use crate::cid::ConnectionIdEntry;
+use crate::frame;
+use crate::packet;
+use crate::recovery;
+use crate::ConnectionId;
+use crate::Error;
+use crate::Result;
+
+/// The largest path identifier allowed by the extension.
+pub(crate) const MAX_PATH_ID: u64 = u32::MAX as u64;
+
+/// The error code sent in PATH_ABANDON frames replying to the peer's.
+const NO_ERROR: u64 = 0;
+
+/// The status of a path, as advertised with PATH_STATUS_AVAILABLE and
+/// PATH_STATUS_BACKUP frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathStatus {
+    /// The path can be used to send application data.
+    #[default]
+    Available,
+
+    /// The path should only be used when no available path can be used.
+    Backup,
+}
+
+/// A path on which the next packet can be sent, as given to a
+/// [`PathScheduler`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathCandidate {
+    /// The multipath path identifier.
+    pub path_id: u64,
+
+    /// The local address of the path.
+    pub local_addr: SocketAddr,
+
+    /// The peer address of the path.
+    pub peer_addr: SocketAddr,
+
+    /// The estimated round-trip time of the path.
+    pub rtt: Duration,
+
+    /// The number of bytes the congestion window of the path currently
+    /// allows to send.
+    pub cwnd_available: usize,
+
+    /// Whether the path was marked as backup by either endpoint.
+    pub backup: bool,
+}
+
+/// Decides which path carries the next packet of a multipath connection.
+///
+/// The scheduler is only called for packets that are not bound to a specific
+/// path (e.g. path probing), and only when more than one path can be used.
+///
+/// A scheduler can be set with [`set_path_scheduler()`]. The default one is
+/// [`MinRttScheduler`].
+///
+/// [`set_path_scheduler()`]: struct.Connection.html#method.set_path_scheduler
+pub trait PathScheduler: Send + Sync {
+    /// Returns the index in `candidates` of the path on which the next packet
+    /// should be sent, or `None` to send it on the active path.
+    fn select_path(&mut self, candidates: &[PathCandidate]) -> Option<usize>;
+}
+
+/// A scheduler sending packets on the path with the lowest RTT that has room
+/// in its congestion window.
+///
+/// Backup paths are only used when no available path is left.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MinRttScheduler;
+
+impl PathScheduler for MinRttScheduler {
+    fn select_path(&mut self, candidates: &[PathCandidate]) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| (c.backup, c.cwnd_available == 0, c.rtt))
+            .map(|(i, _)| i)
+    }
+}
+
+/// A scheduler sending packets on each available path in turn.
+///
+/// Paths with a full congestion window are skipped, and backup paths are only
+/// used when no available path is left.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoundRobinScheduler {
+    last_path_id: Option<u64>,
+}
+
+impl PathScheduler for RoundRobinScheduler {
+    fn select_path(&mut self, candidates: &[PathCandidate]) -> Option<usize> {
+        let best = candidates
+            .iter()
+            .map(|c| (c.backup, c.cwnd_available == 0))
+            .min()?;
+
+        let eligible = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| (c.backup, c.cwnd_available == 0) == best);
+
+        let last = self.last_path_id;
+
+        let (i, c) = eligible
+            .clone()
+            .filter(|(_, c)| last.is_none_or(|last| c.path_id > last))
+            .min_by_key(|(_, c)| c.path_id)
+            .or_else(|| eligible.min_by_key(|(_, c)| c.path_id))?;
+
+        self.last_path_id = Some(c.path_id);
+
+        Some(i)
+    }
+}
+
+/// The status of a path identifier, as set by each endpoint.
+#[derive(Default)]
+struct StatusState {
+    /// The status set locally.
+    local: PathStatus,
+
+    /// The sequence number of the last status set locally, if any.
+    local_seq: Option<u64>,
+
+    /// Whether the local status needs to be sent to the peer.
+    local_pending: bool,
+
+    /// The status set by the peer.
+    peer: PathStatus,
+
+    /// The largest sequence number of the PATH_STATUS frames received.
+    peer_seq: Option<u64>,
+}
+
+impl StatusState {
+    fn frame(&self, path_id: u64) -> Option<frame::Frame> {
+        let seq_num = self.local_seq?;
+
+        if !self.local_pending {
+            return None;
+        }
+
+        Some(match self.local {
+            PathStatus::Available =>
+                frame::Frame::PathStatusAvailable { path_id, seq_num },
+
+            PathStatus::Backup =>
+                frame::Frame::PathStatusBackup { path_id, seq_num },
+        })
+    }
+}
+
+/// The state of a path identifier other than 0.
+struct PathIdState {
+    /// The Source Connection IDs issued for this path identifier.
+    scids: VecDeque<ConnectionIdEntry>,
+
+    /// Next sequence number of the Source Connection IDs.
+    next_scid_seq: u64,
+
+    /// Source Connection IDs to be advertised to the peer.
+    advertise_scid_seqs: VecDeque<u64>,
+
+    /// The Destination Connection IDs issued by the peer.
+    dcids: VecDeque<ConnectionIdEntry>,
+
+    /// Largest "Retire Prior To" received from the peer.
+    largest_peer_retire_prior_to: u64,
+
+    /// Destination Connection IDs to be retired.
+    retire_dcid_seqs: VecDeque<u64>,
+
+    /// The packet number space of the path.
+    pkt_num_space: packet::PktNumSpace,
+
+    /// Next packet number to send on the path.
+    next_pkt_num: u64,
+
+    /// The status of the path.
+    status: StatusState,
+
+    /// Whether the path identifier has been used by a path.
+    in_use: bool,
+
+    /// The error code of the local PATH_ABANDON frame, and whether it still
+    /// needs to be sent.
+    local_abandon: Option<(u64, bool)>,
+
+    /// Whether the peer abandoned the path.
+    peer_abandoned: bool,
+}
+
+impl PathIdState {
+    fn new() -> Self {
+        PathIdState {
+            scids: VecDeque::new(),
+            next_scid_seq: 0,
+            advertise_scid_seqs: VecDeque::new(),
+            dcids: VecDeque::new(),
+            largest_peer_retire_prior_to: 0,
+            retire_dcid_seqs: VecDeque::new(),
+            pkt_num_space: packet::PktNumSpace::new(),
+            next_pkt_num: 0,
+            status: StatusState::default(),
+            in_use: false,
+            local_abandon: None,
+            peer_abandoned: false,
+        }
+    }
+
+    fn abandoned(&self) -> bool {
+        self.local_abandon.is_some() || self.peer_abandoned
+    }
+
+    fn retire_dcid(&mut self, seq: u64) -> Option<usize> {
+        let idx = self.dcids.iter().position(|e| e.seq == seq)?;
+
+        let e = self.dcids.remove(idx)?;
+
+        self.retire_dcid_seqs.push_back(seq);
+
+        e.path_id
+    }
+}
+
+/// The multipath state of a connection.
+pub(crate) struct Multipath {
+    /// Whether both endpoints negotiated the extension.
+    enabled: bool,
+
+    /// The maximum path identifier the peer can use.
+    local_max_path_id: u64,
+
+    /// Whether a MAX_PATH_ID frame needs to be sent.
+    max_path_id_pending: bool,
+
+    /// The maximum path identifier allowed by the peer.
+    peer_max_path_id: u64,
+
+    /// The maximum path identifier to report in a PATHS_BLOCKED frame.
+    paths_blocked_pending: Option<u64>,
+
+    /// The path identifier and next expected sequence number to report in a
+    /// PATH_CIDS_BLOCKED frame.
+    cids_blocked_pending: Option<(u64, u64)>,
+
+    /// The status of path 0.
+    path0_status: StatusState,
+
+    /// The state of path identifiers other than 0.
+    path_ids: BTreeMap<u64, PathIdState>,
+
+    /// Retired Source Connection IDs that should be notified to the
+    /// application.
+    retired_scids: VecDeque<ConnectionId<'static>>,
+
+    /// The path scheduler.
+    scheduler: Box<dyn PathScheduler>,
+}
+
+impl Default for Multipath {
+    fn default() -> Self {
+        Multipath {
+            enabled: false,
+            local_max_path_id: 0,
+            max_path_id_pending: false,
+            peer_max_path_id: 0,
+            paths_blocked_pending: None,
+            cids_blocked_pending: None,
+            path0_status: StatusState::default(),
+            path_ids: BTreeMap::new(),
+            retired_scids: VecDeque::new(),
+            scheduler: Box::new(MinRttScheduler),
+        }
+    }
+}
+
+impl Multipath {
+    /// Enables the extension with the maximum path identifiers advertised by
+    /// both endpoints.
+    pub fn enable(&mut self, local_max_path_id: u64, peer_max_path_id: u64) {
+        self.enabled = true;
+        self.local_max_path_id = local_max_path_id;
+        self.peer_max_path_id = peer_max_path_id;
+    }
+
+    /// Returns whether the extension was negotiated.
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Replaces the path scheduler.
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn PathScheduler>) {
+        self.scheduler = scheduler;
+    }
+
+    /// Asks the scheduler on which candidate the next packet should be sent.
+    pub fn select_path(&mut self, candidates: &[PathCandidate]) -> Option<usize> {
+        self.scheduler
+            .select_path(candidates)
+            .filter(|&i| i < candidates.len())
+    }
+
+    /// Checks that a multipath frame can be received for `path_id`.
+    pub fn check_frame(&self, path_id: u64) -> Result<()> {
+        if !self.enabled || path_id > self.local_max_path_id {
+            return Err(Error::InvalidState);
+        }
+
+        Ok(())
+    }
+
+    /// Handles a MAX_PATH_ID frame.
+    pub fn on_max_path_id(&mut self, max: u64) -> Result<()> {
+        if !self.enabled {
+            return Err(Error::InvalidState);
+        }
+
+        if max > MAX_PATH_ID {
+            return Err(Error::InvalidFrame);
+        }
+
+        self.peer_max_path_id = self.peer_max_path_id.max(max);
+
+        Ok(())
+    }
+
+    /// Adds a Source Connection ID for `path_id`, to be advertised with a
+    /// PATH_NEW_CONNECTION_ID frame, and returns its sequence number.
+    pub fn new_scid(
+        &mut self, path_id: u64, cid: ConnectionId<'static>, reset_token: u128,
+        limit: u64,
+    ) -> Result<u64> {
+        if !self.enabled || path_id == 0 || path_id > self.peer_max_path_id {
+            return Err(Error::InvalidState);
+        }
+
+        let state = self
+            .path_ids
+            .entry(path_id)
+            .or_insert_with(PathIdState::new);
+
+        if state.abandoned() {
+            return Err(Error::InvalidState);
+        }
+
+        if let Some(e) = state.scids.iter().find(|e| e.cid == cid) {
+            if e.reset_token != Some(reset_token) {
+                return Err(Error::InvalidState);
+            }
+
+            return Ok(e.seq);
+        }
+
+        if state.scids.len() as u64 >= limit {
+            return Err(Error::IdLimit);
+        }
+
+        let seq = state.next_scid_seq;
+
+        state.scids.push_back(ConnectionIdEntry {
+            cid,
+            seq,
+            reset_token: Some(reset_token),
+            path_id: None,
+        });
+        state.next_scid_seq += 1;
+        state.advertise_scid_seqs.push_back(seq);
+
+        Ok(seq)
+    }
+
+    /// Handles a PATH_NEW_CONNECTION_ID frame.
+    ///
+    /// The sequence numbers of the retired Destination Connection IDs that
+    /// were used by a path are added to `retired_path_ids`, along with the
+    /// identifier of that path.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_dcid(
+        &mut self, path_id: u64, cid: ConnectionId<'static>, seq: u64,
+        reset_token: u128, retire_prior_to: u64, limit: u64,
+        retired_path_ids: &mut SmallVec<[(u64, usize); 1]>,
+    ) -> Result<()> {
+        self.check_frame(path_id)?;
+
+        if retire_prior_to > seq {
+            return Err(Error::InvalidFrame);
+        }
+
+        let state = self
+            .path_ids
+            .entry(path_id)
+            .or_insert_with(PathIdState::new);
+
+        if state.abandoned() {
+            return Ok(());
+        }
+
+        if let Some(e) = state.dcids.iter().find(|e| e.cid == cid || e.seq == seq)
+        {
+            if e.cid != cid || e.seq != seq || e.reset_token != Some(reset_token)
+            {
+                return Err(Error::InvalidFrame);
+            }
+
+            return Ok(());
+        }
+
+        // The Connection ID was already retired.
+        if seq < state.largest_peer_retire_prior_to {
+            state.retire_dcid_seqs.push_back(seq);
+            return Ok(());
+        }
+
+        if retire_prior_to > state.largest_peer_retire_prior_to {
+            let retired = state
+                .dcids
+                .iter()
+                .filter(|e| e.seq < retire_prior_to)
+                .map(|e| e.seq)
+                .collect::<SmallVec<[u64; 2]>>();
+
+            for seq in retired {
+                if let Some(pid) = state.retire_dcid(seq) {
+                    retired_path_ids.push((seq, pid));
+                }
+            }
+
+            state.largest_peer_retire_prior_to = retire_prior_to;
+        }
+
+        if state.dcids.len() as u64 >= limit {
+            return Err(Error::IdLimit);
+        }
+
+        state.dcids.push_back(ConnectionIdEntry {
+            cid,
+            seq,
+            reset_token: Some(reset_token),
+            path_id: None,
+        });
+
+        Ok(())
+    }
+
+    /// Handles a PATH_RETIRE_CONNECTION_ID frame, and returns the identifier
+    /// of the path that used the retired Source Connection ID, if any.
+    pub fn retire_scid(
+        &mut self, path_id: u64, seq: u64, pkt_dcid: &ConnectionId,
+    ) -> Result<Option<usize>> {
+        self.check_frame(path_id)?;
+
+        let state = self.path_ids.get_mut(&path_id).ok_or(Error::InvalidState)?;
+
+        if seq >= state.next_scid_seq {
+            return Err(Error::InvalidState);
+        }
+
+        let idx = match state.scids.iter().position(|e| e.seq == seq) {
+            Some(v) => v,
+
+            // Already retired.
+            None => return Ok(None),
+        };
+
+        if state.scids[idx].cid == *pkt_dcid {
+            return Err(Error::InvalidState);
+        }
+
+        let e = state.scids.remove(idx).ok_or(Error::InvalidState)?;
+
+        state.advertise_scid_seqs.retain(|s| *s != seq);
+
+        self.retired_scids.push_back(e.cid);
+
+        Ok(e.path_id)
+    }
+
+    /// Finds the path identifier and sequence number of the provided Source
+    /// Connection ID, along with the path using it.
+    pub fn find_scid(
+        &self, scid: &ConnectionId,
+    ) -> Option<(u64, u64, Option<usize>)> {
+        self.path_ids.iter().find_map(|(&path_id, state)| {
+            state
+                .scids
+                .iter()
+                .find(|e| e.cid == *scid)
+                .map(|e| (path_id, e.seq, e.path_id))
+        })
+    }
+
+    /// Returns the Destination Connection ID of `path_id` with sequence
+    /// number `seq`.
+    pub fn get_dcid(&self, path_id: u64, seq: u64) -> Result<&ConnectionIdEntry> {
+        self.path_ids
+            .get(&path_id)
+            .and_then(|s| s.dcids.iter().find(|e| e.seq == seq))
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Links the Source Connection ID of `path_id` with sequence number `seq`
+    /// to the path `pid`.
+    pub fn link_scid(
+        &mut self, path_id: u64, seq: u64, pid: usize,
+    ) -> Result<()> {
+        let state = self.path_ids.get_mut(&path_id).ok_or(Error::InvalidState)?;
+
+        let e = state
+            .scids
+            .iter_mut()
+            .find(|e| e.seq == seq)
+            .ok_or(Error::InvalidState)?;
+
+        e.path_id = Some(pid);
+        state.in_use = true;
+
+        Ok(())
+    }
+
+    /// Links the lowest unused Destination Connection ID of `path_id` to the
+    /// path `pid`, and returns its sequence number.
+    pub fn link_lowest_available_dcid(
+        &mut self, path_id: u64, pid: usize,
+    ) -> Option<u64> {
+        let state = self.path_ids.get_mut(&path_id)?;
+
+        if state.abandoned() {
+            return None;
+        }
+
+        let e = state
+            .dcids
+            .iter_mut()
+            .filter(|e| e.path_id.is_none())
+            .min_by_key(|e| e.seq)?;
+
+        e.path_id = Some(pid);
+        state.in_use = true;
+
+        Some(e.seq)
+    }
+
+    /// Finds an unused path identifier for a new client path, for which both
+    /// endpoints issued Connection IDs.
+    ///
+    /// When there is none, PATHS_BLOCKED or PATH_CIDS_BLOCKED frames are
+    /// scheduled to let the peer know.
+    pub fn next_client_path_id(&mut self) -> Result<u64> {
+        if !self.enabled {
+            return Err(Error::InvalidState);
+        }
+
+        let max = self.local_max_path_id.min(self.peer_max_path_id);
+
+        let mut first_unused = None;
+
+        for path_id in 1..=max {
+            let state = match self.path_ids.get(&path_id) {
+                Some(v) => v,
+
+                None => {
+                    first_unused.get_or_insert((path_id, 0));
+                    continue;
+                },
+            };
+
+            if state.in_use || state.abandoned() {
+                continue;
+            }
+
+            let has_scid = state.scids.iter().any(|e| e.path_id.is_none());
+            let has_dcid = state.dcids.iter().any(|e| e.path_id.is_none());
+
+            if has_scid && has_dcid {
+                return Ok(path_id);
+            }
+
+            if !has_dcid {
+                let next_seq = state
+                    .dcids
+                    .iter()
+                    .map(|e| e.seq + 1)
+                    .max()
+                    .unwrap_or(state.largest_peer_retire_prior_to);
+
+                first_unused.get_or_insert((path_id, next_seq));
+            }
+        }
+
+        match first_unused {
+            Some(v) => self.cids_blocked_pending = Some(v),
+
+            None if max == self.peer_max_path_id =>
+                self.paths_blocked_pending = Some(self.peer_max_path_id),
+
+            None => (),
+        }
+
+        Err(Error::OutOfIdentifiers)
+    }
+
+    /// Returns the lowest unused Source Connection ID of `path_id`.
+    pub fn lowest_available_scid_seq(&self, path_id: u64) -> Option<u64> {
+        self.path_ids
+            .get(&path_id)?
+            .scids
+            .iter()
+            .filter(|e| e.path_id.is_none())
+            .map(|e| e.seq)
+            .min()
+    }
+
+    /// Returns the packet number space of `path_id`.
+    pub fn pkt_num_space(&self, path_id: u64) -> Result<&packet::PktNumSpace> {
+        self.path_ids
+            .get(&path_id)
+            .map(|s| &s.pkt_num_space)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the mutable packet number space of `path_id`.
+    pub fn pkt_num_space_mut(
+        &mut self, path_id: u64,
+    ) -> Result<&mut packet::PktNumSpace> {
+        self.path_ids
+            .get_mut(&path_id)
+            .map(|s| &mut s.pkt_num_space)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the next packet number to send on `path_id`.
+    pub fn next_pkt_num(&self, path_id: u64) -> Result<u64> {
+        self.path_ids
+            .get(&path_id)
+            .map(|s| s.next_pkt_num)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Records a packet sent on `path_id`.
+    pub fn on_packet_sent(
+        &mut self, path_id: u64, sent_pkt: &recovery::Sent,
+    ) -> Result<()> {
+        let state = self.path_ids.get_mut(&path_id).ok_or(Error::InvalidState)?;
+
+        state.pkt_num_space.on_packet_sent(sent_pkt);
+        state.next_pkt_num = sent_pkt.pkt_num + 1;
+
+        Ok(())
+    }
+
+    fn status_mut(&mut self, path_id: u64) -> Option<&mut StatusState> {
+        if path_id == 0 {
+            return Some(&mut self.path0_status);
+        }
+
+        self.path_ids.get_mut(&path_id).map(|s| &mut s.status)
+    }
+
+    /// Sets the local status of `path_id`, to be advertised to the peer.
+    pub fn set_status(&mut self, path_id: u64, status: PathStatus) -> Result<()> {
+        let state = self.status_mut(path_id).ok_or(Error::InvalidState)?;
+
+        state.local = status;
+        state.local_seq = Some(state.local_seq.map_or(0, |s| s + 1));
+        state.local_pending = true;
+
+        Ok(())
+    }
+
+    /// Handles a PATH_STATUS_AVAILABLE or PATH_STATUS_BACKUP frame.
+    pub fn on_status_received(
+        &mut self, path_id: u64, seq: u64, status: PathStatus,
+    ) -> Result<()> {
+        self.check_frame(path_id)?;
+
+        let state = match self.status_mut(path_id) {
+            Some(v) => v,
+
+            // Unknown path identifiers are ignored.
+            None => return Ok(()),
+        };
+
+        if state.peer_seq.is_none_or(|s| seq > s) {
+            state.peer = status;
+            state.peer_seq = Some(seq);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether either endpoint marked `path_id` as backup.
+    pub fn is_backup(&self, path_id: u64) -> bool {
+        let state = if path_id == 0 {
+            Some(&self.path0_status)
+        } else {
+            self.path_ids.get(&path_id).map(|s| &s.status)
+        };
+
+        state.is_some_and(|s| {
+            s.local == PathStatus::Backup || s.peer == PathStatus::Backup
+        })
+    }
+
+    /// Returns whether `path_id` was abandoned by either endpoint.
+    pub fn is_abandoned(&self, path_id: u64) -> bool {
+        self.path_ids.get(&path_id).is_some_and(|s| s.abandoned())
+    }
+
+    /// Abandons `path_id`, scheduling a PATH_ABANDON frame, and the
+    /// retirement of the Destination Connection IDs of the path.
+    ///
+    /// Returns whether the path identifier was newly abandoned.
+    pub fn abandon(&mut self, path_id: u64, error_code: u64) -> Result<bool> {
+        if !self.enabled || path_id == 0 {
+            return Err(Error::InvalidState);
+        }
+
+        let state = self.path_ids.get_mut(&path_id).ok_or(Error::InvalidState)?;
+
+        if state.local_abandon.is_some() {
+            return Ok(false);
+        }
+
+        let newly_abandoned = !state.peer_abandoned;
+
+        state.local_abandon = Some((error_code, true));
+
+        let seqs = state.dcids.iter().map(|e| e.seq).collect::<Vec<_>>();
+
+        for seq in seqs {
+            state.retire_dcid(seq);
+        }
+
+        if newly_abandoned {
+            self.on_path_id_closed();
+        }
+
+        Ok(newly_abandoned)
+    }
+
+    /// Handles a PATH_ABANDON frame. Returns whether the path identifier was
+    /// newly abandoned.
+    pub fn on_abandon_received(&mut self, path_id: u64) -> Result<bool> {
+        self.check_frame(path_id)?;
+
+        let state = match self.path_ids.get_mut(&path_id) {
+            Some(v) => v,
+
+            None => return Ok(false),
+        };
+
+        if state.peer_abandoned {
+            return Ok(false);
+        }
+
+        state.peer_abandoned = true;
+
+        // Reply with our own PATH_ABANDON.
+        self.abandon(path_id, NO_ERROR)?;
+
+        Ok(true)
+    }
+
+    /// Lets the peer open a new path once a path identifier was closed.
+    fn on_path_id_closed(&mut self) {
+        if self.local_max_path_id < MAX_PATH_ID {
+            self.local_max_path_id += 1;
+            self.max_path_id_pending = true;
+        }
+    }
+
+    /// Returns the PATH_ACK frames to send for the path identifiers that
+    /// received ack-eliciting packets.
+    pub fn ack_frames(
+        &self, ack_delay_exponent: u64, now: Instant,
+    ) -> SmallVec<[frame::Frame; 1]> {
+        self.path_ids
+            .iter()
+            .filter(|(_, s)| {
+                s.pkt_num_space.ack_elicited &&
+                    s.pkt_num_space.recv_pkt_need_ack.len() > 0
+            })
+            .map(|(&path_id, s)| {
+                let pkt_space = &s.pkt_num_space;
+
+                #[cfg(not(feature = "fuzzing"))]
+                let ack_delay = pkt_space
+                    .largest_rx_pkt_time
+                    .map_or(Duration::ZERO, |t| now.saturating_duration_since(t))
+                    .as_micros() as u64 /
+                    2_u64.pow(ack_delay_exponent as u32);
+
+                // pseudo-random reproducible ack delays when fuzzing
+                #[cfg(feature = "fuzzing")]
+                let ack_delay = {
+                    let _ = (ack_delay_exponent, now);
+                    crate::rand::rand_u8() as u64 + 1
+                };
+
+                let ecn_counts = if pkt_space.recv_ecn_counts.is_empty() {
+                    None
+                } else {
+                    Some(pkt_space.recv_ecn_counts.clone())
+                };
+
+                frame::Frame::PathAck {
+                    path_id,
+                    ack_delay,
+                    ranges: pkt_space.recv_pkt_need_ack.clone(),
+                    ecn_counts,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the multipath control frames that need to be sent.
+    ///
+    /// `dcid` is the path identifier and sequence number of the Destination
+    /// Connection ID of the packet, which can't be retired by the packet
+    /// itself.
+    pub fn frames(&self, dcid: (u64, u64)) -> SmallVec<[frame::Frame; 1]> {
+        let mut frames = SmallVec::new();
+
+        if self.max_path_id_pending {
+            frames.push(frame::Frame::MaxPathId {
+                max: self.local_max_path_id,
+            });
+        }
+
+        if let Some(max) = self.paths_blocked_pending {
+            frames.push(frame::Frame::PathsBlocked { max });
+        }
+
+        if let Some((path_id, next_seq_num)) = self.cids_blocked_pending {
+            frames.push(frame::Frame::PathCidsBlocked {
+                path_id,
+                next_seq_num,
+            });
+        }
+
+        frames.extend(self.path0_status.frame(0));
+
+        for (&path_id, state) in &self.path_ids {
+            if let Some((error_code, true)) = state.local_abandon {
+                frames.push(frame::Frame::PathAbandon {
+                    path_id,
+                    error_code,
+                });
+            }
+
+            if !state.abandoned() {
+                frames.extend(state.status.frame(path_id));
+            }
+
+            for &seq_num in &state.advertise_scid_seqs {
+                let e = match state.scids.iter().find(|e| e.seq == seq_num) {
+                    Some(v) => v,
+
+                    None => continue,
+                };
+
+                frames.push(frame::Frame::PathNewConnectionId {
+                    path_id,
+                    seq_num,
+                    retire_prior_to: 0,
+                    conn_id: e.cid.to_vec(),
+                    reset_token: e.reset_token.unwrap_or_default().to_be_bytes(),
+                });
+            }
+
+            for &seq_num in &state.retire_dcid_seqs {
+                if (path_id, seq_num) == dcid {
+                    continue;
+                }
+
+                frames.push(frame::Frame::PathRetireConnectionId {
+                    path_id,
+                    seq_num,
+                });
+            }
+        }
+
+        frames
+    }
+
+    /// Returns whether there are PATH_ACK or control frames to send.
+    pub fn has_pending_frames(&self) -> bool {
+        self.max_path_id_pending ||
+            self.paths_blocked_pending.is_some() ||
+            self.cids_blocked_pending.is_some() ||
+            self.path0_status.local_pending ||
+            self.path_ids.values().any(|s| {
+                s.pkt_num_space.ready() ||
+                    matches!(s.local_abandon, Some((_, true))) ||
+                    s.status.local_pending ||
+                    !s.advertise_scid_seqs.is_empty() ||
+                    !s.retire_dcid_seqs.is_empty()
+            })
+    }
+
+    /// Records that a multipath frame was sent.
+    pub fn on_frame_sent(&mut self, frame: &frame::Frame) {
+        match frame {
+            frame::Frame::PathAck { path_id, .. } => {
+                if let Some(s) = self.path_ids.get_mut(path_id) {
+                    s.pkt_num_space.ack_elicited = false;
+                }
+            },
+
+            frame::Frame::MaxPathId { .. } => self.max_path_id_pending = false,
+
+            frame::Frame::PathsBlocked { .. } =>
+                self.paths_blocked_pending = None,
+
+            frame::Frame::PathCidsBlocked { .. } =>
+                self.cids_blocked_pending = None,
+
+            frame::Frame::PathStatusAvailable { path_id, .. } |
+            frame::Frame::PathStatusBackup { path_id, .. } => {
+                if let Some(s) = self.status_mut(*path_id) {
+                    s.local_pending = false;
+                }
+            },
+
+            frame::Frame::PathAbandon { path_id, .. } => {
+                if let Some((_, pending)) = self
+                    .path_ids
+                    .get_mut(path_id)
+                    .and_then(|s| s.local_abandon.as_mut())
+                {
+                    *pending = false;
+                }
+            },
+
+            frame::Frame::PathNewConnectionId {
+                path_id, seq_num, ..
+            } =>
+                if let Some(s) = self.path_ids.get_mut(path_id) {
+                    s.advertise_scid_seqs.retain(|seq| seq != seq_num);
+                },
+
+            frame::Frame::PathRetireConnectionId { path_id, seq_num } =>
+                if let Some(s) = self.path_ids.get_mut(path_id) {
+                    s.retire_dcid_seqs.retain(|seq| seq != seq_num);
+                },
+
+            _ => (),
+        }
+    }
+
+    /// Schedules the retransmission of a lost multipath frame.
+    pub fn on_frame_lost(&mut self, frame: &frame::Frame) {
+        match frame {
+            frame::Frame::PathAck { path_id, .. } => {
+                if let Some(s) = self.path_ids.get_mut(path_id) {
+                    s.pkt_num_space.ack_elicited = true;
+                }
+            },
+
+            frame::Frame::MaxPathId { max } if *max == self.local_max_path_id =>
+                self.max_path_id_pending = true,
+
+            frame::Frame::PathStatusAvailable { path_id, seq_num } |
+            frame::Frame::PathStatusBackup { path_id, seq_num } => {
+                if let Some(s) = self.status_mut(*path_id) {
+                    if s.local_seq == Some(*seq_num) {
+                        s.local_pending = true;
+                    }
+                }
+            },
+
+            frame::Frame::PathAbandon { path_id, .. } => {
+                if let Some((_, pending)) = self
+                    .path_ids
+                    .get_mut(path_id)
+                    .and_then(|s| s.local_abandon.as_mut())
+                {
+                    *pending = true;
+                }
+            },
+
+            frame::Frame::PathNewConnectionId {
+                path_id, seq_num, ..
+            } =>
+                if let Some(s) = self.path_ids.get_mut(path_id) {
+                    if s.scids.iter().any(|e| e.seq == *seq_num) {
+                        s.advertise_scid_seqs.push_back(*seq_num);
+                    }
+                },
+
+            frame::Frame::PathRetireConnectionId { path_id, seq_num } =>
+                if let Some(s) = self.path_ids.get_mut(path_id) {
+                    s.retire_dcid_seqs.push_back(*seq_num);
+                },
+
+            _ => (),
+        }
+    }
+
+    /// Handles the acknowledgement of a PATH_ACK frame.
+    pub fn on_path_ack_acked(&mut self, path_id: u64, largest_acked: u64) {
+        if let Some(s) = self.path_ids.get_mut(&path_id) {
+            // Stop acknowledging packets less than or equal to the largest
+            // acknowledged in the sent PATH_ACK frame that, in turn, got
+            // acked.
+            s.pkt_num_space
+                .recv_pkt_need_ack
+                .remove_until(largest_acked);
+        }
+    }
+
+    /// Pops a retired Source Connection ID to notify to the application.
+    pub fn pop_retired_scid(&mut self) -> Option<ConnectionId<'static>> {
+        self.retired_scids.pop_front()
+    }
+
+    /// Returns the number of retired Source Connection IDs waiting to be
+    /// notified to the application.
+    pub fn retired_scids(&self) -> usize {
+        self.retired_scids.len()
+    }
+
+    /// Returns the Source Connection IDs of path identifiers other than 0.
+    pub fn scids_iter(&self) -> impl Iterator<Item = &ConnectionId<'_>> {
+        self.path_ids
+            .values()
+            .flat_map(|s| s.scids.iter().map(|e| &e.cid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(
+        path_id: u64, rtt_ms: u64, cwnd_available: usize, backup: bool,
+    ) -> PathCandidate {
+        PathCandidate {
+            path_id,
+            local_addr: "127.0.0.1:1234".parse().unwrap(),
+            peer_addr: "127.0.0.1:4321".parse().unwrap(),
+            rtt: Duration::from_millis(rtt_ms),
+            cwnd_available,
+            backup,
+        }
+    }
+
+    #[test]
+    fn min_rtt_scheduler() {
+        let mut s = MinRttScheduler;
+
+        let candidates = [
+            candidate(0, 50, 1000, false),
+            candidate(1, 20, 1000, false),
+            candidate(2, 10, 1000, true),
+        ];
+        assert_eq!(s.select_path(&candidates), Some(1));
+
+        // The fastest path is full.
+        let candidates =
+            [candidate(0, 50, 1000, false), candidate(1, 20, 0, false)];
+        assert_eq!(s.select_path(&candidates), Some(0));
+
+        // Backup paths are only used when there is nothing else.
+        let candidates =
+            [candidate(0, 50, 0, false), candidate(1, 20, 1000, true)];
+        assert_eq!(s.select_path(&candidates), Some(0));
+
+        let candidates =
+            [candidate(3, 50, 0, true), candidate(1, 20, 1000, true)];
+        assert_eq!(s.select_path(&candidates), Some(1));
+
+        assert_eq!(s.select_path(&[]), None);
+    }
+
+    #[test]
+    fn round_robin_scheduler() {
+        let mut s = RoundRobinScheduler::default();
+
+        let candidates = [
+            candidate(2, 50, 1000, false),
+            candidate(0, 50, 1000, false),
+            candidate(1, 20, 1000, false),
+            candidate(3, 10, 1000, true),
+        ];
+
+        assert_eq!(s.select_path(&candidates), Some(1));
+        assert_eq!(s.select_path(&candidates), Some(2));
+        assert_eq!(s.select_path(&candidates), Some(0));
+        assert_eq!(s.select_path(&candidates), Some(1));
+
+        // Full paths are skipped.
+        let candidates = [
+            candidate(2, 50, 1000, false),
+            candidate(0, 50, 0, false),
+            candidate(1, 20, 1000, false),
+        ];
+
+        assert_eq!(s.select_path(&candidates), Some(2));
+        assert_eq!(s.select_path(&candidates), Some(0));
+        assert_eq!(s.select_path(&candidates), Some(2));
+    }
+
+    #[test]
+    fn path_cids() {
+        let mut mp = Multipath::default();
+
+        let cid = ConnectionId::from_vec(vec![0xba; 16]);
+
+        // The extension was not negotiated.
+        assert_eq!(mp.new_scid(1, cid.clone(), 1, 2), Err(Error::InvalidState));
+
+        mp.enable(2, 1);
+
+        // Path 0 uses the regular Connection IDs, and path 2 is above the
+        // peer's limit.
+        assert_eq!(mp.new_scid(0, cid.clone(), 1, 2), Err(Error::InvalidState));
+        assert_eq!(mp.new_scid(2, cid.clone(), 1, 2), Err(Error::InvalidState));
+
+        assert_eq!(mp.new_scid(1, cid.clone(), 1, 2), Ok(0));
+        assert_eq!(mp.new_scid(1, cid.clone(), 1, 2), Ok(0));
+        assert_eq!(mp.new_scid(1, cid.clone(), 2, 2), Err(Error::InvalidState));
+        assert_eq!(
+            mp.new_scid(1, ConnectionId::from_vec(vec![0xbb; 16]), 3, 2),
+            Ok(1)
+        );
+        assert_eq!(
+            mp.new_scid(1, ConnectionId::from_vec(vec![0xbc; 16]), 4, 2),
+            Err(Error::IdLimit)
+        );
+
+        assert_eq!(mp.find_scid(&cid), Some((1, 0, None)));
+
+        let frames = mp.frames((0, 0));
+        assert_eq!(frames.len(), 2);
+        assert!(mp.has_pending_frames());
+
+        for f in &frames {
+            mp.on_frame_sent(f);
+        }
+        assert!(!mp.has_pending_frames());
+
+        mp.on_frame_lost(&frames[1]);
+        assert_eq!(mp.frames((0, 0)).as_slice(), &frames[1..]);
+
+        // The peer can't issue Connection IDs above our limit.
+        let mut retired = SmallVec::new();
+        let dcid = ConnectionId::from_vec(vec![0xda; 16]);
+
+        assert_eq!(
+            mp.new_dcid(3, dcid.clone(), 0, 5, 0, 2, &mut retired),
+            Err(Error::InvalidState)
+        );
+
+        // No Connection ID was issued by the peer yet.
+        assert_eq!(mp.next_client_path_id(), Err(Error::OutOfIdentifiers));
+        assert_eq!(mp.frames((0, 0)).as_slice(), &[
+            frame::Frame::PathCidsBlocked {
+                path_id: 1,
+                next_seq_num: 0,
+            },
+            frames[1].clone(),
+        ]);
+
+        assert_eq!(mp.new_dcid(1, dcid, 0, 5, 0, 2, &mut retired), Ok(()));
+        assert_eq!(mp.next_client_path_id(), Ok(1));
+
+        assert_eq!(mp.link_lowest_available_dcid(1, 3), Some(0));
+        assert_eq!(mp.link_scid(1, 0, 3), Ok(()));
+
+        // Retiring the Connection ID used by the packet is not allowed.
+        assert_eq!(mp.retire_scid(1, 0, &cid), Err(Error::InvalidState));
+        assert_eq!(mp.retire_scid(1, 2, &cid), Err(Error::InvalidState));
+        assert_eq!(
+            mp.retire_scid(1, 0, &ConnectionId::from_vec(vec![0xbb; 16])),
+            Ok(Some(3))
+        );
+        assert_eq!(mp.pop_retired_scid(), Some(cid));
+        assert_eq!(mp.pop_retired_scid(), None);
+
+        // Abandoning the path retires its Connection IDs and lets the peer
+        // use another path identifier.
+        assert_eq!(mp.abandon(1, 42), Ok(true));
+        assert_eq!(mp.abandon(1, 42), Ok(false));
+        assert!(mp.is_abandoned(1));
+
+        let frames = mp.frames((0, 0));
+        assert!(frames.contains(&frame::Frame::MaxPathId { max: 3 }));
+        assert!(frames.contains(&frame::Frame::PathAbandon {
+            path_id: 1,
+            error_code: 42,
+        }));
+        assert!(frames.contains(&frame::Frame::PathRetireConnectionId {
+            path_id: 1,
+            seq_num: 0,
+        }));
+
+        // The Connection ID used by the packet itself can't be retired.
+        assert!(!mp.frames((1, 0)).contains(
+            &frame::Frame::PathRetireConnectionId {
+                path_id: 1,
+                seq_num: 0,
+            }
+        ));
+    }
+
+    #[test]
+    fn path_status() {
+        let mut mp = Multipath::default();
+        mp.enable(1, 1);
+
+        assert!(!mp.is_backup(0));
+
+        assert_eq!(mp.on_status_received(0, 3, PathStatus::Backup), Ok(()));
+        assert!(mp.is_backup(0));
+
+        // Older status updates are ignored.
+        assert_eq!(mp.on_status_received(0, 2, PathStatus::Available), Ok(()));
+        assert!(mp.is_backup(0));
+
+        assert_eq!(mp.on_status_received(0, 4, PathStatus::Available), Ok(()));
+        assert!(!mp.is_backup(0));
+
+        assert_eq!(
+            mp.on_status_received(2, 0, PathStatus::Available),
+            Err(Error::InvalidState)
+        );
+
+        assert_eq!(mp.set_status(0, PathStatus::Backup), Ok(()));
+        assert!(mp.is_backup(0));
+        assert_eq!(mp.frames((0, 0)).as_slice(), &[
+            frame::Frame::PathStatusBackup {
+                path_id: 0,
+                seq_num: 0,
+            }
+        ]);
+
+        assert_eq!(mp.set_status(0, PathStatus::Available), Ok(()));
+        assert_eq!(mp.frames((0, 0)).as_slice(), &[
+            frame::Frame::PathStatusAvailable {
+                path_id: 0,
+                seq_num: 1,
+            }
+        ]);
+
+        // Only the latest status is retransmitted.
+        mp.on_frame_sent(&frame::Frame::PathStatusAvailable {
+            path_id: 0,
+            seq_num: 1,
+        });
+        mp.on_frame_lost(&frame::Frame::PathStatusBackup {
+            path_id: 0,
+            seq_num: 0,
+        });
+        assert!(!mp.has_pending_frames());
+    }
+}
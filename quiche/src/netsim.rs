@@ -0,0 +1,1149 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Deterministic network simulator.
+//!
+//! A [`Simulation`] runs a client and a server [`Connection`] across a pair of
+//! simulated links, one in each direction, under a virtual [`ManualClock`].
+//! Each link models a bottleneck with a fixed bandwidth and a drop-tail
+//! buffer, followed by a propagation delay with optional jitter, reordering
+//! and random or bursty losses. Packets never touch the network, and the
+//! virtual clock only jumps from one event to the next, so simulating a long
+//! transfer takes as long as processing its packets.
+//!
+//! The randomness of the links is drawn from a generator seeded by the caller,
+//! so the same seed always produces the same drops and delays for the same
+//! sequence of packets. The connections still pick random connection IDs and
+//! TLS secrets, which can slightly change the size of handshake packets
+//! between runs.
+//!
+//! This module is only built with the `netsim` feature.
+//!
+//! [`Connection`]: ../struct.Connection.html
+//! [`ManualClock`]: ../struct.ManualClock.html
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::rand;
+use crate::Clock;
+use crate::Config;
+use crate::Connection;
+use crate::ConnectionId;
+use crate::Error;
+use crate::ManualClock;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+
+/// The minimum amount of time the virtual clock moves forward when a
+/// connection timer is already expired.
+const TIMER_GRANULARITY: Duration = Duration::from_millis(1);
+
+/// The loss model of a simulated link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LossModel {
+    /// Packets are only lost when the link buffer is full.
+    None,
+
+    /// Each packet is lost independently with the given probability.
+    Random(f64),
+
+    /// Packets are lost in bursts, following a two-state Gilbert-Elliott
+    /// model.
+    GilbertElliott {
+        /// The probability of moving from the good to the bad state, checked
+        /// for each packet.
+        good_to_bad: f64,
+
+        /// The probability of moving from the bad to the good state, checked
+        /// for each packet.
+        bad_to_good: f64,
+
+        /// The probability of losing a packet in the good state.
+        good_loss: f64,
+
+        /// The probability of losing a packet in the bad state.
+        bad_loss: f64,
+    },
+}
+
+/// The configuration of a simulated link.
+#[derive(Clone, Copy, Debug)]
+pub struct LinkConfig {
+    /// The bottleneck bandwidth, in bits per second.
+    pub bandwidth: u64,
+
+    /// The one-way propagation delay.
+    pub delay: Duration,
+
+    /// The maximum random delay added on top of `delay`. Jitter alone never
+    /// reorders packets.
+    pub jitter: Duration,
+
+    /// The size of the bottleneck buffer, in bytes. Packets that don't fit
+    /// are dropped.
+    pub buffer_size: usize,
+
+    /// The probability of delaying a packet by `reorder_delay`, so that it
+    /// arrives after packets sent later.
+    pub reorder_rate: f64,
+
+    /// The extra delay of reordered packets.
+    pub reorder_delay: Duration,
+
+    /// The loss model applied to packets leaving the bottleneck.
+    pub loss: LossModel,
+}
+
+impl LinkConfig {
+    /// Creates a link with the given bandwidth, in bits per second, and
+    /// one-way delay.
+    ///
+    /// The buffer holds one bandwidth-delay product of the round trip, and
+    /// the link doesn't add jitter, reordering or losses.
+    pub fn new(bandwidth: u64, delay: Duration) -> LinkConfig {
+        let bdp = bandwidth as u128 * 2 * delay.as_nanos() / 8 / 1_000_000_000;
+
+        LinkConfig {
+            bandwidth,
+            delay,
+            jitter: Duration::ZERO,
+            buffer_size: cmp::max(bdp as usize, crate::MAX_SEND_UDP_PAYLOAD_SIZE),
+            reorder_rate: 0.0,
+            reorder_delay: Duration::ZERO,
+            loss: LossModel::None,
+        }
+    }
+
+    /// Returns the time it takes to put `len` bytes on the link.
+    fn transmission_time(&self, len: usize) -> Duration {
+        let nanos = len as u128 * 8 * 1_000_000_000 / self.bandwidth as u128;
+
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// Statistics about a simulated link.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkStats {
+    /// The number of packets handed to the link.
+    pub sent: u64,
+
+    /// The number of packets delivered to the receiver.
+    pub delivered: u64,
+
+    /// The time spent putting packets on the link.
+    pub busy: Duration,
+
+    /// The number of packets dropped because the buffer was full.
+    pub dropped: u64,
+
+    /// The number of packets lost according to the loss model.
+    pub lost: u64,
+
+    /// The number of packets delayed by the reordering model.
+    pub reordered: u64,
+
+    /// The longest time a packet waited in the buffer.
+    pub max_queue_delay: Duration,
+}
+
+/// A small deterministic pseudo-random number generator (SplitMix64).
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number uniformly distributed in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns true with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+}
+
+/// One direction of the simulated network.
+struct Link {
+    config: LinkConfig,
+
+    rng: Rng,
+
+    /// Whether the Gilbert-Elliott model is in the bad state.
+    bad_state: bool,
+
+    /// The time at which the last queued packet leaves the bottleneck.
+    busy_until: Instant,
+
+    /// The departure times and sizes of the packets in the buffer.
+    queue: VecDeque<(Instant, usize)>,
+
+    queued_bytes: usize,
+
+    /// The arrival time of the last packet that wasn't reordered.
+    last_arrival: Instant,
+
+    stats: LinkStats,
+}
+
+impl Link {
+    fn new(config: LinkConfig, seed: u64, now: Instant) -> Link {
+        Link {
+            config,
+            rng: Rng(seed),
+            bad_state: false,
+            busy_until: now,
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+            last_arrival: now,
+            stats: LinkStats::default(),
+        }
+    }
+
+    /// Hands a packet of `len` bytes to the link at time `now`, and returns
+    /// the time at which it arrives at the other end, or `None` if it is
+    /// dropped or lost.
+    fn transmit(&mut self, len: usize, now: Instant) -> Option<Instant> {
+        self.stats.sent += 1;
+
+        while let Some(&(departure, size)) = self.queue.front() {
+            if departure > now {
+                break;
+            }
+
+            self.queue.pop_front();
+            self.queued_bytes -= size;
+        }
+
+        if self.queued_bytes + len > self.config.buffer_size {
+            self.stats.dropped += 1;
+
+            return None;
+        }
+
+        let start = cmp::max(now, self.busy_until);
+        let transmission_time = self.config.transmission_time(len);
+        let departure = start + transmission_time;
+
+        self.busy_until = departure;
+        self.queue.push_back((departure, len));
+        self.queued_bytes += len;
+
+        self.stats.busy += transmission_time;
+        self.stats.max_queue_delay =
+            cmp::max(self.stats.max_queue_delay, start - now);
+
+        if self.lose() {
+            self.stats.lost += 1;
+
+            return None;
+        }
+
+        let mut arrival = departure + self.config.delay;
+
+        if !self.config.jitter.is_zero() {
+            arrival += self.config.jitter.mul_f64(self.rng.next_f64());
+        }
+
+        if self.rng.chance(self.config.reorder_rate) {
+            arrival += self.config.reorder_delay;
+
+            self.stats.reordered += 1;
+        } else {
+            arrival = cmp::max(arrival, self.last_arrival);
+
+            self.last_arrival = arrival;
+        }
+
+        self.stats.delivered += 1;
+
+        Some(arrival)
+    }
+
+    /// Returns whether the next packet is lost, according to the loss model.
+    fn lose(&mut self) -> bool {
+        match self.config.loss {
+            LossModel::None => false,
+
+            LossModel::Random(p) => self.rng.chance(p),
+
+            LossModel::GilbertElliott {
+                good_to_bad,
+                bad_to_good,
+                good_loss,
+                bad_loss,
+            } => {
+                if self.bad_state {
+                    self.bad_state = !self.rng.chance(bad_to_good);
+                } else {
+                    self.bad_state = self.rng.chance(good_to_bad);
+                }
+
+                if self.bad_state {
+                    self.rng.chance(bad_loss)
+                } else {
+                    self.rng.chance(good_loss)
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Endpoint {
+    Client,
+    Server,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum EventKind {
+    /// A packet sent by the endpoint reaches its link.
+    Transmit(Endpoint),
+
+    /// A packet arrives at the endpoint.
+    Deliver(Endpoint),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Event {
+    time: Instant,
+
+    /// Breaks ties between events scheduled at the same time, in the order
+    /// they were scheduled.
+    seq: u64,
+
+    kind: EventKind,
+
+    pkt: Vec<u8>,
+
+    info: SendInfo,
+}
+
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        // Reversed, so that the `BinaryHeap` pops the earliest event first.
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The outcome of a simulated transfer.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The number of stream bytes received by the client.
+    pub bytes: u64,
+
+    /// The time from the start of the transfer until the client received the
+    /// last byte, or until the simulation timed out.
+    pub duration: Duration,
+
+    /// Whether the client received the whole transfer.
+    pub complete: bool,
+
+    /// The smoothed round-trip time estimated by the server at the end of the
+    /// transfer.
+    pub rtt: Duration,
+
+    /// The minimum round-trip time observed by the server.
+    pub min_rtt: Option<Duration>,
+
+    /// The maximum round-trip time observed by the server.
+    pub max_rtt: Option<Duration>,
+
+    /// The number of packets the server declared lost during the transfer.
+    pub lost: usize,
+
+    /// The number of packets the server retransmitted during the transfer.
+    pub retrans: usize,
+
+    /// Statistics about the server-to-client link during the transfer.
+    pub downlink: LinkStats,
+
+    /// Statistics about the client-to-server link during the transfer.
+    pub uplink: LinkStats,
+}
+
+impl Report {
+    /// Returns the goodput of the transfer, in bits per second.
+    pub fn goodput(&self) -> f64 {
+        if self.duration.is_zero() {
+            return 0.0;
+        }
+
+        self.bytes as f64 * 8.0 / self.duration.as_secs_f64()
+    }
+
+    /// Returns the fraction of the time the server-to-client link spent
+    /// transmitting packets during the transfer.
+    ///
+    /// Unlike the goodput, this accounts for packet overhead and
+    /// retransmissions.
+    pub fn utilization(&self) -> f64 {
+        if self.duration.is_zero() {
+            return 0.0;
+        }
+
+        self.downlink.busy.as_secs_f64() / self.duration.as_secs_f64()
+    }
+}
+
+/// A client and a server connected through simulated links.
+pub struct Simulation {
+    client: Connection,
+
+    server: Connection,
+
+    /// The server-to-client link.
+    downlink: Link,
+
+    /// The client-to-server link.
+    uplink: Link,
+
+    events: BinaryHeap<Event>,
+
+    next_seq: u64,
+
+    /// The stream the server uses for the next bulk transfer.
+    next_stream_id: u64,
+
+    /// The virtual clock shared by both connections.
+    clock: Arc<ManualClock>,
+
+    buf: Vec<u8>,
+}
+
+impl Simulation {
+    /// Creates a client connection with `client_config` and a server
+    /// connection with `server_config`, connected through the `downlink`
+    /// (server to client) and `uplink` (client to server) links.
+    ///
+    /// Both configurations have their clock replaced with the simulation's
+    /// virtual clock.
+    ///
+    /// The random behavior of the links is derived from `seed`, and the
+    /// virtual clock starts at `epoch`.
+    pub fn new(
+        client_config: &mut Config, server_config: &mut Config,
+        downlink: LinkConfig, uplink: LinkConfig, seed: u64, epoch: Instant,
+    ) -> Result<Simulation> {
+        let clock = Arc::new(ManualClock::new(epoch));
+
+        client_config.set_clock(clock.clone());
+        server_config.set_clock(clock.clone());
+
+        let client_addr = "127.0.0.1:1234".parse().unwrap();
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+
+        let mut client_scid = [0; 16];
+        rand::rand_bytes(&mut client_scid[..]);
+        let client_scid = ConnectionId::from_ref(&client_scid);
+
+        let mut server_scid = [0; 16];
+        rand::rand_bytes(&mut server_scid[..]);
+        let server_scid = ConnectionId::from_ref(&server_scid);
+
+        let client = crate::connect(
+            Some("quic.tech"),
+            &client_scid,
+            client_addr,
+            server_addr,
+            client_config,
+        )?;
+
+        let server = crate::accept(
+            &server_scid,
+            None,
+            server_addr,
+            client_addr,
+            server_config,
+        )?;
+
+        let mut rng = Rng(seed);
+
+        Ok(Simulation {
+            client,
+            server,
+            downlink: Link::new(downlink, rng.next_u64(), epoch),
+            uplink: Link::new(uplink, rng.next_u64(), epoch),
+            events: BinaryHeap::new(),
+            next_seq: 0,
+            next_stream_id: 1,
+            clock,
+            buf: vec![0; 65535],
+        })
+    }
+
+    /// Returns the client connection.
+    pub fn client(&mut self) -> &mut Connection {
+        &mut self.client
+    }
+
+    /// Returns the server connection.
+    pub fn server(&mut self) -> &mut Connection {
+        &mut self.server
+    }
+
+    /// Returns the current virtual time.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Writes the qlog of the client and server connections to the given
+    /// writers.
+    ///
+    /// This needs to be called before running the simulation.
+    #[cfg(feature = "qlog")]
+    pub fn set_qlog(
+        &mut self, client: Box<dyn std::io::Write + Send + Sync>,
+        server: Box<dyn std::io::Write + Send + Sync>,
+    ) {
+        self.client.set_qlog(
+            client,
+            "netsim client".to_string(),
+            "client of a simulated connection".to_string(),
+        );
+
+        self.server.set_qlog(
+            server,
+            "netsim server".to_string(),
+            "server of a simulated connection".to_string(),
+        );
+    }
+
+    /// Transfers `len` bytes from the server to the client on a new stream,
+    /// until the client receives all of them or `timeout` elapses.
+    ///
+    /// The server opens a bidirectional stream as soon as the handshake
+    /// completes, so the client's configuration needs to allow enough
+    /// bidirectional streams for all the transfers.
+    pub fn bulk_transfer(
+        &mut self, len: usize, timeout: Duration,
+    ) -> Result<Report> {
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 4;
+
+        let deadline = self.now() + timeout;
+        let data = vec![0; 65535];
+
+        let initial_stats = self.server.stats();
+
+        self.downlink.stats = LinkStats::default();
+        self.uplink.stats = LinkStats::default();
+
+        let mut written = 0;
+        let mut received = 0;
+        let mut start = None;
+        let mut end = None;
+
+        loop {
+            if self.server.is_established() && written < len {
+                start.get_or_insert(self.now());
+
+                while written < len {
+                    let n = cmp::min(len - written, data.len());
+
+                    match self.server.stream_send(
+                        stream_id,
+                        &data[..n],
+                        written + n == len,
+                    ) {
+                        Ok(v) => written += v,
+
+                        Err(Error::Done) => break,
+
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+
+            while let Ok((n, fin)) =
+                self.client.stream_recv(stream_id, &mut self.buf)
+            {
+                received += n;
+
+                if fin {
+                    end = Some(self.now());
+                }
+            }
+
+            if end.is_some() {
+                break;
+            }
+
+            self.flush()?;
+
+            if !self.advance(deadline)? {
+                break;
+            }
+        }
+
+        let start = start.unwrap_or(self.now());
+        let path = self.server.path_stats().next();
+        let stats = self.server.stats();
+
+        Ok(Report {
+            bytes: received as u64,
+            duration: end.unwrap_or(self.now()) - start,
+            complete: end.is_some(),
+            rtt: path.as_ref().map(|p| p.rtt).unwrap_or_default(),
+            min_rtt: path.as_ref().and_then(|p| p.min_rtt),
+            max_rtt: path.as_ref().and_then(|p| p.max_rtt),
+            lost: stats.lost - initial_stats.lost,
+            retrans: stats.retrans - initial_stats.retrans,
+            downlink: self.downlink.stats,
+            uplink: self.uplink.stats,
+        })
+    }
+
+    /// Hands all the packets the two connections can send to their links.
+    fn flush(&mut self) -> Result<()> {
+        for endpoint in [Endpoint::Client, Endpoint::Server] {
+            loop {
+                let conn = match endpoint {
+                    Endpoint::Client => &mut self.client,
+                    Endpoint::Server => &mut self.server,
+                };
+
+                let (len, info) = match conn.send(&mut self.buf) {
+                    Ok(v) => v,
+
+                    Err(Error::Done) => break,
+
+                    Err(e) => return Err(e),
+                };
+
+                // Paced packets reach the link at their scheduled time.
+                let time = cmp::max(info.at, self.clock.now());
+                let pkt = self.buf[..len].to_vec();
+
+                self.schedule(time, EventKind::Transmit(endpoint), pkt, info);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the virtual clock to the next event and processes all the
+    /// events due at that time.
+    ///
+    /// Returns false when there are no more events before `deadline`.
+    fn advance(&mut self, deadline: Instant) -> Result<bool> {
+        let now = self.clock.now();
+
+        let timers =
+            [self.client.timeout_instant(), self.server.timeout_instant()]
+                .into_iter()
+                .flatten()
+                .map(|t| if t <= now { now + TIMER_GRANULARITY } else { t });
+
+        let next = self
+            .events
+            .peek()
+            .map(|e| e.time)
+            .into_iter()
+            .chain(timers)
+            .min();
+
+        let next = match next {
+            Some(v) if v <= deadline => v,
+
+            _ => {
+                self.clock.set(deadline);
+
+                return Ok(false);
+            },
+        };
+
+        self.clock.set(next);
+
+        let now = self.clock.now();
+
+        while self.events.peek().is_some_and(|e| e.time <= now) {
+            let Event {
+                kind,
+                mut pkt,
+                info,
+                ..
+            } = self.events.pop().unwrap();
+
+            match kind {
+                EventKind::Transmit(from) => {
+                    let (link, to) = match from {
+                        Endpoint::Client => (&mut self.uplink, Endpoint::Server),
+                        Endpoint::Server =>
+                            (&mut self.downlink, Endpoint::Client),
+                    };
+
+                    if let Some(arrival) = link.transmit(pkt.len(), now) {
+                        self.schedule(arrival, EventKind::Deliver(to), pkt, info);
+                    }
+                },
+
+                EventKind::Deliver(to) => {
+                    let conn = match to {
+                        Endpoint::Client => &mut self.client,
+                        Endpoint::Server => &mut self.server,
+                    };
+
+                    let recv_info = RecvInfo {
+                        from: info.from,
+                        to: info.to,
+                        ecn: info.ecn,
+                    };
+
+                    match conn.recv(&mut pkt, recv_info) {
+                        Ok(_) | Err(Error::Done) => (),
+
+                        Err(e) => return Err(e),
+                    }
+                },
+            }
+        }
+
+        for conn in [&mut self.client, &mut self.server] {
+            if conn.timeout_instant().is_some_and(|t| t <= now) {
+                conn.on_timeout();
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn schedule(
+        &mut self, time: Instant, kind: EventKind, pkt: Vec<u8>, info: SendInfo,
+    ) {
+        self.events.push(Event {
+            time,
+            seq: self.next_seq,
+            kind,
+            pkt,
+            info,
+        });
+
+        self.next_seq += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(cc_algorithm_name: &str) -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config.set_cc_algorithm_name(cc_algorithm_name).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(&[b"proto1"]).unwrap();
+        config.set_initial_max_data(100_000_000);
+        config.set_initial_max_stream_data_bidi_local(100_000_000);
+        config.set_initial_max_stream_data_bidi_remote(100_000_000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_max_idle_timeout(180_000);
+        config.set_send_capacity_factor(2.0);
+        config.verify_peer(false);
+        config
+    }
+
+    fn simulation(
+        cc_algorithm_name: &str, link: LinkConfig, seed: u64,
+    ) -> Simulation {
+        let mut client_config = config(cc_algorithm_name);
+        let mut server_config = config(cc_algorithm_name);
+
+        Simulation::new(
+            &mut client_config,
+            &mut server_config,
+            link,
+            link,
+            seed,
+            Instant::now(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn link_transmission() {
+        let now = Instant::now();
+
+        // 1200 bytes take 1ms at 9.6 Mbps.
+        let config = LinkConfig::new(9_600_000, Duration::from_millis(10));
+        let mut link = Link::new(config, 0, now);
+
+        assert_eq!(config.buffer_size, 24_000);
+
+        assert_eq!(
+            link.transmit(1200, now),
+            Some(now + Duration::from_millis(11))
+        );
+        assert_eq!(
+            link.transmit(1200, now),
+            Some(now + Duration::from_millis(12))
+        );
+
+        // Once the queue drains, packets only pay the transmission time and
+        // the propagation delay.
+        let later = now + Duration::from_millis(5);
+
+        assert_eq!(
+            link.transmit(1200, later),
+            Some(later + Duration::from_millis(11))
+        );
+
+        assert_eq!(link.stats.sent, 3);
+        assert_eq!(link.stats.delivered, 3);
+        assert_eq!(link.stats.max_queue_delay, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn link_buffer_overflow() {
+        let now = Instant::now();
+
+        let config = LinkConfig {
+            buffer_size: 3600,
+            ..LinkConfig::new(9_600_000, Duration::from_millis(10))
+        };
+        let mut link = Link::new(config, 0, now);
+
+        for _ in 0..3 {
+            assert!(link.transmit(1200, now).is_some());
+        }
+
+        assert_eq!(link.transmit(1200, now), None);
+        assert_eq!(link.stats.dropped, 1);
+
+        // The first packet left the buffer, making room for another one.
+        let later = now + Duration::from_millis(1);
+
+        assert_eq!(
+            link.transmit(1200, later),
+            Some(now + Duration::from_millis(14))
+        );
+        assert_eq!(link.stats.max_queue_delay, Duration::from_millis(2));
+    }
+
+    #[test]
+    fn link_jitter() {
+        let now = Instant::now();
+
+        let config = LinkConfig {
+            jitter: Duration::from_millis(5),
+            ..LinkConfig::new(1_000_000_000, Duration::from_millis(10))
+        };
+        let mut link = Link::new(config, 42, now);
+
+        let mut last = now;
+
+        for i in 0..1000 {
+            let sent = now + Duration::from_micros(i * 100);
+            let arrival = link.transmit(1200, sent).unwrap();
+
+            assert!(arrival >= sent + config.delay);
+            assert!(arrival <= sent + config.delay + Duration::from_millis(6));
+
+            // Jitter never reorders packets.
+            assert!(arrival >= last);
+
+            last = arrival;
+        }
+    }
+
+    #[test]
+    fn link_reordering() {
+        let now = Instant::now();
+
+        let config = LinkConfig {
+            reorder_rate: 0.1,
+            reorder_delay: Duration::from_millis(5),
+            ..LinkConfig::new(1_000_000_000, Duration::from_millis(10))
+        };
+        let mut link = Link::new(config, 42, now);
+
+        let mut arrivals = Vec::new();
+
+        for i in 0..1000 {
+            let sent = now + Duration::from_micros(i * 100);
+
+            arrivals.push(link.transmit(1200, sent).unwrap());
+        }
+
+        let reordered =
+            arrivals.windows(2).filter(|w| w[1] < w[0]).count() as u64;
+
+        assert!(link.stats.reordered > 50 && link.stats.reordered < 150);
+        assert!(reordered > 0 && reordered <= link.stats.reordered);
+    }
+
+    #[test]
+    fn link_random_loss() {
+        let now = Instant::now();
+
+        let config = LinkConfig {
+            buffer_size: usize::MAX / 2,
+            loss: LossModel::Random(0.01),
+            ..LinkConfig::new(1_000_000_000, Duration::from_millis(10))
+        };
+        let mut link = Link::new(config, 42, now);
+
+        for _ in 0..100_000 {
+            link.transmit(1200, now);
+        }
+
+        assert!(link.stats.lost > 900 && link.stats.lost < 1100);
+        assert_eq!(link.stats.delivered + link.stats.lost, 100_000);
+    }
+
+    #[test]
+    fn link_bursty_loss() {
+        let now = Instant::now();
+
+        // A bad state lasting 10 packets on average, entered every 1000
+        // packets, gives about 1% of losses.
+        let config = LinkConfig {
+            buffer_size: usize::MAX / 2,
+            loss: LossModel::GilbertElliott {
+                good_to_bad: 0.001,
+                bad_to_good: 0.1,
+                good_loss: 0.0,
+                bad_loss: 1.0,
+            },
+            ..LinkConfig::new(1_000_000_000, Duration::from_millis(10))
+        };
+        let mut link = Link::new(config, 42, now);
+
+        let mut bursts = 0;
+        let mut was_lost = false;
+
+        for _ in 0..100_000 {
+            let lost = link.transmit(1200, now).is_none();
+
+            if lost && !was_lost {
+                bursts += 1;
+            }
+
+            was_lost = lost;
+        }
+
+        assert!(link.stats.lost > 500 && link.stats.lost < 1500);
+
+        // Losses come in bursts of several packets.
+        assert!(link.stats.lost > bursts * 5);
+    }
+
+    #[test]
+    fn cubic_utilization() {
+        // 50 Mbps, 80 ms round trip.
+        let link = LinkConfig::new(50_000_000, Duration::from_millis(40));
+        let mut sim = simulation("cubic", link, 0);
+
+        let report = sim
+            .bulk_transfer(30_000_000, Duration::from_secs(60))
+            .unwrap();
+
+        assert!(report.complete);
+        assert_eq!(report.bytes, 30_000_000);
+        assert!(report.utilization() >= 0.9);
+        assert!(report.goodput() <= link.bandwidth as f64);
+
+        // Queuing adds at most one buffer worth of delay.
+        assert!(report.min_rtt.unwrap() >= Duration::from_millis(80));
+        assert!(report.max_rtt.unwrap() <= Duration::from_millis(250));
+        assert!(report.downlink.max_queue_delay <= Duration::from_millis(81));
+    }
+
+    #[test]
+    fn bursty_loss() {
+        let link = LinkConfig {
+            loss: LossModel::GilbertElliott {
+                good_to_bad: 0.002,
+                bad_to_good: 0.3,
+                good_loss: 0.0,
+                bad_loss: 0.8,
+            },
+            ..LinkConfig::new(20_000_000, Duration::from_millis(20))
+        };
+        let mut sim = simulation("cubic", link, 1);
+
+        let report = sim
+            .bulk_transfer(5_000_000, Duration::from_secs(60))
+            .unwrap();
+
+        assert!(report.complete);
+        assert_eq!(report.bytes, 5_000_000);
+        assert!(report.downlink.lost > 0);
+        assert!(report.lost > 0);
+        assert!(report.retrans > 0);
+    }
+
+    #[test]
+    fn reordering_and_jitter() {
+        let link = LinkConfig {
+            jitter: Duration::from_millis(2),
+            reorder_rate: 0.01,
+            reorder_delay: Duration::from_millis(5),
+            ..LinkConfig::new(20_000_000, Duration::from_millis(20))
+        };
+        let mut sim = simulation("reno", link, 2);
+
+        let report = sim
+            .bulk_transfer(5_000_000, Duration::from_secs(60))
+            .unwrap();
+
+        assert!(report.complete);
+        assert!(report.downlink.reordered > 0);
+        assert_eq!(report.downlink.lost, 0);
+    }
+
+    #[test]
+    fn timeout() {
+        let link = LinkConfig::new(1_000_000, Duration::from_millis(50));
+        let mut sim = simulation("cubic", link, 0);
+
+        let start = sim.now();
+
+        let report = sim
+            .bulk_transfer(10_000_000, Duration::from_secs(2))
+            .unwrap();
+
+        assert!(!report.complete);
+        assert!(report.bytes > 0 && report.bytes < 10_000_000);
+        assert_eq!(sim.now(), start + Duration::from_secs(2));
+
+        // The transfer starts once the handshake completes.
+        assert!(report.duration < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn consecutive_transfers() {
+        let link = LinkConfig::new(20_000_000, Duration::from_millis(20));
+        let mut sim = simulation("cubic", link, 0);
+
+        let first = sim
+            .bulk_transfer(1_000_000, Duration::from_secs(10))
+            .unwrap();
+
+        assert!(first.complete);
+
+        // The second transfer reuses the congestion window grown by the
+        // first one.
+        let second = sim
+            .bulk_transfer(1_000_000, Duration::from_secs(10))
+            .unwrap();
+
+        assert!(second.complete);
+        assert_eq!(second.bytes, 1_000_000);
+        assert!(second.duration < first.duration);
+        assert!(second.downlink.sent < first.downlink.sent * 2);
+    }
+
+    #[test]
+    fn link_deterministic() {
+        let now = Instant::now();
+
+        let config = LinkConfig {
+            jitter: Duration::from_millis(3),
+            reorder_rate: 0.01,
+            reorder_delay: Duration::from_millis(5),
+            loss: LossModel::Random(0.01),
+            ..LinkConfig::new(20_000_000, Duration::from_millis(30))
+        };
+
+        let run = |seed| {
+            let mut link = Link::new(config, seed, now);
+
+            (0..10_000)
+                .map(|i| {
+                    link.transmit(1200, now + Duration::from_micros(i * 400))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn qlog() {
+        use std::sync::Arc;
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let link = LinkConfig::new(10_000_000, Duration::from_millis(10));
+        let mut sim = simulation("cubic", link, 0);
+
+        let client_log = SharedBuf::default();
+        let server_log = SharedBuf::default();
+
+        sim.set_qlog(Box::new(client_log.clone()), Box::new(server_log.clone()));
+
+        let report = sim.bulk_transfer(100_000, Duration::from_secs(10)).unwrap();
+
+        assert!(report.complete);
+
+        let server_log =
+            String::from_utf8(server_log.0.lock().unwrap().clone()).unwrap();
+
+        assert!(server_log.contains("netsim server"));
+        assert!(server_log.contains("recovery:metrics_updated"));
+
+        assert!(!client_log.0.lock().unwrap().is_empty());
+    }
+}
@@ -37,9 +37,11 @@ use crate::Result;
 use crate::DEFAULT_INITIAL_CONGESTION_WINDOW_PACKETS;
 
 use crate::crypto;
+use crate::frame;
 use crate::rand;
 use crate::ranges;
 use crate::recovery;
+use crate::snapshot;
 use crate::stream;
 
 const FORM_BIT: u8 = 0x80;
@@ -163,6 +165,46 @@ impl Type {
         }
     }
 
+    /// Decodes the long header packet type bits for the given version.
+    fn from_long_header_bits(version: u32, bits: u8) -> Result<Type> {
+        // QUIC version 2 uses different codepoints for the long header packet
+        // types, see RFC 9369 Section 3.2.
+        let ty = match (version, bits) {
+            (crate::PROTOCOL_VERSION_V2, 0x00) => Type::Retry,
+            (crate::PROTOCOL_VERSION_V2, 0x01) => Type::Initial,
+            (crate::PROTOCOL_VERSION_V2, 0x02) => Type::ZeroRTT,
+            (crate::PROTOCOL_VERSION_V2, 0x03) => Type::Handshake,
+
+            (_, 0x00) => Type::Initial,
+            (_, 0x01) => Type::ZeroRTT,
+            (_, 0x02) => Type::Handshake,
+            (_, 0x03) => Type::Retry,
+
+            _ => return Err(Error::InvalidPacket),
+        };
+
+        Ok(ty)
+    }
+
+    /// Encodes the long header packet type bits for the given version.
+    fn to_long_header_bits(self, version: u32) -> Result<u8> {
+        let bits = match (version, self) {
+            (crate::PROTOCOL_VERSION_V2, Type::Retry) => 0x00,
+            (crate::PROTOCOL_VERSION_V2, Type::Initial) => 0x01,
+            (crate::PROTOCOL_VERSION_V2, Type::ZeroRTT) => 0x02,
+            (crate::PROTOCOL_VERSION_V2, Type::Handshake) => 0x03,
+
+            (_, Type::Initial) => 0x00,
+            (_, Type::ZeroRTT) => 0x01,
+            (_, Type::Handshake) => 0x02,
+            (_, Type::Retry) => 0x03,
+
+            _ => return Err(Error::InvalidPacket),
+        };
+
+        Ok(bits)
+    }
+
     #[cfg(feature = "qlog")]
     pub(crate) fn to_qlog(self) -> qlog::events::quic::PacketType {
         match self {
@@ -380,13 +422,7 @@ impl<'a> Header<'a> {
         let ty = if version == 0 {
             Type::VersionNegotiation
         } else {
-            match (first & TYPE_MASK) >> 4 {
-                0x00 => Type::Initial,
-                0x01 => Type::ZeroRTT,
-                0x02 => Type::Handshake,
-                0x03 => Type::Retry,
-                _ => return Err(Error::InvalidPacket),
-            }
+            Type::from_long_header_bits(version, (first & TYPE_MASK) >> 4)?
         };
 
         let dcid_len = b.get_u8()?;
@@ -478,13 +514,7 @@ impl<'a> Header<'a> {
         }
 
         // Encode long header.
-        let ty: u8 = match self.ty {
-            Type::Initial => 0x00,
-            Type::ZeroRTT => 0x01,
-            Type::Handshake => 0x02,
-            Type::Retry => 0x03,
-            _ => return Err(Error::InvalidPacket),
-        };
+        let ty = self.ty.to_long_header_bits(self.version)?;
 
         first |= FORM_BIT | FIXED_BIT | (ty << 4);
 
@@ -654,6 +684,14 @@ pub fn decode_pkt_num(largest_pn: u64, truncated_pn: u64, pn_len: usize) -> u64
 pub fn decrypt_pkt<'a>(
     b: &'a mut octets::OctetsMut, pn: u64, pn_len: usize, payload_len: usize,
     aead: &crypto::Open,
+) -> Result<octets::Octets<'a>> {
+    decrypt_pkt_on_path(b, 0, pn, pn_len, payload_len, aead)
+}
+
+/// Decrypts a packet received on the given multipath path identifier.
+pub fn decrypt_pkt_on_path<'a>(
+    b: &'a mut octets::OctetsMut, path_id: u32, pn: u64, pn_len: usize,
+    payload_len: usize, aead: &crypto::Open,
 ) -> Result<octets::Octets<'a>> {
     let payload_offset = b.off();
 
@@ -665,8 +703,12 @@ pub fn decrypt_pkt<'a>(
 
     let mut ciphertext = payload.peek_bytes_mut(payload_len)?;
 
-    let payload_len =
-        aead.open_with_u64_counter(pn, header.as_ref(), ciphertext.as_mut())?;
+    let payload_len = aead.open_with_path_id(
+        path_id,
+        pn,
+        header.as_ref(),
+        ciphertext.as_mut(),
+    )?;
 
     Ok(b.get_bytes(payload_len)?)
 }
@@ -700,10 +742,30 @@ pub fn encrypt_hdr(
 pub fn encrypt_pkt(
     b: &mut octets::OctetsMut, pn: u64, pn_len: usize, payload_len: usize,
     payload_offset: usize, extra_in: Option<&[u8]>, aead: &crypto::Seal,
+) -> Result<usize> {
+    encrypt_pkt_on_path(
+        b,
+        0,
+        pn,
+        pn_len,
+        payload_len,
+        payload_offset,
+        extra_in,
+        aead,
+    )
+}
+
+/// Encrypts a packet sent on the given multipath path identifier.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_pkt_on_path(
+    b: &mut octets::OctetsMut, path_id: u32, pn: u64, pn_len: usize,
+    payload_len: usize, payload_offset: usize, extra_in: Option<&[u8]>,
+    aead: &crypto::Seal,
 ) -> Result<usize> {
     let (mut header, mut payload) = b.split_at(payload_offset)?;
 
-    let ciphertext_len = aead.seal_with_u64_counter(
+    let ciphertext_len = aead.seal_with_path_id(
+        path_id,
         pn,
         header.as_ref(),
         payload.as_mut(),
@@ -749,6 +811,7 @@ pub fn negotiate_version(
     b.put_u8(dcid.len() as u8)?;
     b.put_bytes(dcid)?;
     b.put_u32(crate::PROTOCOL_VERSION_V1)?;
+    b.put_u32(crate::PROTOCOL_VERSION_V2)?;
 
     Ok(b.off())
 }
@@ -809,10 +872,22 @@ fn compute_retry_integrity_tag(
         0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
     ];
 
+    const RETRY_INTEGRITY_KEY_V2: [u8; KEY_LEN] = [
+        0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce,
+        0xad, 0x7c, 0xcc, 0x92,
+    ];
+
+    const RETRY_INTEGRITY_NONCE_V2: [u8; crypto::MAX_NONCE_LEN] = [
+        0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+    ];
+
     let (key, nonce) = match version {
         crate::PROTOCOL_VERSION_V1 =>
             (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
 
+        crate::PROTOCOL_VERSION_V2 =>
+            (&RETRY_INTEGRITY_KEY_V2, RETRY_INTEGRITY_NONCE_V2),
+
         _ => (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
     };
 
@@ -835,7 +910,8 @@ fn compute_retry_integrity_tag(
 
     let mut out_tag = vec![0_u8; TAG_LEN];
 
-    let out_len = key.seal_with_u64_counter(0, &pseudo, &mut out_tag, 0, None)?;
+    let out_len =
+        key.seal_with_u64_counter(0, 0, &pseudo, &mut out_tag, 0, None)?;
 
     // Ensure that the output only contains the AEAD tag.
     if out_len != out_tag.len() {
@@ -865,8 +941,9 @@ pub struct PktNumSpace {
     /// The largest packet number received.
     pub largest_rx_pkt_num: u64,
 
-    /// Time the largest packet number received.
-    pub largest_rx_pkt_time: Instant,
+    /// Time the largest packet number that still needs to be acked was
+    /// received, if any.
+    pub largest_rx_pkt_time: Option<Instant>,
 
     /// The largest non-probing packet number.
     pub largest_rx_non_probing_pkt_num: u64,
@@ -882,18 +959,26 @@ pub struct PktNumSpace {
 
     /// Track if a received packet is ack eliciting.
     pub ack_elicited: bool,
+
+    /// ECN counts of the received packets, reported in ACK frames.
+    pub recv_ecn_counts: frame::EcnCounts,
+
+    /// The largest ECN counts reported by the peer.
+    pub peer_ecn_counts: frame::EcnCounts,
 }
 
 impl PktNumSpace {
     pub fn new() -> PktNumSpace {
         PktNumSpace {
             largest_rx_pkt_num: 0,
-            largest_rx_pkt_time: Instant::now(),
+            largest_rx_pkt_time: None,
             largest_rx_non_probing_pkt_num: 0,
             largest_tx_pkt_num: None,
             recv_pkt_need_ack: ranges::RangeSet::new(crate::MAX_ACK_RANGES),
             recv_pkt_num: PktNumWindow::default(),
             ack_elicited: false,
+            recv_ecn_counts: frame::EcnCounts::default(),
+            peer_ecn_counts: frame::EcnCounts::default(),
         }
     }
 
@@ -910,6 +995,48 @@ impl PktNumSpace {
         self.largest_tx_pkt_num =
             self.largest_tx_pkt_num.max(Some(sent_pkt.pkt_num));
     }
+
+    /// Writes the packet number space to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_u64(self.largest_rx_pkt_num);
+        w.put_u64(self.largest_rx_non_probing_pkt_num);
+        w.put_opt_u64(self.largest_tx_pkt_num);
+        w.put_ranges(&self.recv_pkt_need_ack);
+        w.put_u64(self.recv_pkt_num.lower);
+        w.put_u128(self.recv_pkt_num.window);
+        w.put_bool(self.ack_elicited);
+
+        for counts in [&self.recv_ecn_counts, &self.peer_ecn_counts] {
+            w.put_u64(counts.ect0_count);
+            w.put_u64(counts.ect1_count);
+            w.put_u64(counts.ecn_ce_count);
+        }
+    }
+
+    /// Reads a packet number space written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.PktNumSpace.html#method.to_snapshot
+    pub fn from_snapshot(r: &mut snapshot::Reader) -> Result<PktNumSpace> {
+        let mut space = PktNumSpace::new();
+
+        space.largest_rx_pkt_num = r.get_u64()?;
+        space.largest_rx_non_probing_pkt_num = r.get_u64()?;
+        space.largest_tx_pkt_num = r.get_opt_u64()?;
+        space.recv_pkt_need_ack = r.get_ranges(crate::MAX_ACK_RANGES)?;
+        space.recv_pkt_num = PktNumWindow {
+            lower: r.get_u64()?,
+            window: r.get_u128()?,
+        };
+        space.ack_elicited = r.get_bool()?;
+
+        for counts in [&mut space.recv_ecn_counts, &mut space.peer_ecn_counts] {
+            counts.ect0_count = r.get_u64()?;
+            counts.ect1_count = r.get_u64()?;
+            counts.ecn_ce_count = r.get_u64()?;
+        }
+
+        Ok(space)
+    }
 }
 
 pub struct CryptoContext {
@@ -1732,7 +1859,9 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Open::from_secret(alg, &secret).unwrap();
+        let aead =
+            crypto::Open::from_secret(alg, crate::PROTOCOL_VERSION_V1, &secret)
+                .unwrap();
 
         let mut hdr = Header::from_bytes(&mut b, 0).unwrap();
         assert_eq!(hdr.ty, Type::Short);
@@ -2077,6 +2206,143 @@ mod tests {
         assert_encrypt_initial_pkt(&mut header, &dcid, &frames, 1, 2, true, &pkt);
     }
 
+    #[test]
+    fn decrypt_server_initial_v2() {
+        let mut pkt = [
+            0xdc, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x00, 0x40, 0x75, 0xd9, 0x2f, 0xaa, 0xf1,
+            0x6f, 0x05, 0xd8, 0xa4, 0x39, 0x8c, 0x47, 0x08, 0x96, 0x98, 0xba,
+            0xee, 0xa2, 0x6b, 0x91, 0xeb, 0x76, 0x1d, 0x9b, 0x89, 0x23, 0x7b,
+            0xbf, 0x87, 0x26, 0x30, 0x17, 0x91, 0x53, 0x58, 0x23, 0x00, 0x35,
+            0xf7, 0xfd, 0x39, 0x45, 0xd8, 0x89, 0x65, 0xcf, 0x17, 0xf9, 0xaf,
+            0x6e, 0x16, 0x88, 0x6c, 0x61, 0xbf, 0xc7, 0x03, 0x10, 0x6f, 0xba,
+            0xf3, 0xcb, 0x4c, 0xfa, 0x52, 0x38, 0x2d, 0xd1, 0x6a, 0x39, 0x3e,
+            0x42, 0x75, 0x75, 0x07, 0x69, 0x80, 0x75, 0xb2, 0xc9, 0x84, 0xc7,
+            0x07, 0xf0, 0xa0, 0x81, 0x2d, 0x8c, 0xd5, 0xa6, 0x88, 0x1e, 0xaf,
+            0x21, 0xce, 0xda, 0x98, 0xf4, 0xbd, 0x23, 0xf6, 0xfe, 0x1a, 0x3e,
+            0x2c, 0x43, 0xed, 0xd9, 0xce, 0x7c, 0xa8, 0x4b, 0xed, 0x85, 0x21,
+            0xe2, 0xe1, 0x40,
+        ];
+
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let frames = [
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x40, 0x5a, 0x02, 0x00,
+            0x00, 0x56, 0x03, 0x03, 0xee, 0xfc, 0xe7, 0xf7, 0xb3, 0x7b, 0xa1,
+            0xd1, 0x63, 0x2e, 0x96, 0x67, 0x78, 0x25, 0xdd, 0xf7, 0x39, 0x88,
+            0xcf, 0xc7, 0x98, 0x25, 0xdf, 0x56, 0x6d, 0xc5, 0x43, 0x0b, 0x9a,
+            0x04, 0x5a, 0x12, 0x00, 0x13, 0x01, 0x00, 0x00, 0x2e, 0x00, 0x33,
+            0x00, 0x24, 0x00, 0x1d, 0x00, 0x20, 0x9d, 0x3c, 0x94, 0x0d, 0x89,
+            0x69, 0x0b, 0x84, 0xd0, 0x8a, 0x60, 0x99, 0x3c, 0x14, 0x4e, 0xca,
+            0x68, 0x4d, 0x10, 0x81, 0x28, 0x7c, 0x83, 0x4d, 0x53, 0x11, 0xbc,
+            0xf3, 0x2b, 0xb9, 0xda, 0x1a, 0x00, 0x2b, 0x00, 0x02, 0x03, 0x04,
+        ];
+
+        assert_decrypt_initial_pkt(&mut pkt, &dcid, false, &frames, 1, 2);
+    }
+
+    #[test]
+    fn encrypt_server_initial_v2() {
+        let mut header = [
+            0xd1, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x00, 0x40, 0x75, 0x00, 0x01,
+        ];
+
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let frames = [
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00, 0x40, 0x5a, 0x02, 0x00,
+            0x00, 0x56, 0x03, 0x03, 0xee, 0xfc, 0xe7, 0xf7, 0xb3, 0x7b, 0xa1,
+            0xd1, 0x63, 0x2e, 0x96, 0x67, 0x78, 0x25, 0xdd, 0xf7, 0x39, 0x88,
+            0xcf, 0xc7, 0x98, 0x25, 0xdf, 0x56, 0x6d, 0xc5, 0x43, 0x0b, 0x9a,
+            0x04, 0x5a, 0x12, 0x00, 0x13, 0x01, 0x00, 0x00, 0x2e, 0x00, 0x33,
+            0x00, 0x24, 0x00, 0x1d, 0x00, 0x20, 0x9d, 0x3c, 0x94, 0x0d, 0x89,
+            0x69, 0x0b, 0x84, 0xd0, 0x8a, 0x60, 0x99, 0x3c, 0x14, 0x4e, 0xca,
+            0x68, 0x4d, 0x10, 0x81, 0x28, 0x7c, 0x83, 0x4d, 0x53, 0x11, 0xbc,
+            0xf3, 0x2b, 0xb9, 0xda, 0x1a, 0x00, 0x2b, 0x00, 0x02, 0x03, 0x04,
+        ];
+
+        let pkt = [
+            0xdc, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x00, 0x40, 0x75, 0xd9, 0x2f, 0xaa, 0xf1,
+            0x6f, 0x05, 0xd8, 0xa4, 0x39, 0x8c, 0x47, 0x08, 0x96, 0x98, 0xba,
+            0xee, 0xa2, 0x6b, 0x91, 0xeb, 0x76, 0x1d, 0x9b, 0x89, 0x23, 0x7b,
+            0xbf, 0x87, 0x26, 0x30, 0x17, 0x91, 0x53, 0x58, 0x23, 0x00, 0x35,
+            0xf7, 0xfd, 0x39, 0x45, 0xd8, 0x89, 0x65, 0xcf, 0x17, 0xf9, 0xaf,
+            0x6e, 0x16, 0x88, 0x6c, 0x61, 0xbf, 0xc7, 0x03, 0x10, 0x6f, 0xba,
+            0xf3, 0xcb, 0x4c, 0xfa, 0x52, 0x38, 0x2d, 0xd1, 0x6a, 0x39, 0x3e,
+            0x42, 0x75, 0x75, 0x07, 0x69, 0x80, 0x75, 0xb2, 0xc9, 0x84, 0xc7,
+            0x07, 0xf0, 0xa0, 0x81, 0x2d, 0x8c, 0xd5, 0xa6, 0x88, 0x1e, 0xaf,
+            0x21, 0xce, 0xda, 0x98, 0xf4, 0xbd, 0x23, 0xf6, 0xfe, 0x1a, 0x3e,
+            0x2c, 0x43, 0xed, 0xd9, 0xce, 0x7c, 0xa8, 0x4b, 0xed, 0x85, 0x21,
+            0xe2, 0xe1, 0x40,
+        ];
+
+        assert_encrypt_initial_pkt(&mut header, &dcid, &frames, 1, 2, true, &pkt);
+    }
+
+    #[test]
+    fn retry_integrity_v2() {
+        let mut pkt = [
+            0xcf, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e, 0xc8, 0x64,
+            0x6c, 0xe8, 0xbf, 0xe3, 0x39, 0x52, 0xd9, 0x55, 0x54, 0x36, 0x65,
+            0xdc, 0xc7, 0xb6,
+        ];
+
+        let odcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+
+        let hdr = Header::from_bytes(&mut b, 0).unwrap();
+        assert_eq!(hdr.ty, Type::Retry);
+        assert_eq!(hdr.version, crate::PROTOCOL_VERSION_V2);
+        assert_eq!(hdr.token, Some(b"token".to_vec()));
+
+        assert!(verify_retry_integrity(&b, &odcid, hdr.version).is_ok());
+
+        // The version 1 retry integrity key doesn't match.
+        assert!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V1)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn long_header_types_v2() {
+        for ty in [Type::Initial, Type::ZeroRTT, Type::Handshake] {
+            let hdr = Header {
+                ty,
+                version: crate::PROTOCOL_VERSION_V2,
+                dcid: vec![0xba; 9].into(),
+                scid: vec![0xbb; 7].into(),
+                pkt_num: 0,
+                pkt_num_len: 0,
+                token: None,
+                versions: None,
+                key_phase: false,
+            };
+
+            let mut d = [0; 50];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert!(hdr.to_bytes(&mut b).is_ok());
+
+            // Version 2 uses different packet type codepoints than version 1.
+            let v1_ty = Type::from_long_header_bits(
+                crate::PROTOCOL_VERSION_V1,
+                (d[0] & TYPE_MASK) >> 4,
+            )
+            .unwrap();
+            assert_ne!(v1_ty, ty);
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            let decoded = Header::from_bytes(&mut b, 9).unwrap();
+            assert_eq!(decoded.ty, ty);
+            assert_eq!(decoded.version, crate::PROTOCOL_VERSION_V2);
+        }
+    }
+
     #[test]
     fn encrypt_chacha20() {
         let secret = [
@@ -2104,7 +2370,9 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Seal::from_secret(alg, &secret).unwrap();
+        let aead =
+            crypto::Seal::from_secret(alg, crate::PROTOCOL_VERSION_V1, &secret)
+                .unwrap();
 
         let pn = 654_360_564;
         let pn_len = 3;
@@ -2132,6 +2400,89 @@ mod tests {
         assert_eq!(&out[..written], &expected_pkt[..]);
     }
 
+    #[test]
+    fn encrypt_decrypt_pkt_on_path() {
+        let secret = [0x42; 32];
+
+        let alg = crypto::Algorithm::ChaCha20_Poly1305;
+
+        let seal =
+            crypto::Seal::from_secret(alg, crate::PROTOCOL_VERSION_V1, &secret)
+                .unwrap();
+        let open =
+            crypto::Open::from_secret(alg, crate::PROTOCOL_VERSION_V1, &secret)
+                .unwrap();
+
+        let pn = 7;
+        let pn_len = 2;
+
+        let hdr = Header {
+            ty: Type::Short,
+            version: 0,
+            dcid: ConnectionId::from_ref(&[0xba; 8]),
+            scid: ConnectionId::default(),
+            pkt_num: pn,
+            pkt_num_len: pn_len,
+            token: None,
+            versions: None,
+            key_phase: false,
+        };
+        let frames = [0x01; 20];
+
+        let mut pkt = [0; 128];
+
+        let written = {
+            let mut b = octets::OctetsMut::with_slice(&mut pkt);
+
+            hdr.to_bytes(&mut b).unwrap();
+            encode_pkt_num(pn, pn_len, &mut b).unwrap();
+
+            let payload_offset = b.off();
+
+            b.put_bytes(&frames).unwrap();
+
+            encrypt_pkt_on_path(
+                &mut b,
+                3,
+                pn,
+                pn_len,
+                frames.len(),
+                payload_offset,
+                None,
+                &seal,
+            )
+            .unwrap()
+        };
+
+        let assert_decrypt = |path_id: u32| {
+            let mut pkt = pkt;
+            let mut b = octets::OctetsMut::with_slice(&mut pkt[..written]);
+
+            let mut hdr = Header::from_bytes(&mut b, 8).unwrap();
+            let payload_len = b.cap();
+
+            decrypt_hdr(&mut b, &mut hdr, &open).unwrap();
+
+            let pn = decode_pkt_num(0, hdr.pkt_num, hdr.pkt_num_len);
+            assert_eq!(pn, 7);
+
+            decrypt_pkt_on_path(
+                &mut b,
+                path_id,
+                pn,
+                hdr.pkt_num_len,
+                payload_len,
+                &open,
+            )
+            .map(|payload| payload.to_vec())
+        };
+
+        // Packets are only decrypted using the path ID they were sent on.
+        assert_eq!(assert_decrypt(0), Err(Error::CryptoFail));
+        assert_eq!(assert_decrypt(1), Err(Error::CryptoFail));
+        assert_eq!(assert_decrypt(3), Ok(frames.to_vec()));
+    }
+
     #[test]
     fn decrypt_pkt_underflow() {
         let mut buf = [0; 65535];
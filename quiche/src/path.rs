@@ -41,6 +41,8 @@ use crate::Error;
 use crate::Result;
 use crate::StartupExit;
 
+use crate::ecn;
+use crate::packet;
 use crate::pmtud;
 use crate::recovery;
 use crate::recovery::Bandwidth;
@@ -134,6 +136,14 @@ pub struct Path {
     /// Destination CID sequence number used over that path.
     pub active_dcid_seq: Option<u64>,
 
+    /// The multipath path identifier of the path. Paths that share the
+    /// identifier 0 use the connection's regular CIDs and packet number
+    /// space.
+    pub multipath_id: u64,
+
+    /// Whether the path was abandoned with the multipath extension.
+    abandoned: bool,
+
     /// The current validation state of the path.
     state: PathState,
 
@@ -146,6 +156,9 @@ pub struct Path {
     /// Path MTU discovery state. None if PMTUD is disabled on the path.
     pub pmtud: Option<pmtud::Pmtud>,
 
+    /// ECN validation state.
+    pub ecn: ecn::EcnState,
+
     /// Pending challenge data with the size of the packet containing them and
     /// when they were sent.
     in_flight_challenges: VecDeque<([u8; 8], usize, Instant)>,
@@ -259,10 +272,16 @@ impl Path {
             peer_addr,
             active_scid_seq,
             active_dcid_seq,
+            multipath_id: 0,
+            abandoned: false,
             state,
             active: false,
             recovery: recovery::Recovery::new_with_config(recovery_config),
             pmtud,
+            ecn: ecn::EcnState::new(
+                config.is_some_and(|c| c.ecn),
+                recovery_config.l4s(),
+            ),
             in_flight_challenges: VecDeque::new(),
             max_challenge_size: 0,
             probing_lost: 0,
@@ -311,7 +330,10 @@ impl Path {
     /// Returns whether the path is active.
     #[inline]
     pub fn active(&self) -> bool {
-        self.active && self.working() && self.active_dcid_seq.is_some()
+        self.active &&
+            self.working() &&
+            self.active_dcid_seq.is_some() &&
+            !self.abandoned
     }
 
     /// Returns whether the path can be used to send non-probing packets.
@@ -319,14 +341,20 @@ impl Path {
     pub fn usable(&self) -> bool {
         self.active() ||
             (self.state == PathState::Validated &&
-                self.active_dcid_seq.is_some())
+                self.active_dcid_seq.is_some() &&
+                !self.abandoned)
     }
 
     /// Returns whether the path is unused.
     #[inline]
     fn unused(&self) -> bool {
         // FIXME: we should check that there is nothing in the sent queue.
-        !self.active() && self.active_dcid_seq.is_none()
+        !self.active() &&
+            self.active_dcid_seq.is_none() &&
+            // The frames sent on abandoned paths are retransmitted on other
+            // paths.
+            !(self.abandoned &&
+                self.recovery.has_lost_frames(packet::Epoch::Application))
     }
 
     /// Returns whether the path requires sending a probing packet.
@@ -351,7 +379,7 @@ impl Path {
 
     /// Returns whether this path failed its validation.
     #[inline]
-    fn validation_failed(&self) -> bool {
+    pub fn validation_failed(&self) -> bool {
         self.state == PathState::Failed
     }
 
@@ -361,6 +389,20 @@ impl Path {
         matches!(self.state, PathState::Validating | PathState::ValidatingMTU)
     }
 
+    /// Marks the path as abandoned, so that it is not used anymore.
+    pub fn abandon(&mut self) {
+        self.abandoned = true;
+        self.active = false;
+        self.challenge_requested = false;
+        self.received_challenges.clear();
+    }
+
+    /// Returns whether the path was abandoned.
+    #[inline]
+    pub fn abandoned(&self) -> bool {
+        self.abandoned
+    }
+
     /// Requests path validation.
     #[inline]
     pub fn request_validation(&mut self) {
@@ -472,6 +514,8 @@ impl Path {
             trace_id,
         );
 
+        self.on_ecn_marked_packets_lost();
+
         let mut lost_probe_time = None;
         self.in_flight_challenges.retain(|(_, _, sent_time)| {
             if *sent_time <= now {
@@ -537,7 +581,8 @@ impl Path {
     pub fn reinit_recovery(
         &mut self, recovery_config: &recovery::RecoveryConfig,
     ) {
-        self.recovery = recovery::Recovery::new_with_config(recovery_config)
+        self.recovery = recovery::Recovery::new_with_config(recovery_config);
+        self.ecn.set_l4s(recovery_config.l4s());
     }
 
     pub fn stats(&self) -> PathStats {
@@ -575,9 +620,22 @@ impl Path {
                 .max_bandwidth()
                 .map(Bandwidth::to_bytes_per_second),
             startup_exit: self.recovery.startup_exit(),
+            ecn_capable: self.ecn.is_capable(),
+            ecn_ect0_sent_count: self.ecn.ect0_sent(),
+            ecn_ect1_sent_count: self.ecn.ect1_sent(),
+            ecn_ce_reported_count: self.ecn.ce_reported(),
         }
     }
 
+    /// Fails ECN validation if all the ECN-marked packets sent on the path
+    /// were lost.
+    pub fn on_ecn_marked_packets_lost(&mut self) {
+        self.ecn.on_marked_packets_lost(
+            self.recovery.ecn_marked_lost(),
+            self.recovery.ecn_marked_acked(),
+        );
+    }
+
     pub fn bytes_in_flight_duration(&self) -> Duration {
         self.recovery.bytes_in_flight_duration()
     }
@@ -763,7 +821,10 @@ impl PathMap {
         self.addrs_to_paths
             .remove(&(path.local_addr, path.peer_addr));
 
-        self.notify_event(PathEvent::Closed(path.local_addr, path.peer_addr));
+        // Abandoned paths were already notified.
+        if !path.abandoned {
+            self.notify_event(PathEvent::Closed(path.local_addr, path.peer_addr));
+        }
 
         Ok(())
     }
@@ -1008,11 +1069,25 @@ pub struct PathStats {
     /// The maximum bandwidth estimate for the connection in bytes/s.
     ///
     /// Note: not all congestion control algorithms provide this metric;
-    /// it is currently only implemented for bbr2_gcongestion.
+    /// it is currently only implemented for bbr2_gcongestion,
+    /// bbr3_gcongestion, prague and ledbat.
     pub max_bandwidth: Option<u64>,
 
     /// Statistics from when a CCA first exited the startup phase.
     pub startup_exit: Option<StartupExit>,
+
+    /// Whether the path was validated as supporting ECN.
+    pub ecn_capable: bool,
+
+    /// The number of QUIC packets sent with the ECT(0) codepoint.
+    pub ecn_ect0_sent_count: usize,
+
+    /// The number of QUIC packets sent with the ECT(1) codepoint, when the
+    /// congestion control algorithm supports L4S.
+    pub ecn_ect1_sent_count: usize,
+
+    /// The number of ECN-CE marks reported by the peer.
+    pub ecn_ce_reported_count: u64,
 }
 
 impl std::fmt::Debug for PathStats {
@@ -1044,6 +1119,15 @@ impl std::fmt::Debug for PathStats {
             f,
             " stream_retrans_bytes={} pmtu={} delivery_rate={}",
             self.stream_retrans_bytes, self.pmtu, self.delivery_rate,
+        )?;
+
+        write!(
+            f,
+            " ecn_capable={} ecn_ect0_sent={} ecn_ect1_sent={} ecn_ce_reported={}",
+            self.ecn_capable,
+            self.ecn_ect0_sent_count,
+            self.ecn_ect1_sent_count,
+            self.ecn_ce_reported_count,
         )
     }
 }
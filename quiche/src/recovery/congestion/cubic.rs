@@ -38,7 +38,6 @@ use std::time::Instant;
 
 use super::rtt::RttStats;
 use super::Acked;
-use super::Sent;
 
 use super::reno;
 use super::Congestion;
@@ -330,9 +329,8 @@ fn on_packet_acked(
 
 fn congestion_event(
     r: &mut Congestion, bytes_in_flight: usize, _lost_bytes: usize,
-    largest_lost_pkt: &Sent, now: Instant,
+    time_sent: Instant, now: Instant,
 ) {
-    let time_sent = largest_lost_pkt.time_sent;
     let in_congestion_recovery = r.in_congestion_recovery(time_sent);
 
     // Start a new congestion event if packet was sent after the
@@ -41,9 +41,11 @@ use super::Sent;
 pub struct Rate {
     delivered: usize,
 
-    delivered_time: Instant,
+    // Not set until the first packet is sent.
+    delivered_time: Option<Instant>,
 
-    first_sent_time: Instant,
+    // Not set until the first packet is sent.
+    first_sent_time: Option<Instant>,
 
     // Packet number of the last sent packet with app limited.
     end_of_app_limited: u64,
@@ -60,14 +62,12 @@ pub struct Rate {
 
 impl Default for Rate {
     fn default() -> Self {
-        let now = Instant::now();
-
         Rate {
             delivered: 0,
 
-            delivered_time: now,
+            delivered_time: None,
 
-            first_sent_time: now,
+            first_sent_time: None,
 
             end_of_app_limited: 0,
 
@@ -86,12 +86,12 @@ impl Rate {
     ) {
         // No packets in flight.
         if bytes_in_flight == 0 {
-            self.first_sent_time = pkt.time_sent;
-            self.delivered_time = pkt.time_sent;
+            self.first_sent_time = Some(pkt.time_sent);
+            self.delivered_time = Some(pkt.time_sent);
         }
 
-        pkt.first_sent_time = self.first_sent_time;
-        pkt.delivered_time = self.delivered_time;
+        pkt.first_sent_time = self.first_sent_time.unwrap_or(pkt.time_sent);
+        pkt.delivered_time = self.delivered_time.unwrap_or(pkt.time_sent);
         pkt.delivered = self.delivered;
         pkt.is_app_limited = self.app_limited();
         pkt.tx_in_flight = bytes_in_flight;
@@ -103,7 +103,7 @@ impl Rate {
     // Update the delivery rate sample when a packet is acked.
     pub fn update_rate_sample(&mut self, pkt: &Acked, now: Instant) {
         self.delivered += pkt.size;
-        self.delivered_time = now;
+        self.delivered_time = Some(now);
 
         // Update info using the newest packet. If rate_sample is not yet
         // initialized, initialize with the first packet.
@@ -116,11 +116,10 @@ impl Rate {
             self.rate_sample.send_elapsed =
                 pkt.time_sent.saturating_duration_since(pkt.first_sent_time);
             self.rate_sample.rtt = pkt.rtt;
-            self.rate_sample.ack_elapsed = self
-                .delivered_time
-                .saturating_duration_since(pkt.delivered_time);
+            self.rate_sample.ack_elapsed =
+                now.saturating_duration_since(pkt.delivered_time);
 
-            self.first_sent_time = pkt.time_sent;
+            self.first_sent_time = Some(pkt.time_sent);
         }
 
         self.largest_acked = self.largest_acked.max(pkt.pkt_num);
@@ -261,7 +261,7 @@ pub(crate) struct CongestionControlOps {
         r: &mut Congestion,
         bytes_in_flight: usize,
         lost_bytes: usize,
-        largest_lost_packet: &Sent,
+        largest_lost_time_sent: Instant,
         now: Instant,
     ),
 
@@ -284,11 +284,14 @@ impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
             CongestionControlAlgorithm::Reno => &reno::RENO,
             CongestionControlAlgorithm::CUBIC => &cubic::CUBIC,
             CongestionControlAlgorithm::None => &none::NONE,
-            // Bbr2Gcongestion is routed to the congestion implementation in
-            // the gcongestion directory by Recovery::new_with_config;
-            // LegacyRecovery never gets a RecoveryConfig with the
-            // Bbr2Gcongestion algorithm.
-            CongestionControlAlgorithm::Bbr2Gcongestion => unreachable!(),
+            // Bbr2Gcongestion, Bbr3Gcongestion, Prague and Ledbat are routed to
+            // the congestion implementation in the gcongestion directory by
+            // Recovery::new_with_config; LegacyRecovery never gets a
+            // RecoveryConfig with those algorithms.
+            CongestionControlAlgorithm::Bbr2Gcongestion |
+            CongestionControlAlgorithm::Bbr3Gcongestion |
+            CongestionControlAlgorithm::Prague |
+            CongestionControlAlgorithm::Ledbat => unreachable!(),
         }
     }
 }
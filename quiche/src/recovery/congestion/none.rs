@@ -36,7 +36,6 @@ use super::rtt::RttStats;
 use super::Acked;
 use super::Congestion;
 use super::CongestionControlOps;
-use super::Sent;
 
 pub(crate) static NONE: CongestionControlOps = CongestionControlOps {
     on_init,
@@ -69,7 +68,7 @@ fn on_packets_acked(
 
 fn congestion_event(
     _r: &mut Congestion, _bytes_in_flight: usize, _lost_bytes: usize,
-    _largest_lost_pkt: &Sent, _now: Instant,
+    _time_sent: Instant, _now: Instant,
 ) {
 }
 
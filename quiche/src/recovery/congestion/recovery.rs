@@ -76,6 +76,9 @@ struct RecoveryEpoch {
     /// far.
     largest_acked_packet: Option<u64>,
 
+    /// The time the largest acknowledged packet was sent.
+    largest_acked_packet_time_sent: Option<Instant>,
+
     /// The time at which the next packet in that packet number space can be
     /// considered lost based on exceeding the reordering window in time.
     loss_time: Option<Instant>,
@@ -101,6 +104,7 @@ struct AckedDetectionResult {
     spurious_pkt_thresh: Option<u64>,
     has_ack_eliciting: bool,
     has_in_flight_spurious_loss: bool,
+    ecn_marked_acked: usize,
 }
 
 struct LossDetectionResult {
@@ -108,6 +112,7 @@ struct LossDetectionResult {
     lost_packets: usize,
     lost_bytes: usize,
     pmtud_lost_bytes: usize,
+    ecn_marked_lost: usize,
 }
 
 impl RecoveryEpoch {
@@ -124,6 +129,7 @@ impl RecoveryEpoch {
         let mut spurious_pkt_thresh = None;
         let mut has_ack_eliciting = false;
         let mut has_in_flight_spurious_loss = false;
+        let mut ecn_marked_acked = 0;
 
         let largest_ack_received = peer_sent_ack_ranges
             .last()
@@ -199,6 +205,11 @@ impl RecoveryEpoch {
                         .extend(std::mem::take(&mut unacked.frames));
 
                     has_ack_eliciting |= unacked.ack_eliciting;
+
+                    if unacked.ecn_marked {
+                        ecn_marked_acked += 1;
+                    }
+
                     unacked.time_acked = Some(now);
                 }
             }
@@ -212,6 +223,7 @@ impl RecoveryEpoch {
             spurious_pkt_thresh,
             has_ack_eliciting,
             has_in_flight_spurious_loss,
+            ecn_marked_acked,
         })
     }
 
@@ -231,6 +243,7 @@ impl RecoveryEpoch {
         let mut lost_packets = 0;
         let mut lost_bytes = 0;
         let mut pmtud_lost_bytes = 0;
+        let mut ecn_marked_lost = 0;
 
         let mut largest_lost_pkt = None;
 
@@ -250,6 +263,10 @@ impl RecoveryEpoch {
 
                 unacked.time_lost = Some(now);
 
+                if unacked.ecn_marked {
+                    ecn_marked_lost += 1;
+                }
+
                 if unacked.is_pmtud_probe {
                     pmtud_lost_bytes += unacked.size;
                     self.in_flight_count -= 1;
@@ -294,6 +311,7 @@ impl RecoveryEpoch {
             lost_packets,
             lost_bytes,
             pmtud_lost_bytes,
+            ecn_marked_lost,
         }
     }
 
@@ -358,6 +376,12 @@ pub struct LegacyRecovery {
 
     /// A resusable list of acks.
     newly_acked: Vec<Acked>,
+
+    /// The number of ECN-marked packets that were acknowledged.
+    ecn_marked_acked: usize,
+
+    /// The number of ECN-marked packets that were declared lost.
+    ecn_marked_lost: usize,
 }
 
 impl LegacyRecovery {
@@ -399,6 +423,10 @@ impl LegacyRecovery {
             congestion: Congestion::from_config(recovery_config),
 
             newly_acked: Vec::new(),
+
+            ecn_marked_acked: 0,
+
+            ecn_marked_lost: 0,
         }
     }
 
@@ -519,7 +547,7 @@ impl LegacyRecovery {
                 &mut self.congestion,
                 self.bytes_in_flight.get(),
                 loss.lost_bytes,
-                &pkt,
+                pkt.time_sent,
                 now,
             );
 
@@ -534,6 +562,7 @@ impl LegacyRecovery {
             .drain_acked_and_lost_packets(now - self.rtt_stats.rtt());
 
         self.congestion.lost_count += loss.lost_packets;
+        self.ecn_marked_lost += loss.ecn_marked_lost;
 
         (loss.lost_packets, loss.lost_bytes)
     }
@@ -643,6 +672,7 @@ impl RecoveryOps for LegacyRecovery {
             spurious_pkt_thresh,
             has_ack_eliciting,
             has_in_flight_spurious_loss,
+            ecn_marked_acked,
         } = self.epochs[epoch].detect_and_remove_acked_packets(
             now,
             peer_sent_ack_ranges,
@@ -653,6 +683,7 @@ impl RecoveryOps for LegacyRecovery {
         )?;
 
         self.lost_spurious_count += spurious_losses;
+        self.ecn_marked_acked += ecn_marked_acked;
         if let Some(thresh) = spurious_pkt_thresh {
             self.pkt_thresh =
                 self.pkt_thresh.max(thresh.min(MAX_PACKET_THRESHOLD));
@@ -678,6 +709,11 @@ impl RecoveryOps for LegacyRecovery {
             .max(largest_newly_acked.pkt_num);
         self.epochs[epoch].largest_acked_packet = Some(largest_acked_pkt_num);
 
+        if largest_newly_acked.pkt_num == largest_acked_pkt_num {
+            self.epochs[epoch].largest_acked_packet_time_sent =
+                Some(largest_newly_acked.time_sent);
+        }
+
         // Check if largest packet is newly acked.
         if largest_newly_acked.pkt_num == largest_acked_pkt_num &&
             has_ack_eliciting
@@ -780,8 +816,17 @@ impl RecoveryOps for LegacyRecovery {
         // This will also trigger sending an ACK and retransmitting frames like
         // HANDSHAKE_DONE and MAX_DATA / MAX_STREAM_DATA as well, in addition
         // to CRYPTO and STREAM, if the original packet carried them.
+        //
+        // DATAGRAM frames are never retransmitted, and the packet carrying
+        // them is not lost (yet), so skip them.
         for unacked in unacked_iter {
-            epoch.lost_frames.extend_from_slice(&unacked.frames);
+            epoch.lost_frames.extend(
+                unacked
+                    .frames
+                    .iter()
+                    .filter(|f| !matches!(f, frame::Frame::DatagramHeader { .. }))
+                    .cloned(),
+            );
         }
 
         self.set_loss_detection_timer(handshake_status, now);
@@ -821,6 +866,38 @@ impl RecoveryOps for LegacyRecovery {
         self.set_loss_detection_timer(handshake_status, now);
     }
 
+    fn on_path_abandoned(
+        &mut self, epoch: Epoch, handshake_status: HandshakeStatus, now: Instant,
+    ) {
+        let unacked_frames = self.epochs[epoch]
+            .sent_packets
+            .iter_mut()
+            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none())
+            .flat_map(|p| p.frames.drain(..))
+            .collect::<Vec<_>>();
+
+        let lost_frames = std::mem::take(&mut self.epochs[epoch].lost_frames);
+
+        self.on_pkt_num_space_discarded(epoch, handshake_status, now);
+
+        let epoch = &mut self.epochs[epoch];
+        epoch.lost_frames = lost_frames;
+        epoch.lost_frames.extend(unacked_frames);
+    }
+
+    fn unacked_frames(&self, epoch: Epoch) -> Vec<frame::Frame> {
+        let epoch = &self.epochs[epoch];
+
+        epoch
+            .sent_packets
+            .iter()
+            .filter(|p| p.time_acked.is_none() && p.time_lost.is_none())
+            .flat_map(|p| p.frames.iter())
+            .chain(epoch.lost_frames.iter())
+            .cloned()
+            .collect()
+    }
+
     fn on_path_change(
         &mut self, epoch: Epoch, now: Instant, trace_id: &str,
     ) -> (usize, usize) {
@@ -962,6 +1039,11 @@ impl RecoveryOps for LegacyRecovery {
         // delivery_rate_update_app_limited used instead.
     }
 
+    fn ack_eliciting_threshold(&self) -> Option<u64> {
+        // Legacy congestion controllers don't request a custom ACK frequency.
+        None
+    }
+
     #[cfg(test)]
     fn largest_sent_pkt_num_on_path(&self, epoch: Epoch) -> Option<u64> {
         self.epochs[epoch].test_largest_sent_pkt_num_on_path
@@ -986,6 +1068,32 @@ impl RecoveryOps for LegacyRecovery {
         self.rtt_stats.max_ack_delay = max_ack_delay;
     }
 
+    fn ecn_marked_acked(&self) -> usize {
+        self.ecn_marked_acked
+    }
+
+    fn ecn_marked_lost(&self) -> usize {
+        self.ecn_marked_lost
+    }
+
+    fn on_ecn_ce(&mut self, epoch: Epoch, _ce_count: u64, now: Instant) {
+        // An increase of the ECN-CE count is handled like a loss of the
+        // largest acknowledged packet, except that no packet needs to be
+        // retransmitted.
+        //
+        // https://www.rfc-editor.org/rfc/rfc9002.html#section-b.7
+        if let Some(time_sent) = self.epochs[epoch].largest_acked_packet_time_sent
+        {
+            (self.congestion.cc_ops.congestion_event)(
+                &mut self.congestion,
+                self.bytes_in_flight.get(),
+                0,
+                time_sent,
+                now,
+            );
+        }
+    }
+
     #[cfg(feature = "qlog")]
     fn state_str(&self, now: Instant) -> &'static str {
         (self.congestion.cc_ops.state_str)(&self.congestion, now)
@@ -937,8 +937,13 @@ mod bandwidth_sampler_tests {
 
         fn make_acked_packet(&self, pkt_num: u64) -> Acked {
             let time_sent = self.get_packet_time(pkt_num);
+            let bytes_acked = self.get_packet_size(pkt_num);
 
-            Acked { pkt_num, time_sent }
+            Acked {
+                pkt_num,
+                time_sent,
+                bytes_acked,
+            }
         }
 
         fn make_lost_packet(&self, pkt_num: u64) -> Lost {
@@ -1569,6 +1574,7 @@ mod bandwidth_sampler_tests {
         let acked = Acked {
             pkt_num: 1,
             time_sent: test_sender.clock,
+            bytes_acked: REGULAR_PACKET_SIZE,
         };
         test_sender.advance_time(Duration::from_millis(10));
         let sample = test_sender.sampler.on_congestion_event(
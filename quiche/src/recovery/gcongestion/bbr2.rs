@@ -56,6 +56,13 @@ use super::RttStats;
 
 const MAX_MODE_CHANGES_PER_CONGESTION_EVENT: usize = 4;
 
+/// The number of ACKs per min_rtt requested from the peer when acknowledgements
+/// are made less frequent.
+const ACKS_PER_MIN_RTT: usize = 4;
+
+/// Upper bound of the ack-eliciting threshold requested from the peer.
+const MAX_ACK_ELICITING_THRESHOLD: usize = 10;
+
 #[derive(Debug)]
 struct Params {
     // STARTUP parameters.
@@ -99,6 +106,11 @@ struct Params {
     /// Minimum duration for BBR-native probes.
     probe_bw_probe_base_duration: Duration,
 
+    /// Upper bound of the random duration added to
+    /// `probe_bw_probe_base_duration`, so that flows sharing a bottleneck
+    /// don't probe in lockstep.
+    probe_bw_probe_rand_duration: Duration,
+
     /// The minimum number of loss marking events to exit the PROBE_UP phase.
     probe_bw_full_loss_count: usize,
 
@@ -144,6 +156,11 @@ struct Params {
     /// Estimate startup/bw probing has gone too far if loss rate exceeds this.
     loss_threshold: f32,
 
+    /// Estimate startup/bw probing has gone too far if the fraction of bytes
+    /// delivered in a round that were ECN-CE marked exceeds this. If `None`,
+    /// ECN-CE marks are accounted as losses instead.
+    ecn_threshold: Option<f32>,
+
     /// A common factor for multiplicative decreases. Used for adjusting
     /// `bandwidth_lo``, `inflight_lo`` and `inflight_hi`` upon losses.
     beta: f32,
@@ -286,6 +303,8 @@ const DEFAULT_PARAMS: Params = Params {
 
     probe_bw_probe_base_duration: Duration::from_millis(2000),
 
+    probe_bw_probe_rand_duration: Duration::ZERO,
+
     probe_bw_full_loss_count: 2,
 
     probe_bw_probe_up_pacing_gain: 1.25,
@@ -318,6 +337,8 @@ const DEFAULT_PARAMS: Params = Params {
 
     loss_threshold: 0.015,
 
+    ecn_threshold: None,
+
     beta: 0.3,
 
     add_ack_height_to_queueing_threshold: false,
@@ -349,6 +370,36 @@ const DEFAULT_PARAMS: Params = Params {
     time_sent_set_to_now: true,
 };
 
+/// Parameters of BBRv3, as described in draft-ietf-ccwg-bbr.
+///
+/// `DEFAULT_PARAMS` already use the BBRv3 gains, so only the parameters below
+/// differ from it.
+const BBRV3_PARAMS: Params = Params {
+    // BBRStartupFullLossCnt.
+    startup_full_loss_count: 6,
+
+    // The bandwidth probe wait is picked between 2 and 3 seconds.
+    probe_bw_probe_rand_duration: Duration::from_millis(1000),
+
+    // ProbeRTTInterval.
+    probe_rtt_period: Duration::from_millis(5000),
+
+    // BBRLossThresh.
+    loss_threshold: 0.02,
+
+    // ECN-CE marks are compared to the delivered bytes, not counted as losses.
+    ecn_threshold: Some(0.5),
+
+    ..DEFAULT_PARAMS
+};
+
+/// The version of the BBR algorithm implemented by [`BBRv2`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BbrVersion {
+    V2,
+    V3,
+}
+
 #[derive(Debug, PartialEq)]
 enum BwLoMode {
     /// Mode that implements the BBRAdaptLowerBoundsFromCongestion()
@@ -500,14 +551,19 @@ impl BBRv2 {
     pub fn new(
         initial_congestion_window: usize, max_congestion_window: usize,
         max_segment_size: usize, smoothed_rtt: Duration,
-        custom_bbr_params: Option<&BbrParams>,
+        custom_bbr_params: Option<&BbrParams>, version: BbrVersion,
     ) -> Self {
         let cwnd = initial_congestion_window * max_segment_size;
 
+        let params = match version {
+            BbrVersion::V2 => DEFAULT_PARAMS,
+            BbrVersion::V3 => BBRV3_PARAMS,
+        };
+
         let params = if let Some(custom_bbr_settings) = custom_bbr_params {
-            DEFAULT_PARAMS.with_overrides(custom_bbr_settings)
+            params.with_overrides(custom_bbr_settings)
         } else {
-            DEFAULT_PARAMS
+            params
         };
 
         BBRv2 {
@@ -740,6 +796,11 @@ impl CongestionControl for BBRv2 {
         network_model.on_packet_neutered(packet_number);
     }
 
+    fn on_ecn_ce(&mut self, ce_bytes: usize) {
+        let network_model = self.mode.network_model_mut();
+        network_model.on_ecn_ce(ce_bytes, &self.params);
+    }
+
     fn on_retransmission_timeout(&mut self, _packets_retransmitted: bool) {}
 
     fn on_connection_migration(&mut self) {}
@@ -796,6 +857,25 @@ impl CongestionControl for BBRv2 {
     fn limit_cwnd(&mut self, max_cwnd: usize) {
         self.cwnd_limits.hi = max_cwnd
     }
+
+    fn ack_eliciting_threshold(&self, _rtt_stats: &RttStats) -> Option<u64> {
+        let network_model = self.mode.network_model();
+
+        // Keep the default ACK frequency until the bottleneck bandwidth has
+        // been found, as STARTUP relies on timely bandwidth samples.
+        if !network_model.full_bandwidth_reached() {
+            return None;
+        }
+
+        let bdp_packets =
+            network_model.bdp1(network_model.bandwidth_estimate()) / self.mss;
+
+        let threshold =
+            (bdp_packets / ACKS_PER_MIN_RTT).min(MAX_ACK_ELICITING_THRESHOLD);
+
+        // A threshold of 1 is the default behavior of the peer anyway.
+        (threshold > 1).then_some(threshold as u64)
+    }
 }
 
 #[cfg(test)]
@@ -804,6 +884,24 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn ack_eliciting_threshold_startup() {
+        let bbr2 = BBRv2::new(
+            10,
+            10000,
+            1200,
+            Duration::from_millis(100),
+            None,
+            BbrVersion::V2,
+        );
+
+        let rtt_stats =
+            RttStats::new(Duration::from_millis(100), Duration::from_millis(25));
+
+        // Acknowledgements aren't made less frequent during STARTUP.
+        assert_eq!(bbr2.ack_eliciting_threshold(&rtt_stats), None);
+    }
+
     #[rstest]
     fn update_mss(#[values(false, true)] scale_pacing_rate_by_mss: bool) {
         const INIT_PACKET_SIZE: usize = 1200;
@@ -827,6 +925,7 @@ mod tests {
             INIT_PACKET_SIZE,
             initial_rtt,
             Some(bbr_params),
+            BbrVersion::V2,
         );
 
         assert_eq!(bbr2.cwnd_limits.lo, INIT_CWND);
@@ -143,6 +143,11 @@ pub(super) struct BBRv2NetworkModel {
     bytes_lost_in_round: usize,
     /// Number of loss marking events in the current round.
     loss_events_in_round: usize,
+    /// Bytes acked in the current round. Updated once per congestion event.
+    bytes_acked_in_round: usize,
+    /// Bytes reported as ECN-CE marked in the current round, when ECN-CE
+    /// marks are not accounted as losses.
+    ce_bytes_in_round: usize,
 
     /// A max of bytes delivered among all congestion events in the current
     /// round. A congestions event's bytes delivered is the total bytes
@@ -221,6 +226,8 @@ impl BBRv2NetworkModel {
             full_bandwidth_reached: false,
             bytes_lost_in_round: 0,
             loss_events_in_round: 0,
+            bytes_acked_in_round: 0,
+            ce_bytes_in_round: 0,
             max_bytes_delivered_in_round: 0,
             bandwidth_latest: Bandwidth::zero(),
             bandwidth_lo: None,
@@ -383,6 +390,8 @@ impl BBRv2NetworkModel {
             self.loss_events_in_round += 1;
         }
 
+        self.bytes_acked_in_round += congestion_event.bytes_acked;
+
         if congestion_event.bytes_acked > 0 &&
             congestion_event.last_packet_send_state.is_valid &&
             self.total_bytes_acked() >
@@ -428,6 +437,35 @@ impl BBRv2NetworkModel {
         self.bandwidth_sampler.on_packet_neutered(packet_number)
     }
 
+    /// ECN-CE marks signal congestion like losses do, so they are accounted
+    /// as lost bytes when checking if inflight is too high and when adapting
+    /// the lower bounds, but not by the bandwidth sampler as the packets were
+    /// delivered.
+    ///
+    /// With an `ecn_threshold` set, they are instead compared to the bytes
+    /// delivered in the round, separately from losses.
+    pub(super) fn on_ecn_ce(&mut self, ce_bytes: usize, params: &Params) {
+        if params.ecn_threshold.is_some() {
+            self.ce_bytes_in_round += ce_bytes;
+            return;
+        }
+
+        self.bytes_lost_in_round += ce_bytes;
+        self.loss_events_in_round += 1;
+    }
+
+    /// Returns true if the fraction of bytes delivered in the current round
+    /// that were ECN-CE marked exceeds `ecn_threshold`.
+    fn is_ecn_too_high(&self, params: &Params) -> bool {
+        let Some(ecn_threshold) = params.ecn_threshold else {
+            return false;
+        };
+
+        self.ce_bytes_in_round > 0 &&
+            self.ce_bytes_in_round as f32 >
+                self.bytes_acked_in_round as f32 * ecn_threshold
+    }
+
     fn adapt_lower_bounds(
         &mut self, congestion_event: &BBRv2CongestionEvent, params: &Params,
     ) {
@@ -438,7 +476,7 @@ impl BBRv2NetworkModel {
                 return;
             }
 
-            if self.bytes_lost_in_round > 0 {
+            if self.bytes_lost_in_round > 0 || self.is_ecn_too_high(params) {
                 if self.bandwidth_lo.is_none() {
                     self.bandwidth_lo = Some(self.max_bandwidth());
                 }
@@ -593,6 +631,10 @@ impl BBRv2NetworkModel {
             return false;
         }
 
+        if self.is_ecn_too_high(params) {
+            return true;
+        }
+
         if self.loss_events_in_round < max_loss_events {
             return false;
         }
@@ -622,6 +664,8 @@ impl BBRv2NetworkModel {
     fn on_new_round(&mut self) {
         self.bytes_lost_in_round = 0;
         self.loss_events_in_round = 0;
+        self.bytes_acked_in_round = 0;
+        self.ce_bytes_in_round = 0;
         self.max_bytes_delivered_in_round = 0;
         self.min_bytes_in_flight_in_round = usize::MAX;
         self.inflight_hi_limited_in_round = false;
@@ -782,3 +826,103 @@ impl BBRv2NetworkModel {
         self.rounds_with_queueing
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recovery::gcongestion::bbr2::SendTimeState;
+    use crate::recovery::gcongestion::bbr2::BBRV3_PARAMS;
+    use crate::recovery::gcongestion::bbr2::DEFAULT_PARAMS;
+
+    #[test]
+    fn ecn_ce_as_loss() {
+        let params = &DEFAULT_PARAMS;
+        let mut model =
+            BBRv2NetworkModel::new(params, Duration::from_millis(100));
+
+        model.bytes_acked_in_round = 10_000;
+        model.on_ecn_ce(1200, params);
+
+        assert_eq!(model.bytes_lost_in_round, 1200);
+        assert_eq!(model.loss_events_in_round, 1);
+        assert!(!model.is_ecn_too_high(params));
+    }
+
+    #[test]
+    fn ecn_ce_threshold() {
+        let params = &BBRV3_PARAMS;
+        let mut model =
+            BBRv2NetworkModel::new(params, Duration::from_millis(100));
+
+        model.bytes_acked_in_round = 10_000;
+
+        model.on_ecn_ce(4800, params);
+        assert_eq!(model.bytes_lost_in_round, 0);
+        assert_eq!(model.loss_events_in_round, 0);
+        assert!(!model.is_ecn_too_high(params));
+
+        // More than half of the delivered bytes were marked.
+        model.on_ecn_ce(1200, params);
+        assert!(model.is_ecn_too_high(params));
+
+        model.on_new_round();
+        assert!(!model.is_ecn_too_high(params));
+    }
+
+    #[test]
+    fn startup_loss_thresholds() {
+        let now = Instant::now();
+
+        let mut congestion_event = BBRv2CongestionEvent::new(now, 0, 0, false);
+        congestion_event.last_packet_send_state = SendTimeState {
+            is_valid: true,
+            bytes_in_flight: 100_000,
+            ..Default::default()
+        };
+
+        let inflight_too_high = |params: &Params, events, bytes_lost| {
+            let mut model =
+                BBRv2NetworkModel::new(params, Duration::from_millis(100));
+            model.loss_events_in_round = events;
+            model.bytes_lost_in_round = bytes_lost;
+
+            model.is_inflight_too_high(
+                &congestion_event,
+                params.startup_full_loss_count,
+                params,
+            )
+        };
+
+        // BBRv3 exits startup after fewer loss events.
+        assert!(!inflight_too_high(&DEFAULT_PARAMS, 6, 3_000));
+        assert!(inflight_too_high(&BBRV3_PARAMS, 6, 3_000));
+
+        // But tolerates a higher loss rate.
+        assert!(inflight_too_high(&DEFAULT_PARAMS, 8, 1_800));
+        assert!(!inflight_too_high(&BBRV3_PARAMS, 8, 1_800));
+    }
+
+    #[test]
+    fn min_rtt_expiry() {
+        let now = Instant::now();
+
+        let mut congestion_event =
+            BBRv2CongestionEvent::new(now + Duration::from_secs(6), 0, 0, false);
+        congestion_event.sample_min_rtt = Some(Duration::from_millis(100));
+
+        let mut model =
+            BBRv2NetworkModel::new(&DEFAULT_PARAMS, Duration::from_millis(100));
+        model
+            .min_rtt_filter
+            .force_update(Duration::from_millis(100), now);
+        assert!(!model.maybe_expire_min_rtt(&congestion_event, &DEFAULT_PARAMS));
+
+        // BBRv3 probes the RTT every 5 seconds.
+        let mut model =
+            BBRv2NetworkModel::new(&BBRV3_PARAMS, Duration::from_millis(100));
+        model
+            .min_rtt_filter
+            .force_update(Duration::from_millis(100), now);
+        assert!(model.maybe_expire_min_rtt(&congestion_event, &BBRV3_PARAMS));
+    }
+}
@@ -32,6 +32,7 @@ use std::ops::Add;
 use std::time::Duration;
 use std::time::Instant;
 
+use crate::rand;
 use crate::recovery::gcongestion::bbr2::Params;
 use crate::recovery::gcongestion::Acked;
 use crate::recovery::gcongestion::Lost;
@@ -220,11 +221,17 @@ impl ProbeBW {
         }
 
         // Pick probe wait time.
-        // TODO(vlad): actually pick time
+        let mut probe_wait_time =
+            params.probe_bw_probe_base_duration + Duration::from_micros(500);
+
+        if !params.probe_bw_probe_rand_duration.is_zero() {
+            let rand_us = params.probe_bw_probe_rand_duration.as_micros() as u64;
+            probe_wait_time +=
+                Duration::from_micros(rand::rand_u64_uniform(rand_us));
+        }
+
         cycle.rounds_since_probe = 0;
-        cycle.probe_wait_time = Some(
-            params.probe_bw_probe_base_duration + Duration::from_micros(500),
-        );
+        cycle.probe_wait_time = Some(probe_wait_time);
 
         cycle.probe_up_bytes = None;
         cycle.probe_up_app_limited_since_inflight_hi_limited = false;
@@ -650,6 +657,7 @@ mod tests {
 
     use super::*;
     use crate::recovery::gcongestion::bbr2::SendTimeState;
+    use crate::recovery::gcongestion::bbr2::BBRV3_PARAMS;
     use crate::recovery::gcongestion::bbr2::DEFAULT_PARAMS;
 
     #[rstest]
@@ -691,7 +699,7 @@ mod tests {
 
         let params = &DEFAULT_PARAMS;
         let model = BBRv2NetworkModel::new(params, Duration::from_millis(333));
-        let cycle = Cycle::default();
+        let cycle = Cycle::new(Instant::now());
         let mut probe_bw = ProbeBW { model, cycle };
         probe_bw.model.set_inflight_hi(100_000);
         probe_bw.raise_inflight_high_slope(100_000);
@@ -726,4 +734,38 @@ mod tests {
         // End inflight_hi should be independent of step size.
         assert_eq!(probe_bw.model.inflight_hi(), 174_100);
     }
+
+    #[test]
+    fn probe_wait_time() {
+        let now = Instant::now();
+
+        let params = &DEFAULT_PARAMS;
+        let model = BBRv2NetworkModel::new(params, Duration::from_millis(333));
+        let mut probe_bw = ProbeBW {
+            model,
+            cycle: Cycle::new(now),
+        };
+
+        probe_bw.enter_probe_down(false, false, now, params);
+        assert_eq!(
+            probe_bw.cycle.probe_wait_time,
+            Some(Duration::from_millis(2000) + Duration::from_micros(500))
+        );
+
+        // BBRv3 randomizes the wait between 2 and 3 seconds.
+        let params = &BBRV3_PARAMS;
+        let model = BBRv2NetworkModel::new(params, Duration::from_millis(333));
+        let mut probe_bw = ProbeBW {
+            model,
+            cycle: Cycle::new(now),
+        };
+
+        for _ in 0..100 {
+            probe_bw.enter_probe_down(false, false, now, params);
+
+            let wait = probe_bw.cycle.probe_wait_time.unwrap();
+            assert!(wait >= Duration::from_millis(2000));
+            assert!(wait < Duration::from_millis(3001));
+        }
+    }
 }
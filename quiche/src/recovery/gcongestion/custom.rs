@@ -0,0 +1,398 @@
+// Copyright (C) 2025, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Support for congestion control algorithms implemented outside of quiche.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::recovery::bandwidth::Bandwidth;
+use crate::recovery::rtt::RttStats;
+use crate::recovery::RecoveryStats;
+
+use super::Acked;
+use super::CongestionControl;
+use super::Lost;
+
+/// Pacing gain applied to `cwnd / srtt` when the controller doesn't provide
+/// its own pacing rate.
+const DEFAULT_PACING_GAIN: f32 = 1.25;
+
+/// Parameters used to create a new [`CongestionController`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CongestionControllerParams {
+    /// The maximum size of outgoing UDP payloads, in bytes.
+    pub max_datagram_size: usize,
+
+    /// The initial congestion window, in packets.
+    pub initial_congestion_window_packets: usize,
+
+    /// The RTT used before the first RTT sample is taken.
+    pub initial_rtt: Duration,
+}
+
+/// A packet that was newly acknowledged by the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AckedPacket {
+    /// The packet number.
+    pub pkt_num: u64,
+
+    /// The time at which the packet was sent.
+    pub time_sent: Instant,
+
+    /// The number of bytes of the packet that counted towards the bytes in
+    /// flight.
+    pub size: usize,
+}
+
+/// A packet that was declared lost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LostPacket {
+    /// The packet number.
+    pub pkt_num: u64,
+
+    /// The number of bytes of the packet that counted towards the bytes in
+    /// flight.
+    pub size: usize,
+}
+
+/// A snapshot of the connection's RTT estimates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RttEstimate {
+    /// The most recent RTT sample.
+    pub latest_rtt: Duration,
+
+    /// The smoothed RTT, or the initial RTT if no sample was taken yet.
+    pub smoothed_rtt: Duration,
+
+    /// The RTT variation.
+    pub rttvar: Duration,
+
+    /// The minimum RTT observed, if any sample was taken yet.
+    pub min_rtt: Option<Duration>,
+}
+
+impl From<&RttStats> for RttEstimate {
+    fn from(rtt_stats: &RttStats) -> Self {
+        RttEstimate {
+            latest_rtt: rtt_stats.latest_rtt(),
+            smoothed_rtt: rtt_stats.rtt(),
+            rttvar: rtt_stats.rttvar(),
+            min_rtt: rtt_stats.min_rtt(),
+        }
+    }
+}
+
+/// A congestion control algorithm implemented outside of quiche.
+///
+/// An instance is created for every path of a connection by the factory
+/// passed to [`set_cc_algorithm_custom()`]. quiche takes care of loss
+/// detection, RTT estimation and pacing, and notifies the controller of the
+/// relevant events. In turn the controller decides how many bytes can be in
+/// flight, and optionally at which rate they are sent.
+///
+/// Losses are reported before acknowledgements when both are the result of
+/// the same ACK frame. Packet numbers are monotonically increasing, so a
+/// controller can, for example, track recovery periods by remembering the
+/// largest packet number sent when the window was last reduced.
+///
+/// [`set_cc_algorithm_custom()`]: struct.Config.html#method.set_cc_algorithm_custom
+pub trait CongestionController: Send + Sync + Debug {
+    /// Called when a packet that counts towards the bytes in flight is sent.
+    ///
+    /// `bytes_in_flight` is the number of bytes in flight before this packet
+    /// was sent.
+    fn on_packet_sent(
+        &mut self, now: Instant, pkt_num: u64, bytes: usize,
+        bytes_in_flight: usize,
+    );
+
+    /// Called when the peer acknowledged new packets.
+    ///
+    /// `bytes_in_flight` is the number of bytes in flight after the packets
+    /// were removed.
+    fn on_packets_acked(
+        &mut self, now: Instant, acked: &[AckedPacket], bytes_in_flight: usize,
+        rtt: &RttEstimate,
+    );
+
+    /// Called when packets were declared lost.
+    ///
+    /// `bytes_in_flight` is the number of bytes in flight after the packets
+    /// were removed.
+    fn on_packets_lost(
+        &mut self, now: Instant, lost: &[LostPacket], bytes_in_flight: usize,
+    );
+
+    /// Called when a new RTT sample was taken.
+    fn on_rtt_update(&mut self, _rtt: &RttEstimate) {}
+
+    /// Called when the peer reported ECN-CE marks on packets carrying
+    /// `ce_bytes` bytes in total.
+    fn on_ecn_ce(&mut self, _ce_bytes: usize) {}
+
+    /// Called when the probe timeout fired.
+    fn on_retransmission_timeout(&mut self) {}
+
+    /// Called when the sender doesn't have enough data to fill the congestion
+    /// window.
+    fn on_app_limited(&mut self, _bytes_in_flight: usize) {}
+
+    /// Called when the maximum datagram size changed, e.g. as a result of path
+    /// MTU discovery.
+    fn on_mss_update(&mut self, _max_datagram_size: usize) {}
+
+    /// Called when the connection migrated to a new path.
+    fn on_connection_migration(&mut self) {}
+
+    /// Returns the current congestion window, in bytes.
+    fn congestion_window(&self) -> usize;
+
+    /// Returns the rate at which packets should be paced, in bytes per second.
+    ///
+    /// When `None` is returned, the pacing rate is derived from the congestion
+    /// window and the smoothed RTT.
+    fn pacing_rate(&self, _rtt: &RttEstimate) -> Option<u64> {
+        None
+    }
+
+    /// Returns whether the controller is in a recovery period.
+    fn in_recovery(&self) -> bool {
+        false
+    }
+
+    /// Returns the slow start threshold, in bytes, if any.
+    fn ssthresh(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the name of the current state of the controller. Used to
+    /// annotate qlogs after state transitions.
+    fn state_str(&self) -> &'static str {
+        "custom"
+    }
+}
+
+type CongestionControllerFactory = dyn Fn(&CongestionControllerParams) -> Box<dyn CongestionController>
+    + Send
+    + Sync;
+
+/// Creates a [`CongestionController`] for every new path.
+#[derive(Clone)]
+pub(crate) struct CustomCongestionControl(Arc<CongestionControllerFactory>);
+
+impl CustomCongestionControl {
+    pub(crate) fn new<F>(factory: F) -> Self
+    where
+        F: Fn(&CongestionControllerParams) -> Box<dyn CongestionController>
+            + Send
+            + Sync
+            + 'static,
+    {
+        CustomCongestionControl(Arc::new(factory))
+    }
+
+    pub(super) fn create(
+        &self, params: &CongestionControllerParams,
+    ) -> Box<dyn CongestionController> {
+        (self.0)(params)
+    }
+}
+
+impl PartialEq for CustomCongestionControl {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for CustomCongestionControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CustomCongestionControl")
+    }
+}
+
+/// Adapts a [`CongestionController`] to the [`CongestionControl`] interface
+/// used by the [`Pacer`](super::pacer::Pacer).
+#[derive(Debug)]
+pub(crate) struct CustomSender {
+    controller: Box<dyn CongestionController>,
+
+    max_datagram_size: usize,
+
+    acked: Vec<AckedPacket>,
+
+    lost: Vec<LostPacket>,
+
+    max_bandwidth: Bandwidth,
+}
+
+impl CustomSender {
+    pub(crate) fn new(
+        controller: Box<dyn CongestionController>, max_datagram_size: usize,
+    ) -> Self {
+        CustomSender {
+            controller,
+            max_datagram_size,
+            acked: Vec::new(),
+            lost: Vec::new(),
+            max_bandwidth: Bandwidth::zero(),
+        }
+    }
+}
+
+impl CongestionControl for CustomSender {
+    #[cfg(feature = "qlog")]
+    fn state_str(&self) -> &'static str {
+        self.controller.state_str()
+    }
+
+    fn get_congestion_window(&self) -> usize {
+        self.controller.congestion_window()
+    }
+
+    fn get_congestion_window_in_packets(&self) -> usize {
+        self.controller.congestion_window() / self.max_datagram_size
+    }
+
+    fn can_send(&self, bytes_in_flight: usize) -> bool {
+        bytes_in_flight < self.controller.congestion_window()
+    }
+
+    fn on_packet_sent(
+        &mut self, sent_time: Instant, bytes_in_flight: usize,
+        packet_number: u64, bytes: usize, _is_retransmissible: bool,
+    ) {
+        self.controller.on_packet_sent(
+            sent_time,
+            packet_number,
+            bytes,
+            bytes_in_flight,
+        );
+    }
+
+    fn on_ecn_ce(&mut self, ce_bytes: usize) {
+        self.controller.on_ecn_ce(ce_bytes);
+    }
+
+    fn on_congestion_event(
+        &mut self, rtt_updated: bool, _prior_in_flight: usize,
+        bytes_in_flight: usize, event_time: Instant, acked_packets: &[Acked],
+        lost_packets: &[Lost], _least_unacked: u64, rtt_stats: &RttStats,
+        _recovery_stats: &mut RecoveryStats,
+    ) {
+        let rtt = RttEstimate::from(rtt_stats);
+
+        if rtt_updated {
+            self.controller.on_rtt_update(&rtt);
+        }
+
+        if !lost_packets.is_empty() {
+            self.lost.clear();
+            self.lost.extend(lost_packets.iter().map(|p| LostPacket {
+                pkt_num: p.packet_number,
+                size: p.bytes_lost,
+            }));
+
+            self.controller.on_packets_lost(
+                event_time,
+                &self.lost,
+                bytes_in_flight,
+            );
+        }
+
+        if !acked_packets.is_empty() {
+            self.acked.clear();
+            self.acked.extend(acked_packets.iter().map(|p| AckedPacket {
+                pkt_num: p.pkt_num,
+                time_sent: p.time_sent,
+                size: p.bytes_acked,
+            }));
+
+            self.controller.on_packets_acked(
+                event_time,
+                &self.acked,
+                bytes_in_flight,
+                &rtt,
+            );
+
+            self.max_bandwidth =
+                self.max_bandwidth.max(self.bandwidth_estimate(rtt_stats));
+        }
+    }
+
+    fn on_retransmission_timeout(&mut self, _packets_retransmitted: bool) {
+        self.controller.on_retransmission_timeout();
+    }
+
+    fn on_connection_migration(&mut self) {
+        self.controller.on_connection_migration();
+    }
+
+    fn is_in_recovery(&self) -> bool {
+        self.controller.in_recovery()
+    }
+
+    fn is_cwnd_limited(&self, bytes_in_flight: usize) -> bool {
+        bytes_in_flight >= self.controller.congestion_window()
+    }
+
+    fn pacing_rate(
+        &self, _bytes_in_flight: usize, rtt_stats: &RttStats,
+    ) -> Bandwidth {
+        match self.controller.pacing_rate(&RttEstimate::from(rtt_stats)) {
+            Some(rate) => Bandwidth::from_bytes_per_second(rate),
+
+            None => self.bandwidth_estimate(rtt_stats) * DEFAULT_PACING_GAIN,
+        }
+    }
+
+    fn bandwidth_estimate(&self, rtt_stats: &RttStats) -> Bandwidth {
+        Bandwidth::from_bytes_and_time_delta(
+            self.controller.congestion_window(),
+            rtt_stats.rtt(),
+        )
+    }
+
+    fn max_bandwidth(&self) -> Bandwidth {
+        self.max_bandwidth
+    }
+
+    fn update_mss(&mut self, new_mss: usize) {
+        self.max_datagram_size = new_mss;
+        self.controller.on_mss_update(new_mss);
+    }
+
+    fn on_app_limited(&mut self, bytes_in_flight: usize) {
+        self.controller.on_app_limited(bytes_in_flight);
+    }
+
+    #[cfg(feature = "qlog")]
+    fn ssthresh(&self) -> Option<u64> {
+        self.controller.ssthresh()
+    }
+}
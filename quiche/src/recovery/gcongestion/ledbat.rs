@@ -0,0 +1,594 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! LEDBAT++ congestion control, a delay-based scavenger for background
+//! transfers.
+//!
+//! LEDBAT++ estimates the queuing delay at the bottleneck as the difference
+//! between the latest RTT sample and the minimum RTT, and keeps it under
+//! [`TARGET_DELAY`]: the congestion window grows slowly while the queuing
+//! delay is below the target, and shrinks in proportion to the excess delay
+//! above it. Loss-based controllers sharing the bottleneck build up a queue
+//! well beyond the target, so a LEDBAT++ flow quickly yields the capacity to
+//! them and only uses what they leave idle.
+//!
+//! To keep the minimum RTT accurate, the window is periodically reduced to
+//! the minimum for two round trips so that the queue built by the flow itself
+//! drains.
+//!
+//! See <https://datatracker.ietf.org/doc/draft-irtf-iccrg-ledbat-plus-plus/>.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::recovery::bandwidth::Bandwidth;
+use crate::recovery::rtt::RttStats;
+use crate::recovery::RecoveryStats;
+
+use super::Acked;
+use super::CongestionControl;
+use super::Lost;
+
+/// The queuing delay the controller aims for.
+const TARGET_DELAY: Duration = Duration::from_millis(60);
+
+/// The upper bound of the inverse of the window growth gain.
+const MAX_GAIN_DIVISOR: f64 = 16.0;
+
+/// The multiplicative decrease applied per round trip for each target delay
+/// of excess queuing delay.
+const DECREASE_CONSTANT: f64 = 1.0;
+
+/// The congestion window reduction factor applied on loss.
+const LOSS_REDUCTION_FACTOR: f64 = 0.5;
+
+/// The minimum congestion window, in packets.
+const MINIMUM_WINDOW_PACKETS: usize = 2;
+
+/// The number of round trips the window is held at the minimum during a
+/// slowdown.
+const SLOWDOWN_ROUNDS: u32 = 2;
+
+/// The interval between two slowdowns, as a multiple of the duration of the
+/// previous slowdown, so that slowdowns cost at most 10% of the throughput.
+const SLOWDOWN_INTERVAL_FACTOR: u32 = 9;
+
+/// The pacing gain applied to `cwnd / srtt` during slow start.
+const SLOW_START_PACING_GAIN: f64 = 2.0;
+
+/// The pacing gain applied to `cwnd / srtt` during congestion avoidance.
+const CONGESTION_AVOIDANCE_PACING_GAIN: f64 = 1.2;
+
+#[derive(Debug)]
+pub(crate) struct Ledbat {
+    mss: usize,
+
+    initial_cwnd: usize,
+
+    max_cwnd: usize,
+
+    cwnd: usize,
+
+    ssthresh: usize,
+
+    /// The fraction of a byte the window changed by, carried over to the
+    /// next ACK.
+    cwnd_change: f64,
+
+    largest_sent: Option<u64>,
+
+    largest_acked: Option<u64>,
+
+    /// The largest packet number sent when the window was last reduced due
+    /// to loss.
+    recovery_end: Option<u64>,
+
+    /// When the next slowdown starts, once slow start is over.
+    next_slowdown: Option<Instant>,
+
+    /// When the current slowdown started, until the window grew back to its
+    /// previous size.
+    slowdown_start: Option<Instant>,
+
+    /// When the window stops being held at the minimum.
+    frozen_until: Option<Instant>,
+
+    max_bandwidth: Bandwidth,
+}
+
+impl Ledbat {
+    pub fn new(
+        initial_cwnd_packets: usize, max_cwnd_packets: usize, mss: usize,
+    ) -> Self {
+        let initial_cwnd = initial_cwnd_packets * mss;
+
+        Ledbat {
+            mss,
+            initial_cwnd,
+            max_cwnd: max_cwnd_packets * mss,
+            cwnd: initial_cwnd,
+            ssthresh: usize::MAX,
+            cwnd_change: 0.0,
+            largest_sent: None,
+            largest_acked: None,
+            recovery_end: None,
+            next_slowdown: None,
+            slowdown_start: None,
+            frozen_until: None,
+            max_bandwidth: Bandwidth::zero(),
+        }
+    }
+
+    fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    fn min_cwnd(&self) -> usize {
+        MINIMUM_WINDOW_PACKETS * self.mss
+    }
+
+    /// Returns the window growth gain, which is lower for short base delays
+    /// so that the growth in time doesn't depend on the RTT.
+    fn gain(base_delay: Duration) -> f64 {
+        let divisor = (2.0 * TARGET_DELAY.as_secs_f64() /
+            base_delay.as_secs_f64())
+        .ceil()
+        .min(MAX_GAIN_DIVISOR);
+
+        1.0 / divisor.max(1.0)
+    }
+
+    /// Adds `change` bytes to the congestion window, carrying fractions of a
+    /// byte over to the next update.
+    fn change_cwnd(&mut self, change: f64) {
+        self.cwnd_change += change;
+
+        let whole = self.cwnd_change.trunc();
+        self.cwnd_change -= whole;
+
+        self.cwnd = (self.cwnd as f64 + whole)
+            .clamp(self.min_cwnd() as f64, self.max_cwnd as f64)
+            as usize;
+    }
+
+    fn on_slow_start_exit(&mut self, now: Instant, srtt: Duration) {
+        match self.slowdown_start.take() {
+            // The window grew back to its size before the slowdown.
+            Some(start) => {
+                self.next_slowdown =
+                    Some(now + (now - start) * SLOWDOWN_INTERVAL_FACTOR);
+            },
+
+            // The initial slow start is followed by a slowdown, as the
+            // minimum RTT could have been measured with a standing queue
+            // built by other flows.
+            None if self.next_slowdown.is_none() => {
+                self.next_slowdown = Some(now + srtt * SLOWDOWN_ROUNDS);
+            },
+
+            None => (),
+        }
+    }
+
+    fn start_slowdown(&mut self, now: Instant, srtt: Duration) {
+        self.slowdown_start = Some(now);
+        self.frozen_until = Some(now + srtt * SLOWDOWN_ROUNDS);
+        self.next_slowdown = None;
+
+        // Slow start brings the window back to its current size after the
+        // slowdown.
+        self.ssthresh = self.cwnd;
+        self.cwnd = self.min_cwnd();
+        self.cwnd_change = 0.0;
+    }
+}
+
+impl CongestionControl for Ledbat {
+    #[cfg(feature = "qlog")]
+    fn state_str(&self) -> &'static str {
+        if self.frozen_until.is_some() {
+            "slowdown"
+        } else if self.is_in_recovery() {
+            "recovery"
+        } else if self.in_slow_start() {
+            "slow_start"
+        } else {
+            "congestion_avoidance"
+        }
+    }
+
+    fn get_congestion_window(&self) -> usize {
+        self.cwnd
+    }
+
+    fn get_congestion_window_in_packets(&self) -> usize {
+        self.cwnd / self.mss
+    }
+
+    fn can_send(&self, bytes_in_flight: usize) -> bool {
+        bytes_in_flight < self.cwnd
+    }
+
+    fn on_packet_sent(
+        &mut self, _sent_time: Instant, _bytes_in_flight: usize,
+        packet_number: u64, _bytes: usize, _is_retransmissible: bool,
+    ) {
+        self.largest_sent = Some(packet_number);
+    }
+
+    fn on_congestion_event(
+        &mut self, _rtt_updated: bool, _prior_in_flight: usize,
+        _bytes_in_flight: usize, event_time: Instant, acked_packets: &[Acked],
+        lost_packets: &[Lost], _least_unacked: u64, rtt_stats: &RttStats,
+        _recovery_stats: &mut RecoveryStats,
+    ) {
+        let srtt = rtt_stats.rtt();
+        let was_in_slow_start = self.in_slow_start();
+
+        let new_loss = lost_packets
+            .iter()
+            .any(|p| self.recovery_end.is_none_or(|end| p.packet_number > end));
+
+        if new_loss {
+            self.cwnd = ((self.cwnd as f64 * LOSS_REDUCTION_FACTOR) as usize)
+                .max(self.min_cwnd());
+            self.ssthresh = self.cwnd;
+            self.cwnd_change = 0.0;
+            self.recovery_end = self.largest_sent;
+        }
+
+        let mut bytes_acked = 0;
+
+        for p in acked_packets {
+            bytes_acked += p.bytes_acked;
+
+            self.largest_acked = self.largest_acked.max(Some(p.pkt_num));
+        }
+
+        if bytes_acked > 0 && !self.is_in_recovery() {
+            if let Some(until) = self.frozen_until {
+                if event_time < until {
+                    return;
+                }
+
+                self.frozen_until = None;
+            }
+
+            if self
+                .next_slowdown
+                .is_some_and(|t| event_time >= t && !self.in_slow_start())
+            {
+                self.start_slowdown(event_time, srtt);
+                return;
+            }
+
+            let base_delay = rtt_stats.min_rtt().unwrap_or(srtt);
+            let queuing_delay = rtt_stats.latest_rtt().saturating_sub(base_delay);
+            let gain = Self::gain(base_delay);
+
+            if self.in_slow_start() {
+                if queuing_delay > TARGET_DELAY * 3 / 4 {
+                    self.ssthresh = self.cwnd;
+                } else {
+                    self.change_cwnd(gain * bytes_acked as f64);
+                    self.cwnd = self.cwnd.min(self.ssthresh);
+                }
+            } else {
+                // The window changes by `gain` packets per round trip when
+                // there is no queuing delay, and decreases by up to half
+                // when the queuing delay exceeds the target.
+                let excess = queuing_delay.as_secs_f64() /
+                    TARGET_DELAY.as_secs_f64() -
+                    1.0;
+
+                let cwnd_packets = self.cwnd as f64 / self.mss as f64;
+                let change_packets =
+                    gain - DECREASE_CONSTANT * cwnd_packets * excess.max(0.0);
+
+                let change =
+                    (change_packets * self.mss as f64 * bytes_acked as f64 /
+                        self.cwnd as f64)
+                        .max(-(bytes_acked as f64) / 2.0);
+
+                self.change_cwnd(change);
+            }
+        }
+
+        if was_in_slow_start && !self.in_slow_start() {
+            self.on_slow_start_exit(event_time, srtt);
+        }
+
+        self.max_bandwidth =
+            self.max_bandwidth.max(self.bandwidth_estimate(rtt_stats));
+    }
+
+    fn on_retransmission_timeout(&mut self, _packets_retransmitted: bool) {}
+
+    fn on_connection_migration(&mut self) {
+        *self = Ledbat::new(
+            self.initial_cwnd / self.mss,
+            self.max_cwnd / self.mss,
+            self.mss,
+        );
+    }
+
+    fn limit_cwnd(&mut self, max_cwnd: usize) {
+        self.max_cwnd = max_cwnd;
+        self.cwnd = self.cwnd.min(max_cwnd);
+    }
+
+    fn is_in_recovery(&self) -> bool {
+        self.recovery_end.is_some_and(|end| {
+            self.largest_acked.is_none_or(|largest| largest <= end)
+        })
+    }
+
+    fn is_cwnd_limited(&self, bytes_in_flight: usize) -> bool {
+        bytes_in_flight >= self.cwnd
+    }
+
+    fn pacing_rate(
+        &self, _bytes_in_flight: usize, rtt_stats: &RttStats,
+    ) -> Bandwidth {
+        let gain = if self.in_slow_start() {
+            SLOW_START_PACING_GAIN
+        } else {
+            CONGESTION_AVOIDANCE_PACING_GAIN
+        };
+
+        self.bandwidth_estimate(rtt_stats) * gain
+    }
+
+    fn bandwidth_estimate(&self, rtt_stats: &RttStats) -> Bandwidth {
+        Bandwidth::from_bytes_and_time_delta(self.cwnd, rtt_stats.rtt())
+    }
+
+    fn max_bandwidth(&self) -> Bandwidth {
+        self.max_bandwidth
+    }
+
+    fn update_mss(&mut self, new_mss: usize) {
+        self.cwnd = self.cwnd * new_mss / self.mss;
+        self.initial_cwnd = self.initial_cwnd * new_mss / self.mss;
+        self.max_cwnd = self.max_cwnd * new_mss / self.mss;
+
+        if self.ssthresh != usize::MAX {
+            self.ssthresh = self.ssthresh * new_mss / self.mss;
+        }
+
+        self.mss = new_mss;
+    }
+
+    #[cfg(feature = "qlog")]
+    fn ssthresh(&self) -> Option<u64> {
+        (self.ssthresh != usize::MAX).then_some(self.ssthresh as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::recovery::gcongestion::prague::Prague;
+    use crate::recovery::gcongestion::test_link::simulate;
+    use crate::recovery::gcongestion::test_link::Flow;
+    use crate::recovery::gcongestion::test_link::MSS;
+
+    fn ack(
+        ledbat: &mut Ledbat, now: Instant, pkt_nums: std::ops::Range<u64>,
+        rtt_stats: &RttStats,
+    ) {
+        let acked: Vec<_> = pkt_nums
+            .map(|pkt_num| Acked {
+                pkt_num,
+                time_sent: now,
+                bytes_acked: MSS,
+            })
+            .collect();
+
+        ledbat.on_congestion_event(
+            true,
+            0,
+            0,
+            now,
+            &acked,
+            &[],
+            0,
+            rtt_stats,
+            &mut RecoveryStats::default(),
+        );
+    }
+
+    fn send(ledbat: &mut Ledbat, now: Instant, pkt_nums: std::ops::Range<u64>) {
+        for pkt_num in pkt_nums {
+            ledbat.on_packet_sent(now, 0, pkt_num, MSS, true);
+        }
+    }
+
+    #[test]
+    fn gain() {
+        assert_eq!(Ledbat::gain(Duration::from_millis(120)), 1.0);
+        assert_eq!(Ledbat::gain(Duration::from_millis(40)), 1.0 / 3.0);
+        assert_eq!(Ledbat::gain(Duration::from_millis(1)), 1.0 / 16.0);
+    }
+
+    #[test]
+    fn slow_start_exit_on_queuing_delay() {
+        let mut ledbat = Ledbat::new(10, 1000, MSS);
+        let mut now = Instant::now();
+
+        let base_delay = Duration::from_millis(120);
+        let mut rtt_stats = RttStats::new(base_delay, Duration::ZERO);
+        rtt_stats.update_rtt(base_delay, Duration::ZERO, now, true);
+
+        send(&mut ledbat, now, 0..10);
+        ack(&mut ledbat, now, 0..10, &rtt_stats);
+
+        // The window doubles in slow start with a gain of 1.
+        assert_eq!(ledbat.get_congestion_window(), 20 * MSS);
+        assert!(ledbat.in_slow_start());
+
+        now += base_delay;
+        rtt_stats.update_rtt(
+            base_delay + TARGET_DELAY,
+            Duration::ZERO,
+            now,
+            true,
+        );
+
+        send(&mut ledbat, now, 10..30);
+        ack(&mut ledbat, now, 10..11, &rtt_stats);
+
+        assert_eq!(ledbat.get_congestion_window(), 20 * MSS);
+        assert!(!ledbat.in_slow_start());
+
+        // The first slowdown is scheduled two round trips later.
+        assert_eq!(ledbat.next_slowdown, Some(now + rtt_stats.rtt() * 2));
+    }
+
+    #[test]
+    fn decrease_with_queuing_delay() {
+        let mut ledbat = Ledbat::new(100, 1000, MSS);
+        ledbat.ssthresh = ledbat.cwnd;
+        ledbat.next_slowdown = Some(Instant::now() + Duration::from_secs(60));
+
+        let now = Instant::now();
+        let base_delay = Duration::from_millis(120);
+        let mut rtt_stats = RttStats::new(base_delay, Duration::ZERO);
+        rtt_stats.update_rtt(base_delay, Duration::ZERO, now, true);
+
+        send(&mut ledbat, now, 0..200);
+
+        // Below the target the window grows by `gain` packets per round trip.
+        ack(&mut ledbat, now, 0..100, &rtt_stats);
+        assert_eq!(ledbat.get_congestion_window(), 101 * MSS);
+
+        // At twice the target, the window is cut in half over a round trip.
+        rtt_stats.update_rtt(
+            base_delay + TARGET_DELAY * 2,
+            Duration::ZERO,
+            now,
+            true,
+        );
+
+        ack(&mut ledbat, now, 100..200, &rtt_stats);
+        assert!(ledbat.get_congestion_window() <= 51 * MSS);
+        assert!(ledbat.get_congestion_window() >= 50 * MSS);
+    }
+
+    #[test]
+    fn slowdown() {
+        let mut ledbat = Ledbat::new(20, 1000, MSS);
+        ledbat.ssthresh = ledbat.cwnd;
+
+        let mut now = Instant::now();
+        let base_delay = Duration::from_millis(120);
+        let mut rtt_stats = RttStats::new(base_delay, Duration::ZERO);
+        rtt_stats.update_rtt(base_delay, Duration::ZERO, now, true);
+
+        ledbat.next_slowdown = Some(now);
+
+        send(&mut ledbat, now, 0..20);
+        ack(&mut ledbat, now, 0..1, &rtt_stats);
+
+        // The window is held at the minimum for two round trips.
+        assert_eq!(ledbat.get_congestion_window(), 2 * MSS);
+
+        now += base_delay;
+        ack(&mut ledbat, now, 1..10, &rtt_stats);
+        assert_eq!(ledbat.get_congestion_window(), 2 * MSS);
+
+        // Then slow start brings it back to its previous size.
+        now += base_delay;
+        ack(&mut ledbat, now, 10..20, &rtt_stats);
+        assert_eq!(ledbat.get_congestion_window(), 12 * MSS);
+
+        send(&mut ledbat, now, 20..40);
+        ack(&mut ledbat, now, 20..40, &rtt_stats);
+        assert_eq!(ledbat.get_congestion_window(), 20 * MSS);
+        assert!(!ledbat.in_slow_start());
+
+        // The next slowdown is scheduled so that slowdowns take 10% of the
+        // time.
+        assert_eq!(
+            ledbat.next_slowdown,
+            Some(now + base_delay * 2 * SLOWDOWN_INTERVAL_FACTOR)
+        );
+    }
+
+    #[test]
+    fn low_queuing_delay() {
+        let mut flows = [Flow::new(
+            Ledbat::new(10, 10000, MSS),
+            Duration::from_millis(20),
+            false,
+            Duration::ZERO,
+        )];
+
+        let outcome = simulate(
+            &mut flows,
+            Bandwidth::from_mbits_per_second(20),
+            Duration::from_secs(5),
+            Duration::from_secs(30),
+        );
+
+        assert!(outcome.link_share[0] > 0.8, "{}", outcome.link_share[0]);
+        assert!(
+            outcome.queue_delay < TARGET_DELAY,
+            "{:?}",
+            outcome.queue_delay
+        );
+    }
+
+    #[test]
+    fn yields_to_loss_based_flow() {
+        let mut flows = [
+            Flow::new(
+                Ledbat::new(10, 10000, MSS),
+                Duration::from_millis(20),
+                false,
+                Duration::ZERO,
+            ),
+            // Prague behaves like Reno on a classic queue.
+            Flow::new(
+                Prague::new(10, 10000, MSS),
+                Duration::from_millis(20),
+                false,
+                Duration::from_secs(5),
+            ),
+        ];
+
+        let outcome = simulate(
+            &mut flows,
+            Bandwidth::from_mbits_per_second(20),
+            Duration::from_secs(10),
+            Duration::from_secs(30),
+        );
+
+        assert!(outcome.link_share[0] < 0.1, "{:?}", outcome.link_share);
+        assert!(outcome.link_share[1] > 0.85, "{:?}", outcome.link_share);
+    }
+}
@@ -26,13 +26,24 @@
 
 mod bbr;
 mod bbr2;
+mod custom;
+mod ledbat;
 pub mod pacer;
+mod prague;
 mod recovery;
+#[cfg(test)]
+mod test_link;
 
 use std::fmt::Debug;
 use std::str::FromStr;
 use std::time::Instant;
 
+pub use self::custom::AckedPacket;
+pub use self::custom::CongestionController;
+pub use self::custom::CongestionControllerParams;
+pub(crate) use self::custom::CustomCongestionControl;
+pub use self::custom::LostPacket;
+pub use self::custom::RttEstimate;
 pub use self::recovery::GRecovery;
 use crate::recovery::bandwidth::Bandwidth;
 
@@ -49,8 +60,10 @@ pub struct Lost {
 pub struct Acked {
     pub(super) pkt_num: u64,
     pub(super) time_sent: Instant,
+    pub(super) bytes_acked: usize,
 }
 
+#[enum_dispatch::enum_dispatch]
 pub(super) trait CongestionControl: Debug {
     /// Returns the name of the current state of the congestion control state
     /// machine. Used to annotate qlogs after state transitions.
@@ -83,6 +96,10 @@ pub(super) trait CongestionControl: Debug {
     /// Inform that `packet_number` has been neutered.
     fn on_packet_neutered(&mut self, _packet_number: u64) {}
 
+    /// Inform that the peer reported ECN-CE marks on packets carrying
+    /// `ce_bytes` bytes in total.
+    fn on_ecn_ce(&mut self, _ce_bytes: usize) {}
+
     /// Indicates an update to the congestion state, caused either by an
     /// incoming ack or loss event timeout. `rtt_updated` indicates whether a
     /// new `latest_rtt` sample has been taken, `prior_in_flight` the bytes in
@@ -125,12 +142,55 @@ pub(super) trait CongestionControl: Debug {
 
     fn on_app_limited(&mut self, _bytes_in_flight: usize) {}
 
+    /// Returns the number of ack-eliciting packets the peer should receive
+    /// before sending an ACK, when the sender would benefit from less
+    /// frequent acknowledgements than one for every other packet.
+    fn ack_eliciting_threshold(&self, _rtt_stats: &RttStats) -> Option<u64> {
+        None
+    }
+
     #[cfg(feature = "qlog")]
     fn ssthresh(&self) -> Option<u64> {
         None
     }
 }
 
+/// The congestion control algorithm driven by the [`Pacer`](pacer::Pacer).
+#[enum_dispatch::enum_dispatch(CongestionControl)]
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub(super) enum Sender {
+    BBRv2(bbr2::BBRv2),
+    Prague(prague::Prague),
+    Ledbat(ledbat::Ledbat),
+    Custom(custom::CustomSender),
+}
+
+impl Sender {
+    fn time_sent_set_to_now(&self) -> bool {
+        match self {
+            Sender::BBRv2(bbr) => bbr.time_sent_set_to_now(),
+            Sender::Prague(_) | Sender::Ledbat(_) | Sender::Custom(_) => false,
+        }
+    }
+
+    #[cfg(feature = "qlog")]
+    fn send_rate(&self) -> Option<Bandwidth> {
+        match self {
+            Sender::BBRv2(bbr) => bbr.send_rate(),
+            Sender::Prague(_) | Sender::Ledbat(_) | Sender::Custom(_) => None,
+        }
+    }
+
+    #[cfg(feature = "qlog")]
+    fn ack_rate(&self) -> Option<Bandwidth> {
+        match self {
+            Sender::BBRv2(bbr) => bbr.ack_rate(),
+            Sender::Prague(_) | Sender::Ledbat(_) | Sender::Custom(_) => None,
+        }
+    }
+}
+
 /// BBR settings used to customize the algorithm's behavior.
 ///
 /// This functionality is experimental and will be removed in the future.
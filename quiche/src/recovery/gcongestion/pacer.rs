@@ -30,9 +30,9 @@
 
 use std::time::Instant;
 
-use crate::recovery::gcongestion::bbr2::BBRv2;
 use crate::recovery::gcongestion::Bandwidth;
 use crate::recovery::gcongestion::CongestionControl;
+use crate::recovery::gcongestion::Sender;
 use crate::recovery::rtt::RttStats;
 use crate::recovery::RecoveryStats;
 use crate::recovery::ReleaseDecision;
@@ -63,7 +63,7 @@ pub struct Pacer {
     /// Should this [`Pacer`] be making any release decisions?
     enabled: bool,
     /// Underlying sender
-    sender: BBRv2,
+    sender: Sender,
     /// The maximum rate the [`Pacer`] will use.
     max_pacing_rate: Option<Bandwidth>,
     /// Number of unpaced packets to be sent before packets are delayed.
@@ -80,11 +80,11 @@ pub struct Pacer {
 }
 
 impl Pacer {
-    /// Create a new [`Pacer`] with and underlying [`Congestion`]
+    /// Create a new [`Pacer`] with and underlying [`Sender`]
     /// implementation, and an optional throttling as specified by
     /// `max_pacing_rate`.
     pub(crate) fn new(
-        enabled: bool, congestion: BBRv2, max_pacing_rate: Option<Bandwidth>,
+        enabled: bool, congestion: Sender, max_pacing_rate: Option<Bandwidth>,
     ) -> Self {
         Pacer {
             enabled,
@@ -236,6 +236,10 @@ impl Pacer {
         self.sender.on_packet_neutered(packet_number);
     }
 
+    pub fn on_ecn_ce(&mut self, ce_bytes: usize) {
+        self.sender.on_ecn_ce(ce_bytes);
+    }
+
     pub fn on_retransmission_timeout(&mut self, packets_retransmitted: bool) {
         self.sender.on_retransmission_timeout(packets_retransmitted)
     }
@@ -273,6 +277,10 @@ impl Pacer {
         self.sender.on_app_limited(bytes_in_flight);
     }
 
+    pub fn ack_eliciting_threshold(&self, rtt_stats: &RttStats) -> Option<u64> {
+        self.sender.ack_eliciting_threshold(rtt_stats)
+    }
+
     pub fn update_mss(&mut self, new_mss: usize) {
         self.sender.update_mss(new_mss)
     }
@@ -0,0 +1,592 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Prague congestion control, a scalable congestion controller for L4S.
+//!
+//! Like DCTCP, Prague reacts to ECN-CE marks in proportion to their extent:
+//! the fraction of bytes marked during each round trip is smoothed into
+//! `alpha`, and the congestion window is reduced by `alpha / 2` at most once
+//! per round trip. L4S queues mark packets as soon as a shallow queue builds
+//! up, so the frequent but small reductions keep the queuing delay low without
+//! leaving the link underutilized.
+//!
+//! The additive increase is scaled down for flows with an RTT shorter than
+//! [`TARGET_RTT`], so that they don't outgrow flows with a longer RTT sharing
+//! the same bottleneck. Losses are handled like Reno, so that Prague falls back
+//! to classic behavior on bottlenecks without L4S support.
+//!
+//! Unlike Reno and CUBIC, Prague is implemented on top of the gcongestion
+//! recovery rather than in `recovery::congestion`. It relies on paced sending
+//! and on the amount of CE-marked bytes in each ACK, but the legacy recovery
+//! has no pacer and handles any ECN-CE increase as a single congestion event.
+//! It is still selected like the others, with
+//! [`CongestionControlAlgorithm::Prague`].
+//!
+//! See <https://datatracker.ietf.org/doc/draft-briscoe-iccrg-prague-congestion-control/>.
+//!
+//! [`CongestionControlAlgorithm::Prague`]: crate::CongestionControlAlgorithm::Prague
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::recovery::bandwidth::Bandwidth;
+use crate::recovery::rtt::RttStats;
+use crate::recovery::RecoveryStats;
+
+use super::Acked;
+use super::CongestionControl;
+use super::Lost;
+
+/// The gain of the moving average of the fraction of CE-marked bytes.
+const ALPHA_GAIN: f64 = 1.0 / 16.0;
+
+/// The congestion window reduction factor applied on loss.
+const LOSS_REDUCTION_FACTOR: f64 = 0.5;
+
+/// The minimum congestion window, in packets.
+const MINIMUM_WINDOW_PACKETS: usize = 2;
+
+/// The RTT below which the additive increase is scaled down.
+const TARGET_RTT: Duration = Duration::from_millis(25);
+
+/// The pacing gain applied to `cwnd / srtt` during slow start.
+const SLOW_START_PACING_GAIN: f64 = 2.0;
+
+/// The pacing gain applied to `cwnd / srtt` during congestion avoidance.
+const CONGESTION_AVOIDANCE_PACING_GAIN: f64 = 1.2;
+
+#[derive(Debug)]
+pub(crate) struct Prague {
+    mss: usize,
+
+    initial_cwnd: usize,
+
+    max_cwnd: usize,
+
+    cwnd: usize,
+
+    ssthresh: usize,
+
+    /// The fraction of a byte the window grew by, carried over to the next
+    /// ACK during congestion avoidance.
+    cwnd_increase: f64,
+
+    /// The moving average of the fraction of CE-marked bytes per round trip.
+    alpha: f64,
+
+    largest_sent: Option<u64>,
+
+    largest_acked: Option<u64>,
+
+    /// The packet number whose acknowledgement ends the current round trip.
+    round_end: Option<u64>,
+
+    /// Whether the last ACK ended a round trip. `alpha` is only updated on the
+    /// next congestion event, as the CE marks reported by an ACK frame are
+    /// processed after the packets it acknowledges.
+    round_ended: bool,
+
+    acked_bytes_in_round: usize,
+
+    ce_bytes_in_round: usize,
+
+    /// The largest packet number sent when the window was last reduced.
+    recovery_end: Option<u64>,
+
+    max_bandwidth: Bandwidth,
+}
+
+impl Prague {
+    pub fn new(
+        initial_cwnd_packets: usize, max_cwnd_packets: usize, mss: usize,
+    ) -> Self {
+        let initial_cwnd = initial_cwnd_packets * mss;
+
+        Prague {
+            mss,
+            initial_cwnd,
+            max_cwnd: max_cwnd_packets * mss,
+            cwnd: initial_cwnd,
+            ssthresh: usize::MAX,
+            cwnd_increase: 0.0,
+            alpha: 1.0,
+            largest_sent: None,
+            largest_acked: None,
+            round_end: None,
+            round_ended: false,
+            acked_bytes_in_round: 0,
+            ce_bytes_in_round: 0,
+            recovery_end: None,
+            max_bandwidth: Bandwidth::zero(),
+        }
+    }
+
+    fn in_slow_start(&self) -> bool {
+        self.cwnd < self.ssthresh
+    }
+
+    fn min_cwnd(&self) -> usize {
+        MINIMUM_WINDOW_PACKETS * self.mss
+    }
+
+    /// Reduces the congestion window by `factor`, and starts a recovery
+    /// period lasting until a packet sent after the reduction is acknowledged.
+    fn reduce_cwnd(&mut self, factor: f64) {
+        self.cwnd = ((self.cwnd as f64 * factor) as usize).max(self.min_cwnd());
+        self.ssthresh = self.cwnd;
+        self.cwnd_increase = 0.0;
+        self.recovery_end = self.largest_sent;
+    }
+
+    fn on_round_end(&mut self) {
+        if self.acked_bytes_in_round > 0 {
+            let ce_fraction = (self.ce_bytes_in_round as f64 /
+                self.acked_bytes_in_round as f64)
+                .min(1.0);
+
+            self.alpha += ALPHA_GAIN * (ce_fraction - self.alpha);
+        }
+
+        self.acked_bytes_in_round = 0;
+        self.ce_bytes_in_round = 0;
+        self.round_ended = false;
+    }
+
+    fn increase_cwnd(&mut self, bytes_acked: usize, srtt: Duration) {
+        if self.in_slow_start() {
+            self.cwnd += bytes_acked;
+        } else {
+            // Grow by one MSS per round trip, scaled by the square of the
+            // ratio to the target RTT, so that the window of flows with a
+            // short RTT grows at the same rate over time as one with the
+            // target RTT.
+            let rtt_scale = if srtt < TARGET_RTT {
+                (srtt.as_secs_f64() / TARGET_RTT.as_secs_f64()).powi(2)
+            } else {
+                1.0
+            };
+
+            self.cwnd_increase +=
+                self.mss as f64 * bytes_acked as f64 * rtt_scale /
+                    self.cwnd as f64;
+
+            let increase = self.cwnd_increase as usize;
+
+            self.cwnd += increase;
+            self.cwnd_increase -= increase as f64;
+        }
+
+        self.cwnd = self.cwnd.min(self.max_cwnd);
+    }
+}
+
+impl CongestionControl for Prague {
+    #[cfg(feature = "qlog")]
+    fn state_str(&self) -> &'static str {
+        if self.is_in_recovery() {
+            "recovery"
+        } else if self.in_slow_start() {
+            "slow_start"
+        } else {
+            "congestion_avoidance"
+        }
+    }
+
+    fn get_congestion_window(&self) -> usize {
+        self.cwnd
+    }
+
+    fn get_congestion_window_in_packets(&self) -> usize {
+        self.cwnd / self.mss
+    }
+
+    fn can_send(&self, bytes_in_flight: usize) -> bool {
+        bytes_in_flight < self.cwnd
+    }
+
+    fn on_packet_sent(
+        &mut self, _sent_time: Instant, _bytes_in_flight: usize,
+        packet_number: u64, _bytes: usize, _is_retransmissible: bool,
+    ) {
+        self.largest_sent = Some(packet_number);
+    }
+
+    fn on_ecn_ce(&mut self, ce_bytes: usize) {
+        self.ce_bytes_in_round += ce_bytes;
+
+        if !self.is_in_recovery() {
+            self.reduce_cwnd(1.0 - self.alpha / 2.0);
+        }
+    }
+
+    fn on_congestion_event(
+        &mut self, _rtt_updated: bool, _prior_in_flight: usize,
+        _bytes_in_flight: usize, _event_time: Instant, acked_packets: &[Acked],
+        lost_packets: &[Lost], _least_unacked: u64, rtt_stats: &RttStats,
+        _recovery_stats: &mut RecoveryStats,
+    ) {
+        if self.round_ended {
+            self.on_round_end();
+        }
+
+        let new_loss = lost_packets
+            .iter()
+            .any(|p| self.recovery_end.is_none_or(|end| p.packet_number > end));
+
+        if new_loss {
+            self.reduce_cwnd(LOSS_REDUCTION_FACTOR);
+        }
+
+        if acked_packets.is_empty() {
+            return;
+        }
+
+        let mut bytes_acked = 0;
+
+        for p in acked_packets {
+            bytes_acked += p.bytes_acked;
+
+            self.largest_acked = self.largest_acked.max(Some(p.pkt_num));
+
+            if self.round_end.is_none_or(|end| p.pkt_num >= end) {
+                self.round_ended = true;
+            }
+        }
+
+        self.acked_bytes_in_round += bytes_acked;
+
+        if self.round_ended {
+            self.round_end = self.largest_sent;
+        }
+
+        if !self.is_in_recovery() {
+            self.increase_cwnd(bytes_acked, rtt_stats.rtt());
+        }
+
+        self.max_bandwidth =
+            self.max_bandwidth.max(self.bandwidth_estimate(rtt_stats));
+    }
+
+    fn on_retransmission_timeout(&mut self, _packets_retransmitted: bool) {}
+
+    fn on_connection_migration(&mut self) {
+        *self = Prague::new(
+            self.initial_cwnd / self.mss,
+            self.max_cwnd / self.mss,
+            self.mss,
+        );
+    }
+
+    fn limit_cwnd(&mut self, max_cwnd: usize) {
+        self.max_cwnd = max_cwnd;
+        self.cwnd = self.cwnd.min(max_cwnd);
+    }
+
+    fn is_in_recovery(&self) -> bool {
+        self.recovery_end.is_some_and(|end| {
+            self.largest_acked.is_none_or(|largest| largest <= end)
+        })
+    }
+
+    fn is_cwnd_limited(&self, bytes_in_flight: usize) -> bool {
+        bytes_in_flight >= self.cwnd
+    }
+
+    fn pacing_rate(
+        &self, _bytes_in_flight: usize, rtt_stats: &RttStats,
+    ) -> Bandwidth {
+        let gain = if self.in_slow_start() {
+            SLOW_START_PACING_GAIN
+        } else {
+            CONGESTION_AVOIDANCE_PACING_GAIN
+        };
+
+        self.bandwidth_estimate(rtt_stats) * gain
+    }
+
+    fn bandwidth_estimate(&self, rtt_stats: &RttStats) -> Bandwidth {
+        Bandwidth::from_bytes_and_time_delta(self.cwnd, rtt_stats.rtt())
+    }
+
+    fn max_bandwidth(&self) -> Bandwidth {
+        self.max_bandwidth
+    }
+
+    fn update_mss(&mut self, new_mss: usize) {
+        self.cwnd = self.cwnd * new_mss / self.mss;
+        self.initial_cwnd = self.initial_cwnd * new_mss / self.mss;
+        self.max_cwnd = self.max_cwnd * new_mss / self.mss;
+
+        if self.ssthresh != usize::MAX {
+            self.ssthresh = self.ssthresh * new_mss / self.mss;
+        }
+
+        self.mss = new_mss;
+    }
+
+    #[cfg(feature = "qlog")]
+    fn ssthresh(&self) -> Option<u64> {
+        (self.ssthresh != usize::MAX).then_some(self.ssthresh as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::recovery::gcongestion::test_link::simulate;
+    use crate::recovery::gcongestion::test_link::Flow;
+    use crate::recovery::gcongestion::test_link::MSS;
+
+    fn acked(pkt_num: u64, time_sent: Instant) -> Acked {
+        Acked {
+            pkt_num,
+            time_sent,
+            bytes_acked: MSS,
+        }
+    }
+
+    fn lost(packet_number: u64) -> Lost {
+        Lost {
+            packet_number,
+            bytes_lost: MSS,
+        }
+    }
+
+    fn congestion_event(
+        prague: &mut Prague, now: Instant, acked: &[Acked], lost: &[Lost],
+        rtt_stats: &RttStats,
+    ) {
+        prague.on_congestion_event(
+            true,
+            0,
+            0,
+            now,
+            acked,
+            lost,
+            0,
+            rtt_stats,
+            &mut RecoveryStats::default(),
+        );
+    }
+
+    #[test]
+    fn reduction_proportional_to_marks() {
+        let mut prague = Prague::new(10, 1000, MSS);
+        let rtt_stats =
+            RttStats::new(Duration::from_millis(50), Duration::from_millis(25));
+        let now = Instant::now();
+
+        for pn in 0..10 {
+            prague.on_packet_sent(now, 0, pn, MSS, true);
+        }
+
+        // Without any previous measurement, the first mark halves the window,
+        // after it grew in slow start by the acknowledged packet.
+        congestion_event(&mut prague, now, &[acked(0, now)], &[], &rtt_stats);
+        prague.on_ecn_ce(MSS);
+
+        assert_eq!(prague.get_congestion_window(), 11 * MSS / 2);
+        assert!(prague.is_in_recovery());
+
+        // Further marks during the recovery period don't reduce it again.
+        congestion_event(&mut prague, now, &[acked(1, now)], &[], &rtt_stats);
+        prague.on_ecn_ce(MSS);
+
+        let acks: Vec<_> = (2..10).map(|pn| acked(pn, now)).collect();
+        congestion_event(&mut prague, now, &acks, &[], &rtt_stats);
+
+        assert_eq!(prague.get_congestion_window(), 11 * MSS / 2);
+        assert!(prague.is_in_recovery());
+
+        // The first round trip only included the first packet, which was
+        // marked.
+        assert_eq!(prague.alpha, 1.0);
+
+        // Recovery ends once a packet sent after the reduction is acknowledged.
+        for pn in 10..20 {
+            prague.on_packet_sent(now, 0, pn, MSS, true);
+        }
+
+        congestion_event(&mut prague, now, &[acked(10, now)], &[], &rtt_stats);
+        assert!(!prague.is_in_recovery());
+
+        // One out of the nine packets acknowledged in the second round trip
+        // was marked.
+        let alpha = 1.0 + ALPHA_GAIN * (1.0 / 9.0 - 1.0);
+        assert!((prague.alpha - alpha).abs() < 1e-9);
+
+        let cwnd = prague.get_congestion_window();
+        prague.on_ecn_ce(MSS);
+        assert_eq!(
+            prague.get_congestion_window(),
+            (cwnd as f64 * (1.0 - alpha / 2.0)) as usize
+        );
+    }
+
+    #[test]
+    fn loss_halves_window() {
+        let mut prague = Prague::new(10, 1000, MSS);
+        let rtt_stats =
+            RttStats::new(Duration::from_millis(50), Duration::from_millis(25));
+        let now = Instant::now();
+
+        for pn in 0..10 {
+            prague.on_packet_sent(now, 0, pn, MSS, true);
+        }
+
+        congestion_event(&mut prague, now, &[], &[lost(0)], &rtt_stats);
+        assert_eq!(prague.get_congestion_window(), 5 * MSS);
+
+        // Losses of packets sent before the reduction are ignored.
+        congestion_event(&mut prague, now, &[], &[lost(1)], &rtt_stats);
+        assert_eq!(prague.get_congestion_window(), 5 * MSS);
+
+        prague.on_packet_sent(now, 0, 10, MSS, true);
+
+        congestion_event(&mut prague, now, &[], &[lost(10)], &rtt_stats);
+        assert_eq!(prague.get_congestion_window(), 5 * MSS / 2);
+
+        // The window doesn't go below the minimum.
+        prague.on_packet_sent(now, 0, 11, MSS, true);
+
+        congestion_event(&mut prague, now, &[], &[lost(11)], &rtt_stats);
+        assert_eq!(prague.get_congestion_window(), 2 * MSS);
+    }
+
+    #[test]
+    fn rtt_independent_increase() {
+        let now = Instant::now();
+
+        let increase_per_round = |rtt: Duration| {
+            let mut prague = Prague::new(10, 1000, MSS);
+            prague.ssthresh = prague.cwnd;
+
+            let mut rtt_stats = RttStats::new(rtt, Duration::from_millis(25));
+            rtt_stats.update_rtt(rtt, Duration::ZERO, now, true);
+
+            let acks: Vec<_> = (0..10).map(|pn| acked(pn, now)).collect();
+            for pn in 0..10 {
+                prague.on_packet_sent(now, 0, pn, MSS, true);
+            }
+
+            congestion_event(&mut prague, now, &acks, &[], &rtt_stats);
+
+            prague.get_congestion_window() - 10 * MSS
+        };
+
+        assert_eq!(increase_per_round(TARGET_RTT), MSS);
+        assert_eq!(increase_per_round(TARGET_RTT * 2), MSS);
+
+        // Half the RTT takes twice the round trips to grow by a quarter of
+        // the increase, i.e. the same increase over time.
+        assert_eq!(increase_per_round(TARGET_RTT / 2), MSS / 4);
+    }
+
+    fn prague() -> Prague {
+        Prague::new(10, 10000, MSS)
+    }
+
+    #[test]
+    fn l4s_queue_low_delay() {
+        let mut flows = [Flow::new(
+            prague(),
+            Duration::from_millis(20),
+            true,
+            Duration::ZERO,
+        )];
+
+        let outcome = simulate(
+            &mut flows,
+            Bandwidth::from_mbits_per_second(20),
+            Duration::from_secs(2),
+            Duration::from_secs(10),
+        );
+
+        assert!(outcome.link_share[0] > 0.9, "{}", outcome.link_share[0]);
+        assert!(
+            outcome.queue_delay < Duration::from_millis(2),
+            "{:?}",
+            outcome.queue_delay
+        );
+    }
+
+    #[test]
+    fn classic_queue_fallback() {
+        // Without ECN-CE marks Prague fills the classic queue until packets
+        // are dropped.
+        let mut flows = [Flow::new(
+            prague(),
+            Duration::from_millis(20),
+            false,
+            Duration::ZERO,
+        )];
+
+        let outcome = simulate(
+            &mut flows,
+            Bandwidth::from_mbits_per_second(20),
+            Duration::from_secs(2),
+            Duration::from_secs(10),
+        );
+
+        assert!(outcome.link_share[0] > 0.9, "{}", outcome.link_share[0]);
+        assert!(
+            outcome.queue_delay > Duration::from_millis(20),
+            "{:?}",
+            outcome.queue_delay
+        );
+    }
+
+    #[test]
+    fn l4s_flows_converge() {
+        let mut flows = [
+            Flow::new(prague(), Duration::from_millis(20), true, Duration::ZERO),
+            Flow::new(
+                prague(),
+                Duration::from_millis(20),
+                true,
+                Duration::from_secs(2),
+            ),
+        ];
+
+        let outcome = simulate(
+            &mut flows,
+            Bandwidth::from_mbits_per_second(20),
+            Duration::from_secs(10),
+            Duration::from_secs(20),
+        );
+
+        for share in &outcome.link_share {
+            assert!((0.35..0.65).contains(share), "{:?}", outcome.link_share);
+        }
+
+        assert!(
+            outcome.queue_delay < Duration::from_millis(2),
+            "{:?}",
+            outcome.queue_delay
+        );
+    }
+}
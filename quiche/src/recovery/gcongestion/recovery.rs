@@ -42,9 +42,15 @@ use crate::recovery::MAX_PTO_PROBES_COUNT;
 use crate::recovery::PACKET_REORDER_TIME_THRESHOLD;
 
 use super::bbr2::BBRv2;
+use super::bbr2::BbrVersion;
+use super::custom::CustomSender;
+use super::ledbat::Ledbat;
 use super::pacer::Pacer;
+use super::prague::Prague;
 use super::Acked;
+use super::CongestionControllerParams;
 use super::Lost;
+use super::Sender;
 
 // Congestion Control
 const MAX_WINDOW_PACKETS: usize = 20_000;
@@ -63,6 +69,7 @@ enum SentStatus {
         in_flight: bool,
         has_data: bool,
         is_pmtud_probe: bool,
+        ecn_marked: bool,
         sent_bytes: usize,
         frames: SmallVec<[frame::Frame; 1]>,
     },
@@ -117,6 +124,7 @@ struct AckedDetectionResult {
     spurious_losses: usize,
     spurious_pkt_thresh: Option<u64>,
     has_ack_eliciting: bool,
+    ecn_marked_acked: usize,
 }
 
 struct LossDetectionResult {
@@ -125,6 +133,8 @@ struct LossDetectionResult {
 
     pmtud_lost_bytes: usize,
     pmtud_lost_packets: SmallVec<[u64; 1]>,
+
+    ecn_marked_lost: usize,
 }
 
 impl RecoveryEpoch {
@@ -174,6 +184,7 @@ impl RecoveryEpoch {
         let mut spurious_losses = 0;
         let mut spurious_pkt_thresh = None;
         let mut has_ack_eliciting = false;
+        let mut ecn_marked_acked = 0;
 
         let largest_ack_received = peer_sent_ack_ranges.last().unwrap();
         let largest_acked = self
@@ -217,6 +228,7 @@ impl RecoveryEpoch {
                             sent_bytes,
                             frames,
                             ack_eliciting,
+                            ecn_marked,
                             ..
                         } => {
                             if in_flight {
@@ -226,12 +238,21 @@ impl RecoveryEpoch {
                             newly_acked.push(Acked {
                                 pkt_num: *pkt_num,
                                 time_sent,
+                                bytes_acked: if in_flight {
+                                    sent_bytes
+                                } else {
+                                    0
+                                },
                             });
 
                             self.acked_frames.extend(frames);
 
                             has_ack_eliciting |= ack_eliciting;
 
+                            if ecn_marked {
+                                ecn_marked_acked += 1;
+                            }
+
                             trace!("{trace_id} packet newly acked {pkt_num}");
                         },
 
@@ -256,6 +277,7 @@ impl RecoveryEpoch {
             spurious_losses,
             spurious_pkt_thresh,
             has_ack_eliciting,
+            ecn_marked_acked,
         })
     }
 
@@ -271,6 +293,7 @@ impl RecoveryEpoch {
         let largest_acked = self.largest_acked_packet.unwrap_or(0);
         let mut pmtud_lost_bytes = 0;
         let mut pmtud_lost_packets = SmallVec::new();
+        let mut ecn_marked_lost = 0;
 
         for SentPacket { pkt_num, status } in &mut self.sent_packets {
             if *pkt_num > largest_acked {
@@ -290,11 +313,16 @@ impl RecoveryEpoch {
                         sent_bytes,
                         frames,
                         is_pmtud_probe,
+                        ecn_marked,
                         ..
                     } = status.lose()
                     {
                         self.lost_frames.extend(frames);
 
+                        if ecn_marked {
+                            ecn_marked_lost += 1;
+                        }
+
                         if in_flight {
                             self.pkts_in_flight -= 1;
 
@@ -326,6 +354,8 @@ impl RecoveryEpoch {
 
             pmtud_lost_bytes,
             pmtud_lost_packets,
+
+            ecn_marked_lost,
         }
     }
 
@@ -465,6 +495,12 @@ pub struct GRecovery {
     /// [`Self::detect_and_remove_lost_packets`] to avoid allocations
     lost_reuse: Vec<Lost>,
 
+    /// The number of ECN-marked packets that were acknowledged.
+    ecn_marked_acked: usize,
+
+    /// The number of ECN-marked packets that were declared lost.
+    ecn_marked_lost: usize,
+
     pacer: Pacer,
 }
 
@@ -480,14 +516,56 @@ impl GRecovery {
     }
 
     pub fn new(recovery_config: &RecoveryConfig) -> Option<Self> {
-        let cc = match recovery_config.cc_algorithm {
-            CongestionControlAlgorithm::Bbr2Gcongestion => BBRv2::new(
-                recovery_config.initial_congestion_window_packets,
-                MAX_WINDOW_PACKETS,
-                recovery_config.max_send_udp_payload_size,
-                recovery_config.initial_rtt,
-                recovery_config.custom_bbr_params.as_ref(),
-            ),
+        let cc = match (&recovery_config.custom_cc, recovery_config.cc_algorithm)
+        {
+            (Some(custom_cc), _) => {
+                let params = CongestionControllerParams {
+                    max_datagram_size: recovery_config.max_send_udp_payload_size,
+                    initial_congestion_window_packets: recovery_config
+                        .initial_congestion_window_packets,
+                    initial_rtt: recovery_config.initial_rtt,
+                };
+
+                Sender::Custom(CustomSender::new(
+                    custom_cc.create(&params),
+                    recovery_config.max_send_udp_payload_size,
+                ))
+            },
+
+            (None, CongestionControlAlgorithm::Bbr2Gcongestion) =>
+                Sender::BBRv2(BBRv2::new(
+                    recovery_config.initial_congestion_window_packets,
+                    MAX_WINDOW_PACKETS,
+                    recovery_config.max_send_udp_payload_size,
+                    recovery_config.initial_rtt,
+                    recovery_config.custom_bbr_params.as_ref(),
+                    BbrVersion::V2,
+                )),
+
+            (None, CongestionControlAlgorithm::Bbr3Gcongestion) =>
+                Sender::BBRv2(BBRv2::new(
+                    recovery_config.initial_congestion_window_packets,
+                    MAX_WINDOW_PACKETS,
+                    recovery_config.max_send_udp_payload_size,
+                    recovery_config.initial_rtt,
+                    recovery_config.custom_bbr_params.as_ref(),
+                    BbrVersion::V3,
+                )),
+
+            (None, CongestionControlAlgorithm::Prague) =>
+                Sender::Prague(Prague::new(
+                    recovery_config.initial_congestion_window_packets,
+                    MAX_WINDOW_PACKETS,
+                    recovery_config.max_send_udp_payload_size,
+                )),
+
+            (None, CongestionControlAlgorithm::Ledbat) =>
+                Sender::Ledbat(Ledbat::new(
+                    recovery_config.initial_congestion_window_packets,
+                    MAX_WINDOW_PACKETS,
+                    recovery_config.max_send_udp_payload_size,
+                )),
+
             _ => return None,
         };
 
@@ -530,6 +608,9 @@ impl GRecovery {
 
             newly_acked: Vec::new(),
             lost_reuse: Vec::new(),
+
+            ecn_marked_acked: 0,
+            ecn_marked_lost: 0,
         })
     }
 
@@ -545,6 +626,7 @@ impl GRecovery {
             lost_packets,
             pmtud_lost_bytes,
             pmtud_lost_packets,
+            ecn_marked_lost,
         } = self.epochs[epoch].detect_and_remove_lost_packets(
             loss_delay,
             self.loss_thresh.pkt_thresh(),
@@ -559,6 +641,8 @@ impl GRecovery {
             self.pacer.on_packet_neutered(pkt);
         }
 
+        self.ecn_marked_lost += ecn_marked_lost;
+
         (lost_bytes, lost_packets)
     }
 
@@ -711,6 +795,7 @@ impl RecoveryOps for GRecovery {
         let ack_eliciting = pkt.ack_eliciting;
         let in_flight = pkt.in_flight;
         let is_pmtud_probe = pkt.is_pmtud_probe;
+        let ecn_marked = pkt.ecn_marked;
         let pkt_num = pkt.pkt_num;
         let sent_bytes = pkt.size;
 
@@ -723,6 +808,7 @@ impl RecoveryOps for GRecovery {
             ack_eliciting,
             in_flight,
             is_pmtud_probe,
+            ecn_marked,
             has_data: pkt.has_data,
             sent_bytes,
             frames: pkt.frames,
@@ -781,6 +867,7 @@ impl RecoveryOps for GRecovery {
             spurious_losses,
             spurious_pkt_thresh,
             has_ack_eliciting,
+            ecn_marked_acked,
         } = self.epochs[epoch].detect_and_remove_acked_packets(
             peer_sent_ack_ranges,
             &mut self.newly_acked,
@@ -789,6 +876,7 @@ impl RecoveryOps for GRecovery {
         )?;
 
         self.lost_spurious_count += spurious_losses;
+        self.ecn_marked_acked += ecn_marked_acked;
         if let Some(thresh) = spurious_pkt_thresh {
             self.loss_thresh.on_spurious_loss(thresh);
         }
@@ -967,6 +1055,43 @@ impl RecoveryOps for GRecovery {
         self.set_loss_detection_timer(handshake_status, now);
     }
 
+    fn on_path_abandoned(
+        &mut self, epoch: packet::Epoch, handshake_status: HandshakeStatus,
+        now: Instant,
+    ) {
+        let unacked_frames = self.epochs[epoch]
+            .sent_packets
+            .iter_mut()
+            .filter_map(|p| match &mut p.status {
+                SentStatus::Sent { frames, .. } => Some(std::mem::take(frames)),
+
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        self.on_pkt_num_space_discarded(epoch, handshake_status, now);
+
+        self.epochs[epoch].lost_frames.extend(unacked_frames);
+    }
+
+    fn unacked_frames(&self, epoch: packet::Epoch) -> Vec<frame::Frame> {
+        let epoch = &self.epochs[epoch];
+
+        epoch
+            .sent_packets
+            .iter()
+            .filter_map(|p| match &p.status {
+                SentStatus::Sent { frames, .. } => Some(frames.iter()),
+
+                _ => None,
+            })
+            .flatten()
+            .chain(epoch.lost_frames.iter())
+            .cloned()
+            .collect()
+    }
+
     fn on_path_change(
         &mut self, epoch: packet::Epoch, now: Instant, _trace_id: &str,
     ) -> (usize, usize) {
@@ -1048,6 +1173,10 @@ impl RecoveryOps for GRecovery {
         self.pacer.on_app_limited(self.bytes_in_flight.get())
     }
 
+    fn ack_eliciting_threshold(&self) -> Option<u64> {
+        self.pacer.ack_eliciting_threshold(&self.rtt_stats)
+    }
+
     #[cfg(test)]
     fn sent_packets_len(&self, epoch: packet::Epoch) -> usize {
         self.epochs[epoch].sent_packets.len()
@@ -1126,6 +1255,19 @@ impl RecoveryOps for GRecovery {
         self.rtt_stats.max_ack_delay = max_ack_delay;
     }
 
+    fn ecn_marked_acked(&self) -> usize {
+        self.ecn_marked_acked
+    }
+
+    fn ecn_marked_lost(&self) -> usize {
+        self.ecn_marked_lost
+    }
+
+    fn on_ecn_ce(&mut self, _epoch: packet::Epoch, ce_count: u64, _now: Instant) {
+        self.pacer
+            .on_ecn_ce(ce_count as usize * self.max_datagram_size);
+    }
+
     fn get_next_release_time(&self) -> ReleaseDecision {
         self.pacer.get_next_release_time()
     }
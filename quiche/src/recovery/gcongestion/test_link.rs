@@ -0,0 +1,367 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A simulated bottleneck shared by bulk transfers, to test congestion
+//! controllers against each other.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::recovery::bandwidth::Bandwidth;
+use crate::recovery::rtt::RttStats;
+use crate::recovery::RecoveryStats;
+
+use super::Acked;
+use super::CongestionControl;
+use super::Lost;
+use super::Sender;
+
+pub(super) const MSS: usize = 1200;
+
+const TICK: Duration = Duration::from_micros(100);
+
+/// A packet queued at the bottleneck.
+struct QueuedPacket {
+    flow: usize,
+    pkt_num: u64,
+    enqueued: Instant,
+}
+
+/// A bottleneck link with the two queues of a DualQ Coupled AQM (RFC
+/// 9332), simplified: ECT(1) packets go in the L4S queue, which marks them
+/// with ECN-CE as soon as their sojourn time exceeds a shallow threshold
+/// and has priority over the classic queue, which drops packets once full.
+struct DualQueueLink {
+    /// The link capacity, in bytes per tick.
+    capacity: usize,
+
+    credit: usize,
+
+    l4s_queue: VecDeque<QueuedPacket>,
+
+    classic_queue: VecDeque<QueuedPacket>,
+
+    classic_queue_limit: usize,
+
+    mark_threshold: Duration,
+}
+
+impl DualQueueLink {
+    fn new(rate: Bandwidth) -> Self {
+        DualQueueLink {
+            capacity: rate.to_bytes_per_period(TICK) as usize,
+            credit: 0,
+            l4s_queue: VecDeque::new(),
+            classic_queue: VecDeque::new(),
+            classic_queue_limit: 200,
+            mark_threshold: Duration::from_millis(1),
+        }
+    }
+
+    /// Queues a packet, returning false if it's dropped.
+    fn enqueue(&mut self, pkt: QueuedPacket, l4s: bool) -> bool {
+        if l4s {
+            self.l4s_queue.push_back(pkt);
+        } else if self.classic_queue.len() < self.classic_queue_limit {
+            self.classic_queue.push_back(pkt);
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    /// Returns the packets sent over the link during the tick, with their
+    /// sojourn time and whether they were marked with ECN-CE.
+    fn dequeue(&mut self, now: Instant) -> Vec<(usize, u64, Duration, bool)> {
+        let mut sent = Vec::new();
+
+        self.credit += self.capacity;
+
+        while self.credit >= MSS {
+            let (pkt, l4s) = match self.l4s_queue.pop_front() {
+                Some(pkt) => (pkt, true),
+
+                None => match self.classic_queue.pop_front() {
+                    Some(pkt) => (pkt, false),
+
+                    None => {
+                        // The link can't save up unused capacity.
+                        self.credit = self.credit.min(MSS);
+                        break;
+                    },
+                },
+            };
+
+            let sojourn = now - pkt.enqueued;
+            let ce = l4s && sojourn > self.mark_threshold;
+
+            sent.push((pkt.flow, pkt.pkt_num, sojourn, ce));
+
+            self.credit -= MSS;
+        }
+
+        sent
+    }
+}
+
+struct SentPacket {
+    pkt_num: u64,
+    time_sent: Instant,
+    dropped: bool,
+}
+
+/// A bulk transfer over the bottleneck.
+pub(super) struct Flow {
+    sender: Sender,
+
+    rtt_stats: RttStats,
+
+    base_rtt: Duration,
+
+    l4s: bool,
+
+    /// When the flow starts, relative to the start of the simulation.
+    start_offset: Duration,
+
+    next_pkt_num: u64,
+
+    next_send_time: Instant,
+
+    bytes_in_flight: usize,
+
+    sent: VecDeque<SentPacket>,
+
+    /// The ACKs travelling back to the sender, with their arrival time and
+    /// whether the packet was marked with ECN-CE.
+    acks: VecDeque<(Instant, u64, bool)>,
+
+    delivered_bytes: usize,
+}
+
+impl Flow {
+    pub(super) fn new(
+        sender: impl Into<Sender>, base_rtt: Duration, l4s: bool,
+        start_offset: Duration,
+    ) -> Self {
+        Flow {
+            sender: sender.into(),
+            rtt_stats: RttStats::new(
+                Duration::from_millis(333),
+                Duration::from_millis(25),
+            ),
+            base_rtt,
+            l4s,
+            start_offset,
+            next_pkt_num: 0,
+            next_send_time: Instant::now(),
+            bytes_in_flight: 0,
+            sent: VecDeque::new(),
+            acks: VecDeque::new(),
+            delivered_bytes: 0,
+        }
+    }
+
+    fn on_acks(&mut self, now: Instant) {
+        let mut acked_packets = Vec::new();
+        let mut lost_packets = Vec::new();
+        let mut ce_bytes = 0;
+
+        while let Some(&(arrival, pkt_num, ce)) = self.acks.front() {
+            if arrival > now {
+                break;
+            }
+
+            self.acks.pop_front();
+
+            // Packets are delivered in order, so any packet sent before
+            // the acknowledged one was dropped.
+            while let Some(pkt) = self.sent.pop_front() {
+                if pkt.pkt_num == pkt_num {
+                    self.rtt_stats.update_rtt(
+                        now - pkt.time_sent,
+                        Duration::ZERO,
+                        now,
+                        true,
+                    );
+
+                    acked_packets.push(Acked {
+                        pkt_num,
+                        time_sent: pkt.time_sent,
+                        bytes_acked: MSS,
+                    });
+                    break;
+                }
+
+                assert!(pkt.dropped);
+                lost_packets.push(Lost {
+                    packet_number: pkt.pkt_num,
+                    bytes_lost: MSS,
+                });
+            }
+
+            if ce {
+                ce_bytes += MSS;
+            }
+        }
+
+        // Declare dropped packets lost after a timeout, in case no later
+        // packet is acknowledged.
+        while self.sent.front().is_some_and(|pkt| {
+            pkt.dropped && now - pkt.time_sent > self.base_rtt * 4
+        }) {
+            let pkt = self.sent.pop_front().unwrap();
+            lost_packets.push(Lost {
+                packet_number: pkt.pkt_num,
+                bytes_lost: MSS,
+            });
+        }
+
+        if acked_packets.is_empty() && lost_packets.is_empty() {
+            return;
+        }
+
+        self.bytes_in_flight -= (acked_packets.len() + lost_packets.len()) * MSS;
+
+        self.sender.on_congestion_event(
+            true,
+            0,
+            self.bytes_in_flight,
+            now,
+            &acked_packets,
+            &lost_packets,
+            0,
+            &self.rtt_stats,
+            &mut RecoveryStats::default(),
+        );
+
+        if ce_bytes > 0 {
+            self.sender.on_ecn_ce(ce_bytes);
+        }
+    }
+
+    fn send(&mut self, flow: usize, link: &mut DualQueueLink, now: Instant) {
+        while now >= self.next_send_time &&
+            self.sender.can_send(self.bytes_in_flight)
+        {
+            let pkt_num = self.next_pkt_num;
+            self.next_pkt_num += 1;
+
+            self.sender.on_packet_sent(
+                now,
+                self.bytes_in_flight,
+                pkt_num,
+                MSS,
+                true,
+            );
+
+            self.bytes_in_flight += MSS;
+
+            let pkt = QueuedPacket {
+                flow,
+                pkt_num,
+                enqueued: now,
+            };
+
+            self.sent.push_back(SentPacket {
+                pkt_num,
+                time_sent: now,
+                dropped: !link.enqueue(pkt, self.l4s),
+            });
+
+            let pacing_rate = self
+                .sender
+                .pacing_rate(self.bytes_in_flight, &self.rtt_stats);
+
+            self.next_send_time = self.next_send_time.max(now - TICK) +
+                pacing_rate.transfer_time(MSS);
+        }
+    }
+}
+
+/// The outcome of a simulation, measured after the warm-up period.
+pub(super) struct Outcome {
+    /// The share of the link capacity used by each flow.
+    pub(super) link_share: Vec<f64>,
+
+    /// The average sojourn time of the packets at the bottleneck.
+    pub(super) queue_delay: Duration,
+}
+
+pub(super) fn simulate(
+    flows: &mut [Flow], rate: Bandwidth, warm_up: Duration, duration: Duration,
+) -> Outcome {
+    let mut link = DualQueueLink::new(rate);
+
+    let start = Instant::now();
+    let measure_start = start + warm_up;
+    let end = start + duration;
+
+    let mut now = start;
+    let mut total_sojourn = Duration::ZERO;
+    let mut total_packets = 0;
+
+    for flow in flows.iter_mut() {
+        flow.next_send_time = start + flow.start_offset;
+    }
+
+    while now < end {
+        for flow in flows.iter_mut() {
+            flow.on_acks(now);
+        }
+
+        for (i, flow) in flows.iter_mut().enumerate() {
+            flow.send(i, &mut link, now);
+        }
+
+        for (i, pkt_num, sojourn, ce) in link.dequeue(now) {
+            let flow = &mut flows[i];
+
+            flow.acks.push_back((now + flow.base_rtt, pkt_num, ce));
+
+            if now >= measure_start {
+                flow.delivered_bytes += MSS;
+
+                total_sojourn += sojourn;
+                total_packets += 1;
+            }
+        }
+
+        now += TICK;
+    }
+
+    let capacity = rate.to_bytes_per_period(duration - warm_up) as f64;
+
+    Outcome {
+        link_share: flows
+            .iter()
+            .map(|f| f.delivered_bytes as f64 / capacity)
+            .collect(),
+
+        queue_delay: total_sojourn / total_packets.max(1),
+    }
+}
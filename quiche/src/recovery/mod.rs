@@ -44,8 +44,14 @@ use smallvec::SmallVec;
 
 use self::congestion::recovery::LegacyRecovery;
 use self::gcongestion::GRecovery;
+pub use gcongestion::AckedPacket;
 pub use gcongestion::BbrBwLoReductionStrategy;
 pub use gcongestion::BbrParams;
+pub use gcongestion::CongestionController;
+pub use gcongestion::CongestionControllerParams;
+pub(crate) use gcongestion::CustomCongestionControl;
+pub use gcongestion::LostPacket;
+pub use gcongestion::RttEstimate;
 
 // Loss Recovery
 const INITIAL_PACKET_THRESHOLD: u64 = 3;
@@ -126,12 +132,13 @@ impl std::fmt::Debug for LossDetectionTimer {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct RecoveryConfig {
     pub initial_rtt: Duration,
     pub max_send_udp_payload_size: usize,
     pub max_ack_delay: Duration,
     pub cc_algorithm: CongestionControlAlgorithm,
+    pub custom_cc: Option<CustomCongestionControl>,
     pub custom_bbr_params: Option<BbrParams>,
     pub hystart: bool,
     pub pacing: bool,
@@ -147,6 +154,7 @@ impl RecoveryConfig {
             max_send_udp_payload_size: config.max_send_udp_payload_size,
             max_ack_delay: Duration::ZERO,
             cc_algorithm: config.cc_algorithm,
+            custom_cc: config.custom_cc.clone(),
             custom_bbr_params: config.custom_bbr_params,
             hystart: config.hystart,
             pacing: config.pacing,
@@ -156,6 +164,13 @@ impl RecoveryConfig {
             enable_relaxed_loss_threshold: config.enable_relaxed_loss_threshold,
         }
     }
+
+    /// Returns whether the congestion control algorithm supports L4S, so
+    /// packets need to be marked with ECT(1).
+    pub fn l4s(&self) -> bool {
+        self.custom_cc.is_none() &&
+            self.cc_algorithm == CongestionControlAlgorithm::Prague
+    }
 }
 
 #[enum_dispatch::enum_dispatch(RecoveryOps)]
@@ -226,6 +241,17 @@ pub trait RecoveryOps {
     fn on_path_change(
         &mut self, epoch: packet::Epoch, now: Instant, _trace_id: &str,
     ) -> (usize, usize);
+
+    /// Stops tracking the packets sent on an abandoned path, and schedules
+    /// the retransmission of the frames they carried that were not acked.
+    fn on_path_abandoned(
+        &mut self, epoch: packet::Epoch, handshake_status: HandshakeStatus,
+        now: Instant,
+    );
+
+    /// Returns the frames that were neither acked nor processed as lost yet,
+    /// including the ones carried by packets still in flight.
+    fn unacked_frames(&self, epoch: packet::Epoch) -> Vec<frame::Frame>;
     fn loss_detection_timer(&self) -> Option<Instant>;
     fn cwnd(&self) -> usize;
     fn cwnd_available(&self) -> usize;
@@ -256,6 +282,11 @@ pub trait RecoveryOps {
 
     fn on_app_limited(&mut self);
 
+    /// The number of ack-eliciting packets the congestion controller would
+    /// like the peer to receive before sending an ACK, if it wants less
+    /// frequent acknowledgements than the default.
+    fn ack_eliciting_threshold(&self) -> Option<u64>;
+
     // Since a recovery module is path specific, this tracks the largest packet
     // sent per path.
     #[cfg(test)]
@@ -302,6 +333,16 @@ pub trait RecoveryOps {
 
     fn update_max_ack_delay(&mut self, max_ack_delay: Duration);
 
+    /// Returns the number of ECN-marked packets that were acknowledged.
+    fn ecn_marked_acked(&self) -> usize;
+
+    /// Returns the number of ECN-marked packets that were declared lost.
+    fn ecn_marked_lost(&self) -> usize;
+
+    /// Reacts to the peer reporting `ce_count` new ECN-CE marks for packets
+    /// in the given packet number space.
+    fn on_ecn_ce(&mut self, epoch: packet::Epoch, ce_count: u64, now: Instant);
+
     #[cfg(feature = "qlog")]
     fn state_str(&self, now: Instant) -> &'static str;
 
@@ -372,6 +413,25 @@ pub enum CongestionControlAlgorithm {
     /// BBRv2 congestion control algorithm implementation from gcongestion
     /// branch. `bbr2_gcongestion` in a string form.
     Bbr2Gcongestion = 4,
+    /// BBRv3 congestion control algorithm, sharing the gcongestion BBRv2
+    /// implementation. `bbr3_gcongestion` in a string form.
+    Bbr3Gcongestion = 5,
+    /// Prague congestion control algorithm, for L4S. `prague` in a string
+    /// form.
+    ///
+    /// Packets are marked with the ECT(1) codepoint when ECN is enabled with
+    /// [`enable_ecn()`], so that L4S queues on the path can signal congestion
+    /// early. Pacing should remain enabled.
+    ///
+    /// [`enable_ecn()`]: struct.Config.html#method.enable_ecn
+    Prague          = 6,
+    /// LEDBAT++ congestion control algorithm, a delay-based scavenger for
+    /// background transfers. `ledbat` in a string form.
+    ///
+    /// It yields the available capacity to other flows as soon as they build
+    /// up a queue at the bottleneck, so it is suited to bulk transfers that
+    /// shouldn't slow down interactive traffic, such as backups.
+    Ledbat          = 7,
 }
 
 impl FromStr for CongestionControlAlgorithm {
@@ -388,6 +448,10 @@ impl FromStr for CongestionControlAlgorithm {
             "bbr" => Ok(CongestionControlAlgorithm::Bbr2Gcongestion),
             "bbr2" => Ok(CongestionControlAlgorithm::Bbr2Gcongestion),
             "bbr2_gcongestion" => Ok(CongestionControlAlgorithm::Bbr2Gcongestion),
+            "bbr3" => Ok(CongestionControlAlgorithm::Bbr3Gcongestion),
+            "bbr3_gcongestion" => Ok(CongestionControlAlgorithm::Bbr3Gcongestion),
+            "prague" => Ok(CongestionControlAlgorithm::Prague),
+            "ledbat" => Ok(CongestionControlAlgorithm::Ledbat),
             _ => Err(crate::Error::CongestionControl),
         }
     }
@@ -426,6 +490,8 @@ pub struct Sent {
     pub has_data: bool,
 
     pub is_pmtud_probe: bool,
+
+    pub ecn_marked: bool,
 }
 
 impl std::fmt::Debug for Sent {
@@ -440,7 +506,8 @@ impl std::fmt::Debug for Sent {
         write!(f, "tx_in_flight={} ", self.tx_in_flight)?;
         write!(f, "lost={} ", self.lost)?;
         write!(f, "has_data={} ", self.has_data)?;
-        write!(f, "is_pmtud_probe={}", self.is_pmtud_probe)?;
+        write!(f, "is_pmtud_probe={} ", self.is_pmtud_probe)?;
+        write!(f, "ecn_marked={}", self.ecn_marked)?;
 
         Ok(())
     }
@@ -854,6 +921,23 @@ mod tests {
             CongestionControlAlgorithm::from_str("bbr2_gcongestion").unwrap();
         assert_eq!(algo, CongestionControlAlgorithm::Bbr2Gcongestion);
         assert!(recovery_for_alg(algo).gcongestion_enabled());
+
+        let algo = CongestionControlAlgorithm::from_str("bbr3").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::Bbr3Gcongestion);
+        assert!(recovery_for_alg(algo).gcongestion_enabled());
+
+        let algo =
+            CongestionControlAlgorithm::from_str("bbr3_gcongestion").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::Bbr3Gcongestion);
+        assert!(recovery_for_alg(algo).gcongestion_enabled());
+
+        let algo = CongestionControlAlgorithm::from_str("prague").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::Prague);
+        assert!(recovery_for_alg(algo).gcongestion_enabled());
+
+        let algo = CongestionControlAlgorithm::from_str("ledbat").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::Ledbat);
+        assert!(recovery_for_alg(algo).gcongestion_enabled());
     }
 
     #[test]
@@ -866,7 +950,16 @@ mod tests {
 
     #[rstest]
     fn loss_on_pto(
-        #[values("reno", "cubic", "none", "bbr2_gcongestion")] cc_algorithm_name: &str,
+        #[values(
+            "reno",
+            "cubic",
+            "none",
+            "bbr2_gcongestion",
+            "bbr3_gcongestion",
+            "prague",
+            "ledbat"
+        )]
+        cc_algorithm_name: &str,
     ) {
         let mut cfg = Config::new(crate::PROTOCOL_VERSION).unwrap();
         assert_eq!(cfg.set_cc_algorithm_name(cc_algorithm_name), Ok(()));
@@ -895,6 +988,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -926,6 +1020,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -957,6 +1052,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -987,6 +1083,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1057,6 +1154,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1087,6 +1185,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1152,7 +1251,16 @@ mod tests {
 
     #[rstest]
     fn loss_on_timer(
-        #[values("reno", "cubic", "none", "bbr2_gcongestion")] cc_algorithm_name: &str,
+        #[values(
+            "reno",
+            "cubic",
+            "none",
+            "bbr2_gcongestion",
+            "bbr3_gcongestion",
+            "prague",
+            "ledbat"
+        )]
+        cc_algorithm_name: &str,
     ) {
         let mut cfg = Config::new(crate::PROTOCOL_VERSION).unwrap();
         assert_eq!(cfg.set_cc_algorithm_name(cc_algorithm_name), Ok(()));
@@ -1181,6 +1289,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1211,6 +1320,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1241,6 +1351,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1271,6 +1382,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1348,7 +1460,16 @@ mod tests {
 
     #[rstest]
     fn loss_on_reordering(
-        #[values("reno", "cubic", "none", "bbr2_gcongestion")] cc_algorithm_name: &str,
+        #[values(
+            "reno",
+            "cubic",
+            "none",
+            "bbr2_gcongestion",
+            "bbr3_gcongestion",
+            "prague",
+            "ledbat"
+        )]
+        cc_algorithm_name: &str,
     ) {
         let mut cfg = Config::new(crate::PROTOCOL_VERSION).unwrap();
         assert_eq!(cfg.set_cc_algorithm_name(cc_algorithm_name), Ok(()));
@@ -1834,11 +1955,13 @@ mod tests {
 
     #[rstest]
     fn pacing(
-        #[values("reno", "cubic", "bbr2_gcongestion")] cc_algorithm_name: &str,
+        #[values("reno", "cubic", "bbr2_gcongestion", "bbr3_gcongestion")]
+        cc_algorithm_name: &str,
         #[values(false, true)] time_sent_set_to_now: bool,
     ) {
         let pacing_enabled = cc_algorithm_name == "bbr2" ||
-            cc_algorithm_name == "bbr2_gcongestion";
+            cc_algorithm_name == "bbr2_gcongestion" ||
+            cc_algorithm_name == "bbr3_gcongestion";
 
         let mut cfg = Config::new(crate::PROTOCOL_VERSION).unwrap();
         assert_eq!(cfg.set_cc_algorithm_name(cc_algorithm_name), Ok(()));
@@ -1874,6 +1997,7 @@ mod tests {
                 lost: 0,
                 has_data: true,
                 is_pmtud_probe: false,
+                ecn_marked: false,
             };
 
             r.on_packet_sent(
@@ -1952,6 +2076,7 @@ mod tests {
             lost: 0,
             has_data: true,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -1992,6 +2117,7 @@ mod tests {
             lost: 0,
             has_data: true,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2024,6 +2150,7 @@ mod tests {
             lost: 0,
             has_data: true,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2403,6 +2530,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2435,6 +2563,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: true,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
@@ -2464,6 +2593,7 @@ mod tests {
             lost: 0,
             has_data: false,
             is_pmtud_probe: false,
+            ecn_marked: false,
         };
 
         r.on_packet_sent(
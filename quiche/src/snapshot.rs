@@ -0,0 +1,670 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Connection state snapshots.
+//!
+//! A snapshot captures the state of an established connection in a versioned
+//! binary blob, so that the connection can be restored in another process,
+//! e.g. to hand off long-lived connections during a graceful restart.
+//!
+//! Only the state needed to resume the connection is captured: the 1-RTT
+//! keys, the connection IDs, the transport parameters, the connection and
+//! stream flow control, the stream buffers, and the RTT estimate of the
+//! active path. Packets in flight are not tracked, so all stream data that
+//! was not acked yet is sent again once the connection is restored, while
+//! the congestion controller starts over using the exported RTT estimate.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::ack_freq;
+use crate::cid;
+use crate::crypto;
+use crate::dgram;
+use crate::ecn;
+use crate::flowcontrol;
+use crate::frame;
+use crate::packet;
+use crate::ranges;
+use crate::recovery;
+use crate::stream;
+
+use crate::BufFactory;
+use crate::Config;
+use crate::Connection;
+use crate::DatagramEvent;
+use crate::Error;
+use crate::Result;
+use crate::TransportParams;
+
+use crate::recovery::RecoveryOps;
+
+/// Marker identifying connection snapshots.
+const MAGIC: &[u8; 4] = b"QCSS";
+
+/// The version of the snapshot format.
+///
+/// This needs to be bumped whenever the format changes, as snapshots are
+/// meant to be exchanged between processes running different builds.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Serializes state into a snapshot.
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer::with_magic(MAGIC)
+    }
+
+    /// Creates a snapshot identified by `magic`, so that snapshots of
+    /// different kinds of state can't be mixed up.
+    pub fn with_magic(magic: &[u8; 4]) -> Self {
+        let mut w = Writer { buf: Vec::new() };
+
+        w.buf.extend_from_slice(magic);
+        w.put_u32(SNAPSHOT_VERSION);
+
+        w
+    }
+
+    pub fn put_u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn put_u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn put_u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn put_u128(&mut self, v: u128) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn put_bool(&mut self, v: bool) {
+        self.put_u8(v as u8);
+    }
+
+    pub fn put_bytes(&mut self, v: &[u8]) {
+        self.put_u64(v.len() as u64);
+        self.buf.extend_from_slice(v);
+    }
+
+    pub fn put_opt_u64(&mut self, v: Option<u64>) {
+        self.put_bool(v.is_some());
+
+        if let Some(v) = v {
+            self.put_u64(v);
+        }
+    }
+
+    pub fn put_opt_u128(&mut self, v: Option<u128>) {
+        self.put_bool(v.is_some());
+
+        if let Some(v) = v {
+            self.put_u128(v);
+        }
+    }
+
+    /// Writes the time left until `at`, as seen from `now`.
+    pub fn put_instant(&mut self, at: Instant, now: Instant) {
+        self.put_u64(at.saturating_duration_since(now).as_micros() as u64);
+    }
+
+    pub fn put_ranges(&mut self, v: &ranges::RangeSet) {
+        self.put_u64(v.len() as u64);
+
+        for r in v.iter() {
+            self.put_u64(r.start);
+            self.put_u64(r.end);
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Maps any error hit while reading a snapshot to [`Error::InvalidSnapshot`].
+pub(crate) fn decode_error<E>(_: E) -> Error {
+    Error::InvalidSnapshot
+}
+
+/// Deserializes state from a snapshot.
+pub(crate) struct Reader<'a> {
+    b: octets::Octets<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Result<Self> {
+        Reader::with_magic(buf, MAGIC)
+    }
+
+    /// Reads a snapshot written by [`Writer::with_magic()`].
+    pub fn with_magic(buf: &'a [u8], magic: &[u8; 4]) -> Result<Self> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        if b.get_bytes(magic.len()).map_err(decode_error)?.buf() != magic {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        if b.get_u32().map_err(decode_error)? != SNAPSHOT_VERSION {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        Ok(Reader { b })
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8> {
+        self.b.get_u8().map_err(decode_error)
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32> {
+        self.b.get_u32().map_err(decode_error)
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64> {
+        self.b.get_u64().map_err(decode_error)
+    }
+
+    pub fn get_u128(&mut self) -> Result<u128> {
+        let hi = self.get_u64()? as u128;
+        let lo = self.get_u64()? as u128;
+
+        Ok((hi << 64) | lo)
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool> {
+        match self.get_u8()? {
+            0 => Ok(false),
+
+            1 => Ok(true),
+
+            _ => Err(Error::InvalidSnapshot),
+        }
+    }
+
+    pub fn get_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.get_u64()?;
+
+        if len > self.b.cap() as u64 {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        Ok(self.b.get_bytes(len as usize).map_err(decode_error)?.buf())
+    }
+
+    pub fn get_opt_u64(&mut self) -> Result<Option<u64>> {
+        if self.get_bool()? {
+            return Ok(Some(self.get_u64()?));
+        }
+
+        Ok(None)
+    }
+
+    pub fn get_opt_u128(&mut self) -> Result<Option<u128>> {
+        if self.get_bool()? {
+            return Ok(Some(self.get_u128()?));
+        }
+
+        Ok(None)
+    }
+
+    /// Reads a time written with [`Writer::put_instant()`], relative to
+    /// `now`.
+    pub fn get_instant(&mut self, now: Instant) -> Result<Instant> {
+        Ok(now + Duration::from_micros(self.get_u64()?))
+    }
+
+    /// Reads a number of elements, checking that the snapshot is large enough
+    /// to hold them, with each taking at least `min_size` bytes.
+    pub fn get_count(&mut self, min_size: usize) -> Result<usize> {
+        let count = self.get_u64()?;
+
+        if count.saturating_mul(min_size as u64) > self.b.cap() as u64 {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        Ok(count as usize)
+    }
+
+    pub fn get_ranges(&mut self, capacity: usize) -> Result<ranges::RangeSet> {
+        let mut v = ranges::RangeSet::new(capacity);
+
+        for _ in 0..self.get_count(16)? {
+            let start = self.get_u64()?;
+            let end = self.get_u64()?;
+
+            if start > end {
+                return Err(Error::InvalidSnapshot);
+            }
+
+            v.insert(start..end);
+        }
+
+        Ok(v)
+    }
+
+    pub fn finish(self) -> Result<()> {
+        if self.b.cap() != 0 {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        Ok(())
+    }
+}
+
+fn put_crypto_alg(w: &mut Writer, alg: crypto::Algorithm) {
+    w.put_u8(match alg {
+        crypto::Algorithm::AES128_GCM => 0,
+
+        crypto::Algorithm::AES256_GCM => 1,
+
+        crypto::Algorithm::ChaCha20_Poly1305 => 2,
+    });
+}
+
+fn get_crypto_alg(r: &mut Reader) -> Result<crypto::Algorithm> {
+    match r.get_u8()? {
+        0 => Ok(crypto::Algorithm::AES128_GCM),
+
+        1 => Ok(crypto::Algorithm::AES256_GCM),
+
+        2 => Ok(crypto::Algorithm::ChaCha20_Poly1305),
+
+        _ => Err(Error::InvalidSnapshot),
+    }
+}
+
+fn put_transport_params(
+    w: &mut Writer, tp: &TransportParams, is_server: bool,
+) -> Result<()> {
+    let mut raw = [0; 4096];
+
+    w.put_bytes(TransportParams::encode(tp, is_server, &mut raw)?);
+
+    Ok(())
+}
+
+fn get_transport_params(
+    r: &mut Reader, is_server: bool,
+) -> Result<TransportParams> {
+    // Parameters are encoded by their sender, so they are decoded from the
+    // point of view of the receiver.
+    TransportParams::decode(r.get_bytes()?, !is_server, None)
+        .map_err(decode_error)
+}
+
+fn put_socket_addr(w: &mut Writer, addr: std::net::SocketAddr) {
+    w.put_bytes(addr.to_string().as_bytes());
+}
+
+fn get_socket_addr(r: &mut Reader) -> Result<std::net::SocketAddr> {
+    std::str::from_utf8(r.get_bytes()?)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or(Error::InvalidSnapshot)
+}
+
+impl<F: BufFactory> Connection<F> {
+    /// Serializes the connection's state, see [`Connection::export_state()`].
+    pub(crate) fn to_snapshot(&self, now: Instant) -> Result<Vec<u8>> {
+        // Only connections that don't need the TLS handshake anymore can be
+        // exported, as the TLS state can't be carried over.
+        if !self.handshake_confirmed ||
+            self.local_error.is_some() ||
+            self.is_closed() ||
+            self.is_draining()
+        {
+            return Err(Error::InvalidState);
+        }
+
+        if self.multipath.enabled() {
+            return Err(Error::InvalidState);
+        }
+
+        let epoch = packet::Epoch::Application;
+
+        let (open, seal) = match &self.crypto_ctx[epoch] {
+            packet::CryptoContext {
+                crypto_open: Some(open),
+                crypto_seal: Some(seal),
+                ..
+            } => (open, seal),
+
+            _ => return Err(Error::InvalidState),
+        };
+
+        let active_path_id = self.paths.get_active_path_id()?;
+        let path = self.paths.get(active_path_id)?;
+
+        let mut w = Writer::new();
+
+        // The fields needed to create the connection come first.
+        w.put_bool(self.is_server);
+        w.put_u32(self.version);
+        put_socket_addr(&mut w, path.local_addr());
+        put_socket_addr(&mut w, path.peer_addr());
+        w.put_bytes(&self.ids.oldest_scid().cid);
+
+        w.put_u32(self.original_version);
+        w.put_bytes(self.trace_id.as_bytes());
+        w.put_bytes(&self.alpn);
+        w.put_bool(self.session.is_some());
+        w.put_bytes(self.session.as_deref().unwrap_or_default());
+
+        put_transport_params(
+            &mut w,
+            &self.local_transport_params,
+            self.is_server,
+        )?;
+        put_transport_params(
+            &mut w,
+            &self.peer_transport_params,
+            !self.is_server,
+        )?;
+
+        // 1-RTT keys. The header protection keys are not derived from the
+        // current secrets after a key update, so they are exported as well.
+        put_crypto_alg(&mut w, open.alg());
+        w.put_bytes(open.secret());
+        w.put_bytes(open.hp_key());
+        w.put_bytes(seal.secret());
+        w.put_bytes(seal.hp_key());
+        w.put_bool(self.key_phase);
+        w.put_u64(self.key_phase_sent_count);
+        w.put_u64(self.key_update_count);
+        w.put_opt_u64(self.key_update_first_pn);
+        w.put_u64(self.auth_fail_count);
+
+        // The previous keys of a key update in progress, which share the
+        // header protection key of the current ones.
+        match &self.crypto_ctx[epoch].key_update {
+            Some(key_update) => {
+                w.put_bool(true);
+                w.put_bytes(key_update.crypto_open.secret());
+                w.put_u64(key_update.pn_on_update);
+                w.put_bool(key_update.update_acked);
+                w.put_instant(key_update.timer, now);
+            },
+
+            None => w.put_bool(false),
+        }
+
+        w.put_u64(self.next_pkt_num);
+        self.pkt_num_spaces[epoch].to_snapshot(&mut w);
+
+        self.ids.to_snapshot(&mut w, active_path_id);
+
+        w.put_opt_u64(path.active_scid_seq);
+        w.put_opt_u64(path.active_dcid_seq);
+        w.put_bool(path.verified_peer_address);
+        w.put_bool(path.peer_verified_local_address);
+        w.put_u64(path.recovery.rtt().as_micros() as u64);
+        w.put_u64(path.recovery.max_datagram_size() as u64);
+
+        w.put_u64(self.rx_data);
+        self.flow_control.to_snapshot(&mut w);
+        w.put_bool(self.almost_full);
+        w.put_u64(self.tx_data);
+        w.put_u64(self.max_tx_data);
+        w.put_u64(self.tx_buffered as u64);
+        w.put_opt_u64(self.blocked_limit);
+
+        self.streams.to_snapshot(&mut w, now);
+
+        w.put_bool(self.handshake_done_acked);
+        w.put_bool(self.peer_verified_initial_address);
+
+        self.ack_freq.to_snapshot(&mut w, now);
+        path.ecn.to_snapshot(&mut w);
+
+        self.dgram_recv_queue.to_snapshot(&mut w, now);
+        self.dgram_send_queue.to_snapshot(&mut w, now);
+        w.put_u64(self.next_dgram_id);
+
+        // Tracked DATAGRAM frames in flight won't be acked by the restored
+        // connection, so they are reported as lost instead.
+        let in_flight = path
+            .recovery
+            .unacked_frames(epoch)
+            .into_iter()
+            .filter_map(|frame| match frame {
+                frame::Frame::DatagramHeader { id: Some(id), .. } =>
+                    Some(DatagramEvent::Lost(id)),
+
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        w.put_u64((self.dgram_events.len() + in_flight.len()) as u64);
+        for ev in self.dgram_events.iter().chain(in_flight.iter()) {
+            let (kind, id) = match *ev {
+                DatagramEvent::Acked(id) => (0, id),
+
+                DatagramEvent::Lost(id) => (1, id),
+
+                DatagramEvent::Dropped(id) => (2, id),
+            };
+
+            w.put_u8(kind);
+            w.put_u64(id);
+        }
+
+        Ok(w.into_vec())
+    }
+
+    /// Restores a connection from a snapshot, see [`crate::restore()`].
+    pub(crate) fn from_snapshot(
+        state: &[u8], config: &mut Config, now: Instant,
+    ) -> Result<Connection<F>> {
+        let mut r = Reader::new(state)?;
+
+        let is_server = r.get_bool()?;
+        let version = r.get_u32()?;
+        let local = get_socket_addr(&mut r)?;
+        let peer = get_socket_addr(&mut r)?;
+        let scid = crate::ConnectionId::from_ref(r.get_bytes()?);
+
+        let mut conn =
+            Connection::new(&scid, None, None, local, peer, config, is_server)?;
+
+        conn.restored = true;
+
+        // The handshake is not going to happen, so drop the keys of the
+        // handshake epochs.
+        conn.drop_epoch_state(packet::Epoch::Initial, now);
+        conn.drop_epoch_state(packet::Epoch::Handshake, now);
+
+        conn.version = version;
+        conn.original_version = r.get_u32()?;
+        conn.trace_id =
+            String::from_utf8(r.get_bytes()?.to_vec()).map_err(decode_error)?;
+        conn.alpn = r.get_bytes()?.to_vec();
+
+        let has_session = r.get_bool()?;
+        let session = r.get_bytes()?;
+        conn.session = has_session.then(|| session.to_vec());
+
+        conn.local_transport_params = get_transport_params(&mut r, is_server)?;
+        let peer_transport_params = get_transport_params(&mut r, !is_server)?;
+
+        let alg = get_crypto_alg(&mut r)?;
+
+        let (secret, hp_key) = (r.get_bytes()?, r.get_bytes()?);
+        let open = crypto::Open::from_secret_and_hp_key(
+            alg,
+            version,
+            secret,
+            hp_key.to_vec(),
+        )
+        .map_err(decode_error)?;
+
+        let (secret, hp_key) = (r.get_bytes()?, r.get_bytes()?);
+        let seal = crypto::Seal::from_secret_and_hp_key(
+            alg,
+            version,
+            secret,
+            hp_key.to_vec(),
+        )
+        .map_err(decode_error)?;
+
+        let epoch = packet::Epoch::Application;
+
+        conn.key_phase = r.get_bool()?;
+        conn.key_phase_sent_count = r.get_u64()?;
+        conn.key_update_count = r.get_u64()?;
+        conn.key_update_first_pn = r.get_opt_u64()?;
+        conn.auth_fail_count = r.get_u64()?;
+
+        if r.get_bool()? {
+            let crypto_open = crypto::Open::from_secret_and_hp_key(
+                alg,
+                version,
+                r.get_bytes()?,
+                open.hp_key().to_vec(),
+            )
+            .map_err(decode_error)?;
+
+            conn.crypto_ctx[epoch].key_update = Some(packet::KeyUpdate {
+                crypto_open,
+                pn_on_update: r.get_u64()?,
+                update_acked: r.get_bool()?,
+                timer: r.get_instant(now)?,
+            });
+        }
+
+        conn.crypto_ctx[epoch].crypto_open = Some(open);
+        conn.crypto_ctx[epoch].crypto_seal = Some(seal);
+
+        conn.next_pkt_num = r.get_u64()?;
+        conn.pkt_num_spaces[epoch] = packet::PktNumSpace::from_snapshot(&mut r)?;
+
+        let active_path_id = conn.paths.get_active_path_id()?;
+
+        let ids =
+            cid::ConnectionIdentifiers::from_snapshot(&mut r, active_path_id)?;
+
+        let path = conn.paths.get_mut(active_path_id)?;
+        path.active_scid_seq = r.get_opt_u64()?;
+        path.active_dcid_seq = r.get_opt_u64()?;
+        path.verified_peer_address = r.get_bool()?;
+        path.peer_verified_local_address = r.get_bool()?;
+
+        // The recovery state is deliberately not restored: packets in flight
+        // can't be acked by the restored connection, so the congestion
+        // controller and pacer start over, and are only seeded with the RTT
+        // estimate of the exported connection.
+        let rtt = Duration::from_micros(r.get_u64()?);
+        let max_datagram_size = r.get_u64()? as usize;
+
+        let mut recovery_config = conn.recovery_config.clone();
+        recovery_config.initial_rtt = rtt;
+
+        path.recovery = recovery::Recovery::new_with_config(&recovery_config);
+
+        conn.process_peer_transport_params(peer_transport_params)
+            .map_err(decode_error)?;
+
+        conn.paths
+            .get_mut(active_path_id)?
+            .recovery
+            .pmtud_update_max_datagram_size(max_datagram_size);
+
+        // Applied after the peer's transport parameters, which also update
+        // the source Connection ID limit.
+        conn.ids = ids;
+
+        conn.rx_data = r.get_u64()?;
+        conn.flow_control = flowcontrol::FlowControl::from_snapshot(&mut r)?;
+        conn.almost_full = r.get_bool()?;
+        conn.tx_data = r.get_u64()?;
+        conn.max_tx_data = r.get_u64()?;
+        conn.tx_buffered = r.get_u64()? as usize;
+        conn.blocked_limit = r.get_opt_u64()?;
+
+        conn.streams = stream::StreamMap::from_snapshot(&mut r, now)?;
+
+        // A HANDSHAKE_DONE frame in flight might have been lost.
+        conn.handshake_done_acked = r.get_bool()?;
+        conn.handshake_done_sent = conn.handshake_done_acked;
+        conn.peer_verified_initial_address = r.get_bool()?;
+
+        conn.ack_freq = ack_freq::AckFrequency::from_snapshot(&mut r, now)?;
+        conn.paths.get_mut(active_path_id)?.ecn = ecn::EcnState::from_snapshot(
+            &mut r,
+            config.ecn,
+            conn.recovery_config.l4s(),
+        )?;
+
+        conn.dgram_recv_queue = dgram::DatagramQueue::from_snapshot(
+            &mut r,
+            config.dgram_recv_max_queue_len,
+            now,
+        )?;
+        conn.dgram_send_queue = dgram::DatagramQueue::from_snapshot(
+            &mut r,
+            config.dgram_send_max_queue_len,
+            now,
+        )?;
+        conn.next_dgram_id = r.get_u64()?;
+
+        for _ in 0..r.get_count(9)? {
+            let kind = r.get_u8()?;
+            let id = r.get_u64()?;
+
+            conn.dgram_events.push_back(match kind {
+                0 => DatagramEvent::Acked(id),
+
+                1 => DatagramEvent::Lost(id),
+
+                2 => DatagramEvent::Dropped(id),
+
+                _ => return Err(Error::InvalidSnapshot),
+            });
+        }
+
+        r.finish()?;
+
+        // The version was already settled during the handshake.
+        conn.did_version_negotiation = true;
+        conn.derived_initial_secrets = true;
+        conn.got_peer_conn_id = true;
+        conn.parsed_peer_transport_params = true;
+        conn.handshake_completed = true;
+        conn.handshake_confirmed = true;
+
+        conn.update_tx_cap();
+
+        conn.idle_timer = conn.idle_timeout().map(|timeout| now + timeout);
+
+        Ok(conn)
+    }
+}
@@ -28,6 +28,8 @@ use std::cmp;
 
 use std::sync::Arc;
 
+use std::time::Instant;
+
 use std::collections::hash_map;
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -40,6 +42,7 @@ use intrusive_collections::RBTreeAtomicLink;
 use smallvec::SmallVec;
 
 use crate::range_buf::DefaultBufFactory;
+use crate::snapshot;
 use crate::BufFactory;
 use crate::Error;
 use crate::Result;
@@ -187,9 +190,10 @@ pub struct StreamMap<F: BufFactory = DefaultBufFactory> {
     blocked: StreamIdHashMap<u64>,
 
     /// Set of stream IDs corresponding to streams that are reset. The value
-    /// of the map elements is a tuple of the error code and final size values
-    /// to include in the RESET_STREAM frame.
-    reset: StreamIdHashMap<(u64, u64)>,
+    /// of the map elements is a tuple of the error code, final size and
+    /// reliable size values to include in the RESET_STREAM or RESET_STREAM_AT
+    /// frame.
+    reset: StreamIdHashMap<(u64, u64, u64)>,
 
     /// Set of stream IDs corresponding to streams that are shutdown on the
     /// receive side, and need to send a STOP_SENDING frame. The value of the
@@ -198,6 +202,14 @@ pub struct StreamMap<F: BufFactory = DefaultBufFactory> {
 
     /// The maximum size of a stream window.
     max_stream_window: u64,
+
+    /// The scheduler selecting which flushable stream to send data from,
+    /// if the default RFC 9218 order is not used.
+    scheduler: Option<Box<dyn StreamScheduler>>,
+
+    /// Set of stream IDs corresponding to streams that have outgoing data
+    /// with a send deadline.
+    deadlines: StreamIdHashSet,
 }
 
 impl<F: BufFactory> StreamMap<F> {
@@ -421,6 +433,8 @@ impl<F: BufFactory> StreamMap<F> {
     pub fn insert_flushable(&mut self, priority_key: &Arc<StreamPriorityKey>) {
         if !priority_key.flushable.is_linked() {
             self.flushable.insert(Arc::clone(priority_key));
+
+            self.push_scheduled(priority_key);
         }
     }
 
@@ -430,6 +444,28 @@ impl<F: BufFactory> StreamMap<F> {
             return;
         }
 
+        self.unlink_flushable(priority_key);
+
+        if let Some(scheduler) = self.scheduler.as_mut() {
+            scheduler.pop_stream(priority_key.id);
+        }
+    }
+
+    /// Moves a flushable stream behind the other flushable streams of the
+    /// same urgency.
+    pub fn requeue_flushable(&mut self, priority_key: &Arc<StreamPriorityKey>) {
+        if !priority_key.flushable.is_linked() {
+            return;
+        }
+
+        self.unlink_flushable(priority_key);
+
+        self.flushable.insert(Arc::clone(priority_key));
+    }
+
+    /// Removes a linked stream from the flushable streams set, without
+    /// telling the scheduler.
+    fn unlink_flushable(&mut self, priority_key: &Arc<StreamPriorityKey>) {
         let mut c = {
             let ptr = Arc::as_ptr(priority_key);
             unsafe { self.flushable.cursor_mut_from_ptr(ptr) }
@@ -438,8 +474,71 @@ impl<F: BufFactory> StreamMap<F> {
         c.remove();
     }
 
-    pub fn peek_flushable(&self) -> Option<Arc<StreamPriorityKey>> {
-        self.flushable.front().clone_pointer()
+    /// Tells the scheduler, if any, about a flushable stream.
+    fn push_scheduled(&mut self, priority_key: &StreamPriorityKey) {
+        if let Some(scheduler) = self.scheduler.as_mut() {
+            scheduler.push_stream(&StreamCandidate {
+                stream_id: priority_key.id,
+                urgency: priority_key.urgency,
+                incremental: priority_key.incremental,
+                weight: self
+                    .streams
+                    .get(&priority_key.id)
+                    .map_or(DEFAULT_WEIGHT, |s| s.weight),
+            });
+        }
+    }
+
+    /// Returns the flushable stream to send data from next.
+    pub fn peek_flushable(&mut self) -> Option<Arc<StreamPriorityKey>> {
+        let selected = self
+            .scheduler
+            .as_mut()
+            .and_then(|s| s.select_stream())
+            .and_then(|id| self.streams.get(&id))
+            .filter(|s| s.priority_key.flushable.is_linked());
+
+        match selected {
+            Some(s) => Some(Arc::clone(&s.priority_key)),
+
+            None => self.flushable.front().clone_pointer(),
+        }
+    }
+
+    /// Notifies the scheduler that `len` bytes of the given stream were
+    /// written into a packet.
+    pub fn on_stream_sent(&mut self, stream_id: u64, len: usize) {
+        if let Some(scheduler) = self.scheduler.as_mut() {
+            scheduler.on_stream_sent(stream_id, len);
+        }
+    }
+
+    /// Sets the scheduler selecting which flushable stream to send data from.
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn StreamScheduler>) {
+        self.scheduler = Some(scheduler);
+
+        let flushable = self.flushable.iter().cloned().collect::<Vec<_>>();
+
+        for priority_key in flushable {
+            self.push_scheduled(&priority_key);
+        }
+    }
+
+    /// Sets the weight of a stream.
+    pub fn set_weight(&mut self, stream_id: u64, weight: u32) {
+        let priority_key = match self.streams.get_mut(&stream_id) {
+            Some(stream) => {
+                stream.weight = weight;
+
+                Arc::clone(&stream.priority_key)
+            },
+
+            None => return,
+        };
+
+        if priority_key.flushable.is_linked() {
+            self.push_scheduled(&priority_key);
+        }
     }
 
     /// Updates the priorities of a stream.
@@ -457,9 +556,81 @@ impl<F: BufFactory> StreamMap<F> {
         }
 
         if old.flushable.is_linked() {
-            self.remove_flushable(old);
+            self.unlink_flushable(old);
             self.flushable.insert(Arc::clone(new));
+
+            self.push_scheduled(new);
+        }
+    }
+
+    /// Adds a send deadline to the given stream.
+    ///
+    /// A deadline with an `end_off` of `u64::MAX` replaces the stream's
+    /// previous stream-wide deadline, if any.
+    pub fn insert_send_deadline(
+        &mut self, stream_id: u64, deadline: SendDeadline,
+    ) {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if deadline.end_off == u64::MAX {
+            stream.send_deadlines.retain(|d| d.end_off != u64::MAX);
         }
+
+        stream.send_deadlines.push(deadline);
+
+        self.deadlines.insert(stream_id);
+    }
+
+    /// Removes the stream-wide send deadline of the given stream.
+    pub fn remove_stream_deadline(&mut self, stream_id: u64) {
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.send_deadlines.retain(|d| d.end_off != u64::MAX);
+
+            if stream.send_deadlines.is_empty() {
+                self.deadlines.remove(&stream_id);
+            }
+        }
+    }
+
+    /// Returns the earliest send deadline among all streams.
+    pub fn next_send_deadline(&self) -> Option<Instant> {
+        self.deadlines
+            .iter()
+            .filter_map(|id| self.streams.get(id))
+            .flat_map(|s| s.send_deadlines.iter().map(|d| d.at))
+            .min()
+    }
+
+    /// Removes the send deadlines that were met, and returns the IDs of the
+    /// streams whose deadline expired along with the deadline that did.
+    ///
+    /// All deadlines of the returned streams are removed.
+    pub fn expire_send_deadlines(
+        &mut self, now: Instant,
+    ) -> Vec<(u64, SendDeadline)> {
+        let mut expired = Vec::new();
+
+        self.deadlines.retain(|id| {
+            let stream = match self.streams.get_mut(id) {
+                Some(v) => v,
+
+                None => return false,
+            };
+
+            if let Some(deadline) = stream.expire_send_deadlines(now) {
+                stream.send_deadlines.clear();
+
+                expired.push((*id, deadline));
+            }
+
+            !stream.send_deadlines.is_empty()
+        });
+
+        expired
     }
 
     /// Adds the stream ID to the almost full streams set.
@@ -494,7 +665,20 @@ impl<F: BufFactory> StreamMap<F> {
     pub fn insert_reset(
         &mut self, stream_id: u64, error_code: u64, final_size: u64,
     ) {
-        self.reset.insert(stream_id, (error_code, final_size));
+        self.insert_reset_at(stream_id, error_code, final_size, 0);
+    }
+
+    /// Adds the stream ID to the reset streams set with the given error code,
+    /// final size and reliable size values.
+    ///
+    /// A non-zero reliable size causes a RESET_STREAM_AT frame to be sent
+    /// instead of RESET_STREAM.
+    pub fn insert_reset_at(
+        &mut self, stream_id: u64, error_code: u64, final_size: u64,
+        reliable_size: u64,
+    ) {
+        self.reset
+            .insert(stream_id, (error_code, final_size, reliable_size));
     }
 
     /// Removes the stream ID from the reset streams set.
@@ -593,6 +777,8 @@ impl<F: BufFactory> StreamMap<F> {
 
         self.remove_flushable(&s.priority_key);
 
+        self.deadlines.remove(&stream_id);
+
         self.collected.insert(stream_id);
     }
 
@@ -623,7 +809,7 @@ impl<F: BufFactory> StreamMap<F> {
     }
 
     /// Creates an iterator over streams that need to send RESET_STREAM.
-    pub fn reset(&self) -> hash_map::Iter<'_, u64, (u64, u64)> {
+    pub fn reset(&self) -> hash_map::Iter<'_, u64, (u64, u64, u64)> {
         self.reset.iter()
     }
 
@@ -699,6 +885,133 @@ impl<F: BufFactory> StreamMap<F> {
     pub fn len(&self) -> usize {
         self.streams.len()
     }
+
+    /// Writes the streams and their limits to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer, now: Instant) {
+        w.put_u64(self.streams.len() as u64);
+        for stream in self.streams.values() {
+            stream.to_snapshot(w, now);
+        }
+
+        w.put_u64(self.collected.len() as u64);
+        for id in &self.collected {
+            w.put_u64(*id);
+        }
+
+        w.put_u64(self.peer_max_streams_bidi);
+        w.put_u64(self.peer_max_streams_uni);
+        w.put_u64(self.peer_opened_streams_bidi);
+        w.put_u64(self.peer_opened_streams_uni);
+        w.put_u64(self.local_max_streams_bidi);
+        w.put_u64(self.local_max_streams_bidi_next);
+        w.put_u64(self.initial_max_streams_bidi);
+        w.put_u64(self.local_max_streams_uni);
+        w.put_u64(self.local_max_streams_uni_next);
+        w.put_u64(self.initial_max_streams_uni);
+        w.put_u64(self.local_opened_streams_bidi);
+        w.put_u64(self.local_opened_streams_uni);
+        w.put_u64(self.max_stream_window);
+
+        w.put_u64(self.almost_full.len() as u64);
+        for id in &self.almost_full {
+            w.put_u64(*id);
+        }
+
+        w.put_u64(self.blocked.len() as u64);
+        for (id, off) in &self.blocked {
+            w.put_u64(*id);
+            w.put_u64(*off);
+        }
+
+        w.put_u64(self.reset.len() as u64);
+        for (id, (error_code, final_size, reliable_size)) in &self.reset {
+            w.put_u64(*id);
+            w.put_u64(*error_code);
+            w.put_u64(*final_size);
+            w.put_u64(*reliable_size);
+        }
+
+        w.put_u64(self.stopped.len() as u64);
+        for (id, error_code) in &self.stopped {
+            w.put_u64(*id);
+            w.put_u64(*error_code);
+        }
+    }
+
+    /// Reads streams written by [`to_snapshot()`].
+    ///
+    /// The stream scheduler is not part of the snapshot, and needs to be set
+    /// again by the application.
+    ///
+    /// [`to_snapshot()`]: struct.StreamMap.html#method.to_snapshot
+    pub fn from_snapshot(
+        r: &mut snapshot::Reader, now: Instant,
+    ) -> Result<StreamMap<F>> {
+        let mut map = StreamMap::default();
+
+        for _ in 0..r.get_count(1)? {
+            let stream = Stream::from_snapshot(r, now)?;
+
+            if stream.is_readable() {
+                map.insert_readable(&stream.priority_key);
+            }
+
+            if stream.is_writable() {
+                map.insert_writable(&stream.priority_key);
+            }
+
+            if stream.is_flushable() {
+                map.insert_flushable(&stream.priority_key);
+            }
+
+            if !stream.send_deadlines.is_empty() {
+                map.deadlines.insert(stream.priority_key.id);
+            }
+
+            map.streams.insert(stream.priority_key.id, stream);
+        }
+
+        for _ in 0..r.get_count(8)? {
+            map.collected.insert(r.get_u64()?);
+        }
+
+        map.peer_max_streams_bidi = r.get_u64()?;
+        map.peer_max_streams_uni = r.get_u64()?;
+        map.peer_opened_streams_bidi = r.get_u64()?;
+        map.peer_opened_streams_uni = r.get_u64()?;
+        map.local_max_streams_bidi = r.get_u64()?;
+        map.local_max_streams_bidi_next = r.get_u64()?;
+        map.initial_max_streams_bidi = r.get_u64()?;
+        map.local_max_streams_uni = r.get_u64()?;
+        map.local_max_streams_uni_next = r.get_u64()?;
+        map.initial_max_streams_uni = r.get_u64()?;
+        map.local_opened_streams_bidi = r.get_u64()?;
+        map.local_opened_streams_uni = r.get_u64()?;
+        map.max_stream_window = r.get_u64()?;
+
+        for _ in 0..r.get_count(8)? {
+            map.almost_full.insert(r.get_u64()?);
+        }
+
+        for _ in 0..r.get_count(16)? {
+            map.blocked.insert(r.get_u64()?, r.get_u64()?);
+        }
+
+        for _ in 0..r.get_count(32)? {
+            map.insert_reset_at(
+                r.get_u64()?,
+                r.get_u64()?,
+                r.get_u64()?,
+                r.get_u64()?,
+            );
+        }
+
+        for _ in 0..r.get_count(16)? {
+            map.stopped.insert(r.get_u64()?, r.get_u64()?);
+        }
+
+        Ok(map)
+    }
 }
 
 /// A QUIC stream.
@@ -723,6 +1036,16 @@ pub struct Stream<F: BufFactory = DefaultBufFactory> {
     /// Whether the stream can be flushed incrementally. Default is `true`.
     pub incremental: bool,
 
+    /// The stream's weight, used by custom stream schedulers. Default is
+    /// `DEFAULT_WEIGHT`.
+    pub weight: u32,
+
+    /// Deadlines by which outgoing data must be acked.
+    pub send_deadlines: Vec<SendDeadline>,
+
+    /// Whether data was received on the stream in 0-RTT packets.
+    pub early_data: bool,
+
     pub priority_key: Arc<StreamPriorityKey>,
 }
 
@@ -745,10 +1068,33 @@ impl<F: BufFactory> Stream<F> {
             local,
             urgency: priority_key.urgency,
             incremental: priority_key.incremental,
+            weight: DEFAULT_WEIGHT,
+            send_deadlines: Vec::new(),
+            early_data: false,
             priority_key,
         }
     }
 
+    /// Removes the send deadlines that were met, and returns the error code
+    /// of the first expired one, if any.
+    fn expire_send_deadlines(&mut self, now: Instant) -> Option<SendDeadline> {
+        // Nothing is left to abandon once the send side is done.
+        if self.send.is_complete() ||
+            self.send.is_shutdown() ||
+            self.send.is_stopped()
+        {
+            self.send_deadlines.clear();
+
+            return None;
+        }
+
+        let ack_off = self.send.ack_off();
+
+        self.send_deadlines.retain(|d| d.end_off > ack_off);
+
+        self.send_deadlines.iter().find(|d| d.at <= now).copied()
+    }
+
     /// Returns true if the stream has data to read.
     pub fn is_readable(&self) -> bool {
         self.recv.ready()
@@ -773,6 +1119,76 @@ impl<F: BufFactory> Stream<F> {
             off_front < self.send.max_off()
     }
 
+    /// Writes the stream to a connection snapshot.
+    ///
+    /// Send deadlines are recorded relative to `now`.
+    fn to_snapshot(&self, w: &mut snapshot::Writer, now: Instant) {
+        w.put_u64(self.priority_key.id);
+        w.put_bool(self.bidi);
+        w.put_bool(self.local);
+        w.put_u8(self.urgency);
+        w.put_bool(self.incremental);
+        w.put_u32(self.weight);
+        w.put_u64(self.send_lowat as u64);
+        w.put_bool(self.early_data);
+
+        w.put_u64(self.send_deadlines.len() as u64);
+        for d in &self.send_deadlines {
+            w.put_u64(d.end_off);
+            w.put_instant(d.at, now);
+            w.put_u64(d.error_code);
+            w.put_opt_u64(d.reliable_size);
+        }
+
+        self.recv.to_snapshot(w);
+        self.send.to_snapshot(w);
+    }
+
+    /// Reads a stream written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.Stream.html#method.to_snapshot
+    fn from_snapshot(r: &mut snapshot::Reader, now: Instant) -> Result<Self> {
+        let id = r.get_u64()?;
+        let bidi = r.get_bool()?;
+        let local = r.get_bool()?;
+        let urgency = r.get_u8()?;
+        let incremental = r.get_bool()?;
+        let weight = r.get_u32()?;
+        let send_lowat = r.get_u64()? as usize;
+        let early_data = r.get_bool()?;
+
+        let mut send_deadlines = Vec::new();
+        for _ in 0..r.get_count(25)? {
+            send_deadlines.push(SendDeadline {
+                end_off: r.get_u64()?,
+                at: r.get_instant(now)?,
+                error_code: r.get_u64()?,
+                reliable_size: r.get_opt_u64()?,
+            });
+        }
+
+        let priority_key = Arc::new(StreamPriorityKey {
+            urgency,
+            incremental,
+            id,
+            ..Default::default()
+        });
+
+        Ok(Stream {
+            recv: recv_buf::RecvBuf::from_snapshot(r)?,
+            send: send_buf::SendBuf::from_snapshot(r)?,
+            send_lowat,
+            bidi,
+            local,
+            urgency,
+            incremental,
+            weight,
+            send_deadlines,
+            early_data,
+            priority_key,
+        })
+    }
+
     /// Returns true if the stream is complete.
     ///
     /// For bidirectional streams this happens when both the receive and send
@@ -799,6 +1215,24 @@ impl<F: BufFactory> Stream<F> {
     }
 }
 
+/// A deadline by which outgoing stream data must be acked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendDeadline {
+    /// The offset up to which data must be acked, or `u64::MAX` for all the
+    /// stream's data.
+    pub end_off: u64,
+
+    /// The time after which unacked data is abandoned.
+    pub at: Instant,
+
+    /// The error code to reset the stream with once the deadline expires.
+    pub error_code: u64,
+
+    /// The amount of data to still deliver when the stream is reset, if the
+    /// peer supports reliable stream resets.
+    pub reliable_size: Option<u64>,
+}
+
 /// Returns true if the stream was created locally.
 pub fn is_local(stream_id: u64, is_server: bool) -> bool {
     (stream_id & 0x1) == (is_server as u64)
@@ -975,7 +1409,7 @@ mod tests {
 
         assert!(stream.recv.almost_full());
 
-        stream.recv.update_max_data(std::time::Instant::now());
+        stream.recv.update_max_data(Instant::now());
         assert_eq!(stream.recv.max_data_next(), 25);
         assert!(!stream.recv.almost_full());
 
@@ -2195,4 +2629,10 @@ mod tests {
 }
 
 mod recv_buf;
+mod scheduler;
 mod send_buf;
+
+pub use scheduler::DeficitRoundRobinScheduler;
+pub use scheduler::StreamCandidate;
+pub use scheduler::StreamScheduler;
+pub use scheduler::DEFAULT_WEIGHT;
@@ -38,6 +38,7 @@ use crate::Error;
 use crate::Result;
 
 use crate::flowcontrol;
+use crate::snapshot;
 
 use crate::range_buf::RangeBuf;
 
@@ -66,9 +67,13 @@ pub struct RecvBuf {
     /// The final stream offset received from the peer, if any.
     fin_off: Option<u64>,
 
-    /// The error code received via RESET_STREAM.
+    /// The error code received via RESET_STREAM or RESET_STREAM_AT.
     error: Option<u64>,
 
+    /// The offset up to which data is still delivered to the application
+    /// after a RESET_STREAM_AT, if the reset is pending.
+    reliable_off: Option<u64>,
+
     /// Whether incoming data is validated but not buffered.
     drain: bool,
 }
@@ -91,7 +96,7 @@ impl RecvBuf {
     /// This also takes care of enforcing stream flow control limits, as well
     /// as handling incoming data that overlaps data that is already in the
     /// buffer.
-    pub fn write(&mut self, buf: RangeBuf) -> Result<()> {
+    pub fn write(&mut self, mut buf: RangeBuf) -> Result<()> {
         if buf.max_off() > self.max_data() {
             return Err(Error::FlowControl);
         }
@@ -119,6 +124,17 @@ impl RecvBuf {
             return Ok(());
         }
 
+        // Data past the reliable size of a pending reset is not delivered.
+        if let Some(reliable_off) = self.reliable_off {
+            if buf.off() >= reliable_off {
+                return Ok(());
+            }
+
+            if buf.max_off() > reliable_off {
+                buf.split_off((reliable_off - buf.off()) as usize);
+            }
+        }
+
         if buf.fin() {
             self.fin_off = Some(buf.max_off());
         }
@@ -242,10 +258,19 @@ impl RecvBuf {
         }
 
         // The stream was reset, so clear its data and return the error code
-        // instead.
+        // instead, once all reliable data was read.
         if let Some(e) = self.error {
-            self.data.clear();
-            return Err(Error::StreamReset(e));
+            if self.off >= self.reliable_off.unwrap_or(0) {
+                self.data.clear();
+
+                if let Some(fin_off) = self.fin_off {
+                    self.off = cmp::max(self.off, fin_off);
+                }
+
+                self.reliable_off = None;
+
+                return Err(Error::StreamReset(e));
+            }
         }
 
         while cap > 0 && self.ready() {
@@ -287,6 +312,17 @@ impl RecvBuf {
     /// Resets the stream at the given offset.
     pub fn reset(
         &mut self, error_code: u64, final_size: u64,
+    ) -> Result<RecvBufResetReturn> {
+        self.reset_at(error_code, final_size, 0)
+    }
+
+    /// Resets the stream at the given offset, but keeps delivering data up to
+    /// `reliable_size` to the application before reporting the reset.
+    ///
+    /// The reliable size of a stream that was already reset can only be
+    /// reduced.
+    pub fn reset_at(
+        &mut self, error_code: u64, final_size: u64, reliable_size: u64,
     ) -> Result<RecvBufResetReturn> {
         // Stream's size is already known, forbid changing it.
         if let Some(fin_off) = self.fin_off {
@@ -300,19 +336,61 @@ impl RecvBuf {
             return Err(Error::FinalSize);
         }
 
-        if self.error.is_some() {
+        // Data that is not stored can't be delivered reliably.
+        let reliable_size = if self.drain { 0 } else { reliable_size };
+
+        let prev_reliable_off = match (self.error, self.reliable_off) {
+            (None, _) => None,
+
+            // A pending reset's reliable size can only be reduced.
+            (Some(_), Some(v)) if reliable_size < v => Some(v),
+
             // We already verified that the final size matches
-            return Ok(RecvBufResetReturn::zero());
+            (Some(_), _) => return Ok(RecvBufResetReturn::zero()),
+        };
+
+        // The connection flow control was already updated for the final size
+        // by the previous reset, if any, and data past its reliable size was
+        // already considered consumed.
+        let max_data_delta = match prev_reliable_off {
+            Some(_) => 0,
+
+            None => final_size - self.len,
+        };
+
+        let unread_off = prev_reliable_off.unwrap_or(final_size);
+
+        self.error = Some(error_code);
+
+        if reliable_size > self.off {
+            // Keep data up to the reliable size, and drop the rest.
+            self.len = final_size;
+            self.fin_off = Some(final_size);
+            self.reliable_off = Some(reliable_size);
+
+            if let Some((_, mut buf)) =
+                self.data.split_off(&(reliable_size + 1)).pop_first()
+            {
+                if buf.off() < reliable_size {
+                    buf.split_off((reliable_size - buf.off()) as usize);
+                    self.data.insert(reliable_size, buf);
+                }
+            }
+
+            return Ok(RecvBufResetReturn {
+                max_data_delta,
+                consumed_flowcontrol: unread_off - reliable_size,
+            });
         }
 
         // Calculate how many bytes need to be removed from the connection flow
         // control.
         let result = RecvBufResetReturn {
-            max_data_delta: final_size - self.len,
-            consumed_flowcontrol: final_size - self.off,
+            max_data_delta,
+            consumed_flowcontrol: unread_off - self.off,
         };
 
-        self.error = Some(error_code);
+        self.reliable_off = None;
 
         // Clear all data already buffered.
         self.off = final_size;
@@ -322,7 +400,16 @@ impl RecvBuf {
         // In order to ensure the application is notified when the stream is
         // reset, enqueue a zero-length buffer at the final size offset.
         let buf = RangeBuf::from(b"", final_size, true);
-        self.write(buf)?;
+
+        match prev_reliable_off {
+            // The final size is already known, so the buffer needs to be
+            // enqueued directly.
+            Some(_) => {
+                self.data.insert(final_size, buf);
+            },
+
+            None => self.write(buf)?,
+        }
 
         Ok(result)
     }
@@ -364,7 +451,11 @@ impl RecvBuf {
 
         self.data.clear();
 
-        let consumed = self.max_off() - self.off;
+        // Data past the reliable size of a pending reset was already
+        // considered consumed.
+        let unread_off = self.reliable_off.take().unwrap_or(self.max_off());
+
+        let consumed = unread_off - self.off;
         self.off = self.max_off();
 
         Ok(consumed)
@@ -390,6 +481,11 @@ impl RecvBuf {
     /// This happens when the stream's receive final size is known, and the
     /// application has read all data from the stream.
     pub fn is_fin(&self) -> bool {
+        // A pending reset still needs to be reported to the application.
+        if self.reliable_off.is_some() {
+            return false;
+        }
+
         if self.fin_off == Some(self.off) {
             return true;
         }
@@ -404,6 +500,12 @@ impl RecvBuf {
 
     /// Returns true if the stream has data to be read.
     pub fn ready(&self) -> bool {
+        // A pending reset needs to be reported once all reliable data was
+        // read.
+        if self.reliable_off.is_some_and(|off| self.off >= off) {
+            return true;
+        }
+
         let (_, buf) = match self.data.first_key_value() {
             Some(v) => v,
             None => return false,
@@ -411,6 +513,50 @@ impl RecvBuf {
 
         buf.off() == self.off
     }
+
+    /// Writes the receive buffer to a connection snapshot.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        w.put_u64(self.data.len() as u64);
+        for buf in self.data.values() {
+            w.put_u64(buf.off());
+            w.put_bool(buf.fin());
+            w.put_bytes(buf);
+        }
+
+        w.put_u64(self.off);
+        w.put_u64(self.len);
+        self.flow_control.to_snapshot(w);
+        w.put_opt_u64(self.fin_off);
+        w.put_opt_u64(self.error);
+        w.put_opt_u64(self.reliable_off);
+        w.put_bool(self.drain);
+    }
+
+    /// Reads a receive buffer written by [`to_snapshot()`].
+    ///
+    /// [`to_snapshot()`]: struct.RecvBuf.html#method.to_snapshot
+    pub fn from_snapshot(r: &mut snapshot::Reader) -> Result<RecvBuf> {
+        let mut data = BTreeMap::new();
+
+        for _ in 0..r.get_count(17)? {
+            let off = r.get_u64()?;
+            let fin = r.get_bool()?;
+            let buf = RangeBuf::from(r.get_bytes()?, off, fin);
+
+            data.insert(buf.max_off(), buf);
+        }
+
+        Ok(RecvBuf {
+            data,
+            off: r.get_u64()?,
+            len: r.get_u64()?,
+            flow_control: flowcontrol::FlowControl::from_snapshot(r)?,
+            fin_off: r.get_opt_u64()?,
+            error: r.get_opt_u64()?,
+            reliable_off: r.get_opt_u64()?,
+            drain: r.get_bool()?,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -625,6 +771,100 @@ mod tests {
         assert_emit_discard_done(&mut recv, emit);
     }
 
+    #[rstest]
+    fn reset_at(#[values(true, false)] emit: bool) {
+        let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW);
+        assert_eq!(recv.len, 0);
+
+        let first = RangeBuf::from(b"hello", 0, false);
+        let second = RangeBuf::from(b"world", 5, false);
+        let third = RangeBuf::from(b"something", 10, false);
+        let fourth = RangeBuf::from(b"something else", 10, false);
+
+        assert!(recv.write(first).is_ok());
+        assert!(recv.write(third).is_ok());
+        assert_eq!(recv.len, 19);
+        assert_eq!(recv.data.len(), 2);
+
+        // Data past the reliable size is dropped.
+        assert_eq!(
+            recv.reset_at(42, 30, 12),
+            Ok(RecvBufResetReturn {
+                max_data_delta: 11,
+                consumed_flowcontrol: 18,
+            })
+        );
+        assert_eq!(recv.len, 30);
+        assert_eq!(recv.off, 0);
+        assert_eq!(recv.data.len(), 2);
+
+        assert_emit_discard(&mut recv, emit, 32, 5, false, Some(b"hello"));
+        assert_emit_discard_done(&mut recv, emit);
+
+        // Data below the reliable size is still delivered.
+        assert!(recv.write(second).is_ok());
+        assert!(recv.write(fourth).is_ok());
+        assert_eq!(recv.len, 30);
+
+        assert_emit_discard(&mut recv, emit, 32, 7, false, Some(b"worldso"));
+        assert_eq!(recv.off, 12);
+
+        // The reset is reported once all reliable data was read.
+        let mut buf = [0; 32];
+        assert_eq!(
+            recv.emit_or_discard(RecvAction::Emit { out: &mut buf }),
+            Err(Error::StreamReset(42))
+        );
+        assert_eq!(recv.off, 30);
+        assert!(recv.is_fin());
+        assert_eq!(recv.data.len(), 0);
+    }
+
+    #[test]
+    fn reset_at_reduce_reliable_size() {
+        let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW);
+
+        assert_eq!(
+            recv.reset_at(42, 10, 8),
+            Ok(RecvBufResetReturn {
+                max_data_delta: 10,
+                consumed_flowcontrol: 2,
+            })
+        );
+
+        // The reliable size can be reduced...
+        assert_eq!(
+            recv.reset_at(42, 10, 4),
+            Ok(RecvBufResetReturn {
+                max_data_delta: 0,
+                consumed_flowcontrol: 4,
+            })
+        );
+
+        // ... but not increased.
+        assert_eq!(recv.reset_at(42, 10, 6), Ok(RecvBufResetReturn::zero()));
+
+        // The final size can't change.
+        assert_eq!(recv.reset_at(42, 11, 4), Err(Error::FinalSize));
+
+        // Reducing it to zero reports the reset immediately.
+        assert_eq!(
+            recv.reset(42, 10),
+            Ok(RecvBufResetReturn {
+                max_data_delta: 0,
+                consumed_flowcontrol: 4,
+            })
+        );
+        assert!(recv.ready());
+        assert_eq!(recv.off, 10);
+
+        let mut buf = [0; 32];
+        assert_eq!(
+            recv.emit_or_discard(RecvAction::Emit { out: &mut buf }),
+            Err(Error::StreamReset(42))
+        );
+    }
+
     #[rstest]
     fn split_read(#[values(true, false)] emit: bool) {
         let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW);
@@ -0,0 +1,316 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Pluggable stream scheduling.
+//!
+//! By default, flushable streams are scheduled following the [RFC 9218]
+//! extensible priorities: streams with a lower urgency are always sent
+//! first, and streams with the same urgency are either sent one after the
+//! other or interleaved in round-robin, depending on whether they are
+//! incremental.
+//!
+//! A [`StreamScheduler`] can replace this policy, for example to share the
+//! available bandwidth between streams according to their weight.
+//!
+//! [RFC 9218]: https://www.rfc-editor.org/rfc/rfc9218.html
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+
+use super::StreamIdHashMap;
+
+/// The default weight of a stream.
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+/// The number of bytes a stream of weight 1 can send in each round of
+/// [`DeficitRoundRobinScheduler`].
+const DRR_QUANTUM: i64 = 1500;
+
+/// A stream which has data to send, as given to a [`StreamScheduler`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamCandidate {
+    /// The stream ID.
+    pub stream_id: u64,
+
+    /// The stream's urgency (lower is better).
+    pub urgency: u8,
+
+    /// Whether the stream can be flushed incrementally.
+    pub incremental: bool,
+
+    /// The stream's weight, as set with [`stream_weight()`].
+    ///
+    /// [`stream_weight()`]: struct.Connection.html#method.stream_weight
+    pub weight: u32,
+}
+
+/// Decides which stream the next STREAM frame is sent on.
+///
+/// The scheduler is told when streams start and stop having data to send,
+/// with enough flow control credit to send at least some of it, and is asked
+/// for the stream to send from every time a STREAM frame is about to be
+/// written into a packet.
+///
+/// A scheduler can be set with [`set_stream_scheduler()`]. By default streams
+/// are scheduled following RFC 9218.
+///
+/// [`set_stream_scheduler()`]:
+/// struct.Connection.html#method.set_stream_scheduler
+pub trait StreamScheduler: Send + Sync {
+    /// Called when a stream starts having data to send, or when the priority
+    /// or weight of such a stream changes.
+    fn push_stream(&mut self, stream: &StreamCandidate);
+
+    /// Called when a stream doesn't have data it can send anymore, including
+    /// when it is closed.
+    fn pop_stream(&mut self, stream_id: u64);
+
+    /// Returns the ID of the pushed stream to send data from, or `None` to
+    /// use the default RFC 9218 order.
+    fn select_stream(&mut self) -> Option<u64>;
+
+    /// Called after `len` bytes of the given stream were written into a
+    /// packet.
+    fn on_stream_sent(&mut self, _stream_id: u64, _len: usize) {}
+}
+
+/// The state of a stream in a [`DeficitRoundRobinScheduler`].
+struct DrrStream {
+    urgency: u8,
+
+    weight: u32,
+
+    /// The number of bytes the stream can still send in the current round.
+    deficit: i64,
+}
+
+/// A scheduler sharing bandwidth between streams in proportion to their
+/// weight, using deficit round robin.
+///
+/// Streams with a lower urgency are still sent first: the bandwidth is only
+/// shared between the streams of the most urgent level that has data to send.
+/// Whether a stream is incremental is ignored.
+#[derive(Default)]
+pub struct DeficitRoundRobinScheduler {
+    /// The streams with data to send for each urgency level, in round-robin
+    /// order. The stream at the front is the one being served.
+    rings: BTreeMap<u8, VecDeque<u64>>,
+
+    /// The streams with data to send.
+    streams: StreamIdHashMap<DrrStream>,
+
+    /// The stream whose turn is in progress, if any.
+    current: Option<u64>,
+}
+
+impl DeficitRoundRobinScheduler {
+    fn remove_from_ring(&mut self, stream_id: u64, urgency: u8) {
+        if let Some(ring) = self.rings.get_mut(&urgency) {
+            if let Some(i) = ring.iter().position(|id| *id == stream_id) {
+                ring.remove(i);
+            }
+
+            if ring.is_empty() {
+                self.rings.remove(&urgency);
+            }
+        }
+
+        if self.current == Some(stream_id) {
+            self.current = None;
+        }
+    }
+}
+
+impl StreamScheduler for DeficitRoundRobinScheduler {
+    fn push_stream(&mut self, stream: &StreamCandidate) {
+        if let Some(s) = self.streams.get_mut(&stream.stream_id) {
+            s.weight = stream.weight;
+
+            if s.urgency == stream.urgency {
+                return;
+            }
+
+            let urgency = std::mem::replace(&mut s.urgency, stream.urgency);
+
+            self.remove_from_ring(stream.stream_id, urgency);
+        } else {
+            self.streams.insert(stream.stream_id, DrrStream {
+                urgency: stream.urgency,
+                weight: stream.weight,
+                deficit: 0,
+            });
+        }
+
+        self.rings
+            .entry(stream.urgency)
+            .or_default()
+            .push_back(stream.stream_id);
+    }
+
+    fn pop_stream(&mut self, stream_id: u64) {
+        // A stream that has nothing to send loses its deficit.
+        if let Some(s) = self.streams.remove(&stream_id) {
+            self.remove_from_ring(stream_id, s.urgency);
+        }
+    }
+
+    fn select_stream(&mut self) -> Option<u64> {
+        let (_, ring) = self.rings.iter_mut().next()?;
+
+        loop {
+            let stream_id = *ring.front()?;
+            let s = self.streams.get_mut(&stream_id)?;
+
+            // Start the turn of the stream at the front of the ring.
+            if self.current != Some(stream_id) {
+                s.deficit += DRR_QUANTUM * i64::from(s.weight.max(1));
+
+                self.current = Some(stream_id);
+
+                return Some(stream_id);
+            }
+
+            // Keep serving the current stream until it uses up its deficit.
+            if s.deficit > 0 {
+                return Some(stream_id);
+            }
+
+            ring.rotate_left(1);
+
+            self.current = None;
+        }
+    }
+
+    fn on_stream_sent(&mut self, stream_id: u64, len: usize) {
+        if let Some(s) = self.streams.get_mut(&stream_id) {
+            s.deficit -= len as i64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(stream_id: u64, urgency: u8, weight: u32) -> StreamCandidate {
+        StreamCandidate {
+            stream_id,
+            urgency,
+            incremental: true,
+            weight,
+        }
+    }
+
+    #[test]
+    fn drr_weighted_shares() {
+        let mut sched = DeficitRoundRobinScheduler::default();
+
+        sched.push_stream(&candidate(0, 3, 1));
+        sched.push_stream(&candidate(4, 3, 3));
+
+        let mut sent = [0; 2];
+
+        for _ in 0..400 {
+            let stream_id = sched.select_stream().unwrap();
+
+            sched.on_stream_sent(stream_id, 1000);
+            sent[stream_id as usize / 4] += 1000;
+        }
+
+        // Stream 4 gets three times the bandwidth of stream 0.
+        assert!(sent[1] >= sent[0] * 29 / 10);
+        assert!(sent[1] <= sent[0] * 31 / 10);
+    }
+
+    #[test]
+    fn drr_urgency_first() {
+        let mut sched = DeficitRoundRobinScheduler::default();
+
+        sched.push_stream(&candidate(4, 3, 10));
+        sched.push_stream(&candidate(0, 0, 1));
+        sched.push_stream(&candidate(8, 3, 10));
+
+        for _ in 0..10 {
+            assert_eq!(sched.select_stream(), Some(0));
+            sched.on_stream_sent(0, 1000);
+        }
+
+        // Once the most urgent stream is done, the other ones share the
+        // bandwidth.
+        sched.pop_stream(0);
+
+        let mut served = [0; 2];
+
+        for _ in 0..90 {
+            let stream_id = sched.select_stream().unwrap();
+
+            sched.on_stream_sent(stream_id, 1000);
+            served[stream_id as usize / 4 - 1] += 1;
+        }
+
+        assert_eq!(served[0], served[1]);
+    }
+
+    #[test]
+    fn drr_reprioritize() {
+        let mut sched = DeficitRoundRobinScheduler::default();
+
+        sched.push_stream(&candidate(0, 3, 1));
+        sched.push_stream(&candidate(4, 3, 1));
+
+        assert_eq!(sched.select_stream(), Some(0));
+
+        sched.push_stream(&candidate(4, 0, 1));
+        assert_eq!(sched.select_stream(), Some(4));
+        assert_eq!(sched.rings.len(), 2);
+
+        sched.push_stream(&candidate(4, 3, 1));
+        assert_eq!(sched.rings.len(), 1);
+        assert_eq!(sched.rings[&3], [0, 4]);
+    }
+
+    #[test]
+    fn drr_idle_stream_loses_deficit() {
+        let mut sched = DeficitRoundRobinScheduler::default();
+
+        sched.push_stream(&candidate(0, 3, 10));
+        sched.push_stream(&candidate(4, 3, 1));
+
+        assert_eq!(sched.select_stream(), Some(0));
+        sched.on_stream_sent(0, 1000);
+
+        // Stream 0 has nothing left to send.
+        sched.pop_stream(0);
+        assert_eq!(sched.select_stream(), Some(4));
+        assert!(!sched.streams.contains_key(&0));
+
+        sched.pop_stream(4);
+        assert!(sched.streams.is_empty());
+        assert!(sched.rings.is_empty());
+        assert_eq!(sched.current, None);
+        assert_eq!(sched.select_stream(), None);
+    }
+}
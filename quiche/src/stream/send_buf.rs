@@ -36,6 +36,7 @@ use crate::Result;
 
 use crate::range_buf::DefaultBufFactory;
 use crate::ranges;
+use crate::snapshot;
 
 #[cfg(test)]
 const SEND_BUFFER_SIZE: usize = 5;
@@ -241,6 +242,12 @@ impl<F: BufFactory> SendBuf<F> {
         let mut next_off = out_off;
 
         while out_len > 0 {
+            // Skip buffers that were already sent entirely, so that
+            // `off_front()` doesn't need to walk past them again.
+            while self.data.get(self.pos).is_some_and(|b| b.is_empty()) {
+                self.pos += 1;
+            }
+
             let off_front = self.off_front();
 
             if self.is_empty() ||
@@ -257,11 +264,6 @@ impl<F: BufFactory> SendBuf<F> {
                 None => break,
             };
 
-            if buf.is_empty() {
-                self.pos += 1;
-                continue;
-            }
-
             let buf_len = cmp::min(buf.len(), out_len);
             let partial = buf_len < buf.len();
 
@@ -460,6 +462,48 @@ impl<F: BufFactory> SendBuf<F> {
         Ok(self.reset())
     }
 
+    /// Shuts down sending data, but keeps data up to `reliable_size` to be
+    /// delivered to the peer.
+    ///
+    /// The final size, as well as the amount of data that was written but
+    /// will never be sent, are returned as a tuple.
+    pub fn reset_at(&mut self, reliable_size: u64) -> Result<(u64, u64)> {
+        if self.shutdown {
+            return Err(Error::Done);
+        }
+
+        self.shutdown = true;
+
+        let unsent_off = cmp::max(self.off_front(), self.emit_off);
+        let final_size = cmp::max(unsent_off, reliable_size);
+        let unsent_len = self.off_back().saturating_sub(final_size);
+
+        // Drop buffered data past the reliable size.
+        while let Some(buf) = self.data.back_mut() {
+            if buf.off >= reliable_size {
+                self.data.pop_back();
+                continue;
+            }
+
+            if buf.max_off() > reliable_size {
+                buf.split_off((reliable_size - buf.off) as usize);
+            }
+
+            break;
+        }
+
+        self.pos = cmp::min(self.pos, self.data.len());
+        self.len = self.data.iter().map(|b| b.len() as u64).sum();
+
+        self.fin_off = Some(final_size);
+        self.off = final_size;
+
+        // Mark data past the reliable size as acked.
+        self.ack(reliable_size, (final_size - reliable_size) as usize);
+
+        Ok((final_size, unsent_len))
+    }
+
     /// Returns the largest offset of data buffered.
     pub fn off_back(&self) -> u64 {
         self.off
@@ -553,6 +597,86 @@ impl<F: BufFactory> SendBuf<F> {
     pub fn bufs_count(&self) -> usize {
         self.data.len()
     }
+
+    /// Writes the send buffer to a connection snapshot.
+    ///
+    /// Packets in flight are not part of the snapshot, so all buffered data
+    /// that was not acked yet is recorded, regardless of whether it was
+    /// already sent or not.
+    pub fn to_snapshot(&self, w: &mut snapshot::Writer) {
+        let mut chunks = Vec::new();
+
+        for buf in &self.data {
+            let data = &buf.data.as_ref()[buf.start..buf.start + buf.len];
+
+            let mut off = buf.off;
+            let max_off = buf.off + buf.len as u64;
+
+            // Skip ranges that were acked out of order.
+            for acked in self.acked.iter() {
+                if acked.end <= off || acked.start >= max_off {
+                    continue;
+                }
+
+                if acked.start > off {
+                    chunks.push((
+                        off,
+                        &data[(off - buf.off) as usize..]
+                            [..(acked.start - off) as usize],
+                    ));
+                }
+
+                off = cmp::min(acked.end, max_off);
+            }
+
+            if off < max_off {
+                chunks.push((off, &data[(off - buf.off) as usize..]));
+            }
+        }
+
+        w.put_u64(chunks.len() as u64);
+        for (off, data) in chunks {
+            w.put_u64(off);
+            w.put_bytes(data);
+        }
+
+        w.put_u64(self.off);
+        w.put_u64(self.emit_off);
+        w.put_u64(self.max_data);
+        w.put_opt_u64(self.blocked_at);
+        w.put_opt_u64(self.fin_off);
+        w.put_bool(self.shutdown);
+        w.put_ranges(&self.acked);
+        w.put_opt_u64(self.error);
+    }
+
+    /// Reads a send buffer written by [`to_snapshot()`].
+    ///
+    /// All the buffered data is scheduled to be sent again.
+    ///
+    /// [`to_snapshot()`]: struct.SendBuf.html#method.to_snapshot
+    pub fn from_snapshot(r: &mut snapshot::Reader) -> Result<SendBuf<F>> {
+        let mut send = SendBuf::default();
+
+        for _ in 0..r.get_count(16)? {
+            let off = r.get_u64()?;
+            let data = r.get_bytes()?;
+
+            send.len += data.len() as u64;
+            send.data.push_back(RangeBuf::from(data, off, false));
+        }
+
+        send.off = r.get_u64()?;
+        send.emit_off = r.get_u64()?;
+        send.max_data = r.get_u64()?;
+        send.blocked_at = r.get_opt_u64()?;
+        send.fin_off = r.get_opt_u64()?;
+        send.shutdown = r.get_bool()?;
+        send.acked = r.get_ranges(usize::MAX)?;
+        send.error = r.get_opt_u64()?;
+
+        Ok(send)
+    }
 }
 
 #[cfg(test)]
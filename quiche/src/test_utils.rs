@@ -262,6 +262,7 @@ impl Pipe {
         let info = RecvInfo {
             to: server_path.peer_addr(),
             from: server_path.local_addr(),
+            ecn: Ecn::NotEct,
         };
 
         self.client.recv(buf, info)
@@ -272,6 +273,7 @@ impl Pipe {
         let info = RecvInfo {
             to: client_path.peer_addr(),
             from: client_path.local_addr(),
+            ecn: Ecn::NotEct,
         };
 
         self.server.recv(buf, info)
@@ -323,6 +325,7 @@ pub fn recv_send<F: BufFactory>(
     let info = RecvInfo {
         to: active_path.local_addr(),
         from: active_path.peer_addr(),
+        ecn: Ecn::NotEct,
     };
 
     conn.recv(&mut buf[..len], info)?;
@@ -347,6 +350,7 @@ pub fn process_flight(
         let info = RecvInfo {
             to: si.to,
             from: si.from,
+            ecn: si.ecn,
         };
 
         conn.recv(&mut pkt, info)?;
@@ -540,6 +544,7 @@ pub fn helper_packet_sent(pkt_num: u64, now: Instant, size: usize) -> Sent {
         lost: 0,
         has_data: true,
         is_pmtud_probe: false,
+        ecn_marked: false,
     }
 }
 
@@ -584,6 +589,7 @@ pub fn trigger_ack_based_loss(
         let info = RecvInfo {
             to: receiver.paths.get_active().unwrap().local_addr(),
             from: receiver.paths.get_active().unwrap().peer_addr(),
+            ecn: Ecn::NotEct,
         };
         receiver.recv(&mut buf[..len], info).unwrap();
     }
@@ -595,6 +601,7 @@ pub fn trigger_ack_based_loss(
     let info = RecvInfo {
         to: sender.paths.get_active().unwrap().local_addr(),
         from: sender.paths.get_active().unwrap().peer_addr(),
+        ecn: Ecn::NotEct,
     };
     sender.recv(&mut buf[..ack_len], info).unwrap();
 }
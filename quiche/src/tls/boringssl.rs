@@ -104,6 +104,12 @@ impl Context {
 }
 
 impl Handshake {
+    pub fn set_early_data_enabled(&mut self, enabled: bool) {
+        unsafe {
+            SSL_set_early_data_enabled(self.as_mut_ptr(), i32::from(enabled));
+        }
+    }
+
     pub fn set_quic_early_data_context(&mut self, context: &[u8]) -> Result<()> {
         map_result(unsafe {
             SSL_set_quic_early_data_context(
@@ -310,6 +316,8 @@ extern "C" {
     ) -> c_int;
     fn SSL_CTX_set_early_data_enabled(ctx: *mut SSL_CTX, enabled: i32);
 
+    fn SSL_set_early_data_enabled(ssl: *mut SSL, enabled: i32);
+
     pub(super) fn SSL_CTX_set_session_cache_mode(
         ctx: *mut SSL_CTX, mode: c_int,
     ) -> c_int;
@@ -455,7 +455,7 @@ impl Handshake {
     pub fn set_quic_transport_params(
         &mut self, params: &crate::TransportParams, is_server: bool,
     ) -> Result<()> {
-        let mut raw_params = [0; 128];
+        let mut raw_params = [0; 256];
 
         let raw_params =
             crate::TransportParams::encode(params, is_server, &mut raw_params)?;
@@ -700,6 +700,10 @@ pub struct ExData<'a> {
 
     pub trace_id: &'a str,
 
+    pub version: u32,
+
+    pub original_version: u32,
+
     pub local_transport_params: crate::TransportParams,
 
     pub recovery_config: crate::recovery::RecoveryConfig,
@@ -750,6 +754,18 @@ fn get_cipher_from_ptr(cipher: *const SSL_CIPHER) -> Result<crypto::Algorithm> {
     Ok(alg)
 }
 
+/// Returns the QUIC version used to derive packet protection keys for the
+/// given encryption level.
+fn secret_version(level: crypto::Level, ex_data: &ExData) -> u32 {
+    // 0-RTT packets are sent before compatible version negotiation completes,
+    // so they are always protected using the original version.
+    if level == crypto::Level::ZeroRTT {
+        return ex_data.original_version;
+    }
+
+    ex_data.version
+}
+
 extern "C" fn set_read_secret(
     ssl: *mut SSL, level: crypto::Level, cipher: *const SSL_CIPHER,
     secret: *const u8, secret_len: usize,
@@ -762,6 +778,8 @@ extern "C" fn set_read_secret(
 
     trace!("{} set read secret lvl={:?}", ex_data.trace_id, level);
 
+    let version = secret_version(level, ex_data);
+
     let space = match level {
         crypto::Level::Initial => &mut ex_data.crypto_ctx[packet::Epoch::Initial],
         crypto::Level::ZeroRTT =>
@@ -782,7 +800,7 @@ extern "C" fn set_read_secret(
     if level != crypto::Level::ZeroRTT || ex_data.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let open = match crypto::Open::from_secret(aead, secret) {
+        let open = match crypto::Open::from_secret(aead, version, secret) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -811,6 +829,8 @@ extern "C" fn set_write_secret(
 
     trace!("{} set write secret lvl={:?}", ex_data.trace_id, level);
 
+    let version = secret_version(level, ex_data);
+
     let space = match level {
         crypto::Level::Initial => &mut ex_data.crypto_ctx[packet::Epoch::Initial],
         crypto::Level::ZeroRTT =>
@@ -831,7 +851,7 @@ extern "C" fn set_write_secret(
     if level != crypto::Level::ZeroRTT || !ex_data.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let seal = match crypto::Seal::from_secret(aead, secret) {
+        let seal = match crypto::Seal::from_secret(aead, version, secret) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -58,6 +58,20 @@ impl Context {
 }
 
 impl Handshake {
+    pub fn set_early_data_enabled(&mut self, enabled: bool) {
+        unsafe {
+            SSL_set_quic_early_data_enabled(
+                self.as_mut_ptr(),
+                i32::from(enabled),
+            );
+        }
+    }
+
+    #[cfg(test)]
+    pub fn max_early_data(&self) -> u32 {
+        unsafe { SSL_get_max_early_data(self.as_ptr()) }
+    }
+
     pub fn set_quic_early_data_context(&mut self, _context: &[u8]) -> Result<()> {
         // not supported for now.
         map_result(1)
@@ -359,4 +373,9 @@ extern "C" {
     ) -> c_int;
 
     fn SSL_group_to_name(ssl: *const SSL, id: c_int) -> *const c_char;
+
+    fn SSL_set_quic_early_data_enabled(ssl: *mut SSL, enabled: c_int);
+
+    #[cfg(test)]
+    fn SSL_get_max_early_data(ssl: *const SSL) -> u32;
 }
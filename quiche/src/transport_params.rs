@@ -29,12 +29,18 @@
 
 use std::collections::HashSet;
 use std::mem::size_of;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddrV4;
+use std::net::SocketAddrV6;
 
 use crate::ConnectionId;
 use crate::Error;
 use crate::Result;
 use crate::MAX_STREAM_ID;
 
+use crate::multipath::MAX_PATH_ID;
+
 #[cfg(feature = "qlog")]
 use crate::crypto;
 #[cfg(feature = "qlog")]
@@ -42,6 +48,10 @@ use qlog::events::connectivity::TransportOwner;
 #[cfg(feature = "qlog")]
 use qlog::events::EventData;
 
+// The `min_ack_delay` transport parameter must be smaller than 2^24
+// microseconds.
+const MAX_MIN_ACK_DELAY: u64 = 1 << 24;
+
 /// QUIC Unknown Transport Parameter.
 ///
 /// A QUIC transport parameter that is not specifically recognized
@@ -146,6 +156,38 @@ impl<'a> Iterator for UnknownTransportParameterIterator<'a> {
     }
 }
 
+/// Preferred Address transport parameter.
+///
+/// As defined in [RFC 9000](https://www.rfc-editor.org/rfc/rfc9000.html#section-18.2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreferredAddress {
+    /// The server's preferred IPv4 address, if any.
+    pub addr_v4: Option<SocketAddrV4>,
+    /// The server's preferred IPv6 address, if any.
+    pub addr_v6: Option<SocketAddrV6>,
+    /// The connection ID to use when sending to the preferred address.
+    pub connection_id: ConnectionId<'static>,
+    /// The stateless reset token associated with the connection ID.
+    pub stateless_reset_token: u128,
+}
+
+impl PreferredAddress {
+    fn encoded_len(&self) -> usize {
+        4 + 2 + 16 + 2 + 1 + self.connection_id.len() + 16
+    }
+}
+
+/// Version Information transport parameter.
+///
+/// As defined in [RFC 9368](https://www.rfc-editor.org/rfc/rfc9368.html#section-3).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionInformation {
+    /// The version in use for the connection.
+    pub chosen_version: u32,
+    /// The versions supported by the endpoint, in order of preference.
+    pub available_versions: Vec<u32>,
+}
+
 /// QUIC Transport Parameters
 #[derive(Clone, Debug, PartialEq)]
 pub struct TransportParams {
@@ -185,9 +227,20 @@ pub struct TransportParams {
     pub retry_source_connection_id: Option<ConnectionId<'static>>,
     /// DATAGRAM frame extension parameter, if any.
     pub max_datagram_frame_size: Option<u64>,
+    /// Version Information parameter, if any.
+    pub version_information: Option<VersionInformation>,
+    /// The server's preferred address, if any.
+    pub preferred_address: Option<PreferredAddress>,
+    /// The minimum ACK delay in microseconds, if the ACK frequency extension
+    /// is supported.
+    pub min_ack_delay: Option<u64>,
+    /// The initial maximum multipath path identifier, if the multipath
+    /// extension is supported.
+    pub initial_max_path_id: Option<u64>,
+    /// Whether the reliable stream reset extension is supported.
+    pub reset_stream_at: bool,
     /// Unknown peer transport parameters and values, if any.
     pub unknown_params: Option<UnknownTransportParameters>,
-    // pub preferred_address: ...,
 }
 
 impl Default for TransportParams {
@@ -210,6 +263,11 @@ impl Default for TransportParams {
             initial_source_connection_id: None,
             retry_source_connection_id: None,
             max_datagram_frame_size: None,
+            version_information: None,
+            preferred_address: None,
+            min_ack_delay: None,
+            initial_max_path_id: None,
+            reset_stream_at: false,
             unknown_params: Default::default(),
         }
     }
@@ -341,7 +399,48 @@ impl TransportParams {
                         return Err(Error::InvalidTransportParam);
                     }
 
-                    // TODO: decode preferred_address
+                    let ip_v4 = Ipv4Addr::from(val.get_u32()?);
+                    let port_v4 = val.get_u16()?;
+
+                    let ip_v6: [u8; 16] = val
+                        .get_bytes(16)?
+                        .buf()
+                        .try_into()
+                        .map_err(|_| Error::BufferTooShort)?;
+                    let ip_v6 = Ipv6Addr::from(ip_v6);
+                    let port_v6 = val.get_u16()?;
+
+                    let connection_id = val.get_bytes_with_u8_length()?;
+
+                    // A zero-length connection ID can't be used with a
+                    // preferred address.
+                    if connection_id.is_empty() ||
+                        connection_id.len() > crate::MAX_CONN_ID_LEN
+                    {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    let stateless_reset_token = u128::from_be_bytes(
+                        val.get_bytes(16)?
+                            .to_vec()
+                            .try_into()
+                            .map_err(|_| Error::BufferTooShort)?,
+                    );
+
+                    // An all-zero address and port signal that the address
+                    // family is not available.
+                    let addr_v4 = (!ip_v4.is_unspecified() || port_v4 != 0)
+                        .then(|| SocketAddrV4::new(ip_v4, port_v4));
+
+                    let addr_v6 = (!ip_v6.is_unspecified() || port_v6 != 0)
+                        .then(|| SocketAddrV6::new(ip_v6, port_v6, 0, 0));
+
+                    tp.preferred_address = Some(PreferredAddress {
+                        addr_v4,
+                        addr_v6,
+                        connection_id: connection_id.to_vec().into(),
+                        stateless_reset_token,
+                    });
                 },
 
                 0x000e => {
@@ -366,10 +465,65 @@ impl TransportParams {
                     tp.retry_source_connection_id = Some(val.to_vec().into());
                 },
 
+                0x0011 => {
+                    // The parameter must contain at least the chosen version,
+                    // and be composed of a list of 32-bit versions.
+                    if val.cap() < 4 || val.cap() % 4 != 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    let chosen_version = val.get_u32()?;
+
+                    if chosen_version == 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    let mut available_versions = Vec::new();
+
+                    while val.cap() > 0 {
+                        let version = val.get_u32()?;
+
+                        if version == 0 {
+                            return Err(Error::InvalidTransportParam);
+                        }
+
+                        available_versions.push(version);
+                    }
+
+                    tp.version_information = Some(VersionInformation {
+                        chosen_version,
+                        available_versions,
+                    });
+                },
+
                 0x0020 => {
                     tp.max_datagram_frame_size = Some(val.get_varint()?);
                 },
 
+                0xff04de1b => {
+                    let min_ack_delay = val.get_varint()?;
+
+                    if min_ack_delay >= MAX_MIN_ACK_DELAY {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.min_ack_delay = Some(min_ack_delay);
+                },
+
+                0x0f739bbc1b666d0c => {
+                    let max_path_id = val.get_varint()?;
+
+                    if max_path_id > MAX_PATH_ID {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.initial_max_path_id = Some(max_path_id);
+                },
+
+                0x17f7586d2cb571 => {
+                    tp.reset_stream_at = true;
+                },
+
                 // Track unknown transport parameters specially.
                 unknown_tp_id => {
                     if let Some(unknown_params) = &mut tp.unknown_params {
@@ -384,6 +538,13 @@ impl TransportParams {
             }
         }
 
+        // The minimum ACK delay can't be larger than the maximum one.
+        if let Some(min_ack_delay) = tp.min_ack_delay {
+            if min_ack_delay > tp.max_ack_delay.saturating_mul(1000) {
+                return Err(Error::InvalidTransportParam);
+            }
+        }
+
         Ok(tp)
     }
 
@@ -521,7 +682,28 @@ impl TransportParams {
             TransportParams::encode_param(&mut b, 0x000c, 0)?;
         }
 
-        // TODO: encode preferred_address
+        if is_server {
+            if let Some(pa) = &tp.preferred_address {
+                TransportParams::encode_param(&mut b, 0x000d, pa.encoded_len())?;
+
+                let addr_v4 = pa.addr_v4.unwrap_or_else(|| {
+                    SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)
+                });
+                b.put_bytes(&addr_v4.ip().octets())?;
+                b.put_u16(addr_v4.port())?;
+
+                let addr_v6 = pa.addr_v6.unwrap_or_else(|| {
+                    SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0)
+                });
+                b.put_bytes(&addr_v6.ip().octets())?;
+                b.put_u16(addr_v6.port())?;
+
+                b.put_u8(pa.connection_id.len() as u8)?;
+                b.put_bytes(&pa.connection_id)?;
+
+                b.put_bytes(&pa.stateless_reset_token.to_be_bytes())?;
+            }
+        }
 
         if tp.active_conn_id_limit != 2 {
             assert!(tp.active_conn_id_limit <= octets::MAX_VAR_INT);
@@ -545,6 +727,19 @@ impl TransportParams {
             }
         }
 
+        if let Some(vi) = &tp.version_information {
+            TransportParams::encode_param(
+                &mut b,
+                0x0011,
+                4 * (1 + vi.available_versions.len()),
+            )?;
+            b.put_u32(vi.chosen_version)?;
+
+            for &v in &vi.available_versions {
+                b.put_u32(v)?;
+            }
+        }
+
         if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
             assert!(max_datagram_frame_size <= octets::MAX_VAR_INT);
             TransportParams::encode_param(
@@ -555,6 +750,30 @@ impl TransportParams {
             b.put_varint(max_datagram_frame_size)?;
         }
 
+        if let Some(min_ack_delay) = tp.min_ack_delay {
+            assert!(min_ack_delay < MAX_MIN_ACK_DELAY);
+            TransportParams::encode_param(
+                &mut b,
+                0xff04de1b,
+                octets::varint_len(min_ack_delay),
+            )?;
+            b.put_varint(min_ack_delay)?;
+        }
+
+        if let Some(max_path_id) = tp.initial_max_path_id {
+            assert!(max_path_id <= MAX_PATH_ID);
+            TransportParams::encode_param(
+                &mut b,
+                0x0f739bbc1b666d0c,
+                octets::varint_len(max_path_id),
+            )?;
+            b.put_varint(max_path_id)?;
+        }
+
+        if tp.reset_stream_at {
+            TransportParams::encode_param(&mut b, 0x17f7586d2cb571, 0)?;
+        }
+
         let out_len = b.off();
 
         Ok(&mut out[..out_len])
@@ -603,6 +822,30 @@ impl TransportParams {
                 initial_max_streams_bidi: Some(self.initial_max_streams_bidi),
                 initial_max_streams_uni: Some(self.initial_max_streams_uni),
 
+                preferred_address: self.preferred_address.as_ref().map(|pa| {
+                    let addr_v4 = pa.addr_v4.unwrap_or_else(|| {
+                        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)
+                    });
+                    let addr_v6 = pa.addr_v6.unwrap_or_else(|| {
+                        SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0)
+                    });
+
+                    qlog::events::quic::PreferredAddress {
+                        ip_v4: addr_v4.ip().to_string(),
+                        ip_v6: addr_v6.ip().to_string(),
+                        port_v4: addr_v4.port(),
+                        port_v6: addr_v6.port(),
+                        connection_id: qlog::HexSlice::maybe_string(Some(
+                            &pa.connection_id,
+                        ))
+                        .unwrap_or_default(),
+                        stateless_reset_token: qlog::HexSlice::maybe_string(
+                            Some(&pa.stateless_reset_token.to_be_bytes()),
+                        )
+                        .unwrap_or_default(),
+                    }
+                }),
+
                 unknown_parameters: self
                     .unknown_params
                     .as_ref()
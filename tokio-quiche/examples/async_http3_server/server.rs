@@ -154,6 +154,11 @@ where
                     .await;
                 Ok(())
             },
+
+            ServerH3Event::ConnectIp { .. } => {
+                log::info!("received unsupported CONNECT-IP request");
+                Ok(())
+            },
         }
     }
 
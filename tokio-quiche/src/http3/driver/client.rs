@@ -172,6 +172,14 @@ impl ClientHooks {
             );
             let _ = driver.get_or_insert_flow(flow_id)?;
             stream_ctx.associated_dgram_flow_id = Some(flow_id);
+
+            if datagram::is_connect_ip(&request.headers) {
+                // The flow carries IP packets, and the stream capsules.
+                if let Some(flow) = driver.flow_map.get_mut(&flow_id) {
+                    flow.connect_ip = true;
+                }
+                stream_ctx.capsule_protocol = true;
+            }
         }
 
         if let Some(body_writer) = request.body_writer {
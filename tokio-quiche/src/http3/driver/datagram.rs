@@ -33,6 +33,27 @@ use quiche::h3::{
     self,
 };
 
+/// The context ID of CONNECT-IP datagrams that carry full IP packets.
+/// See <https://www.rfc-editor.org/rfc/rfc9484.html#section-6>
+pub(crate) const IP_PACKET_CONTEXT_ID: u64 = 0;
+
+/// Returns whether `headers` make up a CONNECT-IP request.
+/// See <https://www.rfc-editor.org/rfc/rfc9484.html#section-4>
+pub(crate) fn is_connect_ip(headers: &[h3::Header]) -> bool {
+    let mut method = None;
+    let mut protocol = None;
+
+    for header in headers {
+        match header.name() {
+            b":method" => method = Some(header.value()),
+            b":protocol" => protocol = Some(header.value()),
+            _ => {},
+        };
+    }
+
+    method == Some(b"CONNECT") && protocol == Some(b"connect-ip")
+}
+
 /// Extracts the DATAGRAM flow ID proxied over the given `stream_id`,
 /// or `None` if this is not a proxy request.
 pub(crate) fn extract_flow_id(
@@ -75,34 +96,53 @@ pub(crate) fn extract_flow_id(
 }
 
 /// Sends an HTTP/3 datagram over the QUIC connection with the given `flow_id`.
+///
+/// If `context_id` is set, it is inserted between the flow ID and the payload.
 pub(crate) fn send_h3_dgram(
-    conn: &mut QuicheConnection, flow_id: u64, mut dgram: PooledDgram,
+    conn: &mut QuicheConnection, flow_id: u64, context_id: Option<u64>,
+    mut dgram: PooledDgram,
 ) -> quiche::Result<()> {
-    let mut prefix = [0u8; 8];
+    let mut prefix = [0u8; 16];
     let mut buf = octets::OctetsMut::with_slice(&mut prefix);
-    let flow_id = buf.put_varint(flow_id)?;
+    buf.put_varint(flow_id)?;
+    if let Some(context_id) = context_id {
+        buf.put_varint(context_id)?;
+    }
+    let len = buf.off();
+    let prefix = &prefix[..len];
 
-    if dgram.add_prefix(flow_id) {
+    if dgram.add_prefix(prefix) {
         conn.dgram_send(&dgram)
     } else {
         let mut inner = dgram.into_inner().into_vec();
-        inner.splice(..0, flow_id.iter().copied());
+        inner.splice(..0, prefix.iter().copied());
         conn.dgram_send_vec(inner)
     }
 }
 
 /// Reads the next HTTP/3 datagram from the QUIC connection.
 ///
+/// `is_connect_ip` is called with the datagram's flow ID. If it returns true,
+/// the context ID is stripped from the payload and `None` is returned for
+/// datagrams that don't carry an IP packet.
+///
 /// [`quiche::Error::Done`] is returned if there is no datagram to read.
 pub(crate) fn receive_h3_dgram(
-    conn: &mut QuicheConnection,
-) -> quiche::Result<(u64, InboundFrame)> {
+    conn: &mut QuicheConnection, is_connect_ip: impl FnOnce(u64) -> bool,
+) -> quiche::Result<(u64, Option<InboundFrame>)> {
     let dgram = conn.dgram_recv_vec()?;
     let mut buf = octets::Octets::with_slice(&dgram);
     let flow_id = buf.get_varint()?;
+
+    if is_connect_ip(flow_id) &&
+        buf.get_varint().ok() != Some(IP_PACKET_CONTEXT_ID)
+    {
+        return Ok((flow_id, None));
+    }
+
     let advance = buf.off();
     let datagram =
         InboundFrame::Datagram(BufFactory::dgram_from_slice(&dgram[advance..]));
 
-    Ok((flow_id, datagram))
+    Ok((flow_id, Some(datagram)))
 }
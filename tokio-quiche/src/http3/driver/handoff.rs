@@ -0,0 +1,82 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use super::H3ConnectionError;
+use super::H3ConnectionResult;
+
+/// The state of an HTTP/3 connection handed off by an
+/// [H3Driver](super::H3Driver).
+///
+/// This is obtained from
+/// [`H3Controller::hand_off`](super::H3Controller::hand_off) and consists of
+/// the QUIC connection's state, to be restored with
+/// [`quic::restore_with_config`](crate::quic::restore_with_config), and the
+/// HTTP/3 connection's state, to be restored with
+/// [`H3Driver::restore`](super::H3Driver::restore). [`Self::encode`] merges
+/// both into a single buffer that can be passed to
+/// [`socket::handoff::send_connection`](crate::socket::handoff::send_connection).
+#[derive(Debug, Clone)]
+pub struct HandOffState {
+    /// The state exported by [`quiche::Connection::export_state`].
+    pub quic_state: Vec<u8>,
+    /// The state exported by [`quiche::h3::Connection::export_state`].
+    pub h3_state: Vec<u8>,
+}
+
+impl HandOffState {
+    /// Merges the QUIC and HTTP/3 states into a single buffer.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf =
+            Vec::with_capacity(8 + self.quic_state.len() + self.h3_state.len());
+
+        buf.extend_from_slice(&(self.quic_state.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&self.quic_state);
+        buf.extend_from_slice(&self.h3_state);
+
+        buf
+    }
+
+    /// Splits a buffer written by [`Self::encode`] into the QUIC and HTTP/3
+    /// states.
+    pub fn decode(buf: &[u8]) -> H3ConnectionResult<Self> {
+        let invalid = || H3ConnectionError::from(quiche::Error::InvalidSnapshot);
+
+        let (len, rest) = buf.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let len =
+            usize::try_from(u64::from_be_bytes(*len)).map_err(|_| invalid())?;
+
+        if len > rest.len() {
+            return Err(invalid());
+        }
+
+        let (quic_state, h3_state) = rest.split_at(len);
+
+        Ok(Self {
+            quic_state: quic_state.to_vec(),
+            h3_state: h3_state.to_vec(),
+        })
+    }
+}
@@ -28,6 +28,7 @@ mod client;
 /// Wrapper for running HTTP/3 connections.
 pub mod connection;
 mod datagram;
+mod handoff;
 // `DriverHooks` must stay private to prevent users from creating their own
 // H3Drivers.
 mod hooks;
@@ -57,6 +58,7 @@ use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
 use tokio_stream::StreamExt;
 use tokio_util::sync::PollSender;
 
@@ -76,6 +78,7 @@ use crate::buf_factory::PooledDgram;
 use crate::http3::settings::Http3Settings;
 use crate::http3::H3AuditStats;
 use crate::metrics::Metrics;
+use crate::quic::ConnectionHandedOff;
 use crate::quic::HandshakeInfo;
 use crate::quic::QuicCommand;
 use crate::quic::QuicheConnection;
@@ -89,6 +92,7 @@ pub use self::client::ClientH3Driver;
 pub use self::client::ClientH3Event;
 pub use self::client::ClientRequestSender;
 pub use self::client::NewClientRequest;
+pub use self::handoff::HandOffState;
 pub use self::server::IsInEarlyData;
 pub use self::server::RawPriorityValue;
 pub use self::server::ServerEventStream;
@@ -210,6 +214,37 @@ impl fmt::Debug for IncomingH3Headers {
     }
 }
 
+/// A WebTransport stream associated with a WebTransport session.
+///
+/// The stream was either opened by the peer and reported via
+/// [`H3Event::WebTransportStream`], or opened locally with
+/// [`H3Command::OpenWebTransportStream`]. Data is exchanged as
+/// [`OutboundFrame::Body`] and [`InboundFrame::Body`] frames.
+pub struct WebTransportStream {
+    /// Stream ID of the CONNECT request that established the session.
+    pub session_id: u64,
+    /// Stream ID of the WebTransport stream.
+    pub stream_id: u64,
+    /// An [`OutboundFrameSender`] for streaming data to the peer. The channel
+    /// is closed for unidirectional streams opened by the peer.
+    pub send: OutboundFrameSender,
+    /// An [`InboundFrameStream`] of data received from the peer. The channel
+    /// is closed for unidirectional streams opened locally.
+    pub recv: InboundFrameStream,
+    /// Handle to the [`H3AuditStats`] for the stream.
+    pub h3_audit_stats: Arc<H3AuditStats>,
+}
+
+impl fmt::Debug for WebTransportStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebTransportStream")
+            .field("session_id", &self.session_id)
+            .field("stream_id", &self.stream_id)
+            .field("h3_audit_stats", &self.h3_audit_stats)
+            .finish()
+    }
+}
+
 /// [`H3Event`]s are produced by an [H3Driver] to describe HTTP/3 state updates.
 ///
 /// Both [ServerH3Driver] and [ClientH3Driver] may extend this enum with
@@ -259,6 +294,9 @@ pub enum H3Event {
     /// don't result from RST_STREAM frames, unlike the
     /// [`H3Event::ResetStream`] variant.
     StreamClosed { stream_id: u64 },
+    /// The peer opened a WebTransport stream. This is only emitted if
+    /// WebTransport was enabled in [`Http3Settings`].
+    WebTransportStream(WebTransportStream),
 }
 
 impl H3Event {
@@ -297,6 +335,9 @@ pub enum OutboundFrame {
     PeerStreamError,
     /// DATAGRAM flow explicitly closed.
     FlowShutdown { flow_id: u64, stream_id: u64 },
+    /// A capsule to send on a stream using the Capsule Protocol, such as a
+    /// CONNECT-IP request stream.
+    Capsule(h3::Capsule),
 }
 
 impl OutboundFrame {
@@ -316,8 +357,11 @@ impl OutboundFrame {
 pub enum InboundFrame {
     /// Request body/CONNECT upstream data plus FIN flag.
     Body(PooledBuf, bool),
-    /// CONNECT-UDP (DATAGRAM) upstream data.
+    /// CONNECT-UDP (DATAGRAM) upstream data, or an IP packet for CONNECT-IP.
     Datagram(PooledDgram),
+    /// A capsule received on a stream using the Capsule Protocol, such as a
+    /// CONNECT-IP request stream.
+    Capsule(h3::Capsule),
 }
 
 /// A ready-made [`ApplicationOverQuic`] which can handle HTTP/3 and MASQUE.
@@ -367,6 +411,15 @@ pub struct H3Driver<H: DriverHooks> {
     /// Tracks whether we have forwarded the HTTP/3 SETTINGS frame
     /// to the [H3Controller] once.
     settings_received_and_forwarded: bool,
+
+    /// Whether the connection was handed off by another driver. See
+    /// [`H3Driver::restore`].
+    restored: bool,
+    /// The HTTP/3 state to restore `conn` from, if `restored` is set.
+    restored_state: Option<Vec<u8>>,
+    /// Set once the connection's state was exported in response to
+    /// [`H3Command::HandOff`]. The driver stops immediately afterwards.
+    handed_off: bool,
 }
 
 impl<H: DriverHooks> H3Driver<H> {
@@ -399,6 +452,10 @@ impl<H: DriverHooks> H3Driver<H> {
                 waiting_streams: FuturesUnordered::new(),
 
                 settings_received_and_forwarded: false,
+
+                restored: false,
+                restored_state: None,
+                handed_off: false,
             },
             H3Controller {
                 cmd_sender,
@@ -407,6 +464,24 @@ impl<H: DriverHooks> H3Driver<H> {
         )
     }
 
+    /// Builds a new [H3Driver] and an associated [H3Controller] for a
+    /// connection handed off by another driver.
+    ///
+    /// `h3_state` is the [`HandOffState::h3_state`] obtained from
+    /// [`H3Controller::hand_off`], while the QUIC connection should be
+    /// restored from [`HandOffState::quic_state`] with
+    /// [`quic::restore_with_config`](crate::quic::restore_with_config), passing
+    /// it the returned driver.
+    pub fn restore(
+        http3_settings: Http3Settings, h3_state: Vec<u8>,
+    ) -> (Self, H3Controller<H>) {
+        let (mut driver, controller) = Self::new(http3_settings);
+        driver.restored = true;
+        driver.restored_state = Some(h3_state);
+
+        (driver, controller)
+    }
+
     /// Retrieve the [FlowCtx] associated with the given `flow_id`. If no
     /// context is found, a new one will be created.
     fn get_or_insert_flow(
@@ -431,6 +506,17 @@ impl<H: DriverHooks> H3Driver<H> {
         })
     }
 
+    /// Creates the [FlowCtx] for a CONNECT-IP request with the given
+    /// `flow_id`, replacing any flow created by earlier datagrams. Unlike
+    /// `Self::get_or_insert_flow`, no [`H3Event::NewFlow`] is emitted, so the
+    /// returned receiver must be handed to the application by the caller.
+    fn insert_connect_ip_flow(&mut self, flow_id: u64) -> InboundFrameStream {
+        let (mut flow, recv) = FlowCtx::new(FLOW_CAPACITY);
+        flow.connect_ip = true;
+        self.flow_map.insert(flow_id, flow);
+        recv
+    }
+
     /// Adds a [StreamCtx] to the stream map with the given `stream_id`.
     fn insert_stream(&mut self, stream_id: u64, ctx: StreamCtx) {
         self.stream_map.insert(stream_id, ctx);
@@ -511,14 +597,25 @@ impl<H: DriverHooks> H3Driver<H> {
                 };
             }
 
-            match conn.recv_body(qconn, stream_id, &mut self.pooled_buf) {
-                Ok(n) => {
-                    let mut body = std::mem::replace(
-                        &mut self.pooled_buf,
-                        BufFactory::get_max_buf(),
-                    );
-                    body.truncate(n);
+            let res = if ctx.capsule_protocol {
+                conn.recv_capsule(qconn, stream_id).map(|capsule| {
+                    (capsule.wire_len(), InboundFrame::Capsule(capsule))
+                })
+            } else {
+                conn.recv_body(qconn, stream_id, &mut self.pooled_buf)
+                    .map(|n| {
+                        let mut body = std::mem::replace(
+                            &mut self.pooled_buf,
+                            BufFactory::get_max_buf(),
+                        );
+                        body.truncate(n);
 
+                        (n, InboundFrame::Body(body, false))
+                    })
+            };
+
+            match res {
+                Ok((n, frame)) => {
                     ctx.audit_stats.add_downstream_bytes_recvd(n as u64);
                     let event = H3Event::BodyBytesReceived {
                         stream_id,
@@ -527,7 +624,7 @@ impl<H: DriverHooks> H3Driver<H> {
                     };
                     let _ = self.h3_event_sender.send(event.into());
 
-                    permit.send(InboundFrame::Body(body, false));
+                    permit.send(frame);
                 },
                 Err(h3::Error::Done) =>
                     break StreamStatus::Done { close: false },
@@ -665,7 +762,110 @@ impl<H: DriverHooks> H3Driver<H> {
 
             h3::Event::PriorityUpdate => Ok(()),
             h3::Event::GoAway => Err(H3ConnectionError::GoAway),
+
+            h3::Event::WebTransportStream { session_id } =>
+                self.process_webtransport_stream(session_id, stream_id),
+
+            // Server push is never enabled, so these can't be received.
+            h3::Event::PushPromise { .. } | h3::Event::CancelPush => Ok(()),
+        }
+    }
+
+    /// Allocates a [StreamCtx] for a WebTransport stream opened by the peer
+    /// and forwards it to the [H3Controller].
+    fn process_webtransport_stream(
+        &mut self, session_id: u64, stream_id: u64,
+    ) -> H3ConnectionResult<()> {
+        let (mut ctx, send, recv) = StreamCtx::new(stream_id, STREAM_CAPACITY);
+
+        if stream_id & 0x2 == 0 {
+            self.waiting_streams.push(ctx.wait_for_recv(stream_id));
+        } else {
+            // Unidirectional streams can't be written to.
+            ctx.recv = None;
+            ctx.fin_or_reset_sent = true;
+        }
+
+        let event = H3Event::WebTransportStream(WebTransportStream {
+            session_id,
+            stream_id,
+            send,
+            recv,
+            h3_audit_stats: Arc::clone(&ctx.audit_stats),
+        });
+
+        self.insert_stream(stream_id, ctx);
+        self.h3_event_sender
+            .send(event.into())
+            .map_err(|_| H3ConnectionError::ControllerWentAway)?;
+        Ok(())
+    }
+
+    /// Opens a new WebTransport stream on the given session and allocates a
+    /// [StreamCtx] for it.
+    fn open_webtransport_stream(
+        &mut self, qconn: &mut QuicheConnection, session_id: u64,
+        bidirectional: bool,
+    ) -> H3ConnectionResult<WebTransportStream> {
+        let stream_id = self.conn_mut()?.open_webtransport_stream(
+            qconn,
+            session_id,
+            bidirectional,
+        )?;
+
+        let (mut ctx, send, recv) = StreamCtx::new(stream_id, STREAM_CAPACITY);
+        self.waiting_streams.push(ctx.wait_for_recv(stream_id));
+
+        if !bidirectional {
+            // Nothing will ever be received on a local unidirectional stream.
+            ctx.send = None;
+            ctx.fin_or_reset_recv = true;
+        }
+
+        let stream = WebTransportStream {
+            session_id,
+            stream_id,
+            send,
+            recv,
+            h3_audit_stats: Arc::clone(&ctx.audit_stats),
+        };
+
+        self.insert_stream(stream_id, ctx);
+        Ok(stream)
+    }
+
+    /// Closes a WebTransport session by sending a
+    /// `WT_CLOSE_SESSION` capsule and a FIN on the session's CONNECT stream.
+    /// The session's streams are reset and their [StreamCtx]s dropped.
+    fn close_webtransport_session(
+        &mut self, qconn: &mut QuicheConnection, session_id: u64,
+        error_code: u32, reason: &[u8],
+    ) -> H3ConnectionResult<()> {
+        // Split self borrow between conn and stream_map
+        let conn = self.conn.as_mut().ok_or(Self::connection_not_present())?;
+
+        let session_streams: Vec<u64> = self
+            .stream_map
+            .keys()
+            .copied()
+            .filter(|id| conn.webtransport_session_id(*id) == Some(session_id))
+            .collect();
+
+        conn.close_webtransport_session(qconn, session_id, error_code, reason)?;
+
+        for stream_id in session_streams {
+            self.cleanup_stream(qconn, stream_id)?;
+        }
+
+        let Some(ctx) = self.stream_map.get_mut(&session_id) else {
+            return Ok(());
+        };
+
+        if Self::on_fin_sent(ctx).is_err() {
+            return self.cleanup_stream(qconn, session_id);
         }
+
+        Ok(())
     }
 
     /// The SETTINGS frame can be received at any point, so we
@@ -796,6 +996,12 @@ impl<H: DriverHooks> H3Driver<H> {
                 res
             },
 
+            OutboundFrame::Capsule(capsule) => {
+                conn.send_capsule(qconn, stream_id, capsule)?;
+                audit_stats.add_downstream_bytes_sent(capsule.wire_len() as _);
+                Ok(())
+            },
+
             OutboundFrame::PeerStreamError => Err(h3::Error::MessageError),
 
             OutboundFrame::FlowShutdown { .. } => {
@@ -886,8 +1092,16 @@ impl<H: DriverHooks> H3Driver<H> {
         loop {
             match frame {
                 Ok(OutboundFrame::Datagram(dgram, flow_id)) => {
+                    let context_id = self
+                        .flow_map
+                        .get(&flow_id)
+                        .filter(|flow| flow.connect_ip)
+                        .map(|_| datagram::IP_PACKET_CONTEXT_ID);
+
                     // Drop datagrams if there is no capacity
-                    let _ = datagram::send_h3_dgram(qconn, flow_id, dgram);
+                    let _ = datagram::send_h3_dgram(
+                        qconn, flow_id, context_id, dgram,
+                    );
                 },
                 Ok(OutboundFrame::FlowShutdown { flow_id, stream_id }) => {
                     self.shutdown_stream(
@@ -1037,7 +1251,14 @@ impl<H: DriverHooks> H3Driver<H> {
         match cmd {
             H3Command::QuicCmd(cmd) => cmd.execute(qconn),
             H3Command::GoAway => {
-                let max_id = self.max_stream_seen;
+                // Clients send a push ID instead, and server push is never
+                // enabled.
+                let max_id = if qconn.is_server() {
+                    self.max_stream_seen
+                } else {
+                    0
+                };
+
                 self.conn_mut()
                     .expect("connection should be established")
                     .send_goaway(qconn, max_id)?;
@@ -1048,9 +1269,63 @@ impl<H: DriverHooks> H3Driver<H> {
             } => {
                 self.shutdown_stream(qconn, stream_id, shutdown)?;
             },
+            H3Command::OpenWebTransportStream {
+                session_id,
+                bidirectional,
+                stream,
+            } => {
+                let res = self.open_webtransport_stream(
+                    qconn,
+                    session_id,
+                    bidirectional,
+                );
+                let _ = stream.send(res);
+            },
+            H3Command::CloseWebTransportSession {
+                session_id,
+                error_code,
+                reason,
+            } => {
+                self.close_webtransport_session(
+                    qconn, session_id, error_code, &reason,
+                )?;
+            },
+            H3Command::DrainWebTransportSession { session_id } => {
+                self.conn_mut()?
+                    .drain_webtransport_session(qconn, session_id)?;
+            },
+            H3Command::HandOff { state } => {
+                let res = self.export_state(qconn);
+                let exported = res.is_ok();
+
+                // If the controller is not listening anymore, the state would
+                // be lost, so the driver keeps serving the connection instead.
+                self.handed_off = state.send(res).is_ok() && exported;
+            },
         }
         Ok(())
     }
+
+    /// Exports the state of the QUIC and HTTP/3 connections, to hand the
+    /// connection off to another driver.
+    ///
+    /// Only connections without requests in progress can be handed off, as
+    /// their streams' channels can't be carried over.
+    fn export_state(
+        &mut self, qconn: &QuicheConnection,
+    ) -> H3ConnectionResult<HandOffState> {
+        if !self.stream_map.is_empty() {
+            return Err(quiche::Error::InvalidState.into());
+        }
+
+        let h3_state = self.conn_mut()?.export_state();
+        let quic_state = qconn.export_state()?;
+
+        Ok(HandOffState {
+            quic_state,
+            h3_state,
+        })
+    }
 }
 
 impl<H: DriverHooks> H3Driver<H> {
@@ -1060,10 +1335,17 @@ impl<H: DriverHooks> H3Driver<H> {
         &mut self, qconn: &mut QuicheConnection,
     ) -> H3ConnectionResult<()> {
         loop {
-            match datagram::receive_h3_dgram(qconn) {
-                Ok((flow_id, dgram)) => {
+            let flow_map = &self.flow_map;
+            let is_connect_ip = |flow_id| {
+                flow_map.get(&flow_id).is_some_and(|flow| flow.connect_ip)
+            };
+
+            match datagram::receive_h3_dgram(qconn, is_connect_ip) {
+                Ok((flow_id, Some(dgram))) => {
                     self.get_or_insert_flow(flow_id)?.send_best_effort(dgram);
                 },
+                // Not an IP packet, silently drop it.
+                Ok((_, None)) => (),
                 Err(quiche::Error::Done) => return Ok(()),
                 Err(err) => return Err(H3ConnectionError::from(err)),
             }
@@ -1197,7 +1479,10 @@ impl<H: DriverHooks> ApplicationOverQuic for H3Driver<H> {
         &mut self, quiche_conn: &mut QuicheConnection,
         handshake_info: &HandshakeInfo,
     ) -> QuicResult<()> {
-        let conn = h3::Connection::with_transport(quiche_conn, &self.h3_config)?;
+        let conn = match self.restored_state.take() {
+            Some(state) => h3::Connection::restore(quiche_conn, &state)?,
+            None => h3::Connection::with_transport(quiche_conn, &self.h3_config)?,
+        };
         self.conn = Some(conn);
 
         H::conn_established(self, quiche_conn, handshake_info)?;
@@ -1270,6 +1555,10 @@ impl<H: DriverHooks> ApplicationOverQuic for H3Driver<H> {
             return;
         };
 
+        if work_loop_error.is::<ConnectionHandedOff>() {
+            return;
+        }
+
         Self::record_quiche_error(quiche_conn, metrics);
 
         let Some(h3_err) = work_loop_error.downcast_ref::<H3ConnectionError>()
@@ -1308,8 +1597,15 @@ impl<H: DriverHooks> ApplicationOverQuic for H3Driver<H> {
         // Make sure controller is not starved, but also not prioritized in the
         // biased select. So poll it last, however also perform a try_recv
         // each iteration.
-        if let Ok(cmd) = self.cmd_recv.try_recv() {
-            H::conn_command(self, qconn, cmd)?;
+        if !self.handed_off {
+            if let Ok(cmd) = self.cmd_recv.try_recv() {
+                H::conn_command(self, qconn, cmd)?;
+            }
+        }
+
+        // Nothing may touch the connection once its state was exported.
+        if self.handed_off {
+            return Err(ConnectionHandedOff.into());
         }
 
         Ok(())
@@ -1350,6 +1646,39 @@ pub enum H3Command {
         stream_id: u64,
         shutdown: StreamShutdown,
     },
+    /// Opens a new WebTransport stream on the session established by the
+    /// CONNECT request on `session_id`.
+    ///
+    /// The resulting [`WebTransportStream`], or the error encountered while
+    /// opening it, is sent back on `stream`. See
+    /// [`quiche::h3::Connection::open_webtransport_stream`] for details.
+    OpenWebTransportStream {
+        session_id: u64,
+        bidirectional: bool,
+        stream: oneshot::Sender<Result<WebTransportStream, H3ConnectionError>>,
+    },
+    /// Closes the WebTransport session established on `session_id` with a
+    /// `WT_CLOSE_SESSION` capsule, and sends a FIN on the session's CONNECT
+    /// stream.
+    ///
+    /// No further body data may be sent on the CONNECT stream afterwards.
+    CloseWebTransportSession {
+        session_id: u64,
+        error_code: u32,
+        reason: Vec<u8>,
+    },
+    /// Asks the peer to gracefully wind down the WebTransport session
+    /// established on `session_id` with a `WT_DRAIN_SESSION` capsule.
+    DrainWebTransportSession { session_id: u64 },
+    /// Exports the connection's state and stops the driver without closing
+    /// the connection, so that it can be restored by another process.
+    ///
+    /// The exported [`HandOffState`], or the error encountered while
+    /// exporting it, is sent back on `state`. See [`H3Controller::hand_off`]
+    /// for details.
+    HandOff {
+        state: oneshot::Sender<Result<HandOffState, H3ConnectionError>>,
+    },
 }
 
 /// Specifies which direction(s) of a stream to shut down.
@@ -1471,4 +1800,45 @@ impl<H: DriverHooks> H3Controller<H> {
             .into(),
         );
     }
+
+    /// Opens a new WebTransport stream on the session established on
+    /// `session_id`. The returned receiver resolves once the driver has
+    /// processed the request.
+    pub fn open_webtransport_stream(
+        &self, session_id: u64, bidirectional: bool,
+    ) -> oneshot::Receiver<Result<WebTransportStream, H3ConnectionError>> {
+        let (stream, rx) = oneshot::channel();
+        let _ = self.cmd_sender.send(
+            H3Command::OpenWebTransportStream {
+                session_id,
+                bidirectional,
+                stream,
+            }
+            .into(),
+        );
+        rx
+    }
+
+    /// Hands the connection off, e.g. to another process during a graceful
+    /// restart. The returned receiver resolves once the driver has processed
+    /// the request.
+    ///
+    /// On success, the driver stops without closing the connection or sending
+    /// any further packets, and the returned [`HandOffState`] can be restored
+    /// with [`H3Driver::restore`] and
+    /// [`quic::restore_with_config`](crate::quic::restore_with_config). See
+    /// [`socket::handoff`](crate::socket::handoff) for passing it to another
+    /// process.
+    ///
+    /// Only connections without requests in progress can be handed off.
+    /// Otherwise, the driver keeps serving the connection and an
+    /// [`H3ConnectionError`] wrapping [`quiche::Error::InvalidState`] is
+    /// returned.
+    pub fn hand_off(
+        &self,
+    ) -> oneshot::Receiver<Result<HandOffState, H3ConnectionError>> {
+        let (state, rx) = oneshot::channel();
+        let _ = self.cmd_sender.send(H3Command::HandOff { state }.into());
+        rx
+    }
 }
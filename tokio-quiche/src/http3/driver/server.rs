@@ -37,8 +37,10 @@ use super::H3ConnectionResult;
 use super::H3Controller;
 use super::H3Driver;
 use super::H3Event;
+use super::InboundFrameStream;
 use super::InboundHeaders;
 use super::IncomingH3Headers;
+use super::OutboundFrameSender;
 use super::StreamCtx;
 use super::STREAM_CAPACITY;
 use crate::http3::settings::Http3Settings;
@@ -79,6 +81,11 @@ impl Deref for RawPriorityValue {
 }
 
 /// The request was received during early data (0-RTT).
+///
+/// Early data can be replayed, so requests that are not safe to repeat should
+/// be answered with a `425 Too Early` status code ([RFC 8470]).
+///
+/// [RFC 8470]: https://www.rfc-editor.org/rfc/rfc8470
 #[derive(Clone, Debug)]
 pub struct IsInEarlyData(bool);
 
@@ -107,6 +114,26 @@ pub enum ServerH3Event {
         priority: Option<RawPriorityValue>,
         is_in_early_data: IsInEarlyData,
     },
+
+    /// A CONNECT-IP request was received. Its stream exchanges
+    /// [`InboundFrame::Capsule`](super::InboundFrame::Capsule)s and
+    /// [`OutboundFrame::Capsule`](super::OutboundFrame::Capsule)s instead of
+    /// body data, and IP packets are proxied on the request's DATAGRAM flow.
+    /// See <https://www.rfc-editor.org/rfc/rfc9484.html>
+    ConnectIp {
+        incoming_headers: IncomingH3Headers,
+        /// Flow ID of the request's IP packets. Packets are sent to the peer
+        /// as [`OutboundFrame::Datagram`](super::OutboundFrame::Datagram)s
+        /// with this ID on `ip_packet_send`.
+        flow_id: u64,
+        /// An [`OutboundFrameSender`] for transmitting IP packets to the peer.
+        ip_packet_send: OutboundFrameSender,
+        /// An [`InboundFrameStream`] for receiving IP packets from the peer.
+        ip_packet_recv: InboundFrameStream,
+        /// The latest PRIORITY_UPDATE frame value, if any.
+        priority: Option<RawPriorityValue>,
+        is_in_early_data: IsInEarlyData,
+    },
 }
 
 impl From<H3Event> for ServerH3Event {
@@ -191,8 +218,16 @@ impl ServerHooks {
         let (mut stream_ctx, send, recv) =
             StreamCtx::new(stream_id, STREAM_CAPACITY);
 
+        let mut ip_packet_flow = None;
+
         if let Some(flow_id) = datagram::extract_flow_id(stream_id, &headers) {
-            let _ = driver.get_or_insert_flow(flow_id)?;
+            if datagram::is_connect_ip(&headers) {
+                let recv = driver.insert_connect_ip_flow(flow_id);
+                ip_packet_flow = Some((flow_id, recv));
+                stream_ctx.capsule_protocol = true;
+            } else {
+                let _ = driver.get_or_insert_flow(flow_id)?;
+            }
             stream_ctx.associated_dgram_flow_id = Some(flow_id);
         }
 
@@ -227,13 +262,29 @@ impl ServerHooks {
             .push(stream_ctx.wait_for_recv(stream_id));
         driver.insert_stream(stream_id, stream_ctx);
 
-        driver
-            .h3_event_sender
-            .send(ServerH3Event::Headers {
+        let is_in_early_data = IsInEarlyData::new(
+            driver.conn_mut()?.is_early_data_request(stream_id),
+        );
+
+        let event = match ip_packet_flow {
+            Some((flow_id, ip_packet_recv)) => ServerH3Event::ConnectIp {
                 incoming_headers: headers,
+                flow_id,
+                ip_packet_send: driver.dgram_send.clone(),
+                ip_packet_recv,
                 priority: latest_priority_update,
-                is_in_early_data: IsInEarlyData::new(qconn.is_in_early_data()),
-            })
+                is_in_early_data,
+            },
+            None => ServerH3Event::Headers {
+                incoming_headers: headers,
+                priority: latest_priority_update,
+                is_in_early_data,
+            },
+        };
+
+        driver
+            .h3_event_sender
+            .send(event)
             .map_err(|_| H3ConnectionError::ControllerWentAway)?;
         driver.hooks.requests += 1;
 
@@ -263,8 +314,13 @@ impl DriverHooks for ServerHooks {
             "ServerH3Driver requires a server-side QUIC connection"
         );
 
-        if let Some(post_accept_timeout) =
-            driver.hooks.settings_enforcer.post_accept_timeout()
+        // A restored connection was accepted by the driver it was handed off
+        // from already.
+        if let Some(post_accept_timeout) = driver
+            .hooks
+            .settings_enforcer
+            .post_accept_timeout()
+            .filter(|_| !driver.restored)
         {
             let remaining = post_accept_timeout
                 .checked_sub(handshake_info.elapsed())
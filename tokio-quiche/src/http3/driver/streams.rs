@@ -63,6 +63,9 @@ pub(crate) struct StreamCtx {
     /// The flow ID for proxying datagrams over this stream. If `None`,
     /// the stream has no associated DATAGRAM flow.
     pub(crate) associated_dgram_flow_id: Option<u64>,
+    /// Indicates the stream uses the Capsule Protocol, so its data is
+    /// exchanged as capsules instead of raw body bytes.
+    pub(crate) capsule_protocol: bool,
 }
 
 impl StreamCtx {
@@ -87,6 +90,7 @@ impl StreamCtx {
             fin_or_reset_sent: false,
 
             associated_dgram_flow_id: None,
+            capsule_protocol: false,
         };
 
         (ctx, PollSender::new(backward_sender), forward_receiver)
@@ -167,6 +171,9 @@ pub(crate) struct FlowCtx {
     /// Sends inbound datagrams to a local task.
     send: mpsc::Sender<InboundFrame>,
     // No `recv`: all outbound datagrams are sent on a shared channel in H3Driver
+    /// Indicates the flow belongs to a CONNECT-IP request, so its datagrams
+    /// carry a context ID in front of the IP packet.
+    pub(crate) connect_ip: bool,
 }
 
 impl FlowCtx {
@@ -176,6 +183,7 @@ impl FlowCtx {
         let (forward_sender, forward_receiver) = mpsc::channel(capacity);
         let ctx = FlowCtx {
             send: forward_sender,
+            connect_ip: false,
         };
         (ctx, forward_receiver)
     }
@@ -257,6 +257,9 @@ impl<H: DriverHooks + GetConnectionForHook> DriverTestHelper<H> {
                 Ok(InboundFrame::Datagram(..)) => {
                     panic!("Unexepected InboundFrame::Datagram");
                 },
+                Ok(InboundFrame::Capsule(..)) => {
+                    panic!("Unexepected InboundFrame::Capsule");
+                },
                 Err(err) => return (buf, had_fin, err),
             }
             self.work_loop_iter().unwrap();
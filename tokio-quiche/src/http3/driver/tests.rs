@@ -547,6 +547,87 @@ mod server_side_driver {
         assert_eq!(helper.driver.stream_map.len(), 0);
     }
 
+    #[test]
+    fn hand_off_and_restore() {
+        let mut helper = DriverTestHelper::<ServerHooks>::new().unwrap();
+        helper.complete_handshake().unwrap();
+        helper.advance_and_run_loop().unwrap();
+
+        let stream_id = helper
+            .peer_client_send_request(make_request_headers("GET"), false)
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+        let req = assert_matches!(
+            helper.driver_recv_server_event().unwrap(),
+            ServerH3Event::Headers{incoming_headers, ..} => { incoming_headers }
+        );
+        assert_eq!(req.stream_id, stream_id);
+        let to_client = req.send.get_ref().unwrap().clone();
+        let mut from_client = req.recv;
+
+        // a request in progress prevents the hand-off
+        let mut handed_off = helper.controller.hand_off();
+        helper.work_loop_iter().unwrap();
+        assert_matches!(
+            handed_off.try_recv(),
+            Ok(Err(H3ConnectionError::H3(h3::Error::TransportError(
+                quiche::Error::InvalidState
+            ))))
+        );
+
+        // complete the request
+        to_client
+            .try_send(OutboundFrame::Headers(make_response_headers(), None))
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+        assert_eq!(helper.peer_client_send_body(0, &[1; 5], true), Ok(5));
+        helper.advance_and_run_loop().unwrap();
+        let (body, fin, _err) = helper.driver_try_recv_body(&mut from_client);
+        assert_eq!(body, vec![1; 5]);
+        assert!(fin);
+        to_client
+            .try_send(OutboundFrame::Body(
+                BufFactory::buf_from_slice(&[42]),
+                true,
+            ))
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+        assert_eq!(helper.driver.stream_map.len(), 0);
+
+        // the driver stops once the state is exported
+        let mut handed_off = helper.controller.hand_off();
+        let err = helper.work_loop_iter().unwrap_err();
+        assert!(err.is::<ConnectionHandedOff>());
+        let state = handed_off.try_recv().unwrap().unwrap();
+        let state = HandOffState::decode(&state.encode()).unwrap();
+
+        // a restored driver serves the connection from now on
+        helper.pipe.server =
+            quiche::restore(&state.quic_state, &mut default_quiche_config())
+                .unwrap();
+        (helper.driver, helper.controller) =
+            ServerH3Driver::restore(Http3Settings::default(), state.h3_state);
+        helper.complete_handshake().unwrap();
+        helper.advance_and_run_loop().unwrap();
+
+        assert_matches!(
+            helper.peer_client_poll(),
+            Ok((0, h3::Event::Headers { .. }))
+        );
+        assert_eq!(helper.peer_client_poll(), Ok((0, h3::Event::Data)));
+        assert_eq!(helper.peer_client_recv_body_vec(0, 1024), Ok(vec![42]));
+
+        let stream_id = helper
+            .peer_client_send_request(make_request_headers("GET"), true)
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+        let req = assert_matches!(
+            helper.driver_recv_server_event().unwrap(),
+            ServerH3Event::Headers{incoming_headers, ..} => { incoming_headers }
+        );
+        assert_eq!(req.stream_id, stream_id);
+    }
+
     /// Test the case where the client sends a STOP_SENDING quiche frame.
     #[test]
     fn client_sends_stop_sending() {
@@ -1528,4 +1609,141 @@ mod server_side_driver {
             READ_ERROR_CODE as i64
         );
     }
+
+    /// Test that a CONNECT-IP request hands the server an IP packet flow and
+    /// exchanges capsules on the request stream. The server routes packets
+    /// through a stand-in TUN device that echoes them back.
+    #[test]
+    fn server_connect_ip() {
+        use crate::http3::settings::Http3Settings;
+        use quiche::h3::AddressEntry;
+        use quiche::h3::Capsule;
+        use quiche::h3::Header;
+        use std::net::IpAddr;
+        use std::net::Ipv4Addr;
+
+        let mut config = default_quiche_config();
+        config.enable_dgram(true, 10, 10);
+
+        let mut helper =
+            DriverTestHelper::<ServerHooks>::with_pipe_and_http3_settings(
+                quiche::test_utils::Pipe::with_config(&mut config).unwrap(),
+                Http3Settings {
+                    enable_extended_connect: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        helper.complete_handshake().unwrap();
+        helper.advance_and_run_loop().unwrap();
+
+        // client sends a CONNECT-IP request
+        let headers = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"connect-ip"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/.well-known/masque/ip/*/*/"),
+            Header::new(b"capsule-protocol", b"?1"),
+        ];
+        let stream_id = helper.peer_client_send_request(headers, false).unwrap();
+
+        // server reads request and gets the IP packet flow
+        helper.advance_and_run_loop().unwrap();
+        let (req, flow_id, ip_packet_send, mut ip_packet_recv) = assert_matches!(
+            helper.driver_recv_server_event().unwrap(),
+            ServerH3Event::ConnectIp {
+                incoming_headers,
+                flow_id,
+                ip_packet_send,
+                ip_packet_recv,
+                ..
+            } => (incoming_headers, flow_id, ip_packet_send, ip_packet_recv)
+        );
+        assert_eq!(req.stream_id, stream_id);
+        assert_eq!(flow_id, stream_id / 4);
+        let to_client = req.send.get_ref().unwrap().clone();
+        let mut from_client = req.recv;
+
+        // server responds and assigns an address
+        let assign = Capsule::AddressAssign {
+            addresses: vec![AddressEntry {
+                request_id: 0,
+                address: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                prefix_len: 32,
+            }],
+        };
+        to_client
+            .try_send(OutboundFrame::Headers(make_response_headers(), None))
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+        to_client
+            .try_send(OutboundFrame::Capsule(assign.clone()))
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+
+        assert_matches!(
+            helper.peer_client_poll(),
+            Ok((0, h3::Event::Headers { .. }))
+        );
+        assert_eq!(helper.peer_client_poll(), Ok((0, h3::Event::Data)));
+        assert_eq!(
+            helper.peer.recv_capsule(&mut helper.pipe.client, stream_id),
+            Ok(assign)
+        );
+
+        // client sends a capsule to the server
+        let request = Capsule::AddressRequest {
+            addresses: vec![AddressEntry {
+                request_id: 1,
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                prefix_len: 0,
+            }],
+        };
+        helper
+            .peer
+            .send_capsule(&mut helper.pipe.client, stream_id, &request)
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+        assert_matches!(
+            from_client.try_recv(),
+            Ok(InboundFrame::Capsule(capsule)) => {
+                assert_eq!(capsule, request);
+            }
+        );
+
+        // client sends an IP packet, and a datagram with an unknown context
+        // ID which must be dropped
+        let packet = [0x45, 0x00, 0x00, 0x14, 1, 2, 3, 4];
+        let mut dgram = vec![flow_id as u8, 0];
+        dgram.extend_from_slice(&packet);
+        helper.pipe.client.dgram_send(&dgram).unwrap();
+        helper
+            .pipe
+            .client
+            .dgram_send(&[flow_id as u8, 1, 0xff])
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+
+        // the stand-in TUN device echoes the packet back
+        let received = assert_matches!(
+            ip_packet_recv.try_recv(),
+            Ok(InboundFrame::Datagram(dgram)) => dgram
+        );
+        assert_eq!(&received[..], &packet[..]);
+        assert_matches!(ip_packet_recv.try_recv(), Err(TryRecvError::Empty));
+
+        ip_packet_send
+            .get_ref()
+            .unwrap()
+            .try_send(OutboundFrame::Datagram(
+                BufFactory::dgram_from_slice(&received),
+                flow_id,
+            ))
+            .unwrap();
+        helper.advance_and_run_loop().unwrap();
+
+        // client receives the packet with the context ID
+        assert_eq!(helper.pipe.client.dgram_recv_vec(), Ok(dgram));
+    }
 }
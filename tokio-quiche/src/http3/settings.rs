@@ -61,6 +61,11 @@ pub struct Http3Settings {
     /// Set the `SETTINGS_ENABLE_CONNECT_PROTOCOL` HTTP/3 setting.
     /// See <https://www.rfc-editor.org/rfc/rfc9220#section-3-2>
     pub enable_extended_connect: bool,
+    /// Set the `SETTINGS_WT_MAX_SESSIONS` HTTP/3 setting, which enables
+    /// WebTransport over HTTP/3. Servers must also set
+    /// `enable_extended_connect`.
+    /// See <https://datatracker.ietf.org/doc/draft-ietf-webtrans-http3/>
+    pub webtransport_max_sessions: Option<u64>,
 }
 
 impl From<&Http3Settings> for quiche::h3::Config {
@@ -83,6 +88,10 @@ impl From<&Http3Settings> for quiche::h3::Config {
             config.enable_extended_connect(value.enable_extended_connect)
         }
 
+        if let Some(v) = value.webtransport_max_sessions {
+            config.set_webtransport_max_sessions(v);
+        }
+
         config
     }
 }
@@ -41,6 +41,16 @@ pub enum HandshakeError {
     ConnectionClosed,
 }
 
+/// Returned by an [`ApplicationOverQuic`](super::ApplicationOverQuic) after it
+/// exported the connection's state to hand the connection off to another
+/// process.
+///
+/// The IO worker stops driving the connection without sending any further
+/// packets, as the connection now lives on in the process that restores it.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("connection handed off")]
+pub struct ConnectionHandedOff;
+
 // We use io::Result for `IQC::handshake` to provide a uniform interface with
 // handshakes of other connection types, for example TLS. This is a best-effort
 // mapping to match the existing io::ErrorKind values.
@@ -28,6 +28,7 @@ mod error;
 mod id;
 mod map;
 
+pub use self::error::ConnectionHandedOff;
 pub use self::error::HandshakeError;
 pub use self::id::ConnectionIdGenerator;
 pub use self::id::SharedConnectionIdGenerator;
@@ -200,6 +201,13 @@ pub struct Incoming {
     /// If set, then `buf` is a GRO buffer containing multiple packets.
     /// Each individual packet has a size of `gso` (except for the last one).
     pub gro: Option<i32>,
+    /// The ECN codepoint of the packet.
+    ///
+    /// This is only reported if the socket has `IP_RECVTOS` or
+    /// `IPV6_RECVTCLASS` enabled, see [`SocketCapabilities`].
+    ///
+    /// [`SocketCapabilities`]: crate::socket::SocketCapabilities
+    pub ecn: quiche::Ecn,
     /// [SO_MARK] control message value received from the socket.
     ///
     /// This will always be `None` after the connection has been spawned as
@@ -119,7 +119,8 @@ impl PktInfo {
 pub async fn send_to(
     socket: &tokio::net::UdpSocket, to: SocketAddr, from: Option<SocketAddr>,
     send_buf: &[u8], segment_size: usize, tx_time: Option<Instant>,
-    would_block_metric: Counter, send_to_wouldblock_duration_s: TimeHistogram,
+    ecn: quiche::Ecn, would_block_metric: Counter,
+    send_to_wouldblock_duration_s: TimeHistogram,
 ) -> io::Result<usize> {
     // An instant with the value of zero, since [`Instant`] is backed by a version
     // of timespec this allows to extract raw values from an [`Instant`]
@@ -136,7 +137,10 @@ pub async fn send_to(
 
         let pkt_info = from.map(PktInfo::from_socket_addr);
 
-        let mut cmsgs: SmallVec<[ControlMessage; 3]> = SmallVec::new();
+        let tos = u8::from(ecn);
+        let tclass = i32::from(tos);
+
+        let mut cmsgs: SmallVec<[ControlMessage; 4]> = SmallVec::new();
 
         // Create cmsg for UDP_SEGMENT.
         cmsgs.push(ControlMessage::UdpGsoSegments(&segment_size_u16));
@@ -151,6 +155,14 @@ pub async fn send_to(
             cmsgs.push(pkt.make_cmsg());
         }
 
+        if ecn != quiche::Ecn::NotEct {
+            // Create cmsg for IP_TOS / IPV6_TCLASS.
+            cmsgs.push(match to {
+                SocketAddr::V4(_) => ControlMessage::Ipv4Tos(&tos),
+                SocketAddr::V6(_) => ControlMessage::Ipv6TClass(&tclass),
+            });
+        }
+
         let addr = SockaddrStorage::from(to);
 
         // Must use [`try_io`] so tokio can properly clear its readyness flag
@@ -180,7 +192,8 @@ pub async fn send_to(
 pub(crate) async fn send_to(
     socket: &tokio::net::UdpSocket, to: SocketAddr, _from: Option<SocketAddr>,
     send_buf: &[u8], _segment_size: usize, _tx_time: Option<Instant>,
-    _would_block_metric: Counter, _send_to_wouldblock_duration_s: TimeHistogram,
+    _ecn: quiche::Ecn, _would_block_metric: Counter,
+    _send_to_wouldblock_duration_s: TimeHistogram,
 ) -> io::Result<usize> {
     socket.send_to(send_buf, to).await
 }
@@ -44,6 +44,7 @@ use super::utilization_estimator::BandwidthReporter;
 use crate::metrics::labels;
 use crate::metrics::Metrics;
 use crate::quic::connection::ApplicationOverQuic;
+use crate::quic::connection::ConnectionHandedOff;
 use crate::quic::connection::HandshakeError;
 use crate::quic::connection::Incoming;
 use crate::quic::connection::QuicConnectionStats;
@@ -93,6 +94,11 @@ pub(crate) struct WriteState {
     segment_size: usize,
     num_pkts: usize,
     tx_time: Option<Instant>,
+    // The ECN codepoint of the packets in the current write cycle.
+    ecn: quiche::Ecn,
+    // A packet marked with a different ECN codepoint than the rest of the
+    // previous write cycle, to be sent at the start of the next one.
+    deferred_pkt: Option<(Vec<u8>, SendInfo)>,
     has_pending_data: bool,
     // If pacer schedules packets too far into the future, we want to pause
     // sending, until the future arrives
@@ -398,6 +404,17 @@ where
             None
         };
 
+        if let Some((pkt, info)) = self.write_state.deferred_pkt.take() {
+            send_buf[..pkt.len()].copy_from_slice(&pkt);
+
+            self.write_state.bytes_written = pkt.len();
+            self.write_state.num_pkts = 1;
+            self.write_state.selected_path = Some((info.from, info.to));
+
+            segment_size = Some(pkt.len());
+            send_info = Some(info);
+        }
+
         let buffer_write_outcome = loop {
             let outcome = self.write_packet_to_buffer(
                 qconn,
@@ -488,6 +505,7 @@ where
 
         self.write_state.conn_established = qconn.is_established();
         self.write_state.tx_time = tx_time;
+        self.write_state.ecn = send_info.map(|v| v.ecn).unwrap_or_default();
         self.write_state.segment_size =
             segment_size.unwrap_or(self.write_state.bytes_written);
 
@@ -573,6 +591,20 @@ where
         let (from, to) = self.select_path(qconn).unzip();
 
         match qconn.send_on_path(send_buf, from, to) {
+            // The ECN codepoint applies to the whole GSO buffer, so a packet
+            // marked differently than the previous ones ends the write cycle,
+            // and starts the next one instead.
+            Ok((packet_size, info))
+                if send_info.is_some_and(|v| v.ecn != info.ecn) =>
+            {
+                self.write_state.deferred_pkt =
+                    Some((send_buf[..packet_size].to_vec(), info));
+
+                self.write_state.has_pending_data = true;
+
+                Ok(0)
+            },
+
             Ok((packet_size, info)) => {
                 let _ = send_info.get_or_insert(info);
 
@@ -642,6 +674,7 @@ where
                     current_send_buf,
                     self.write_state.segment_size,
                     self.write_state.tx_time,
+                    self.write_state.ecn,
                     self.metrics
                         .write_errors(labels::QuicWriteError::WouldBlock),
                     self.metrics.send_to_wouldblock_duration_s(),
@@ -676,6 +709,7 @@ where
         let recv_info = quiche::RecvInfo {
             from: pkt.peer_addr,
             to: pkt.local_addr,
+            ecn: pkt.ecn,
         };
 
         if let Some(gro) = pkt.gro {
@@ -949,12 +983,21 @@ where
             );
         }
 
+        // A connection that was handed off is driven by another process now,
+        // so anything sent from here would clash with its packets.
+        let handed_off = matches!(
+            &self.conn_stage.work_loop_result,
+            Err(err) if err.is::<ConnectionHandedOff>()
+        );
+
         // TODO: this assumes that the tidy_up operation can be completed in one
         // send (ignoring flow/congestion control constraints). We should
         // guarantee that it gets sent by doublechecking the
         // gathered/flushed byte totals and retry if they don't match.
-        let _ = self.gather_data_from_quiche_conn(qconn, ctx.buffer());
-        self.flush_buffer_to_socket(ctx.buffer()).await;
+        if !handed_off {
+            let _ = self.gather_data_from_quiche_conn(qconn, ctx.buffer());
+            self.flush_buffer_to_socket(ctx.buffer()).await;
+        }
 
         *ctx.stats.lock().unwrap() = QuicConnectionStats::from_conn(qconn);
 
@@ -80,6 +80,7 @@
 //! [listen]: crate::listen
 //! [iqc]: crate::InitialQuicConnection
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -93,6 +94,7 @@ use crate::metrics::Metrics;
 use crate::settings::Config;
 use crate::socket::QuicListener;
 use crate::socket::Socket;
+use crate::socket::SocketCapabilities;
 use crate::ClientH3Controller;
 use crate::ClientH3Driver;
 use crate::ConnectionParams;
@@ -113,8 +115,10 @@ use self::connection::QuicConnection;
 use self::router::acceptor::ConnectionAcceptor;
 use self::router::acceptor::ConnectionAcceptorConfig;
 use self::router::connector::ClientConnector;
+use self::router::connector::RestoredConnector;
 use self::router::InboundPacketRouter;
 
+pub use self::connection::ConnectionHandedOff;
 pub use self::connection::ConnectionShutdownBehaviour;
 pub use self::connection::HandshakeError;
 pub use self::connection::HandshakeInfo;
@@ -278,6 +282,148 @@ where
         .start(app))
 }
 
+/// Restores a QUIC connection from `state`, as exported by
+/// [`quiche::Connection::export_state`], and drives it with `app` on
+/// `socket`.
+///
+/// This is the receiving end of a connection hand-off (see
+/// [`socket::handoff`](crate::socket::handoff)): `socket` is usually built
+/// from the file descriptor received along with `state`, and `params` should
+/// match the configuration the connection was created with. Since the
+/// connection is already established, `app`'s
+/// [`on_conn_established`](ApplicationOverQuic::on_conn_established) hook runs
+/// right away.
+///
+/// # Note
+/// The restored connection is the only one served on `socket`. Packets for
+/// other connections sharing the socket are dropped. Use
+/// [`restore_all_with_config`] to restore all connections of a socket at once.
+pub async fn restore_with_config<Tx, Rx, App>(
+    socket: Socket<Tx, Rx>, state: &[u8], params: &ConnectionParams<'_>, app: App,
+) -> QuicResult<QuicConnection>
+where
+    Tx: DatagramSocketSend + Send + 'static,
+    Rx: DatagramSocketRecv + Unpin + 'static,
+    App: ApplicationOverQuic,
+{
+    let mut connections = restore_connections(
+        Arc::new(socket.send),
+        socket.recv,
+        socket.local_addr,
+        socket.capabilities,
+        [(state, app)],
+        params,
+    )
+    .await?;
+
+    Ok(connections.pop().ok_or("unable to restore connection")?)
+}
+
+/// Restores several QUIC connections sharing the listening `socket`, and
+/// drives each with its own `app`.
+///
+/// This is the receiving end of the hand-off of all connections of a listener
+/// (see [`socket::send_connections`]). Each state is restored like
+/// in [`restore_with_config`], but the connections are served by a single
+/// packet router reading from `socket`, which dispatches packets to them by
+/// connection ID. The connections are returned in the order of
+/// `connections`. If any state fails to be restored, none of the connections
+/// is started.
+///
+/// # Note
+/// Packets for connections other than the restored ones are dropped, so the
+/// socket can't accept new connections.
+///
+/// [`socket::send_connections`]: crate::socket::send_connections
+pub async fn restore_all_with_config<S, App>(
+    socket: QuicListener, connections: impl IntoIterator<Item = (S, App)>,
+    params: &ConnectionParams<'_>,
+) -> QuicResult<Vec<QuicConnection>>
+where
+    S: AsRef<[u8]>,
+    App: ApplicationOverQuic,
+{
+    let local_addr = socket.socket.local_addr()?;
+    let socket_tx = Arc::new(socket.socket);
+    let socket_rx = Arc::clone(&socket_tx);
+
+    restore_connections(
+        socket_tx,
+        socket_rx,
+        local_addr,
+        socket.capabilities,
+        connections,
+        params,
+    )
+    .await
+}
+
+async fn restore_connections<Tx, Rx, S, App>(
+    socket_tx: Arc<Tx>, socket_rx: Rx, local_addr: SocketAddr,
+    capabilities: SocketCapabilities,
+    connections: impl IntoIterator<Item = (S, App)>,
+    params: &ConnectionParams<'_>,
+) -> QuicResult<Vec<QuicConnection>>
+where
+    Tx: DatagramSocketSend + Send + 'static,
+    Rx: DatagramSocketRecv + Unpin + 'static,
+    S: AsRef<[u8]>,
+    App: ApplicationOverQuic,
+{
+    let mut config = Config::new(params, capabilities)?;
+
+    let mut restored = Vec::new();
+
+    for (state, app) in connections {
+        #[cfg(feature = "zero-copy")]
+        let quiche_conn =
+            quiche::restore_with_buf_factory(state.as_ref(), config.as_mut())?;
+
+        #[cfg(not(feature = "zero-copy"))]
+        let quiche_conn = quiche::restore(state.as_ref(), config.as_mut())?;
+
+        log::info!("restored quiche::Connection"; "scid" => ?quiche_conn.source_id());
+
+        restored.push((quiche_conn, app));
+    }
+
+    let (mut router, mut quic_connection_stream) = InboundPacketRouter::new(
+        config,
+        socket_tx,
+        socket_rx,
+        local_addr,
+        RestoredConnector,
+        DefaultMetrics,
+    );
+
+    let mut quic_connections = Vec::with_capacity(restored.len());
+
+    for (quiche_conn, app) in restored {
+        router.adopt_connection(quiche_conn)?;
+
+        // Take each connection right away, so that the accept queue can't
+        // overflow with more connections than the listen backlog.
+        let conn = quic_connection_stream
+            .recv()
+            .await
+            .ok_or("unable to restore connection")??;
+
+        quic_connections.push(conn.start(app));
+    }
+
+    // drive the packet router:
+    tokio::spawn(async move {
+        match router.await {
+            Ok(()) => log::debug!("incoming packet router finished"),
+            Err(error) => {
+                log::error!("incoming packet router failed"; "error"=>error)
+            },
+        }
+    });
+
+    Ok(quic_connections)
+}
+
 pub(crate) fn start_listener<M>(
     socket: QuicListener, params: &ConnectionParams, metrics: M,
 ) -> std::io::Result<QuicConnectionStream<M>>
@@ -175,6 +175,7 @@ where
                     send_buf,
                     send_buf.len(),
                     None,
+                    quiche::Ecn::NotEct,
                     would_block_metric,
                     send_to_wouldblock_duration_s,
                 )
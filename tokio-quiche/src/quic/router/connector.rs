@@ -153,6 +153,7 @@ where
         let recv_info = quiche::RecvInfo {
             from: incoming.peer_addr,
             to: incoming.local_addr,
+            ecn: incoming.ecn,
         };
 
         if let Some(gro) = incoming.gro {
@@ -255,6 +256,20 @@ where
     }
 }
 
+/// A [`RestoredConnector`] is used for sockets that carry connections restored
+/// from an exported state. Since those connections are established already,
+/// Initial packets for any other connection are dropped.
+pub(crate) struct RestoredConnector;
+
+impl InitialPacketHandler for RestoredConnector {
+    fn handle_initials(
+        &mut self, _: Incoming, hdr: Header<'static>, _: &mut quiche::Config,
+    ) -> io::Result<Option<NewConnection>> {
+        log::debug!("Received Initial packet for unknown connection ID"; "scid" => ?hdr.dcid);
+        Ok(None)
+    }
+}
+
 /// Repeatedly send packets until quiche reports that it's done.
 ///
 /// This does not have to be efficent, since once a connection is established
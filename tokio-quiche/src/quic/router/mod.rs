@@ -110,6 +110,8 @@ struct PollRecvData {
     dst_addr_override: Option<SocketAddr>,
     rx_time: Option<SystemTime>,
     gro: Option<i32>,
+    // The ECN codepoint of the packet.
+    ecn: quiche::Ecn,
     #[cfg(target_os = "linux")]
     so_mark_data: Option<[u8; 4]>,
 }
@@ -218,7 +220,8 @@ where
                     u16, // drop count
                     sockaddr_in, // IP_RECVORIGDSTADDR
                     sockaddr_in6, // IPV6_RECVORIGDSTADDR
-                    u32 // SO_MARK
+                    u32, // SO_MARK
+                    i32 // IP_TOS / IPV6_TCLASS
                 ),
                 config,
 
@@ -295,6 +298,40 @@ where
         }
     }
 
+    /// Starts driving `conn`, a connection restored from an exported state,
+    /// on this router's socket.
+    ///
+    /// The connection is yielded by the router's connection stream like any
+    /// newly established connection. Any number of connections can be adopted
+    /// by the same router.
+    pub(crate) fn adopt_connection(
+        &mut self, conn: QuicheConnection,
+    ) -> io::Result<()> {
+        let path =
+            conn.path_stats().find(|path| path.active).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "restored connection has no active path",
+                )
+            })?;
+
+        let new_connection = NewConnection {
+            conn,
+            pending_cid: None,
+            initial_pkt: None,
+            cid_generator: None,
+            handshake_start_time: Instant::now(),
+        };
+
+        self.spawn_new_connection(
+            new_connection,
+            path.local_addr,
+            path.peer_addr,
+            #[cfg(feature = "perf-quic-listener-metrics")]
+            None,
+        )
+    }
+
     /// Creates a new [`QuicConnection`](super::QuicConnection) and spawns an
     /// associated io worker.
     fn spawn_new_connection(
@@ -408,6 +445,7 @@ where
             src_addr: addr,
             rx_time: None,
             gro: None,
+            ecn: quiche::Ecn::NotEct,
             dst_addr_override: None,
             #[cfg(target_os = "linux")]
             so_mark_data: None,
@@ -474,6 +512,7 @@ where
 
                         let mut rx_time = None;
                         let mut gro = None;
+                        let mut ecn = quiche::Ecn::NotEct;
                         let mut dst_addr_override = None;
                         let mut mark_bytes: Option<[u8; 4]> = None;
 
@@ -485,6 +524,7 @@ where
                                 dst_addr_override,
                                 rx_time,
                                 gro,
+                                ecn,
                                 so_mark_data: mark_bytes,
                             }));
                         };
@@ -513,6 +553,10 @@ where
                                 },
                                 ControlMessageOwned::UdpGroSegments(val) =>
                                     gro = Some(val),
+                                ControlMessageOwned::Ipv4Tos(val) =>
+                                    ecn = quiche::Ecn::from(val),
+                                ControlMessageOwned::Ipv6TClass(val) =>
+                                    ecn = quiche::Ecn::from(val as u8),
                                 ControlMessageOwned::Ipv4OrigDstAddr(val) => {
                                     let source_addr = std::net::Ipv4Addr::from(
                                         u32::to_be(val.sin_addr.s_addr),
@@ -597,6 +641,7 @@ where
                             dst_addr_override,
                             rx_time,
                             gro,
+                            ecn,
                             so_mark_data: mark_bytes,
                         }));
                     },
@@ -701,6 +746,7 @@ where
                     dst_addr_override,
                     rx_time,
                     gro,
+                    ecn,
                     #[cfg(target_os = "linux")]
                     so_mark_data,
                 })) => {
@@ -723,6 +769,7 @@ where
                         buf,
                         rx_time,
                         gro,
+                        ecn,
                         #[cfg(target_os = "linux")]
                         so_mark_data,
                     });
@@ -855,8 +902,15 @@ mod tests {
     use crate::ConnectionParams;
     use crate::ServerH3Driver;
 
+    use crate::quic::connection::ApplicationOverQuic;
+    use crate::quic::connection::ConnectionIdGenerator as _;
+    use crate::socket::QuicListener;
+    use crate::QuicResult;
+
     use datagram_socket::MAX_DATAGRAM_SIZE;
     use h3i::actions::h3::Action;
+    use quiche::test_utils::emit_flight;
+    use quiche::test_utils::process_flight;
     use std::sync::Arc;
     use std::time::Duration;
     use tokio::net::UdpSocket;
@@ -968,4 +1022,179 @@ mod tests {
         // never resolve hanging the test.
         drop_check.closed().await;
     }
+
+    /// Forwards the data received on any stream of the connection.
+    struct StreamReader {
+        buf: Vec<u8>,
+        received: mpsc::UnboundedSender<Vec<u8>>,
+    }
+
+    impl ApplicationOverQuic for StreamReader {
+        fn on_conn_established(
+            &mut self, _: &mut QuicheConnection, _: &HandshakeInfo,
+        ) -> QuicResult<()> {
+            Ok(())
+        }
+
+        fn should_act(&self) -> bool {
+            true
+        }
+
+        fn buffer(&mut self) -> &mut [u8] {
+            &mut self.buf
+        }
+
+        async fn wait_for_data(
+            &mut self, _: &mut QuicheConnection,
+        ) -> QuicResult<()> {
+            std::future::pending().await
+        }
+
+        fn process_reads(
+            &mut self, qconn: &mut QuicheConnection,
+        ) -> QuicResult<()> {
+            for stream_id in qconn.readable() {
+                while let Ok((len, _)) =
+                    qconn.stream_recv(stream_id, &mut self.buf)
+                {
+                    let _ = self.received.send(self.buf[..len].to_vec());
+                }
+            }
+
+            Ok(())
+        }
+
+        fn process_writes(&mut self, _: &mut QuicheConnection) -> QuicResult<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_connections_sharing_socket() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = socket.local_addr().unwrap();
+
+        let mut server_config =
+            quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file(TEST_CERT_FILE)
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file(TEST_KEY_FILE)
+            .unwrap();
+        server_config.set_application_protos(&[b"proto1"]).unwrap();
+        server_config.set_initial_max_data(1000);
+        server_config.set_initial_max_stream_data_bidi_remote(1000);
+        server_config.set_initial_max_streams_bidi(10);
+
+        let mut client_config =
+            quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+        client_config.set_application_protos(&[b"proto1"]).unwrap();
+        client_config.verify_peer(false);
+
+        // Establish two connections to the socket's address, and export the
+        // server side of both.
+        let mut clients = Vec::new();
+        let mut states = Vec::new();
+
+        for _ in 0..2 {
+            let client_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let client_addr = client_socket.local_addr().unwrap();
+
+            let mut client = quiche::connect(
+                None,
+                &SimpleConnectionIdGenerator.new_connection_id(),
+                client_addr,
+                server_addr,
+                &mut client_config,
+            )
+            .unwrap();
+            let mut server = quiche::accept(
+                &SimpleConnectionIdGenerator.new_connection_id(),
+                None,
+                server_addr,
+                client_addr,
+                &mut server_config,
+            )
+            .unwrap();
+
+            loop {
+                match emit_flight(&mut client) {
+                    Ok(flight) => process_flight(&mut server, flight).unwrap(),
+                    Err(quiche::Error::Done) if server.is_established() => break,
+                    Err(e) => panic!("client failed to send: {e:?}"),
+                }
+
+                if let Ok(flight) = emit_flight(&mut server) {
+                    process_flight(&mut client, flight).unwrap();
+                }
+            }
+
+            states.push(server.export_state().unwrap());
+            clients.push((client_socket, client));
+        }
+
+        let quic_settings = QuicSettings {
+            max_recv_udp_payload_size: MAX_DATAGRAM_SIZE,
+            max_send_udp_payload_size: MAX_DATAGRAM_SIZE,
+            ..Default::default()
+        };
+
+        let tls_cert_settings = TlsCertificatePaths {
+            cert: TEST_CERT_FILE,
+            private_key: TEST_KEY_FILE,
+            kind: crate::settings::CertificateKind::X509,
+        };
+
+        let params = ConnectionParams::new_server(
+            quic_settings,
+            tls_cert_settings,
+            Hooks::default(),
+        );
+
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        let connections = states.into_iter().map(|state| {
+            let app = StreamReader {
+                buf: vec![0; MAX_DATAGRAM_SIZE],
+                received: received_tx.clone(),
+            };
+
+            (state, app)
+        });
+
+        let _conns = crate::quic::restore_all_with_config(
+            QuicListener::try_from(socket).unwrap(),
+            connections,
+            &params,
+        )
+        .await
+        .unwrap();
+
+        // Both connections are served by the same router.
+        for (i, (client_socket, client)) in clients.iter_mut().enumerate() {
+            let msg = format!("hello from client {i}");
+            client.stream_send(0, msg.as_bytes(), true).unwrap();
+
+            let mut buf = [0; MAX_DATAGRAM_SIZE];
+            while let Ok((len, send_info)) = client.send(&mut buf) {
+                client_socket
+                    .send_to(&buf[..len], send_info.to)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let mut received = Vec::new();
+
+        for _ in 0..2 {
+            let data = time::timeout(Duration::from_secs(5), received_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push(String::from_utf8(data).unwrap());
+        }
+
+        received.sort();
+        assert_eq!(received, ["hello from client 0", "hello from client 1"]);
+    }
 }
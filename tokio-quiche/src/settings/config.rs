@@ -27,6 +27,7 @@
 use foundations::telemetry::log;
 use std::borrow::Cow;
 use std::fs::File;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::result::QuicResult;
@@ -173,6 +174,7 @@ fn make_quiche_config(
     );
     config.discover_pmtu(quic_settings.discover_path_mtu);
     config.set_pmtud_max_probes(quic_settings.pmtud_max_probes);
+    config.enable_ecn(quic_settings.enable_ecn);
     config.enable_hystart(quic_settings.enable_hystart);
 
     config.enable_pacing(quic_settings.enable_pacing);
@@ -208,6 +210,13 @@ fn make_quiche_config(
         config.enable_early_data();
     }
 
+    if let Some(window) = quic_settings.anti_replay_window {
+        config.set_anti_replay(Arc::new(quiche::BloomAntiReplay::new(
+            window,
+            quic_settings.anti_replay_capacity,
+        )));
+    }
+
     if should_log_keys {
         config.log_keys();
     }
@@ -65,6 +65,25 @@ pub struct QuicSettings {
     /// Defaults to `false`.
     pub enable_early_data: bool,
 
+    /// How long servers remember the ClientHellos that offered early data, in
+    /// milliseconds, in order to reject replayed 0-RTT data. This should
+    /// cover the lifetime of the server's session tickets.
+    ///
+    /// Defaults to `None`, e.g., no anti-replay protection. See
+    /// [`set_anti_replay()`] for more.
+    ///
+    /// [`set_anti_replay()`]: https://docs.rs/quiche/latest/quiche/struct.Config.html#method.set_anti_replay
+    #[serde(rename = "anti_replay_window_ms")]
+    #[serde_as(as = "Option<DurationMilliSeconds>")]
+    pub anti_replay_window: Option<Duration>,
+
+    /// The expected number of ClientHellos offering early data received
+    /// during the anti-replay window, used to size the anti-replay store.
+    ///
+    /// Defaults to `100_000`.
+    #[serde(default = "QuicSettings::default_anti_replay_capacity")]
+    pub anti_replay_capacity: usize,
+
     /// Sets the `initial_max_data` transport parameter.
     ///
     /// Defaults to 10 MB.
@@ -185,6 +204,15 @@ pub struct QuicSettings {
     #[serde(default = "QuicSettings::default_pmtud_max_probes")]
     pub pmtud_max_probes: u8,
 
+    /// Configures whether to mark outgoing packets as ECN-capable.
+    ///
+    /// Note: the ECN codepoints are only read from and written to packets
+    /// if the socket has `IP_RECVTOS`/`IPV6_RECVTCLASS` and GSO enabled in its
+    /// [`SocketCapabilities`](crate::socket::SocketCapabilities).
+    ///
+    /// Defaults to `false`.
+    pub enable_ecn: bool,
+
     /// Whether to use HyStart++ (only with `cubic` and `reno` CC).
     ///
     /// Defaults to `true`.
@@ -349,6 +377,11 @@ impl QuicSettings {
         10_000_000
     }
 
+    #[inline]
+    fn default_anti_replay_capacity() -> usize {
+        100_000
+    }
+
     #[inline]
     fn default_initial_max_stream_data() -> u64 {
         1_000_000
@@ -469,4 +502,15 @@ mod test {
         assert_eq!(quic.handshake_timeout.unwrap(), Duration::from_secs(5));
         assert_eq!(quic.max_idle_timeout.unwrap(), Duration::from_secs(7));
     }
+
+    #[test]
+    fn anti_replay_window_parses_as_milliseconds() {
+        let quic = serde_json::from_str::<QuicSettings>(
+            r#"{ "anti_replay_window_ms": 60000 }"#,
+        )
+        .unwrap();
+
+        assert_eq!(quic.anti_replay_window, Some(Duration::from_secs(60)));
+        assert_eq!(quic.anti_replay_capacity, 100_000);
+    }
 }
@@ -42,11 +42,13 @@ mod linux_imports {
     pub use nix::sys::socket::getsockopt;
     pub use nix::sys::socket::setsockopt;
     pub use nix::sys::socket::sockopt::IpFreebind;
+    pub use nix::sys::socket::sockopt::IpRecvTos;
     pub use nix::sys::socket::sockopt::IpTransparent;
     pub use nix::sys::socket::sockopt::Ipv4OrigDstAddr;
     pub use nix::sys::socket::sockopt::Ipv4PacketInfo;
     pub use nix::sys::socket::sockopt::Ipv6OrigDstAddr;
     pub use nix::sys::socket::sockopt::Ipv6RecvPacketInfo;
+    pub use nix::sys::socket::sockopt::Ipv6RecvTClass;
     #[cfg(feature = "perf-quic-listener-metrics")]
     pub use nix::sys::socket::sockopt::ReceiveTimestampns;
     pub use nix::sys::socket::sockopt::RxqOvfl;
@@ -269,6 +271,24 @@ impl<'s> SocketCapabilitiesBuilder<'s> {
         Ok(())
     }
 
+    /// Enables [`IP_RECVTOS`](https://man7.org/linux/man-pages/man7/ip.7.html),
+    /// which reports the ECN codepoint of received IPv4 packets.
+    pub fn ip_recvtos(&mut self) -> io::Result<()> {
+        setsockopt(&self.socket.as_fd(), IpRecvTos, &true)?;
+
+        self.cap.has_iprecvtos = true;
+        Ok(())
+    }
+
+    /// Enables [`IPV6_RECVTCLASS`](https://man7.org/linux/man-pages/man7/ipv6.7.html),
+    /// which reports the ECN codepoint of received IPv6 packets.
+    pub fn ipv6_recvtclass(&mut self) -> io::Result<()> {
+        setsockopt(&self.socket.as_fd(), Ipv6RecvTClass, &true)?;
+
+        self.cap.has_ipv6recvtclass = true;
+        Ok(())
+    }
+
     /// Sets [`IP_MTU_DISCOVER`](https://man7.org/linux/man-pages/man7/ip.7.html), to
     /// `IP_PMTUDISC_PROBE`, which disables kernel PMTUD and sets the `DF`
     /// (Don't Fragment) flag.
@@ -365,6 +385,14 @@ pub struct SocketCapabilities {
     #[cfg_attr(not(target_os = "linux"), expect(dead_code))]
     pub(crate) has_ipv6recvorigdstaddr: bool,
 
+    /// Indicates if the socket has `IP_RECVTOS` set.
+    #[cfg_attr(not(target_os = "linux"), expect(dead_code))]
+    pub(crate) has_iprecvtos: bool,
+
+    /// Indicates if the socket has `IPV6_RECVTCLASS` set.
+    #[cfg_attr(not(target_os = "linux"), expect(dead_code))]
+    pub(crate) has_ipv6recvtclass: bool,
+
     // Indicates if the socket has `IP_MTU_DISCOVER` set to `IP_PMTUDISC_PROBE`.
     #[cfg_attr(not(target_os = "linux"), expect(dead_code))]
     pub(crate) has_ip_mtu_discover_probe: bool,
@@ -401,6 +429,8 @@ impl SocketCapabilities {
         // the relevant options for both
         let _ = b.ip_mtu_discover_probe();
         let _ = b.ipv6_mtu_discover_probe();
+        let _ = b.ip_recvtos();
+        let _ = b.ipv6_recvtclass();
         if let Ok(true) = b.allows_nonlocal_source() {
             let _ = b.ipv4_pktinfo();
             let _ = b.ipv4_recvorigdstaddr();
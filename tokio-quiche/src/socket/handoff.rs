@@ -0,0 +1,299 @@
+// Copyright (C) 2026, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Hand-off of QUIC connections between processes.
+//!
+//! A connection's state, as exported by [`quiche::Connection::export_state`],
+//! is sent over a Unix domain socket along with the UDP socket the
+//! connection uses, so that the receiving process can take over the
+//! connection with [`quiche::restore`] without the peer noticing, e.g.
+//! during a graceful restart.
+//!
+//! For connections driven by an [`H3Driver`](crate::http3::driver::H3Driver),
+//! the state to send is obtained from
+//! [`H3Controller::hand_off`](crate::http3::driver::H3Controller::hand_off)
+//! and encoded with [`HandOffState::encode`]. The receiving process decodes
+//! it with [`HandOffState::decode`], and resumes the connection by passing
+//! the received socket and the QUIC state to
+//! [`quic::restore_with_config`](crate::quic::restore_with_config), along
+//! with a driver created by
+//! [`H3Driver::restore`](crate::http3::driver::H3Driver::restore).
+//!
+//! Connections sharing a socket, such as those of a listener, should be sent
+//! together with [`send_connections`] and restored with
+//! [`quic::restore_all_with_config`](crate::quic::restore_all_with_config),
+//! so that a single packet router serves all of them.
+//!
+//! [`HandOffState::encode`]: crate::http3::driver::HandOffState::encode
+//! [`HandOffState::decode`]: crate::http3::driver::HandOffState::decode
+
+use std::io;
+use std::io::IoSlice;
+use std::io::IoSliceMut;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+
+use nix::sys::socket::recvmsg;
+use nix::sys::socket::sendmsg;
+use nix::sys::socket::ControlMessage;
+use nix::sys::socket::ControlMessageOwned;
+use nix::sys::socket::MsgFlags;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::Interest;
+use tokio::net::UnixStream;
+
+/// The maximum size of a connection state accepted by [`recv_connection`] and
+/// [`recv_connections`], to avoid allocating arbitrary amounts of memory.
+const MAX_STATE_LEN: u64 = 64 * 1024 * 1024;
+
+/// Sends a connection's exported `state`, along with the `socket` it uses,
+/// over the Unix domain socket `stream`.
+///
+/// The socket's file descriptor is duplicated into the receiving process, so
+/// the caller is free to close its own copy once this returns. The other end
+/// is expected to call [`recv_connection`].
+pub async fn send_connection(
+    stream: &mut UnixStream, socket: BorrowedFd<'_>, state: &[u8],
+) -> io::Result<()> {
+    send_socket(stream, socket, (state.len() as u64).to_be_bytes()).await?;
+    stream.write_all(state).await?;
+
+    stream.flush().await
+}
+
+/// Sends the exported `states` of several connections sharing `socket`, such
+/// as all the connections of a listener, over the Unix domain socket `stream`.
+///
+/// The socket is sent only once, so that the receiving process can serve all
+/// the connections with
+/// [`quic::restore_all_with_config`](crate::quic::restore_all_with_config).
+/// The other end is expected to call [`recv_connections`].
+pub async fn send_connections<S: AsRef<[u8]>>(
+    stream: &mut UnixStream, socket: BorrowedFd<'_>, states: &[S],
+) -> io::Result<()> {
+    send_socket(stream, socket, (states.len() as u64).to_be_bytes()).await?;
+
+    for state in states {
+        let state = state.as_ref();
+
+        stream
+            .write_all(&(state.len() as u64).to_be_bytes())
+            .await?;
+        stream.write_all(state).await?;
+    }
+
+    stream.flush().await
+}
+
+/// Receives a connection's state and the socket it uses, as sent by
+/// [`send_connection`] over the Unix domain socket `stream`.
+///
+/// The returned state can be passed to [`quiche::restore`], or to
+/// [`quic::restore_with_config`](crate::quic::restore_with_config) along with
+/// the returned socket, which should be used to send and receive the
+/// connection's packets from now on.
+pub async fn recv_connection(
+    stream: &mut UnixStream,
+) -> io::Result<(OwnedFd, Vec<u8>)> {
+    let (fd, len) = recv_socket(stream).await?;
+    let state = recv_state(stream, len).await?;
+
+    Ok((fd, state))
+}
+
+/// Receives the states of several connections and the socket they share, as
+/// sent by [`send_connections`] over the Unix domain socket `stream`.
+///
+/// The returned states are in the order they were sent in.
+pub async fn recv_connections(
+    stream: &mut UnixStream,
+) -> io::Result<(OwnedFd, Vec<Vec<u8>>)> {
+    let (fd, count) = recv_socket(stream).await?;
+
+    let mut states = Vec::new();
+
+    for _ in 0..count {
+        let mut len = [0; 8];
+        stream.read_exact(&mut len).await?;
+
+        states.push(recv_state(stream, u64::from_be_bytes(len)).await?);
+    }
+
+    Ok((fd, states))
+}
+
+/// Sends `socket` over `stream`, along with the 8 bytes of `prefix`.
+async fn send_socket(
+    stream: &mut UnixStream, socket: BorrowedFd<'_>, prefix: [u8; 8],
+) -> io::Result<()> {
+    let fds = [socket.as_raw_fd()];
+
+    // The file descriptor is attached to the prefix, so that it is received
+    // along with the first byte of the message.
+    let sent = stream
+        .async_io(Interest::WRITABLE, || {
+            sendmsg::<()>(
+                stream.as_raw_fd(),
+                &[IoSlice::new(&prefix)],
+                &[ControlMessage::ScmRights(&fds)],
+                MsgFlags::MSG_NOSIGNAL,
+                None,
+            )
+            .map_err(io::Error::from)
+        })
+        .await?;
+
+    stream.write_all(&prefix[sent..]).await
+}
+
+/// Receives a socket sent by [`send_socket`], and the prefix sent along with
+/// it.
+async fn recv_socket(stream: &mut UnixStream) -> io::Result<(OwnedFd, u64)> {
+    let mut prefix = [0; 8];
+
+    let (read, fd) = stream
+        .async_io(Interest::READABLE, || {
+            let mut cmsg_buf = nix::cmsg_space!(RawFd);
+            let mut iov = [IoSliceMut::new(&mut prefix)];
+
+            let msg = recvmsg::<()>(
+                stream.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg_buf),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            )
+            .map_err(io::Error::from)?;
+
+            let mut fd = None;
+
+            for cmsg in msg.cmsgs().map_err(io::Error::from)? {
+                if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                    for raw in fds {
+                        // Take ownership of all received descriptors, so that
+                        // unexpected ones are closed.
+                        let owned = unsafe { OwnedFd::from_raw_fd(raw) };
+                        fd.get_or_insert(owned);
+                    }
+                }
+            }
+
+            Ok((msg.bytes, fd))
+        })
+        .await?;
+
+    if read == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let fd = fd.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "no socket received")
+    })?;
+
+    stream.read_exact(&mut prefix[read..]).await?;
+
+    Ok((fd, u64::from_be_bytes(prefix)))
+}
+
+/// Receives a connection state of `len` bytes.
+async fn recv_state(stream: &mut UnixStream, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_STATE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "connection state too large",
+        ));
+    }
+
+    let mut state = vec![0; len as usize];
+    stream.read_exact(&mut state).await?;
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::UdpSocket;
+    use std::os::fd::AsFd;
+
+    #[tokio::test]
+    async fn send_and_recv_connection() {
+        let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let state = b"connection state".to_vec();
+
+        send_connection(&mut tx, socket.as_fd(), &state)
+            .await
+            .unwrap();
+        drop(socket);
+
+        let (fd, received) = recv_connection(&mut rx).await.unwrap();
+
+        assert_eq!(received, state);
+
+        let socket = UdpSocket::from(fd);
+        assert_eq!(socket.local_addr().unwrap(), addr);
+    }
+
+    #[tokio::test]
+    async fn send_and_recv_connections() {
+        let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let states = vec![b"first".to_vec(), Vec::new(), b"third".to_vec()];
+
+        send_connections(&mut tx, socket.as_fd(), &states)
+            .await
+            .unwrap();
+        drop(socket);
+
+        let (fd, received) = recv_connections(&mut rx).await.unwrap();
+
+        assert_eq!(received, states);
+
+        let socket = UdpSocket::from(fd);
+        assert_eq!(socket.local_addr().unwrap(), addr);
+    }
+
+    #[tokio::test]
+    async fn recv_connection_without_socket() {
+        let (mut tx, mut rx) = UnixStream::pair().unwrap();
+
+        tx.write_all(&0_u64.to_be_bytes()).await.unwrap();
+
+        let err = recv_connection(&mut rx).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
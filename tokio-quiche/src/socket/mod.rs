@@ -28,6 +28,8 @@
 
 mod capabilities;
 mod connected;
+#[cfg(target_os = "linux")]
+mod handoff;
 mod listener;
 
 pub use self::capabilities::SocketCapabilities;
@@ -35,4 +37,12 @@ pub use self::capabilities::SocketCapabilities;
 pub use self::capabilities::SocketCapabilitiesBuilder;
 pub use self::connected::BoxedSocket;
 pub use self::connected::Socket;
+#[cfg(target_os = "linux")]
+pub use self::handoff::recv_connection;
+#[cfg(target_os = "linux")]
+pub use self::handoff::recv_connections;
+#[cfg(target_os = "linux")]
+pub use self::handoff::send_connection;
+#[cfg(target_os = "linux")]
+pub use self::handoff::send_connections;
 pub use self::listener::QuicListener;
@@ -152,6 +152,8 @@ pub async fn serve_connection_details(
                             request_counter.fetch_add(1, Ordering::SeqCst);
                             request_futs.push(handle_forwarded_headers_frame(stream_id, headers, send, recv));
                     }
+
+                    ServerH3Event::ConnectIp { .. } => unreachable!(),
                 }
             }
             Some(_) = request_futs.next() => {}
@@ -200,7 +202,8 @@ pub async fn handle_forwarded_headers_frame(
                     .unwrap();
                     return;
                 },
-            InboundFrame::Datagram(_) => unreachable!(),
+            InboundFrame::Datagram(_) | InboundFrame::Capsule(_) =>
+                unreachable!(),
         }
     }
 }
@@ -87,6 +87,8 @@ async fn test_additional_headers() {
                         .await
                         .unwrap();
                     },
+
+                    ServerH3Event::ConnectIp { .. } => unreachable!(),
                 }
             }
         },
@@ -215,6 +215,7 @@ async fn process_flight(
         let recv_info = quiche::RecvInfo {
             to: client_addr,
             from,
+            ecn: quiche::Ecn::NotEct,
         };
 
         // Process potentially coalesced packets.
@@ -182,6 +182,7 @@ async fn test_handshake_timeout_with_one_client_flight() {
     let recv_info = quiche::RecvInfo {
         from,
         to: socket.local_addr().unwrap(),
+        ecn: quiche::Ecn::NotEct,
     };
     let _ = quiche_conn.recv(&mut out[..len], recv_info);
 
@@ -198,6 +199,7 @@ async fn test_handshake_timeout_with_one_client_flight() {
                 let recv_info = quiche::RecvInfo {
                     from,
                     to: socket.local_addr().unwrap(),
+                    ecn: quiche::Ecn::NotEct,
                 };
                 let _ = quiche_conn.recv(&mut out[..len], recv_info);
 
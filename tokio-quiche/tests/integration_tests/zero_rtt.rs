@@ -227,6 +227,8 @@ fn helper_server_handler(
                     .await
                     .unwrap();
                 },
+
+                ServerH3Event::ConnectIp { .. } => unreachable!(),
             }
         }
     }
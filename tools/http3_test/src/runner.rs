@@ -184,10 +184,7 @@ pub fn run(
 
             debug!("got {len} bytes");
 
-            let recv_info = quiche::RecvInfo {
-                from,
-                to: local_addr,
-            };
+            let recv_info = quiche::RecvInfo::new(from, local_addr);
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
@@ -360,6 +357,13 @@ pub fn run(
 
                     Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
 
+                    Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                    Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                    Ok((_, quiche::h3::Event::WebTransportStream { .. })) =>
+                        (),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },